@@ -0,0 +1,330 @@
+use std::fmt;
+
+use crate::xdiff;
+
+/// A line of a hunk, with its line terminator unless it's the last line of a
+/// file without a final newline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Context(Vec<u8>),
+    Removed(Vec<u8>),
+    Added(Vec<u8>),
+}
+
+/// A group of nearby changed lines, with the unchanged lines around them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// The first old line, counting from 1, or the line before the hunk if it
+    /// has no old lines, like in `@@ -0,0 +1 @@`.
+    pub old_start: usize,
+    pub old_count: usize,
+    pub new_start: usize,
+    pub new_count: usize,
+    /// The line before the hunk that looks like the start of a function,
+    /// shown after the `@@` line, or empty.
+    pub function: Vec<u8>,
+    pub lines: Vec<Line>,
+}
+
+impl Hunk {
+    /// Returns the hunk like in a unified diff, starting with its `@@` line,
+    /// with its lines as they are even if they aren't UTF-8.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header();
+        for line in &self.lines {
+            let (prefix, text) = match line {
+                Line::Context(text) => (b' ', text),
+                Line::Removed(text) => (b'-', text),
+                Line::Added(text) => (b'+', text),
+            };
+            bytes.push(prefix);
+            bytes.extend(text);
+            if !text.ends_with(b"\n") {
+                bytes.extend(b"\n\\ No newline at end of file\n");
+            }
+        }
+        bytes
+    }
+
+    /// Returns the `@@ -old +new @@` line of the hunk, followed by its
+    /// function if any.
+    pub fn header(&self) -> Vec<u8> {
+        let range = |start: usize, count: usize| match count {
+            1 => format!("{start}"),
+            _ => format!("{start},{count}"),
+        };
+        let mut header = format!(
+            "@@ -{} +{} @@",
+            range(self.old_start, self.old_count),
+            range(self.new_start, self.new_count)
+        )
+        .into_bytes();
+        if !self.function.is_empty() {
+            header.push(b' ');
+            header.extend(&self.function);
+        }
+        header.push(b'\n');
+        header
+    }
+}
+
+impl fmt::Display for Hunk {
+    /// Formats the hunk like [`Hunk::to_bytes`], replacing invalid UTF-8.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.to_bytes()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Returns the hunks turning `old` into `new`, with `context` unchanged lines
+/// around the changes. Hunks whose context would overlap are merged, like in
+/// git.
+///
+/// The function of a hunk is the closest line before it that starts with a
+/// letter, `_` or `$`, like git's default, searching no further than the
+/// previous hunk, whose function is kept otherwise.
+pub fn hunks(old: &[u8], new: &[u8], context: usize) -> Vec<Hunk> {
+    hunks_with(old, new, context, true, &function_name)
+}
+
+/// Returns hunks like [`hunks`], with or without the indent heuristic, and
+/// with `function` returning the name of the function a line starts, if it
+/// does.
+pub fn hunks_with(
+    old: &[u8],
+    new: &[u8],
+    context: usize,
+    indent_heuristic: bool,
+    function: &dyn Fn(&[u8]) -> Option<Vec<u8>>,
+) -> Vec<Hunk> {
+    let (old_lines, new_lines) = (split_lines(old), split_lines(new));
+    // (edit, old line index, new line index) where the edit happens.
+    let mut ops = vec![];
+    let (mut i, mut j) = (0, 0);
+    for edit in diff_lines(&old_lines, &new_lines, indent_heuristic) {
+        ops.push((edit, i, j));
+        match edit {
+            Edit::Equal => (i, j) = (i + 1, j + 1),
+            Edit::Delete => i += 1,
+            Edit::Insert => j += 1,
+        }
+    }
+
+    let changes: Vec<usize> = (0..ops.len())
+        .filter(|&k| ops[k].0 != Edit::Equal)
+        .collect();
+    let mut hunks: Vec<Hunk> = vec![];
+    // The old line before the previous hunk, where the search for functions
+    // stops.
+    let mut searched = None;
+    let mut k = 0;
+    while k < changes.len() {
+        let first = changes[k];
+        while k + 1 < changes.len() && changes[k + 1] - changes[k] - 1 <= 2 * context {
+            k += 1;
+        }
+        let last = changes[k];
+        k += 1;
+
+        let start = first.saturating_sub(context);
+        let end = (last + 1 + context).min(ops.len());
+        let (_, old_index, new_index) = ops[start];
+        let mut hunk = Hunk {
+            old_start: old_index + 1,
+            old_count: 0,
+            new_start: new_index + 1,
+            new_count: 0,
+            function: (0..old_index)
+                .rev()
+                .take_while(|&i| Some(i) != searched)
+                .find_map(|i| function(old_lines[i]))
+                .or_else(|| hunks.last().map(|hunk| hunk.function.clone()))
+                .unwrap_or_default(),
+            lines: vec![],
+        };
+        searched = old_index.checked_sub(1);
+        for &(edit, i, j) in &ops[start..end] {
+            match edit {
+                Edit::Equal => {
+                    hunk.old_count += 1;
+                    hunk.new_count += 1;
+                    hunk.lines.push(Line::Context(old_lines[i].to_vec()));
+                }
+                Edit::Delete => {
+                    hunk.old_count += 1;
+                    hunk.lines.push(Line::Removed(old_lines[i].to_vec()));
+                }
+                Edit::Insert => {
+                    hunk.new_count += 1;
+                    hunk.lines.push(Line::Added(new_lines[j].to_vec()));
+                }
+            }
+        }
+        if hunk.old_count == 0 {
+            hunk.old_start -= 1;
+        }
+        if hunk.new_count == 0 {
+            hunk.new_start -= 1;
+        }
+        hunks.push(hunk);
+    }
+    hunks
+}
+
+/// Applies the hunks of `old` for which `selected` is true, returning the new
+/// content. The hunks must come from [`hunks`] for the same `old`.
+pub fn apply(old: &[u8], hunks: &[Hunk], selected: &[bool]) -> Vec<u8> {
+    let old_lines = split_lines(old);
+    let mut result = vec![];
+    let mut position = 0;
+    for (hunk, &selected) in hunks.iter().zip(selected) {
+        let start = match hunk.old_count {
+            0 => hunk.old_start,
+            _ => hunk.old_start - 1,
+        };
+        for line in &old_lines[position..start] {
+            result.extend_from_slice(line);
+        }
+        for line in &hunk.lines {
+            match line {
+                Line::Context(text) => result.extend_from_slice(text),
+                Line::Removed(text) if !selected => result.extend_from_slice(text),
+                Line::Added(text) if selected => result.extend_from_slice(text),
+                _ => {}
+            }
+        }
+        position = start + hunk.old_count;
+    }
+    for line in &old_lines[position..] {
+        result.extend_from_slice(line);
+    }
+    result
+}
+
+/// Returns the function name a line starts, if it starts with a letter, `_`
+/// or `$`, truncated like git does.
+fn function_name(line: &[u8]) -> Option<Vec<u8>> {
+    let first = *line.first()?;
+    if !(first.is_ascii_alphabetic() || first == b'_' || first == b'$') {
+        return None;
+    }
+    let line = &line[..line.len().min(80)];
+    Some(line.trim_ascii_end().to_vec())
+}
+
+/// Splits content into lines, keeping their terminators.
+fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Returns the edit script turning `old` into `new` that git would show,
+/// see [`xdiff::diff`]. Deletions come before insertions.
+fn diff_lines(old: &[&[u8]], new: &[&[u8]], indent_heuristic: bool) -> Vec<Edit> {
+    let (removed, added) = xdiff::diff(old, new, indent_heuristic);
+    let mut edits = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && removed[i] {
+            edits.push(Edit::Delete);
+            i += 1;
+        } else if j < new.len() && added[j] {
+            edits.push(Edit::Insert);
+            j += 1;
+        } else {
+            edits.push(Edit::Equal);
+            (i, j) = (i + 1, j + 1);
+        }
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hunks() {
+        let old = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = b"1\ntwo\n3\n4\n5\n6\n7\n8\n9\n10\n11";
+        let hunks = hunks(old, new, 3);
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(
+            hunks[0].to_string(),
+            "@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n"
+        );
+        assert_eq!(
+            hunks[1].to_string(),
+            "@@ -8,3 +8,4 @@\n 8\n 9\n 10\n+11\n\\ No newline at end of file\n"
+        );
+
+        // Changes closer than twice the context are in the same hunk.
+        let hunks = super::hunks(b"a\nb\nc\n", b"A\nb\nC\n", 1);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(
+            hunks[0].to_string(),
+            "@@ -1,3 +1,3 @@\n-a\n+A\n b\n-c\n+C\n"
+        );
+
+        let hunks = super::hunks(b"", b"new\n", 3);
+        assert_eq!(hunks[0].to_string(), "@@ -0,0 +1 @@\n+new\n");
+        assert!(super::hunks(old, old, 3).is_empty());
+    }
+
+    #[test]
+    fn test_function_names() {
+        let old = b"fn a() {\n    1\n    2\n    3\n    4\n}\n\nfn b() {\n    5\n    6\n    7\n    8\n    9\n    10\n}\n";
+        let new = b"fn a() {\n    1\n    2\n    3\n    four\n}\n\nfn b() {\n    5\n    6\n    7\n    8\n    9\n    ten\n}\n";
+        let hunks = hunks(old, new, 1);
+        let functions: Vec<&[u8]> = hunks.iter().map(|hunk| &hunk.function[..]).collect();
+        assert_eq!(functions, [&b"fn a() {"[..], b"fn b() {"]);
+        assert!(hunks[0]
+            .to_string()
+            .starts_with("@@ -4,3 +4,3 @@ fn a() {\n"));
+
+        // The function of the previous hunk is kept when there is none
+        // between them.
+        let hunks = super::hunks(old, b"fn a() {\n    one\n    2\n    3\n    4\n}\n", 0);
+        assert_eq!(hunks[1].function, b"fn a() {");
+        assert!(super::hunks(b"    x\n", b"    y\n", 3)[0]
+            .function
+            .is_empty());
+    }
+
+    #[test]
+    fn test_apply() {
+        let old = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = b"1\ntwo\n3\n4\n5\n6\n7\n8\n9\n10\n11";
+        let hunks = hunks(old, new, 3);
+        assert_eq!(apply(old, &hunks, &[true, true]), new);
+        assert_eq!(apply(old, &hunks, &[false, false]), old);
+        assert_eq!(
+            apply(old, &hunks, &[true, false]),
+            b"1\ntwo\n3\n4\n5\n6\n7\n8\n9\n10\n"
+        );
+        assert_eq!(
+            apply(old, &hunks, &[false, true]),
+            b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11"
+        );
+
+        let hunks = super::hunks(b"", b"new\n", 3);
+        assert_eq!(apply(b"", &hunks, &[true]), b"new\n");
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let lines = |s: &'static str| split_lines(s.as_bytes());
+        let (old, new) = (lines("a\nb\nc\na\nb\nb\na\n"), lines("c\nb\na\nb\na\nc\n"));
+        let edits = diff_lines(&old, &new, true);
+        // The shortest edit script of Myers' paper has 5 edits.
+        let changes = edits.iter().filter(|&&edit| edit != Edit::Equal).count();
+        assert_eq!(changes, 5);
+        assert_eq!(diff_lines(&[], &[], true), vec![]);
+        assert_eq!(diff_lines(&old, &[], true), vec![Edit::Delete; 7]);
+    }
+}
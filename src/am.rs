@@ -0,0 +1,148 @@
+use anyhow::{anyhow, Result};
+use std::{fs, path::PathBuf};
+
+use crate::repo::Repo;
+
+/// The state of an `am` session stopped at a patch that didn't apply, kept
+/// in `.git/rebase-apply` like git's: the mails to apply, numbered from 1 in
+/// files like `0001`, and where the session is. An `applying` file tells it
+/// apart from a rebase, and the patch of the current mail is in `patch`, see
+/// [`crate::status::operations_in_progress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    /// The number of the mail being applied.
+    pub next: usize,
+    /// The number of the last mail.
+    pub last: usize,
+    /// Whether to fall back to a three-way merge when a patch doesn't apply.
+    pub three_way: bool,
+    /// The commit HEAD was at before the session, if any, to go back to
+    /// when it's aborted.
+    pub orig_head: Option<String>,
+    /// Where HEAD was after the last patch, so that aborting doesn't undo
+    /// commits made since.
+    pub abort_safety: Option<String>,
+}
+
+impl State {
+    /// Starts a session applying `mails`, saving them and the state.
+    pub fn start(
+        repo: &Repo,
+        mails: &[String],
+        three_way: bool,
+        orig_head: Option<String>,
+    ) -> Result<State> {
+        fs::create_dir_all(dir(repo))?;
+        fs::write(dir(repo).join("applying"), "")?;
+        for (number, mail) in (1..).zip(mails) {
+            fs::write(mail_path(repo, number), mail)?;
+        }
+        let state = State {
+            next: 1,
+            last: mails.len(),
+            three_way,
+            abort_safety: orig_head.clone(),
+            orig_head,
+        };
+        state.write(repo)?;
+        Ok(state)
+    }
+
+    /// Reads the state of the session in progress, if any.
+    pub fn read(repo: &Repo) -> Result<Option<State>> {
+        let read = |name: &str| match fs::read_to_string(dir(repo).join(name)) {
+            Ok(value) => Ok(Some(value.trim_end().to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+        let (Some(next), Some(last)) = (read("next")?, read("last")?) else {
+            return Ok(None);
+        };
+        let number = |value: String| {
+            value
+                .parse()
+                .map_err(|_| anyhow!("Invalid am state in {}", dir(repo).display()))
+        };
+        Ok(Some(State {
+            next: number(next)?,
+            last: number(last)?,
+            three_way: read("threeway")?.as_deref() == Some("t"),
+            orig_head: read("orig-head")?,
+            abort_safety: read("abort-safety")?.filter(|head| !head.is_empty()),
+        }))
+    }
+
+    /// Saves the state, with the number of the next mail last so that it's
+    /// only found once complete.
+    pub fn write(&self, repo: &Repo) -> Result<()> {
+        fs::write(
+            dir(repo).join("threeway"),
+            if self.three_way { "t\n" } else { "f\n" },
+        )?;
+        match &self.orig_head {
+            Some(hash) => fs::write(dir(repo).join("orig-head"), format!("{hash}\n"))?,
+            None => {
+                let _ = fs::remove_file(dir(repo).join("orig-head"));
+            }
+        }
+        fs::write(
+            dir(repo).join("abort-safety"),
+            format!("{}\n", self.abort_safety.as_deref().unwrap_or("")),
+        )?;
+        fs::write(dir(repo).join("last"), format!("{}\n", self.last))?;
+        fs::write(dir(repo).join("next"), format!("{}\n", self.next))?;
+        Ok(())
+    }
+
+    /// Returns the mail being applied.
+    pub fn mail(&self, repo: &Repo) -> Result<String> {
+        Ok(fs::read_to_string(mail_path(repo, self.next))?)
+    }
+
+    /// Saves the patch of the mail being applied.
+    pub fn write_patch(repo: &Repo, patch: &str) -> Result<()> {
+        Ok(fs::write(dir(repo).join("patch"), patch)?)
+    }
+
+    /// Removes the state once the session is over.
+    pub fn remove(repo: &Repo) -> Result<()> {
+        Ok(fs::remove_dir_all(dir(repo))?)
+    }
+}
+
+/// The directory of the state, `.git/rebase-apply`.
+pub fn dir(repo: &Repo) -> PathBuf {
+    repo.git_dir().join("rebase-apply")
+}
+
+fn mail_path(repo: &Repo, number: usize) -> PathBuf {
+    dir(repo).join(format!("{number:04}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_and_read() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        fs::create_dir_all(repo.git_dir()).unwrap();
+        assert_eq!(State::read(&repo).unwrap(), None);
+
+        let mails = ["Subject: a\n".to_string(), "Subject: b\n".to_string()];
+        let mut state = State::start(&repo, &mails, true, Some("a".repeat(40))).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&state));
+        assert_eq!(state.mail(&repo).unwrap(), "Subject: a\n");
+
+        state.next = 2;
+        state.orig_head = None;
+        state.abort_safety = Some("b".repeat(40));
+        state.write(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&state));
+        assert_eq!(state.mail(&repo).unwrap(), "Subject: b\n");
+
+        State::remove(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap(), None);
+    }
+}
@@ -0,0 +1,921 @@
+use anyhow::{anyhow, Result};
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+use crate::{
+    add_patch::{Hunk, Line},
+    base85, format,
+    object::{self, Blob, Object},
+    refs::ZERO_HASH,
+    repo::Repo,
+};
+
+/// The changes of a file in a patch, like a section of `git diff`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilePatch {
+    /// The path the patch applies to, `None` for a new file.
+    pub old_path: Option<String>,
+    /// The path of the result, `None` for a deleted file. It differs from
+    /// `old_path` for renames and copies.
+    pub new_path: Option<String>,
+    pub old_mode: Option<u32>,
+    pub new_mode: Option<u32>,
+    /// Whether the file at `old_path` is kept, for copies.
+    pub copy: bool,
+    /// The hashes of the `index` line, which may be abbreviated.
+    pub old_hash: Option<String>,
+    pub new_hash: Option<String>,
+    pub changes: Changes,
+}
+
+/// How the content of a file changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Changes {
+    /// Hunks of lines, none if only the mode or the path changes.
+    Hunks(Vec<Hunk>),
+    /// A `GIT binary patch`, with the data to apply it in reverse if any.
+    Binary {
+        forward: BinaryData,
+        reverse: Option<BinaryData>,
+    },
+    /// `Binary files a/path and b/path differ`, which can only be applied if
+    /// the new blob is in the repository.
+    BinaryDiffer,
+}
+
+impl Default for Changes {
+    fn default() -> Self {
+        Changes::Hunks(vec![])
+    }
+}
+
+/// The data of one side of a binary patch, inflated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BinaryData {
+    /// The whole content.
+    Literal(Vec<u8>),
+    /// A delta from the content of the other side, like in packs.
+    Delta(Vec<u8>),
+}
+
+impl FilePatch {
+    /// Returns the path to report the patch with.
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Returns the patch undoing this one, like `git apply --reverse`.
+    pub fn reverse(self) -> Result<FilePatch> {
+        let path = self.path().to_string();
+        let changes = match self.changes {
+            Changes::Hunks(hunks) => Changes::Hunks(
+                hunks
+                    .into_iter()
+                    .map(|hunk| Hunk {
+                        old_start: hunk.new_start,
+                        old_count: hunk.new_count,
+                        new_start: hunk.old_start,
+                        new_count: hunk.old_count,
+                        function: hunk.function,
+                        lines: hunk
+                            .lines
+                            .into_iter()
+                            .map(|line| match line {
+                                Line::Context(text) => Line::Context(text),
+                                Line::Removed(text) => Line::Added(text),
+                                Line::Added(text) => Line::Removed(text),
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+            ),
+            Changes::Binary {
+                forward,
+                reverse: Some(reverse),
+            } => Changes::Binary {
+                forward: reverse,
+                reverse: Some(forward),
+            },
+            Changes::Binary { reverse: None, .. } => {
+                return Err(anyhow!(
+                    "cannot reverse-apply a binary patch without the reverse hunk to '{path}'"
+                ))
+            }
+            Changes::BinaryDiffer => Changes::BinaryDiffer,
+        };
+        Ok(FilePatch {
+            old_path: self.new_path,
+            new_path: self.old_path,
+            old_mode: self.new_mode,
+            new_mode: self.old_mode,
+            copy: self.copy,
+            old_hash: self.new_hash,
+            new_hash: self.old_hash,
+            changes,
+        })
+    }
+
+    /// Returns the new content of the file whose content was `old`, or empty
+    /// for a new file.
+    ///
+    /// Hunks are placed like [`apply_hunks`]. Like git, binary patches need
+    /// the full hashes of the `index` line: the old one must be the hash of
+    /// `old`, and the new one that of the result, which is read from the
+    /// repository when it's there.
+    pub fn apply(&self, repo: &Repo, old: &[u8], context: Option<usize>) -> Result<Vec<u8>> {
+        let path = self.path();
+        let data = match &self.changes {
+            Changes::Hunks(hunks) => {
+                return apply_hunks(old, hunks, context)
+                    .map_err(|line| anyhow!("patch failed: {path}:{line}"))
+            }
+            Changes::Binary { forward, .. } => Some(forward),
+            Changes::BinaryDiffer => None,
+        };
+        let full_hashes = [&self.old_hash, &self.new_hash]
+            .map(|hash| hash.as_ref().filter(|hash| hash.len() == ZERO_HASH.len()));
+        let [Some(old_hash), Some(new_hash)] = full_hashes else {
+            return Err(anyhow!(
+                "cannot apply binary patch to '{path}' without full index line"
+            ));
+        };
+        if self.old_path.is_some() {
+            let hash = Blob::new(old.to_vec()).hash();
+            if hash != *old_hash {
+                return Err(anyhow!(
+                    "the patch applies to '{path}' ({hash}), which does not match the current contents."
+                ));
+            }
+        } else if !old.is_empty() {
+            return Err(anyhow!(
+                "the patch applies to an empty '{path}' but it is not empty"
+            ));
+        }
+        if new_hash == ZERO_HASH {
+            return Ok(vec![]);
+        }
+        if object::exists(repo, new_hash) {
+            let Object::Blob(blob) = Object::from_hash(repo, new_hash)? else {
+                return Err(anyhow!("Not a blob: {new_hash}"));
+            };
+            return Ok(blob.content);
+        }
+        let new = match data {
+            Some(BinaryData::Literal(content)) => content.clone(),
+            Some(BinaryData::Delta(delta)) => apply_delta(old, delta)?,
+            None => return Err(anyhow!("missing binary patch data for '{path}'")),
+        };
+        let hash = Blob::new(new.clone()).hash();
+        if hash != *new_hash {
+            return Err(anyhow!(
+                "binary patch to '{path}' creates incorrect result (expecting {new_hash}, got {hash})"
+            ));
+        }
+        Ok(new)
+    }
+}
+
+/// Parses the patches of the files in a unified diff, like `git apply`.
+///
+/// Both git diffs, with their extended headers for modes, new and deleted
+/// files, renames, copies and binary patches, and plain `diff -u` output are
+/// understood. Anything around the patches, like the message of a mail, is
+/// skipped. `strip` leading components are removed from the paths, like
+/// with `-p<n>`, except for those of renames and copies.
+///
+/// Like git, paths that could escape the working tree or write into `.git`
+/// are refused, see [`verify_path`].
+pub fn parse(patch: &[u8], strip: usize) -> Result<Vec<FilePatch>> {
+    let mut parser = Parser {
+        lines: patch.split_inclusive(|&b| b == b'\n').collect(),
+        next: 0,
+        strip,
+    };
+    let mut patches = vec![];
+    while parser.next < parser.lines.len() {
+        let line = parser.line(parser.next);
+        if let Some(names) = line.strip_prefix("diff --git ") {
+            parser.next += 1;
+            patches.push(parser.git_patch(names)?);
+        } else if line.starts_with("--- ")
+            && parser.line(parser.next + 1).starts_with("+++ ")
+            && parser.line(parser.next + 2).starts_with("@@ -")
+        {
+            patches.push(parser.traditional_patch()?);
+        } else {
+            parser.next += 1;
+        }
+    }
+    for patch in &patches {
+        for path in [&patch.old_path, &patch.new_path].into_iter().flatten() {
+            if !verify_path(path) {
+                return Err(anyhow!("invalid path '{path}'"));
+            }
+        }
+    }
+    Ok(patches)
+}
+
+/// Returns whether a path is safe to write below the working tree, like
+/// git's `verify_path`: it's relative, and none of its components is empty,
+/// `.`, `..` or `.git`, in any case.
+pub fn verify_path(path: &str) -> bool {
+    !path.is_empty()
+        && path.split('/').all(|component| {
+            !matches!(component, "" | "." | "..") && !component.eq_ignore_ascii_case(".git")
+        })
+}
+
+struct Parser<'a> {
+    lines: Vec<&'a [u8]>,
+    /// The index of the next line to parse.
+    next: usize,
+    strip: usize,
+}
+
+impl Parser<'_> {
+    /// Returns a line without its line ending, or empty past the end.
+    fn line(&self, i: usize) -> String {
+        let line = self.lines.get(i).copied().unwrap_or_default();
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        String::from_utf8_lossy(line).into_owned()
+    }
+
+    /// Parses the extended headers and the changes of a patch after its
+    /// `diff --git` line, whose paths are `names`.
+    fn git_patch(&mut self, names: &str) -> Result<FilePatch> {
+        let header_line = self.next;
+        let mut patch = FilePatch::default();
+        // The paths of the `---` and `+++` lines, `None` for `/dev/null`.
+        let (mut old_name, mut new_name) = (None, None);
+        let (mut created, mut deleted) = (false, false);
+        let (mut from, mut to) = (None, None);
+        loop {
+            let line = self.line(self.next);
+            let mode = |mode: &str| {
+                u32::from_str_radix(mode.trim(), 8)
+                    .map_err(|_| anyhow!("invalid mode on line {}: {line}", self.next + 1))
+            };
+            if let Some(value) = line.strip_prefix("old mode ") {
+                patch.old_mode = Some(mode(value)?);
+            } else if let Some(value) = line.strip_prefix("new mode ") {
+                patch.new_mode = Some(mode(value)?);
+            } else if let Some(value) = line.strip_prefix("deleted file mode ") {
+                patch.old_mode = Some(mode(value)?);
+                deleted = true;
+            } else if let Some(value) = line.strip_prefix("new file mode ") {
+                patch.new_mode = Some(mode(value)?);
+                created = true;
+            } else if let Some(value) = line
+                .strip_prefix("rename from ")
+                .or_else(|| line.strip_prefix("rename old "))
+            {
+                from = Some(unquote(value));
+            } else if let Some(value) = line
+                .strip_prefix("rename to ")
+                .or_else(|| line.strip_prefix("rename new "))
+            {
+                to = Some(unquote(value));
+            } else if let Some(value) = line.strip_prefix("copy from ") {
+                from = Some(unquote(value));
+                patch.copy = true;
+            } else if let Some(value) = line.strip_prefix("copy to ") {
+                to = Some(unquote(value));
+                patch.copy = true;
+            } else if let Some(value) = line.strip_prefix("index ") {
+                let (hashes, index_mode) = match value.split_once(' ') {
+                    Some((hashes, index_mode)) => (hashes, Some(mode(index_mode)?)),
+                    None => (value, None),
+                };
+                let (old_hash, new_hash) = hashes
+                    .split_once("..")
+                    .ok_or_else(|| anyhow!("invalid index line {}: {line}", self.next + 1))?;
+                patch.old_hash = Some(old_hash.to_string());
+                patch.new_hash = Some(new_hash.to_string());
+                if index_mode.is_some() {
+                    (patch.old_mode, patch.new_mode) = (index_mode, index_mode);
+                }
+            } else if let Some(value) = line.strip_prefix("--- ") {
+                old_name = Some(self.file_name(value)?);
+            } else if let Some(value) = line.strip_prefix("+++ ") {
+                new_name = Some(self.file_name(value)?);
+            } else if !line.starts_with("similarity index ")
+                && !line.starts_with("dissimilarity index ")
+            {
+                break;
+            }
+            self.next += 1;
+        }
+
+        let header_name = git_header_name(names, self.strip);
+        let lacks_name = || {
+            anyhow!(
+                "git diff header lacks filename information when removing {} leading pathname component{} (line {header_line})",
+                self.strip,
+                if self.strip == 1 { "" } else { "s" }
+            )
+        };
+        patch.old_path = match created {
+            true => None,
+            false => Some(
+                from.clone()
+                    .or(old_name.flatten())
+                    .or(header_name.clone())
+                    .ok_or_else(lacks_name)?,
+            ),
+        };
+        patch.new_path = match deleted {
+            true => None,
+            false => Some(
+                to.or(new_name.flatten())
+                    .or(header_name)
+                    .or(from)
+                    .ok_or_else(lacks_name)?,
+            ),
+        };
+        patch.changes = self.changes()?;
+        Ok(patch)
+    }
+
+    /// Parses a patch without git's headers, from its `---` line.
+    fn traditional_patch(&mut self) -> Result<FilePatch> {
+        let old_name = self.file_name(&self.line(self.next)[4..])?;
+        let new_name = self.file_name(&self.line(self.next + 1)[4..])?;
+        self.next += 2;
+        let (old_path, new_path) = match (old_name, new_name) {
+            (None, None) => {
+                return Err(anyhow!(
+                    "unable to find filename in patch at line {}",
+                    self.next - 1
+                ))
+            }
+            (None, Some(new_name)) => (None, Some(new_name)),
+            (Some(old_name), None) => (Some(old_name), None),
+            // Like git, the new name is the one to patch, unless it's the
+            // old one with something tacked on, like `file.orig`.
+            (Some(old_name), Some(new_name)) => {
+                let name = match new_name.starts_with(&old_name) {
+                    true => old_name,
+                    false => new_name,
+                };
+                (Some(name.clone()), Some(name))
+            }
+        };
+        Ok(FilePatch {
+            old_path,
+            new_path,
+            changes: self.changes()?,
+            ..Default::default()
+        })
+    }
+
+    /// Returns the path of a `---` or `+++` line without its leading
+    /// components, `None` for `/dev/null`.
+    fn file_name(&self, value: &str) -> Result<Option<String>> {
+        let name = match value.starts_with('"') {
+            true => unquote(value),
+            // Plain diffs have a timestamp after a tab.
+            false => value.split('\t').next().unwrap_or_default().to_string(),
+        };
+        let name = name.trim_end();
+        if name == "/dev/null" {
+            return Ok(None);
+        }
+        strip_components(name, self.strip)
+            .map(|name| Some(name.to_string()))
+            .ok_or_else(|| anyhow!("invalid path on line {}: {value}", self.next + 1))
+    }
+
+    /// Parses the hunks or the binary patch after the headers of a file.
+    fn changes(&mut self) -> Result<Changes> {
+        let line = self.line(self.next);
+        if line == "GIT binary patch" {
+            self.next += 1;
+            let forward = self.binary_data()?;
+            let reverse = match self.line(self.next).starts_with(['l', 'd']) {
+                true => Some(self.binary_data()?),
+                false => None,
+            };
+            return Ok(Changes::Binary { forward, reverse });
+        }
+        if line.starts_with("Binary files ") && line.ends_with(" differ") {
+            self.next += 1;
+            return Ok(Changes::BinaryDiffer);
+        }
+        let mut hunks = vec![];
+        while self.line(self.next).starts_with("@@ -") {
+            hunks.push(self.hunk()?);
+        }
+        Ok(Changes::Hunks(hunks))
+    }
+
+    /// Parses a hunk, from its `@@` line.
+    fn hunk(&mut self) -> Result<Hunk> {
+        let header = self.line(self.next);
+        let corrupt = |line: usize| anyhow!("corrupt patch at line {}", line + 1);
+        let (ranges, function) = header[3..]
+            .split_once(" @@")
+            .ok_or_else(|| corrupt(self.next))?;
+        let range = |range: Option<&str>, sign: char| -> Option<(usize, usize)> {
+            let range = range?.strip_prefix(sign)?;
+            match range.split_once(',') {
+                Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+                None => Some((range.parse().ok()?, 1)),
+            }
+        };
+        let mut ranges = ranges.split(' ');
+        let (Some((old_start, old_count)), Some((new_start, new_count))) =
+            (range(ranges.next(), '-'), range(ranges.next(), '+'))
+        else {
+            return Err(corrupt(self.next));
+        };
+        let mut hunk = Hunk {
+            old_start,
+            old_count,
+            new_start,
+            new_count,
+            function: function.trim_start().as_bytes().to_vec(),
+            lines: vec![],
+        };
+        self.next += 1;
+
+        let (mut old_left, mut new_left) = (old_count, new_count);
+        while old_left > 0 || new_left > 0 {
+            let Some(&line) = self.lines.get(self.next) else {
+                return Err(corrupt(self.next));
+            };
+            let text = line.get(1..).unwrap_or_default().to_vec();
+            match line.first() {
+                // Like git, empty lines are context lines whose space was
+                // lost.
+                Some(b' ') | Some(b'\n') | Some(b'\r') if old_left > 0 && new_left > 0 => {
+                    let text = match line[0] {
+                        b' ' => text,
+                        _ => line.to_vec(),
+                    };
+                    hunk.lines.push(Line::Context(text));
+                    (old_left, new_left) = (old_left - 1, new_left - 1);
+                }
+                Some(b'-') if old_left > 0 => {
+                    hunk.lines.push(Line::Removed(text));
+                    old_left -= 1;
+                }
+                Some(b'+') if new_left > 0 => {
+                    hunk.lines.push(Line::Added(text));
+                    new_left -= 1;
+                }
+                Some(b'\\') if !hunk.lines.is_empty() => strip_newline(&mut hunk.lines),
+                _ => return Err(corrupt(self.next)),
+            }
+            self.next += 1;
+        }
+        if self
+            .lines
+            .get(self.next)
+            .is_some_and(|line| line.starts_with(b"\\"))
+        {
+            strip_newline(&mut hunk.lines);
+            self.next += 1;
+        }
+        Ok(hunk)
+    }
+
+    /// Parses the `literal` or `delta` data of one side of a binary patch,
+    /// up to the empty line ending it.
+    fn binary_data(&mut self) -> Result<BinaryData> {
+        let line = self.line(self.next);
+        let number = self.next + 1;
+        let corrupt = || anyhow!("corrupt binary patch at line {number}: {line}");
+        let (kind, size) = line.split_once(' ').ok_or_else(corrupt)?;
+        let size: usize = size.parse().map_err(|_| corrupt())?;
+        self.next += 1;
+        let mut deflated = vec![];
+        loop {
+            let line = self.line(self.next);
+            if line.is_empty() {
+                break;
+            }
+            deflated.extend(base85::decode_line(line.as_bytes())?);
+            self.next += 1;
+        }
+        self.next += 1;
+        let mut data = Vec::with_capacity(size);
+        ZlibDecoder::new(&deflated[..]).read_to_end(&mut data)?;
+        if data.len() != size {
+            return Err(corrupt());
+        }
+        match kind {
+            "literal" => Ok(BinaryData::Literal(data)),
+            "delta" => Ok(BinaryData::Delta(data)),
+            _ => Err(corrupt()),
+        }
+    }
+}
+
+/// Removes the line ending of the last line of a hunk, for `\ No newline at
+/// end of file`.
+fn strip_newline(lines: &mut [Line]) {
+    if let Some(Line::Context(text) | Line::Removed(text) | Line::Added(text)) = lines.last_mut() {
+        if text.ends_with(b"\n") {
+            text.pop();
+        }
+    }
+}
+
+/// Returns a path as is, or unquoted if it's quoted.
+fn unquote(value: &str) -> String {
+    match format::unquote_path(value) {
+        Some((path, _)) => path,
+        None => value.to_string(),
+    }
+}
+
+/// Removes `count` leading components from a path, or returns `None` if it
+/// doesn't have that many directories.
+fn strip_components(path: &str, count: usize) -> Option<&str> {
+    let mut path = path;
+    for _ in 0..count {
+        let (_, rest) = path.split_once('/')?;
+        path = rest.trim_start_matches('/');
+    }
+    Some(path)
+}
+
+/// Returns the path of a `diff --git a/<path> b/<path>` line, when both are
+/// the same once stripped.
+fn git_header_name(names: &str, strip: usize) -> Option<String> {
+    if names.starts_with('"') {
+        let (old, rest) = format::unquote_path(names)?;
+        let new = match rest.trim_start() {
+            new if new.starts_with('"') => format::unquote_path(new)?.0,
+            new => new.to_string(),
+        };
+        let old = strip_components(&old, strip)?;
+        return (Some(old) == strip_components(&new, strip)).then(|| old.to_string());
+    }
+    names.match_indices(' ').find_map(|(i, _)| {
+        let old = strip_components(&names[..i], strip)?;
+        let new = &names[i + 1..];
+        let new = match new.starts_with('"') {
+            true => format::unquote_path(new)?.0,
+            false => new.to_string(),
+        };
+        (!old.is_empty() && Some(old) == strip_components(&new, strip)).then(|| old.to_string())
+    })
+}
+
+/// Applies hunks to `content`, like git: each hunk is looked for at the line
+/// where it's expected first, then ever further before and after it. Hunks
+/// starting at the first line must match at the beginning, and those without
+/// trailing context at the end.
+///
+/// With `context`, like `-C<n>`, hunks that don't match may lose their
+/// outermost context lines, as long as `context` lines are kept on each side.
+///
+/// Returns the old line of the first hunk that doesn't apply on error.
+pub fn apply_hunks(
+    content: &[u8],
+    hunks: &[Hunk],
+    context: Option<usize>,
+) -> Result<Vec<u8>, usize> {
+    let mut image: Vec<&[u8]> = content.split_inclusive(|&b| b == b'\n').collect();
+    for hunk in hunks {
+        let mut preimage: Vec<&[u8]> = vec![];
+        let mut postimage: Vec<&[u8]> = vec![];
+        for line in &hunk.lines {
+            match line {
+                Line::Context(text) => {
+                    preimage.push(text);
+                    postimage.push(text);
+                }
+                Line::Removed(text) => preimage.push(text),
+                Line::Added(text) => postimage.push(text),
+            }
+        }
+        let is_context = |line: &&Line| matches!(line, Line::Context(_));
+        let mut leading = hunk.lines.iter().take_while(is_context).count();
+        let mut trailing = hunk.lines.iter().rev().take_while(is_context).count();
+        let mut match_beginning = hunk.old_start <= 1;
+        let mut match_end = trailing == 0;
+        let mut position = hunk.new_start.saturating_sub(1);
+        let limit = context.unwrap_or(usize::MAX);
+        let found = loop {
+            if let Some(found) =
+                find_position(&image, &preimage, position, match_beginning, match_end)
+            {
+                break found;
+            }
+            if leading <= limit && trailing <= limit {
+                return Err(hunk.old_start);
+            }
+            if match_beginning || match_end {
+                (match_beginning, match_end) = (false, false);
+                continue;
+            }
+            // Like git, the larger context is reduced, or both.
+            if leading >= trailing {
+                preimage.remove(0);
+                postimage.remove(0);
+                position = position.saturating_sub(1);
+                leading -= 1;
+            }
+            if trailing > leading {
+                preimage.pop();
+                postimage.pop();
+                trailing -= 1;
+            }
+        };
+        image.splice(found..found + preimage.len(), postimage);
+    }
+    Ok(image.concat())
+}
+
+/// Returns the line where `preimage` matches `image`, searching from `line`
+/// alternately after and before it, like git's `find_pos`.
+fn find_position(
+    image: &[&[u8]],
+    preimage: &[&[u8]],
+    line: usize,
+    match_beginning: bool,
+    match_end: bool,
+) -> Option<usize> {
+    let line = match (match_beginning, match_end) {
+        (true, _) => 0,
+        (false, true) => image.len().saturating_sub(preimage.len()),
+        (false, false) => line,
+    }
+    .min(image.len());
+    let matches = |start: usize| {
+        let end = start + preimage.len();
+        end <= image.len()
+            && (!match_end || end == image.len())
+            && (!match_beginning || start == 0)
+            && image[start..end] == *preimage
+    };
+    if matches(line) {
+        return Some(line);
+    }
+    let (mut backwards, mut forwards) = (line, line);
+    while backwards > 0 || forwards < image.len() {
+        if forwards < image.len() {
+            forwards += 1;
+            if matches(forwards) {
+                return Some(forwards);
+            }
+        }
+        if backwards > 0 {
+            backwards -= 1;
+            if matches(backwards) {
+                return Some(backwards);
+            }
+        }
+    }
+    None
+}
+
+/// Applies a delta to `base`, like in packs: the sizes of the base and the
+/// result, then instructions to copy ranges of the base or insert data.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let corrupt = || anyhow!("corrupt delta");
+    let mut rest = delta;
+    let mut size = || -> Result<usize> {
+        let mut size = 0;
+        let mut shift = 0;
+        loop {
+            let (&byte, after) = rest.split_first().ok_or_else(corrupt)?;
+            rest = after;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(size);
+            }
+        }
+    };
+    let base_size = size()?;
+    let result_size = size()?;
+    if base_size != base.len() {
+        return Err(corrupt());
+    }
+    let mut result = Vec::with_capacity(result_size);
+    while let Some((&command, after)) = rest.split_first() {
+        rest = after;
+        if command & 0x80 != 0 {
+            // Which bytes of the offset and the size follow.
+            let mut value = |bits: std::ops::Range<u8>| -> Result<usize> {
+                let mut value = 0;
+                for bit in bits.clone() {
+                    if command & (1 << bit) != 0 {
+                        let (&byte, after) = rest.split_first().ok_or_else(corrupt)?;
+                        rest = after;
+                        value |= (byte as usize) << (8 * (bit - bits.start));
+                    }
+                }
+                Ok(value)
+            };
+            let offset = value(0..4)?;
+            let size = match value(4..7)? {
+                0 => 0x10000,
+                size => size,
+            };
+            let copied = base.get(offset..offset + size).ok_or_else(corrupt)?;
+            result.extend_from_slice(copied);
+        } else if command != 0 {
+            let size = command as usize;
+            if rest.len() < size {
+                return Err(corrupt());
+            }
+            result.extend_from_slice(&rest[..size]);
+            rest = &rest[size..];
+        } else {
+            return Err(corrupt());
+        }
+    }
+    if result.len() != result_size {
+        return Err(corrupt());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::add_patch;
+
+    #[test]
+    fn test_parse() {
+        let patch = b"From: someone\n\
+            Subject: a change\n\
+            \n\
+            diff --git a/src/a.txt b/src/a.txt\n\
+            index 1234567..89abcde 100644\n\
+            --- a/src/a.txt\n\
+            +++ b/src/a.txt\n\
+            @@ -1,2 +1,2 @@ fn main\n\
+            -old\n\
+            +new\n\
+            \x20end\n\
+            \\ No newline at end of file\n\
+            diff --git a/run.sh b/run.sh\n\
+            old mode 100644\n\
+            new mode 100755\n\
+            diff --git a/old name b/new name\n\
+            similarity index 90%\n\
+            rename from old name\n\
+            rename to new name\n\
+            diff --git a/gone b/gone\n\
+            deleted file mode 100644\n\
+            index e69de29..0000000\n\
+            diff --git a/empty b/empty\n\
+            new file mode 100644\n\
+            index 0000000..e69de29\n\
+            Only in b: c.c\n\
+            --- a/c.c\t2024-01-01 00:00:00\n\
+            +++ b/c.c.orig\t2024-01-01 00:00:00\n\
+            @@ -0,0 +1 @@\n\
+            +x\n";
+        let patches = parse(patch, 1).unwrap();
+        assert_eq!(patches.len(), 6);
+        assert_eq!(patches[0].old_path.as_deref(), Some("src/a.txt"));
+        assert_eq!(patches[0].new_path.as_deref(), Some("src/a.txt"));
+        assert_eq!(patches[0].old_mode, Some(0o100644));
+        assert_eq!(patches[0].old_hash.as_deref(), Some("1234567"));
+        let Changes::Hunks(hunks) = &patches[0].changes else {
+            panic!("not hunks");
+        };
+        assert_eq!(
+            hunks[0].to_string(),
+            "@@ -1,2 +1,2 @@ fn main\n-old\n+new\n end\n\\ No newline at end of file\n"
+        );
+        assert_eq!(
+            (patches[1].path(), patches[1].old_mode, patches[1].new_mode),
+            ("run.sh", Some(0o100644), Some(0o100755))
+        );
+        assert_eq!(patches[1].changes, Changes::Hunks(vec![]));
+        assert_eq!(patches[2].old_path.as_deref(), Some("old name"));
+        assert_eq!(patches[2].new_path.as_deref(), Some("new name"));
+        assert_eq!(patches[3].old_path.as_deref(), Some("gone"));
+        assert_eq!(patches[3].new_path, None);
+        assert_eq!(patches[4].old_path, None);
+        assert_eq!(patches[4].new_path.as_deref(), Some("empty"));
+        // Plain diffs patch the new name, unless it's a backup of the old one.
+        assert_eq!(patches[5].old_path.as_deref(), Some("c.c"));
+        assert_eq!(patches[5].new_path.as_deref(), Some("c.c"));
+
+        assert!(parse(b"--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n-a\n", 1).is_err());
+        assert!(parse(b"diff --git a/x b/y\nold mode 100644\n", 1).is_err());
+        assert!(parse(b"not a patch\n", 1).unwrap().is_empty());
+
+        // Paths outside of the working tree or into .git are refused.
+        for path in [
+            "../x",
+            "a/../../x",
+            ".git/hooks/x",
+            "a/.GIT/x",
+            "a//x",
+            "./x",
+        ] {
+            let patch = format!("diff --git a/{path} b/{path}\nnew file mode 100644\n");
+            assert_eq!(
+                parse(patch.as_bytes(), 1).unwrap_err().to_string(),
+                format!("invalid path '{path}'")
+            );
+        }
+        let patch = b"--- /dev/null\n+++ /etc/passwd\n@@ -0,0 +1 @@\n+x\n";
+        assert!(parse(patch, 0).is_err());
+        let patch = b"diff --git a/x b/x\nrename from x\nrename to ../x\n";
+        assert!(parse(patch, 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_binary() {
+        let patch = b"diff --git a/bin b/bin\n\
+            index e69de29bb2d1d6434b8b29ae775ad8c2e48c5391..8352675d67aed6625ece79af41c27fdb4ee2e867 100644\n\
+            GIT binary patch\n\
+            literal 3\n\
+            KcmZQzWC8#H2LJ>B\n\
+            \n\
+            literal 0\n\
+            HcmV?d00001\n\
+            \n";
+        let patches = parse(patch, 1).unwrap();
+        assert_eq!(
+            patches[0].changes,
+            Changes::Binary {
+                forward: BinaryData::Literal(vec![0, 1, 2]),
+                reverse: Some(BinaryData::Literal(vec![])),
+            }
+        );
+        let reversed = patches[0].clone().reverse().unwrap();
+        assert_eq!(
+            reversed.new_hash.as_deref(),
+            Some("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391")
+        );
+        assert_eq!(
+            reversed.changes,
+            Changes::Binary {
+                forward: BinaryData::Literal(vec![]),
+                reverse: Some(BinaryData::Literal(vec![0, 1, 2])),
+            }
+        );
+        // Without the reverse data, binary patches can't be reversed.
+        let patch = &patch[..patch.len() - "literal 0\nHcmV?d00001\n\n".len()];
+        let patches = parse(patch, 1).unwrap();
+        assert!(patches[0].clone().reverse().is_err());
+    }
+
+    #[test]
+    fn test_apply_hunks() {
+        let old = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = b"1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n";
+        let hunks = add_patch::hunks(old, new, 3);
+        assert_eq!(apply_hunks(old, &hunks, None).unwrap(), new);
+
+        // Hunks are found where lines were added or removed before them.
+        let moved = b"0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        assert_eq!(
+            apply_hunks(moved, &hunks, None).unwrap(),
+            b"0\n1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n"
+        );
+        assert_eq!(
+            apply_hunks(&moved[4..], &hunks, None).unwrap(),
+            b"2\n3\n4\nfive\n6\n7\n8\n9\n10\n"[..]
+        );
+        // But hunks at the beginning or without trailing context must match
+        // there.
+        let hunks = add_patch::hunks(old, b"one\n2\n3\n4\n5\n6\n7\n8\n9\n10\n", 3);
+        assert_eq!(apply_hunks(moved, &hunks, None), Err(1));
+        let hunks = add_patch::hunks(old, b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n", 3);
+        assert_eq!(apply_hunks(&moved[..moved.len() - 3], &hunks, None), Err(8));
+
+        // Context lines that changed need a lower context.
+        let hunks = add_patch::hunks(old, new, 3);
+        let changed = b"1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n";
+        assert_eq!(apply_hunks(changed, &hunks, None), Err(2));
+        assert_eq!(apply_hunks(changed, &hunks, Some(3)), Err(2));
+        assert_eq!(
+            apply_hunks(changed, &hunks, Some(1)).unwrap(),
+            b"1\n2\nthree\n4\nfive\n6\n7\n8\n9\n10\n"
+        );
+
+        // Lines without a final newline only match the end of the file.
+        let hunks = add_patch::hunks(b"a\nb", b"a\nc", 3);
+        assert_eq!(apply_hunks(b"a\nb", &hunks, None).unwrap(), b"a\nc");
+        assert_eq!(apply_hunks(b"a\nb\n", &hunks, None), Err(1));
+        let hunks = add_patch::hunks(b"", b"new\n", 3);
+        assert_eq!(apply_hunks(b"", &hunks, None).unwrap(), b"new\n");
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        // Copy 3 bytes from offset 1, then insert "xy".
+        let delta = [5, 5, 0x91, 1, 3, 2, b'x', b'y'];
+        assert_eq!(apply_delta(b"abcde", &delta).unwrap(), b"bcdxy");
+        assert!(apply_delta(b"abcd", &delta).is_err());
+        assert!(apply_delta(b"abcde", &[5, 6, 0x91, 1, 3, 2, b'x', b'y']).is_err());
+        assert!(apply_delta(b"abcde", &[5, 1, 0]).is_err());
+    }
+}
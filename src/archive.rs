@@ -0,0 +1,606 @@
+use anyhow::{anyhow, Result};
+use std::io::Write;
+
+use crate::{
+    attributes::{AttributeValue, Attributes},
+    binary::{BinaryClassifier, TextAttribute},
+    config::Config,
+    object::{self, Object, ObjectType},
+    pretty,
+    repo::Repo,
+};
+
+/// The format of an archive.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A POSIX tar archive, with pax headers for long names.
+    #[default]
+    Tar,
+    /// A zip archive, with files compressed unless that makes them bigger.
+    Zip,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "tar" => Ok(Format::Tar),
+            "zip" => Ok(Format::Zip),
+            _ => Err(anyhow!("Unknown archive format '{s}'")),
+        }
+    }
+}
+
+/// The content of a tree to archive, like `git archive` does.
+///
+/// Like git, the attributes of the tree apply: paths with `export-ignore`
+/// are left out, and the `$Format:...$` placeholders of the files with
+/// `export-subst` are expanded for the commit, see
+/// [`pretty::format_commit`].
+pub struct Archive<'a> {
+    pub repo: &'a Repo,
+    pub tree: String,
+    /// The commit the tree comes from, if any, which is recorded in the
+    /// archive.
+    pub commit: Option<String>,
+    /// The modification time of all the entries, in seconds since the epoch.
+    pub mtime: i64,
+    /// Prepended to the paths of the entries, like `project-1.0/`.
+    pub prefix: String,
+}
+
+/// A file, directory, symlink or submodule of an archive.
+struct Entry {
+    /// The path, with the prefix and a trailing `/` for directories.
+    path: String,
+    /// The mode of the tree entry, like `100755`.
+    mode: u32,
+    hash: String,
+    /// Whether the file has the `export-subst` attribute.
+    subst: bool,
+    /// What the `diff` attribute says about the content being binary.
+    diff: TextAttribute,
+}
+
+/// Returns whether a tree entry is archived as a directory, which
+/// submodules are, empty.
+fn is_dir(mode: u32) -> bool {
+    matches!(mode >> 12, 0o04 | 0o16)
+}
+
+impl Entry {
+    fn is_dir(&self) -> bool {
+        is_dir(self.mode)
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.mode >> 12 == 0o12
+    }
+}
+
+impl Archive<'_> {
+    /// Writes the archive in a format.
+    pub fn write(&self, format: Format, writer: &mut dyn Write) -> Result<()> {
+        match format {
+            Format::Tar => self.write_tar(writer),
+            Format::Zip => self.write_zip(writer),
+        }
+    }
+
+    /// Calls `visit` for each entry of the archive, directories before their
+    /// content, in the order of the tree.
+    fn walk(&self, visit: &mut dyn FnMut(&Entry) -> Result<()>) -> Result<()> {
+        // A prefix that is a directory gets its own entry, like with git.
+        if self.prefix.ends_with('/') {
+            visit(&Entry {
+                path: self.prefix.clone(),
+                mode: 0o40000,
+                hash: self.tree.clone(),
+                subst: false,
+                diff: TextAttribute::Unspecified,
+            })?;
+        }
+        let attributes = Attributes::from_tree(self.repo, &self.tree)?;
+        self.walk_tree(&attributes, &self.tree, "", visit)
+    }
+
+    /// Walks a tree whose path is `base`, without the prefix.
+    fn walk_tree(
+        &self,
+        attributes: &Attributes,
+        hash: &str,
+        base: &str,
+        visit: &mut dyn FnMut(&Entry) -> Result<()>,
+    ) -> Result<()> {
+        let Object::Tree(tree) = Object::from_hash(self.repo, hash)? else {
+            return Err(anyhow!("Not a tree: {hash}"));
+        };
+        for file in tree.files {
+            let is_tree = file.object_type() == ObjectType::Tree;
+            let mode = u32::from_str_radix(&file.mode, 8)?;
+            let mut path = format!("{base}{}", file.name);
+            if is_dir(mode) {
+                path.push('/');
+            }
+            let attribute = |name| attributes.get(&path, name);
+            if attribute("export-ignore")? == Some(AttributeValue::Set) {
+                continue;
+            }
+            let entry = Entry {
+                path: format!("{}{path}", self.prefix),
+                mode,
+                hash: file.hash,
+                subst: attribute("export-subst")? == Some(AttributeValue::Set),
+                diff: match attribute("diff")? {
+                    Some(AttributeValue::Unset) => TextAttribute::Binary,
+                    _ => TextAttribute::Unspecified,
+                },
+            };
+            visit(&entry)?;
+            if is_tree {
+                self.walk_tree(attributes, &entry.hash, &path, visit)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the content of a file, or the target of a symlink.
+    fn content(&self, entry: &Entry) -> Result<Vec<u8>> {
+        let Object::Blob(blob) = Object::from_hash(self.repo, &entry.hash)? else {
+            return Err(anyhow!("Not a blob: {}", entry.hash));
+        };
+        match (&self.commit, entry.subst && !entry.is_symlink()) {
+            (Some(commit), true) => self.substitute(commit, &blob.content),
+            _ => Ok(blob.content),
+        }
+    }
+
+    /// Expands the `$Format:...$` placeholders of `export-subst` files.
+    fn substitute(&self, hash: &str, content: &[u8]) -> Result<Vec<u8>> {
+        let Object::Commit(commit) = Object::from_hash(self.repo, hash)? else {
+            return Err(anyhow!("Not a commit: {hash}"));
+        };
+        let abbreviator = object::Abbreviator::from_config(self.repo)?;
+        let mut output = vec![];
+        let mut rest = content;
+        while let Some(start) = find(rest, b"$Format:") {
+            let Some(length) = find(&rest[start + 8..], b"$") else {
+                break;
+            };
+            let format = String::from_utf8_lossy(&rest[start + 8..start + 8 + length]);
+            output.extend(&rest[..start]);
+            output.extend(pretty::format_commit(
+                self.repo,
+                &abbreviator,
+                hash,
+                &commit,
+                &format,
+            )?);
+            rest = &rest[start + 8 + length + 1..];
+        }
+        output.extend(rest);
+        Ok(output)
+    }
+
+    /// Writes the archive as a tar stream, byte for byte like `git archive`.
+    ///
+    /// A pax global header records the commit, names that don't fit in the
+    /// ustar fields get a pax extended header, and the archive is padded to
+    /// 10240-byte blocks.
+    fn write_tar(&self, writer: &mut dyn Write) -> Result<()> {
+        let mut tar = Tar {
+            writer,
+            written: 0,
+            mtime: self.mtime,
+        };
+        if let Some(commit) = &self.commit {
+            let records = pax_record("comment", commit.as_bytes());
+            tar.write_header(&TarHeader {
+                name: b"pax_global_header",
+                prefix: b"",
+                mode: 0o666,
+                size: records.len() as u64,
+                typeflag: b'g',
+                linkname: b"",
+            })?;
+            tar.write_content(&records)?;
+        }
+        self.walk(&mut |entry| {
+            let (typeflag, mode, content) = if entry.is_dir() {
+                (b'5', 0o775, vec![])
+            } else if entry.is_symlink() {
+                (b'2', 0o777, self.content(entry)?)
+            } else if entry.mode & 0o100 != 0 {
+                (b'0', 0o775, self.content(entry)?)
+            } else {
+                (b'0', 0o664, self.content(entry)?)
+            };
+            let path = entry.path.as_bytes();
+            let (linkname, size) = match entry.is_symlink() {
+                true => (&content[..], 0),
+                false => (&b""[..], content.len() as u64),
+            };
+
+            // Like git, names that don't fit refer to the pax header, named
+            // after the object.
+            let mut records = vec![];
+            let data_name = format!("{}.data", entry.hash);
+            let (prefix, name) = split_path(path).unwrap_or_else(|| {
+                records.extend(pax_record("path", path));
+                (&b""[..], data_name.as_bytes())
+            });
+            let see_header = format!("see {}.paxheader", entry.hash);
+            let linkname = if linkname.len() > 100 {
+                records.extend(pax_record("linkpath", linkname));
+                see_header.as_bytes()
+            } else {
+                linkname
+            };
+            if !records.is_empty() {
+                let header_name = format!("{}.paxheader", entry.hash);
+                tar.write_header(&TarHeader {
+                    name: header_name.as_bytes(),
+                    prefix: b"",
+                    mode: 0o666,
+                    size: records.len() as u64,
+                    typeflag: b'x',
+                    linkname: b"",
+                })?;
+                tar.write_content(&records)?;
+            }
+            tar.write_header(&TarHeader {
+                name,
+                prefix,
+                mode,
+                size,
+                typeflag,
+                linkname,
+            })?;
+            if typeflag == b'0' {
+                tar.write_content(&content)?;
+            }
+            Ok(())
+        })?;
+        tar.finish()
+    }
+
+    /// Writes the archive as a zip file, byte for byte like `git archive`.
+    ///
+    /// Files are deflated unless that doesn't make them smaller, and
+    /// flagged as text or binary. Modification times are in local time, and
+    /// in an extra field in UTC. The commit is the comment of the archive.
+    fn write_zip(&self, writer: &mut dyn Write) -> Result<()> {
+        let classifier = BinaryClassifier::from_config(&Config::load(self.repo)?)?;
+        let mut zip = Zip {
+            writer,
+            written: 0,
+            directory: vec![],
+            entries: 0,
+            mtime: self.mtime,
+            dos_time: dos_time(self.mtime),
+        };
+        self.walk(&mut |entry| {
+            let (content, external, creator) = if entry.is_dir() {
+                (None, 0o20, 0)
+            } else if entry.is_symlink() {
+                (
+                    Some(self.content(entry)?),
+                    (entry.mode | 0o777) << 16,
+                    0x0317,
+                )
+            } else if entry.mode & 0o111 != 0 {
+                (Some(self.content(entry)?), entry.mode << 16, 0x0317)
+            } else {
+                (Some(self.content(entry)?), 0, 0)
+            };
+            let text = content
+                .as_ref()
+                .is_some_and(|content| !classifier.is_binary(content, 0, entry.diff));
+            let content = content.unwrap_or_default();
+            let compress = !entry.is_dir() && !entry.is_symlink() && !content.is_empty();
+            zip.write_entry(&ZipEntry {
+                path: entry.path.as_bytes(),
+                content: &content,
+                compress,
+                text,
+                external,
+                creator,
+            })
+        })?;
+        zip.finish(self.commit.as_deref())
+    }
+}
+
+/// Returns the first position of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The size of tar records, and of the blocks they are written in.
+const RECORD_SIZE: usize = 512;
+const BLOCK_SIZE: usize = RECORD_SIZE * 20;
+
+struct Tar<'w> {
+    writer: &'w mut dyn Write,
+    written: usize,
+    mtime: i64,
+}
+
+struct TarHeader<'a> {
+    name: &'a [u8],
+    prefix: &'a [u8],
+    /// The permissions.
+    mode: u32,
+    size: u64,
+    typeflag: u8,
+    linkname: &'a [u8],
+}
+
+impl Tar<'_> {
+    fn write_header(&mut self, header: &TarHeader) -> Result<()> {
+        let mut record = [0_u8; RECORD_SIZE];
+        let mut field = |offset: usize, value: &[u8]| {
+            record[offset..offset + value.len()].copy_from_slice(value);
+        };
+        field(0, header.name);
+        field(100, format!("{:07o}", header.mode).as_bytes());
+        field(108, b"0000000");
+        field(116, b"0000000");
+        field(124, format!("{:011o}", header.size).as_bytes());
+        field(136, format!("{:011o}", self.mtime.max(0)).as_bytes());
+        field(148, b"        ");
+        field(156, &[header.typeflag]);
+        field(157, header.linkname);
+        field(257, b"ustar\x0000");
+        field(265, b"root");
+        field(297, b"root");
+        field(329, b"0000000");
+        field(337, b"0000000");
+        field(345, header.prefix);
+        let checksum: u32 = record.iter().map(|&b| b as u32).sum();
+        record[148..156].copy_from_slice(format!("{checksum:07o}\0").as_bytes());
+        self.write(&record)
+    }
+
+    /// Writes content, padded to a whole number of records.
+    fn write_content(&mut self, content: &[u8]) -> Result<()> {
+        self.write(content)?;
+        let padding = (RECORD_SIZE - content.len() % RECORD_SIZE) % RECORD_SIZE;
+        self.write(&[0; RECORD_SIZE][..padding])
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.writer.write_all(data)?;
+        self.written += data.len();
+        Ok(())
+    }
+
+    /// Ends the archive with at least two empty records, filling the last
+    /// block.
+    fn finish(&mut self) -> Result<()> {
+        let mut tail = BLOCK_SIZE - self.written % BLOCK_SIZE;
+        if tail < 2 * RECORD_SIZE {
+            tail += BLOCK_SIZE;
+        }
+        self.write(&vec![0; tail])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+struct Zip<'w> {
+    writer: &'w mut dyn Write,
+    written: usize,
+    /// The central directory, written at the end.
+    directory: Vec<u8>,
+    entries: usize,
+    mtime: i64,
+    /// The modification time as MS-DOS (time, date).
+    dos_time: (u16, u16),
+}
+
+struct ZipEntry<'a> {
+    path: &'a [u8],
+    content: &'a [u8],
+    /// Whether to try deflating the content.
+    compress: bool,
+    text: bool,
+    /// The external attributes, with the Unix mode in the high bytes.
+    external: u32,
+    /// The version made by, which tells that the external attributes are
+    /// Unix ones when they have a mode.
+    creator: u16,
+}
+
+/// The `UT` extra field: a flag telling that only the modification time
+/// follows, in seconds since the epoch.
+const ZIP_EXTRA_MTIME: u16 = 0x5455;
+const ZIP_EXTRA_MTIME_SIZE: u16 = 9;
+/// The flag telling that a path is in UTF-8.
+const ZIP_UTF8: u16 = 1 << 11;
+
+impl Zip<'_> {
+    fn write_entry(&mut self, entry: &ZipEntry) -> Result<()> {
+        let crc = {
+            let mut crc = flate2::Crc::new();
+            crc.update(entry.content);
+            crc.sum()
+        };
+        let mut method = 0_u16;
+        let mut data = entry.content.to_vec();
+        if entry.compress {
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(entry.content)?;
+            let deflated = encoder.finish()?;
+            if deflated.len() < entry.content.len() {
+                method = 8;
+                data = deflated;
+            }
+        }
+        let flags = match std::str::from_utf8(entry.path) {
+            Ok(path) if !path.is_ascii() => ZIP_UTF8,
+            _ => 0,
+        };
+        let fits = |value: usize| -> Result<u32> {
+            u32::try_from(value).map_err(|_| anyhow!("Zip archives over 4 GiB are not supported"))
+        };
+        let (time, date) = self.dos_time;
+        let mut extra = vec![];
+        extra.extend(ZIP_EXTRA_MTIME.to_le_bytes());
+        extra.extend(5_u16.to_le_bytes());
+        extra.push(1);
+        extra.extend((self.mtime as u32).to_le_bytes());
+
+        // The fields shared by the local header and the central directory.
+        let mut common = vec![];
+        common.extend(10_u16.to_le_bytes());
+        common.extend(flags.to_le_bytes());
+        common.extend(method.to_le_bytes());
+        common.extend(time.to_le_bytes());
+        common.extend(date.to_le_bytes());
+        common.extend(crc.to_le_bytes());
+        common.extend(fits(data.len())?.to_le_bytes());
+        common.extend(fits(entry.content.len())?.to_le_bytes());
+        common.extend((entry.path.len() as u16).to_le_bytes());
+        common.extend(ZIP_EXTRA_MTIME_SIZE.to_le_bytes());
+
+        let directory = &mut self.directory;
+        directory.extend(0x02014b50_u32.to_le_bytes());
+        directory.extend(entry.creator.to_le_bytes());
+        directory.extend(&common);
+        directory.extend(0_u16.to_le_bytes());
+        directory.extend(0_u16.to_le_bytes());
+        directory.extend(u16::from(entry.text).to_le_bytes());
+        directory.extend(entry.external.to_le_bytes());
+        directory.extend(fits(self.written)?.to_le_bytes());
+        directory.extend(entry.path);
+        directory.extend(&extra);
+        self.entries += 1;
+
+        let mut header = vec![];
+        header.extend(0x04034b50_u32.to_le_bytes());
+        header.extend(&common);
+        header.extend(entry.path);
+        header.extend(&extra);
+        self.write(&header)?;
+        self.write(&data)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.writer.write_all(data)?;
+        self.written += data.len();
+        Ok(())
+    }
+
+    /// Writes the central directory and the trailer, with the commit as the
+    /// comment.
+    fn finish(&mut self, commit: Option<&str>) -> Result<()> {
+        let entries = u16::try_from(self.entries)
+            .map_err(|_| anyhow!("Zip archives over 65535 files are not supported"))?;
+        let comment = commit.unwrap_or_default();
+        let mut trailer = vec![];
+        trailer.extend(0x06054b50_u32.to_le_bytes());
+        trailer.extend(0_u16.to_le_bytes());
+        trailer.extend(0_u16.to_le_bytes());
+        trailer.extend(entries.to_le_bytes());
+        trailer.extend(entries.to_le_bytes());
+        trailer.extend((self.directory.len() as u32).to_le_bytes());
+        trailer.extend((self.written as u32).to_le_bytes());
+        trailer.extend((comment.len() as u16).to_le_bytes());
+        trailer.extend(comment.as_bytes());
+        let directory = std::mem::take(&mut self.directory);
+        self.write(&directory)?;
+        self.write(&trailer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Converts a timestamp to an MS-DOS (time, date) in local time, with a
+/// two-second precision.
+fn dos_time(timestamp: i64) -> (u16, u16) {
+    #[cfg(unix)]
+    {
+        let time = timestamp as libc::time_t;
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        if !unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+            let time = tm.tm_sec / 2 + tm.tm_min * 32 + tm.tm_hour * 2048;
+            let date = tm.tm_mday + (tm.tm_mon + 1) * 32 + (tm.tm_year + 1900 - 1980) * 512;
+            return (time as u16, date as u16);
+        }
+    }
+    let _ = timestamp;
+    (0, 0x21)
+}
+
+/// Splits a path that doesn't fit in the 100 bytes of the name field into
+/// the 155 bytes of the prefix field and the name, at a `/`. Returns the
+/// whole path as the name if it fits, or `None` if it can't be split.
+fn split_path(path: &[u8]) -> Option<(&[u8], &[u8])> {
+    if path.len() <= 100 {
+        return Some((b"", path));
+    }
+    // A trailing `/` stays in the name.
+    let mut end = path.len();
+    if path.ends_with(b"/") {
+        end -= 1;
+    }
+    let slash = path[..end.min(155)].iter().rposition(|&b| b == b'/')?;
+    let name = &path[slash + 1..];
+    (slash > 0 && name.len() <= 100).then(|| (&path[..slash], name))
+}
+
+/// Returns a pax header record, `<length> <key>=<value>\n`, where the length
+/// counts the whole record, including its own digits.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let base = key.len() + value.len() + 3;
+    let mut length = base + base.to_string().len();
+    if length.to_string().len() > base.to_string().len() {
+        length += 1;
+    }
+    let mut record = format!("{length} {key}=").into_bytes();
+    record.extend(value);
+    record.push(b'\n');
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pax_record() {
+        assert_eq!(pax_record("path", b"abc"), b"12 path=abc\n");
+        assert_eq!(pax_record("a", b"bcd"), b"8 a=bcd\n");
+        let value = "x".repeat(89);
+        let record = pax_record("k", value.as_bytes());
+        assert_eq!(record.len(), 95);
+        assert!(record.starts_with(b"95 k="));
+        // 99 bytes with 2 digits would be 101 with 3.
+        let value = "x".repeat(94);
+        let record = pax_record("k", value.as_bytes());
+        assert_eq!(record.len(), 101);
+        assert!(record.starts_with(b"101 k="));
+    }
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(split_path(b"a/b"), Some((&b""[..], &b"a/b"[..])));
+        let long_dir = format!("{}/", "d".repeat(120));
+        let long_file = format!("{long_dir}file");
+        assert_eq!(
+            split_path(long_file.as_bytes()),
+            Some((&long_dir.as_bytes()[..120], &b"file"[..]))
+        );
+        let dir = format!("{long_dir}sub/");
+        assert_eq!(
+            split_path(dir.as_bytes()),
+            Some((&long_dir.as_bytes()[..120], &b"sub/"[..]))
+        );
+        assert_eq!(split_path("f".repeat(101).as_bytes()), None);
+    }
+}
@@ -0,0 +1,571 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    config::{self, Config},
+    format,
+    index::FileModes,
+    object::{Object, ObjectType},
+    repo::Repo,
+    wildmatch::{wildmatch, Flags},
+};
+
+/// The state of an attribute for a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeValue {
+    /// `name`
+    Set,
+    /// `-name`
+    Unset,
+    /// `name=value`
+    Value(String),
+}
+
+impl std::fmt::Display for AttributeValue {
+    /// Formats the value like `check-attr`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AttributeValue::Set => write!(f, "set"),
+            AttributeValue::Unset => write!(f, "unset"),
+            AttributeValue::Value(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+/// The attributes of a line, `None` standing for `!name`, which makes an
+/// attribute unspecified again.
+type States = Vec<(String, Option<AttributeValue>)>;
+
+/// A line of an attributes file: a pattern followed by attributes.
+#[derive(Debug)]
+struct Rule {
+    /// The pattern without the leading and trailing `/`.
+    pattern: String,
+    /// Whether the pattern has a `/` other than a trailing one, so that it's
+    /// matched against the path relative to `base` instead of the file name.
+    anchored: bool,
+    /// Whether the pattern ends with `/` and only matches directories.
+    dir_only: bool,
+    /// The directory of the `.gitattributes` file, with a trailing `/`, or
+    /// empty for the root of the working tree and the other files.
+    base: String,
+    states: States,
+}
+
+impl Rule {
+    fn matches(&self, path: &str, is_dir: bool, ignore_case: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let base = self.base.as_bytes();
+        let relative = match path.as_bytes().get(..base.len()) {
+            Some(prefix) if prefix == base => &path[base.len()..],
+            Some(prefix) if ignore_case && prefix.eq_ignore_ascii_case(base) => &path[base.len()..],
+            _ => return false,
+        };
+        let flags = Flags {
+            casefold: ignore_case,
+            ..Flags::PATHNAME
+        };
+        if self.anchored {
+            wildmatch(self.pattern.as_bytes(), relative.as_bytes(), flags)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(self.pattern.as_bytes(), name.as_bytes(), flags)
+        }
+    }
+}
+
+/// The attributes set by `.gitattributes` files, `.git/info/attributes` and
+/// `core.attributesFile`.
+///
+/// A `.gitattributes` file applies to the files of its directory and below,
+/// and takes precedence over the ones of the parent directories, all of them
+/// being overridden by `.git/info/attributes`. Within a file, later lines
+/// take precedence.
+///
+/// Macros like `[attr]binary -diff -merge -text` can be defined in all files
+/// but the `.gitattributes` of subdirectories. Setting a macro sets its
+/// attributes, with a lower precedence than the other attributes of the line.
+/// The `binary` macro is built in.
+///
+/// The `.gitattributes` files of subdirectories are read lazily, when a path
+/// in their directory is looked up.
+#[derive(Debug, Default)]
+pub struct Attributes {
+    /// The root of the working tree, or `None` to only use the rules given
+    /// to [`Attributes::parse`].
+    root: Option<PathBuf>,
+    /// The rules of `core.attributesFile`, which have the lowest precedence.
+    global: Vec<Rule>,
+    /// The rules of `.git/info/attributes`, which have the highest.
+    info: Vec<Rule>,
+    macros: HashMap<String, States>,
+    /// Whether patterns match paths whatever their case, with
+    /// `core.ignoreCase`.
+    ignore_case: bool,
+    lazy: Mutex<Lazy>,
+}
+
+/// The part of [`Attributes`] filled as paths are looked up.
+#[derive(Debug, Default)]
+struct Lazy {
+    /// The rules of the `.gitattributes` file of each directory that was
+    /// read, keyed by directory with a trailing `/`, or empty for the root.
+    dirs: HashMap<String, Arc<Vec<Rule>>>,
+    /// The names of all attributes in the order they were first read, which
+    /// is the order `check-attr --all` lists them in.
+    names: Vec<String>,
+    /// The warnings about ignored lines not taken yet, see
+    /// [`Attributes::take_warnings`].
+    warnings: Vec<String>,
+}
+
+impl Attributes {
+    pub fn load(repo: &Repo) -> Result<Attributes> {
+        let root_rules = read_file(&repo.root.join(".gitattributes"))?;
+        let mut attributes = Attributes::with_root_rules(repo, &root_rules)?;
+        attributes.root = Some(repo.root.clone());
+        Ok(attributes)
+    }
+
+    /// Like [`Attributes::load`], with the `.gitattributes` files of a tree
+    /// instead of the ones of the working tree, like `git archive`.
+    pub fn from_tree(repo: &Repo, tree: &str) -> Result<Attributes> {
+        let mut files = vec![];
+        collect_tree_files(repo, tree, "", &mut files)?;
+        let root_rules = match files.iter().position(|(dir, _)| dir.is_empty()) {
+            Some(i) => files.remove(i).1,
+            None => String::new(),
+        };
+        let mut attributes = Attributes::with_root_rules(repo, &root_rules)?;
+        let lazy = attributes.lazy_mut();
+        for (dir, content) in files {
+            let source = format!("{dir}.gitattributes");
+            let rules = parse_lines(&content, &source, &dir, lazy, None);
+            lazy.dirs.insert(dir, Arc::new(rules));
+        }
+        Ok(attributes)
+    }
+
+    /// Reads `core.attributesFile` and `.git/info/attributes`, with the rules
+    /// of the root `.gitattributes` between them.
+    fn with_root_rules(repo: &Repo, root_rules: &str) -> Result<Attributes> {
+        let config = Config::load(repo)?;
+        let attributes_file = config.get_path("core.attributesFile").or_else(|| {
+            match std::env::var_os("XDG_CONFIG_HOME") {
+                Some(xdg_config_home) => Some(Path::new(&xdg_config_home).join("git/attributes")),
+                None => config::home_dir().map(|home| home.join(".config/git/attributes")),
+            }
+        });
+        let mut attributes = Attributes::builtin();
+        attributes.ignore_case = FileModes::from_config(&config)?.ignore_case;
+        if let Some(path) = attributes_file {
+            let source = path.display().to_string();
+            attributes.global = attributes.read(&path, &source, "")?;
+        }
+        let root_rules = attributes.parse_rules(root_rules, ".gitattributes", "");
+        attributes
+            .lazy_mut()
+            .dirs
+            .insert(String::new(), Arc::new(root_rules));
+        let info = repo.common_dir().join("info/attributes");
+        attributes.info = attributes.read(&info, ".git/info/attributes", "")?;
+        Ok(attributes)
+    }
+
+    /// Parses the lines of a `.gitattributes` file at the root of the working
+    /// tree, each being a pattern followed by attributes. Blank lines and
+    /// comments are skipped, as well as negative patterns, which git forbids.
+    pub fn parse(s: &str) -> Attributes {
+        let mut attributes = Attributes::builtin();
+        let rules = attributes.parse_rules(s, ".gitattributes", "");
+        attributes
+            .lazy_mut()
+            .dirs
+            .insert(String::new(), Arc::new(rules));
+        attributes
+    }
+
+    fn builtin() -> Attributes {
+        let mut attributes = Attributes::default();
+        attributes.parse_rules("[attr]binary -diff -merge -text", "[builtin]", "");
+        attributes
+    }
+
+    fn lazy_mut(&mut self) -> &mut Lazy {
+        self.lazy.get_mut().unwrap()
+    }
+
+    /// Returns the value of an attribute for a path relative to the root of
+    /// the working tree, or `None` if it's unspecified. Directories are
+    /// looked up with a trailing `/`.
+    pub fn get(&self, path: &str, name: &str) -> Result<Option<AttributeValue>> {
+        Ok(self.states(path)?.remove(name).flatten())
+    }
+
+    /// Returns all the attributes that aren't unspecified for a path, in the
+    /// order they were first read.
+    pub fn all(&self, path: &str) -> Result<Vec<(String, AttributeValue)>> {
+        let mut states = self.states(path)?;
+        let lazy = self.lazy.lock().unwrap();
+        Ok(lazy
+            .names
+            .iter()
+            .filter_map(|name| Some((name.clone(), states.remove(name)??)))
+            .collect())
+    }
+
+    /// Returns the attributes mentioned by the rules matching a path.
+    ///
+    /// Like git, rules are applied from the highest precedence to the lowest,
+    /// and each attribute is only set by the first rule mentioning it.
+    fn states(&self, path: &str) -> Result<HashMap<String, Option<AttributeValue>>> {
+        let is_dir = path.ends_with('/');
+        let path = path.trim_end_matches('/');
+        // From the root, whose rules are always read, to the deepest.
+        let mut dirs: Vec<_> = self
+            .lazy
+            .lock()
+            .unwrap()
+            .dirs
+            .get("")
+            .cloned()
+            .into_iter()
+            .collect();
+        for (i, _) in path.match_indices('/') {
+            let dir = format!("{}/", &path[..i]);
+            dirs.push(self.dir_rules(&dir)?);
+        }
+
+        let mut states = HashMap::new();
+        let rules = self
+            .info
+            .iter()
+            .rev()
+            .chain(dirs.iter().rev().flat_map(|rules| rules.iter().rev()))
+            .chain(self.global.iter().rev());
+        for rule in rules.filter(|rule| rule.matches(path, is_dir, self.ignore_case)) {
+            fill(&rule.states, &self.macros, &mut states);
+        }
+        Ok(states)
+    }
+
+    /// Returns the warnings about the lines that were ignored since the last
+    /// call, like lines with an invalid attribute name. Since the files of
+    /// subdirectories are read lazily, there may be more after lookups.
+    pub fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut self.lazy.lock().unwrap().warnings)
+    }
+
+    /// Returns the rules of the `.gitattributes` file of a subdirectory,
+    /// reading it if needed.
+    fn dir_rules(&self, dir: &str) -> Result<Arc<Vec<Rule>>> {
+        if let Some(rules) = self.lazy.lock().unwrap().dirs.get(dir) {
+            return Ok(rules.clone());
+        }
+        let Some(root) = &self.root else {
+            return Ok(Arc::default());
+        };
+        let source = format!("{dir}.gitattributes");
+        let content = read_file(&root.join(&source))?;
+        let mut lazy = self.lazy.lock().unwrap();
+        let rules = Arc::new(parse_lines(&content, &source, dir, &mut lazy, None));
+        lazy.dirs.insert(dir.to_string(), rules.clone());
+        Ok(rules)
+    }
+
+    /// Reads a file that may define macros, which may not exist.
+    fn read(&mut self, path: &Path, source: &str, base: &str) -> Result<Vec<Rule>> {
+        Ok(self.parse_rules(&read_file(path)?, source, base))
+    }
+
+    fn parse_rules(&mut self, content: &str, source: &str, base: &str) -> Vec<Rule> {
+        let lazy = self.lazy.get_mut().unwrap();
+        parse_lines(content, source, base, lazy, Some(&mut self.macros))
+    }
+}
+
+/// Sets the attributes of a rule that aren't set yet, from the last one, and
+/// the attributes of the macros it sets.
+fn fill(
+    rule: &States,
+    macros: &HashMap<String, States>,
+    states: &mut HashMap<String, Option<AttributeValue>>,
+) {
+    for (name, value) in rule.iter().rev() {
+        if states.contains_key(name) {
+            continue;
+        }
+        states.insert(name.clone(), value.clone());
+        if let (Some(AttributeValue::Set), Some(macro_states)) = (value, macros.get(name)) {
+            fill(macro_states, macros, states);
+        }
+    }
+}
+
+/// Collects the content of the `.gitattributes` files of a tree, keyed by
+/// directory with a trailing `/`, or empty for the root.
+fn collect_tree_files(
+    repo: &Repo,
+    tree: &str,
+    dir: &str,
+    files: &mut Vec<(String, String)>,
+) -> Result<()> {
+    let Object::Tree(tree) = Object::from_hash(repo, tree)? else {
+        return Err(anyhow!("Not a tree: {tree}"));
+    };
+    for file in tree.files {
+        match file.object_type() {
+            ObjectType::Tree => {
+                collect_tree_files(repo, &file.hash, &format!("{dir}{}/", file.name), files)?
+            }
+            ObjectType::Blob if file.name == ".gitattributes" && file.mode != "120000" => {
+                if let Object::Blob(blob) = Object::from_hash(repo, &file.hash)? {
+                    let content = String::from_utf8_lossy(&blob.content).into_owned();
+                    files.push((dir.to_string(), content));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Reads an attributes file, which may not exist.
+fn read_file(path: &Path) -> Result<String> {
+    match fs::read(path) {
+        Ok(content) => Ok(String::from_utf8_lossy(&content).into_owned()),
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
+            Ok(String::new())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parses the lines of an attributes file, registering the attribute names
+/// in `lazy`. Macro definitions are added to `macros`, or ignored with a
+/// warning if the file can't define them.
+///
+/// Like git, lines with an invalid attribute name are ignored with a warning,
+/// and so are negative patterns.
+fn parse_lines(
+    content: &str,
+    source: &str,
+    base: &str,
+    lazy: &mut Lazy,
+    mut macros: Option<&mut HashMap<String, States>>,
+) -> Vec<Rule> {
+    let Lazy {
+        names, warnings, ..
+    } = lazy;
+    let mut rules = vec![];
+    let mut warned_negative = false;
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim_start_matches([' ', '\t', '\r']);
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (pattern, rest) = match format::unquote_path(line) {
+            Some((pattern, rest)) => (pattern, rest),
+            None => {
+                let end = line.find([' ', '\t', '\r']).unwrap_or(line.len());
+                (line[..end].to_string(), &line[end..])
+            }
+        };
+        let mut states = vec![];
+        let mut valid = true;
+        for field in rest.split_whitespace() {
+            let (name, value) = if let Some(name) = field.strip_prefix('-') {
+                (name, Some(AttributeValue::Unset))
+            } else if let Some(name) = field.strip_prefix('!') {
+                (name, None)
+            } else if let Some((name, value)) = field.split_once('=') {
+                (name, Some(AttributeValue::Value(value.to_string())))
+            } else {
+                (field, Some(AttributeValue::Set))
+            };
+            if !is_valid_name(name) {
+                warnings.push(format!(
+                    "{name} is not a valid attribute name: {source}:{}",
+                    i + 1
+                ));
+                valid = false;
+                break;
+            }
+            states.push((name.to_string(), value));
+        }
+        if !valid {
+            continue;
+        }
+
+        if let Some(name) = pattern.strip_prefix("[attr]") {
+            let Some(macros) = macros.as_deref_mut() else {
+                warnings.push(format!("{pattern}{rest} not allowed: {source}:{}", i + 1));
+                continue;
+            };
+            if !is_valid_name(name) {
+                warnings.push(format!(
+                    "{name} is not a valid attribute name: {source}:{}",
+                    i + 1
+                ));
+                continue;
+            }
+            register(names, name);
+            states.iter().for_each(|(name, _)| register(names, name));
+            macros.insert(name.to_string(), states);
+            continue;
+        }
+        if pattern.starts_with('!') {
+            if !warned_negative {
+                warnings.push(
+                    "warning: Negative patterns are ignored in git attributes\n\
+                     Use '\\!' for literal leading exclamation."
+                        .to_string(),
+                );
+                warned_negative = true;
+            }
+            continue;
+        }
+        states.iter().for_each(|(name, _)| register(names, name));
+
+        // `\!` starts a pattern with a literal `!`.
+        let pattern = pattern.strip_prefix('\\').unwrap_or(&pattern);
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        if pattern.is_empty() {
+            continue;
+        }
+        rules.push(Rule {
+            pattern: pattern.to_string(),
+            anchored,
+            dir_only,
+            base: base.to_string(),
+            states,
+        });
+    }
+    rules
+}
+
+fn register(names: &mut Vec<String>, name: &str) {
+    if !names.iter().any(|other| other == name) {
+        names.push(name.to_string());
+    }
+}
+
+/// Returns whether a name is a valid attribute name: made of letters,
+/// digits, `-`, `.` and `_`, and not starting with `-`.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get() {
+        let attributes = Attributes::parse(
+            "# Comment\n\
+             *.pdf diff=pdf -text\n\
+             docs/*.pdf diff\n\
+             !*.txt text\n\
+             /build.pdf -diff\n",
+        );
+        let get = |path: &str, name: &str| attributes.get(path, name).unwrap();
+        let pdf = AttributeValue::Value("pdf".to_string());
+        assert_eq!(get("a.pdf", "diff"), Some(pdf.clone()));
+        assert_eq!(get("dir/a.pdf", "diff"), Some(pdf.clone()));
+        assert_eq!(get("a.pdf", "text"), Some(AttributeValue::Unset));
+        assert_eq!(get("docs/a.pdf", "diff"), Some(AttributeValue::Set));
+        assert_eq!(get("docs/sub/a.pdf", "diff"), Some(pdf.clone()));
+        assert_eq!(get("build.pdf", "diff"), Some(AttributeValue::Unset));
+        assert_eq!(get("dir/build.pdf", "diff"), Some(pdf));
+        assert_eq!(get("a.txt", "text"), None);
+        assert_eq!(get("a.pdf", "eol"), None);
+    }
+
+    #[test]
+    fn test_macros() {
+        let attributes = Attributes::parse(
+            "[attr]mine foo -bar\n\
+             *.bin binary diff\n\
+             *.m mine\n\
+             *.n mine -foo\n\
+             *.u -mine\n\
+             *.c text eol=lf\n\
+             *.c !text\n\
+             d/ dir\n",
+        );
+        let all = |path: &str| {
+            attributes
+                .all(path)
+                .unwrap()
+                .into_iter()
+                .map(|(name, value)| format!("{name}: {value}"))
+                .collect::<Vec<_>>()
+        };
+        // The attributes of the line override the ones of its macros.
+        assert_eq!(
+            all("x.bin"),
+            ["binary: set", "diff: set", "merge: unset", "text: unset"]
+        );
+        assert_eq!(all("x.m"), ["mine: set", "foo: set", "bar: unset"]);
+        assert_eq!(all("x.n"), ["mine: set", "foo: unset", "bar: unset"]);
+        // Unsetting a macro doesn't touch its attributes.
+        assert_eq!(all("x.u"), ["mine: unset"]);
+        // `!` makes an attribute unspecified again.
+        assert_eq!(all("x.c"), ["eol: lf"]);
+        assert_eq!(all("d"), Vec::<String>::new());
+        assert_eq!(all("d/"), ["dir: set"]);
+    }
+
+    #[test]
+    fn test_load() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        fs::create_dir_all(repo.git_dir().join("info")).unwrap();
+        fs::create_dir_all(repo.root.join("sub/deep")).unwrap();
+        fs::write(repo.root.join(".gitattributes"), "*.c text zeta\n").unwrap();
+        fs::write(
+            repo.root.join("sub/.gitattributes"),
+            "*.c -text\n[attr]ignored x\ndeep/*.c deep\n",
+        )
+        .unwrap();
+        fs::write(repo.git_dir().join("info/attributes"), "*.c -zeta\n").unwrap();
+        let attributes = Attributes::load(&repo).unwrap();
+        let get = |path: &str, name: &str| attributes.get(path, name).unwrap();
+
+        assert_eq!(get("a.c", "text"), Some(AttributeValue::Set));
+        assert_eq!(get("sub/a.c", "text"), Some(AttributeValue::Unset));
+        assert_eq!(get("sub/deep/a.c", "text"), Some(AttributeValue::Unset));
+        assert_eq!(get("sub/deep/a.c", "deep"), Some(AttributeValue::Set));
+        // Patterns with a slash are relative to their `.gitattributes`.
+        assert_eq!(get("deep/a.c", "deep"), None);
+        // `.git/info/attributes` overrides everything.
+        assert_eq!(get("sub/a.c", "zeta"), Some(AttributeValue::Unset));
+        // Subdirectories can't define macros.
+        assert_eq!(get("sub/a.c", "ignored"), None);
+        assert_eq!(
+            attributes.take_warnings(),
+            ["[attr]ignored x not allowed: sub/.gitattributes:2"]
+        );
+        assert!(attributes.take_warnings().is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Suggestions with a larger distance aren't shown.
+const SIMILARITY_FLOOR: usize = 7;
+
+/// What to do with an unknown command, from `help.autocorrect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Autocorrect {
+    /// Don't even suggest similar commands.
+    Never,
+    /// Only suggest similar commands.
+    #[default]
+    Show,
+    /// Ask whether to run the most similar command.
+    Prompt,
+    /// Run the most similar command right away.
+    Immediate,
+    /// Run the most similar command after a delay, so it can be canceled.
+    Delay(Duration),
+}
+
+impl Autocorrect {
+    /// Reads `help.autocorrect`, which is `never`, `show`, `prompt`,
+    /// `immediate`, or a delay in tenths of a second, where 0 means `show`
+    /// and negative delays `immediate`.
+    pub fn from_config(config: &Config) -> Result<Autocorrect> {
+        Ok(match config.get("help.autocorrect") {
+            None | Some("show") => Autocorrect::Show,
+            Some("never") => Autocorrect::Never,
+            Some("prompt") => Autocorrect::Prompt,
+            Some("immediate") => Autocorrect::Immediate,
+            Some(_) => match config.get_int("help.autocorrect")? {
+                Some(0) | None => Autocorrect::Show,
+                Some(tenths) if tenths < 0 => Autocorrect::Immediate,
+                Some(tenths) => Autocorrect::Delay(Duration::from_millis(tenths as u64 * 100)),
+            },
+        })
+    }
+}
+
+/// Returns the commands most similar to an unknown command, best first, or
+/// nothing if none is similar enough. A single suggestion can be run
+/// instead of the unknown command.
+///
+/// Like git, commands starting with the unknown command are the most
+/// similar, then the commands with the smallest edit distance, where
+/// swapping two letters is free and deleting letters is costly.
+pub fn similar_commands<'a>(unknown: &str, commands: &[&'a str]) -> Vec<&'a str> {
+    let mut scored: Vec<(usize, &str)> = commands
+        .iter()
+        .map(|&command| {
+            let score = if command.starts_with(unknown) {
+                0
+            } else {
+                levenshtein(unknown, command, 0, 2, 1, 3) + 1
+            };
+            (score, command)
+        })
+        .collect();
+    scored.sort();
+
+    let prefix_count = scored.iter().take_while(|(score, _)| *score == 0).count();
+    // A prefix of everything is too ambiguous.
+    let Some(&(best, _)) = scored.get(prefix_count) else {
+        return vec![];
+    };
+    if best >= SIMILARITY_FLOOR {
+        return vec![];
+    }
+    scored
+        .into_iter()
+        .take_while(|&(score, _)| score == 0 || score == best)
+        .map(|(_, command)| command)
+        .collect()
+}
+
+/// The edit distance between two strings, with weights for swapping two
+/// adjacent characters, substituting, adding and deleting characters.
+fn levenshtein(
+    a: &str,
+    b: &str,
+    swap: usize,
+    substitute: usize,
+    add: usize,
+    delete: usize,
+) -> usize {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    // The rows for the first i - 1, i and i + 1 characters of `a`.
+    let mut row0 = vec![0; b.len() + 1];
+    let mut row1: Vec<usize> = (0..=b.len()).map(|j| j * add).collect();
+    let mut row2 = vec![0; b.len() + 1];
+    for i in 0..a.len() {
+        row2[0] = (i + 1) * delete;
+        for j in 0..b.len() {
+            row2[j + 1] = row1[j] + substitute * usize::from(a[i] != b[j]);
+            if i > 0 && j > 0 && a[i - 1] == b[j] && a[i] == b[j - 1] {
+                row2[j + 1] = row2[j + 1].min(row0[j - 1] + swap);
+            }
+            row2[j + 1] = row2[j + 1].min(row1[j + 1] + delete).min(row2[j] + add);
+        }
+        std::mem::swap(&mut row0, &mut row1);
+        std::mem::swap(&mut row1, &mut row2);
+    }
+    row1[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMANDS: &[&str] = &["add", "commit", "stage", "stash", "status", "switch"];
+
+    #[test]
+    fn test_similar_commands() {
+        assert_eq!(similar_commands("stauts", COMMANDS), vec!["status"]);
+        assert_eq!(similar_commands("comit", COMMANDS), vec!["commit"]);
+        // Prefix matches come first, then the most similar other commands.
+        assert_eq!(
+            similar_commands("sta", COMMANDS),
+            vec!["stage", "stash", "status", "switch"]
+        );
+        assert!(similar_commands("xyzzy", COMMANDS).is_empty());
+        assert!(similar_commands("", COMMANDS).is_empty());
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("status", "status", 0, 2, 1, 3), 0);
+        // Swaps are free.
+        assert_eq!(levenshtein("stauts", "status", 0, 2, 1, 3), 0);
+        assert_eq!(levenshtein("comit", "commit", 0, 2, 1, 3), 1);
+        assert_eq!(levenshtein("commmit", "commit", 0, 2, 1, 3), 3);
+        assert_eq!(levenshtein("kitten", "sitting", 1, 1, 1, 1), 3);
+    }
+
+    #[test]
+    fn test_from_config() {
+        let autocorrect = |value: &str| {
+            let config = Config::parse(&format!("[help]\nautocorrect = {value}")).unwrap();
+            Autocorrect::from_config(&config).unwrap()
+        };
+        assert_eq!(autocorrect("never"), Autocorrect::Never);
+        assert_eq!(autocorrect("0"), Autocorrect::Show);
+        assert_eq!(autocorrect("-1"), Autocorrect::Immediate);
+        assert_eq!(
+            autocorrect("15"),
+            Autocorrect::Delay(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            Autocorrect::from_config(&Config::default()).unwrap(),
+            Autocorrect::Show
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::{anyhow, Result};
+
+/// The digits of git's base85, which is used in binary patches.
+const DIGITS: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Encodes data as 5 digits per 4 bytes, the last bytes being padded with
+/// zeros.
+pub fn encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(4) * 5);
+    for chunk in data.chunks(4) {
+        let mut bytes = [0; 4];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        let mut value = u32::from_be_bytes(bytes);
+        let mut digits = [0; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = DIGITS[(value % 85) as usize];
+            value /= 85;
+        }
+        encoded.extend(digits.map(char::from));
+    }
+    encoded
+}
+
+/// Decodes `len` bytes from their digits, the reverse of [`encode`].
+pub fn decode(text: &[u8], len: usize) -> Result<Vec<u8>> {
+    if text.len() != len.div_ceil(4) * 5 {
+        return Err(anyhow!("Bad base85 length: {} for {len} bytes", text.len()));
+    }
+    let mut data = Vec::with_capacity(text.len() / 5 * 4);
+    for chunk in text.chunks(5) {
+        let mut value: u32 = 0;
+        for &digit in chunk {
+            let digit = DIGITS
+                .iter()
+                .position(|&d| d == digit)
+                .ok_or_else(|| anyhow!("Bad base85 digit: {:?}", char::from(digit)))?;
+            value = value
+                .checked_mul(85)
+                .and_then(|value| value.checked_add(digit as u32))
+                .ok_or_else(|| {
+                    anyhow!("Bad base85 sequence: {}", String::from_utf8_lossy(chunk))
+                })?;
+        }
+        data.extend(value.to_be_bytes());
+    }
+    data.truncate(len);
+    Ok(data)
+}
+
+/// Encodes data as the lines of a binary patch: a length character, `A`-`Z`
+/// for 1 to 26 bytes and `a`-`z` for 27 to 52, then up to 52 bytes in
+/// base85.
+pub fn encode_lines(data: &[u8]) -> String {
+    let mut lines = String::new();
+    for chunk in data.chunks(52) {
+        lines.push(match chunk.len() {
+            len @ 1..=26 => char::from(b'A' + len as u8 - 1),
+            len => char::from(b'a' + len as u8 - 27),
+        });
+        lines += &encode(chunk);
+        lines.push('\n');
+    }
+    lines
+}
+
+/// Decodes a line of a binary patch, the reverse of [`encode_lines`].
+pub fn decode_line(line: &[u8]) -> Result<Vec<u8>> {
+    let (&first, text) = line
+        .split_first()
+        .ok_or_else(|| anyhow!("Empty binary patch line"))?;
+    let len = match first {
+        b'A'..=b'Z' => first - b'A' + 1,
+        b'a'..=b'z' => first - b'a' + 27,
+        _ => {
+            return Err(anyhow!(
+                "Bad binary patch line length: {:?}",
+                char::from(first)
+            ))
+        }
+    };
+    decode(text, len as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(&[0, 0, 0, 0]), "00000");
+        assert_eq!(encode(&[0xff; 4]), "|NsC0");
+        // Git's binary patches are deflated at the fastest level, so their
+        // lines start with the "x\x01" zlib header.
+        assert_eq!(encode(b"x\x01\x03\x00"), "cmV?d");
+
+        let data: Vec<u8> = (0..=255).collect();
+        for len in 0..data.len() {
+            let encoded = encode(&data[..len]);
+            assert_eq!(decode(encoded.as_bytes(), len).unwrap(), &data[..len]);
+        }
+        assert!(decode(b"0000", 3).is_err());
+        assert!(decode(b"0000\"", 4).is_err());
+        assert!(decode(b"~~~~~", 4).is_err());
+    }
+
+    #[test]
+    fn test_encode_lines() {
+        // From `git diff --binary` of an empty file.
+        let empty = [0x78, 0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01];
+        assert_eq!(encode_lines(&empty), "HcmV?d00001\n");
+        assert_eq!(decode_line(b"HcmV?d00001").unwrap(), empty);
+
+        let data = vec![7; 60];
+        let lines = encode_lines(&data);
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with('z'));
+        assert!(lines[1].starts_with('H'));
+        let decoded: Vec<u8> = lines
+            .iter()
+            .flat_map(|line| decode_line(line.as_bytes()).unwrap())
+            .collect();
+        assert_eq!(decoded, data);
+        assert!(decode_line(b"").is_err());
+        assert!(decode_line(b"0cmV?d").is_err());
+    }
+}
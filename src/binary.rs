@@ -0,0 +1,117 @@
+use anyhow::Result;
+use std::io::Read;
+
+use crate::{
+    attributes::{AttributeValue, Attributes},
+    config::Config,
+    object,
+    repo::Repo,
+};
+
+/// Like git, only the start of a file is checked for NUL bytes.
+const SNIFF_LEN: usize = 8000;
+
+/// What the `text`, `-text` and `binary` attributes of a path say about its
+/// content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextAttribute {
+    /// No attribute is set, so the content decides.
+    #[default]
+    Unspecified,
+    /// `text`: always text.
+    Text,
+    /// `-text` or `binary`: always binary.
+    Binary,
+}
+
+impl TextAttribute {
+    /// Returns what the `diff` attribute of a path says about its content,
+    /// where a `diff=<driver>` attribute defers to `diff.<driver>.binary`.
+    pub fn for_path(attributes: &Attributes, config: &Config, path: &str) -> Result<TextAttribute> {
+        Ok(match attributes.get(path, "diff")? {
+            Some(AttributeValue::Unset) => TextAttribute::Binary,
+            Some(AttributeValue::Set) => TextAttribute::Text,
+            Some(AttributeValue::Value(driver)) => {
+                match config.get_bool(&format!("diff.{driver}.binary"))? {
+                    Some(true) => TextAttribute::Binary,
+                    Some(false) => TextAttribute::Text,
+                    None => TextAttribute::Unspecified,
+                }
+            }
+            None => TextAttribute::Unspecified,
+        })
+    }
+}
+
+/// Decides whether content is binary, so that diff, grep, merge and cat-file
+/// all agree.
+///
+/// Content is binary if its attributes say so, if it's larger than
+/// `core.bigFileThreshold`, or if there is a NUL byte in its first 8000
+/// bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct BinaryClassifier {
+    big_file_threshold: u64,
+}
+
+impl BinaryClassifier {
+    pub fn new(big_file_threshold: u64) -> BinaryClassifier {
+        BinaryClassifier { big_file_threshold }
+    }
+
+    pub fn from_config(config: &Config) -> Result<BinaryClassifier> {
+        Ok(BinaryClassifier::new(config.big_file_threshold()?))
+    }
+
+    /// Returns whether `content` is binary. `content` may be just the start
+    /// of a file of `size` bytes.
+    pub fn is_binary(&self, content: &[u8], size: u64, attribute: TextAttribute) -> bool {
+        match attribute {
+            TextAttribute::Text => false,
+            TextAttribute::Binary => true,
+            TextAttribute::Unspecified => {
+                size > self.big_file_threshold
+                    || content[..content.len().min(SNIFF_LEN)].contains(&0)
+            }
+        }
+    }
+
+    /// Returns whether a blob is binary, only reading as much of it as needed.
+    pub fn is_binary_blob(
+        &self,
+        repo: &Repo,
+        hash: &str,
+        attribute: TextAttribute,
+    ) -> Result<bool> {
+        if attribute != TextAttribute::Unspecified {
+            return Ok(attribute == TextAttribute::Binary);
+        }
+        let (size, reader) = object::open_blob(repo, hash)?;
+        let mut start = Vec::with_capacity(SNIFF_LEN.min(size));
+        reader.take(SNIFF_LEN as u64).read_to_end(&mut start)?;
+        Ok(self.is_binary(&start, size as u64, attribute))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_binary() {
+        let classifier = BinaryClassifier::new(100);
+        let unspecified = TextAttribute::Unspecified;
+        assert!(!classifier.is_binary(b"hello\n", 6, unspecified));
+        assert!(classifier.is_binary(b"hel\0lo", 6, unspecified));
+        assert!(!classifier.is_binary(b"hel\0lo", 6, TextAttribute::Text));
+        assert!(classifier.is_binary(b"hello\n", 6, TextAttribute::Binary));
+        // Too big.
+        assert!(classifier.is_binary(b"hello\n", 101, unspecified));
+
+        // NUL bytes after the first 8000 bytes don't count.
+        let mut content = vec![b'a'; SNIFF_LEN];
+        content.push(0);
+        let size = content.len() as u64;
+        assert!(!BinaryClassifier::new(u64::MAX).is_binary(&content, size, unspecified));
+    }
+}
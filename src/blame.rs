@@ -0,0 +1,542 @@
+use anyhow::{anyhow, Result};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet},
+    io,
+};
+
+use crate::{
+    config::Config,
+    diff,
+    ident::{self, DateFormat, Ident},
+    object::{self, Blob, Commit, Object},
+    refs, rename,
+    repo::Repo,
+    width, xdiff,
+};
+
+/// A version of the blamed file that lines can be blamed on: the file at a
+/// path in a commit, or in the working tree.
+#[derive(Debug)]
+pub struct Origin {
+    /// The hash of the commit, all zeros for the working tree.
+    pub hash: String,
+    /// The commit, made up for the working tree like git's: by "Not
+    /// Committed Yet", on top of HEAD.
+    pub commit: Commit,
+    pub path: String,
+    /// The first parent commit and path the file differs from, which the
+    /// lines that didn't change were passed to.
+    pub previous: Option<(String, String)>,
+    /// Whether the commit is a boundary of the search, a root commit.
+    pub boundary: bool,
+    blob: String,
+    content: Vec<u8>,
+}
+
+impl Origin {
+    /// Returns the file at a path in a commit, if there's one.
+    pub fn from_commit(repo: &Repo, hash: &str, path: &str) -> Result<Option<Origin>> {
+        let Object::Commit(commit) = Object::from_hash(repo, hash)? else {
+            return Err(anyhow!("{hash} is not a commit"));
+        };
+        match find_blob(repo, hash, path)? {
+            Some(blob) => Ok(Some(Origin::new(repo, hash, commit, path, &blob)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the file at a path in the working tree, as a commit on top of
+    /// HEAD.
+    pub fn working_tree(repo: &Repo, path: &str) -> Result<Origin> {
+        let head = refs::find_ref(repo, "HEAD")?;
+        if find_blob(repo, &head, path)?.is_none() {
+            return Err(anyhow!("no such path '{path}' in HEAD"));
+        }
+        let content = std::fs::read(repo.root.join(path))
+            .map_err(|e| anyhow!("Cannot lstat '{path}': {e}"))?;
+        let config = Config::load(repo)?;
+        let ident = Ident::with_env(&config, ident::Role::Committer, |key| match key {
+            "GIT_COMMITTER_NAME" => Some("Not Committed Yet".to_string()),
+            "GIT_COMMITTER_EMAIL" => Some("not.committed.yet".to_string()),
+            key => std::env::var(key).ok(),
+        })?;
+        let commit = Commit {
+            parents: vec![head],
+            author: ident.to_string(),
+            committer: ident.to_string(),
+            message: format!("Version of {path} from {path}"),
+            ..Default::default()
+        };
+        Ok(Origin {
+            hash: refs::ZERO_HASH.to_string(),
+            commit,
+            path: path.to_string(),
+            previous: None,
+            boundary: false,
+            blob: Blob::new(content.clone()).hash(),
+            content,
+        })
+    }
+
+    /// Returns the number of lines of the file.
+    pub fn line_count(&self) -> usize {
+        self.content.split_inclusive(|&c| c == b'\n').count()
+    }
+
+    fn new(repo: &Repo, hash: &str, commit: Commit, path: &str, blob: &str) -> Result<Origin> {
+        let Object::Blob(content) = Object::from_hash(repo, blob)? else {
+            return Err(anyhow!("{path} is not a file in {hash}"));
+        };
+        Ok(Origin {
+            hash: hash.to_string(),
+            commit,
+            path: path.to_string(),
+            previous: None,
+            boundary: false,
+            blob: blob.to_string(),
+            content: content.content,
+        })
+    }
+}
+
+/// Returns the hash of the file at a path in a commit, if there's one.
+fn find_blob(repo: &Repo, commit: &str, path: &str) -> Result<Option<String>> {
+    let Ok(hash) = Object::resolve_rev(repo, &format!("{commit}:{path}")) else {
+        return Ok(None);
+    };
+    match Object::from_hash(repo, &hash)? {
+        Object::Blob(_) => Ok(Some(hash)),
+        _ => Ok(None),
+    }
+}
+
+/// The lines of a file, each blamed on the origin that introduced it.
+#[derive(Debug)]
+pub struct Blame {
+    pub origins: Vec<Origin>,
+    /// The content of the file.
+    pub content: Vec<u8>,
+    /// The numbers of the blamed lines of the file, from 1, with the index
+    /// of their origin and their number in it.
+    pub lines: BTreeMap<usize, (usize, usize)>,
+}
+
+/// Parses a range of lines like git's `-L`, from 1 to `lines` inclusive:
+/// `<start>,<end>`, where `<end>` can be `+<count>` or `-<count>` to count
+/// lines from `<start>`, and where a missing `<start>` is the first line
+/// and a missing `<end>` the last one.
+pub fn parse_range(spec: &str, path: &str, lines: usize) -> Result<(usize, usize)> {
+    let invalid = || anyhow!("invalid line range '{spec}'");
+    let number = |s: &str| s.parse::<usize>().map_err(|_| invalid());
+    let (start, end) = spec.split_once(',').unwrap_or((spec, ""));
+    let start = match start {
+        "" => 1,
+        start => number(start)?,
+    };
+    let (mut start, mut end) = match end {
+        "" => (start, lines.max(start)),
+        end => match (end.strip_prefix('+'), end.strip_prefix('-')) {
+            (Some(count), _) => (start, (start + number(count)?).saturating_sub(1)),
+            (_, Some(count)) => ((start + 1).saturating_sub(number(count)?), start),
+            _ => (start, number(end)?),
+        },
+    };
+    if end < start {
+        (start, end) = (end, start);
+    }
+    if lines < start {
+        let plural = if lines == 1 { "line" } else { "lines" };
+        return Err(anyhow!("file {path} has only {lines} {plural}"));
+    }
+    Ok((start.max(1), end.min(lines)))
+}
+
+/// Blames the lines of the ranges of a file on the origins that introduced
+/// them, like `git blame`, from the newest commits to the oldest.
+///
+/// The lines of an origin are passed to the version of the file in each of
+/// its parents in turn, found at the same path or else at the path it was
+/// renamed from: all of them to the first parent with the same file, or
+/// else those the diff between the two versions leaves unchanged, ignoring
+/// whitespace with `ignore_whitespace`. The lines no parent has are blamed
+/// on the origin.
+pub fn blame(
+    repo: &Repo,
+    origin: Origin,
+    ranges: &[(usize, usize)],
+    ignore_whitespace: bool,
+) -> Result<Blame> {
+    let content = origin.content.clone();
+    let lines = ranges
+        .iter()
+        .flat_map(|&(start, end)| start - 1..end)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|line| (line, line))
+        .collect();
+    let mut blamer = Blamer {
+        repo,
+        ignore_whitespace,
+        origins: vec![],
+        pending: vec![],
+        ids: HashMap::new(),
+        queue: BinaryHeap::new(),
+        queued: 0,
+    };
+    let index = blamer.add_origin(origin);
+    blamer.pass(index, lines);
+    let mut blamed = BTreeMap::new();
+    while let Some((_, _, index)) = blamer.queue.pop() {
+        for (line, final_line) in blamer.pass_blame(index)? {
+            blamed.insert(final_line + 1, (index, line + 1));
+        }
+    }
+    Ok(Blame {
+        origins: blamer.origins,
+        content,
+        lines: blamed,
+    })
+}
+
+struct Blamer<'a> {
+    repo: &'a Repo,
+    ignore_whitespace: bool,
+    origins: Vec<Origin>,
+    /// The lines passed to each origin and not blamed yet, as their index in
+    /// it and in the final file.
+    pending: Vec<Vec<(usize, usize)>>,
+    /// The indexes of the origins by commit and path.
+    ids: HashMap<(String, String), usize>,
+    /// The origins with pending lines, newest commit first, then in the
+    /// order they were queued.
+    queue: BinaryHeap<(i64, Reverse<usize>, usize)>,
+    queued: usize,
+}
+
+impl Blamer<'_> {
+    fn add_origin(&mut self, origin: Origin) -> usize {
+        let index = self.origins.len();
+        self.ids
+            .insert((origin.hash.clone(), origin.path.clone()), index);
+        self.origins.push(origin);
+        self.pending.push(vec![]);
+        index
+    }
+
+    /// Passes lines to an origin, queuing it if it had none.
+    fn pass(&mut self, index: usize, lines: Vec<(usize, usize)>) {
+        if lines.is_empty() {
+            return;
+        }
+        if self.pending[index].is_empty() {
+            let time = Ident::parse(&self.origins[index].commit.committer)
+                .map_or(0, |committer| committer.timestamp);
+            self.queue.push((time, Reverse(self.queued), index));
+            self.queued += 1;
+        }
+        self.pending[index].extend(lines);
+    }
+
+    /// Passes the pending lines of an origin to its parents, returning
+    /// those blamed on it.
+    fn pass_blame(&mut self, index: usize) -> Result<Vec<(usize, usize)>> {
+        let mut lines = std::mem::take(&mut self.pending[index]);
+        let parents = self.origins[index].commit.parents.clone();
+        if parents.is_empty() {
+            self.origins[index].boundary = true;
+        }
+        for parent in parents {
+            let Some(parent_index) = self.parent_origin(index, &parent)? else {
+                continue;
+            };
+            let (origin, parent_origin) = (&self.origins[index], &self.origins[parent_index]);
+            if parent_origin.blob == origin.blob {
+                self.pass(parent_index, lines);
+                return Ok(vec![]);
+            }
+            let unchanged = unchanged_lines(
+                &parent_origin.content,
+                &origin.content,
+                self.ignore_whitespace,
+            );
+            if origin.previous.is_none() {
+                let previous = (parent.clone(), parent_origin.path.clone());
+                self.origins[index].previous = Some(previous);
+            }
+            let (passed, kept) = lines
+                .into_iter()
+                .partition::<Vec<_>, _>(|(line, _)| unchanged[*line].is_some());
+            let passed = passed
+                .into_iter()
+                .filter_map(|(line, final_line)| Some((unchanged[line]?, final_line)))
+                .collect();
+            self.pass(parent_index, passed);
+            lines = kept;
+            if lines.is_empty() {
+                break;
+            }
+        }
+        Ok(lines)
+    }
+
+    /// Returns the origin of the file of an origin in a parent commit, at
+    /// the same path or at the path it was renamed from, if any.
+    fn parent_origin(&mut self, index: usize, parent: &str) -> Result<Option<usize>> {
+        let origin = &self.origins[index];
+        let mut path = origin.path.clone();
+        let mut blob = find_blob(self.repo, parent, &path)?;
+        if blob.is_none() && !origin.commit.tree.is_empty() {
+            let Object::Commit(parent_commit) = Object::from_hash(self.repo, parent)? else {
+                return Err(anyhow!("{parent} is not a commit"));
+            };
+            let changes = diff::diff_trees(
+                self.repo,
+                Some(&parent_commit.tree),
+                Some(&origin.commit.tree),
+                true,
+            )?;
+            let (mut deleted, mut added) = (BTreeMap::new(), BTreeMap::new());
+            for change in changes {
+                match (change.old, change.new) {
+                    (Some(old), None) => deleted.insert(change.path, old),
+                    (None, Some(new)) if change.path == path => added.insert(change.path, new),
+                    _ => None,
+                };
+            }
+            let renames =
+                rename::find_renames(self.repo, &deleted, &added, rename::DEFAULT_MIN_SCORE)?;
+            if let Some(rename) = renames.into_iter().next() {
+                blob = find_blob(self.repo, parent, &rename.old_path)?;
+                path = rename.old_path;
+            }
+        }
+        let Some(blob) = blob else {
+            return Ok(None);
+        };
+        if let Some(&index) = self.ids.get(&(parent.to_string(), path.clone())) {
+            return Ok(Some(index));
+        }
+        let Object::Commit(commit) = Object::from_hash(self.repo, parent)? else {
+            return Err(anyhow!("{parent} is not a commit"));
+        };
+        let origin = Origin::new(self.repo, parent, commit, &path, &blob)?;
+        Ok(Some(self.add_origin(origin)))
+    }
+}
+
+/// Returns the number of the line of `old` each line of `new` was kept
+/// from, if it was, like in the diff between them.
+fn unchanged_lines(old: &[u8], new: &[u8], ignore_whitespace: bool) -> Vec<Option<usize>> {
+    let split = |content: &[u8]| -> Vec<Vec<u8>> {
+        content
+            .split_inclusive(|&c| c == b'\n')
+            .map(|line| match ignore_whitespace {
+                true => line
+                    .iter()
+                    .filter(|c| !c.is_ascii_whitespace() && **c != 0x0b)
+                    .copied()
+                    .collect(),
+                false => line.to_vec(),
+            })
+            .collect()
+    };
+    let (old, new) = (split(old), split(new));
+    let old: Vec<&[u8]> = old.iter().map(Vec::as_slice).collect();
+    let new: Vec<&[u8]> = new.iter().map(Vec::as_slice).collect();
+    let (removed, added) = xdiff::diff(&old, &new, true);
+    let mut unchanged = vec![None; new.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && removed[i] {
+            i += 1;
+        } else if j < new.len() && added[j] {
+            j += 1;
+        } else {
+            unchanged[j] = Some(i);
+            (i, j) = (i + 1, j + 1);
+        }
+    }
+    unchanged
+}
+
+/// Returns the runs of blamed lines following each other in the file and in
+/// the same origin, as their first line, its origin and number in it, and
+/// their count.
+fn groups(blame: &Blame) -> Vec<(usize, usize, usize, usize)> {
+    let mut groups: Vec<(usize, usize, usize, usize)> = vec![];
+    for (&line, &(origin, origin_line)) in &blame.lines {
+        match groups.last_mut() {
+            Some((first, last_origin, first_origin_line, count))
+                if *first + *count == line
+                    && *last_origin == origin
+                    && *first_origin_line + *count == origin_line =>
+            {
+                *count += 1
+            }
+            _ => groups.push((line, origin, origin_line, 1)),
+        }
+    }
+    groups
+}
+
+/// Writes the blamed lines like `git blame`: the abbreviated hash of their
+/// commit, prefixed with `^` for a boundary, their path if some lines come
+/// from another one, the author and date of the commit, and the line
+/// number, aligned, before the line itself.
+pub fn write_blame(repo: &Repo, blame: &Blame, stdout: &mut dyn io::Write) -> Result<()> {
+    let lines: Vec<&[u8]> = blame.content.split_inclusive(|&c| c == b'\n').collect();
+    let final_path = &blame.origins[0].path;
+    let used: BTreeSet<usize> = blame.lines.values().map(|(origin, _)| *origin).collect();
+    let mut authors = HashMap::new();
+    let abbreviator = object::Abbreviator::from_config(repo)?;
+    let mut abbrev = 0;
+    for &index in &used {
+        let origin = &blame.origins[index];
+        let author = Ident::parse(&origin.commit.author)
+            .ok_or_else(|| anyhow!("Invalid author in {}", origin.hash))?;
+        authors.insert(index, author);
+        let length = match origin.hash == refs::ZERO_HASH {
+            true => 7,
+            false => abbreviator.abbreviate(&origin.hash)?.len(),
+        };
+        abbrev = abbrev.max(length + 1);
+    }
+    let show_path = used
+        .iter()
+        .any(|&index| blame.origins[index].path != *final_path);
+    let path_width = used
+        .iter()
+        .map(|&index| width::display_width(&blame.origins[index].path))
+        .max()
+        .unwrap_or(0);
+    let author_width = authors
+        .values()
+        .map(|author| width::display_width(&author.name))
+        .max()
+        .unwrap_or(0);
+    let line_width = blame
+        .lines
+        .keys()
+        .last()
+        .map_or(1, |line| line.to_string().len());
+    for (&line, &(index, _)) in &blame.lines {
+        let origin = &blame.origins[index];
+        let hash = match origin.boundary {
+            true => format!("^{}", &origin.hash[..abbrev - 1]),
+            false => origin.hash[..abbrev].to_string(),
+        };
+        let path = match show_path {
+            true => format!(" {}", width::pad(&origin.path, path_width)),
+            false => String::new(),
+        };
+        let author = &authors[&index];
+        let date = ident::format_date(author.timestamp, &author.timezone, DateFormat::Iso);
+        write!(
+            stdout,
+            "{hash}{path} ({} {date} {line:>line_width$}) ",
+            width::pad(&author.name, author_width)
+        )?;
+        write_line(lines[line - 1], stdout)?;
+    }
+    Ok(())
+}
+
+/// Writes the blamed lines in git's porcelain format, for other programs:
+/// for each run of lines of the same origin, a header with the hash of its
+/// commit, its line number there and in the file, and the number of lines,
+/// then the details of the commit the first time, then each line after a
+/// tab, following the same header without the number of lines. With
+/// `repeat`, like `--line-porcelain`, the details come after every header.
+pub fn write_porcelain(blame: &Blame, repeat: bool, stdout: &mut dyn io::Write) -> Result<()> {
+    let lines: Vec<&[u8]> = blame.content.split_inclusive(|&c| c == b'\n').collect();
+    let mut paths: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for (index, _) in blame.lines.values() {
+        let origin = &blame.origins[*index];
+        paths.entry(&origin.hash).or_default().insert(&origin.path);
+    }
+    let mut shown = HashSet::new();
+    for (line, index, origin_line, count) in groups(blame) {
+        let origin = &blame.origins[index];
+        for i in 0..count {
+            match i {
+                0 => writeln!(stdout, "{} {origin_line} {line} {count}", origin.hash)?,
+                i => writeln!(stdout, "{} {} {}", origin.hash, origin_line + i, line + i)?,
+            }
+            if i == 0 || repeat {
+                let details = repeat || shown.insert(&origin.hash);
+                if details {
+                    write_details(origin, stdout)?;
+                }
+                if details || paths[origin.hash.as_str()].len() > 1 {
+                    if let Some((commit, path)) = &origin.previous {
+                        writeln!(stdout, "previous {commit} {path}")?;
+                    }
+                    writeln!(stdout, "filename {}", origin.path)?;
+                }
+            }
+            stdout.write_all(b"\t")?;
+            write_line(lines[line + i - 1], stdout)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_details(origin: &Origin, stdout: &mut dyn io::Write) -> Result<()> {
+    for (role, ident) in [
+        ("author", &origin.commit.author),
+        ("committer", &origin.commit.committer),
+    ] {
+        let ident =
+            Ident::parse(ident).ok_or_else(|| anyhow!("Invalid {role} in {}", origin.hash))?;
+        writeln!(stdout, "{role} {}", ident.name)?;
+        writeln!(stdout, "{role}-mail <{}>", ident.email)?;
+        writeln!(stdout, "{role}-time {}", ident.timestamp)?;
+        writeln!(stdout, "{role}-tz {}", ident.timezone)?;
+    }
+    let summary = origin.commit.message.lines().next().unwrap_or("");
+    writeln!(stdout, "summary {summary}")?;
+    if origin.boundary {
+        writeln!(stdout, "boundary")?;
+    }
+    Ok(())
+}
+
+/// Writes a line of the file, with a newline even if it's the last line
+/// and has none.
+fn write_line(line: &[u8], stdout: &mut dyn io::Write) -> Result<()> {
+    stdout.write_all(line)?;
+    if !line.ends_with(b"\n") {
+        stdout.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("2,4", "f", 12).unwrap(), (2, 4));
+        assert_eq!(parse_range("3,+2", "f", 12).unwrap(), (3, 4));
+        assert_eq!(parse_range("9,-2", "f", 12).unwrap(), (8, 9));
+        assert_eq!(parse_range("10", "f", 12).unwrap(), (10, 12));
+        assert_eq!(parse_range(",3", "f", 12).unwrap(), (1, 3));
+        assert_eq!(parse_range("11,20", "f", 12).unwrap(), (11, 12));
+        assert_eq!(parse_range("4,2", "f", 12).unwrap(), (2, 4));
+        assert_eq!(
+            parse_range("20", "f", 12).unwrap_err().to_string(),
+            "file f has only 12 lines"
+        );
+        assert!(parse_range("a,b", "f", 12).is_err());
+    }
+
+    #[test]
+    fn test_unchanged_lines() {
+        let old = b"one\ntwo\n  three\n";
+        let new = b"zero\none\nthree\n";
+        assert_eq!(unchanged_lines(old, new, false), [None, Some(0), None]);
+        assert_eq!(unchanged_lines(old, new, true), [None, Some(0), Some(2)]);
+    }
+}
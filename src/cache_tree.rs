@@ -0,0 +1,291 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::prelude::*;
+
+use crate::{
+    index::{self, IndexEntry},
+    object::{self, File, ObjectType, Tree},
+    repo::Repo,
+};
+
+/// The cache tree, stored in the `TREE` index extension.
+///
+/// It records the tree object of each directory in the index, so unchanged
+/// directories don't have to be hashed again when writing a tree. A node is
+/// invalidated when an entry below it changes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CacheTree {
+    /// The name of the directory, empty for the root.
+    pub name: String,
+    /// The hash of the tree, or `None` if the node was invalidated.
+    pub hash: Option<String>,
+    /// The number of index entries below this directory. Only meaningful
+    /// when the node is valid.
+    pub entry_count: usize,
+    pub subtrees: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    /// Parses the data of a `TREE` extension.
+    ///
+    /// Each node is written as `name\0entry_count subtree_count\n` followed
+    /// by the binary hash, then its subtrees. Invalid nodes have an entry
+    /// count of -1 and no hash.
+    pub fn from_bytes(s: &[u8]) -> Result<CacheTree> {
+        let mut reader = s;
+        let tree = CacheTree::from_reader(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(anyhow!("Unexpected data after cache tree"));
+        }
+        Ok(tree)
+    }
+
+    /// Reads a node and its subtrees.
+    ///
+    /// Nodes are read in a loop rather than recursively, keeping the nodes
+    /// whose subtrees are still being read on a stack, so that corrupt data
+    /// can't overflow the call stack.
+    fn from_reader(reader: &mut &[u8]) -> Result<CacheTree> {
+        // (node, number of subtrees left to read)
+        let mut stack: Vec<(CacheTree, usize)> = vec![];
+        loop {
+            if stack.len() > index::MAX_DIR_DEPTH {
+                return Err(anyhow!("Cache tree is too deep"));
+            }
+            stack.push(CacheTree::read_node(reader)?);
+            while let Some(&(_, 0)) = stack.last() {
+                let (node, _) = stack.pop().expect("the stack isn't empty");
+                match stack.last_mut() {
+                    Some((parent, left)) => {
+                        parent.subtrees.push(node);
+                        *left -= 1;
+                    }
+                    None => return Ok(node),
+                }
+            }
+        }
+    }
+
+    /// Reads a node without its subtrees, returning it with the number of
+    /// subtrees that follow.
+    fn read_node(reader: &mut &[u8]) -> Result<(CacheTree, usize)> {
+        let mut name = vec![];
+        reader.read_until(0, &mut name)?;
+        if name.pop() != Some(0) {
+            return Err(anyhow!("Cache tree is truncated"));
+        }
+        let mut counts = vec![];
+        reader.read_until(b'\n', &mut counts)?;
+        if counts.pop() != Some(b'\n') {
+            return Err(anyhow!("Cache tree is truncated"));
+        }
+        let counts = std::str::from_utf8(&counts)?;
+        let (entry_count, subtree_count) = counts
+            .split_once(' ')
+            .ok_or(anyhow!("Invalid cache tree counts: {counts:?}"))?;
+        let entry_count: i64 = entry_count.parse()?;
+        let subtree_count: usize = subtree_count.parse()?;
+
+        let (hash, entry_count) = if entry_count < 0 {
+            (None, 0)
+        } else {
+            let mut hash = [0_u8; 20];
+            reader
+                .read_exact(&mut hash)
+                .context("Cache tree is truncated")?;
+            (Some(hex::encode(hash)), usize::try_from(entry_count)?)
+        };
+
+        let node = CacheTree {
+            name: String::from_utf8(name)?,
+            hash,
+            entry_count,
+            subtrees: vec![],
+        };
+        Ok((node, subtree_count))
+    }
+
+    /// Serializes the cache tree as the data of a `TREE` extension.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut s = vec![];
+        self.write_to(&mut s);
+        s
+    }
+
+    fn write_to(&self, s: &mut Vec<u8>) {
+        s.extend(self.name.as_bytes());
+        s.push(0);
+        match &self.hash {
+            Some(hash) => {
+                s.extend(format!("{} {}\n", self.entry_count, self.subtrees.len()).as_bytes());
+                s.extend(hex::decode(hash).expect("cache tree hashes are valid"));
+            }
+            None => s.extend(format!("-1 {}\n", self.subtrees.len()).as_bytes()),
+        }
+        for subtree in &self.subtrees {
+            subtree.write_to(s);
+        }
+    }
+
+    /// Invalidates the nodes of all directories containing `path`.
+    pub fn invalidate_path(&mut self, path: &str) {
+        self.hash = None;
+        if let Some((dir, rest)) = path.split_once('/') {
+            if let Some(subtree) = self.subtrees.iter_mut().find(|tree| tree.name == dir) {
+                subtree.invalidate_path(rest);
+            }
+        }
+    }
+
+    /// Writes the tree objects for `entries`, reusing the valid nodes of the
+    /// cache tree, and returns the hash of the root tree.
+    ///
+    /// `entries` must be sorted by path and only contain stage 0 entries.
+    /// Entries added with `add -N` are left out, like git does.
+    pub fn update(&mut self, repo: &Repo, entries: &[&IndexEntry]) -> Result<String> {
+        let entries: Vec<(&str, &IndexEntry)> = entries
+            .iter()
+            .filter(|entry| !entry.intent_to_add)
+            .map(|entry| (entry.path.as_str(), *entry))
+            .collect();
+        self.update_entries(repo, &entries)
+    }
+
+    /// Like [`CacheTree::update`], with paths relative to this directory.
+    fn update_entries(&mut self, repo: &Repo, entries: &[(&str, &IndexEntry)]) -> Result<String> {
+        if let Some(hash) = &self.hash {
+            if self.entry_count == entries.len() {
+                return Ok(hash.clone());
+            }
+        }
+
+        let mut files = vec![];
+        let mut subtrees = vec![];
+        let mut rest = entries;
+        while let Some(&(path, entry)) = rest.first() {
+            let Some((dir, _)) = path.split_once('/') else {
+                files.push(File {
+                    mode: format!("{:o}", entry.mode),
+                    name: path.to_string(),
+                    hash: entry.hash.clone(),
+                });
+                rest = &rest[1..];
+                continue;
+            };
+
+            let prefix_len = dir.len() + 1;
+            let dir_len = rest
+                .iter()
+                .take_while(|(path, _)| {
+                    path.strip_prefix(dir)
+                        .is_some_and(|rest| rest.starts_with('/'))
+                })
+                .count();
+            let dir_entries: Vec<(&str, &IndexEntry)> = rest[..dir_len]
+                .iter()
+                .map(|&(path, entry)| (&path[prefix_len..], entry))
+                .collect();
+            let mut subtree = match self.subtrees.iter().position(|tree| tree.name == dir) {
+                Some(position) => self.subtrees.swap_remove(position),
+                None => CacheTree {
+                    name: dir.to_string(),
+                    ..Default::default()
+                },
+            };
+            let hash = subtree.update_entries(repo, &dir_entries)?;
+            files.push(File {
+                mode: "40000".to_string(),
+                name: dir.to_string(),
+                hash,
+            });
+            subtrees.push(subtree);
+            rest = &rest[dir_len..];
+        }
+
+        // Git keeps subtrees sorted by name length first.
+        subtrees.sort_by(|a, b| (a.name.len(), &a.name).cmp(&(b.name.len(), &b.name)));
+
+        let hash =
+            object::write_checked_object(repo, ObjectType::Tree, &Tree::new(files).to_bytes())?;
+        self.hash = Some(hash.clone());
+        self.entry_count = entries.len();
+        self.subtrees = subtrees;
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, hash: &str) -> IndexEntry {
+        IndexEntry {
+            mode: 0o100644,
+            hash: hash.to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cache_tree_round_trips() {
+        let mut s = b"\x003 2\n".to_vec();
+        s.extend([0xaa; 20]);
+        s.extend(b"dir\0-1 0\n");
+        s.extend(b"other\x001 0\n");
+        s.extend([0xbb; 20]);
+
+        let tree = CacheTree::from_bytes(&s).unwrap();
+        assert_eq!(tree.hash, Some("aa".repeat(20)));
+        assert_eq!(tree.entry_count, 3);
+        assert_eq!(tree.subtrees[0].name, "dir");
+        assert_eq!(tree.subtrees[0].hash, None);
+        assert_eq!(tree.subtrees[1].hash, Some("bb".repeat(20)));
+        assert_eq!(tree.to_bytes(), s);
+    }
+
+    #[test]
+    fn test_cache_tree_truncated() {
+        assert_eq!(
+            CacheTree::from_bytes(b"\x001 0\n\xaa")
+                .unwrap_err()
+                .to_string(),
+            "Cache tree is truncated"
+        );
+    }
+
+    #[test]
+    fn test_update_writes_trees_and_reuses_valid_nodes() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        // The blob for "hello\n".
+        let blob = "ce013625030ba8dba906f756967f9e9ca394464a";
+        let entries = [
+            entry("a/b/c.txt", blob),
+            entry("a/d.txt", blob),
+            entry("a.txt", blob),
+        ];
+        let mut sorted: Vec<&IndexEntry> = entries.iter().collect();
+        sorted.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut tree = CacheTree::default();
+        let hash = tree.update(&repo, &sorted).unwrap();
+        // Same as `git write-tree` for these files.
+        assert_eq!(hash, "e31c126771d97b65cbbc755a0757a485207382db");
+        assert_eq!(tree.entry_count, 3);
+        assert_eq!(tree.subtrees.len(), 1);
+        assert_eq!(tree.subtrees[0].entry_count, 2);
+
+        // A valid node is used as is, even if its tree object is missing.
+        let subtree_hash = tree.subtrees[0].subtrees[0].hash.clone().unwrap();
+        std::fs::remove_dir_all(repo.git_dir().join("objects").join(&subtree_hash[..2])).unwrap();
+        tree.invalidate_path("a/d.txt");
+        assert_eq!(tree.hash, None);
+        assert_eq!(tree.subtrees[0].hash, None);
+        assert_eq!(tree.update(&repo, &sorted).unwrap(), hash);
+        assert!(!repo
+            .git_dir()
+            .join("objects")
+            .join(&subtree_hash[..2])
+            .exists());
+    }
+}
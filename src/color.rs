@@ -0,0 +1,382 @@
+use anyhow::{anyhow, Result};
+
+use crate::config::Config;
+
+/// Ends a color.
+pub const RESET: &str = "\x1b[m";
+
+/// When output is colored, from `color.ui`, `color.<command>` and `--color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum When {
+    Never,
+    Always,
+    /// Only when writing to a terminal, see [`When::enabled`].
+    #[default]
+    Auto,
+}
+
+impl When {
+    /// Parses the value of `--color=<when>`: `always`, `never` or `auto`.
+    pub fn parse(value: &str) -> Result<When> {
+        match value.to_lowercase().as_str() {
+            "always" => Ok(When::Always),
+            "never" => Ok(When::Never),
+            "auto" => Ok(When::Auto),
+            _ => Err(anyhow!(
+                "option `color' expects \"always\", \"auto\", or \"never\""
+            )),
+        }
+    }
+
+    /// Reads `color.<command>`, falling back to `color.ui`, `auto` if
+    /// neither is set.
+    ///
+    /// Like git, they can also be booleans, where true means `auto`.
+    pub fn from_config(config: &Config, command: &str) -> Result<When> {
+        for key in [format!("color.{command}"), "color.ui".to_string()] {
+            let Some(value) = config.get(&key) else {
+                continue;
+            };
+            return match When::parse(value) {
+                Ok(when) => Ok(when),
+                Err(_) => match config.get_bool(&key)? {
+                    Some(false) => Ok(When::Never),
+                    _ => Ok(When::Auto),
+                },
+            };
+        }
+        Ok(When::Auto)
+    }
+
+    /// Returns whether to color output, `auto` coloring it only on a
+    /// terminal, with `TERM` set to something else than `dumb`, and unless
+    /// `NO_COLOR` is set.
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        let var = |name| std::env::var(name).ok();
+        self.resolve(
+            is_terminal,
+            var("TERM").as_deref(),
+            var("NO_COLOR").as_deref(),
+        )
+    }
+
+    fn resolve(self, is_terminal: bool, term: Option<&str>, no_color: Option<&str>) -> bool {
+        match self {
+            When::Never => false,
+            When::Always => true,
+            When::Auto => {
+                is_terminal
+                    && term.is_some_and(|term| term != "dumb")
+                    && no_color.is_none_or(str::is_empty)
+            }
+        }
+    }
+}
+
+/// Parses a color like git's `color.*` values, such as `red`, `bold blue
+/// white` or `#ff8000 ul`, into its escape sequence.
+///
+/// The first color is the foreground and the second the background, each
+/// being `normal`, `default`, one of the 8 basic colors optionally prefixed
+/// with `bright`, a number up to 255 or `#rrggbb`. Attributes are `bold`,
+/// `dim`, `italic`, `ul`, `blink`, `reverse` and `strike`, turned off when
+/// prefixed with `no` or `no-`, and `reset` resets everything first. Like
+/// git, words are case sensitive and attributes are written first, in the
+/// order of their codes.
+pub fn parse(value: &str) -> Result<String> {
+    let invalid = || anyhow!("invalid color value: {value}");
+    let mut reset = false;
+    let mut attributes: Vec<u8> = vec![];
+    let mut colors: Vec<Option<ColorCode>> = vec![];
+    for word in value.split_ascii_whitespace() {
+        if word == "reset" {
+            reset = true;
+        } else if let Some(color) = parse_color(word) {
+            if colors.len() == 2 {
+                return Err(invalid());
+            }
+            colors.push(color);
+        } else {
+            attributes.push(parse_attribute(word).ok_or_else(invalid)?);
+        }
+    }
+    attributes.sort_unstable();
+    attributes.dedup();
+
+    let mut codes: Vec<String> = attributes.iter().map(u8::to_string).collect();
+    for (color, base) in colors.iter().zip([30, 40]) {
+        match color {
+            None => {}
+            Some(ColorCode::Ansi(value)) => codes.push((base + value).to_string()),
+            Some(ColorCode::Indexed(index)) => codes.push(format!("{};5;{index}", base + 8)),
+            Some(ColorCode::Rgb(r, g, b)) => codes.push(format!("{};2;{r};{g};{b}", base + 8)),
+        }
+    }
+    Ok(match (reset, codes.is_empty()) {
+        (false, true) => String::new(),
+        (true, true) => RESET.to_string(),
+        // Like git, the reset is an empty code.
+        (true, false) => format!("\x1b[;{}m", codes.join(";")),
+        (false, false) => format!("\x1b[{}m", codes.join(";")),
+    })
+}
+
+/// A color other than `normal`, as the offset of its code from 30 for the
+/// foreground or 40 for the background, or for the extended colors.
+#[derive(Debug, Clone, Copy)]
+enum ColorCode {
+    Ansi(u8),
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// Parses a color, `None` inside for `normal`, which leaves it unchanged.
+fn parse_color(word: &str) -> Option<Option<ColorCode>> {
+    const NAMES: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    match word {
+        "normal" => return Some(None),
+        "default" => return Some(Some(ColorCode::Ansi(9))),
+        _ => {}
+    }
+    let (bright, name) = match word.strip_prefix("bright") {
+        Some(name) => (true, name),
+        None => (false, word),
+    };
+    if let Some(index) = NAMES.iter().position(|&color| color == name) {
+        return Some(Some(ColorCode::Ansi(
+            index as u8 + if bright { 60 } else { 0 },
+        )));
+    }
+    if let Some(hex) = word.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Some(ColorCode::Rgb(channel(0)?, channel(2)?, channel(4)?)));
+    }
+    match word.parse::<i32>().ok()? {
+        -1 => Some(None),
+        value @ 0..=7 => Some(Some(ColorCode::Ansi(value as u8))),
+        value @ 8..=15 => Some(Some(ColorCode::Ansi(value as u8 - 8 + 60))),
+        value @ 16..=255 => Some(Some(ColorCode::Indexed(value as u8))),
+        _ => None,
+    }
+}
+
+/// Returns the code of an attribute, or of turning it off.
+fn parse_attribute(word: &str) -> Option<u8> {
+    const ATTRIBUTES: [(&str, u8, u8); 7] = [
+        ("bold", 1, 22),
+        ("dim", 2, 22),
+        ("italic", 3, 23),
+        ("ul", 4, 24),
+        ("blink", 5, 25),
+        ("reverse", 7, 27),
+        ("strike", 9, 29),
+    ];
+    let (negate, name) = match word.strip_prefix("no") {
+        Some(name) => (true, name.strip_prefix('-').unwrap_or(name)),
+        None => (false, word),
+    };
+    let (_, code, negated) = ATTRIBUTES
+        .iter()
+        .find(|(attribute, ..)| *attribute == name)?;
+    Some(if negate { *negated } else { *code })
+}
+
+/// Returns the color of `<section>.<slot>` if it's set, or else `default`.
+fn slot(config: &Config, section: &str, slot: &str, default: &str) -> Result<String> {
+    match config.get(&format!("{section}.{slot}")) {
+        Some(value) => parse(value),
+        None => Ok(default.to_string()),
+    }
+}
+
+/// The colors of patches and `--stat` histograms, from `color.diff.<slot>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffColors {
+    /// Unchanged lines.
+    pub context: String,
+    /// The header lines of files.
+    pub meta: String,
+    /// The ranges of hunk headers.
+    pub frag: String,
+    /// The function names of hunk headers.
+    pub func: String,
+    pub old: String,
+    pub new: String,
+    /// Commit hashes, in logs.
+    pub commit: String,
+    /// Whitespace errors in added lines.
+    pub whitespace: String,
+}
+
+impl Default for DiffColors {
+    fn default() -> Self {
+        DiffColors {
+            context: String::new(),
+            meta: "\x1b[1m".to_string(),
+            frag: "\x1b[36m".to_string(),
+            func: String::new(),
+            old: "\x1b[31m".to_string(),
+            new: "\x1b[32m".to_string(),
+            commit: "\x1b[33m".to_string(),
+            whitespace: "\x1b[41m".to_string(),
+        }
+    }
+}
+
+impl DiffColors {
+    pub fn from_config(config: &Config) -> Result<DiffColors> {
+        let default = DiffColors::default();
+        let slot = |name: &str, default: &str| slot(config, "color.diff", name, default);
+        Ok(DiffColors {
+            // Like git, `plain` is an older name of `context`.
+            context: match config.get("color.diff.context") {
+                Some(_) => slot("context", "")?,
+                None => slot("plain", "")?,
+            },
+            meta: slot("meta", &default.meta)?,
+            frag: slot("frag", &default.frag)?,
+            func: slot("func", &default.func)?,
+            old: slot("old", &default.old)?,
+            new: slot("new", &default.new)?,
+            commit: slot("commit", &default.commit)?,
+            whitespace: slot("whitespace", &default.whitespace)?,
+        })
+    }
+}
+
+/// The colors of the long format of status, from `color.status.<slot>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusColors {
+    /// Changes to be committed.
+    pub added: String,
+    /// Changes not staged for commit.
+    pub changed: String,
+    pub untracked: String,
+    pub unmerged: String,
+    /// A detached HEAD.
+    pub no_branch: String,
+}
+
+impl Default for StatusColors {
+    fn default() -> Self {
+        StatusColors {
+            added: "\x1b[32m".to_string(),
+            changed: "\x1b[31m".to_string(),
+            untracked: "\x1b[31m".to_string(),
+            unmerged: "\x1b[31m".to_string(),
+            no_branch: "\x1b[31m".to_string(),
+        }
+    }
+}
+
+impl StatusColors {
+    pub fn from_config(config: &Config) -> Result<StatusColors> {
+        let default = StatusColors::default();
+        let slot = |name: &str, default: &str| slot(config, "color.status", name, default);
+        Ok(StatusColors {
+            // Like git, `updated` is another name of `added`.
+            added: match config.get("color.status.added") {
+                Some(_) => slot("added", "")?,
+                None => slot("updated", &default.added)?,
+            },
+            changed: slot("changed", &default.changed)?,
+            untracked: slot("untracked", &default.untracked)?,
+            unmerged: slot("unmerged", &default.unmerged)?,
+            no_branch: slot("nobranch", &default.no_branch)?,
+        })
+    }
+}
+
+/// The colors of the refs decorating commits in logs, from
+/// `color.decorate.<slot>`, and of their hashes, from `color.diff.commit`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecorateColors {
+    pub commit: String,
+    pub head: String,
+    pub branch: String,
+    pub remote_branch: String,
+    pub tag: String,
+    pub stash: String,
+    /// Other refs.
+    pub other: String,
+}
+
+impl Default for DecorateColors {
+    fn default() -> Self {
+        DecorateColors {
+            commit: DiffColors::default().commit,
+            head: "\x1b[1;36m".to_string(),
+            branch: "\x1b[1;32m".to_string(),
+            remote_branch: "\x1b[1;31m".to_string(),
+            tag: "\x1b[1;33m".to_string(),
+            stash: "\x1b[1;35m".to_string(),
+            other: RESET.to_string(),
+        }
+    }
+}
+
+impl DecorateColors {
+    pub fn from_config(config: &Config) -> Result<DecorateColors> {
+        let default = DecorateColors::default();
+        let slot = |name: &str, default: &str| slot(config, "color.decorate", name, default);
+        Ok(DecorateColors {
+            commit: DiffColors::from_config(config)?.commit,
+            head: slot("HEAD", &default.head)?,
+            branch: slot("branch", &default.branch)?,
+            remote_branch: slot("remoteBranch", &default.remote_branch)?,
+            tag: slot("tag", &default.tag)?,
+            stash: slot("stash", &default.stash)?,
+            other: default.other,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        // From `git config --get-color`.
+        let cases = [
+            ("red bold", "\x1b[1;31m"),
+            ("bold red blue", "\x1b[1;31;44m"),
+            ("reset", "\x1b[m"),
+            ("reset green", "\x1b[;32m"),
+            ("-1 red", "\x1b[41m"),
+            ("8", "\x1b[90m"),
+            ("ul nobold 12", "\x1b[4;22;94m"),
+            ("#ff0000 brightblue", "\x1b[38;2;255;0;0;104m"),
+            ("normal", ""),
+            ("normal red", "\x1b[41m"),
+            ("dim italic 200 7", "\x1b[2;3;38;5;200;47m"),
+            ("no-ul strike default", "\x1b[9;24;39m"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse(value).unwrap(), expected, "{value}");
+        }
+        assert!(parse("red green blue").is_err());
+        assert!(parse("purple").is_err());
+        assert!(parse("Red").is_err());
+        assert!(parse("256").is_err());
+        assert!(parse("#12345").is_err());
+    }
+
+    #[test]
+    fn test_when() {
+        assert!(!When::Auto.resolve(false, Some("xterm"), None));
+        assert!(When::Auto.resolve(true, Some("xterm"), None));
+        assert!(When::Auto.resolve(true, Some("xterm"), Some("")));
+        assert!(!When::Auto.resolve(true, Some("xterm"), Some("1")));
+        assert!(!When::Auto.resolve(true, Some("dumb"), None));
+        assert!(!When::Auto.resolve(true, None, None));
+        assert!(When::Always.resolve(false, None, Some("1")));
+        assert!(!When::Never.resolve(true, Some("xterm"), None));
+        assert!(When::parse("sometimes").is_err());
+    }
+}
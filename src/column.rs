@@ -0,0 +1,341 @@
+use anyhow::{anyhow, Result};
+use std::io;
+
+use crate::{config::Config, width};
+
+/// Whether lists are laid out in columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Enable {
+    #[default]
+    Never,
+    Always,
+    /// Only when writing to a terminal, see [`Mode::resolve_auto`].
+    Auto,
+}
+
+/// How items are placed in the columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// Fill columns before rows.
+    #[default]
+    Column,
+    /// Fill rows before columns.
+    Row,
+    /// One item per line.
+    Plain,
+}
+
+/// The column settings, from `column.ui`, `column.<command>` and
+/// `--column`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Mode {
+    pub enable: Enable,
+    pub layout: Layout,
+    /// Make columns as narrow as their widest item instead of all as wide
+    /// as the widest item, which fits more of them.
+    pub dense: bool,
+}
+
+impl Mode {
+    /// Reads `column.ui`, then `column.<command>` on top of it.
+    pub fn from_config(config: &Config, command: &str) -> Result<Mode> {
+        let mut mode = Mode::default();
+        for key in ["column.ui".to_string(), format!("column.{command}")] {
+            if let Some(value) = config.get(&key) {
+                mode.parse(value)?;
+            }
+        }
+        Ok(mode)
+    }
+
+    /// Applies options separated by spaces or commas, like `always,row`,
+    /// where later options win.
+    ///
+    /// Like git, setting the layout or density without `always`, `never` or
+    /// `auto` implies `always`.
+    pub fn parse(&mut self, options: &str) -> Result<()> {
+        let mut enable_set = false;
+        let mut layout_set = false;
+        for option in options
+            .split([' ', ','])
+            .filter(|option| !option.is_empty())
+        {
+            match option {
+                "always" => self.enable = Enable::Always,
+                "never" => self.enable = Enable::Never,
+                "auto" => self.enable = Enable::Auto,
+                "column" => self.layout = Layout::Column,
+                "row" => self.layout = Layout::Row,
+                "plain" => self.layout = Layout::Plain,
+                "dense" => self.dense = true,
+                "nodense" => self.dense = false,
+                _ => return Err(anyhow!("unsupported option '{option}'")),
+            }
+            match option {
+                "always" | "never" | "auto" => enable_set = true,
+                _ => layout_set = true,
+            }
+        }
+        if layout_set && !enable_set {
+            self.enable = Enable::Always;
+        }
+        Ok(())
+    }
+
+    /// Turns `auto` into `always` when writing to a terminal, and into
+    /// `never` otherwise.
+    pub fn resolve_auto(self, is_terminal: bool) -> Mode {
+        match self.enable {
+            Enable::Auto if is_terminal => Mode {
+                enable: Enable::Always,
+                ..self
+            },
+            Enable::Auto => Mode {
+                enable: Enable::Never,
+                ..self
+            },
+            _ => self,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnOptions {
+    pub mode: Mode,
+    /// The width of the output, usually [`terminal_width`].
+    pub width: usize,
+    /// Printed before each line.
+    pub indent: String,
+    /// The number of spaces between columns.
+    pub padding: usize,
+    /// Printed after each line.
+    pub nl: String,
+}
+
+impl Default for ColumnOptions {
+    fn default() -> ColumnOptions {
+        ColumnOptions {
+            mode: Mode::default(),
+            width: 80,
+            indent: String::new(),
+            padding: 1,
+            nl: "\n".to_string(),
+        }
+    }
+}
+
+/// Returns the width of the terminal: `$COLUMNS` if set, then the width of
+/// the terminal of stdout, and 80 otherwise.
+pub fn terminal_width() -> usize {
+    if let Some(columns) = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|&columns| columns > 0)
+    {
+        return columns;
+    }
+    #[cfg(unix)]
+    {
+        // SAFETY: TIOCGWINSZ only writes to the winsize struct it's given.
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+            && size.ws_col > 0
+        {
+            return usize::from(size.ws_col);
+        }
+    }
+    80
+}
+
+/// Prints items one per line, or in columns fitted to the width when
+/// enabled, like git's `print_columns`.
+///
+/// `auto` has to be resolved beforehand, it's treated as `never`. When
+/// disabled, the indent and line terminator aren't used either.
+pub fn print(items: &[String], options: &ColumnOptions, stdout: &mut dyn io::Write) -> Result<()> {
+    if options.mode.enable != Enable::Always {
+        for item in items {
+            writeln!(stdout, "{item}")?;
+        }
+        return Ok(());
+    }
+    if options.mode.layout == Layout::Plain {
+        for item in items {
+            write!(stdout, "{}{item}{}", options.indent, options.nl)?;
+        }
+        return Ok(());
+    }
+    if items.is_empty() {
+        return Ok(());
+    }
+
+    let table = Table::new(items, options);
+    for y in 0..table.rows {
+        for x in 0..table.cols {
+            let i = table.index(x, y);
+            let Some(item) = items.get(i) else {
+                break;
+            };
+            let newline = match options.mode.layout {
+                Layout::Column => i + table.rows >= items.len(),
+                _ => x == table.cols - 1 || i == items.len() - 1,
+            };
+            let indent = if x == 0 { options.indent.as_str() } else { "" };
+            if newline {
+                write!(stdout, "{indent}{item}{}", options.nl)?;
+            } else {
+                let width = table.widths[x] + options.padding;
+                let spaces = width.saturating_sub(table.lengths[i]);
+                write!(stdout, "{indent}{item}{:spaces$}", "")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The shape of the columns for a list of items.
+struct Table {
+    layout: Layout,
+    rows: usize,
+    cols: usize,
+    /// The width of each item, in columns.
+    lengths: Vec<usize>,
+    /// The width of each column, without padding.
+    widths: Vec<usize>,
+}
+
+impl Table {
+    /// Fits as many columns as the width allows, all as wide as the widest
+    /// item. Dense tables then take one row away at a time, as long as the
+    /// resulting columns still fit.
+    fn new(items: &[String], options: &ColumnOptions) -> Table {
+        let lengths: Vec<usize> = items
+            .iter()
+            .map(|item| width::display_width(item))
+            .collect();
+        let max_length = lengths.iter().copied().max().unwrap_or(0);
+        let available = options
+            .width
+            .saturating_sub(width::display_width(&options.indent));
+        let cols = (available / (max_length + options.padding).max(1)).max(1);
+        let mut table = Table {
+            layout: options.mode.layout,
+            rows: items.len().div_ceil(cols),
+            cols,
+            widths: vec![max_length; cols],
+            lengths,
+        };
+        if options.mode.dense {
+            table.widths = table.column_widths();
+            while table.rows > 1 {
+                let (rows, cols) = (table.rows, table.cols);
+                table.rows -= 1;
+                table.cols = items.len().div_ceil(table.rows);
+                let widths = table.column_widths();
+                let total: usize = width::display_width(&options.indent)
+                    + widths
+                        .iter()
+                        .map(|width| width + options.padding)
+                        .sum::<usize>();
+                if total > options.width {
+                    (table.rows, table.cols) = (rows, cols);
+                    break;
+                }
+                table.widths = widths;
+            }
+        }
+        table
+    }
+
+    /// Returns the index of the item at a column and row.
+    fn index(&self, x: usize, y: usize) -> usize {
+        match self.layout {
+            Layout::Column => x * self.rows + y,
+            _ => y * self.cols + x,
+        }
+    }
+
+    /// Returns the width of the widest item of each column.
+    fn column_widths(&self) -> Vec<usize> {
+        (0..self.cols)
+            .map(|x| {
+                (0..self.rows)
+                    .filter_map(|y| self.lengths.get(self.index(x, y)))
+                    .copied()
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(items: &[&str], mode: &str, width: usize) -> String {
+        let mut options = ColumnOptions {
+            width,
+            ..Default::default()
+        };
+        options.mode.parse(mode).unwrap();
+        let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+        let mut stdout = Vec::new();
+        print(&items, &options, &mut stdout).unwrap();
+        String::from_utf8(stdout).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut mode = Mode::default();
+        mode.parse("row dense").unwrap();
+        assert_eq!(
+            mode,
+            Mode {
+                enable: Enable::Always,
+                layout: Layout::Row,
+                dense: true
+            }
+        );
+        mode.parse("auto,nodense").unwrap();
+        assert_eq!(mode.enable, Enable::Auto);
+        assert!(!mode.dense);
+        assert_eq!(mode.resolve_auto(false).enable, Enable::Never);
+        assert_eq!(
+            mode.parse("bogus").unwrap_err().to_string(),
+            "unsupported option 'bogus'"
+        );
+    }
+
+    #[test]
+    fn test_print() {
+        let items = ["a", "bbbbbbbbbbb", "c", "d", "e", "fffffff", "g"];
+        assert_eq!(columns(&items, "never", 20), items.join("\n") + "\n");
+        assert_eq!(
+            columns(&items, "always", 40),
+            "a           d           g\n\
+             bbbbbbbbbbb e\n\
+             c           fffffff\n"
+        );
+        assert_eq!(
+            columns(&items, "row", 40),
+            "a           bbbbbbbbbbb c\n\
+             d           e           fffffff\n\
+             g\n"
+        );
+        assert_eq!(
+            columns(&items, "dense", 20),
+            "a           e\n\
+             bbbbbbbbbbb fffffff\n\
+             c           g\n\
+             d\n"
+        );
+        // Items wider than the output get a line each.
+        assert_eq!(
+            columns(&["aaaaaaaaaa", "b"], "always", 5),
+            "aaaaaaaaaa\nb\n"
+        );
+        // Wide characters take two columns.
+        assert_eq!(columns(&["山田", "ab", "c"], "always", 10), "山田 c\nab\n");
+    }
+}
@@ -0,0 +1,263 @@
+use anyhow::{anyhow, Result};
+use std::fs;
+
+use crate::repo::Repo;
+
+/// Parent positions with this value mean there is no parent.
+const NO_PARENT: u32 = 0x7000_0000;
+/// When set on the second parent, the other parents are in the `EDGE` chunk
+/// starting at the position in the lower bits.
+const EXTRA_PARENTS: u32 = 0x8000_0000;
+/// Generations are capped, so commits at this generation can't be compared.
+pub const GENERATION_MAX: u32 = 0x3fff_ffff;
+
+/// The commit-graph file at `.git/objects/info/commit-graph`, written by
+/// `git commit-graph write` or `git gc`.
+///
+/// It stores the parents and the generation of commits, which is 1 for root
+/// commits and 1 more than the largest generation of the parents otherwise.
+/// A commit can only reach commits with a smaller generation, which lets
+/// history walks stop early. Split commit-graph chains aren't read.
+#[derive(Debug)]
+pub struct CommitGraph {
+    data: Vec<u8>,
+    count: usize,
+    fanout: usize,
+    oids: usize,
+    commit_data: usize,
+    extra_edges: Option<usize>,
+}
+
+impl CommitGraph {
+    /// Returns the commit-graph of a repository, if it has one.
+    pub fn load(repo: &Repo) -> Result<Option<CommitGraph>> {
+        let path = repo.common_dir().join("objects/info/commit-graph");
+        match fs::read(path) {
+            Ok(data) => Ok(Some(CommitGraph::parse(data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Parses a commit-graph file.
+    ///
+    /// The format is a header (`CGPH`, version 1, hash version 1 for SHA-1,
+    /// chunk count, base graph count), a table of (chunk id, offset) pairs
+    /// ending with a zero id, then the chunks. The `OIDF` chunk is a fanout
+    /// table of the hashes by first byte, `OIDL` the sorted hashes, `CDAT`
+    /// the tree, parent positions, generation and date of each commit, and
+    /// the optional `EDGE` chunk the parents of octopus merges.
+    pub fn parse(data: Vec<u8>) -> Result<CommitGraph> {
+        if data.len() < 8 || &data[..4] != b"CGPH" || data[4] != 1 || data[5] != 1 {
+            return Err(corrupt());
+        }
+        let chunk_count = data[6] as usize;
+        let (mut fanout, mut oids, mut commit_data, mut extra_edges) = (None, None, None, None);
+        for i in 0..chunk_count {
+            let entry = data.get(8 + i * 12..8 + i * 12 + 12).ok_or_else(corrupt)?;
+            let offset = u64::from_be_bytes(entry[4..].try_into().unwrap()) as usize;
+            match &entry[..4] {
+                b"OIDF" => fanout = Some(offset),
+                b"OIDL" => oids = Some(offset),
+                b"CDAT" => commit_data = Some(offset),
+                b"EDGE" => extra_edges = Some(offset),
+                _ => {}
+            }
+        }
+        let (Some(fanout), Some(oids), Some(commit_data)) = (fanout, oids, commit_data) else {
+            return Err(corrupt());
+        };
+        let count = read_u32(&data, fanout + 255 * 4).ok_or_else(corrupt)? as usize;
+        if data.len() < oids + count * 20 || data.len() < commit_data + count * 36 {
+            return Err(corrupt());
+        }
+        Ok(CommitGraph {
+            data,
+            count,
+            fanout,
+            oids,
+            commit_data,
+            extra_edges,
+        })
+    }
+
+    /// Returns the position of a commit in the graph.
+    fn position(&self, hash: &str) -> Option<usize> {
+        let hash = hex::decode(hash).ok()?;
+        let first = *hash.first()? as usize;
+        let start = match first {
+            0 => 0,
+            _ => read_u32(&self.data, self.fanout + (first - 1) * 4)? as usize,
+        };
+        let end = read_u32(&self.data, self.fanout + first * 4)? as usize;
+        let oid = |position: usize| &self.data[self.oids + position * 20..][..20];
+        let (mut low, mut high) = (start, end.min(self.count));
+        while low < high {
+            let middle = (low + high) / 2;
+            match oid(middle).cmp(&hash[..]) {
+                std::cmp::Ordering::Equal => return Some(middle),
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+            }
+        }
+        None
+    }
+
+    /// Returns the hash of the commit at a position, which comes from the
+    /// file and so may be out of bounds.
+    fn hash_at(&self, position: usize) -> Result<String> {
+        if position >= self.count {
+            return Err(corrupt());
+        }
+        Ok(hex::encode(&self.data[self.oids + position * 20..][..20]))
+    }
+
+    /// Returns whether a commit is in the graph. Since the graph is written
+    /// from all the commits reachable from some tips, commits in the graph
+    /// never reach commits outside of it.
+    pub fn contains(&self, hash: &str) -> bool {
+        self.position(hash).is_some()
+    }
+
+    /// Returns the generation of a commit, if it's in the graph.
+    pub fn generation(&self, hash: &str) -> Option<u32> {
+        let position = self.position(hash)?;
+        Some(read_u32(&self.data, self.commit_data + position * 36 + 28)? >> 2)
+    }
+
+    /// Returns the parents of a commit, if it's in the graph.
+    pub fn parents(&self, hash: &str) -> Result<Option<Vec<String>>> {
+        let Some(position) = self.position(hash) else {
+            return Ok(None);
+        };
+        let entry = self.commit_data + position * 36;
+        let mut parents = vec![];
+        let first = read_u32(&self.data, entry + 20).ok_or_else(corrupt)?;
+        if first != NO_PARENT {
+            parents.push(self.hash_at(first as usize)?);
+        }
+        let second = read_u32(&self.data, entry + 24).ok_or_else(corrupt)?;
+        if second & EXTRA_PARENTS != 0 {
+            let edges = self.extra_edges.ok_or_else(corrupt)?;
+            let mut edge = edges + (second & !EXTRA_PARENTS) as usize * 4;
+            loop {
+                let parent = read_u32(&self.data, edge).ok_or_else(corrupt)?;
+                parents.push(self.hash_at((parent & !EXTRA_PARENTS) as usize)?);
+                // The last parent is marked with the high bit.
+                if parent & EXTRA_PARENTS != 0 {
+                    break;
+                }
+                edge += 4;
+            }
+        } else if second != NO_PARENT {
+            parents.push(self.hash_at(second as usize)?);
+        }
+        Ok(Some(parents))
+    }
+}
+
+fn corrupt() -> anyhow::Error {
+    anyhow!("Corrupt commit-graph file")
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a commit-graph with commits `0101…` (root), `0202…` (child of
+    /// the root) and `0303…` (merge of the other two, with the second parent
+    /// in the `EDGE` chunk).
+    fn test_graph() -> Vec<u8> {
+        let hash = |b: u8| [b; 20];
+        let mut data = b"CGPH\x01\x01\x04\x00".to_vec();
+        let header_len = 8 + 5 * 12;
+        let fanout = header_len;
+        let oids = fanout + 256 * 4;
+        let commit_data = oids + 3 * 20;
+        let edges = commit_data + 3 * 36;
+        for (id, offset) in [
+            (b"OIDF", fanout),
+            (b"OIDL", oids),
+            (b"CDAT", commit_data),
+            (b"EDGE", edges),
+            (&[0u8; 4], edges + 4),
+        ] {
+            data.extend(id);
+            data.extend((offset as u64).to_be_bytes());
+        }
+        for first_byte in 0..256u32 {
+            data.extend(first_byte.min(3).to_be_bytes());
+        }
+        for b in 1..=3 {
+            data.extend(hash(b));
+        }
+        for (parents, generation) in [
+            ([NO_PARENT, NO_PARENT], 1u32),
+            ([0, NO_PARENT], 2),
+            ([1, EXTRA_PARENTS], 3),
+        ] {
+            data.extend([0; 20]);
+            data.extend(parents[0].to_be_bytes());
+            data.extend(parents[1].to_be_bytes());
+            data.extend((generation << 2).to_be_bytes());
+            data.extend(0u32.to_be_bytes());
+        }
+        // The root is the second and last parent in the edge list.
+        data.extend(EXTRA_PARENTS.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_commit_graph() {
+        let graph = CommitGraph::parse(test_graph()).unwrap();
+        let hash = |b: &str| b.repeat(20);
+        assert!(graph.contains(&hash("01")));
+        assert!(!graph.contains(&hash("04")));
+        assert_eq!(graph.generation(&hash("01")), Some(1));
+        assert_eq!(graph.generation(&hash("03")), Some(3));
+        assert_eq!(graph.generation(&hash("04")), None);
+        assert_eq!(graph.parents(&hash("01")).unwrap(), Some(vec![]));
+        assert_eq!(graph.parents(&hash("02")).unwrap(), Some(vec![hash("01")]));
+        assert_eq!(
+            graph.parents(&hash("03")).unwrap(),
+            Some(vec![hash("02"), hash("01")])
+        );
+        assert_eq!(graph.parents(&hash("04")).unwrap(), None);
+
+        assert!(CommitGraph::parse(b"CGPH\x02\x01\x00\x00".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_commit_graph_corrupt() {
+        let data = test_graph();
+        let commit_data = 8 + 5 * 12 + 256 * 4 + 3 * 20;
+        // The first parent of the second commit is out of bounds.
+        let mut corrupt = data.clone();
+        corrupt[commit_data + 36 + 20..][..4].copy_from_slice(&7u32.to_be_bytes());
+        let graph = CommitGraph::parse(corrupt).unwrap();
+        assert_eq!(
+            graph.parents(&"02".repeat(20)).unwrap_err().to_string(),
+            "Corrupt commit-graph file"
+        );
+
+        // No byte value makes reading the graph panic.
+        for i in 0..data.len() {
+            for byte in [0, 0x7f, 0x80, 0xff] {
+                let mut mutated = data.clone();
+                mutated[i] = byte;
+                if let Ok(graph) = CommitGraph::parse(mutated) {
+                    for b in ["01", "02", "03"] {
+                        let _ = graph.generation(&b.repeat(20));
+                        let _ = graph.parents(&b.repeat(20));
+                    }
+                }
+            }
+        }
+    }
+}
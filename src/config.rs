@@ -0,0 +1,468 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{lockfile::LockFile, repo::Repo};
+
+/// Git configuration, as read from one or more config files.
+///
+/// Keys are written as `section.name` or `section.subsection.name`. Section
+/// and variable names are case insensitive, subsections are not. When a key
+/// is set several times, the last value wins.
+#[derive(Debug, Default)]
+pub struct Config {
+    entries: Vec<(String, String)>,
+}
+
+impl Config {
+    /// Parses the contents of a config file.
+    pub fn parse(s: &str) -> Result<Config> {
+        let mut entries = vec![];
+        let mut section: Option<String> = None;
+        let mut lines = s.lines().enumerate();
+
+        while let Some((line_index, line)) = lines.next() {
+            let invalid = || anyhow!("Invalid config on line {}", line_index + 1);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let (header, rest) = header.split_once(']').ok_or_else(invalid)?;
+                section = Some(parse_section_header(header).ok_or_else(invalid)?);
+                // Variables are allowed on the same line as the section header.
+                let rest = rest.trim();
+                if rest.is_empty() || rest.starts_with('#') || rest.starts_with(';') {
+                    continue;
+                }
+                return Err(invalid());
+            }
+
+            let section = section.as_ref().ok_or_else(invalid)?;
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (line, None),
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                return Err(invalid());
+            }
+
+            // A variable without a value is a shorthand for true.
+            let value = match value {
+                None => "true".to_string(),
+                Some(value) => {
+                    let mut raw = value.to_string();
+                    // A trailing backslash continues the value on the next line.
+                    while raw.ends_with('\\') && !raw.ends_with("\\\\") {
+                        raw.pop();
+                        let (_, next) = lines.next().ok_or_else(invalid)?;
+                        raw.push_str(next);
+                    }
+                    parse_value(&raw).ok_or_else(invalid)?
+                }
+            };
+            entries.push((format!("{section}.{}", name.to_lowercase()), value));
+        }
+
+        Ok(Config { entries })
+    }
+
+    /// Loads the system and global config files.
+    ///
+    /// Missing files are ignored.
+    pub fn load_global() -> Result<Config> {
+        let mut config = Config::default();
+        let mut paths = vec![PathBuf::from("/etc/gitconfig")];
+        if let Some(xdg_config_home) = std::env::var_os("XDG_CONFIG_HOME") {
+            paths.push(PathBuf::from(xdg_config_home).join("git/config"));
+        } else if let Some(home) = home_dir() {
+            paths.push(home.join(".config/git/config"));
+        }
+        if let Some(home) = home_dir() {
+            paths.push(home.join(".gitconfig"));
+        }
+
+        for path in paths {
+            config.extend_from_file(&path)?;
+        }
+        Ok(config)
+    }
+
+    /// Loads the system, global and repository config files.
+    pub fn load(repo: &Repo) -> Result<Config> {
+        let mut config = Config::load_global()?;
+        config.extend_from_file(&repo.common_dir().join("config"))?;
+        Ok(config)
+    }
+
+    /// Returns the last value set for a key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns all values set for a key, in the order they were set.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Returns the value of a key interpreted as a boolean.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+        match value.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Ok(Some(true)),
+            "false" | "no" | "off" | "0" | "" => Ok(Some(false)),
+            _ => Err(anyhow!("Invalid boolean value for {key}: {value}")),
+        }
+    }
+
+    /// Returns the value of a key interpreted as an integer.
+    ///
+    /// The suffixes `k`, `m` and `g` multiply the value by 1024, 1024^2 and
+    /// 1024^3 respectively.
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+        let invalid = || anyhow!("Invalid integer value for {key}: {value}");
+        let lower = value.to_lowercase();
+        let (number, factor) = match lower.chars().last() {
+            Some('k') => (&lower[..lower.len() - 1], 1 << 10),
+            Some('m') => (&lower[..lower.len() - 1], 1 << 20),
+            Some('g') => (&lower[..lower.len() - 1], 1 << 30),
+            _ => (lower.as_str(), 1),
+        };
+        let number: i64 = number.trim().parse().map_err(|_| invalid())?;
+        number.checked_mul(factor).map(Some).ok_or_else(invalid)
+    }
+
+    /// Returns the value of a key interpreted as a path, where a leading `~/`
+    /// is the home directory.
+    pub fn get_path(&self, key: &str) -> Option<PathBuf> {
+        let value = self.get(key)?;
+        match (value.strip_prefix("~/"), home_dir()) {
+            (Some(rest), Some(home)) => Some(home.join(rest)),
+            _ => Some(PathBuf::from(value)),
+        }
+    }
+
+    /// Returns `core.bigFileThreshold`, the size in bytes above which files
+    /// are streamed instead of loaded into memory and treated as binary.
+    pub fn big_file_threshold(&self) -> Result<u64> {
+        const DEFAULT_BIG_FILE_THRESHOLD: i64 = 512 * 1024 * 1024;
+        let threshold = self
+            .get_int("core.bigFileThreshold")?
+            .unwrap_or(DEFAULT_BIG_FILE_THRESHOLD);
+        Ok(u64::try_from(threshold)?)
+    }
+
+    fn extend_from_file(&mut self, path: &std::path::Path) -> Result<()> {
+        if !path.is_file() {
+            return Ok(());
+        }
+        let config = Config::parse(&fs::read_to_string(path)?)
+            .map_err(|e| anyhow!("{e} in {}", path.display()))?;
+        self.entries.extend(config.entries);
+        Ok(())
+    }
+}
+
+/// Sets a key in a config file, replacing its last value if it's set, or
+/// adding it after the last variable of the last section it belongs to, or
+/// in a new section at the end of the file. The file is created if needed.
+pub fn set_value(path: &Path, key: &str, value: &str) -> Result<()> {
+    let (section, name) = key
+        .rsplit_once('.')
+        .filter(|(section, name)| !section.is_empty() && !name.is_empty())
+        .ok_or(anyhow!("key does not contain a section: {key}"))?;
+    let (section_key, _) = normalize_key(key)
+        .rsplit_once('.')
+        .map(|(section, name)| (section.to_string(), name.to_string()))
+        .expect("the key has a section");
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut in_section = false;
+    // The last variable or header of the last section with the key, and the
+    // line setting it.
+    let mut section_end = None;
+    let mut existing = None;
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('[') {
+            in_section = header
+                .split_once(']')
+                .and_then(|(header, _)| parse_section_header(header))
+                .is_some_and(|header| header == section_key);
+        } else if !in_section || line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        } else if line
+            .split('=')
+            .next()
+            .is_some_and(|variable| variable.trim().eq_ignore_ascii_case(name))
+        {
+            existing = Some(i);
+        }
+        if in_section {
+            section_end = Some(i);
+        }
+    }
+
+    let entry = format!("\t{name} = {}", quote_value(value));
+    match (existing, section_end) {
+        (Some(i), _) => lines[i] = entry,
+        (None, Some(i)) => lines.insert(i + 1, entry),
+        (None, None) => {
+            let header = match section.split_once('.') {
+                Some((section, subsection)) => {
+                    let subsection = subsection.replace('\\', "\\\\").replace('"', "\\\"");
+                    format!("[{section} \"{subsection}\"]")
+                }
+                None => format!("[{section}]"),
+            };
+            lines.push(header);
+            lines.push(entry);
+        }
+    }
+
+    let mut lock = LockFile::acquire(path)?;
+    for line in lines {
+        writeln!(lock, "{line}")?;
+    }
+    lock.commit()
+}
+
+/// Quotes a value for a config file if it has characters that would be
+/// lost or misread otherwise.
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    if value.trim() != value || value.contains(['#', ';']) {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+/// Parses a section header (without brackets) into the `section[.subsection]`
+/// prefix used for keys.
+///
+/// Supports both `section "subsection"` and the deprecated `section.subsection`.
+fn parse_section_header(header: &str) -> Option<String> {
+    match header.split_once(' ') {
+        Some((section, subsection)) => {
+            let subsection = subsection.trim().strip_prefix('"')?.strip_suffix('"')?;
+            let subsection = subsection.replace("\\\"", "\"").replace("\\\\", "\\");
+            Some(format!("{}.{subsection}", section.to_lowercase()))
+        }
+        None => match header.split_once('.') {
+            Some((section, subsection)) => Some(format!(
+                "{}.{}",
+                section.to_lowercase(),
+                subsection.to_lowercase()
+            )),
+            None => Some(header.to_lowercase()),
+        },
+    }
+}
+
+/// Parses a raw value, handling quotes, escapes and trailing comments.
+fn parse_value(raw: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    // Whitespace is only kept if it's followed by something else than a comment.
+    let mut pending_whitespace = String::new();
+    let mut chars = raw.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                value.push_str(&std::mem::take(&mut pending_whitespace));
+                in_quotes = !in_quotes;
+            }
+            '\\' => {
+                value.push_str(&std::mem::take(&mut pending_whitespace));
+                match chars.next()? {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'b' => {
+                        value.pop();
+                    }
+                    c @ ('\\' | '"') => value.push(c),
+                    _ => return None,
+                }
+            }
+            '#' | ';' if !in_quotes => break,
+            c if c.is_whitespace() && !in_quotes => pending_whitespace.push(c),
+            c => {
+                value.push_str(&std::mem::take(&mut pending_whitespace));
+                value.push(c);
+            }
+        }
+    }
+
+    (!in_quotes).then_some(value)
+}
+
+fn normalize_key(key: &str) -> String {
+    match (key.split_once('.'), key.rsplit_once('.')) {
+        (Some((section, _)), Some((rest, name))) if rest.len() > section.len() => format!(
+            "{}{}.{}",
+            section.to_lowercase(),
+            &rest[section.len()..],
+            name.to_lowercase()
+        ),
+        _ => key.to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(
+            "\
+# A comment
+[core]
+\tbare = false
+\tFileMode = true ; another comment
+[remote \"Origin\"]
+\turl = https://example.com/repo.git
+\tfetch = +refs/heads/*:refs/remotes/origin/*
+[user]
+\tname = \"Good  Git\" # the name
+\temail = good@git.com
+[safe]
+\tdirectory = /a
+\tdirectory = /b
+[alias]
+\tlg = log \\
+\t\t--oneline
+[section.Sub]
+\tflag
+",
+        )
+        .unwrap();
+
+        assert_eq!(config.get("core.bare"), Some("false"));
+        assert_eq!(config.get("CORE.filemode"), Some("true"));
+        assert_eq!(
+            config.get("remote.Origin.url"),
+            Some("https://example.com/repo.git")
+        );
+        assert_eq!(config.get("remote.origin.url"), None);
+        assert_eq!(config.get("user.name"), Some("Good  Git"));
+        assert_eq!(config.get("safe.directory"), Some("/b"));
+        assert_eq!(config.get_all("safe.directory"), vec!["/a", "/b"]);
+        assert_eq!(config.get("alias.lg"), Some("log \t\t--oneline"));
+        assert_eq!(config.get("section.sub.flag"), Some("true"));
+        assert_eq!(config.get_bool("section.sub.flag").unwrap(), Some(true));
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(false));
+    }
+
+    #[test]
+    fn test_parse_config_invalid() {
+        assert_eq!(
+            Config::parse("[core]\nbare = \"false\n")
+                .unwrap_err()
+                .to_string(),
+            "Invalid config on line 2"
+        );
+        assert_eq!(
+            Config::parse("name = value").unwrap_err().to_string(),
+            "Invalid config on line 1"
+        );
+    }
+
+    #[test]
+    fn test_set_value() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("config");
+        fs::write(
+            &path,
+            "[core]\n\tbare = false\n[branch \"main\"]\n\tremote = origin\n\n[core]\n\t# comment\n",
+        )
+        .unwrap();
+        set_value(&path, "branch.main.remote", "upstream").unwrap();
+        set_value(&path, "core.autocrlf", "input").unwrap();
+        set_value(&path, "branch.Feature.merge", "refs/heads/feature").unwrap();
+        set_value(&path, "user.name", " Good; Git ").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[core]\n\tbare = false\n[branch \"main\"]\n\tremote = upstream\n\n[core]\n\tautocrlf = input\n\
+             \t# comment\n[branch \"Feature\"]\n\tmerge = refs/heads/feature\n\
+             [user]\n\tname = \" Good; Git \"\n"
+        );
+        let config = Config::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(config.get("user.name"), Some(" Good; Git "));
+        assert_eq!(
+            config.get("branch.Feature.merge"),
+            Some("refs/heads/feature")
+        );
+        assert!(set_value(&path, "core", "x").is_err());
+    }
+
+    #[test]
+    fn test_get_int() {
+        let config = Config::parse("[core]\na = 12\nb = 2k\nc = 1m\nd = nope").unwrap();
+        assert_eq!(config.get_int("core.a").unwrap(), Some(12));
+        assert_eq!(config.get_int("core.b").unwrap(), Some(2048));
+        assert_eq!(config.get_int("core.c").unwrap(), Some(1024 * 1024));
+        assert_eq!(config.get_int("core.missing").unwrap(), None);
+        assert!(config.get_int("core.d").is_err());
+    }
+
+    #[test]
+    fn test_get_path() {
+        let config =
+            Config::parse("[init]\ntemplateDir = /a/b\n[core]\nhooksPath = ~/hooks").unwrap();
+        assert_eq!(
+            config.get_path("init.templateDir"),
+            Some(PathBuf::from("/a/b"))
+        );
+        if let Some(home) = home_dir() {
+            assert_eq!(config.get_path("core.hooksPath"), Some(home.join("hooks")));
+        }
+        assert_eq!(config.get_path("core.missing"), None);
+    }
+
+    #[test]
+    fn test_big_file_threshold() {
+        let config = Config::parse("[core]\nbigFileThreshold = 1k").unwrap();
+        assert_eq!(config.big_file_threshold().unwrap(), 1024);
+        assert_eq!(
+            Config::default().big_file_threshold().unwrap(),
+            512 * 1024 * 1024
+        );
+    }
+}
@@ -0,0 +1,1628 @@
+use anyhow::{anyhow, Result};
+use flate2::{write::ZlibEncoder, Compression};
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, Write},
+};
+
+use crate::{
+    add_patch::{self, Hunk, Line},
+    attributes::{AttributeValue, Attributes},
+    base85,
+    binary::{BinaryClassifier, TextAttribute},
+    color::{DiffColors, RESET},
+    config::Config,
+    filter::Filters,
+    index::{FileModes, Index},
+    object::{self, Blob, Object},
+    refs::ZERO_HASH,
+    rename,
+    repo::Repo,
+    status::{self, Change},
+    width,
+    word_diff::{self, WordDiffMode},
+};
+
+/// How paths are shown in diffs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffPathOptions {
+    /// Prefix of the old path in headers, `a/` by default.
+    pub src_prefix: String,
+    /// Prefix of the new path in headers, `b/` by default.
+    pub dst_prefix: String,
+    /// With `--relative`, only changes in this directory are shown, with
+    /// paths relative to it.
+    pub relative: Option<String>,
+}
+
+impl Default for DiffPathOptions {
+    fn default() -> Self {
+        DiffPathOptions {
+            src_prefix: "a/".to_string(),
+            dst_prefix: "b/".to_string(),
+            relative: None,
+        }
+    }
+}
+
+impl DiffPathOptions {
+    /// Returns the options set by `diff.noprefix`, `diff.srcPrefix` and
+    /// `diff.dstPrefix`. Command line options are applied on top of these.
+    pub fn from_config(config: &Config) -> Result<DiffPathOptions> {
+        let mut options = DiffPathOptions::default();
+        if config.get_bool("diff.noprefix")? == Some(true) {
+            options.src_prefix.clear();
+            options.dst_prefix.clear();
+        }
+        if let Some(prefix) = config.get("diff.srcPrefix") {
+            options.src_prefix = prefix.to_string();
+        }
+        if let Some(prefix) = config.get("diff.dstPrefix") {
+            options.dst_prefix = prefix.to_string();
+        }
+        Ok(options)
+    }
+
+    /// Returns the path to show for a path relative to the root of the
+    /// repository, or `None` if it's outside of the `--relative` directory.
+    pub fn display_path<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let Some(dir) = &self.relative else {
+            return Some(path);
+        };
+        let dir = dir.trim_end_matches('/');
+        if dir.is_empty() {
+            return Some(path);
+        }
+        path.strip_prefix(dir)?.strip_prefix('/')
+    }
+
+    /// Returns the `diff --git`, `---` and `+++` lines for a change, where a
+    /// missing path is an added or deleted file.
+    ///
+    /// Paths must already be made relative with [`DiffPathOptions::display_path`].
+    pub fn header(&self, old_path: Option<&str>, new_path: Option<&str>) -> String {
+        format!(
+            "{}{}",
+            self.git_header(old_path, new_path),
+            self.file_header(old_path, new_path)
+        )
+    }
+
+    /// Returns the `diff --git` line of [`DiffPathOptions::header`].
+    pub fn git_header(&self, old_path: Option<&str>, new_path: Option<&str>) -> String {
+        let old_name = old_path.or(new_path).unwrap_or_default();
+        let new_name = new_path.or(old_path).unwrap_or_default();
+        format!(
+            "diff --git {}{old_name} {}{new_name}\n",
+            self.src_prefix, self.dst_prefix
+        )
+    }
+
+    /// Returns the `---` and `+++` lines of [`DiffPathOptions::header`].
+    pub fn file_header(&self, old_path: Option<&str>, new_path: Option<&str>) -> String {
+        format!(
+            "--- {}\n+++ {}\n",
+            side_name(&self.src_prefix, old_path),
+            side_name(&self.dst_prefix, new_path),
+        )
+    }
+
+    /// Returns the line replacing the hunks of binary files.
+    pub fn binary_differ(&self, old_path: Option<&str>, new_path: Option<&str>) -> String {
+        format!(
+            "Binary files {} and {} differ\n",
+            side_name(&self.src_prefix, old_path),
+            side_name(&self.dst_prefix, new_path),
+        )
+    }
+}
+
+/// Returns a prefixed path, or `/dev/null` for a missing side.
+fn side_name(prefix: &str, path: Option<&str>) -> String {
+    match path {
+        Some(path) => format!("{prefix}{path}"),
+        None => "/dev/null".to_string(),
+    }
+}
+
+/// How changes are shown as patches, like with `git diff -p`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchOptions {
+    /// The number of unchanged lines around changes, 3 by default.
+    pub context: usize,
+    /// Show binary files as binary patches, which can be applied, instead of
+    /// `Binary files differ`, with their full hashes, like `--binary`.
+    pub binary: bool,
+    /// Show all files as text, like `--text`.
+    pub text: bool,
+    /// Compare changed lines word by word, like `--word-diff`.
+    pub word_diff: Option<WordDiffMode>,
+    /// The regex matching words, instead of the `diff.<driver>.wordRegex` of
+    /// the `diff=<driver>` attribute of a file or `diff.wordRegex`.
+    pub word_regex: Option<String>,
+    /// Color the patches, see [`write_patch`].
+    pub colors: Option<DiffColors>,
+    pub paths: DiffPathOptions,
+}
+
+impl Default for PatchOptions {
+    fn default() -> Self {
+        PatchOptions {
+            context: 3,
+            binary: false,
+            text: false,
+            word_diff: None,
+            word_regex: None,
+            colors: None,
+            paths: DiffPathOptions::default(),
+        }
+    }
+}
+
+impl PatchOptions {
+    /// Returns the options set by `diff.context` and those of
+    /// [`DiffPathOptions::from_config`].
+    pub fn from_config(config: &Config) -> Result<PatchOptions> {
+        let context = match config.get_int("diff.context")? {
+            Some(context) => usize::try_from(context)
+                .map_err(|_| anyhow!("Bad config variable 'diff.context': {context}"))?,
+            None => 3,
+        };
+        Ok(PatchOptions {
+            context,
+            binary: false,
+            text: false,
+            word_diff: None,
+            word_regex: None,
+            colors: None,
+            paths: DiffPathOptions::from_config(config)?,
+        })
+    }
+}
+
+/// A changed path, as shown in git's raw diff format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawChange {
+    pub path: String,
+    /// The old mode and hash, `None` if the path was added.
+    pub old: Option<(u32, String)>,
+    /// The new mode and hash, `None` if the path was deleted. The hash of a
+    /// file in the working tree is all zeros, since it's not hashed.
+    pub new: Option<(u32, String)>,
+    /// `A`, `D`, `M`, `T`, `R` for renames, or `U` for unmerged paths, which
+    /// have no sides.
+    pub status: char,
+    /// The old path of a rename and the similarity of its sides, see
+    /// [`detect_renames`].
+    pub renamed_from: Option<(String, u64)>,
+}
+
+impl RawChange {
+    /// Returns the change between two versions of a path, if they differ.
+    fn between(path: &str, old: Option<(u32, String)>, new: Option<(u32, String)>) -> Option<Self> {
+        let status = match (&old, &new) {
+            (None, None) => return None,
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Deleted,
+            (Some(old), Some(new)) if old == new => return None,
+            (Some((old_mode, _)), Some((new_mode, _))) if old_mode >> 12 != new_mode >> 12 => {
+                Change::TypeChanged
+            }
+            _ => Change::Modified,
+        };
+        Some(RawChange {
+            path: path.to_string(),
+            old,
+            new,
+            status: status.code(),
+            renamed_from: None,
+        })
+    }
+
+    fn unmerged(path: &str) -> Self {
+        RawChange {
+            path: path.to_string(),
+            old: None,
+            new: None,
+            status: 'U',
+            renamed_from: None,
+        }
+    }
+
+    /// The status letter, followed by the similarity in percent for renames,
+    /// like `R066`.
+    fn status(&self) -> String {
+        match &self.renamed_from {
+            Some((_, score)) => format!("R{:03}", score * 100 / rename::MAX_SCORE),
+            None => self.status.to_string(),
+        }
+    }
+
+    /// The path, or both paths of a rename separated by a tab.
+    fn paths(&self) -> String {
+        match &self.renamed_from {
+            Some((old_path, _)) => format!("{old_path}\t{}", self.path),
+            None => self.path.clone(),
+        }
+    }
+
+    /// The path as shown in stats and summaries, where renames are like
+    /// `dir/{old => new}`.
+    fn display_path(&self) -> String {
+        match &self.renamed_from {
+            Some((old_path, _)) => rename_display(old_path, &self.path),
+            None => self.path.clone(),
+        }
+    }
+}
+
+/// Shows a rename like git, `old => new`, with the leading and trailing
+/// directories both paths have in common outside of braces, as in
+/// `dir/{old => new}/file`.
+fn rename_display(old: &str, new: &str) -> String {
+    let (old_bytes, new_bytes) = (old.as_bytes(), new.as_bytes());
+    let prefix = old_bytes
+        .iter()
+        .zip(new_bytes)
+        .take_while(|(a, b)| a == b)
+        .enumerate()
+        .filter(|(_, (&byte, _))| byte == b'/')
+        .last()
+        .map_or(0, |(i, _)| i + 1);
+    // The suffix may start at the slash ending the prefix.
+    let start = prefix.saturating_sub(1);
+    let mut suffix = 0;
+    for (i, (a, b)) in old_bytes
+        .iter()
+        .rev()
+        .zip(new_bytes.iter().rev())
+        .enumerate()
+    {
+        if a != b || old.len() - i <= start || new.len() - i <= start {
+            break;
+        }
+        if *a == b'/' {
+            suffix = i + 1;
+        }
+    }
+    if prefix + suffix == 0 {
+        return format!("{old} => {new}");
+    }
+    let middle = |path: &str| path[prefix..(path.len() - suffix).max(prefix)].to_string();
+    format!(
+        "{}{{{} => {}}}{}",
+        &old[..prefix],
+        middle(old),
+        middle(new),
+        &old[old.len() - suffix..]
+    )
+}
+
+/// Pairs the deleted and added paths of changes into renames, see
+/// [`rename::find_renames`]. A rename takes the place of its added path.
+pub fn detect_renames(repo: &Repo, changes: Vec<RawChange>) -> Result<Vec<RawChange>> {
+    let sides = |status: char| -> BTreeMap<String, (u32, String)> {
+        changes
+            .iter()
+            .filter(|change| change.status == status)
+            .filter_map(|change| {
+                Some((
+                    change.path.clone(),
+                    change.old.clone().or(change.new.clone())?,
+                ))
+            })
+            .collect()
+    };
+    let (deleted, added) = (sides('D'), sides('A'));
+    let renames = rename::find_renames(repo, &deleted, &added, rename::DEFAULT_MIN_SCORE)?;
+    if renames.is_empty() {
+        return Ok(changes);
+    }
+    let old_paths: BTreeMap<&str, &rename::Rename> = renames
+        .iter()
+        .map(|rename| (rename.old_path.as_str(), rename))
+        .collect();
+    let new_paths: BTreeMap<&str, &rename::Rename> = renames
+        .iter()
+        .map(|rename| (rename.new_path.as_str(), rename))
+        .collect();
+    let mut detected = vec![];
+    for change in changes {
+        match change.status {
+            'D' if old_paths.contains_key(change.path.as_str()) => {}
+            'A' => match new_paths.get(change.path.as_str()) {
+                Some(rename) => detected.push(RawChange {
+                    old: Some(deleted[&rename.old_path].clone()),
+                    status: 'R',
+                    renamed_from: Some((rename.old_path.clone(), rename.score)),
+                    ..change
+                }),
+                None => detected.push(change),
+            },
+            _ => detected.push(change),
+        }
+    }
+    Ok(detected)
+}
+
+impl RawChange {
+    /// Formats the change like its [`Display`](fmt::Display), with the hashes
+    /// shortened by `abbreviate`.
+    fn format_with(&self, abbreviate: impl Fn(&str) -> Result<String>) -> Result<String> {
+        let side = |side: &Option<(u32, String)>| -> Result<(u32, String)> {
+            Ok(match side {
+                Some((mode, hash)) => (*mode, abbreviate(hash)?),
+                None => (0, abbreviate(ZERO_HASH)?),
+            })
+        };
+        let ((old_mode, old_hash), (new_mode, new_hash)) = (side(&self.old)?, side(&self.new)?);
+        Ok(format!(
+            ":{old_mode:06o} {new_mode:06o} {old_hash} {new_hash} {}\t{}",
+            self.status(),
+            self.paths()
+        ))
+    }
+
+    /// Formats the change like its [`Display`](fmt::Display), with
+    /// abbreviated hashes like `git diff --raw`.
+    pub fn to_abbreviated_string(&self, abbreviator: &object::Abbreviator) -> Result<String> {
+        self.format_with(|hash| abbreviator.abbreviate(hash))
+    }
+}
+
+impl fmt::Display for RawChange {
+    /// Formats the change like `:100644 100644 <old hash> <new hash> M\tpath`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let raw = self
+            .format_with(|hash| Ok(hash.to_string()))
+            .map_err(|_| fmt::Error)?;
+        write!(f, "{raw}")
+    }
+}
+
+/// How [`RawChange`]s are printed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    /// With modes and hashes.
+    #[default]
+    Raw,
+    /// Only the paths.
+    NameOnly,
+    /// The status letters and the paths.
+    NameStatus,
+}
+
+impl RawFormat {
+    pub fn format(self, change: &RawChange) -> String {
+        match self {
+            RawFormat::Raw => change.to_string(),
+            RawFormat::NameOnly => change.path.clone(),
+            RawFormat::NameStatus => format!("{}\t{}", change.status(), change.paths()),
+        }
+    }
+}
+
+/// Reads both sides of changes for patches and stats. Files in the working
+/// tree, with an all zeros hash, are read through their clean filters and
+/// hashed. Submodules are the commit they point to.
+///
+/// Like git, a side is binary if the `diff` attribute of its path is unset,
+/// as with the `binary` macro, or if `diff.<driver>.binary` is set for its
+/// `diff=<driver>`. A set `diff` attribute makes it text, and otherwise its
+/// content decides, see [`BinaryClassifier`].
+pub struct ChangeReader<'a> {
+    repo: &'a Repo,
+    filters: Filters<'a>,
+    config: Config,
+    attributes: Attributes,
+    classifier: BinaryClassifier,
+    abbreviator: object::Abbreviator<'a>,
+}
+
+impl<'a> ChangeReader<'a> {
+    pub fn new(repo: &'a Repo) -> Result<ChangeReader<'a>> {
+        let config = Config::load(repo)?;
+        Ok(ChangeReader {
+            repo,
+            filters: Filters::new(repo)?,
+            classifier: BinaryClassifier::from_config(&config)?,
+            abbreviator: object::Abbreviator::new(repo, object::abbrev_len(&config)?),
+            config,
+            attributes: Attributes::load(repo)?,
+        })
+    }
+
+    /// Returns both sides of a change, and whether any of them is binary.
+    fn read(&self, change: &RawChange) -> Result<(Option<PatchSide>, Option<PatchSide>, bool)> {
+        let old = self.read_side(&change.path, &change.old)?;
+        let new = self.read_side(&change.path, &change.new)?;
+        let attribute = TextAttribute::for_path(&self.attributes, &self.config, &change.path)?;
+        let binary = [&old, &new].into_iter().flatten().any(|side| {
+            side.mode != 0o160000
+                && self
+                    .classifier
+                    .is_binary(&side.content, side.content.len() as u64, attribute)
+        });
+        Ok((old, new, binary))
+    }
+
+    fn read_side(&self, path: &str, side: &Option<(u32, String)>) -> Result<Option<PatchSide>> {
+        let Some((mode, hash)) = side else {
+            return Ok(None);
+        };
+        let (mode, mut hash) = (*mode, hash.clone());
+        let content = if mode == 0o160000 {
+            format!("Subproject commit {hash}\n").into_bytes()
+        } else if hash == ZERO_HASH {
+            let full_path = self.repo.worktree_path(path)?;
+            let content = match fs::symlink_metadata(&full_path)?.is_symlink() {
+                true => fs::read_link(&full_path)?
+                    .to_string_lossy()
+                    .into_owned()
+                    .into_bytes(),
+                false => self.filters.clean(path, fs::read(&full_path)?)?,
+            };
+            let blob = Blob::new(content);
+            hash = blob.hash();
+            blob.content
+        } else {
+            match Object::from_hash(self.repo, &hash)? {
+                Object::Blob(blob) => blob.content,
+                _ => return Err(anyhow!("Not a blob: {hash}")),
+            }
+        };
+        Ok(Some(PatchSide {
+            mode,
+            hash,
+            content,
+        }))
+    }
+
+    /// Returns the `diff.<driver>.wordRegex` of the `diff=<driver>` attribute
+    /// of a path, or else `diff.wordRegex`.
+    fn word_regex(&self, path: &str) -> Result<Option<String>> {
+        if let Some(AttributeValue::Value(driver)) = self.attributes.get(path, "diff")? {
+            if let Some(regex) = self.config.get(&format!("diff.{driver}.wordRegex")) {
+                return Ok(Some(regex.to_string()));
+            }
+        }
+        Ok(self.config.get("diff.wordRegex").map(str::to_string))
+    }
+}
+
+/// Writes a change as a patch, like `git diff -p`: its `diff --git` line,
+/// the lines describing the modes and hashes of both sides, and the hunks
+/// of their content, if any.
+///
+/// Type changes are a deletion followed by an addition. The hunks of binary
+/// files are replaced by `Binary files a/path and b/path differ`, or by a
+/// binary patch with [`PatchOptions::binary`].
+pub fn write_patch(
+    reader: &ChangeReader,
+    change: &RawChange,
+    options: &PatchOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let Some(path) = options.paths.display_path(&change.path) else {
+        return Ok(());
+    };
+    match change.status {
+        'U' => return Ok(writeln!(stdout, "* Unmerged path {path}")?),
+        'T' => {
+            for (old, new, status) in [(&change.old, &None, 'D'), (&None, &change.new, 'A')] {
+                let change = RawChange {
+                    path: change.path.clone(),
+                    old: old.clone(),
+                    new: new.clone(),
+                    status,
+                    renamed_from: None,
+                };
+                write_patch(reader, &change, options, stdout)?;
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let (old, new, binary) = reader.read(change)?;
+    let renamed_from = match &change.renamed_from {
+        Some((old_path, _)) => options.paths.display_path(old_path).unwrap_or(old_path),
+        None => path,
+    };
+    let (old_path, new_path) = (
+        old.as_ref().map(|_| renamed_from),
+        new.as_ref().map(|_| path),
+    );
+    let mut header = options.paths.git_header(old_path, new_path);
+    match (&old, &new) {
+        (None, Some(new)) => header += &format!("new file mode {:06o}\n", new.mode),
+        (Some(old), None) => header += &format!("deleted file mode {:06o}\n", old.mode),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            header += &format!("old mode {:06o}\nnew mode {:06o}\n", old.mode, new.mode)
+        }
+        _ => {}
+    }
+    if let Some((_, score)) = &change.renamed_from {
+        header += &format!(
+            "similarity index {}%\nrename from {renamed_from}\nrename to {path}\n",
+            score * 100 / rename::MAX_SCORE
+        );
+    }
+    // Like git, a file whose content is the same on both sides isn't shown,
+    // unless its mode or its path changed.
+    let must_show_header = match (&old, &new) {
+        (Some(old), Some(new)) => old.mode != new.mode || change.renamed_from.is_some(),
+        _ => true,
+    };
+    let hash = |side: &Option<PatchSide>| match side {
+        Some(side) => side.hash.clone(),
+        None => ZERO_HASH.to_string(),
+    };
+    // Like git, binary files have full hashes with --binary, even as text.
+    let abbreviate = |hash: String| match options.binary && binary {
+        true => Ok(hash),
+        false => reader.abbreviator.abbreviate(&hash),
+    };
+    let same_content = hash(&old) == hash(&new);
+    if !same_content {
+        header += &format!(
+            "index {}..{}",
+            abbreviate(hash(&old))?,
+            abbreviate(hash(&new))?
+        );
+        match (&old, &new) {
+            (Some(old), Some(new)) if old.mode == new.mode => {
+                header += &format!(" {:06o}\n", old.mode)
+            }
+            _ => header.push('\n'),
+        }
+    }
+
+    // Like git, word diffs in color are colored even without colors.
+    let default_colors = DiffColors::default();
+    let colors = match (&options.colors, options.word_diff) {
+        (Some(colors), _) => Some(colors),
+        (None, Some(WordDiffMode::Color)) => Some(&default_colors),
+        (None, _) => None,
+    };
+    let meta = |lines: &str| match colors {
+        Some(colors) => lines.lines().fold(String::new(), |mut meta, line| {
+            meta += &format!("{}{line}{RESET}\n", colors.meta);
+            meta
+        }),
+        None => lines.to_string(),
+    };
+    let content = |side: Option<PatchSide>| side.map(|side| side.content).unwrap_or_default();
+    if binary && !options.text {
+        if must_show_header || !same_content {
+            write!(stdout, "{}", meta(&header))?;
+        }
+        if !same_content {
+            match options.binary {
+                true => write_binary_patch(&content(old), &content(new), stdout)?,
+                false => write!(
+                    stdout,
+                    "{}",
+                    options.paths.binary_differ(old_path, new_path)
+                )?,
+            }
+        }
+        return Ok(());
+    }
+    let (old, new) = (content(old), content(new));
+    let hunks = add_patch::hunks(&old, &new, options.context);
+    if must_show_header || !hunks.is_empty() {
+        write!(stdout, "{}", meta(&header))?;
+    }
+    if hunks.is_empty() {
+        return Ok(());
+    }
+    write!(
+        stdout,
+        "{}",
+        meta(&options.paths.file_header(old_path, new_path))
+    )?;
+    let Some(mode) = options.word_diff else {
+        let blank_at_eof = blank_lines_at_eof(&old, &new);
+        for hunk in hunks {
+            match colors {
+                Some(colors) => stdout.write_all(&colored_hunk(&hunk, colors, blank_at_eof))?,
+                None => stdout.write_all(&hunk.to_bytes())?,
+            }
+        }
+        return Ok(());
+    };
+    let regex = match &options.word_regex {
+        Some(regex) => Some(regex.clone()),
+        None => reader.word_regex(&change.path)?,
+    };
+    let regex = regex
+        .map(|regex| {
+            regex::bytes::RegexBuilder::new(&regex)
+                .multi_line(true)
+                .build()
+                .map_err(|_| anyhow!("invalid regular expression: {regex}"))
+        })
+        .transpose()?;
+    for hunk in hunks {
+        stdout.write_all(&word_diff::hunk_to_bytes(
+            &hunk,
+            mode,
+            regex.as_ref(),
+            colors,
+        ))?;
+    }
+    Ok(())
+}
+
+/// Returns the `@@` line of a hunk, with colors like git's: the ranges in
+/// `frag` and the function in `func`.
+pub fn hunk_header(hunk: &Hunk, colors: Option<&DiffColors>) -> Vec<u8> {
+    let Some(colors) = colors else {
+        return hunk.header();
+    };
+    let header = Hunk {
+        function: vec![],
+        lines: vec![],
+        ..*hunk
+    }
+    .header();
+    let mut out = colors.frag.as_bytes().to_vec();
+    out.extend(header.strip_suffix(b"\n").unwrap_or(&header));
+    out.extend(RESET.as_bytes());
+    let function = hunk.function.strip_suffix(b"\r").unwrap_or(&hunk.function);
+    if !function.is_empty() {
+        out.extend(format!("{} {RESET}", colors.context).as_bytes());
+        out.extend(colors.func.as_bytes());
+        out.extend(function);
+        out.extend(RESET.as_bytes());
+    }
+    out.push(b'\n');
+    out
+}
+
+/// Returns a hunk like [`Hunk::to_bytes`], colored like git's: the removed
+/// lines in `old`, the added lines in `new`, except for their whitespace
+/// errors, and the unchanged lines in `context`.
+///
+/// Whitespace errors are git's default ones: trailing whitespace, spaces
+/// before tabs in the indentation, and blank lines added at the end of the
+/// file after `blank_at_eof`, see [`blank_lines_at_eof`].
+fn colored_hunk(hunk: &Hunk, colors: &DiffColors, blank_at_eof: Option<(usize, usize)>) -> Vec<u8> {
+    let mut out = hunk_header(hunk, Some(colors));
+    // Like git, the numbers of the lines after the current ones.
+    let (mut old_line, mut new_line) = (hunk.old_start, hunk.new_start);
+    for line in &hunk.lines {
+        let text = match line {
+            Line::Context(text) => {
+                (old_line, new_line) = (old_line + 1, new_line + 1);
+                write_colored_line(&colors.context, b' ', text, &mut out);
+                text
+            }
+            Line::Removed(text) => {
+                old_line += 1;
+                write_colored_line(&colors.old, b'-', text, &mut out);
+                text
+            }
+            Line::Added(text) => {
+                new_line += 1;
+                let at_eof =
+                    blank_at_eof.is_some_and(|(old, new)| old <= old_line && new <= new_line);
+                if colors.whitespace.is_empty() {
+                    write_colored_line(&colors.new, b'+', text, &mut out);
+                } else if at_eof && text.iter().all(|&b| is_space(b)) {
+                    write_colored_line(&colors.whitespace, b'+', text, &mut out);
+                } else {
+                    out.extend(format!("{}+{RESET}", colors.new).as_bytes());
+                    write_whitespace_errors(text, colors, &mut out);
+                }
+                text
+            }
+        };
+        if !text.ends_with(b"\n") {
+            out.push(b'\n');
+            write_colored_line(
+                &colors.context,
+                b'\\',
+                b" No newline at end of file\n",
+                &mut out,
+            );
+        }
+    }
+    out
+}
+
+/// Writes the sign and the text of a line in a color, before the `\r` and
+/// `\n` ending it.
+fn write_colored_line(color: &str, sign: u8, text: &[u8], out: &mut Vec<u8>) {
+    let (text, newline) = split_end(text, b'\n');
+    let (text, cr) = split_end(text, b'\r');
+    out.extend(color.as_bytes());
+    out.push(sign);
+    out.extend(text);
+    out.extend(RESET.as_bytes());
+    out.extend(cr);
+    out.extend(newline);
+}
+
+/// Writes the text of an added line in the `new` color, except for its
+/// whitespace errors, in the `whitespace` color, like git's `ws_check_emit`.
+fn write_whitespace_errors(text: &[u8], colors: &DiffColors, out: &mut Vec<u8>) {
+    let (text, newline) = split_end(text, b'\n');
+    let trailing = text.len() - text.iter().rev().take_while(|&&b| is_space(b)).count();
+    // The indentation is written as is, except for spaces before tabs.
+    let mut written = 0;
+    for i in 0..trailing {
+        match text[i] {
+            b' ' => continue,
+            b'\t' => {}
+            _ => break,
+        }
+        if written < i {
+            out.extend(colors.whitespace.as_bytes());
+            out.extend(&text[written..i]);
+            out.extend(RESET.as_bytes());
+        } else {
+            out.extend(&text[written..i]);
+        }
+        out.push(b'\t');
+        written = i + 1;
+    }
+    for (range, color) in [
+        (written..trailing, &colors.new),
+        (trailing..text.len(), &colors.whitespace),
+    ] {
+        if !range.is_empty() {
+            out.extend(color.as_bytes());
+            out.extend(&text[range]);
+            out.extend(RESET.as_bytes());
+        }
+    }
+    out.extend(newline);
+}
+
+/// Splits a byte off the end of a text if it's there.
+fn split_end(text: &[u8], end: u8) -> (&[u8], &[u8]) {
+    match text.last() {
+        Some(&last) if last == end => text.split_at(text.len() - 1),
+        _ => (text, &[]),
+    }
+}
+
+/// Returns whether a byte is whitespace for git.
+fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// Returns the first old and new lines of the blank lines that `new` has at
+/// its end, if it has more than `old`, like git's `check_blank_at_eof`.
+fn blank_lines_at_eof(old: &[u8], new: &[u8]) -> Option<(usize, usize)> {
+    let (old_blank, new_blank) = (trailing_blank_lines(old), trailing_blank_lines(new));
+    if new_blank <= old_blank {
+        return None;
+    }
+    let count = |text: &[u8]| {
+        let newlines = text.iter().filter(|&&b| b == b'\n').count();
+        newlines + usize::from(!text.is_empty() && !text.ends_with(b"\n"))
+    };
+    Some((count(old) - old_blank + 1, count(new) - new_blank + 1))
+}
+
+/// Returns the number of lines at the end of a text that are only whitespace.
+///
+/// Like git's `count_trailing_blank`, the first line of a text that is only
+/// newlines isn't counted.
+fn trailing_blank_lines(text: &[u8]) -> usize {
+    let Some(mut end) = text.len().checked_sub(1) else {
+        return 0;
+    };
+    if text[end] == b'\n' {
+        end = end.saturating_sub(1);
+    }
+    let mut count = 0;
+    // `end` is the last byte of the current line, or its newline if it's
+    // empty.
+    while end > 0 {
+        let start = text[..=end]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |newline| newline + 1);
+        if !text[start..end + 1].iter().all(|&b| is_space(b)) {
+            break;
+        }
+        count += 1;
+        match start.checked_sub(2) {
+            Some(previous) => end = previous,
+            None => break,
+        }
+    }
+    count
+}
+
+/// Writes git's binary patch from `old` to `new`: the `GIT binary patch`
+/// line, then the new content and the old one, to apply it in reverse, each
+/// as a `literal <size>` line followed by its data, deflated at git's
+/// default level, the fastest, in base85 lines, and an empty line.
+///
+/// Unlike git, which uses a `delta` from the other side when it's smaller,
+/// the content is always literal.
+fn write_binary_patch(old: &[u8], new: &[u8], stdout: &mut dyn io::Write) -> Result<()> {
+    writeln!(stdout, "GIT binary patch")?;
+    for content in [new, old] {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(content)?;
+        let deflated = encoder.finish()?;
+        write!(
+            stdout,
+            "literal {}\n{}\n",
+            content.len(),
+            base85::encode_lines(&deflated)
+        )?;
+    }
+    Ok(())
+}
+
+/// Drops the changes of files in the working tree whose stat data changed but
+/// not their content or mode, like `git diff` does.
+pub fn drop_stat_only_changes(
+    reader: &ChangeReader,
+    changes: Vec<RawChange>,
+) -> Result<Vec<RawChange>> {
+    let mut kept = vec![];
+    for change in changes {
+        let unchanged = match (&change.old, &change.new) {
+            (Some((old_mode, old_hash)), Some((new_mode, new_hash)))
+                if new_hash == ZERO_HASH && new_mode == old_mode =>
+            {
+                let new = reader.read_side(&change.path, &change.new)?;
+                new.is_some_and(|new| new.hash == *old_hash)
+            }
+            _ => false,
+        };
+        if !unchanged {
+            kept.push(change);
+        }
+    }
+    Ok(kept)
+}
+
+/// The number of lines added and deleted in a changed file, as summed up by
+/// `--stat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    pub path: String,
+    pub added: usize,
+    pub deleted: usize,
+    /// Unmerged paths have no counts.
+    pub unmerged: bool,
+    /// The counts of binary files are the sizes of their sides in bytes,
+    /// `deleted` being the old one, or zero if their content is the same.
+    pub binary: bool,
+}
+
+impl FileStat {
+    /// Counts the lines added and deleted by a change, reading its sides
+    /// like [`write_patch`].
+    pub fn compute(reader: &ChangeReader, change: &RawChange) -> Result<FileStat> {
+        let mut stat = FileStat {
+            path: change.display_path(),
+            added: 0,
+            deleted: 0,
+            unmerged: change.status == 'U',
+            binary: false,
+        };
+        let (old, new, binary) = reader.read(change)?;
+        // Like git, files in the working tree aren't hashed to compare them.
+        let hash = |side: &Option<(u32, String)>| side.as_ref().map(|(_, hash)| hash.clone());
+        let same_content = hash(&change.old) == hash(&change.new);
+        let content = |side: Option<PatchSide>| side.map(|side| side.content).unwrap_or_default();
+        let (old, new) = (content(old), content(new));
+        if binary {
+            stat.binary = true;
+            if !same_content {
+                (stat.added, stat.deleted) = (new.len(), old.len());
+            }
+            return Ok(stat);
+        }
+        for line in add_patch::hunks(&old, &new, 0)
+            .into_iter()
+            .flat_map(|hunk| hunk.lines)
+        {
+            match line {
+                add_patch::Line::Added(_) => stat.added += 1,
+                add_patch::Line::Removed(_) => stat.deleted += 1,
+                add_patch::Line::Context(_) => {}
+            }
+        }
+        Ok(stat)
+    }
+}
+
+/// Which summary of the changes is printed, before their patches if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatFormat {
+    /// A line per file with a histogram of its changes, and the totals, like
+    /// `--stat`.
+    Stat(StatOptions),
+    /// The numbers of added and deleted lines of each file, tab separated.
+    Numstat,
+    /// Only the totals.
+    Shortstat,
+}
+
+/// The widths and colors of `--stat` lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatOptions {
+    /// The width of the lines, usually the width of the terminal.
+    pub width: usize,
+    /// The maximum width of the paths, if the lines are long enough.
+    pub name_width: Option<usize>,
+    /// The maximum width of the histograms.
+    pub graph_width: Option<usize>,
+    /// Color the histograms and the sizes of binary files, added in `new`
+    /// and deleted in `old`.
+    pub colors: Option<Box<DiffColors>>,
+}
+
+impl Default for StatOptions {
+    fn default() -> Self {
+        StatOptions {
+            width: 80,
+            name_width: None,
+            graph_width: None,
+            colors: None,
+        }
+    }
+}
+
+/// Prints a summary of changes in a format.
+pub fn write_stats(
+    stats: &[FileStat],
+    format: &StatFormat,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    match format {
+        StatFormat::Stat(options) => write_stat(stats, options, stdout),
+        StatFormat::Numstat => {
+            for stat in stats.iter().filter(|stat| !stat.unmerged) {
+                match stat.binary {
+                    true => writeln!(stdout, "-\t-\t{}", stat.path)?,
+                    false => writeln!(stdout, "{}\t{}\t{}", stat.added, stat.deleted, stat.path)?,
+                }
+            }
+            Ok(())
+        }
+        StatFormat::Shortstat if stats.is_empty() => Ok(()),
+        StatFormat::Shortstat => write_stat_totals(stats, stdout),
+    }
+}
+
+/// Prints a `--stat` line per file, like ` path | 3 ++-`, then the totals.
+///
+/// Like git, paths take the width they need unless the lines would be too
+/// long, in which case they get up to 5/8 of it, and are shortened from
+/// their start. Histograms are scaled down to fit the rest.
+fn write_stat(stats: &[FileStat], options: &StatOptions, stdout: &mut dyn io::Write) -> Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let max_len = stats
+        .iter()
+        .map(|stat| width::display_width(&stat.path))
+        .max()
+        .unwrap_or_default();
+    // The width of what replaces the counts and histograms of files without
+    // counts.
+    let mut other_width = 0;
+    let mut max_change = 0;
+    // Binary files have `Bin` instead of a count.
+    let mut number_width = 0;
+    for stat in stats {
+        if stat.unmerged {
+            other_width = other_width.max("Unmerged".len());
+        } else if stat.binary {
+            let sizes_width = stat.added.to_string().len() + stat.deleted.to_string().len();
+            other_width = other_width.max(14 + sizes_width);
+            number_width = "Bin".len();
+        } else {
+            max_change = max_change.max(stat.added + stat.deleted);
+        }
+    }
+    let number_width = number_width.max(max_change.to_string().len());
+    let width = options.width.max(16 + 6 + number_width);
+
+    let mut graph_width = match max_change + 4 > other_width {
+        true => max_change,
+        false => other_width - 4,
+    };
+    let max_graph_width = options.graph_width.filter(|&max| max > 0);
+    if let Some(max) = max_graph_width {
+        graph_width = graph_width.min(max);
+    }
+    let mut name_width = match options.name_width {
+        Some(name_width) if name_width > 0 => max_len.min(name_width),
+        _ => max_len,
+    };
+    if name_width + number_width + 6 + graph_width > width {
+        let available = (width * 3 / 8).saturating_sub(number_width + 6);
+        if graph_width > available {
+            graph_width = available.max(6);
+        }
+        if let Some(max) = max_graph_width {
+            graph_width = graph_width.min(max);
+        }
+        let rest = width - number_width - 6;
+        if name_width > rest.saturating_sub(graph_width) {
+            name_width = rest.saturating_sub(graph_width);
+        } else {
+            graph_width = rest - name_width;
+        }
+    }
+
+    let colored = |color: fn(&DiffColors) -> &String, text: String| match &options.colors {
+        Some(colors) if !text.is_empty() => format!("{}{text}{RESET}", color(colors)),
+        _ => text,
+    };
+    let scale = |count: usize| match count {
+        0 => 0,
+        count => 1 + count * (graph_width - 1) / max_change,
+    };
+    for stat in stats {
+        let mut name = stat.path.as_str();
+        let mut prefix = "";
+        let mut len = name_width;
+        if width::display_width(name) > name_width {
+            prefix = "...";
+            len = len.saturating_sub(3);
+            let mut name_len = width::display_width(name);
+            let mut chars = name.chars();
+            while name_len > len {
+                name_len -= chars.next().map_or(0, width::char_width);
+            }
+            name = chars.as_str();
+            if let Some(slash) = name.find('/') {
+                name = &name[slash..];
+            }
+        }
+        let name = format!("{prefix}{}", width::pad(name, len));
+        if stat.unmerged {
+            writeln!(stdout, " {name} | Unmerged")?;
+            continue;
+        }
+        if stat.binary {
+            write!(stdout, " {name} | {:>number_width$}", "Bin")?;
+            if stat.added > 0 || stat.deleted > 0 {
+                write!(
+                    stdout,
+                    " {} -> {} bytes",
+                    colored(|colors| &colors.old, stat.deleted.to_string()),
+                    colored(|colors| &colors.new, stat.added.to_string())
+                )?;
+            }
+            writeln!(stdout)?;
+            continue;
+        }
+
+        let total = stat.added + stat.deleted;
+        let (mut added, mut deleted) = (stat.added, stat.deleted);
+        if graph_width <= max_change {
+            let mut scaled = scale(total);
+            if scaled < 2 && added > 0 && deleted > 0 {
+                // Both need a column.
+                scaled = 2;
+            }
+            if added < deleted {
+                added = scale(added);
+                deleted = scaled - added;
+            } else {
+                deleted = scale(deleted);
+                added = scaled - deleted;
+            }
+        }
+        let separator = if total > 0 { " " } else { "" };
+        writeln!(
+            stdout,
+            " {name} | {total:>number_width$}{separator}{}{}",
+            colored(|colors| &colors.new, "+".repeat(added)),
+            colored(|colors| &colors.old, "-".repeat(deleted))
+        )?;
+    }
+    write_stat_totals(stats, stdout)
+}
+
+/// Prints the line summing up changes, like ` 2 files changed, 3
+/// insertions(+), 1 deletion(-)`. Unmerged paths aren't counted, and binary
+/// files have no lines.
+fn write_stat_totals(stats: &[FileStat], stdout: &mut dyn io::Write) -> Result<()> {
+    let stats: Vec<&FileStat> = stats.iter().filter(|stat| !stat.unmerged).collect();
+    let plural = |count: usize| if count == 1 { "" } else { "s" };
+    let files = stats.len();
+    let lines = || stats.iter().filter(|stat| !stat.binary);
+    let added: usize = lines().map(|stat| stat.added).sum();
+    let deleted: usize = lines().map(|stat| stat.deleted).sum();
+    if files == 0 {
+        return Ok(writeln!(stdout, " 0 files changed")?);
+    }
+    write!(stdout, " {files} file{} changed", plural(files))?;
+    // Like git, the insertions are shown when there are no changes at all,
+    // and so are the deletions.
+    if added > 0 || deleted == 0 {
+        write!(stdout, ", {added} insertion{}(+)", plural(added))?;
+    }
+    if deleted > 0 || added == 0 {
+        write!(stdout, ", {deleted} deletion{}(-)", plural(deleted))?;
+    }
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Prints the lines of `--summary` for the changes that create, delete or
+/// rename files or change their mode, like ` create mode 100644 path`.
+pub fn write_summary(changes: &[RawChange], stdout: &mut dyn io::Write) -> Result<()> {
+    for change in changes {
+        if let Some((_, score)) = &change.renamed_from {
+            let percent = score * 100 / rename::MAX_SCORE;
+            writeln!(stdout, " rename {} ({percent}%)", change.display_path())?;
+        }
+        match (&change.old, &change.new) {
+            (None, Some((mode, _))) => writeln!(stdout, " create mode {mode:06o} {}", change.path)?,
+            (Some((mode, _)), None) => writeln!(stdout, " delete mode {mode:06o} {}", change.path)?,
+            (Some((old_mode, _)), Some((new_mode, _))) if old_mode != new_mode => writeln!(
+                stdout,
+                " mode change {old_mode:06o} => {new_mode:06o} {}",
+                change.path
+            )?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// One side of a change shown as a patch.
+struct PatchSide {
+    mode: u32,
+    hash: String,
+    content: Vec<u8>,
+}
+
+/// Compares two trees, where `None` is the empty tree, like `git diff-tree`.
+///
+/// Without `recursive`, only the entries at the root are compared, a changed
+/// directory being a change of its tree. Subtrees with the same hash on both
+/// sides aren't read, since nothing changed in them.
+///
+/// Changes come in tree order, where directories sort as if their name
+/// ended with `/`, so a file replaced by a directory, or the other way
+/// around, is a deletion and an addition.
+pub fn diff_trees(
+    repo: &Repo,
+    old: Option<&str>,
+    new: Option<&str>,
+    recursive: bool,
+) -> Result<Vec<RawChange>> {
+    let mut changes = vec![];
+    walk_trees(repo, old, new, "", recursive, &mut changes)?;
+    Ok(changes)
+}
+
+/// Collects the changes between two trees whose paths start with `prefix`.
+fn walk_trees(
+    repo: &Repo,
+    old: Option<&str>,
+    new: Option<&str>,
+    prefix: &str,
+    recursive: bool,
+    changes: &mut Vec<RawChange>,
+) -> Result<()> {
+    type Side = Option<(u32, String)>;
+    let mut entries: BTreeMap<Vec<u8>, (String, Side, Side)> = BTreeMap::new();
+    for (tree, is_new) in [(old, false), (new, true)] {
+        let Some(tree) = tree else {
+            continue;
+        };
+        let Object::Tree(tree) = Object::from_hash(repo, tree)? else {
+            return Err(anyhow!("Not a tree: {tree}"));
+        };
+        for file in tree.files {
+            let mode = u32::from_str_radix(&file.mode, 8)?;
+            let mut key = file.name.clone().into_bytes();
+            if is_tree(mode) {
+                key.push(b'/');
+            }
+            let entry = entries.entry(key).or_insert((file.name, None, None));
+            match is_new {
+                false => entry.1 = Some((mode, file.hash)),
+                true => entry.2 = Some((mode, file.hash)),
+            }
+        }
+    }
+
+    for (name, old, new) in entries.into_values() {
+        if old == new {
+            continue;
+        }
+        let path = format!("{prefix}{name}");
+        let side = old.as_ref().or(new.as_ref());
+        if recursive && side.is_some_and(|(mode, _)| is_tree(*mode)) {
+            let hash = |side: &Side| side.as_ref().map(|(_, hash)| hash.clone());
+            let (old, new) = (hash(&old), hash(&new));
+            walk_trees(
+                repo,
+                old.as_deref(),
+                new.as_deref(),
+                &format!("{path}/"),
+                recursive,
+                changes,
+            )?;
+        } else {
+            changes.extend(RawChange::between(&path, old, new));
+        }
+    }
+    Ok(())
+}
+
+fn is_tree(mode: u32) -> bool {
+    mode == 0o040000
+}
+
+/// Compares a tree with the index, or with the working tree unless `cached`,
+/// like `git diff-index`.
+///
+/// Files in the working tree whose stat data doesn't match their index entry
+/// are changed, with an all zeros hash.
+pub fn diff_index(repo: &Repo, tree: &str, cached: bool) -> Result<Vec<RawChange>> {
+    let mut old = BTreeMap::new();
+    status::tree_files(repo, tree, "", &mut old)?;
+    let index = Index::read(repo)?;
+    let modes = FileModes::load(repo)?;
+    let filters = Filters::new(repo)?;
+    let mut new = BTreeMap::new();
+    let mut unmerged = vec![];
+    for entry in &index.entries {
+        if entry.stage != 0 {
+            unmerged.push(entry.path.clone());
+            continue;
+        }
+        let side = match cached {
+            true => Some((entry.mode, entry.hash.clone())),
+            false => worktree_side(repo, &filters, entry, modes)?
+                .unwrap_or(Some((entry.mode, entry.hash.clone()))),
+        };
+        if let Some(side) = side {
+            new.insert(entry.path.clone(), side);
+        }
+    }
+    let mut changes = compare(&old, &new);
+    add_unmerged(&mut changes, unmerged);
+    Ok(changes)
+}
+
+/// Compares the index with the working tree, like `git diff-files`.
+pub fn diff_files(repo: &Repo) -> Result<Vec<RawChange>> {
+    let index = Index::read(repo)?;
+    let modes = FileModes::load(repo)?;
+    let filters = Filters::new(repo)?;
+    let mut changes = vec![];
+    let mut unmerged = vec![];
+    for entry in &index.entries {
+        if entry.stage != 0 {
+            unmerged.push(entry.path.clone());
+            continue;
+        }
+        let Some(new) = worktree_side(repo, &filters, entry, modes)? else {
+            continue;
+        };
+        let old = (!entry.intent_to_add).then(|| (entry.mode, entry.hash.clone()));
+        changes.extend(RawChange::between(&entry.path, old, new));
+    }
+    add_unmerged(&mut changes, unmerged);
+    Ok(changes)
+}
+
+/// Returns `None` if the file of an index entry didn't change in the working
+/// tree, and otherwise its mode and hash there, `None` if it's missing.
+fn worktree_side(
+    repo: &Repo,
+    filters: &Filters,
+    entry: &crate::index::IndexEntry,
+    modes: FileModes,
+) -> Result<Option<Option<(u32, String)>>> {
+    Ok(
+        match status::worktree_change(repo, filters, entry, modes)? {
+            (None, _) => None,
+            (Some(Change::Deleted), _) => Some(None),
+            (Some(_), mode) => Some(Some((mode.unwrap_or(entry.mode), ZERO_HASH.to_string()))),
+        },
+    )
+}
+
+/// Compares two sets of files by path.
+fn compare(
+    old: &BTreeMap<String, (u32, String)>,
+    new: &BTreeMap<String, (u32, String)>,
+) -> Vec<RawChange> {
+    let mut paths: Vec<&String> = old.keys().chain(new.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    paths
+        .into_iter()
+        .filter_map(|path| RawChange::between(path, old.get(path).cloned(), new.get(path).cloned()))
+        .collect()
+}
+
+/// Replaces the changes of unmerged paths with a single `U` change each.
+fn add_unmerged(changes: &mut Vec<RawChange>, mut unmerged: Vec<String>) {
+    unmerged.dedup();
+    changes.retain(|change| unmerged.binary_search(&change.path).is_err());
+    changes.extend(unmerged.iter().map(|path| RawChange::unmerged(path)));
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let options = DiffPathOptions::default();
+        assert_eq!(
+            options.header(Some("a.txt"), Some("a.txt")),
+            "diff --git a/a.txt b/a.txt\n--- a/a.txt\n+++ b/a.txt\n"
+        );
+        assert_eq!(
+            options.header(None, Some("new.txt")),
+            "diff --git a/new.txt b/new.txt\n--- /dev/null\n+++ b/new.txt\n"
+        );
+
+        let options = DiffPathOptions {
+            src_prefix: "old/".to_string(),
+            dst_prefix: String::new(),
+            relative: None,
+        };
+        assert_eq!(
+            options.header(Some("gone.txt"), None),
+            "diff --git old/gone.txt gone.txt\n--- old/gone.txt\n+++ /dev/null\n"
+        );
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = Config::parse("[diff]\nnoprefix = true").unwrap();
+        let options = DiffPathOptions::from_config(&config).unwrap();
+        assert_eq!(
+            (options.src_prefix.as_str(), options.dst_prefix.as_str()),
+            ("", "")
+        );
+
+        let config = Config::parse("[diff]\nnoprefix\nsrcPrefix = x/").unwrap();
+        let options = DiffPathOptions::from_config(&config).unwrap();
+        assert_eq!(
+            (options.src_prefix.as_str(), options.dst_prefix.as_str()),
+            ("x/", "")
+        );
+    }
+
+    #[test]
+    fn test_raw_change() {
+        let blob = |hash: &str| Some((0o100644, hash.repeat(40)));
+        let change = RawChange::between("a.txt", blob("1"), blob("2")).unwrap();
+        assert_eq!(
+            change.to_string(),
+            format!(
+                ":100644 100644 {} {} M\ta.txt",
+                "1".repeat(40),
+                "2".repeat(40)
+            )
+        );
+        let change = RawChange::between("a.txt", None, blob("2")).unwrap();
+        assert_eq!(
+            change.to_string(),
+            format!(":000000 100644 {ZERO_HASH} {} A\ta.txt", "2".repeat(40))
+        );
+        assert_eq!(RawFormat::NameStatus.format(&change), "A\ta.txt");
+        let symlink = Some((0o120000, "2".repeat(40)));
+        let change = RawChange::between("a.txt", blob("1"), symlink).unwrap();
+        assert_eq!(change.status, 'T');
+        assert_eq!(RawChange::between("a.txt", blob("1"), blob("1")), None);
+        let change = RawChange {
+            status: 'R',
+            renamed_from: Some(("b.txt".to_string(), rename::MAX_SCORE * 2 / 3)),
+            ..RawChange::between("a.txt", blob("1"), blob("2")).unwrap()
+        };
+        assert!(change.to_string().ends_with(" R066\tb.txt\ta.txt"));
+        assert_eq!(RawFormat::NameOnly.format(&change), "a.txt");
+    }
+
+    #[test]
+    fn test_rename_display() {
+        assert_eq!(rename_display("f", "g"), "f => g");
+        assert_eq!(rename_display("a/b/c", "a/d/c"), "a/{b => d}/c");
+        assert_eq!(rename_display("a/b", "a/c/b"), "a/{ => c}/b");
+        assert_eq!(rename_display("a/b.txt", "c/b.txt"), "{a => c}/b.txt");
+        assert_eq!(rename_display("src/a.rs", "src/b.rs"), "src/{a.rs => b.rs}");
+    }
+
+    #[test]
+    fn test_write_stats() {
+        let stat = |path: &str, added, deleted, unmerged| FileStat {
+            path: path.to_string(),
+            added,
+            deleted,
+            unmerged,
+            binary: false,
+        };
+        let mut stats = vec![
+            stat("a.txt", 3, 1, false),
+            stat("dir/long-name.txt", 0, 0, false),
+        ];
+        let write = |stats: &[FileStat], format| {
+            let mut stdout = Vec::new();
+            write_stats(stats, &format, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        assert_eq!(
+            write(&stats, StatFormat::Numstat),
+            "3\t1\ta.txt\n0\t0\tdir/long-name.txt\n"
+        );
+        let totals = " 2 files changed, 3 insertions(+), 1 deletion(-)\n";
+        assert_eq!(write(&stats, StatFormat::Shortstat), totals);
+
+        // Long paths are shortened from their start when lines are too long.
+        let narrow = StatOptions {
+            width: 24,
+            ..Default::default()
+        };
+        assert_eq!(
+            write(&stats, StatFormat::Stat(narrow)),
+            format!(" a.txt       | 4 +++-\n ...name.txt | 0\n{totals}")
+        );
+
+        stats.push(stat("c", 0, 0, true));
+        assert_eq!(
+            write(&stats, StatFormat::Stat(Default::default())),
+            format!(
+                " a.txt             | 4 +++-\n \
+                 dir/long-name.txt | 0\n \
+                 c                 | Unmerged\n{totals}"
+            )
+        );
+        assert_eq!(write(&[], StatFormat::Shortstat), "");
+
+        // Binary files show their sizes in bytes, aligned with the counts.
+        let binary = |path: &str, added, deleted| FileStat {
+            binary: true,
+            ..stat(path, added, deleted, false)
+        };
+        let stats = vec![
+            stat("a.txt", 3, 1, false),
+            binary("b.png", 1024, 10),
+            binary("same.png", 0, 0),
+        ];
+        assert_eq!(
+            write(&stats, StatFormat::Stat(Default::default())),
+            " a.txt    |   4 +++-\n \
+             b.png    | Bin 10 -> 1024 bytes\n \
+             same.png | Bin\n \
+             3 files changed, 3 insertions(+), 1 deletion(-)\n"
+        );
+        assert_eq!(
+            write(&stats, StatFormat::Numstat),
+            "3\t1\ta.txt\n-\t-\tb.png\n-\t-\tsame.png\n"
+        );
+
+        let colored = StatOptions {
+            colors: Some(Box::default()),
+            ..Default::default()
+        };
+        assert_eq!(
+            write(&stats[..2], StatFormat::Stat(colored)),
+            " a.txt |   4 \x1b[32m+++\x1b[m\x1b[31m-\x1b[m\n \
+             b.png | Bin \x1b[31m10\x1b[m -> \x1b[32m1024\x1b[m bytes\n \
+             2 files changed, 3 insertions(+), 1 deletion(-)\n"
+        );
+    }
+
+    #[test]
+    fn test_colored_hunk() {
+        let colors = DiffColors::default();
+        let old = b"a\n\tb\nc";
+        let new = b"a\n \tb \nc\n\n";
+        let hunks = add_patch::hunks(old, new, 3);
+        let hunk = colored_hunk(&hunks[0], &colors, blank_lines_at_eof(old, new));
+        assert_eq!(
+            String::from_utf8(hunk).unwrap(),
+            "\x1b[36m@@ -1,3 +1,4 @@\x1b[m\n \
+             a\x1b[m\n\
+             \x1b[31m-\tb\x1b[m\n\
+             \x1b[31m-c\x1b[m\n\
+             \\ No newline at end of file\x1b[m\n\
+             \x1b[32m+\x1b[m\x1b[41m \x1b[m\t\x1b[32mb\x1b[m\x1b[41m \x1b[m\n\
+             \x1b[32m+\x1b[m\x1b[32mc\x1b[m\n\
+             \x1b[41m+\x1b[m\n"
+        );
+
+        assert_eq!(trailing_blank_lines(b"a\n \n\t\n"), 2);
+        assert_eq!(trailing_blank_lines(b"a\n\n"), 1);
+        // Like git, the first line isn't counted if it's empty.
+        assert_eq!(trailing_blank_lines(b"\n\n"), 0);
+        assert_eq!(blank_lines_at_eof(b"a\n", b"a\n\n\n"), Some((2, 2)));
+        assert_eq!(blank_lines_at_eof(b"a\n\n", b"a\n\n"), None);
+    }
+
+    #[test]
+    fn test_diff_trees() {
+        use crate::object::{self, File, ObjectType, Tree};
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let blob = |content: &str| {
+            let hash = object::write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            ("100644", hash)
+        };
+        let tree = |files: Vec<(&str, (&str, String))>| {
+            let files = files
+                .into_iter()
+                .map(|(name, (mode, hash))| File {
+                    mode: mode.to_string(),
+                    name: name.to_string(),
+                    hash,
+                })
+                .collect();
+            let hash = object::write_object(&repo, ObjectType::Tree, &Tree::new(files).to_bytes())
+                .unwrap();
+            ("40000", hash)
+        };
+        let same = tree(vec![("a", blob("same"))]);
+        let old = tree(vec![
+            ("same", same.clone()),
+            ("x", blob("x")),
+            ("x-y", blob("1")),
+            ("dir", tree(vec![("a", blob("a")), ("b", blob("b"))])),
+        ]);
+        let new = tree(vec![
+            ("same", same.clone()),
+            ("x", tree(vec![("a", blob("x"))])),
+            ("x-y", blob("2")),
+            ("dir", tree(vec![("a", blob("a2")), ("c", blob("c"))])),
+        ]);
+        // The identical subtree isn't read.
+        std::fs::remove_file(repo.git_dir().join(format!(
+            "objects/{}/{}",
+            &same.1[..2],
+            &same.1[2..]
+        )))
+        .unwrap();
+
+        let changes = |recursive| {
+            diff_trees(&repo, Some(&old.1), Some(&new.1), recursive)
+                .unwrap()
+                .into_iter()
+                .map(|change| format!("{} {}", change.status, change.path))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            changes(true),
+            ["M dir/a", "D dir/b", "A dir/c", "D x", "M x-y", "A x/a"]
+        );
+        assert_eq!(changes(false), ["M dir", "D x", "M x-y", "A x"]);
+        let added = diff_trees(&repo, None, Some(&new.1), false).unwrap();
+        assert_eq!(added.len(), 4);
+    }
+
+    #[test]
+    fn test_display_path() {
+        let options = DiffPathOptions {
+            relative: Some("src/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(options.display_path("src/main.rs"), Some("main.rs"));
+        assert_eq!(options.display_path("src/a/b.rs"), Some("a/b.rs"));
+        assert_eq!(options.display_path("srcs/main.rs"), None);
+        assert_eq!(options.display_path("README.md"), None);
+        assert_eq!(
+            DiffPathOptions::default().display_path("README.md"),
+            Some("README.md")
+        );
+    }
+}
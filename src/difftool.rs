@@ -0,0 +1,275 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    filter::Filters,
+    index::{self, Index},
+    object::{Blob, Object},
+    repo::Repo,
+    status,
+};
+
+/// A side of a diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Side {
+    /// The files of a tree.
+    Tree(String),
+    /// The files staged in the index.
+    Index,
+    /// The tracked files of the working tree.
+    Worktree,
+}
+
+/// The two sides of a diff written to temporary directories, for tools that
+/// compare directories.
+///
+/// Only the files that differ are written, so only their blobs are read.
+/// Edits made to the working tree side can be copied back with
+/// [`DirDiff::copy_back`]. The directories are removed when dropped.
+pub struct DirDiff {
+    root: PathBuf,
+    pub left: PathBuf,
+    pub right: PathBuf,
+    /// The paths that differ.
+    pub paths: Vec<String>,
+    /// (path, hash of the content) of the working tree files that were
+    /// written to the right side.
+    worktree_files: Vec<(String, String)>,
+    /// Whether to keep the directories when dropped.
+    keep: bool,
+}
+
+impl DirDiff {
+    pub fn create(repo: &Repo, left: &Side, right: &Side) -> Result<DirDiff> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let index = Index::read(repo)?;
+        let left_files = side_files(repo, left, &index)?;
+        let right_files = side_files(repo, right, &index)?;
+        let all_paths: BTreeSet<&String> = left_files.keys().chain(right_files.keys()).collect();
+        let paths = all_paths
+            .into_iter()
+            .filter(|path| left_files.get(*path) != right_files.get(*path))
+            .cloned()
+            .collect();
+
+        let root = std::env::temp_dir().join(format!(
+            "good_git-difftool.{}.{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir(&root)?;
+        let mut dir_diff = DirDiff {
+            left: root.join("left"),
+            right: root.join("right"),
+            root,
+            paths,
+            worktree_files: vec![],
+            keep: false,
+        };
+        fs::create_dir(&dir_diff.left)?;
+        fs::create_dir(&dir_diff.right)?;
+        for path in &dir_diff.paths {
+            if let Some(entry) = left_files.get(path) {
+                let content = side_content(repo, left, &index, path, entry)?;
+                write_file(&dir_diff.left.join(path), &content, entry.0)?;
+            }
+            if let Some(entry) = right_files.get(path) {
+                let content = side_content(repo, right, &index, path, entry)?;
+                write_file(&dir_diff.right.join(path), &content, entry.0)?;
+                // Only regular files can be edited.
+                if *right == Side::Worktree
+                    && entry.0 & 0o170000 == 0o100000
+                    && !ignores_worktree(&index, path)
+                {
+                    let hash = Blob::new(content).hash();
+                    dir_diff.worktree_files.push((path.clone(), hash));
+                }
+            }
+        }
+        Ok(dir_diff)
+    }
+
+    /// Copies the files edited in the right side back to the working tree,
+    /// unless they were also changed in the working tree in the meantime.
+    /// Returns the paths changed on both sides, which are left alone.
+    pub fn copy_back(&self, repo: &Repo) -> Result<Vec<String>> {
+        let mut both_changed = vec![];
+        for (path, hash) in &self.worktree_files {
+            let Ok(content) = fs::read(self.right.join(path)) else {
+                continue;
+            };
+            if Blob::new(content.clone()).hash() == *hash {
+                continue;
+            }
+            // The hash is of the content as it was in the working tree.
+            let full_path = repo.worktree_path(path)?;
+            let unchanged =
+                fs::read(&full_path).is_ok_and(|current| Blob::new(current).hash() == *hash);
+            if !unchanged {
+                both_changed.push(path.clone());
+                continue;
+            }
+            fs::write(&full_path, content)?;
+        }
+        Ok(both_changed)
+    }
+
+    /// Keeps the directories when dropped, e.g. to recover the edits that
+    /// couldn't be copied back, and returns their parent.
+    pub fn keep(&mut self) -> &Path {
+        self.keep = true;
+        &self.root
+    }
+}
+
+impl Drop for DirDiff {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+}
+
+/// Returns the mode and hash of the files of a side.
+fn side_files(repo: &Repo, side: &Side, index: &Index) -> Result<BTreeMap<String, (u32, String)>> {
+    let mut files = BTreeMap::new();
+    match side {
+        Side::Tree(hash) => status::tree_files(repo, hash, "", &mut files)?,
+        Side::Index => {
+            for entry in index.entries.iter().filter(|entry| entry.stage == 0) {
+                files.insert(entry.path.clone(), (entry.mode, entry.hash.clone()));
+            }
+        }
+        Side::Worktree => {
+            let modes = index::FileModes::load(repo)?;
+            let filters = Filters::new(repo)?;
+            for entry in &index.entries {
+                if entry.stage == 0 && entry.ignores_worktree() {
+                    files.insert(entry.path.clone(), (entry.mode, entry.hash.clone()));
+                    continue;
+                }
+                let full_path = repo.worktree_path(&entry.path)?;
+                let Ok(metadata) = fs::symlink_metadata(&full_path) else {
+                    continue;
+                };
+                if entry.stage == 0 && (entry.matches_stat(&metadata, modes) || metadata.is_dir()) {
+                    files.insert(entry.path.clone(), (entry.mode, entry.hash.clone()));
+                } else if !metadata.is_dir() {
+                    let hash =
+                        index::worktree_blob_hash(&filters, &entry.path, &full_path, &metadata)?;
+                    let mode = modes.mode(&metadata, Some(entry.mode));
+                    files.insert(entry.path.clone(), (mode, hash));
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Returns the content of a file of a side, as git shows it in directory
+/// diffs: symlinks are files holding their target, and submodules files
+/// holding their commit.
+fn side_content(
+    repo: &Repo,
+    side: &Side,
+    index: &Index,
+    path: &str,
+    entry: &(u32, String),
+) -> Result<Vec<u8>> {
+    let (mode, hash) = entry;
+    if *mode == 0o160000 {
+        return Ok(format!("Subproject commit {hash}\n").into_bytes());
+    }
+    // The staged content of ignored files stands for their working tree
+    // content.
+    if *side == Side::Worktree && !ignores_worktree(index, path) {
+        let full_path = repo.worktree_path(path)?;
+        return Ok(if *mode == 0o120000 {
+            fs::read_link(&full_path)?
+                .to_string_lossy()
+                .into_owned()
+                .into_bytes()
+        } else {
+            fs::read(&full_path)?
+        });
+    }
+    match Object::from_hash(repo, hash)? {
+        Object::Blob(blob) => Ok(blob.content),
+        _ => Err(anyhow!("Not a blob: {hash}")),
+    }
+}
+
+/// Returns whether the working tree file of a path is ignored, see
+/// [`index::IndexEntry::ignores_worktree`].
+fn ignores_worktree(index: &Index, path: &str) -> bool {
+    index
+        .entries
+        .iter()
+        .any(|entry| entry.path == path && entry.stage == 0 && entry.ignores_worktree())
+}
+
+fn write_file(path: &Path, content: &[u8], mode: u32) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    #[cfg(unix)]
+    if mode == 0o100755 {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{self, ObjectType};
+
+    #[test]
+    fn test_dir_diff() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let mut index = Index::default();
+        for (path, content) in [("same.txt", "same\n"), ("changed.txt", "old\n")] {
+            let hash = object::write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            fs::write(tmpdir.path().join(path), content).unwrap();
+            let metadata = fs::symlink_metadata(tmpdir.path().join(path)).unwrap();
+            index.add(index::IndexEntry::from_metadata(path, &hash, &metadata));
+        }
+        index.write(&repo).unwrap();
+        fs::write(tmpdir.path().join("changed.txt"), "new\n").unwrap();
+
+        let dir_diff = DirDiff::create(&repo, &Side::Index, &Side::Worktree).unwrap();
+        assert_eq!(dir_diff.paths, vec!["changed.txt"]);
+        assert_eq!(
+            fs::read_to_string(dir_diff.left.join("changed.txt")).unwrap(),
+            "old\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir_diff.right.join("changed.txt")).unwrap(),
+            "new\n"
+        );
+        assert!(!dir_diff.left.join("same.txt").exists());
+
+        fs::write(dir_diff.right.join("changed.txt"), "edited\n").unwrap();
+        assert!(dir_diff.copy_back(&repo).unwrap().is_empty());
+        assert_eq!(
+            fs::read_to_string(tmpdir.path().join("changed.txt")).unwrap(),
+            "edited\n"
+        );
+
+        let root = dir_diff.root.clone();
+        drop(dir_diff);
+        assert!(!root.exists());
+    }
+}
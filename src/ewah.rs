@@ -0,0 +1,116 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::prelude::*;
+
+/// Reads an EWAH compressed bitmap, as used by the split index, and returns
+/// the positions of the set bits in increasing order.
+///
+/// The format is the number of bits, the number of 64-bit words, the words
+/// and the position of the last run length word, all big endian. The words
+/// are a sequence of run length words, each followed by literal words. A run
+/// length word has the running bit in bit 0, the number of words filled with
+/// the running bit in bits 1-32 and the number of literal words that follow
+/// in bits 33-63.
+///
+/// Bitmaps of more than `max_bits` bits are rejected, so that a corrupt size
+/// can't make the positions take arbitrary amounts of memory.
+pub fn read(reader: &mut &[u8], max_bits: usize) -> Result<Vec<usize>> {
+    let bit_size = read_u32(reader)? as usize;
+    if bit_size > max_bits {
+        return Err(anyhow!("EWAH bitmap has too many bits: {bit_size}"));
+    }
+    let word_count = read_u32(reader)? as usize;
+    let mut words = Vec::with_capacity(word_count.min(reader.len() / 8));
+    for _ in 0..word_count {
+        let mut word = [0_u8; 8];
+        reader
+            .read_exact(&mut word)
+            .context("EWAH bitmap is truncated")?;
+        words.push(u64::from_be_bytes(word));
+    }
+    // The position of the last run length word is only needed to append bits.
+    read_u32(reader)?;
+
+    let mut bits = vec![];
+    let mut position: usize = 0;
+    let mut words = words.into_iter();
+    while let Some(rlw) = words.next() {
+        let running_bit = rlw & 1 == 1;
+        let running_len = ((rlw >> 1) & 0xffff_ffff) as usize;
+        let literal_count = (rlw >> 33) as usize;
+        if running_bit {
+            bits.extend(position..position.saturating_add(running_len * 64).min(bit_size));
+        }
+        position = position.saturating_add(running_len * 64);
+        for _ in 0..literal_count {
+            let word = words.next().ok_or(anyhow!("EWAH bitmap is truncated"))?;
+            bits.extend(
+                (0..64)
+                    .filter(|bit| word & (1 << bit) != 0)
+                    .map(|bit| position.saturating_add(bit))
+                    .filter(|&bit| bit < bit_size),
+            );
+            position = position.saturating_add(64);
+        }
+    }
+    Ok(bits)
+}
+
+/// Writes a bitmap with the given bits set, which must be in increasing order.
+///
+/// All words are written as literal words, which git reads fine.
+pub fn write(bits: &[usize]) -> Vec<u8> {
+    let bit_size = bits.last().map_or(0, |bit| bit + 1);
+    let mut literals = vec![0_u64; bit_size.div_ceil(64)];
+    for bit in bits {
+        literals[bit / 64] |= 1 << (bit % 64);
+    }
+    let rlw = (literals.len() as u64) << 33;
+
+    let mut s = vec![];
+    s.extend((bit_size as u32).to_be_bytes());
+    s.extend((literals.len() as u32 + 1).to_be_bytes());
+    s.extend(rlw.to_be_bytes());
+    for word in literals {
+        s.extend(word.to_be_bytes());
+    }
+    s.extend(0_u32.to_be_bytes());
+    s
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<u32> {
+    let mut buf = [0_u8; 4];
+    reader
+        .read_exact(&mut buf)
+        .context("EWAH bitmap is truncated")?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for bits in [vec![], vec![0], vec![1, 5, 63, 64, 200]] {
+            let s = write(&bits);
+            let mut reader = s.as_slice();
+            assert_eq!(read(&mut reader, 201).unwrap(), bits);
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_read_runs() {
+        // 130 bits: a run of 2 words of ones, then a literal word with bit 1 set.
+        let mut s = vec![];
+        s.extend(130_u32.to_be_bytes());
+        s.extend(2_u32.to_be_bytes());
+        s.extend(((1_u64 << 33) | (2 << 1) | 1).to_be_bytes());
+        s.extend(0b10_u64.to_be_bytes());
+        s.extend(0_u32.to_be_bytes());
+
+        let bits = read(&mut s.as_slice(), 130).unwrap();
+        assert_eq!(bits, (0..128).chain([129]).collect::<Vec<_>>());
+        assert!(read(&mut s.as_slice(), 129).is_err());
+    }
+}
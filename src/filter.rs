@@ -0,0 +1,516 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::Mutex,
+};
+
+use crate::{
+    attributes::{AttributeValue, Attributes},
+    config::Config,
+    lfs,
+    repo::Repo,
+};
+
+/// Which way content goes through a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the working tree to the repository, when adding files.
+    Clean,
+    /// From the repository to the working tree, when checking files out.
+    Smudge,
+}
+
+impl Direction {
+    fn name(self) -> &'static str {
+        match self {
+            Direction::Clean => "clean",
+            Direction::Smudge => "smudge",
+        }
+    }
+}
+
+/// Converts content between the repository and the working tree with the
+/// filter driver of the `filter=<driver>` attribute of its path, like git.
+///
+/// A driver has a `filter.<driver>.clean` and a `filter.<driver>.smudge`
+/// command, reading the content on stdin and writing the converted content
+/// on stdout, where `%f` is replaced by the path. Or it has a
+/// `filter.<driver>.process` command, started once and kept running to
+/// convert all the files with git's long-running process protocol.
+///
+/// Content without a command is left as is, as is content whose command
+/// fails, unless `filter.<driver>.required` is set, which makes it an error.
+///
+/// This is how a Git LFS client is hooked in: `filter=lfs` paths with a
+/// `filter.lfs.process` command. Smudging a [`lfs::Pointer`] without a
+/// command warns that the pointer is checked out instead of the content.
+/// Warnings and the errors of commands that may fail are collected, see
+/// [`Filters::take_warnings`].
+pub struct Filters<'a> {
+    repo: &'a Repo,
+    config: Config,
+    attributes: Attributes,
+    /// The long-running processes started so far, by driver, or `None` if
+    /// the process couldn't be started or aborted.
+    processes: Mutex<HashMap<String, Option<Process>>>,
+    warnings: Mutex<Vec<String>>,
+}
+
+impl<'a> Filters<'a> {
+    pub fn new(repo: &'a Repo) -> Result<Filters<'a>> {
+        Ok(Filters {
+            repo,
+            config: Config::load(repo)?,
+            attributes: Attributes::load(repo)?,
+            processes: Mutex::new(HashMap::new()),
+            warnings: Mutex::new(vec![]),
+        })
+    }
+
+    /// Returns the warnings and errors of the conversions since the last
+    /// call, which git prints as it goes.
+    pub fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut self.warnings.lock().unwrap())
+    }
+
+    fn warn(&self, warning: String) {
+        self.warnings.lock().unwrap().push(warning);
+    }
+
+    /// Returns the filter driver of a path, if any.
+    pub fn driver(&self, path: &str) -> Result<Option<String>> {
+        match self.attributes.get(path, "filter")? {
+            Some(AttributeValue::Value(driver)) => Ok(Some(driver)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Converts the content of a file of the working tree to store it.
+    pub fn clean(&self, path: &str, content: Vec<u8>) -> Result<Vec<u8>> {
+        self.apply(Direction::Clean, path, content)
+    }
+
+    /// Converts the content of a blob to write it to the working tree.
+    pub fn smudge(&self, path: &str, content: Vec<u8>) -> Result<Vec<u8>> {
+        self.apply(Direction::Smudge, path, content)
+    }
+
+    fn apply(&self, direction: Direction, path: &str, content: Vec<u8>) -> Result<Vec<u8>> {
+        let Some(driver) = self.driver(path)? else {
+            return Ok(content);
+        };
+        let key = |name: &str| format!("filter.{driver}.{name}");
+        let required = self.config.get_bool(&key("required"))? == Some(true);
+        let output = if let Some(command) = self.config.get(&key("process")) {
+            self.run_process(&driver, command, direction, path, &content)
+        } else if let Some(command) = self.config.get(&key(direction.name())) {
+            let output = run(self.repo, command, path, &content);
+            if let Err(e) = &output {
+                // Like git, the failure is reported even if the filter isn't
+                // required.
+                self.warn(format!("error: {e}"));
+            }
+            output.map(Some)
+        } else {
+            Ok(None)
+        };
+        match output {
+            Ok(Some(output)) => Ok(output),
+            Ok(None) if !required => {
+                // Checking out a pointer instead of its content is likely a
+                // missing Git LFS client, which shouldn't go unnoticed.
+                if direction == Direction::Smudge && lfs::Pointer::parse(&content).is_some() {
+                    self.warn(format!(
+                        "warning: {path}: checked out as a Git LFS pointer, \
+                         filter '{driver}' has no smudge command"
+                    ));
+                }
+                Ok(content)
+            }
+            Ok(None) | Err(_) if !required => Ok(content),
+            _ => Err(anyhow!(
+                "{path}: {} filter '{driver}' failed",
+                direction.name()
+            )),
+        }
+    }
+
+    /// Converts content with the long-running process of a driver, starting
+    /// it if needed. Returns `None` if the process can't convert this way.
+    fn run_process(
+        &self,
+        driver: &str,
+        command: &str,
+        direction: Direction,
+        path: &str,
+        content: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let mut processes = self.processes.lock().unwrap();
+        if !processes.contains_key(driver) {
+            let process = Process::start(self.repo, command);
+            if let Err(e) = &process {
+                self.warn(format!(
+                    "error: initialization for external filter '{command}' failed: {e}"
+                ));
+            }
+            processes.insert(driver.to_string(), process.ok());
+        }
+        let Some(process) = processes.get_mut(driver).expect("inserted above") else {
+            return Err(anyhow!("external filter '{command}' is not available"));
+        };
+        if !process.capabilities.contains(direction.name()) {
+            return Ok(None);
+        }
+        match process.convert(direction, path, content) {
+            Ok(Outcome::Success(output)) => Ok(Some(output)),
+            Ok(Outcome::Error) => Err(anyhow!("external filter '{command}' failed")),
+            Ok(Outcome::Abort) | Err(_) => {
+                // The process can't be used anymore.
+                processes.insert(driver.to_string(), None);
+                Err(anyhow!("external filter '{command}' failed"))
+            }
+        }
+    }
+}
+
+/// Runs a filter command like git does, through the shell in the root of
+/// the working tree with the content on stdin.
+fn run(repo: &Repo, command: &str, path: &str, content: &[u8]) -> Result<Vec<u8>> {
+    // Like git, `%f` is the path, quoted for the shell.
+    let quoted_path = format!("'{}'", path.replace('\'', "'\\''"));
+    let command = command.replace("%f", &quoted_path);
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .current_dir(&repo.root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| anyhow!("cannot fork to run external filter '{command}': {e}"))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // The content is written while the output is read, as the command may
+    // not read all its input before writing.
+    let output = std::thread::scope(|scope| {
+        let writer = scope.spawn(move || stdin.write_all(content));
+        let output = child.wait_with_output();
+        // A command may exit without reading everything, which is fine.
+        let _ = writer.join();
+        output
+    })?;
+    if !output.status.success() {
+        return Err(anyhow!("external filter '{command}' failed"));
+    }
+    Ok(output.stdout)
+}
+
+/// The biggest content of a pkt-line.
+const MAX_PACKET_DATA: usize = 65516;
+
+/// A long-running filter process, speaking version 2 of git's filter
+/// protocol: pkt-lines, where a `0000` flush packet ends each list.
+struct Process {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    /// The commands it supports: `clean` and `smudge`.
+    capabilities: HashSet<String>,
+}
+
+/// The result of a conversion by a [`Process`].
+enum Outcome {
+    Success(Vec<u8>),
+    /// The content couldn't be converted.
+    Error,
+    /// No more content can be converted.
+    Abort,
+}
+
+impl Process {
+    /// Starts a process and negotiates the version and the capabilities.
+    fn start(repo: &Repo, command: &str) -> Result<Process> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(&repo.root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let mut process = Process {
+            child,
+            stdin: Some(stdin),
+            stdout,
+            capabilities: HashSet::new(),
+        };
+
+        process.write_list(&["git-filter-client", "version=2"])?;
+        let welcome = process.read_list()?;
+        if welcome.first().map(String::as_str) != Some("git-filter-server")
+            || !welcome.iter().any(|line| line == "version=2")
+        {
+            return Err(anyhow!("unexpected filter process handshake: {welcome:?}"));
+        }
+        process.write_list(&["capability=clean", "capability=smudge"])?;
+        for line in process.read_list()? {
+            if let Some(capability) = line.strip_prefix("capability=") {
+                process.capabilities.insert(capability.to_string());
+            }
+        }
+        Ok(process)
+    }
+
+    fn convert(&mut self, direction: Direction, path: &str, content: &[u8]) -> Result<Outcome> {
+        let command = format!("command={}", direction.name());
+        let pathname = format!("pathname={path}");
+        self.write_list(&[&command, &pathname])?;
+        let stdin = self.stdin.as_mut().expect("stdin is open until dropped");
+        for chunk in content.chunks(MAX_PACKET_DATA) {
+            write_packet(stdin, Some(chunk))?;
+        }
+        write_packet(stdin, None)?;
+        stdin.flush()?;
+
+        // A status, then the content and a status that may change it, or
+        // keep it if empty.
+        let mut status = last_status(&self.read_list()?);
+        let mut output = vec![];
+        if status.as_deref() == Some("success") {
+            while let Some(packet) = read_packet(&mut self.stdout)? {
+                output.extend(packet);
+            }
+            status = last_status(&self.read_list()?).or(status);
+        }
+        Ok(match status.as_deref() {
+            Some("success") => Outcome::Success(output),
+            Some("abort") => Outcome::Abort,
+            _ => Outcome::Error,
+        })
+    }
+
+    /// Writes text packets, then a flush packet.
+    fn write_list(&mut self, lines: &[&str]) -> Result<()> {
+        let stdin = self.stdin.as_mut().expect("stdin is open until dropped");
+        for line in lines {
+            write_packet(stdin, Some(format!("{line}\n").as_bytes()))?;
+        }
+        write_packet(stdin, None)?;
+        stdin.flush()?;
+        Ok(())
+    }
+
+    /// Reads text packets up to a flush packet.
+    fn read_list(&mut self) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        while let Some(packet) = read_packet(&mut self.stdout)? {
+            let line = String::from_utf8(packet)?;
+            lines.push(line.strip_suffix('\n').unwrap_or(&line).to_string());
+        }
+        Ok(lines)
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // Closing stdin tells the process to exit.
+        drop(self.stdin.take());
+        let _ = self.child.wait();
+    }
+}
+
+/// Returns the value of the last `status=` line of a list.
+fn last_status(lines: &[String]) -> Option<String> {
+    lines
+        .iter()
+        .rev()
+        .find_map(|line| line.strip_prefix("status="))
+        .map(str::to_string)
+}
+
+/// Writes a pkt-line: the length of the packet, including the 4 bytes of the
+/// length, in hex, then the data. `None` writes a flush packet, `0000`.
+fn write_packet(writer: &mut dyn Write, data: Option<&[u8]>) -> Result<()> {
+    match data {
+        Some(data) => {
+            write!(writer, "{:04x}", data.len() + 4)?;
+            writer.write_all(data)?;
+        }
+        None => writer.write_all(b"0000")?,
+    }
+    Ok(())
+}
+
+/// Reads a pkt-line, `None` for a flush packet.
+fn read_packet(reader: &mut dyn BufRead) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+    let length = std::str::from_utf8(&length)
+        .ok()
+        .and_then(|length| usize::from_str_radix(length, 16).ok())
+        .ok_or_else(|| anyhow!("Invalid packet length: {length:?}"))?;
+    if length == 0 {
+        return Ok(None);
+    }
+    let mut data = vec![
+        0;
+        length
+            .checked_sub(4)
+            .ok_or_else(|| anyhow!("Invalid packet length: {length}"))?
+    ];
+    reader.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_filters<'a>(repo: &'a Repo, config: &str) -> Filters<'a> {
+        std::fs::create_dir_all(repo.git_dir()).unwrap();
+        std::fs::write(repo.git_dir().join("config"), config).unwrap();
+        std::fs::write(repo.root.join(".gitattributes"), "*.txt filter=upper\n").unwrap();
+        Filters::new(repo).unwrap()
+    }
+
+    #[test]
+    fn test_clean_and_smudge() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        let filters = load_filters(
+            &repo,
+            "[filter \"upper\"]\n\tclean = tr a-z A-Z\n\tsmudge = echo %f && cat\n",
+        );
+        assert_eq!(filters.clean("a.txt", b"abc\n".to_vec()).unwrap(), b"ABC\n");
+        assert_eq!(
+            filters.smudge("it's.txt", b"ABC\n".to_vec()).unwrap(),
+            b"it's.txt\nABC\n"
+        );
+        assert_eq!(filters.clean("a.rs", b"abc\n".to_vec()).unwrap(), b"abc\n");
+    }
+
+    #[test]
+    fn test_failing_filter() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        let filters = load_filters(&repo, "[filter \"upper\"]\n\tclean = false\n");
+        // Failures and missing commands leave the content as is.
+        assert_eq!(filters.clean("a.txt", b"abc\n".to_vec()).unwrap(), b"abc\n");
+        assert_eq!(
+            filters.smudge("a.txt", b"abc\n".to_vec()).unwrap(),
+            b"abc\n"
+        );
+        // The failures are still reported.
+        assert_eq!(
+            filters.take_warnings(),
+            ["error: external filter 'false' failed"]
+        );
+        assert!(filters.take_warnings().is_empty());
+
+        let filters = load_filters(
+            &repo,
+            "[filter \"upper\"]\n\tclean = false\n\trequired = true\n",
+        );
+        assert_eq!(
+            filters
+                .clean("a.txt", b"abc\n".to_vec())
+                .unwrap_err()
+                .to_string(),
+            "a.txt: clean filter 'upper' failed"
+        );
+        assert_eq!(
+            filters
+                .smudge("a.txt", b"abc\n".to_vec())
+                .unwrap_err()
+                .to_string(),
+            "a.txt: smudge filter 'upper' failed"
+        );
+    }
+
+    #[test]
+    fn test_packets() {
+        let mut output = vec![];
+        write_packet(&mut output, Some(b"version=2\n")).unwrap();
+        write_packet(&mut output, None).unwrap();
+        assert_eq!(output, b"000eversion=2\n0000");
+
+        let mut reader = &output[..];
+        assert_eq!(
+            read_packet(&mut reader).unwrap().as_deref(),
+            Some(&b"version=2\n"[..])
+        );
+        assert_eq!(read_packet(&mut reader).unwrap(), None);
+        assert!(read_packet(&mut &b"0002"[..]).is_err());
+    }
+
+    /// A long-running filter process that upper-cases on clean and
+    /// lower-cases on smudge, logging the commands it gets.
+    const PROCESS: &str = r#"
+import sys
+
+def read():
+    length = sys.stdin.buffer.read(4)
+    if not length:
+        sys.exit()
+    length = int(length, 16)
+    return sys.stdin.buffer.read(length - 4) if length else None
+
+def read_list():
+    packets = []
+    while (packet := read()) is not None:
+        packets.append(packet)
+    return packets
+
+def write(*packets):
+    for packet in packets:
+        sys.stdout.buffer.write(b"%04x" % (len(packet) + 4) + packet)
+    sys.stdout.buffer.write(b"0000")
+    sys.stdout.buffer.flush()
+
+read_list()
+write(b"git-filter-server\n", b"version=2\n")
+read_list()
+write(b"capability=clean\n", b"capability=smudge\n")
+while (request := read_list()):
+    content = b"".join(read_list())
+    with open("log", "a") as log:
+        log.write(request[0].decode())
+    write(b"status=success\n")
+    if request[0] == b"command=clean\n":
+        content = content.upper()
+    else:
+        content = content.lower()
+    write(*[content[i:i + 65516] for i in range(0, len(content), 65516)])
+    write()
+"#;
+
+    #[test]
+    fn test_process() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        std::fs::write(repo.root.join("filter.py"), PROCESS).unwrap();
+        let filters = load_filters(
+            &repo,
+            "[filter \"upper\"]\n\tprocess = python3 filter.py\n\tclean = false\n",
+        );
+        assert_eq!(filters.clean("a.txt", b"abc\n".to_vec()).unwrap(), b"ABC\n");
+        assert_eq!(
+            filters.smudge("a.txt", b"ABC\n".to_vec()).unwrap(),
+            b"abc\n"
+        );
+        let big = vec![b'a'; MAX_PACKET_DATA * 2 + 1];
+        assert_eq!(
+            filters.clean("b.txt", big.clone()).unwrap(),
+            big.to_ascii_uppercase()
+        );
+        drop(filters);
+        // The process is started once, and preferred to the commands.
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("log")).unwrap(),
+            "command=clean\ncommand=smudge\ncommand=clean\n"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Result};
+
+/// Expands a format string as used by `--format` options, like
+/// `%(objectname)%x09%(path)`.
+///
+/// `%(name)` is replaced by what `field` returns for `name`, `%%` by a
+/// literal `%` and `%xx` by the byte with the hex value `xx`. Fields are only
+/// looked up when they're used, so expensive ones cost nothing otherwise.
+/// `field` returns `None` for unknown names, which is an error.
+pub fn expand(format: &str, field: impl Fn(&str) -> Result<Option<String>>) -> Result<Vec<u8>> {
+    let mut output = vec![];
+    let mut rest = format;
+    while let Some(index) = rest.find('%') {
+        output.extend(rest[..index].as_bytes());
+        rest = &rest[index + 1..];
+        if let Some(after) = rest.strip_prefix('%') {
+            output.push(b'%');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('(') {
+            let (name, after) = after
+                .split_once(')')
+                .ok_or(anyhow!("Unterminated format placeholder: %({after}"))?;
+            let value = field(name)?.ok_or(anyhow!("Unknown format placeholder: %({name})"))?;
+            output.extend(value.as_bytes());
+            rest = after;
+        } else if let Some(byte) = rest
+            .strip_prefix('x')
+            .and_then(|hex| hex.get(..2))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            output.push(byte);
+            rest = &rest[3..];
+        } else {
+            // Like git, a lone % is kept as is.
+            output.push(b'%');
+        }
+    }
+    output.extend(rest.as_bytes());
+    Ok(output)
+}
+
+/// Quotes a path like git when it has control characters, `"`, `\\` or non
+/// ASCII characters: in double quotes, with C-style escapes and octal bytes.
+pub fn quote_path(path: &str) -> String {
+    let needs_quotes = path
+        .bytes()
+        .any(|b| b < 0x20 || b == b'"' || b == b'\\' || b >= 0x7f);
+    if !needs_quotes {
+        return path.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for b in path.bytes() {
+        match b {
+            b'\x07' => quoted.push_str("\\a"),
+            b'\x08' => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\x0b' => quoted.push_str("\\v"),
+            b'\x0c' => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b if !(0x20..0x7f).contains(&b) => quoted.push_str(&format!("\\{b:03o}")),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Reverses [`quote_path`] for a string starting with `"`. Returns the
+/// unquoted string and what follows the closing quote, or `None` if the
+/// string isn't properly quoted.
+pub fn unquote_path(quoted: &str) -> Option<(String, &str)> {
+    let mut bytes = vec![];
+    let mut rest = quoted.strip_prefix('"')?.as_bytes();
+    loop {
+        match rest {
+            [] => return None,
+            [b'"', after @ ..] => {
+                let unquoted = String::from_utf8(bytes).ok()?;
+                return Some((unquoted, &quoted[quoted.len() - after.len()..]));
+            }
+            [b'\\', a @ b'0'..=b'3', b @ b'0'..=b'7', c @ b'0'..=b'7', after @ ..] => {
+                bytes.push((a - b'0') << 6 | (b - b'0') << 3 | (c - b'0'));
+                rest = after;
+            }
+            [b'\\', escaped, after @ ..] => {
+                bytes.push(match escaped {
+                    b'a' => b'\x07',
+                    b'b' => b'\x08',
+                    b't' => b'\t',
+                    b'n' => b'\n',
+                    b'v' => b'\x0b',
+                    b'f' => b'\x0c',
+                    b'r' => b'\r',
+                    b'"' | b'\\' => *escaped,
+                    _ => return None,
+                });
+                rest = after;
+            }
+            [b, after @ ..] => {
+                bytes.push(*b);
+                rest = after;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str) -> Result<Option<String>> {
+        Ok(match name {
+            "a" => Some("apple".to_string()),
+            "b" => Some("banana".to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(
+            expand("%(a) and %(b)%x09100%% %x0%", field).unwrap(),
+            b"apple and banana\t100% %x0%"
+        );
+        assert_eq!(expand("%x00", field).unwrap(), b"\0");
+        assert_eq!(expand("", field).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_expand_fails_on_bad_placeholders() {
+        assert_eq!(
+            expand("%(c)", field).unwrap_err().to_string(),
+            "Unknown format placeholder: %(c)"
+        );
+        assert_eq!(
+            expand("%(a", field).unwrap_err().to_string(),
+            "Unterminated format placeholder: %(a"
+        );
+    }
+
+    #[test]
+    fn test_quote_path() {
+        assert_eq!(quote_path("a b/c.txt"), "a b/c.txt");
+        assert_eq!(quote_path("tab\there"), "\"tab\\there\"");
+        assert_eq!(quote_path("quote\"back\\"), "\"quote\\\"back\\\\\"");
+        assert_eq!(quote_path("caf\u{e9}"), "\"caf\\303\\251\"");
+    }
+
+    #[test]
+    fn test_unquote_path() {
+        for path in ["tab\there", "quote\"back\\", "caf\u{e9}"] {
+            let quoted = format!("{} rest", quote_path(path));
+            assert_eq!(unquote_path(&quoted), Some((path.to_string(), " rest")));
+        }
+        assert_eq!(unquote_path("\"unclosed"), None);
+        assert_eq!(unquote_path("unquoted"), None);
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::{anyhow, Result};
+use std::io;
+
+use crate::{
+    diff::{self, ChangeReader, PatchOptions, RawChange, StatFormat, StatOptions},
+    ident::{DateFormat, Ident},
+    object::{Commit, Object},
+    pretty,
+    repo::Repo,
+};
+
+/// The width git folds the headers of mails at.
+const MAX_HEADER_WIDTH: usize = 78;
+
+/// The width git folds RFC 2047 encoded words at.
+const MAX_WORD_LINE: usize = 76;
+
+/// The longest name of a patch file, with its `.patch` extension.
+const MAX_FILE_NAME: usize = 64;
+
+/// The `date` git puts in the `From` line starting each mail, so that tools
+/// can recognize patches.
+const MAGIC_DATE: &str = "Mon Sep 17 00:00:00 2001";
+
+/// Returns the changes of a commit from its first parent, or from the empty
+/// tree for a root commit.
+pub fn commit_changes(repo: &Repo, commit: &Commit) -> Result<Vec<RawChange>> {
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => match Object::from_hash(repo, parent)? {
+            Object::Commit(parent) => Some(parent.tree),
+            _ => return Err(anyhow!("Not a commit: {parent}")),
+        },
+        None => None,
+    };
+    diff::diff_trees(repo, parent_tree.as_deref(), Some(&commit.tree), true)
+}
+
+/// Writes a commit as a mail, like `git format-patch`: a `From <hash>` line
+/// for mbox files, the author, the date and the subject prefixed with
+/// `[<prefix>]`, the rest of the message, then a `---` line followed by the
+/// stat, the summary and the patches of `changes`, and the signature if any.
+///
+/// Like git, headers are folded at 78 columns, and those with non-ASCII
+/// characters are encoded as RFC 2047 words, folded at 76 columns. MIME
+/// headers tell that the body is UTF-8 if the message isn't ASCII.
+pub fn write_mail(
+    repo: &Repo,
+    hash: &str,
+    commit: &Commit,
+    changes: &[RawChange],
+    prefix: &str,
+    signature: Option<&str>,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let author = Ident::parse(&commit.author)
+        .ok_or_else(|| anyhow!("Invalid author in {hash}: {}", commit.author))?;
+    writeln!(stdout, "From {hash} {MAGIC_DATE}")?;
+    writeln!(stdout, "{}", from_header(&author))?;
+    writeln!(stdout, "Date: {}", author.date(DateFormat::Rfc2822))?;
+    let subject = pretty::subject(&commit.message);
+    let name = match prefix.is_empty() {
+        true => "Subject: ".to_string(),
+        false => format!("Subject: [{prefix}] "),
+    };
+    writeln!(stdout, "{}", header(&name, &subject, false))?;
+    if !commit.message.is_ascii() {
+        writeln!(
+            stdout,
+            "MIME-Version: 1.0\n\
+             Content-Type: text/plain; charset=UTF-8\n\
+             Content-Transfer-Encoding: 8bit"
+        )?;
+    }
+    writeln!(stdout)?;
+    let body = pretty::body(&commit.message);
+    write!(stdout, "{body}")?;
+    if !body.is_empty() && !body.ends_with('\n') {
+        writeln!(stdout)?;
+    }
+
+    writeln!(stdout, "---")?;
+    let reader = ChangeReader::new(repo)?;
+    let stats = changes
+        .iter()
+        .map(|change| diff::FileStat::compute(&reader, change))
+        .collect::<Result<Vec<_>>>()?;
+    // Like git, the stat fits in the width of mails.
+    let stat = StatFormat::Stat(StatOptions {
+        width: 72,
+        ..Default::default()
+    });
+    diff::write_stats(&stats, &stat, stdout)?;
+    diff::write_summary(changes, stdout)?;
+    writeln!(stdout)?;
+    let options = PatchOptions {
+        binary: true,
+        ..Default::default()
+    };
+    for change in changes {
+        diff::write_patch(&reader, change, &options, stdout)?;
+    }
+    if let Some(signature) = signature {
+        writeln!(stdout, "-- \n{signature}\n")?;
+    }
+    Ok(())
+}
+
+/// Returns the name of the file of the `number`th patch, like
+/// `0001-Fix-the-thing.patch`: the subject with runs of characters other
+/// than ASCII letters, digits, `.` and `_` replaced by `-`, shortened to fit
+/// git's limit.
+pub fn file_name(number: usize, subject: &str) -> String {
+    let mut name = format!("{number:04}-");
+    let start = name.len();
+    let mut separated = false;
+    let mut previous = None;
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            if separated && name.len() > start {
+                name.push('-');
+            }
+            separated = false;
+            // Runs of dots are squashed, so there's no `..`.
+            if !(c == '.' && previous == Some('.')) {
+                name.push(c);
+            }
+        } else {
+            separated = true;
+        }
+        previous = Some(c);
+    }
+    let trimmed = name[start..].trim_end_matches(['.', '-']).len();
+    name.truncate((start + trimmed).min(MAX_FILE_NAME - ".patch".len() - 1));
+    name + ".patch"
+}
+
+/// Returns the `From:` header of an author, quoting or encoding the name if
+/// needed. Like git, the address goes on its own line if it doesn't fit in
+/// 78 columns, or 76 after encoded words.
+fn from_header(author: &Ident) -> String {
+    const SPECIALS: &str = "()<>@,;:\\\".[]";
+    let name = &author.name;
+    let mut from = match !needs_encoding(name) && name.contains(|c| SPECIALS.contains(c)) {
+        true => {
+            let quoted = name.replace('\\', "\\\\").replace('"', "\\\"");
+            header("From: ", &format!("\"{quoted}\""), true)
+        }
+        false => header("From: ", name, true),
+    };
+    let max_width = match needs_encoding(name) {
+        true => MAX_WORD_LINE,
+        false => MAX_HEADER_WIDTH,
+    };
+    let last_line = from.rsplit('\n').next().unwrap_or_default().len();
+    if last_line + " <".len() + author.email.len() + ">".len() > max_width {
+        from.push('\n');
+    }
+    from + &format!(" <{}>", author.email)
+}
+
+/// Returns a header made of `name` and `value`, encoded as RFC 2047 words
+/// if needed, or else folded at spaces to fit in 78 columns.
+fn header(name: &str, value: &str, is_address: bool) -> String {
+    let mut header = name.to_string();
+    if needs_encoding(value) {
+        encode_words(&mut header, value, is_address);
+        return header;
+    }
+    let mut width = header.len();
+    for (i, word) in value.split(' ').enumerate() {
+        if i > 0 {
+            if width > 1 && width + 1 + word.len() > MAX_HEADER_WIDTH {
+                header += "\n";
+                width = 0;
+            }
+            header.push(' ');
+            width += 1;
+        }
+        header += word;
+        width += word.len();
+    }
+    header
+}
+
+/// Returns whether a header value must be encoded, because it has
+/// non-ASCII characters or something that looks like an encoded word.
+fn needs_encoding(value: &str) -> bool {
+    !value.is_ascii() || value.contains('\n') || value.contains("=?")
+}
+
+/// Appends `value` to `header` as `=?UTF-8?q?...?=` words, like git's
+/// `add_rfc2047`: characters are never split across words, which are
+/// folded to fit in 76 columns.
+fn encode_words(header: &mut String, value: &str, is_address: bool) {
+    const START: &str = "=?UTF-8?q?";
+    let mut width = header.rsplit('\n').next().unwrap_or_default().len() + START.len();
+    *header += START;
+    for c in value.chars() {
+        // In addresses, words may only have some of the ASCII punctuation.
+        let is_special = !c.is_ascii()
+            || c.is_ascii_whitespace()
+            || "=?_".contains(c)
+            || is_address && !(c.is_ascii_alphanumeric() || "!*+-/".contains(c));
+        let mut encoded = String::new();
+        match is_special {
+            true => {
+                let mut bytes = [0; 4];
+                for byte in c.encode_utf8(&mut bytes).bytes() {
+                    encoded += &format!("={byte:02X}");
+                }
+            }
+            false => encoded.push(c),
+        }
+        if width + encoded.len() + "?=".len() > MAX_WORD_LINE {
+            *header += "?=\n ";
+            *header += START;
+            width = START.len() + 1;
+        }
+        *header += &encoded;
+        width += encoded.len();
+    }
+    *header += "?=";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name(1, "Fix the thing"), "0001-Fix-the-thing.patch");
+        assert_eq!(
+            file_name(12, "[tag] Odd: chars & stuff!..."),
+            "0012-tag-Odd-chars-stuff.patch"
+        );
+        assert_eq!(file_name(3, "v1...v2 caf\u{e9}"), "0003-v1.v2-caf.patch");
+        assert_eq!(
+            file_name(
+                1,
+                "Change several things in a rather long subject line that wraps"
+            ),
+            "0001-Change-several-things-in-a-rather-long-subject-line-.patch"
+        );
+    }
+
+    #[test]
+    fn test_headers() {
+        assert_eq!(
+            header("Subject: [PATCH 1/2] ", "Change several things in a rather long subject line that wraps beyond the limit", false),
+            "Subject: [PATCH 1/2] Change several things in a rather long subject line that\n wraps beyond the limit"
+        );
+        assert_eq!(
+            header("Subject: [PATCH] ", "Caf\u{e9}: na\u{ef}ve_test =?x? r\u{e9}sum\u{e9} \u{fc}ber-long subject to see how wrapping works with encoded words in it", false),
+            "Subject: [PATCH] =?UTF-8?q?Caf=C3=A9:=20na=C3=AFve=5Ftest=20=3D=3Fx=3F=20r?=\n \
+             =?UTF-8?q?=C3=A9sum=C3=A9=20=C3=BCber-long=20subject=20to=20see=20how=20w?=\n \
+             =?UTF-8?q?rapping=20works=20with=20encoded=20words=20in=20it?="
+        );
+
+        let author = |name: &str| Ident {
+            name: name.to_string(),
+            email: "a@b".to_string(),
+            timestamp: 0,
+            timezone: "+0000".to_string(),
+        };
+        assert_eq!(from_header(&author("A U Thor")), "From: A U Thor <a@b>");
+        assert_eq!(
+            from_header(&author("Dr. \"Q\" Who")),
+            "From: \"Dr. \\\"Q\\\" Who\" <a@b>"
+        );
+        assert_eq!(
+            from_header(&author("J\u{f6}hn D\u{153}")),
+            "From: =?UTF-8?q?J=C3=B6hn=20D=C5=93?= <a@b>"
+        );
+        // Addresses after encoded words fit in 76 columns.
+        let long = Ident {
+            email: format!("{}@example.com", "x".repeat(29)),
+            ..author("J\u{f6}hn Doe")
+        };
+        assert_eq!(
+            from_header(&long),
+            format!("From: =?UTF-8?q?J=C3=B6hn=20Doe?=\n <{}>", long.email)
+        );
+    }
+}
@@ -0,0 +1,707 @@
+use anyhow::{anyhow, Result};
+use flate2::read::ZlibDecoder;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::Read,
+    path::Path,
+};
+
+use crate::{
+    cache_tree::CacheTree,
+    index::Index,
+    object::{self, Object, ObjectType},
+    refs,
+    repo::Repo,
+    shallow, worktree,
+};
+
+/// What [`check`] found in a repository.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    /// Objects that can't be read or whose content doesn't match their hash.
+    pub errors: Vec<String>,
+    /// Refs, reflog entries and cache trees that point to missing objects.
+    pub broken_refs: Vec<String>,
+    /// Reachable objects that aren't in the repository, with the type they
+    /// are referenced as.
+    pub missing: BTreeMap<String, ObjectType>,
+    /// Objects that can't be reached from any ref, reflog or index, with
+    /// whether they are dangling: not referenced by another unreachable
+    /// object either.
+    pub unreachable: BTreeMap<String, (ObjectType, bool)>,
+}
+
+impl Report {
+    /// Returns git's exit code for the report: bit 1 for corrupt objects and
+    /// bit 2 for missing objects. Unreachable objects aren't errors.
+    pub fn exit_code(&self) -> i32 {
+        let mut code = 0;
+        if !self.errors.is_empty() {
+            code |= 1;
+        }
+        if !self.broken_refs.is_empty() || !self.missing.is_empty() {
+            code |= 2;
+        }
+        code
+    }
+}
+
+/// Checks the objects of a repository and which of them are reachable from
+/// the refs, the reflogs and the indexes of all worktrees.
+///
+/// With `connectivity_only`, objects aren't hashed and reachable blobs aren't
+/// read at all: only commits, trees and tags are parsed to follow their
+/// links, which is much faster. Corrupt blobs then go unnoticed.
+pub fn check(repo: &Repo, connectivity_only: bool) -> Result<Report> {
+    // Refs are read before objects are listed, so that the objects of a
+    // concurrent commit are found.
+    let roots = roots(repo)?;
+    let shallow = shallow::read(repo)?;
+    let mut checker = Checker {
+        repo,
+        present: HashSet::new(),
+        nodes: HashMap::new(),
+        report: Report::default(),
+    };
+    for hash in Object::all_hashes(repo)? {
+        if connectivity_only {
+            checker.present.insert(hash);
+        } else {
+            checker.verify(hash)?;
+        }
+    }
+
+    let mut reachable = HashSet::new();
+    let mut queue = vec![];
+    for root in roots {
+        match root.broken {
+            Some(message) if !checker.load(&root.hash) => {
+                checker.report.broken_refs.push(message);
+            }
+            _ => queue.push((root.hash, root.object_type)),
+        }
+    }
+    while let Some((hash, expected)) = queue.pop() {
+        if !reachable.insert(hash.clone()) {
+            continue;
+        }
+        let is_blob = expected == Some(ObjectType::Blob);
+        if connectivity_only && is_blob && checker.present.contains(&hash) {
+            // Blobs don't point to anything, so they don't need to be read.
+            continue;
+        }
+        if !checker.load(&hash) {
+            let object_type = expected.unwrap_or(ObjectType::Blob);
+            checker.report.missing.insert(hash, object_type);
+            continue;
+        }
+        let node = &checker.nodes[&hash];
+        if let Some(expected) = expected.filter(|expected| *expected != node.object_type) {
+            let message = format!("object {hash} is a {}, not a {expected}", node.object_type);
+            checker.report.errors.push(message);
+        }
+        let links = node.links.iter().filter(|(_, link_type)| {
+            // The parents of shallow commits weren't fetched.
+            !(shallow.contains(&hash) && *link_type == ObjectType::Commit)
+        });
+        queue.extend(links.map(|(link, link_type)| (link.clone(), Some(*link_type))));
+    }
+
+    let mut unreachable: Vec<String> = checker
+        .present
+        .iter()
+        .filter(|hash| !reachable.contains(*hash))
+        .cloned()
+        .collect();
+    unreachable.sort();
+    let mut referenced = HashSet::new();
+    for hash in unreachable {
+        if checker.load(&hash) {
+            let node = &checker.nodes[&hash];
+            referenced.extend(node.links.iter().map(|(link, _)| link.clone()));
+            let object_type = node.object_type;
+            checker.report.unreachable.insert(hash, (object_type, true));
+        }
+    }
+    for (hash, (_, dangling)) in checker.report.unreachable.iter_mut() {
+        *dangling = !referenced.contains(hash);
+    }
+    Ok(checker.report)
+}
+
+/// Where the traversal starts.
+struct Root {
+    hash: String,
+    /// The type the object should have, if any.
+    object_type: Option<ObjectType>,
+    /// The error to report if the object is missing, or `None` to report it
+    /// as a missing object.
+    broken: Option<String>,
+}
+
+/// Returns the objects the refs, the reflogs and the indexes of all worktrees
+/// point to.
+fn roots(repo: &Repo) -> Result<Vec<Root>> {
+    let mut roots = vec![];
+    let mut reflogs = vec![];
+    collect_reflogs(&repo.common_dir().join("logs"), "", &mut reflogs)?;
+    for worktree in worktree::list(repo)? {
+        let worktree_repo = worktree.repo();
+        for (name, value) in refs::read_all(&worktree_repo)? {
+            // Like git, pseudorefs such as `ORIG_HEAD` don't keep objects.
+            let is_ref = name == "HEAD" || name.starts_with("refs/");
+            if is_ref && !value.starts_with("ref: ") {
+                roots.extend(ref_root(
+                    &value,
+                    format!("{name}: invalid sha1 pointer {value}"),
+                ));
+            }
+        }
+        if let Some(git_dir) = worktree.git_dir(repo) {
+            let path = git_dir.join("logs/HEAD");
+            if path.is_file() {
+                reflogs.push(("HEAD".to_string(), path));
+            }
+        }
+
+        let index = Index::read(&worktree_repo)?;
+        for entry in &index.entries {
+            // Submodules are commits of another repository.
+            if entry.mode != 0o160000 {
+                roots.push(Root {
+                    hash: entry.hash.clone(),
+                    object_type: Some(ObjectType::Blob),
+                    broken: None,
+                });
+            }
+        }
+        if let Some(cache_tree) = &index.cache_tree {
+            add_cache_tree(cache_tree, &mut roots);
+        }
+    }
+
+    for (name, path) in reflogs {
+        for line in fs::read_to_string(path)?.lines() {
+            for hash in line.split(' ').take(2) {
+                roots.extend(ref_root(
+                    hash,
+                    format!("{name}: invalid reflog entry {hash}"),
+                ));
+            }
+        }
+    }
+    Ok(roots)
+}
+
+/// Returns the root for a ref or reflog entry, which doesn't point to
+/// anything if it's the zero hash.
+fn ref_root(hash: &str, broken: String) -> Option<Root> {
+    (hash != refs::ZERO_HASH).then(|| Root {
+        hash: hash.to_string(),
+        object_type: None,
+        broken: Some(broken),
+    })
+}
+
+/// Collects the (ref name, path) pairs of the reflogs below `dir`.
+fn collect_reflogs(
+    dir: &Path,
+    prefix: &str,
+    reflogs: &mut Vec<(String, std::path::PathBuf)>,
+) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let Some(name) = entry
+            .file_name()
+            .to_str()
+            .map(|name| format!("{prefix}{name}"))
+        else {
+            continue;
+        };
+        if entry.file_type()?.is_dir() {
+            collect_reflogs(&entry.path(), &format!("{name}/"), reflogs)?;
+        } else {
+            reflogs.push((name, entry.path()));
+        }
+    }
+    Ok(())
+}
+
+fn add_cache_tree(cache_tree: &CacheTree, roots: &mut Vec<Root>) {
+    if let Some(hash) = &cache_tree.hash {
+        roots.push(Root {
+            hash: hash.clone(),
+            object_type: Some(ObjectType::Tree),
+            broken: Some(format!("{hash}: invalid sha1 pointer in cache-tree")),
+        });
+    }
+    for subtree in &cache_tree.subtrees {
+        add_cache_tree(subtree, roots);
+    }
+}
+
+/// An object with the objects it points to.
+struct Node {
+    object_type: ObjectType,
+    links: Vec<(String, ObjectType)>,
+}
+
+impl Node {
+    fn new(object: &Object) -> Node {
+        match object {
+            Object::Blob(_) => Node {
+                object_type: ObjectType::Blob,
+                links: vec![],
+            },
+            Object::Tree(tree) => Node {
+                object_type: ObjectType::Tree,
+                links: tree
+                    .files
+                    .iter()
+                    // Submodules are commits of another repository.
+                    .filter(|file| file.mode != "160000")
+                    .map(|file| (file.hash.clone(), file.object_type()))
+                    .collect(),
+            },
+            Object::Commit(commit) => Node {
+                object_type: ObjectType::Commit,
+                links: [(commit.tree.clone(), ObjectType::Tree)]
+                    .into_iter()
+                    .chain(
+                        commit
+                            .parents
+                            .iter()
+                            .map(|parent| (parent.clone(), ObjectType::Commit)),
+                    )
+                    .collect(),
+            },
+            Object::Tag(tag) => Node {
+                object_type: ObjectType::Tag,
+                links: match tag.object_type.parse() {
+                    Ok(object_type) => vec![(tag.object.clone(), object_type)],
+                    Err(_) => vec![],
+                },
+            },
+        }
+    }
+}
+
+struct Checker<'a> {
+    repo: &'a Repo,
+    /// The objects in the repository that weren't found to be corrupt.
+    present: HashSet<String>,
+    /// The objects that were read.
+    nodes: HashMap<String, Node>,
+    report: Report,
+}
+
+impl Checker<'_> {
+    /// Reads an object and checks that its content matches its hash.
+    fn verify(&mut self, hash: String) -> Result<()> {
+        let path = object::object_path(self.repo, &hash)?;
+        let data = fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| {
+                let mut content = vec![];
+                ZlibDecoder::new(&data[..]).read_to_end(&mut content)?;
+                Ok(content)
+            });
+        match data.and_then(|data| Ok((object::hash(&data), Object::from_bytes(&data)?))) {
+            Ok((actual, object)) if actual == hash => {
+                self.nodes.insert(hash.clone(), Node::new(&object));
+                self.present.insert(hash);
+            }
+            Ok((actual, _)) => self.report.errors.push(format!(
+                "{actual}: hash-path mismatch, found at: {}",
+                path.display()
+            )),
+            Err(_) => self.report.errors.push(format!(
+                "{hash}: object corrupt or missing: {}",
+                path.display()
+            )),
+        }
+        Ok(())
+    }
+
+    /// Makes sure an object was read, only reading the header of blobs.
+    /// Returns false if the object is missing or corrupt.
+    fn load(&mut self, hash: &str) -> bool {
+        if self.nodes.contains_key(hash) {
+            return true;
+        }
+        if !self.present.contains(hash) {
+            return false;
+        }
+        let node =
+            object::read_header(self.repo, hash).and_then(|(object_type, _)| match object_type {
+                ObjectType::Blob => Ok(Node {
+                    object_type,
+                    links: vec![],
+                }),
+                _ => Object::from_hash(self.repo, hash).map(|object| Node::new(&object)),
+            });
+        match node {
+            Ok(node) => {
+                self.nodes.insert(hash.to_string(), node);
+                true
+            }
+            Err(_) => {
+                let path = object::object_path(self.repo, hash)
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default();
+                self.report
+                    .errors
+                    .push(format!("{hash}: object corrupt or missing: {path}"));
+                self.present.remove(hash);
+                false
+            }
+        }
+    }
+}
+
+/// Checks that the content of an object is well formed, like `git fsck`:
+/// tree entries have valid modes and names and are sorted without
+/// duplicates, and commits and tags have their headers in order, with valid
+/// hashes and identities whose dates make sense.
+///
+/// Errors are git's `<id>: <message>`, like `treeNotSorted: not properly
+/// sorted`. Names that git only warns about, like `.git`, are errors as they
+/// can't be checked out.
+pub fn check_object(object_type: ObjectType, content: &[u8]) -> Result<()> {
+    match object_type {
+        ObjectType::Blob => Ok(()),
+        ObjectType::Tree => check_tree(content),
+        ObjectType::Commit => {
+            let mut headers = Headers::new(content)?;
+            headers.hash("tree", "missingTree", "badTreeSha1")?;
+            while headers.peek("parent") {
+                headers.hash("parent", "missingParent", "badParentSha1")?;
+            }
+            check_ident(headers.expect("author", "missingAuthor")?)?;
+            check_ident(headers.expect("committer", "missingCommitter")?)
+        }
+        ObjectType::Tag => {
+            let mut headers = Headers::new(content)?;
+            headers.hash("object", "missingObject", "badObjectSha1")?;
+            let object_type = headers.expect("type", "missingTypeEntry")?;
+            if object_type.parse::<ObjectType>().is_err() {
+                return Err(anyhow!("badType: invalid 'type' value"));
+            }
+            headers.expect("tag", "missingTagEntry")?;
+            // Very old tags have no tagger.
+            if headers.peek("tagger") {
+                check_ident(headers.expect("tagger", "missingTaggerEntry")?)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn check_tree(content: &[u8]) -> Result<()> {
+    let bad_tree = || anyhow!("badTree: cannot be parsed as a tree");
+    let mut names = HashSet::new();
+    let mut previous_key: Option<Vec<u8>> = None;
+    let mut rest = content;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&b| b == b' ').ok_or_else(bad_tree)?;
+        let (mode, after) = (&rest[..space], &rest[space + 1..]);
+        let nul = after.iter().position(|&b| b == 0).ok_or_else(bad_tree)?;
+        let name = &after[..nul];
+        rest = after.get(nul + 21..).ok_or_else(bad_tree)?;
+
+        if mode.first() == Some(&b'0') {
+            return Err(anyhow!(
+                "zeroPaddedFilemode: contains zero-padded file modes"
+            ));
+        }
+        let is_tree = mode == b"40000";
+        let valid_modes: [&[u8]; 5] = [b"100644", b"100755", b"120000", b"40000", b"160000"];
+        if !valid_modes.contains(&mode) {
+            return Err(anyhow!("badFilemode: contains bad file modes"));
+        }
+        match name {
+            b"" => return Err(anyhow!("emptyName: contains empty pathname")),
+            b"." => return Err(anyhow!("hasDot: contains '.'")),
+            b".." => return Err(anyhow!("hasDotdot: contains '..'")),
+            _ if name.contains(&b'/') => {
+                return Err(anyhow!("fullPathname: contains full pathnames"))
+            }
+            _ if name.eq_ignore_ascii_case(b".git") => {
+                return Err(anyhow!("hasDotgit: contains '.git'"))
+            }
+            _ => {}
+        }
+        if !names.insert(name) {
+            return Err(anyhow!("duplicateEntries: contains duplicate file entries"));
+        }
+        // Trees are sorted as if their name ended with a `/`.
+        let mut key = name.to_vec();
+        if is_tree {
+            key.push(b'/');
+        }
+        if previous_key
+            .as_ref()
+            .is_some_and(|previous| *previous > key)
+        {
+            return Err(anyhow!("treeNotSorted: not properly sorted"));
+        }
+        previous_key = Some(key);
+    }
+    Ok(())
+}
+
+/// The header lines of a commit or a tag, read in order.
+struct Headers<'a> {
+    lines: std::iter::Peekable<std::str::Split<'a, char>>,
+}
+
+impl<'a> Headers<'a> {
+    fn new(content: &'a [u8]) -> Result<Headers<'a>> {
+        // The headers end with an empty line, or with the object.
+        let end = content
+            .windows(2)
+            .position(|pair| pair == b"\n\n")
+            .map(|i| i + 1)
+            .or_else(|| content.ends_with(b"\n").then_some(content.len()))
+            .ok_or_else(|| anyhow!("unterminatedHeader: unterminated header"))?;
+        let headers = &content[..end];
+        if let Some(offset) = headers.iter().position(|&b| b == 0) {
+            return Err(anyhow!(
+                "nulInHeader: unterminated header: NUL at offset {offset}"
+            ));
+        }
+        let headers = std::str::from_utf8(&headers[..end - 1])
+            .map_err(|_| anyhow!("badHeader: invalid UTF-8 in header"))?;
+        Ok(Headers {
+            lines: headers.split('\n').peekable(),
+        })
+    }
+
+    /// Returns whether the next header is `key`.
+    fn peek(&mut self, key: &str) -> bool {
+        self.lines
+            .peek()
+            .and_then(|line| line.strip_prefix(key))
+            .is_some_and(|rest| rest.starts_with(' '))
+    }
+
+    /// Returns the value of the next header, which must be `key`, or fails
+    /// with `id`.
+    fn expect(&mut self, key: &str, id: &str) -> Result<&'a str> {
+        if !self.peek(key) {
+            return Err(anyhow!("{id}: invalid format - expected '{key}' line"));
+        }
+        let line = self.lines.next().expect("peeked above");
+        Ok(&line[key.len() + 1..])
+    }
+
+    /// Checks that the next header is `key` with a hash.
+    fn hash(&mut self, key: &str, missing_id: &str, bad_id: &str) -> Result<()> {
+        let value = self.expect(key, missing_id)?;
+        let is_hash = value.len() == 40
+            && value
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+        if !is_hash {
+            return Err(anyhow!("{bad_id}: invalid '{key}' line format - bad sha1"));
+        }
+        Ok(())
+    }
+}
+
+/// Checks an identity like git: `Name <email> <timestamp> <+hhmm>`.
+fn check_ident(ident: &str) -> Result<()> {
+    let fail =
+        |id: &str, problem: &str| Err(anyhow!("{id}: invalid author/committer line - {problem}"));
+    if ident.starts_with('<') {
+        return fail("missingNameBeforeEmail", "missing space before email");
+    }
+    let Some((name, rest)) = ident.split_once('<') else {
+        return fail("missingEmail", "missing email");
+    };
+    if name.contains('>') {
+        return fail("badName", "bad name");
+    }
+    if !name.ends_with(' ') {
+        return fail("missingSpaceBeforeEmail", "missing space before email");
+    }
+    let Some((email, rest)) = rest.split_once('>') else {
+        return fail("badEmail", "bad email");
+    };
+    if email.contains('<') {
+        return fail("badEmail", "bad email");
+    }
+    let Some(rest) = rest.strip_prefix(' ') else {
+        return fail("missingSpaceBeforeDate", "missing space before date");
+    };
+    let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+    if digits > 1 && rest.starts_with('0') {
+        return fail("zeroPaddedDate", "zero-padded date");
+    }
+    let (date, time_zone) = rest.split_at(digits);
+    if date.parse::<i64>().is_err() {
+        return match digits {
+            0 => fail("badDate", "bad date"),
+            _ => fail("badDateOverflow", "date causes integer overflow"),
+        };
+    }
+    let Some(time_zone) = time_zone.strip_prefix(' ') else {
+        return fail("badDate", "bad date");
+    };
+    let valid_time_zone = time_zone.len() == 5
+        && time_zone.starts_with(['+', '-'])
+        && time_zone[1..].bytes().all(|b| b.is_ascii_digit());
+    if !valid_time_zone {
+        return fail("badTimezone", "bad time zone");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "d670460b4b4aece5915caf5c68d12f560a9fe3e4";
+
+    fn tree(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut content = vec![];
+        for (mode, name) in entries {
+            content.extend(format!("{mode} {name}\0").as_bytes());
+            content.extend(hex::decode(HASH).unwrap());
+        }
+        content
+    }
+
+    fn check(object_type: ObjectType, content: &[u8]) -> String {
+        match check_object(object_type, content) {
+            Ok(()) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_check_tree() {
+        let check_tree = |entries: &[(&str, &str)]| check(ObjectType::Tree, &tree(entries));
+        assert_eq!(check_tree(&[]), "ok");
+        // `a.b` comes before the tree `a`, compared as `a/`.
+        assert_eq!(
+            check_tree(&[("100644", "a.b"), ("40000", "a"), ("160000", "b")]),
+            "ok"
+        );
+        assert_eq!(
+            check_tree(&[("100644", "b"), ("100644", "a")]),
+            "treeNotSorted: not properly sorted"
+        );
+        assert_eq!(
+            check_tree(&[("100644", "a"), ("40000", "a")]),
+            "duplicateEntries: contains duplicate file entries"
+        );
+        assert_eq!(
+            check_tree(&[("040000", "a")]),
+            "zeroPaddedFilemode: contains zero-padded file modes"
+        );
+        assert_eq!(
+            check_tree(&[("100664", "a")]),
+            "badFilemode: contains bad file modes"
+        );
+        assert_eq!(
+            check_tree(&[("40000", ".GIT")]),
+            "hasDotgit: contains '.git'"
+        );
+        assert_eq!(
+            check_tree(&[("100644", "a/b")]),
+            "fullPathname: contains full pathnames"
+        );
+        assert_eq!(
+            check(ObjectType::Tree, b"100644 a\0short"),
+            "badTree: cannot be parsed as a tree"
+        );
+    }
+
+    #[test]
+    fn test_check_commit() {
+        let commit = |author: &str| {
+            format!(
+                "tree {HASH}\nparent {HASH}\nauthor {author}\n\
+                 committer A U Thor <a@example.com> 1112911993 -0700\n\nMessage\n"
+            )
+        };
+        let check_author = |author: &str| check(ObjectType::Commit, commit(author).as_bytes());
+        assert_eq!(check_author("A U Thor <a@example.com> 0 +0000"), "ok");
+        // Git allows empty names and emails.
+        assert_eq!(check_author(" <> 1112911993 -0700"), "ok");
+        for (author, error) in [
+            ("<a@example.com> 0 +0000", "missingNameBeforeEmail"),
+            ("A U Thor a@example.com 0 +0000", "missingEmail"),
+            ("A>Thor <a@example.com> 0 +0000", "badName"),
+            ("A U Thor<a@example.com> 0 +0000", "missingSpaceBeforeEmail"),
+            ("A U Thor <a<b@example.com> 0 +0000", "badEmail"),
+            ("A U Thor <a@example.com>0 +0000", "missingSpaceBeforeDate"),
+            ("A U Thor <a@example.com> 0123 +0000", "zeroPaddedDate"),
+            (
+                "A U Thor <a@example.com> 99999999999999999999 +0000",
+                "badDateOverflow",
+            ),
+            ("A U Thor <a@example.com> yesterday +0000", "badDate"),
+            ("A U Thor <a@example.com> 0 0000", "badTimezone"),
+            ("A U Thor <a@example.com> 0 +00:00", "badTimezone"),
+        ] {
+            let result = check_author(author);
+            assert!(
+                result.starts_with(&format!("{error}: ")),
+                "{author}: {result}"
+            );
+        }
+
+        let valid = commit("A U Thor <a@example.com> 0 +0000");
+        assert_eq!(
+            check(ObjectType::Commit, valid.replace("tree", "tre").as_bytes()),
+            "missingTree: invalid format - expected 'tree' line"
+        );
+        assert_eq!(
+            check(ObjectType::Commit, valid.replace(HASH, "abc").as_bytes()),
+            "badTreeSha1: invalid 'tree' line format - bad sha1"
+        );
+        assert_eq!(
+            check(
+                ObjectType::Commit,
+                valid.replace("author", "writer").as_bytes()
+            ),
+            "missingAuthor: invalid format - expected 'author' line"
+        );
+        assert_eq!(
+            check(
+                ObjectType::Commit,
+                valid.trim_end_matches("\n\nMessage\n").as_bytes()
+            ),
+            "unterminatedHeader: unterminated header"
+        );
+    }
+
+    #[test]
+    fn test_check_tag() {
+        let tag = format!(
+            "object {HASH}\ntype commit\ntag v1.0\n\
+             tagger A U Thor <a@example.com> 1112911993 -0700\n\nRelease\n"
+        );
+        assert_eq!(check(ObjectType::Tag, tag.as_bytes()), "ok");
+        let without_tagger = format!("object {HASH}\ntype commit\ntag v1.0\n\nRelease\n");
+        assert_eq!(check(ObjectType::Tag, without_tagger.as_bytes()), "ok");
+        assert_eq!(
+            check(
+                ObjectType::Tag,
+                tag.replace("type commit", "type note").as_bytes()
+            ),
+            "badType: invalid 'type' value"
+        );
+        assert_eq!(
+            check(ObjectType::Tag, tag.replace("tag v1.0\n", "").as_bytes()),
+            "missingTagEntry: invalid format - expected 'tag' line"
+        );
+        assert_eq!(check(ObjectType::Blob, b"\0anything"), "ok");
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{anyhow, Result};
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use crate::{config::Config, index::Index, object::Object, pack_index::PackIndex};
+
+/// The most bytes an untrusted loose object may inflate to.
+pub const MAX_UNTRUSTED_OBJECT_SIZE: u64 = 16 << 20;
+
+/// Parses a loose object file, as found under `.git/objects`, from an
+/// untrusted source.
+///
+/// Like the other `parse_*_untrusted` functions, this is meant to be fuzzed:
+/// it never panics, never touches the file system, and its allocations are
+/// bounded by its input, or by [`MAX_UNTRUSTED_OBJECT_SIZE`] for inflated
+/// data.
+pub fn parse_object_untrusted(data: &[u8]) -> Result<Object> {
+    let mut inflated = vec![];
+    ZlibDecoder::new(data)
+        .take(MAX_UNTRUSTED_OBJECT_SIZE + 1)
+        .read_to_end(&mut inflated)?;
+    if inflated.len() as u64 > MAX_UNTRUSTED_OBJECT_SIZE {
+        return Err(anyhow!(
+            "Object is larger than {MAX_UNTRUSTED_OBJECT_SIZE} bytes"
+        ));
+    }
+    Object::from_bytes(&inflated)
+}
+
+/// Parses an index file from an untrusted source, see
+/// [`parse_object_untrusted`].
+///
+/// Split indexes are rejected since their shared index is another file.
+pub fn parse_index_untrusted(data: &[u8]) -> Result<Index> {
+    Index::from_bytes(data)
+}
+
+/// Parses a config file from an untrusted source, see
+/// [`parse_object_untrusted`]. Includes aren't followed.
+pub fn parse_config_untrusted(data: &[u8]) -> Result<Config> {
+    Config::parse(std::str::from_utf8(data)?)
+}
+
+/// Parses a pack index from an untrusted source, see
+/// [`parse_object_untrusted`].
+pub fn parse_pack_index_untrusted(data: &[u8]) -> Result<PackIndex> {
+    PackIndex::from_bytes(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache_tree::CacheTree,
+        index::IndexEntry,
+        object::{self, ObjectType},
+        untracked_cache::UntrackedCache,
+    };
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::{io::Write, path::Path};
+
+    /// Calls `parse` on every truncation of `data` and on copies with each
+    /// byte replaced by a few interesting values, after `fix` restores
+    /// checksums.
+    fn mutate(data: &[u8], fix: impl Fn(&mut Vec<u8>), parse: impl Fn(&[u8])) {
+        for len in 0..data.len() {
+            let mut truncated = data[..len].to_vec();
+            fix(&mut truncated);
+            parse(&truncated);
+        }
+        for i in 0..data.len() {
+            for byte in [
+                0,
+                0xff,
+                b' ',
+                b'\n',
+                b'-',
+                data[i] ^ 1,
+                data[i].wrapping_add(1),
+            ] {
+                let mut mutated = data.to_vec();
+                mutated[i] = byte;
+                fix(&mut mutated);
+                parse(&mutated);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_object_untrusted() {
+        let tree = b"100644 a.txt\0aaaaaaaaaaaaaaaaaaaa40000 dir\0bbbbbbbbbbbbbbbbbbbb";
+        let commit = b"tree 1234\nparent 5678\nauthor A <a@a> 1 +0000\n\nmessage\n";
+        for (object_type, content) in [(ObjectType::Tree, &tree[..]), (ObjectType::Commit, commit)]
+        {
+            let mut raw = format!("{object_type} {}\0", content.len()).into_bytes();
+            raw.extend(content);
+            assert!(Object::from_bytes(&raw).is_ok());
+            mutate(
+                &raw,
+                |_| {},
+                |data| {
+                    let _ = Object::from_bytes(data);
+                },
+            );
+
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&raw).unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert!(parse_object_untrusted(&compressed).is_ok());
+            mutate(
+                &compressed,
+                |_| {},
+                |data| {
+                    let _ = parse_object_untrusted(data);
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_index_untrusted() {
+        let mut cache = UntrackedCache::new(Path::new("/repo"));
+        cache.update("", Default::default(), None, vec!["a/".to_string()]);
+        cache.update("a/b", Default::default(), None, vec!["x".to_string()]);
+        let mut cache_tree = b"\x002 1\n".to_vec();
+        cache_tree.extend([0xaa; 20]);
+        cache_tree.extend(b"dir\0-1 0\n");
+        for version in [2, 4] {
+            let index = Index {
+                version,
+                entries: ["a.txt", "dir/b.txt"]
+                    .map(|path| IndexEntry {
+                        mode: 0o100644,
+                        hash: "ab".repeat(20),
+                        path: path.to_string(),
+                        ..Default::default()
+                    })
+                    .to_vec(),
+                cache_tree: Some(CacheTree::from_bytes(&cache_tree).unwrap()),
+                untracked_cache: Some(cache.clone()),
+                ..Default::default()
+            };
+            let data = index.to_bytes().unwrap();
+            assert_eq!(parse_index_untrusted(&data).unwrap(), index);
+
+            let content = &data[..data.len() - 20];
+            let fix_checksum = |data: &mut Vec<u8>| {
+                data.extend(hex::decode(object::hash(data)).unwrap());
+            };
+            mutate(content, fix_checksum, |data| {
+                let _ = parse_index_untrusted(data);
+            });
+        }
+    }
+
+    #[test]
+    fn test_deeply_nested_extensions() {
+        // Each level has one subtree.
+        let nested = b"\0-1 1\n".repeat(100_000);
+        assert_eq!(
+            CacheTree::from_bytes(&nested).unwrap_err().to_string(),
+            "Cache tree is too deep"
+        );
+        let mut nested = b"\0-1 1\n".repeat(2048);
+        nested.extend(b"\0-1 0\n");
+        assert!(CacheTree::from_bytes(&nested).is_ok());
+
+        let mut cache = UntrackedCache::new(Path::new("/repo"));
+        let deep_path = vec!["a"; 2048].join("/");
+        cache.update(&deep_path, Default::default(), None, vec![]);
+        assert!(UntrackedCache::from_bytes(&cache.to_bytes()).is_ok());
+        cache.update(&format!("{deep_path}/a"), Default::default(), None, vec![]);
+        assert_eq!(
+            UntrackedCache::from_bytes(&cache.to_bytes())
+                .unwrap_err()
+                .to_string(),
+            "Untracked cache is too deep"
+        );
+    }
+
+    #[test]
+    fn test_parse_config_untrusted() {
+        let config = b"[core]\n\tbare = false\n[remote \"origin\"]\n\turl = \"a\\\\b\" \\\n";
+        mutate(
+            config,
+            |_| {},
+            |data| {
+                let _ = parse_config_untrusted(data);
+            },
+        );
+        assert!(parse_config_untrusted(b"[core]\nbare = \xff").is_err());
+    }
+
+    #[test]
+    fn test_parse_pack_index_untrusted() {
+        // A version 2 index with one object at a large offset.
+        let mut index = b"\xfftOc\0\0\0\x02".to_vec();
+        for byte in 0..=255_u32 {
+            index.extend(u32::from(byte >= 0xab).to_be_bytes());
+        }
+        index.extend([0xab; 20]);
+        index.extend(0x1234_u32.to_be_bytes());
+        index.extend(0x8000_0000_u32.to_be_bytes());
+        index.extend((1_u64 << 33).to_be_bytes());
+        index.extend([0; 40]);
+        assert!(parse_pack_index_untrusted(&index).is_ok());
+        mutate(
+            &index,
+            |_| {},
+            |data| {
+                let _ = parse_pack_index_untrusted(data);
+            },
+        );
+    }
+}
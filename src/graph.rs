@@ -0,0 +1,274 @@
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    commit_graph::{CommitGraph, GENERATION_MAX},
+    object::{Commit, Object},
+    refs,
+    repo::Repo,
+    shallow,
+};
+
+/// A commit in the commit graph, with the information needed to render it.
+#[derive(Debug, PartialEq)]
+pub struct GraphNode {
+    pub hash: String,
+    pub parents: Vec<String>,
+    /// Names of the refs pointing directly at this commit.
+    pub refs: Vec<String>,
+    pub author: String,
+    pub committer: String,
+    /// The first line of the commit message.
+    pub subject: String,
+}
+
+/// Returns the commit graph reachable from `tips`, split into topological layers.
+///
+/// The first layer contains the commits without any children in the graph.
+/// Every other commit is placed in the layer after the deepest of its
+/// children, so a commit is always rendered below all of its descendants.
+/// Commits within a layer are sorted by hash to keep the output stable.
+pub fn commit_graph(repo: &Repo, tips: &[String]) -> Result<Vec<Vec<GraphNode>>> {
+    let shallow = shallow::read(repo)?;
+    let mut commits: HashMap<String, Commit> = HashMap::new();
+    let mut pending = tips.to_vec();
+    while let Some(hash) = pending.pop() {
+        if commits.contains_key(&hash) {
+            continue;
+        }
+        let Object::Commit(mut commit) = Object::from_hash(repo, &hash)? else {
+            continue;
+        };
+        if shallow.contains(&hash) {
+            commit.parents.clear();
+        }
+        pending.extend(commit.parents.iter().cloned());
+        commits.insert(hash, commit);
+    }
+
+    let mut ref_names: HashMap<String, Vec<String>> = HashMap::new();
+    if let Ok(hash) = refs::find_ref(repo, "HEAD") {
+        ref_names.entry(hash).or_default().push("HEAD".to_string());
+    }
+    for (name, hash) in refs::list(repo)? {
+        ref_names.entry(hash).or_default().push(name);
+    }
+
+    // Kahn's algorithm, where the "incoming edges" of a commit are its children.
+    let mut child_counts: HashMap<&str, usize> = HashMap::new();
+    for commit in commits.values() {
+        let unique_parents: HashSet<&String> = commit.parents.iter().collect();
+        for parent in unique_parents {
+            if commits.contains_key(parent) {
+                *child_counts.entry(parent).or_default() += 1;
+            }
+        }
+    }
+    let mut layer: Vec<&str> = commits
+        .keys()
+        .map(String::as_str)
+        .filter(|hash| !child_counts.contains_key(hash))
+        .collect();
+
+    let mut layers = vec![];
+    while !layer.is_empty() {
+        layer.sort();
+        let mut next_layer = vec![];
+        let mut nodes = vec![];
+        for hash in layer {
+            let commit = &commits[hash];
+            let unique_parents: HashSet<&String> = commit.parents.iter().collect();
+            for parent in unique_parents {
+                if let Some(count) = child_counts.get_mut(parent.as_str()) {
+                    *count -= 1;
+                    if *count == 0 {
+                        next_layer.push(parent.as_str());
+                    }
+                }
+            }
+            nodes.push(GraphNode {
+                hash: hash.to_string(),
+                parents: commit.parents.clone(),
+                refs: ref_names.get(hash).cloned().unwrap_or_default(),
+                author: commit.author.clone(),
+                committer: commit.committer.clone(),
+                subject: commit.message.lines().next().unwrap_or("").to_string(),
+            });
+        }
+        layers.push(nodes);
+        layer = next_layer;
+    }
+
+    Ok(layers)
+}
+
+/// Returns whether `ancestor` can be reached from `descendant` by following
+/// parents. A commit is its own ancestor.
+///
+/// With a commit-graph file, parents are read from it, and the walk skips
+/// the commits whose generation is too small to reach `ancestor`.
+pub fn is_ancestor(repo: &Repo, ancestor: &str, descendant: &str) -> Result<bool> {
+    let graph = CommitGraph::load(repo)?;
+    let shallow = shallow::read(repo)?;
+    // Commits in the graph can't reach commits outside of it.
+    let ancestor_generation = graph
+        .as_ref()
+        .map(|graph| graph.generation(ancestor).unwrap_or(u32::MAX));
+    let can_reach_ancestor = |hash: &str| match (&graph, ancestor_generation) {
+        (Some(graph), Some(ancestor_generation)) if ancestor_generation < GENERATION_MAX => graph
+            .generation(hash)
+            .map_or(true, |generation| generation > ancestor_generation),
+        _ => true,
+    };
+
+    let mut seen = HashSet::new();
+    let mut pending = vec![descendant.to_string()];
+    while let Some(hash) = pending.pop() {
+        if hash == ancestor {
+            return Ok(true);
+        }
+        // The parents of shallow commits weren't fetched.
+        if !seen.insert(hash.clone()) || !can_reach_ancestor(&hash) || shallow.contains(&hash) {
+            continue;
+        }
+        let parents = match graph
+            .as_ref()
+            .map(|graph| graph.parents(&hash))
+            .transpose()?
+        {
+            Some(Some(parents)) => parents,
+            _ => match Object::from_hash(repo, &hash)? {
+                Object::Commit(commit) => commit.parents,
+                _ => return Err(anyhow!("Not a commit: {hash}")),
+            },
+        };
+        pending.extend(parents);
+    }
+    Ok(false)
+}
+
+/// Returns the best common ancestors of two commits, newest first, like
+/// `git merge-base --all`: the common ancestors that aren't ancestors of
+/// other common ancestors.
+pub fn merge_bases(repo: &Repo, a: &str, b: &str) -> Result<Vec<String>> {
+    merge_bases_many(repo, &[a.to_string()], b)
+}
+
+/// Returns the best common ancestors of `b` and any of the commits `a`, like
+/// [`merge_bases`] with a commit merging the commits `a`, which doesn't have
+/// to exist.
+pub fn merge_bases_many(repo: &Repo, a: &[String], b: &str) -> Result<Vec<String>> {
+    let shallow = shallow::read(repo)?;
+    let ancestors = |hashes: &[String]| -> Result<HashMap<String, Commit>> {
+        let mut commits = HashMap::new();
+        let mut pending = hashes.to_vec();
+        while let Some(hash) = pending.pop() {
+            if commits.contains_key(&hash) {
+                continue;
+            }
+            let Object::Commit(mut commit) = Object::from_hash(repo, &hash)? else {
+                return Err(anyhow!("Not a commit: {hash}"));
+            };
+            if shallow.contains(&hash) {
+                commit.parents.clear();
+            }
+            pending.extend(commit.parents.iter().cloned());
+            commits.insert(hash, commit);
+        }
+        Ok(commits)
+    };
+    let b_ancestors = ancestors(&[b.to_string()])?;
+    let common: HashMap<String, Commit> = ancestors(a)?
+        .into_iter()
+        .filter(|(hash, _)| b_ancestors.contains_key(hash))
+        .collect();
+    // The parents of common ancestors are common ancestors too, so these are
+    // all the common ancestors that can be reached from another one.
+    let reachable: HashSet<&String> = common.values().flat_map(|commit| &commit.parents).collect();
+    let mut bases: Vec<(&String, &Commit)> = common
+        .iter()
+        .filter(|(hash, _)| !reachable.contains(hash))
+        .collect();
+    bases.sort_by_key(|(hash, commit)| (std::cmp::Reverse(commit.committer_timestamp()), *hash));
+    Ok(bases.into_iter().map(|(hash, _)| hash.clone()).collect())
+}
+
+/// Serializes the layers returned by [`commit_graph`] as JSON.
+///
+/// The format is `{"layers": [[node, ...], ...]}` where each node is an object
+/// with the same fields as [`GraphNode`].
+pub fn to_json(layers: &[Vec<GraphNode>]) -> String {
+    let layers: Vec<String> = layers
+        .iter()
+        .map(|layer| {
+            let nodes: Vec<String> = layer
+                .iter()
+                .map(|node| {
+                    format!(
+                        "{{\"hash\":{},\"parents\":{},\"refs\":{},\"author\":{},\"committer\":{},\"subject\":{}}}",
+                        json_string(&node.hash),
+                        json_array(&node.parents),
+                        json_array(&node.refs),
+                        json_string(&node.author),
+                        json_string(&node.committer),
+                        json_string(&node.subject),
+                    )
+                })
+                .collect();
+            format!("[{}]", nodes.join(","))
+        })
+        .collect();
+    format!("{{\"layers\":[{}]}}", layers.join(","))
+}
+
+fn json_array(values: &[String]) -> String {
+    let values: Vec<String> = values.iter().map(|value| json_string(value)).collect();
+    format!("[{}]", values.join(","))
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_string_escapes_special_characters() {
+        assert_eq!(
+            json_string("a \"b\"\\\n\u{1}"),
+            "\"a \\\"b\\\"\\\\\\n\\u0001\""
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let layers = vec![vec![GraphNode {
+            hash: "abc".to_string(),
+            parents: vec!["def".to_string()],
+            refs: vec![],
+            author: "A <a@a>".to_string(),
+            committer: "C <c@c>".to_string(),
+            subject: "Hello".to_string(),
+        }]];
+        assert_eq!(
+            to_json(&layers),
+            "{\"layers\":[[{\"hash\":\"abc\",\"parents\":[\"def\"],\"refs\":[],\"author\":\"A <a@a>\",\"committer\":\"C <c@c>\",\"subject\":\"Hello\"}]]}"
+        );
+    }
+}
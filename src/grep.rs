@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Result};
+use regex::bytes::{Regex, RegexBuilder};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+
+use crate::config::Config;
+
+/// Like git, at most this many threads are used by default.
+const MAX_DEFAULT_THREADS: usize = 8;
+
+/// Finds the lines matching a pattern.
+///
+/// Patterns use the syntax of the regex crate, which is close to
+/// `git grep -E`.
+#[derive(Debug)]
+pub struct Matcher {
+    regex: Regex,
+    invert: bool,
+}
+
+impl Matcher {
+    pub fn new(pattern: &str, fixed: bool, ignore_case: bool, invert: bool) -> Result<Matcher> {
+        let pattern = if fixed {
+            regex::escape(pattern)
+        } else {
+            pattern.to_string()
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|e| anyhow!("Invalid pattern '{pattern}': {e}"))?;
+        Ok(Matcher { regex, invert })
+    }
+
+    /// Returns the matching lines with their 1-based line numbers, without
+    /// their line endings.
+    pub fn matching_lines<'a>(&self, content: &'a [u8]) -> Vec<(usize, &'a [u8])> {
+        let content = content.strip_suffix(b"\n").unwrap_or(content);
+        if content.is_empty() {
+            return vec![];
+        }
+        content
+            .split(|&b| b == b'\n')
+            .enumerate()
+            .filter(|(_, line)| self.regex.is_match(line) != self.invert)
+            .map(|(i, line)| (i + 1, line))
+            .collect()
+    }
+}
+
+/// Returns the number of threads set by `grep.threads`, where 0 or no value
+/// means the number of CPUs.
+pub fn thread_count(config: &Config) -> Result<usize> {
+    match config.get_int("grep.threads")? {
+        Some(threads) if threads < 0 => Err(anyhow!("Invalid number of threads: {threads}")),
+        Some(threads) if threads > 0 => Ok(threads as usize),
+        _ => Ok(std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_DEFAULT_THREADS)),
+    }
+}
+
+/// Calls `f` on all the items with at most `threads` threads, and returns the
+/// results in the order of the items.
+pub fn parallel_map<T, R, F>(items: &[T], threads: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let threads = threads.clamp(1, items.len().max(1));
+    if threads == 1 {
+        return items.iter().map(f).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<R>>> = items.iter().map(|_| Mutex::new(None)).collect();
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                *results[i].lock().unwrap() = Some(f(item));
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|result| {
+            result
+                .into_inner()
+                .unwrap()
+                .expect("all items are processed")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_lines() {
+        let content = b"foo\nbar\nFoo.bar\n";
+        let matcher = Matcher::new("foo", false, false, false).unwrap();
+        assert_eq!(matcher.matching_lines(content), [(1, &b"foo"[..])]);
+        let matcher = Matcher::new("foo", false, true, false).unwrap();
+        assert_eq!(
+            matcher.matching_lines(content),
+            [(1, &b"foo"[..]), (3, b"Foo.bar")]
+        );
+        let matcher = Matcher::new("o.b", true, true, true).unwrap();
+        assert_eq!(
+            matcher.matching_lines(content),
+            [(1, &b"foo"[..]), (2, b"bar")]
+        );
+        assert!(Matcher::new("(", false, false, false).is_err());
+    }
+
+    #[test]
+    fn test_parallel_map_keeps_order() {
+        let items: Vec<usize> = (0..100).collect();
+        for threads in [1, 4, 200] {
+            assert_eq!(
+                parallel_map(&items, threads, |i| i * 2),
+                (0..200).step_by(2).collect::<Vec<_>>()
+            );
+        }
+        assert!(parallel_map(&[] as &[usize], 4, |i| *i).is_empty());
+    }
+}
@@ -0,0 +1,559 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+use crate::config::Config;
+
+/// Who made a commit and when, as in the `author` and `committer` lines of
+/// commits and in reflogs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub email: String,
+    /// Seconds since the epoch.
+    pub timestamp: i64,
+    /// The time zone, like `+0100`.
+    pub timezone: String,
+}
+
+/// Whose identity to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn name(self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        }
+    }
+}
+
+impl Ident {
+    /// Returns the identity of the author or the committer of a new commit,
+    /// at the current time.
+    ///
+    /// Like git, the `GIT_AUTHOR_NAME`, `GIT_AUTHOR_EMAIL` and
+    /// `GIT_AUTHOR_DATE` environment variables (or their `GIT_COMMITTER_*`
+    /// equivalents) take precedence over `author.name` and `author.email` (or
+    /// `committer.*`), which take precedence over `user.name` and
+    /// `user.email`.
+    pub fn new(config: &Config, role: Role) -> Result<Ident> {
+        Ident::with_env(config, role, |key| std::env::var(key).ok())
+    }
+
+    /// Like [`Ident::new`], reading environment variables with `env`.
+    pub fn with_env(
+        config: &Config,
+        role: Role,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Ident> {
+        let role_name = role.name();
+        let env_name = role_name.to_uppercase();
+        let lookup = |field: &str| {
+            env(&format!("GIT_{env_name}_{}", field.to_uppercase()))
+                .or_else(|| {
+                    config
+                        .get(&format!("{role_name}.{field}"))
+                        .map(str::to_string)
+                })
+                .or_else(|| config.get(&format!("user.{field}")).map(str::to_string))
+        };
+        let (Some(name), Some(email)) = (lookup("name"), lookup("email")) else {
+            let mut capitalized = role_name.to_string();
+            capitalized[..1].make_ascii_uppercase();
+            return Err(anyhow!(
+                "{capitalized} identity unknown, set user.name and user.email"
+            ));
+        };
+        let (name, email) = (without_crud(&name), without_crud(&email));
+        if name.is_empty() {
+            return Err(anyhow!(
+                "name consists only of disallowed characters: {}",
+                lookup("name").unwrap_or_default()
+            ));
+        }
+        let (timestamp, timezone) = match env(&format!("GIT_{env_name}_DATE")) {
+            Some(date) => parse_date(&date)?,
+            None => {
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+                (now.as_secs() as i64, "+0000".to_string())
+            }
+        };
+        Ok(Ident {
+            name,
+            email,
+            timestamp,
+            timezone,
+        })
+    }
+
+    /// Parses an identity as written in commits, `Name <email> 1234 +0100`.
+    pub fn parse(s: &str) -> Option<Ident> {
+        let (name, rest) = s.split_once('<')?;
+        let (email, date) = rest.split_once('>')?;
+        let (timestamp, timezone) = date.trim().split_once(' ')?;
+        Some(Ident {
+            name: name.trim_end().to_string(),
+            email: email.to_string(),
+            timestamp: timestamp.parse().ok()?,
+            timezone: timezone.to_string(),
+        })
+    }
+
+    /// Formats the date in the time zone of the identity.
+    pub fn date(&self, format: DateFormat) -> String {
+        format_date(self.timestamp, &self.timezone, format)
+    }
+}
+
+/// Removes what can't be in the name or email of an identity like git: the
+/// `<`, `>` and newline characters, and the spaces and punctuation around.
+fn without_crud(s: &str) -> String {
+    let crud = |c: char| c <= ' ' || ".,:;<>\"\\'".contains(c);
+    s.trim_matches(crud)
+        .chars()
+        .filter(|c| !matches!(c, '<' | '>' | '\n'))
+        .collect()
+}
+
+/// How to show a date, like git's `--date` formats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DateFormat {
+    /// `Thu Apr 7 22:13:13 2005 +0200`.
+    #[default]
+    Default,
+    /// `Thu, 7 Apr 2005 22:13:13 +0200`.
+    Rfc2822,
+    /// `2005-04-07 22:13:13 +0200`.
+    Iso,
+    /// `2005-04-07T22:13:13+02:00`.
+    IsoStrict,
+}
+
+/// Formats a date in a time zone like `+0200`, which is treated as UTC if it's
+/// invalid.
+pub fn format_date(timestamp: i64, timezone: &str, format: DateFormat) -> String {
+    let offset = parse_timezone(timezone).unwrap_or(0);
+    let local = timestamp + offset;
+    let days = local.div_euclid(86400);
+    let seconds = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    let weekday = DAYS[(days + 4).rem_euclid(7) as usize];
+    let month_name = MONTHS[month as usize - 1];
+    let (hour, minute, second) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    let timezone = format_timezone(offset);
+    match format {
+        DateFormat::Default => format!(
+            "{weekday} {month_name} {day} {hour:02}:{minute:02}:{second:02} {year} {timezone}"
+        ),
+        DateFormat::Rfc2822 => format!(
+            "{weekday}, {day} {month_name} {year} {hour:02}:{minute:02}:{second:02} {timezone}"
+        ),
+        DateFormat::Iso => {
+            format!("{year}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02} {timezone}")
+        }
+        DateFormat::IsoStrict => format!(
+            "{year}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}{}:{}",
+            &timezone[..3],
+            &timezone[3..]
+        ),
+    }
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name, self.email, self.timestamp, self.timezone
+        )
+    }
+}
+
+/// Parses a date as accepted in `GIT_AUTHOR_DATE` and `GIT_COMMITTER_DATE`,
+/// returning the seconds since the epoch and the time zone.
+///
+/// The supported formats are git's internal format (`1112904793 +0200`,
+/// optionally prefixed with `@`), RFC 2822 (`Thu, 07 Apr 2005 22:13:13
+/// +0200`) and ISO 8601 (`2005-04-07T22:13:13+02:00`, with `T` or a space,
+/// or just `2005-04-07`). Dates without a time zone are in UTC.
+pub fn parse_date(date: &str) -> Result<(i64, String)> {
+    let invalid = || anyhow!("invalid date format: {date}");
+    let date = date.trim();
+    let (rest, timezone) = match date.rsplit_once(' ') {
+        Some((rest, timezone)) if parse_timezone(timezone).is_some() => {
+            (rest.trim_end(), parse_timezone(timezone))
+        }
+        _ => (date, None),
+    };
+
+    let raw = rest.strip_prefix('@').unwrap_or(rest);
+    if let Ok(timestamp) = raw.parse::<i64>() {
+        if rest.starts_with('@') || timezone.is_some() {
+            return Ok((timestamp, format_timezone(timezone.unwrap_or(0))));
+        }
+    }
+
+    // RFC 2822: an optional day name, then `07 Apr 2005 22:13:13`.
+    let rfc = rest.split_once(", ").map_or(rest, |(_, rest)| rest);
+    let fields: Vec<&str> = rfc.split_whitespace().collect();
+    if let [day, month, year, time] = fields[..] {
+        let month = MONTHS
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(month))
+            .ok_or_else(invalid)?;
+        let (day, year): (u32, i64) = (
+            day.parse().map_err(|_| invalid())?,
+            year.parse().map_err(|_| invalid())?,
+        );
+        let seconds = parse_time(time).ok_or_else(invalid)?;
+        let offset = timezone.unwrap_or(0);
+        let timestamp = days_from_civil(year, month as u32 + 1, day) * 86400 + seconds - offset;
+        return Ok((timestamp, format_timezone(offset)));
+    }
+
+    // ISO 8601, where the time zone may be attached to the time. A day
+    // without a time is at midnight.
+    let (day, time) = rest
+        .split_once('T')
+        .or_else(|| rest.split_once(' '))
+        .unwrap_or((rest, "00:00"));
+    let (time, timezone) = match time.find(['+', '-', 'Z']) {
+        _ if timezone.is_some() => (time, timezone),
+        Some(i) => (
+            &time[..i],
+            Some(parse_timezone(&time[i..]).ok_or_else(invalid)?),
+        ),
+        None => (time, None),
+    };
+    let mut ymd = day.splitn(3, '-').map(str::parse::<i64>);
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) = (ymd.next(), ymd.next(), ymd.next())
+    else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    let seconds = parse_time(time).ok_or_else(invalid)?;
+    let offset = timezone.unwrap_or(0);
+    let timestamp = days_from_civil(year, month as u32, day as u32) * 86400 + seconds - offset;
+    Ok((timestamp, format_timezone(offset)))
+}
+
+/// Parses a date like git's `--since` and `--until`, returning the seconds
+/// since the epoch: a date accepted by [`parse_date`], seconds since the
+/// epoch alone, `now`, `today`, `yesterday`, a time before `now` like
+/// `2 weeks ago`, `2.weeks.ago` or `last week`, or a day like `2001-09-09`
+/// or `Sep 9 2001`. Months and years ago are calendar months and years.
+///
+/// Like git, days without a time are at the time of day of `now`.
+pub fn parse_approxidate(date: &str, now: i64) -> Result<i64> {
+    let lowercase = date.trim().to_ascii_lowercase().replace('.', " ");
+    let words: Vec<&str> = lowercase.split_whitespace().collect();
+    if let Some(days) = parse_day(&words, now) {
+        return Ok(days * 86400 + now.rem_euclid(86400));
+    }
+    if let Ok((timestamp, _)) = parse_date(date) {
+        return Ok(timestamp);
+    }
+    if let Ok(timestamp) = date.trim().parse() {
+        return Ok(timestamp);
+    }
+    let invalid = || anyhow!("invalid date format: {date}");
+    let (count, unit) = match words[..] {
+        ["now"] | ["today"] => return Ok(now),
+        ["yesterday"] => return Ok(now - 86400),
+        ["last", unit] => (1, unit),
+        [count, unit, "ago"] => (count.parse::<i64>().map_err(|_| invalid())?, unit),
+        _ => return Err(invalid()),
+    };
+    let seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 3600,
+        "day" => 86400,
+        "week" => 7 * 86400,
+        unit @ ("month" | "year") => {
+            let months = match unit {
+                "month" => count,
+                _ => count * 12,
+            };
+            let (year, month, day) = civil_from_days(now.div_euclid(86400));
+            let months = year * 12 + month as i64 - 1 - months;
+            // Days past the end of the month carry over to the next one.
+            let days =
+                days_from_civil(months.div_euclid(12), months.rem_euclid(12) as u32 + 1, day);
+            return Ok(days * 86400 + now.rem_euclid(86400));
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(now - count * seconds)
+}
+
+/// Parses a day without a time, like `2001-09-09`, `Sep 9 2001` or
+/// `9 september, 2001`, into days since the epoch. Months are matched by
+/// their first three letters, and the year is the one of `now` if missing.
+fn parse_day(words: &[&str], now: i64) -> Option<i64> {
+    if let [iso] = words {
+        let mut ymd = iso.splitn(3, '-').map(str::parse::<i64>);
+        let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) = (ymd.next(), ymd.next(), ymd.next())
+        else {
+            return None;
+        };
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        return Some(days_from_civil(year, month as u32, day as u32));
+    }
+    let (mut year, mut month, mut day) = (None, None, None);
+    for word in words {
+        let word = word.trim_end_matches(',');
+        let month_name = || {
+            let name = word.get(..3)?;
+            MONTHS
+                .iter()
+                .position(|month| month.eq_ignore_ascii_case(name))
+        };
+        match word.parse::<u32>().ok() {
+            Some(number) if day.is_none() && word.len() <= 2 && (1..=31).contains(&number) => {
+                day = Some(number)
+            }
+            Some(number) if year.is_none() && word.len() == 4 => year = Some(number as i64),
+            Some(_) => return None,
+            None if month.is_none() && word.bytes().all(|b| b.is_ascii_alphabetic()) => {
+                month = Some(month_name()? as u32 + 1)
+            }
+            None => return None,
+        }
+    }
+    let year = year.unwrap_or_else(|| civil_from_days(now.div_euclid(86400)).0);
+    Some(days_from_civil(year, month?, day?))
+}
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Parses `HH:MM[:SS]` into seconds since midnight.
+fn parse_time(time: &str) -> Option<i64> {
+    let mut fields = time.split(':').map(str::parse::<i64>);
+    let hours = fields.next()?.ok()?;
+    let minutes = fields.next()?.ok()?;
+    let seconds = fields.next().unwrap_or(Ok(0)).ok()?;
+    if fields.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Parses a time zone like `+0200`, `-02:00` or `Z` into seconds east of UTC.
+fn parse_timezone(timezone: &str) -> Option<i64> {
+    if timezone == "Z" {
+        return Some(0);
+    }
+    let sign = match timezone.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits = timezone[1..].replace(':', "");
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+fn format_timezone(offset: i64) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    format!("{sign}{:02}{:02}", minutes / 60, minutes % 60)
+}
+
+/// Returns the number of days between the epoch and a date of the proleptic
+/// Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the year, month and day of a number of days since the epoch, the
+/// inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        let expected = (1112904793, "+0200".to_string());
+        assert_eq!(parse_date("1112904793 +0200").unwrap(), expected);
+        assert_eq!(parse_date("@1112904793 +0200").unwrap(), expected);
+        assert_eq!(
+            parse_date("Thu, 07 Apr 2005 22:13:13 +0200").unwrap(),
+            expected
+        );
+        assert_eq!(parse_date("2005-04-07T22:13:13+02:00").unwrap(), expected);
+        assert_eq!(parse_date("2005-04-07 22:13:13 +0200").unwrap(), expected);
+        assert_eq!(
+            parse_date("2005-04-07T20:13:13Z").unwrap(),
+            (1112904793, "+0000".to_string())
+        );
+        assert_eq!(parse_date("@0").unwrap(), (0, "+0000".to_string()));
+        assert_eq!(
+            parse_date("1970-01-01 00:00:00 -0130").unwrap(),
+            (5400, "-0130".to_string())
+        );
+        assert_eq!(
+            parse_date("2005-04-07").unwrap(),
+            (1112832000, "+0000".to_string())
+        );
+        assert!(parse_date("yesterday").is_err());
+        assert!(parse_date("2005-13-07 22:13:13").is_err());
+    }
+
+    #[test]
+    fn test_parse_approxidate() {
+        // Thu Apr 7 20:13:13 2005 UTC.
+        let now = 1112904793;
+        assert_eq!(parse_approxidate("2005-04-07", now).unwrap(), now);
+        assert_eq!(
+            parse_approxidate("2005-04-07 00:00", now).unwrap(),
+            1112832000
+        );
+        assert_eq!(
+            parse_approxidate("Sep 9 2001", now).unwrap(),
+            parse_date("2001-09-09 20:13:13").unwrap().0
+        );
+        assert_eq!(
+            parse_approxidate("9 September, 2001", now).unwrap(),
+            parse_date("2001-09-09 20:13:13").unwrap().0
+        );
+        assert_eq!(
+            parse_approxidate("jan 2", now).unwrap(),
+            parse_date("2005-01-02 20:13:13").unwrap().0
+        );
+        assert_eq!(parse_approxidate("last week", now).unwrap(), now - 604800);
+        assert_eq!(parse_approxidate("now", now).unwrap(), now);
+        assert_eq!(parse_approxidate("1112904793", 0).unwrap(), now);
+        assert_eq!(parse_approxidate("yesterday", now).unwrap(), now - 86400);
+        assert_eq!(
+            parse_approxidate("2 weeks ago", now).unwrap(),
+            now - 1209600
+        );
+        assert_eq!(parse_approxidate("1.hour.ago", now).unwrap(), now - 3600);
+        assert_eq!(
+            parse_approxidate("1 month ago", now).unwrap(),
+            parse_date("2005-03-07 20:13:13").unwrap().0
+        );
+        assert_eq!(
+            parse_approxidate("2 years ago", now).unwrap(),
+            parse_date("2003-04-07 20:13:13").unwrap().0
+        );
+        // March 31st a month ago is March 3rd.
+        let now = parse_date("2005-03-31 12:00:00").unwrap().0;
+        assert_eq!(
+            parse_approxidate("1 month ago", now).unwrap(),
+            parse_date("2005-03-03 12:00:00").unwrap().0
+        );
+        assert!(parse_approxidate("2 fortnights ago", now).is_err());
+        assert!(parse_approxidate("someday", now).is_err());
+    }
+
+    #[test]
+    fn test_format_date() {
+        let ident = Ident::parse("Alice <alice@example.com> 1112904793 +0200").unwrap();
+        assert_eq!(ident.name, "Alice");
+        assert_eq!(ident.email, "alice@example.com");
+        assert_eq!(
+            ident.date(DateFormat::Default),
+            "Thu Apr 7 22:13:13 2005 +0200"
+        );
+        assert_eq!(
+            ident.date(DateFormat::Rfc2822),
+            "Thu, 7 Apr 2005 22:13:13 +0200"
+        );
+        assert_eq!(ident.date(DateFormat::Iso), "2005-04-07 22:13:13 +0200");
+        assert_eq!(
+            ident.date(DateFormat::IsoStrict),
+            "2005-04-07T22:13:13+02:00"
+        );
+        assert_eq!(
+            format_date(0, "-0130", DateFormat::IsoStrict),
+            "1969-12-31T22:30:00-01:30"
+        );
+        assert_eq!(
+            format_date(951782400, "+0000", DateFormat::IsoStrict),
+            "2000-02-29T00:00:00+00:00"
+        );
+        assert_eq!(Ident::parse("Alice alice@example.com 0 +0000"), None);
+    }
+
+    #[test]
+    fn test_ident() {
+        let config = Config::parse(
+            "[user]\n\tname = Alice\n\temail = alice@example.com\n\
+             [committer]\n\tname = Carol\n",
+        )
+        .unwrap();
+        let env = |key: &str| match key {
+            "GIT_AUTHOR_EMAIL" => Some("bob@example.com".to_string()),
+            "GIT_AUTHOR_DATE" | "GIT_COMMITTER_DATE" => Some("@1234 +0100".to_string()),
+            _ => None,
+        };
+        let author = Ident::with_env(&config, Role::Author, env).unwrap();
+        assert_eq!(author.to_string(), "Alice <bob@example.com> 1234 +0100");
+        let committer = Ident::with_env(&config, Role::Committer, env).unwrap();
+        assert_eq!(
+            committer.to_string(),
+            "Carol <alice@example.com> 1234 +0100"
+        );
+
+        let config = Config::parse("[user]\n\tname = Alice\n").unwrap();
+        assert_eq!(
+            Ident::with_env(&config, Role::Committer, |_| None)
+                .unwrap_err()
+                .to_string(),
+            "Committer identity unknown, set user.name and user.email"
+        );
+
+        let config =
+            Config::parse("[user]\n\tname = \" A <x> B.\"\n\temail = <a@example.com>\n").unwrap();
+        let author = Ident::with_env(&config, Role::Author, env).unwrap();
+        assert_eq!(author.name, "A x B");
+        let committer = Ident::with_env(&config, Role::Committer, env).unwrap();
+        assert_eq!(committer.email, "a@example.com");
+        let config = Config::parse("[user]\n\tname = <>\n\temail = a@example.com\n").unwrap();
+        assert_eq!(
+            Ident::with_env(&config, Role::Author, |_| None)
+                .unwrap_err()
+                .to_string(),
+            "name consists only of disallowed characters: <>"
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{self, Config},
+    index::FileModes,
+    repo::Repo,
+    wildmatch::{wildmatch, Flags},
+};
+
+/// A line of an ignore file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    /// The line as written, for `check-ignore -v`.
+    pub text: String,
+    /// The file the pattern comes from, as shown by `check-ignore -v`.
+    pub source: String,
+    /// The line number in the file, starting at 1.
+    pub line: usize,
+    /// Whether the pattern starts with `!` and re-includes paths.
+    pub negated: bool,
+    /// The pattern without `!`, the trailing `/` and the leading `/`.
+    pattern: String,
+    /// Whether the pattern ends with `/` and only matches directories.
+    dir_only: bool,
+    /// Whether the pattern has a `/` other than a trailing one, so that it's
+    /// matched against the path relative to `base` instead of the file name.
+    anchored: bool,
+    /// The directory of the `.gitignore` file, with a trailing `/`, or empty
+    /// for the root of the working tree and the other ignore files.
+    base: String,
+}
+
+impl Pattern {
+    /// Parses a line of an ignore file. Returns `None` for blank lines and
+    /// comments.
+    fn parse(line: &str, source: &str, number: usize, base: &str) -> Option<Pattern> {
+        if line.starts_with('#') {
+            return None;
+        }
+        let text = trim_trailing_spaces(line);
+        if text.is_empty() {
+            return None;
+        }
+        let (negated, mut pattern) = match text.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            // `\!` and `\#` start patterns with a literal `!` or `#`.
+            None => (
+                false,
+                text.strip_prefix('\\')
+                    .filter(|rest| rest.starts_with(['!', '#']))
+                    .unwrap_or(text),
+            ),
+        };
+        let dir_only = pattern.ends_with('/');
+        pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(Pattern {
+            text: text.to_string(),
+            source: source.to_string(),
+            line: number,
+            negated,
+            pattern: pattern.to_string(),
+            dir_only,
+            anchored,
+            base: base.to_string(),
+        })
+    }
+
+    /// Returns whether the pattern matches a path relative to the root of the
+    /// working tree, ignoring negation, and the case of letters with
+    /// `ignore_case`.
+    pub fn matches(&self, path: &str, is_dir: bool, ignore_case: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let base = self.base.as_bytes();
+        let relative = match path.as_bytes().get(..base.len()) {
+            Some(prefix) if prefix == base => &path[base.len()..],
+            Some(prefix) if ignore_case && prefix.eq_ignore_ascii_case(base) => &path[base.len()..],
+            _ => return false,
+        };
+        let flags = Flags {
+            casefold: ignore_case,
+            ..Flags::PATHNAME
+        };
+        if self.anchored {
+            wildmatch(self.pattern.as_bytes(), relative.as_bytes(), flags)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(self.pattern.as_bytes(), name.as_bytes(), flags)
+        }
+    }
+}
+
+/// Removes the trailing spaces of a line, except a space escaped with `\`.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end_matches([' ', '\t', '\r']);
+    if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    }
+}
+
+/// The rules that decide which untracked files are ignored: `.gitignore`
+/// files in the working tree, `.git/info/exclude` and `core.excludesFile`.
+///
+/// A `.gitignore` file applies to the files of its directory and below, and
+/// takes precedence over the ones of the parent directories. When a pattern
+/// matches a directory, everything in it is ignored and can't be re-included
+/// with `!`.
+///
+/// The `.gitignore` files are read lazily, when a path in their directory is
+/// checked.
+#[derive(Debug, Default)]
+pub struct Ignore {
+    root: std::path::PathBuf,
+    /// The patterns of `core.excludesFile` and `.git/info/exclude`, in order
+    /// of increasing precedence.
+    global: Vec<Pattern>,
+    /// The patterns of the `.gitignore` file of each directory that was
+    /// read, keyed by directory with a trailing `/`, or empty for the root.
+    dirs: HashMap<String, Vec<Pattern>>,
+    /// Whether patterns match paths whatever their case, with
+    /// `core.ignoreCase`.
+    ignore_case: bool,
+}
+
+impl Ignore {
+    pub fn load(repo: &Repo) -> Result<Ignore> {
+        let config = Config::load(repo)?;
+        let mut ignore = Ignore {
+            root: repo.root.clone(),
+            ignore_case: FileModes::from_config(&config)?.ignore_case,
+            ..Default::default()
+        };
+        if let Some(path) = excludes_file(&config) {
+            ignore
+                .global
+                .extend(read_patterns(&path, &path.display().to_string(), "")?);
+        }
+        let exclude = repo.common_dir().join("info/exclude");
+        ignore
+            .global
+            .extend(read_patterns(&exclude, ".git/info/exclude", "")?);
+        Ok(ignore)
+    }
+
+    /// Returns the pattern that decides whether a path is ignored, which is
+    /// negated if the path is re-included, or `None` if no pattern matches.
+    ///
+    /// If a parent directory of the path is ignored, the pattern that
+    /// ignores it is returned.
+    pub fn matching(&mut self, path: &str, is_dir: bool) -> Result<Option<Pattern>> {
+        let path = path.trim_end_matches('/');
+        for (i, _) in path.match_indices('/') {
+            if let Some(pattern) = self.last_match(&path[..i], true)? {
+                if !pattern.negated {
+                    return Ok(Some(pattern));
+                }
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    /// Returns whether a path is ignored, see [`Ignore::matching`].
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> Result<bool> {
+        Ok(self
+            .matching(path, is_dir)?
+            .is_some_and(|pattern| !pattern.negated))
+    }
+
+    /// Returns the pattern with the highest precedence that matches a path,
+    /// without looking at its parent directories.
+    fn last_match(&mut self, path: &str, is_dir: bool) -> Result<Option<Pattern>> {
+        let mut dirs = vec![String::new()];
+        dirs.extend(
+            path.match_indices('/')
+                .map(|(i, _)| format!("{}/", &path[..i])),
+        );
+        for dir in &dirs {
+            if !self.dirs.contains_key(dir) {
+                let source = format!("{dir}.gitignore");
+                let patterns = read_patterns(&self.root.join(&source), &source, dir)?;
+                self.dirs.insert(dir.clone(), patterns);
+            }
+        }
+        let found = dirs
+            .iter()
+            .rev()
+            .flat_map(|dir| self.dirs[dir].iter().rev())
+            .chain(self.global.iter().rev())
+            .find(|pattern| pattern.matches(path, is_dir, self.ignore_case));
+        Ok(found.cloned())
+    }
+}
+
+/// Returns the global exclude file: `core.excludesFile`, which defaults to
+/// `$XDG_CONFIG_HOME/git/ignore`.
+pub fn excludes_file(config: &Config) -> Option<PathBuf> {
+    config
+        .get_path("core.excludesFile")
+        .or_else(|| match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(xdg_config_home) => Some(Path::new(&xdg_config_home).join("git/ignore")),
+            None => config::home_dir().map(|home| home.join(".config/git/ignore")),
+        })
+}
+
+/// Reads the patterns of an ignore file, which may not exist.
+fn read_patterns(path: &Path, source: &str, base: &str) -> Result<Vec<Pattern>> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
+            return Ok(vec![])
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(String::from_utf8_lossy(&content)
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| Pattern::parse(line, source, i + 1, base))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = tmpdir.path();
+        fs::write(
+            root.join(".gitignore"),
+            "*.log\n# Comment\n\\#hash\nbuild/\n/top\ntrailing\\ \n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("sub/deep")).unwrap();
+        fs::write(root.join("sub/.gitignore"), "!keep.log\ndeep/*.txt\n").unwrap();
+        let mut ignore = Ignore {
+            root: root.to_path_buf(),
+            global: vec![Pattern::parse("*.tmp", "exclude", 1, "").unwrap()],
+            dirs: HashMap::new(),
+            ignore_case: false,
+        };
+
+        let source = |ignore: &mut Ignore, path: &str, is_dir: bool| {
+            ignore
+                .matching(path, is_dir)
+                .unwrap()
+                .map(|pattern| format!("{}:{}:{}", pattern.source, pattern.line, pattern.text))
+        };
+        assert_eq!(
+            source(&mut ignore, "a.log", false).as_deref(),
+            Some(".gitignore:1:*.log")
+        );
+        assert_eq!(
+            source(&mut ignore, "x/a.log", false).as_deref(),
+            Some(".gitignore:1:*.log")
+        );
+        assert_eq!(
+            source(&mut ignore, "sub/keep.log", false).as_deref(),
+            Some("sub/.gitignore:1:!keep.log")
+        );
+        assert!(!ignore.is_ignored("sub/keep.log", false).unwrap());
+        assert!(ignore.is_ignored("#hash", false).unwrap());
+        assert!(ignore.is_ignored("trailing ", false).unwrap());
+        assert!(ignore.is_ignored("a.tmp", false).unwrap());
+        // Directory-only patterns.
+        assert!(ignore.is_ignored("build", true).unwrap());
+        assert!(!ignore.is_ignored("build", false).unwrap());
+        // Files in an ignored directory can't be re-included.
+        assert_eq!(
+            source(&mut ignore, "build/keep.log", false).as_deref(),
+            Some(".gitignore:4:build/")
+        );
+        // Anchored patterns are relative to their `.gitignore`.
+        assert!(ignore.is_ignored("top", false).unwrap());
+        assert!(!ignore.is_ignored("sub/top", false).unwrap());
+        assert!(ignore.is_ignored("sub/deep/a.txt", false).unwrap());
+        assert!(!ignore.is_ignored("deep/a.txt", false).unwrap());
+        assert_eq!(source(&mut ignore, "other", false), None);
+        // With core.ignoreCase, the case of letters doesn't matter.
+        assert!(!ignore.is_ignored("A.LOG", false).unwrap());
+        ignore.ignore_case = true;
+        assert!(ignore.is_ignored("A.LOG", false).unwrap());
+        assert!(ignore.is_ignored("sub/DEEP/a.TXT", false).unwrap());
+        assert!(ignore.is_ignored("BUILD", true).unwrap());
+    }
+}
@@ -0,0 +1,1404 @@
+use anyhow::{anyhow, Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    io::prelude::*,
+    ops::{Deref, DerefMut, Range},
+    path::Path,
+};
+
+use crate::{
+    cache_tree::CacheTree,
+    config::Config,
+    ewah,
+    filter::Filters,
+    lockfile::LockFile,
+    object::hash,
+    repo::Repo,
+    untracked_cache::{StatData, UntrackedCache},
+};
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+const CACHE_TREE_SIGNATURE: &[u8; 4] = b"TREE";
+const LINK_SIGNATURE: &[u8; 4] = b"link";
+const UNTRACKED_CACHE_SIGNATURE: &[u8; 4] = b"UNTR";
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const EXTENDED_FLAG_SKIP_WORKTREE: u16 = 0x4000;
+const EXTENDED_FLAG_INTENT_TO_ADD: u16 = 0x2000;
+
+/// How deeply the directories of index extensions may be nested. Paths are
+/// at most 4096 bytes long, so real directories can't be nested deeper, and
+/// the limit keeps corrupt extensions from overflowing the stack.
+pub(crate) const MAX_DIR_DEPTH: usize = 2048;
+
+/// The index (also called the staging area or dircache) at `.git/index`.
+///
+/// See https://git-scm.com/docs/index-format for the format.
+#[derive(Debug, PartialEq)]
+pub struct Index {
+    pub version: u32,
+    /// The entries, sorted by path and stage like in the file, which
+    /// [`Index::add`] and [`Index::remove`] rely on to search them.
+    pub entries: Vec<IndexEntry>,
+    /// The `TREE` extension. Must be invalidated when entries change, see
+    /// [`Index::add`] and [`Index::remove`].
+    pub cache_tree: Option<CacheTree>,
+    /// The `UNTR` extension, invalidated like the cache tree.
+    pub untracked_cache: Option<UntrackedCache>,
+    /// The shared index, if this is a split index.
+    pub shared_index: Option<SharedIndex>,
+    /// Extensions that aren't understood, kept as (signature, data) so they
+    /// can be written back.
+    pub extensions: Vec<([u8; 4], Vec<u8>)>,
+}
+
+/// The shared index a split index is based on, see `core.splitIndex`.
+///
+/// A split index only stores the entries that changed since the shared index,
+/// which is stored at `.git/sharedindex.<hash>`. When the index is written,
+/// it's written as a split index again, and the shared index is left as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedIndex {
+    /// The checksum of the shared index file.
+    pub hash: String,
+    pub entries: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub ctime_seconds: u32,
+    pub ctime_nanoseconds: u32,
+    pub mtime_seconds: u32,
+    pub mtime_nanoseconds: u32,
+    pub dev: u32,
+    pub ino: u32,
+    /// Either 0o100644, 0o100755, 0o120000 (symlink) or 0o160000 (gitlink).
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// File size, truncated to 32 bits.
+    pub size: u32,
+    pub hash: String,
+    /// Set by `update-index --assume-unchanged`.
+    pub assume_valid: bool,
+    /// 0 normally, 1-3 for the base, ours and theirs versions during a merge.
+    pub stage: u8,
+    /// Extended flag (index version 3 and later), set by `update-index
+    /// --skip-worktree` for files outside of a sparse checkout.
+    pub skip_worktree: bool,
+    /// Extended flag (index version 3 and later), set by `add -N`.
+    pub intent_to_add: bool,
+    pub path: String,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index {
+            version: 2,
+            entries: vec![],
+            cache_tree: None,
+            untracked_cache: None,
+            shared_index: None,
+            extensions: vec![],
+        }
+    }
+}
+
+impl Index {
+    /// Parses an index. Use [`Index::from_file`] for split indexes, which
+    /// need to read the shared index next to them.
+    pub fn from_bytes(s: &[u8]) -> Result<Index> {
+        Index::parse(s, None)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Index> {
+        let data = std::fs::read(path).context("Could not read index")?;
+        Index::parse(&data, path.parent())
+    }
+
+    fn parse(s: &[u8], dir: Option<&Path>) -> Result<Index> {
+        if s.len() < 12 + 20 {
+            return Err(anyhow!("Index file is too short"));
+        }
+        let (content, checksum) = s.split_at(s.len() - 20);
+        if hash(content) != hex::encode(checksum) {
+            return Err(anyhow!("Index checksum mismatch"));
+        }
+
+        let mut reader = content;
+        let mut signature = [0_u8; 4];
+        reader.read_exact(&mut signature)?;
+        if &signature != SIGNATURE {
+            return Err(anyhow!("Invalid index signature"));
+        }
+        let version = read_u32(&mut reader)?;
+        if !(2..=4).contains(&version) {
+            return Err(anyhow!("Unsupported index version: {version}"));
+        }
+        let entry_count = read_u32(&mut reader)?;
+
+        let mut entries: Vec<IndexEntry> = vec![];
+        for _ in 0..entry_count {
+            let previous_path = entries.last().map_or("", |entry| entry.path.as_str());
+            let entry = IndexEntry::from_reader(&mut reader, version, previous_path)?;
+            entries.push(entry);
+        }
+
+        let mut cache_tree = None;
+        let mut untracked_cache = None;
+        let mut shared_index = None;
+        let mut extensions = vec![];
+        while !reader.is_empty() {
+            let mut signature = [0_u8; 4];
+            reader
+                .read_exact(&mut signature)
+                .context("Failed to read extension signature")?;
+            let size = read_u32(&mut reader)? as usize;
+            let data = reader
+                .get(..size)
+                .ok_or(anyhow!("Index extension is truncated"))?;
+            if &signature == CACHE_TREE_SIGNATURE {
+                cache_tree = Some(CacheTree::from_bytes(data)?);
+            } else if &signature == UNTRACKED_CACHE_SIGNATURE {
+                untracked_cache = Some(UntrackedCache::from_bytes(data)?);
+            } else if &signature == LINK_SIGNATURE {
+                let dir = dir.ok_or(anyhow!("Reading a split index needs its directory"))?;
+                let shared;
+                (shared, entries) = read_split_index(data, dir, entries)?;
+                shared_index = shared;
+            } else {
+                extensions.push((signature, data.to_vec()));
+            }
+            reader = &reader[size..];
+        }
+
+        Ok(Index {
+            version,
+            entries,
+            cache_tree,
+            untracked_cache,
+            shared_index,
+            extensions,
+        })
+    }
+
+    /// Serializes the index, with entries sorted by path and stage as git expects.
+    ///
+    /// Like git, a version 2 index is written as version 3 if any entry uses
+    /// extended flags.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        if !(2..=4).contains(&self.version) {
+            return Err(anyhow!("Unsupported index version: {}", self.version));
+        }
+        let version = if self.version == 2 && self.entries.iter().any(IndexEntry::is_extended) {
+            3
+        } else {
+            self.version
+        };
+        let mut entries: Vec<&IndexEntry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| {
+            a.path
+                .as_bytes()
+                .cmp(b.path.as_bytes())
+                .then(a.stage.cmp(&b.stage))
+        });
+        let mut extensions = vec![];
+        let split_entries;
+        if let Some(shared_index) = &self.shared_index {
+            let link;
+            (split_entries, link) = split_entries_for(shared_index, &entries)?;
+            entries = split_entries.iter().collect();
+            extensions.push((*LINK_SIGNATURE, link));
+        }
+        // Like git, the cache tree comes right after the link to the shared index.
+        if let Some(cache_tree) = &self.cache_tree {
+            extensions.push((*CACHE_TREE_SIGNATURE, cache_tree.to_bytes()));
+        }
+        if let Some(untracked_cache) = &self.untracked_cache {
+            extensions.push((*UNTRACKED_CACHE_SIGNATURE, untracked_cache.to_bytes()));
+        }
+        extensions.extend(self.extensions.iter().cloned());
+
+        let mut s = SIGNATURE.to_vec();
+        s.extend(version.to_be_bytes());
+        s.extend(u32::try_from(entries.len())?.to_be_bytes());
+        let mut previous_path = "";
+        for entry in entries {
+            entry.write_to(&mut s, version, previous_path)?;
+            previous_path = &entry.path;
+        }
+        for (signature, data) in &extensions {
+            s.extend(signature);
+            s.extend(u32::try_from(data.len())?.to_be_bytes());
+            s.extend(data);
+        }
+        let checksum = hex::decode(hash(&s))?;
+        s.extend(checksum);
+        Ok(s)
+    }
+
+    /// Writes the index of a repository.
+    ///
+    /// The index is written to `index.lock` which then replaces the index, so
+    /// other git clients never see a partially written index.
+    pub fn write(&self, repo: &Repo) -> Result<()> {
+        let mut lock = LockFile::acquire(&repo.git_dir().join("index"))?;
+        lock.write_all(&self.to_bytes()?)?;
+        lock.commit()
+    }
+
+    /// Adds an entry, replacing any entry with the same path and stage.
+    ///
+    /// Returns whether the entry is new or its content or mode changed. When
+    /// only the stat data changed, the caches are kept.
+    pub fn add(&mut self, entry: IndexEntry) -> bool {
+        let position = self.entries.binary_search_by(|e| {
+            e.path
+                .as_bytes()
+                .cmp(entry.path.as_bytes())
+                .then(e.stage.cmp(&entry.stage))
+        });
+        match position {
+            Ok(i) if self.entries[i].hash == entry.hash && self.entries[i].mode == entry.mode => {
+                self.entries[i] = entry;
+                false
+            }
+            Ok(i) => {
+                self.invalidate_path(&entry.path);
+                self.entries[i] = entry;
+                true
+            }
+            Err(i) => {
+                self.invalidate_path(&entry.path);
+                self.entries.insert(i, entry);
+                true
+            }
+        }
+    }
+
+    /// Returns the entries for a path, at any stage.
+    pub fn entries_for(&self, path: &str) -> &[IndexEntry] {
+        &self.entries[self.path_range(path)]
+    }
+
+    /// Returns the range of the entries for a path, at any stage.
+    fn path_range(&self, path: &str) -> Range<usize> {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.path.as_bytes() < path.as_bytes());
+        let len = self.entries[start..].partition_point(|entry| entry.path == path);
+        start..start + len
+    }
+
+    /// Returns a case-insensitive lookup of the paths of the entries, for
+    /// case-insensitive file systems.
+    pub fn aliases(&self) -> Aliases {
+        let mut aliases = Aliases::default();
+        for entry in &self.entries {
+            let folded = entry.path.to_ascii_lowercase();
+            for (i, _) in entry.path.match_indices('/') {
+                aliases
+                    .dirs
+                    .entry(folded[..=i].to_string())
+                    .or_insert_with(|| entry.path[..=i].to_string());
+            }
+            aliases
+                .paths
+                .entry(folded)
+                .or_insert_with(|| entry.path.clone());
+            aliases.exact.insert(entry.path.clone());
+        }
+        aliases
+    }
+
+    /// Returns the modes of the merged entries by path, see
+    /// [`FileModes::entry`].
+    pub fn modes(&self) -> HashMap<String, u32> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.stage == 0)
+            .map(|entry| (entry.path.clone(), entry.mode))
+            .collect()
+    }
+
+    /// Removes all the entries for a path, at any stage. Returns whether any
+    /// entry was removed.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let range = self.path_range(path);
+        if range.is_empty() {
+            return false;
+        }
+        self.entries.drain(range);
+        self.invalidate_path(path);
+        true
+    }
+
+    /// Removes all the entries below a directory. Returns whether any entry
+    /// was removed.
+    pub fn remove_dir(&mut self, dir: &str) -> bool {
+        let prefix = format!("{dir}/");
+        let start = self
+            .entries
+            .partition_point(|entry| entry.path.as_bytes() < prefix.as_bytes());
+        let len = self.entries[start..].partition_point(|entry| entry.path.starts_with(&prefix));
+        if len == 0 {
+            return false;
+        }
+        let paths: Vec<String> = self
+            .entries
+            .drain(start..start + len)
+            .map(|entry| entry.path)
+            .collect();
+        for path in paths {
+            self.invalidate_path(&path);
+        }
+        true
+    }
+
+    fn invalidate_path(&mut self, path: &str) {
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate_path(path);
+        }
+        if let Some(untracked_cache) = &mut self.untracked_cache {
+            untracked_cache.invalidate_path(path);
+        }
+    }
+
+    /// Writes the tree objects for the index and returns the hash of the
+    /// root tree.
+    ///
+    /// Directories whose cache tree node is still valid aren't written
+    /// again, and the cache tree is updated with the new trees.
+    pub fn write_tree(&mut self, repo: &Repo) -> Result<String> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.stage != 0) {
+            return Err(anyhow!("{}: unmerged entry", entry.path));
+        }
+        let mut entries: Vec<&IndexEntry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        self.cache_tree
+            .get_or_insert_with(CacheTree::default)
+            .update(repo, &entries)
+    }
+
+    /// Reads the index of a repository, which is empty if there is no index yet.
+    pub fn read(repo: &Repo) -> Result<Index> {
+        let path = repo.git_dir().join("index");
+        if !path.exists() {
+            return Ok(Index::default());
+        }
+        Index::from_file(&path)
+    }
+
+    /// Takes `index.lock`, then reads the index of a repository to change it
+    /// and write it back with [`LockedIndex::write`].
+    ///
+    /// Unlike reading the index and then writing it, no other git client can
+    /// change the index in between. The lock is removed if the index is
+    /// dropped without being written.
+    pub fn lock(repo: &Repo) -> Result<LockedIndex> {
+        let lock = LockFile::acquire(&repo.git_dir().join("index"))?;
+        Ok(LockedIndex {
+            index: Index::read(repo)?,
+            lock,
+        })
+    }
+}
+
+/// An index read while holding `index.lock`, see [`Index::lock`].
+pub struct LockedIndex {
+    index: Index,
+    lock: LockFile,
+}
+
+impl LockedIndex {
+    /// Writes the index and releases the lock.
+    pub fn write(mut self) -> Result<()> {
+        self.lock.write_all(&self.index.to_bytes()?)?;
+        self.lock.commit()
+    }
+}
+
+impl Deref for LockedIndex {
+    type Target = Index;
+
+    fn deref(&self) -> &Index {
+        &self.index
+    }
+}
+
+impl DerefMut for LockedIndex {
+    fn deref_mut(&mut self) -> &mut Index {
+        &mut self.index
+    }
+}
+
+/// The paths of an index by case, see [`Index::aliases`].
+#[derive(Debug, Default)]
+pub struct Aliases {
+    exact: HashSet<String>,
+    /// The first path of each lowercased path.
+    paths: HashMap<String, String>,
+    /// The first directory, with its trailing slash, of each lowercased one.
+    dirs: HashMap<String, String>,
+}
+
+impl Aliases {
+    /// Returns whether an entry has exactly this path.
+    pub fn contains(&self, path: &str) -> bool {
+        self.exact.contains(path)
+    }
+
+    /// Returns the path of an entry that only differs from `path` by case.
+    /// A new file in a directory with entries gets the case of their
+    /// directory, like git.
+    pub fn get(&self, path: &str) -> Option<String> {
+        if self.contains(path) {
+            return None;
+        }
+        let folded = path.to_ascii_lowercase();
+        if let Some(alias) = self.paths.get(&folded) {
+            return Some(alias.clone());
+        }
+        for (i, _) in path.rmatch_indices('/') {
+            if let Some(dir) = self.dirs.get(&folded[..=i]) {
+                let alias = format!("{dir}{}", &path[i + 1..]);
+                return (alias != path).then_some(alias);
+            }
+        }
+        None
+    }
+}
+
+/// What the file system of the working tree supports, from `core.fileMode`,
+/// `core.symlinks` and `core.ignoreCase`, which `init` sets when it doesn't.
+///
+/// Without executable bits, files keep the mode of their index entry, and
+/// without symlinks, they are checked out as files holding their target,
+/// which keep the symlink mode too. On case-insensitive file systems, files
+/// are matched with their index entry whatever their case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileModes {
+    pub executable_bit: bool,
+    pub symlinks: bool,
+    pub ignore_case: bool,
+}
+
+impl Default for FileModes {
+    fn default() -> FileModes {
+        FileModes {
+            executable_bit: true,
+            symlinks: cfg!(unix),
+            ignore_case: false,
+        }
+    }
+}
+
+impl FileModes {
+    pub fn from_config(config: &Config) -> Result<FileModes> {
+        let default = FileModes::default();
+        Ok(FileModes {
+            executable_bit: config
+                .get_bool("core.fileMode")?
+                .unwrap_or(default.executable_bit),
+            symlinks: config
+                .get_bool("core.symlinks")?
+                .unwrap_or(default.symlinks),
+            ignore_case: config
+                .get_bool("core.ignoreCase")?
+                .unwrap_or(default.ignore_case),
+        })
+    }
+
+    pub fn load(repo: &Repo) -> Result<FileModes> {
+        FileModes::from_config(&Config::load(repo)?)
+    }
+
+    /// Returns the index mode of a file, given the mode of its entry if it's
+    /// tracked, like git's `ce_mode_from_stat`.
+    pub fn mode(&self, metadata: &std::fs::Metadata, tracked: Option<u32>) -> u32 {
+        let mode = file_mode(metadata);
+        match tracked {
+            Some(0o120000) if !self.symlinks && metadata.is_file() => 0o120000,
+            Some(tracked @ (0o100644 | 0o100755)) if !self.executable_bit && metadata.is_file() => {
+                tracked
+            }
+            _ if !self.executable_bit && metadata.is_file() => 0o100644,
+            _ => mode,
+        }
+    }
+
+    /// Returns an entry for a file in the working tree like
+    /// [`IndexEntry::from_metadata`], with the mode of its current entry, as
+    /// in [`Index::modes`], for what the file system doesn't support.
+    pub fn entry(
+        &self,
+        tracked: &HashMap<String, u32>,
+        path: &str,
+        hash: &str,
+        metadata: &std::fs::Metadata,
+    ) -> IndexEntry {
+        IndexEntry {
+            mode: self.mode(metadata, tracked.get(path).copied()),
+            ..IndexEntry::from_metadata(path, hash, metadata)
+        }
+    }
+
+    /// Detects what the file system of a directory supports, by creating
+    /// files in it.
+    pub fn probe(dir: &Path) -> Result<FileModes> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let path = dir.join("probe-file-modes");
+            std::fs::write(&path, "")?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+            let executable_bit = std::fs::metadata(&path)?.permissions().mode() & 0o100 != 0;
+            let ignore_case = dir.join("PROBE-FILE-MODES").exists();
+            std::fs::remove_file(&path)?;
+            let symlinks = std::os::unix::fs::symlink("target", &path).is_ok();
+            let _ = std::fs::remove_file(&path);
+            Ok(FileModes {
+                executable_bit,
+                symlinks,
+                ignore_case,
+            })
+        }
+        #[cfg(not(unix))]
+        {
+            let path = dir.join("probe-file-modes");
+            std::fs::write(&path, "")?;
+            let ignore_case = dir.join("PROBE-FILE-MODES").exists();
+            std::fs::remove_file(&path)?;
+            Ok(FileModes {
+                executable_bit: false,
+                symlinks: false,
+                ignore_case,
+            })
+        }
+    }
+}
+
+/// Returns the index mode of a file: a symlink, an executable or a regular
+/// file.
+pub fn file_mode(metadata: &std::fs::Metadata) -> u32 {
+    if metadata.is_symlink() {
+        return 0o120000;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return 0o100755;
+        }
+    }
+    0o100644
+}
+
+/// Returns the hash of the file of `path` in the working tree as a blob,
+/// without writing it, once converted by its clean filter like `git add`
+/// does. The content of a symlink is its target.
+pub fn worktree_blob_hash(
+    filters: &Filters,
+    path: &str,
+    full_path: &Path,
+    metadata: &std::fs::Metadata,
+) -> Result<String> {
+    let content = if metadata.is_symlink() {
+        std::fs::read_link(full_path)?
+            .to_string_lossy()
+            .into_owned()
+            .into_bytes()
+    } else {
+        filters.clean(path, std::fs::read(full_path)?)?
+    };
+    Ok(crate::object::Blob::new(content).hash())
+}
+
+/// Merges the entries of a split index with its shared index, given the data
+/// of the `link` extension.
+///
+/// The extension holds the hash of the shared index, followed by two bitmaps
+/// of positions in the shared index: the deleted entries and the replaced
+/// entries. Replacements are the first entries of the split index, in order,
+/// and have an empty path. The remaining entries are new.
+fn read_split_index(
+    data: &[u8],
+    dir: &Path,
+    entries: Vec<IndexEntry>,
+) -> Result<(Option<SharedIndex>, Vec<IndexEntry>)> {
+    let (hash_bytes, mut bitmaps) = data
+        .split_at_checked(20)
+        .ok_or(anyhow!("Index extension is truncated"))?;
+    // A null hash means that the index isn't split.
+    if hash_bytes.iter().all(|&b| b == 0) {
+        return Ok((None, entries));
+    }
+    let hash = hex::encode(hash_bytes);
+    let shared_path = dir.join(format!("sharedindex.{hash}"));
+    let shared = Index::from_file(&shared_path)
+        .with_context(|| format!("Could not read shared index {}", shared_path.display()))?;
+    let (deleted, replaced) = if bitmaps.is_empty() {
+        (vec![], vec![])
+    } else {
+        let max_bits = shared.entries.len();
+        (
+            ewah::read(&mut bitmaps, max_bits)?,
+            ewah::read(&mut bitmaps, max_bits)?,
+        )
+    };
+
+    let corrupt = || anyhow!("Corrupt split index");
+
+    let mut merged: Vec<Option<IndexEntry>> = shared.entries.iter().cloned().map(Some).collect();
+    let mut entries = entries.into_iter();
+    for position in replaced {
+        let slot = merged.get_mut(position).ok_or_else(corrupt)?;
+        let mut entry = entries.next().ok_or_else(corrupt)?;
+        let shared_entry = slot.take().ok_or_else(corrupt)?;
+        if !entry.path.is_empty() {
+            return Err(corrupt());
+        }
+        entry.path = shared_entry.path;
+        *slot = Some(entry);
+    }
+    for position in deleted {
+        merged.get_mut(position).ok_or_else(corrupt)?.take();
+    }
+    let mut merged: Vec<IndexEntry> = merged.into_iter().flatten().chain(entries).collect();
+    merged.sort_by(|a, b| {
+        a.path
+            .as_bytes()
+            .cmp(b.path.as_bytes())
+            .then(a.stage.cmp(&b.stage))
+    });
+
+    let shared_index = SharedIndex {
+        hash,
+        entries: shared.entries,
+    };
+    Ok((Some(shared_index), merged))
+}
+
+/// Returns the entries to write in a split index, and the data of its `link`
+/// extension. See [`read_split_index`] for the format.
+fn split_entries_for(
+    shared_index: &SharedIndex,
+    entries: &[&IndexEntry],
+) -> Result<(Vec<IndexEntry>, Vec<u8>)> {
+    let positions: HashMap<(&str, u8), usize> = shared_index
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(position, entry)| ((entry.path.as_str(), entry.stage), position))
+        .collect();
+
+    let mut kept = vec![false; shared_index.entries.len()];
+    let mut replacements = vec![];
+    let mut new_entries = vec![];
+    for &entry in entries {
+        match positions.get(&(entry.path.as_str(), entry.stage)) {
+            Some(&position) => {
+                kept[position] = true;
+                if *entry != shared_index.entries[position] {
+                    replacements.push((position, entry));
+                }
+            }
+            None => new_entries.push(entry.clone()),
+        }
+    }
+    replacements.sort_by_key(|&(position, _)| position);
+
+    let deleted: Vec<usize> = (0..kept.len()).filter(|&i| !kept[i]).collect();
+    let replaced: Vec<usize> = replacements.iter().map(|&(position, _)| position).collect();
+    let mut link = hex::decode(&shared_index.hash)?;
+    link.extend(ewah::write(&deleted));
+    link.extend(ewah::write(&replaced));
+
+    let mut split_entries: Vec<IndexEntry> = replacements
+        .into_iter()
+        .map(|(_, entry)| IndexEntry {
+            path: String::new(),
+            ..entry.clone()
+        })
+        .collect();
+    split_entries.extend(new_entries);
+    Ok((split_entries, link))
+}
+
+impl IndexEntry {
+    /// Returns an entry for a file in the working tree, with its stat data
+    /// and mode. `metadata` must not follow symlinks.
+    pub fn from_metadata(path: &str, hash: &str, metadata: &std::fs::Metadata) -> IndexEntry {
+        let stat = StatData::from_metadata(metadata);
+        IndexEntry {
+            ctime_seconds: stat.ctime_seconds,
+            ctime_nanoseconds: stat.ctime_nanoseconds,
+            mtime_seconds: stat.mtime_seconds,
+            mtime_nanoseconds: stat.mtime_nanoseconds,
+            dev: stat.dev,
+            ino: stat.ino,
+            mode: file_mode(metadata),
+            uid: stat.uid,
+            gid: stat.gid,
+            size: stat.size,
+            hash: hash.to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether the stat data of a file in the working tree matches
+    /// the entry, in which case the file is assumed to be unchanged.
+    pub fn matches_stat(&self, metadata: &std::fs::Metadata, modes: FileModes) -> bool {
+        let stat = StatData::from_metadata(metadata);
+        // Like git, the device isn't compared since it isn't stable on all
+        // file systems.
+        self.mode == modes.mode(metadata, Some(self.mode))
+            && self.mtime_seconds == stat.mtime_seconds
+            && self.mtime_nanoseconds == stat.mtime_nanoseconds
+            && self.ctime_seconds == stat.ctime_seconds
+            && self.ctime_nanoseconds == stat.ctime_nanoseconds
+            && self.ino == stat.ino
+            && self.uid == stat.uid
+            && self.gid == stat.gid
+            && self.size == stat.size
+    }
+
+    /// Returns whether the file in the working tree is ignored when looking
+    /// for changes, because it's marked as unchanged or it's outside of a
+    /// sparse checkout.
+    pub fn ignores_worktree(&self) -> bool {
+        self.assume_valid || self.skip_worktree
+    }
+
+    fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+
+    fn write_to(&self, s: &mut Vec<u8>, version: u32, previous_path: &str) -> Result<()> {
+        let entry_start = s.len();
+        for value in [
+            self.ctime_seconds,
+            self.ctime_nanoseconds,
+            self.mtime_seconds,
+            self.mtime_nanoseconds,
+            self.dev,
+            self.ino,
+            self.mode,
+            self.uid,
+            self.gid,
+            self.size,
+        ] {
+            s.extend(value.to_be_bytes());
+        }
+        s.extend(hex::decode(&self.hash).context("Invalid index entry hash")?);
+        if self.stage > 3 {
+            return Err(anyhow!("Invalid index entry stage: {}", self.stage));
+        }
+        let extended = self.is_extended();
+        if extended && version < 3 {
+            return Err(anyhow!("Extended index entry flags need index version 3"));
+        }
+        let mut flags = (u16::from(self.stage) << 12) | self.path.len().min(0xFFF) as u16;
+        if self.assume_valid {
+            flags |= FLAG_ASSUME_VALID;
+        }
+        if extended {
+            flags |= FLAG_EXTENDED;
+        }
+        s.extend(flags.to_be_bytes());
+        if extended {
+            let mut extended_flags = 0;
+            if self.skip_worktree {
+                extended_flags |= EXTENDED_FLAG_SKIP_WORKTREE;
+            }
+            if self.intent_to_add {
+                extended_flags |= EXTENDED_FLAG_INTENT_TO_ADD;
+            }
+            s.extend(u16::to_be_bytes(extended_flags));
+        }
+
+        if version == 4 {
+            // The path is stored as the number of bytes to remove from the end
+            // of the previous path, followed by the suffix to append.
+            let common = previous_path
+                .bytes()
+                .zip(self.path.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            write_varint(s, previous_path.len() - common);
+            s.extend(&self.path.as_bytes()[common..]);
+            s.push(0);
+        } else {
+            // Pad with 1-8 NUL bytes, the first of which terminates the path.
+            s.extend(self.path.as_bytes());
+            s.push(0);
+            while (s.len() - entry_start) % 8 != 0 {
+                s.push(0);
+            }
+        }
+        Ok(())
+    }
+
+    fn from_reader(reader: &mut &[u8], version: u32, previous_path: &str) -> Result<IndexEntry> {
+        let entry_start = *reader;
+        let mut stat = [0_u32; 10];
+        for value in stat.iter_mut() {
+            *value = read_u32(reader).context("Failed to read index entry")?;
+        }
+        let mut hash = [0_u8; 20];
+        reader
+            .read_exact(&mut hash)
+            .context("Failed to read index entry hash")?;
+        let flags = read_u16(reader)?;
+        let extended_flags = if flags & FLAG_EXTENDED != 0 {
+            if version < 3 {
+                return Err(anyhow!("Extended index entry flags need index version 3"));
+            }
+            read_u16(reader)?
+        } else {
+            0
+        };
+
+        let path = if version == 4 {
+            let strip_len = read_varint(reader)?;
+            let prefix_len = previous_path
+                .len()
+                .checked_sub(strip_len)
+                .ok_or(anyhow!("Invalid index entry path compression"))?;
+            let suffix = read_path(reader)?;
+            *reader = &reader[1..];
+            let mut path = previous_path.as_bytes()[..prefix_len].to_vec();
+            path.extend(suffix);
+            String::from_utf8(path)?
+        } else {
+            let path = std::str::from_utf8(read_path(reader)?)?.to_string();
+            // Entries are padded with 1-8 NUL bytes to a multiple of 8 bytes.
+            let entry_len = entry_start.len() - reader.len();
+            *reader = entry_start
+                .get((entry_len + 8) & !7..)
+                .ok_or(anyhow!("Index entry is truncated"))?;
+            path
+        };
+
+        let [ctime_seconds, ctime_nanoseconds, mtime_seconds, mtime_nanoseconds, dev, ino, mode, uid, gid, size] =
+            stat;
+        Ok(IndexEntry {
+            ctime_seconds,
+            ctime_nanoseconds,
+            mtime_seconds,
+            mtime_nanoseconds,
+            dev,
+            ino,
+            mode,
+            uid,
+            gid,
+            size,
+            hash: hex::encode(hash),
+            assume_valid: flags & FLAG_ASSUME_VALID != 0,
+            stage: ((flags >> 12) & 0x3) as u8,
+            skip_worktree: extended_flags & EXTENDED_FLAG_SKIP_WORKTREE != 0,
+            intent_to_add: extended_flags & EXTENDED_FLAG_INTENT_TO_ADD != 0,
+            path,
+        })
+    }
+}
+
+/// Reads a NUL terminated path, leaving the reader at the NUL byte.
+///
+/// The path length in the entry flags is capped at 0xFFF, so the NUL is used
+/// to find the end of the path instead.
+fn read_path<'a>(reader: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let path_len = reader
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(anyhow!("Index entry path is not terminated"))?;
+    let path = &reader[..path_len];
+    *reader = &reader[path_len..];
+    Ok(path)
+}
+
+/// Reads a variable length integer as used by git for offsets.
+///
+/// Each byte holds 7 bits of the value, most significant first, and the high
+/// bit is set on all but the last byte. Unlike plain base-128 encoding, one is
+/// added before each shift so that every value has exactly one encoding.
+pub(crate) fn read_varint(reader: &mut &[u8]) -> Result<usize> {
+    let mut byte = [0_u8; 1];
+    reader.read_exact(&mut byte)?;
+    let mut value = usize::from(byte[0] & 0x7f);
+    while byte[0] & 0x80 != 0 {
+        reader.read_exact(&mut byte)?;
+        value = value
+            .checked_add(1)
+            .and_then(|v| v.checked_mul(128))
+            .ok_or(anyhow!("Invalid varint"))?
+            | usize::from(byte[0] & 0x7f);
+    }
+    Ok(value)
+}
+
+pub(crate) fn write_varint(s: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.reverse();
+    s.extend(bytes);
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<u32> {
+    let mut bytes = [0_u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u16(reader: &mut &[u8]) -> Result<u16> {
+    let mut bytes = [0_u8; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a version 2 index with a correct checksum.
+    fn index_bytes(entries: &[(&str, u16)], extensions: &[u8]) -> Vec<u8> {
+        let mut s = b"DIRC".to_vec();
+        s.extend(2_u32.to_be_bytes());
+        s.extend((entries.len() as u32).to_be_bytes());
+        for (i, (path, flags)) in entries.iter().enumerate() {
+            let start = s.len();
+            for value in [1, 2, 3, 4, 5, 6, 0o100644, 7, 8, i as u32 + 10] {
+                s.extend(value.to_be_bytes());
+            }
+            s.extend([i as u8 + 1; 20]);
+            s.extend((flags | path.len() as u16).to_be_bytes());
+            s.extend(path.as_bytes());
+            s.push(0);
+            while (s.len() - start) % 8 != 0 {
+                s.push(0);
+            }
+        }
+        s.extend(extensions);
+        let checksum = hex::decode(hash(&s)).unwrap();
+        s.extend(checksum);
+        s
+    }
+
+    #[test]
+    fn test_index_from_bytes() {
+        let s = index_bytes(&[("a.txt", 0), ("dir/b", 0x2000)], b"ABCD\0\0\0\x02hi");
+        let index = Index::from_bytes(&s).unwrap();
+        assert_eq!(index.version, 2);
+        assert_eq!(index.extensions, vec![(*b"ABCD", b"hi".to_vec())]);
+        assert_eq!(
+            index.entries,
+            vec![
+                IndexEntry {
+                    ctime_seconds: 1,
+                    ctime_nanoseconds: 2,
+                    mtime_seconds: 3,
+                    mtime_nanoseconds: 4,
+                    dev: 5,
+                    ino: 6,
+                    mode: 0o100644,
+                    uid: 7,
+                    gid: 8,
+                    size: 10,
+                    hash: "01".repeat(20),
+                    assume_valid: false,
+                    stage: 0,
+                    skip_worktree: false,
+                    intent_to_add: false,
+                    path: "a.txt".to_string(),
+                },
+                IndexEntry {
+                    ctime_seconds: 1,
+                    ctime_nanoseconds: 2,
+                    mtime_seconds: 3,
+                    mtime_nanoseconds: 4,
+                    dev: 5,
+                    ino: 6,
+                    mode: 0o100644,
+                    uid: 7,
+                    gid: 8,
+                    size: 11,
+                    hash: "02".repeat(20),
+                    assume_valid: false,
+                    stage: 2,
+                    skip_worktree: false,
+                    intent_to_add: false,
+                    path: "dir/b".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_index_to_bytes_round_trips() {
+        let s = index_bytes(&[("a.txt", 0), ("dir/b", 0x2000)], b"ABCD\0\0\0\x02hi");
+        let index = Index::from_bytes(&s).unwrap();
+        assert_eq!(index.to_bytes().unwrap(), s);
+    }
+
+    #[test]
+    fn test_index_to_bytes_sorts_entries() {
+        let entry = |path: &str, stage| IndexEntry {
+            hash: "00".repeat(20),
+            stage,
+            path: path.to_string(),
+            ..Default::default()
+        };
+        let index = Index {
+            entries: vec![
+                entry("b", 0),
+                entry("a/c", 3),
+                entry("a/c", 1),
+                entry("a-c", 0),
+            ],
+            ..Default::default()
+        };
+        let paths: Vec<(String, u8)> = Index::from_bytes(&index.to_bytes().unwrap())
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|entry| (entry.path, entry.stage))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                ("a-c".to_string(), 0),
+                ("a/c".to_string(), 1),
+                ("a/c".to_string(), 3),
+                ("b".to_string(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut index = Index::default();
+        for path in ["README.md", "docs/Guide.md"] {
+            index.add(IndexEntry {
+                path: path.to_string(),
+                ..Default::default()
+            });
+        }
+        let aliases = index.aliases();
+        assert_eq!(aliases.get("readme.MD"), Some("README.md".to_string()));
+        assert_eq!(aliases.get("README.md"), None);
+        assert_eq!(
+            aliases.get("docs/guide.md"),
+            Some("docs/Guide.md".to_string())
+        );
+        assert_eq!(aliases.get("DOCS/new.md"), Some("docs/new.md".to_string()));
+        assert_eq!(aliases.get("docs/new.md"), None);
+        assert_eq!(aliases.get("other.md"), None);
+        assert!(aliases.contains("README.md"));
+        assert!(!aliases.contains("readme.md"));
+    }
+
+    #[test]
+    fn test_index_write() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        let index = Index::from_bytes(&index_bytes(&[("a.txt", 0)], b"")).unwrap();
+
+        index.write(&repo).unwrap();
+        assert_eq!(Index::read(&repo).unwrap(), index);
+        assert!(!repo.git_dir().join("index.lock").exists());
+    }
+
+    #[test]
+    fn test_index_lock() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        let index = Index::from_bytes(&index_bytes(&[("a.txt", 0)], b"")).unwrap();
+        index.write(&repo).unwrap();
+
+        let mut locked = Index::lock(&repo).unwrap();
+        assert_eq!(*locked, index);
+        assert!(Index::lock(&repo).is_err());
+        assert!(Index::default().write(&repo).is_err());
+
+        assert!(locked.remove("a.txt"));
+        locked.write().unwrap();
+        assert!(Index::read(&repo).unwrap().entries.is_empty());
+        assert!(!repo.git_dir().join("index.lock").exists());
+    }
+
+    #[test]
+    fn test_index_add_and_remove_keep_entries_sorted() {
+        let mut index = Index::default();
+        for (path, stage) in [
+            ("b", 0),
+            ("a/x", 0),
+            ("a", 2),
+            ("a", 1),
+            ("a-b", 0),
+            ("a/y", 0),
+        ] {
+            assert!(index.add(IndexEntry {
+                path: path.to_string(),
+                stage,
+                ..Default::default()
+            }));
+        }
+        assert!(!index.add(IndexEntry {
+            path: "a".to_string(),
+            stage: 1,
+            ..Default::default()
+        }));
+        let paths = |index: &Index| -> Vec<(String, u8)> {
+            index
+                .entries
+                .iter()
+                .map(|entry| (entry.path.clone(), entry.stage))
+                .collect()
+        };
+        assert_eq!(
+            paths(&index),
+            [
+                ("a", 1),
+                ("a", 2),
+                ("a-b", 0),
+                ("a/x", 0),
+                ("a/y", 0),
+                ("b", 0)
+            ]
+            .map(|(path, stage)| (path.to_string(), stage))
+        );
+        assert_eq!(index.entries_for("a").len(), 2);
+
+        assert!(index.remove_dir("a"));
+        assert!(!index.remove_dir("a"));
+        assert!(index.remove("a"));
+        assert!(!index.remove("a"));
+        assert_eq!(
+            paths(&index),
+            [("a-b".to_string(), 0), ("b".to_string(), 0)]
+        );
+    }
+
+    #[test]
+    fn test_index_write_tree_updates_cache_tree() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        let mut index = Index::default();
+        index.add(IndexEntry {
+            mode: 0o100644,
+            hash: "ce013625030ba8dba906f756967f9e9ca394464a".to_string(),
+            path: "dir/a.txt".to_string(),
+            ..Default::default()
+        });
+
+        let hash = index.write_tree(&repo).unwrap();
+        let cache_tree = index.cache_tree.clone().unwrap();
+        assert_eq!(cache_tree.hash, Some(hash));
+        assert_eq!(cache_tree.subtrees[0].name, "dir");
+
+        let read_back = Index::from_bytes(&index.to_bytes().unwrap()).unwrap();
+        assert_eq!(read_back.cache_tree, Some(cache_tree));
+
+        assert!(index.remove("dir/a.txt"));
+        assert!(!index.remove("dir/a.txt"));
+        assert_eq!(index.cache_tree.unwrap().hash, None);
+    }
+
+    #[test]
+    fn test_index_write_tree_fails_with_unmerged_entries() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut index = Index::default();
+        index.add(IndexEntry {
+            hash: "00".repeat(20),
+            stage: 2,
+            path: "a.txt".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(
+            index
+                .write_tree(&Repo::new(tmpdir.path()))
+                .unwrap_err()
+                .to_string(),
+            "a.txt: unmerged entry"
+        );
+    }
+
+    #[test]
+    fn test_split_index_round_trips() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let entry = |path: &str, hash: &str| IndexEntry {
+            mode: 0o100644,
+            hash: hash.repeat(20),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        let shared = Index {
+            entries: vec![entry("a", "01"), entry("b", "02"), entry("c", "03")],
+            ..Default::default()
+        };
+        let shared_bytes = shared.to_bytes().unwrap();
+        let shared_hash = hex::encode(&shared_bytes[shared_bytes.len() - 20..]);
+        std::fs::write(
+            tmpdir.path().join(format!("sharedindex.{shared_hash}")),
+            &shared_bytes,
+        )
+        .unwrap();
+
+        let mut index = Index {
+            shared_index: Some(SharedIndex {
+                hash: shared_hash,
+                entries: shared.entries.clone(),
+            }),
+            ..shared
+        };
+        index.add(entry("b", "04"));
+        index.remove("c");
+        index.add(entry("d", "05"));
+        let s = index.to_bytes().unwrap();
+        // Only the replaced and the new entry are in the split index.
+        assert_eq!(
+            Index::from_bytes(&s).unwrap_err().to_string(),
+            "Reading a split index needs its directory"
+        );
+        assert_eq!(u32::from_be_bytes(s[8..12].try_into().unwrap()), 2);
+
+        let path = tmpdir.path().join("index");
+        std::fs::write(&path, &s).unwrap();
+        let read_back = Index::from_file(&path).unwrap();
+        assert_eq!(
+            read_back.entries,
+            vec![entry("a", "01"), entry("b", "04"), entry("d", "05")]
+        );
+        assert_eq!(read_back.shared_index, index.shared_index);
+    }
+
+    #[test]
+    fn test_varint() {
+        for (value, bytes) in [
+            (0, vec![0x00]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x00]),
+            (300, vec![0x81, 0x2c]),
+            (16511, vec![0xff, 0x7f]),
+            (16512, vec![0x80, 0x80, 0x00]),
+        ] {
+            let mut s = vec![];
+            write_varint(&mut s, value);
+            assert_eq!(s, bytes, "encoding {value}");
+            assert_eq!(read_varint(&mut bytes.as_slice()).unwrap(), value);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("file");
+        std::fs::write(&path, "").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let executable = std::fs::symlink_metadata(&path).unwrap();
+        std::os::unix::fs::symlink("file", tmpdir.path().join("link")).unwrap();
+        let link = std::fs::symlink_metadata(tmpdir.path().join("link")).unwrap();
+
+        let all = FileModes::default();
+        assert_eq!(all.mode(&executable, Some(0o100644)), 0o100755);
+        assert_eq!(all.mode(&executable, Some(0o120000)), 0o100755);
+        assert_eq!(all.mode(&link, None), 0o120000);
+
+        let none = FileModes {
+            executable_bit: false,
+            symlinks: false,
+            ignore_case: false,
+        };
+        assert_eq!(none.mode(&executable, None), 0o100644);
+        assert_eq!(none.mode(&executable, Some(0o100644)), 0o100644);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let regular = std::fs::symlink_metadata(&path).unwrap();
+        assert_eq!(none.mode(&regular, Some(0o100755)), 0o100755);
+        assert_eq!(none.mode(&regular, Some(0o120000)), 0o120000);
+        assert_eq!(none.mode(&link, None), 0o120000);
+    }
+
+    #[test]
+    fn test_index_versions_round_trip() {
+        let entry = |path: &str, skip_worktree| IndexEntry {
+            mode: 0o100644,
+            hash: "ab".repeat(20),
+            skip_worktree,
+            path: path.to_string(),
+            ..Default::default()
+        };
+        let entries = vec![
+            entry("dir/file1", false),
+            entry("dir/file2", true),
+            entry("dir/sub/file3", false),
+            entry("other", false),
+        ];
+        for version in [3, 4] {
+            let index = Index {
+                version,
+                entries: entries.clone(),
+                ..Default::default()
+            };
+            let s = index.to_bytes().unwrap();
+            assert_eq!(Index::from_bytes(&s).unwrap(), index);
+        }
+
+        // Version 4 compresses paths, so it's smaller.
+        let size = |version| {
+            Index {
+                version,
+                entries: entries.clone(),
+                ..Default::default()
+            }
+            .to_bytes()
+            .unwrap()
+            .len()
+        };
+        assert!(size(4) < size(3));
+    }
+
+    #[test]
+    fn test_index_with_extended_flags_is_written_as_version_3() {
+        let index = Index {
+            entries: vec![IndexEntry {
+                hash: "ab".repeat(20),
+                intent_to_add: true,
+                path: "a".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let read_back = Index::from_bytes(&index.to_bytes().unwrap()).unwrap();
+        assert_eq!(read_back.version, 3);
+        assert!(read_back.entries[0].intent_to_add);
+    }
+
+    #[test]
+    fn test_index_from_bytes_bad_checksum() {
+        let mut s = index_bytes(&[("a.txt", 0)], b"");
+        let len = s.len();
+        s[len - 1] ^= 0xff;
+        assert_eq!(
+            Index::from_bytes(&s).unwrap_err().to_string(),
+            "Index checksum mismatch"
+        );
+    }
+
+    #[test]
+    fn test_index_from_bytes_truncated_extension() {
+        let s = index_bytes(&[], b"ABCD\0\0\0\x09hi");
+        assert_eq!(
+            Index::from_bytes(&s).unwrap_err().to_string(),
+            "Index extension is truncated"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use crate::{lockfile::LockFile, refs, repo::Repo};
+
+/// A journal of the changes an operation is about to make to the index, the
+/// working tree and refs, like a checkout moving HEAD to another commit.
+///
+/// It's written to the git directory of the worktree, along with a copy of
+/// the index, before anything is changed, and removed once everything is.
+/// If the operation is interrupted by a crash or a power loss, the journal
+/// is left behind and the operation can be rolled forward or back from it.
+/// Other operations are refused until then.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Journal {
+    /// The command, like `checkout main`.
+    pub operation: String,
+    /// The tree checked out before, if any.
+    pub old_tree: Option<String>,
+    /// The tree checked out after.
+    pub new_tree: String,
+    pub refs: Vec<RefChange>,
+}
+
+/// A ref update of a [`Journal`], with raw ref values: a hash, or
+/// `ref: <target>` for symbolic refs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefChange {
+    pub name: String,
+    /// The value before, if the ref existed.
+    pub old: Option<String>,
+    pub new: String,
+}
+
+/// Which way to recover an interrupted operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Finish the operation.
+    Forward,
+    /// Undo what the operation did.
+    Back,
+}
+
+impl Journal {
+    /// Reads the journal of an interrupted operation, if any.
+    pub fn read(repo: &Repo) -> Result<Option<Journal>> {
+        match fs::read_to_string(path(repo)) {
+            Ok(text) => Journal::parse(&text).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Fails if an operation was interrupted, which has to be recovered
+    /// before anything else changes.
+    pub fn check_interrupted(repo: &Repo) -> Result<()> {
+        match Journal::read(repo)? {
+            Some(journal) => Err(anyhow!(
+                "An interrupted '{}' was found\n\
+                 Use \"good_git recover --continue\" to finish it or \"good_git recover --abort\" to undo it",
+                journal.operation
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Records the operation before it changes anything, after checking
+    /// that no other operation was interrupted.
+    pub fn begin(&self, repo: &Repo) -> Result<()> {
+        Journal::check_interrupted(repo)?;
+        // The copy of the index is only used once the journal exists, so a
+        // crash before that leaves nothing to recover.
+        let _ = fs::remove_file(index_path(repo));
+        if let Ok(index) = fs::read(repo.git_dir().join("index")) {
+            let mut lock = LockFile::acquire(&index_path(repo))?;
+            lock.write_all(&index)?;
+            lock.commit()?;
+        }
+        let mut lock = LockFile::acquire(&path(repo))?;
+        lock.write_all(self.to_string().as_bytes())?;
+        lock.commit()
+    }
+
+    /// Removes the journal once the operation is done.
+    pub fn finish(repo: &Repo) -> Result<()> {
+        fs::remove_file(path(repo))?;
+        let _ = fs::remove_file(index_path(repo));
+        Ok(())
+    }
+
+    /// Removes the lock files the interrupted operation may have left, which
+    /// would make the recovery fail. Locks younger than [`STALE_LOCK_AGE`]
+    /// may be held by a running process, so they're an error instead.
+    pub fn remove_stale_locks(&self, repo: &Repo) -> Result<()> {
+        let mut locks = vec![repo.git_dir().join("index.lock")];
+        for change in &self.refs {
+            let mut lock = refs::ref_path(repo, &change.name).into_os_string();
+            lock.push(".lock");
+            locks.push(lock.into());
+        }
+        for lock in locks {
+            let modified = match fs::metadata(&lock) {
+                Ok(metadata) => metadata.modified()?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default();
+            if age < STALE_LOCK_AGE {
+                return Err(anyhow!(
+                    "Unable to recover: '{}' exists\n\
+                     Another git process seems to be running in this repository.\n\
+                     If it's not, remove the file manually and try again.",
+                    lock.display()
+                ));
+            }
+            fs::remove_file(lock)?;
+        }
+        Ok(())
+    }
+
+    /// Parses the journal, one `<key> <value>` line per field, with ref
+    /// updates as `ref <name> <old> <new>`, where symbolic values are written
+    /// `ref:<target>` and a missing ref `-`.
+    fn parse(text: &str) -> Result<Journal> {
+        let mut operation = None;
+        let mut old_tree = None;
+        let mut new_tree = None;
+        let mut refs = vec![];
+        for line in text.lines() {
+            let (key, value) = line
+                .split_once(' ')
+                .ok_or(anyhow!("Invalid journal line: {line}"))?;
+            match key {
+                "operation" => operation = Some(value.to_string()),
+                "old-tree" => old_tree = Some(value.to_string()),
+                "new-tree" => new_tree = Some(value.to_string()),
+                "ref" => {
+                    let [name, old, new] = value.split(' ').collect::<Vec<_>>()[..] else {
+                        return Err(anyhow!("Invalid journal line: {line}"));
+                    };
+                    let decode = |value: &str| match value.strip_prefix("ref:") {
+                        Some(target) => format!("ref: {target}"),
+                        None => value.to_string(),
+                    };
+                    refs.push(RefChange {
+                        name: name.to_string(),
+                        old: (old != "-").then(|| decode(old)),
+                        new: decode(new),
+                    });
+                }
+                _ => return Err(anyhow!("Invalid journal line: {line}")),
+            }
+        }
+        Ok(Journal {
+            operation: operation.ok_or(anyhow!("The journal has no operation"))?,
+            old_tree,
+            new_tree: new_tree.ok_or(anyhow!("The journal has no new tree"))?,
+            refs,
+        })
+    }
+}
+
+impl std::fmt::Display for Journal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let encode = |value: &str| value.replacen("ref: ", "ref:", 1);
+        writeln!(f, "operation {}", self.operation)?;
+        if let Some(tree) = &self.old_tree {
+            writeln!(f, "old-tree {tree}")?;
+        }
+        writeln!(f, "new-tree {}", self.new_tree)?;
+        for change in &self.refs {
+            let old = change.old.as_deref().map_or("-".to_string(), encode);
+            writeln!(f, "ref {} {old} {}", change.name, encode(&change.new))?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a raw ref value, see [`RefChange`].
+pub fn write_ref(repo: &Repo, name: &str, value: &str) -> Result<()> {
+    match value.strip_prefix("ref: ") {
+        Some(target) => refs::update_symbolic_ref(repo, name, target),
+        None => refs::update_ref(repo, name, value),
+    }
+}
+
+/// How old a lock left by an interrupted operation must be for recovery to
+/// remove it.
+pub const STALE_LOCK_AGE: Duration = Duration::from_secs(10 * 60);
+
+fn path(repo: &Repo) -> PathBuf {
+    repo.git_dir().join("JOURNAL")
+}
+
+/// The copy of the index from before the operation.
+pub fn index_path(repo: &Repo) -> PathBuf {
+    repo.git_dir().join("JOURNAL_INDEX")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_and_parse() {
+        let journal = Journal {
+            operation: "checkout main".to_string(),
+            old_tree: Some("a".repeat(40)),
+            new_tree: "b".repeat(40),
+            refs: vec![
+                RefChange {
+                    name: "HEAD".to_string(),
+                    old: Some("c".repeat(40)),
+                    new: "ref: refs/heads/main".to_string(),
+                },
+                RefChange {
+                    name: "refs/heads/topic".to_string(),
+                    old: None,
+                    new: "d".repeat(40),
+                },
+            ],
+        };
+        let text = journal.to_string();
+        assert_eq!(
+            text,
+            format!(
+                "operation checkout main\nold-tree {}\nnew-tree {}\n\
+                 ref HEAD {} ref:refs/heads/main\nref refs/heads/topic - {}\n",
+                "a".repeat(40),
+                "b".repeat(40),
+                "c".repeat(40),
+                "d".repeat(40)
+            )
+        );
+        assert_eq!(Journal::parse(&text).unwrap(), journal);
+
+        let initial = Journal {
+            old_tree: None,
+            refs: vec![],
+            ..journal
+        };
+        assert_eq!(Journal::parse(&initial.to_string()).unwrap(), initial);
+        assert!(Journal::parse("new-tree x\n").is_err());
+        assert!(Journal::parse("operation checkout\nref HEAD x\n").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+
+/// The version lines of Git LFS pointers, the current one first.
+const VERSIONS: [&str; 2] = [
+    "https://git-lfs.github.com/spec/v1",
+    "https://hawser.github.com/spec/v1",
+];
+
+/// Pointers are small, bigger content is never one.
+pub const MAX_POINTER_SIZE: u64 = 1024;
+
+/// A Git LFS pointer: what is stored in the repository instead of the content
+/// of a file managed by Git LFS, like:
+///
+/// ```text
+/// version https://git-lfs.github.com/spec/v1
+/// oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393
+/// size 12345
+/// ```
+///
+/// The content itself is fetched by the Git LFS client, run as the smudge
+/// filter of the `filter=lfs` attribute. Without it, the pointer is checked
+/// out instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+    /// The SHA-256 of the content, in hex.
+    pub oid: String,
+    /// The size of the content in bytes.
+    pub size: u64,
+}
+
+impl Pointer {
+    /// Parses a pointer like Git LFS: `key value` lines, the version first
+    /// and the other keys sorted, with at least an `oid` and a `size`.
+    /// Returns `None` if the content isn't a pointer.
+    pub fn parse(content: &[u8]) -> Option<Pointer> {
+        if content.len() as u64 > MAX_POINTER_SIZE {
+            return None;
+        }
+        let text = std::str::from_utf8(content).ok()?;
+        let mut lines = text.strip_suffix('\n')?.split('\n');
+        let version = lines.next()?.strip_prefix("version ")?;
+        if !VERSIONS.contains(&version) {
+            return None;
+        }
+        let mut oid = None;
+        let mut size = None;
+        let mut previous_key = "";
+        for line in lines {
+            let (key, value) = line.split_once(' ')?;
+            let valid_key = !key.is_empty()
+                && key
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"-.".contains(&b));
+            if !valid_key || key <= previous_key {
+                return None;
+            }
+            previous_key = key;
+            match key {
+                "oid" => {
+                    let hash = value.strip_prefix("sha256:")?;
+                    let valid_hash = hash.len() == 64
+                        && hash
+                            .bytes()
+                            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+                    if !valid_hash {
+                        return None;
+                    }
+                    oid = Some(hash.to_string());
+                }
+                "size" if value.bytes().all(|b| b.is_ascii_digit()) => {
+                    size = Some(value.parse().ok()?);
+                }
+                "size" => return None,
+                // Extensions and keys of later versions are ignored.
+                _ => {}
+            }
+        }
+        Some(Pointer {
+            oid: oid?,
+            size: size?,
+        })
+    }
+}
+
+impl fmt::Display for Pointer {
+    /// Formats the pointer as Git LFS writes it.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version {}", VERSIONS[0])?;
+        writeln!(f, "oid sha256:{}", self.oid)?;
+        writeln!(f, "size {}", self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let pointer = Pointer {
+            oid: "4d7a".repeat(16),
+            size: 12345,
+        };
+        let text = pointer.to_string();
+        assert_eq!(
+            text,
+            format!(
+                "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12345\n",
+                "4d7a".repeat(16)
+            )
+        );
+        assert_eq!(Pointer::parse(text.as_bytes()), Some(pointer.clone()));
+
+        let with_extension = text.replace("oid", "ext-0-foo sha256:0\noid");
+        assert_eq!(Pointer::parse(with_extension.as_bytes()), Some(pointer));
+    }
+
+    #[test]
+    fn test_parse_rejects_other_content() {
+        let text = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12345\n",
+            "4d7a".repeat(16)
+        );
+        for content in [
+            "".to_string(),
+            "hello\n".to_string(),
+            text.trim_end().to_string(),
+            text.replace("size 12345\n", ""),
+            text.replace("12345", "-1"),
+            text.replace("sha256:4d7a", "sha256:4D7A"),
+            text.replace("spec/v1", "spec/v2"),
+            // The keys must be sorted.
+            text.replace("oid", "zzz 1\noid"),
+            format!("{text}{}", "x".repeat(1024)),
+        ] {
+            assert_eq!(Pointer::parse(content.as_bytes()), None, "{content:?}");
+        }
+    }
+}
@@ -1,76 +1,314 @@
 use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs,
     io::{self, Write},
+    path::Path,
 };
 
-use anyhow::Result;
-use object::Object;
+use anyhow::{anyhow, Result};
+use object::{Commit, Object, ObjectType};
 use repo::Repo;
 
+pub mod add_patch;
+pub mod am;
+pub mod apply;
+pub mod archive;
+pub mod attributes;
+pub mod autocorrect;
+pub mod base85;
+pub mod binary;
+pub mod blame;
+pub mod cache_tree;
+pub mod color;
+pub mod column;
+pub mod commit_graph;
+pub mod config;
+pub mod diff;
+pub mod difftool;
+mod ewah;
+pub mod filter;
+pub mod format;
+pub mod format_patch;
+pub mod fsck;
+pub mod fuzz;
+pub mod graph;
+pub mod grep;
+pub mod ident;
+pub mod ignore;
+pub mod index;
+pub mod journal;
+pub mod lfs;
+pub mod lockfile;
+pub mod mailinfo;
+pub mod merge;
+pub mod merge_file;
 pub mod object;
+pub mod pack_index;
+pub mod patch_id;
+pub mod pretty;
+pub mod range_diff;
+pub mod read_tree;
+pub mod rebase;
+pub mod refs;
+pub mod remote;
+pub mod rename;
 pub mod repo;
+pub mod rerere;
+pub mod revwalk;
+pub mod sequencer;
+pub mod shallow;
+pub mod snapshot;
+pub mod status;
+pub mod textconv;
+pub mod untracked_cache;
+pub mod width;
+pub mod wildmatch;
+pub mod word_diff;
+pub mod worktree;
+mod xdiff;
 
 pub fn init_repo(repo: &Repo, branch_name: &str) -> Result<()> {
+    init_repo_with_template(repo, branch_name, None, &mut io::stdout())
+}
+
+/// Like [`init_repo`], copying the files of a template directory, such as
+/// sample hooks and `info/exclude`, into the new git directory.
+///
+/// Without `template_dir`, `$GIT_TEMPLATE_DIR` and then `init.templateDir`
+/// are used, falling back to a built-in template. An empty path disables
+/// templates. Existing files are never overwritten.
+pub fn init_repo_with_template(
+    repo: &Repo,
+    branch_name: &str,
+    template_dir: Option<&std::path::Path>,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
     let repo_path = &repo.root;
     let git_folder = repo.git_dir();
-    println!("Initializing repo {repo_path:?} with branch {branch_name}");
+    refs::validate_name(&format!("refs/heads/{branch_name}"))?;
+    let config = config::Config::load_global()?;
+    writeln!(
+        stdout,
+        "Initializing repo {repo_path:?} with branch {branch_name}"
+    )?;
 
     fs::create_dir_all(repo_path)?;
     fs::create_dir_all(&git_folder)?;
     fs::create_dir_all(git_folder.join("objects"))?;
     fs::create_dir_all(git_folder.join("refs/heads"))?;
 
+    let template_dir = match template_dir {
+        Some(dir) => Some(dir.to_path_buf()),
+        None => std::env::var_os("GIT_TEMPLATE_DIR")
+            .map(std::path::PathBuf::from)
+            .or_else(|| config.get_path("init.templateDir")),
+    };
+    // Like git, hooks are copied even with core.hooksPath, which only
+    // changes where they're looked up.
+    match template_dir {
+        Some(dir) if dir.as_os_str().is_empty() => {}
+        Some(dir) if !dir.is_dir() => {
+            writeln!(stdout, "warning: templates not found in {}", dir.display())?;
+        }
+        Some(dir) => copy_template(&dir, &git_folder)?,
+        None => {
+            for (path, content) in DEFAULT_TEMPLATE {
+                let path = git_folder.join(path);
+                if !path.exists() {
+                    fs::create_dir_all(path.parent().expect("template files are in a folder"))?;
+                    fs::write(path, content)?;
+                }
+            }
+        }
+    }
+
+    // Like git, what the file system lacks is recorded, so it's not mistaken
+    // for changes of the files.
+    let modes = index::FileModes::probe(&git_folder)?;
+    if !modes.executable_bit {
+        config::set_value(&git_folder.join("config"), "core.fileMode", "false")?;
+    }
+    if !modes.symlinks {
+        config::set_value(&git_folder.join("config"), "core.symlinks", "false")?;
+    }
+    if modes.ignore_case {
+        config::set_value(&git_folder.join("config"), "core.ignoreCase", "true")?;
+    }
+
     let data = format!("ref: refs/heads/{branch_name}");
     fs::write(git_folder.join("HEAD"), data)?;
     Ok(())
 }
 
+/// The files created by `init` when there is no template directory.
+const DEFAULT_TEMPLATE: [(&str, &str); 2] = [
+    (
+        "description",
+        "Unnamed repository; edit this file 'description' to name the repository.\n",
+    ),
+    (
+        "info/exclude",
+        "\
+# git ls-files --others --exclude-from=.git/info/exclude
+# Lines that start with '#' are comments.
+# For a project mostly in C, the following would be a good set of
+# exclude patterns (uncomment them if you want to use them):
+# *.[oa]
+# *~
+",
+    ),
+];
+
+/// Recursively copies a template directory into a git directory, skipping
+/// files that already exist.
+fn copy_template(template_dir: &std::path::Path, git_dir: &std::path::Path) -> Result<()> {
+    for entry in fs::read_dir(template_dir)? {
+        let entry = entry?;
+        let from = entry.path();
+        let to = git_dir.join(entry.file_name());
+        if from.is_dir() {
+            fs::create_dir_all(&to)?;
+            copy_template(&from, &to)?;
+        } else if !to.exists() {
+            // This keeps the permissions, so hooks stay executable.
+            fs::copy(&from, &to)?;
+        }
+    }
+    Ok(())
+}
+
 pub enum HashObjectMode<'a> {
     HashOnly,
     Write(&'a Repo),
 }
 
+#[derive(Debug, Default)]
+pub struct HashObjectOptions {
+    /// The type of the object, a blob if `None`.
+    pub object_type: Option<ObjectType>,
+    /// Hash the content as is, even if it isn't a well formed object, to
+    /// reproduce malformed objects.
+    pub literally: bool,
+}
+
 pub fn hash_object(
     mode: HashObjectMode,
     object: &mut dyn io::Read,
     stdout: &mut dyn io::Write,
+) -> Result<()> {
+    hash_object_with_options(mode, object, &Default::default(), stdout)
+}
+
+/// Prints the hash of an object read from `object`, and writes it to the
+/// repository with [`HashObjectMode::Write`].
+///
+/// Unless `literally` is set, the content must be a well formed object of its
+/// type, see [`fsck::check_object`].
+pub fn hash_object_with_options(
+    mode: HashObjectMode,
+    object: &mut dyn io::Read,
+    options: &HashObjectOptions,
+    stdout: &mut dyn io::Write,
 ) -> Result<()> {
     let mut data = Vec::new();
     object.read_to_end(&mut data)?;
-    let blob = object::Blob::new(data);
-    let hash = blob.hash();
-
-    if let HashObjectMode::Write(repo) = mode {
-        let dir = &repo.git_dir().join("objects").join(&hash[0..2]);
-        let file_path = dir.join(&hash[2..]);
-        let mut data = Vec::new();
-        let mut writer = flate2::write::ZlibEncoder::new(&mut data, flate2::Compression::default());
-        writer.write_all(b"blob ")?;
-        writer.write_all(blob.content.len().to_string().as_bytes())?;
-        writer.write_all(b"\0")?;
-        writer.write_all(&blob.content)?;
-        drop(writer);
-        fs::create_dir_all(dir)?;
-        fs::write(file_path, data)?;
+    let object_type = options.object_type.unwrap_or(ObjectType::Blob);
+    if !options.literally {
+        fsck::check_object(object_type, &data).map_err(|e| anyhow!("object fails fsck: {e}"))?;
     }
 
+    let hash = match mode {
+        HashObjectMode::HashOnly => {
+            let mut object = format!("{object_type} {}\0", data.len()).into_bytes();
+            object.extend(data);
+            object::hash(&object)
+        }
+        HashObjectMode::Write(repo) => object::write_object(repo, object_type, &data)?,
+    };
     writeln!(stdout, "{hash}")?;
     Ok(())
 }
 
+pub fn hash_file(
+    mode: HashObjectMode,
+    path: &std::path::Path,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    hash_file_with_options(mode, path, &Default::default(), stdout)
+}
+
+/// Like [`hash_object_with_options`] but for a file on disk.
+///
+/// Blobs larger than `core.bigFileThreshold` are streamed instead of being
+/// loaded into memory.
+pub fn hash_file_with_options(
+    mode: HashObjectMode,
+    path: &std::path::Path,
+    options: &HashObjectOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let size = fs::metadata(path)?.len();
+    let (config, repo) = match mode {
+        HashObjectMode::HashOnly => (config::Config::load_global()?, None),
+        HashObjectMode::Write(repo) => (config::Config::load(repo)?, Some(repo)),
+    };
+    let mut file = io::BufReader::new(fs::File::open(path)?);
+
+    let is_blob = matches!(options.object_type, None | Some(ObjectType::Blob));
+    if is_blob && size > config.big_file_threshold()? {
+        let hash = object::write_blob_stream(repo, size, &mut file)?;
+        writeln!(stdout, "{hash}")?;
+        Ok(())
+    } else {
+        hash_object_with_options(mode, &mut file, options, stdout)
+    }
+}
+
+/// Prints the entries of a pack index read from `index`, like `git
+/// show-index`: the offset, hash and, for version 2 indexes, CRC32 of each
+/// object in the pack.
+pub fn show_index(index: &mut dyn io::Read, stdout: &mut dyn io::Write) -> Result<()> {
+    let mut data = Vec::new();
+    index.read_to_end(&mut data)?;
+    let index = pack_index::PackIndex::from_bytes(&data)?;
+    for entry in &index.entries {
+        match entry.crc32 {
+            Some(crc32) => writeln!(stdout, "{} {} ({crc32:08x})", entry.offset, entry.hash)?,
+            None => writeln!(stdout, "{} {}", entry.offset, entry.hash)?,
+        }
+    }
+    Ok(())
+}
+
 pub fn cat_file(repo: &Repo, object_hash: &str, stdout: &mut dyn io::Write) -> Result<()> {
     let object = Object::from_rev(repo, object_hash)?;
 
     match object {
         Object::Blob(blob) => {
-            let content = std::str::from_utf8(&blob.content)?;
-            writeln!(stdout, "{content}")?;
+            let config = config::Config::load(repo)?;
+            let classifier = binary::BinaryClassifier::from_config(&config)?;
+            // The attributes of the path are used when it's known, with
+            // `<rev>:<path>`.
+            let attribute = match object_hash.split_once(':') {
+                Some((_, path)) => {
+                    let attributes = attributes::Attributes::load(repo)?;
+                    binary::TextAttribute::for_path(&attributes, &config, path)?
+                }
+                None => binary::TextAttribute::Unspecified,
+            };
+            let size = blob.content.len() as u64;
+            stdout.write_all(&blob.content)?;
+            // Binary content is written as is, text gets a final newline.
+            if !classifier.is_binary(&blob.content, size, attribute) {
+                writeln!(stdout)?;
+            }
         }
         Object::Tree(tree) => {
             for file in tree.files {
                 writeln!(
                     stdout,
-                    "{:>6} {:>4} {:43} {}",
+                    "{:>6} {:>4} {}    {}",
                     file.mode,
                     file.type_str(),
                     file.hash,
@@ -80,53 +318,8055 @@ pub fn cat_file(repo: &Repo, object_hash: &str, stdout: &mut dyn io::Write) -> R
         }
         Object::Commit(commit) => {
             writeln!(stdout, "tree: {}", commit.tree)?;
-            writeln!(stdout, "parent: {}", commit.parent)?;
+            writeln!(stdout, "parent: {}", commit.parents.join(" "))?;
             writeln!(stdout, "author: {}", commit.author)?;
             writeln!(stdout, "committer: {}", commit.committer)?;
             writeln!(stdout, "\n{}", commit.message)?;
         }
+        Object::Tag(tag) => {
+            writeln!(stdout, "object: {}", tag.object)?;
+            writeln!(stdout, "type: {}", tag.object_type)?;
+            writeln!(stdout, "tag: {}", tag.tag)?;
+            writeln!(stdout, "tagger: {}", tag.tagger)?;
+            writeln!(stdout, "\n{}", tag.message)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the content of a blob given as `<rev>:<path>`, converted with the
+/// textconv driver of the path if it has one.
+pub fn cat_file_textconv(repo: &Repo, object_rev: &str, stdout: &mut dyn io::Write) -> Result<()> {
+    let Some((_, path)) = object_rev.split_once(':') else {
+        return Err(anyhow!(
+            "<object> required in the form <rev>:<path>, got '{object_rev}'"
+        ));
+    };
+    let hash = Object::resolve_rev(repo, object_rev)?;
+    let Object::Blob(blob) = Object::from_hash(repo, &hash)? else {
+        return Err(anyhow!("Not a blob: {object_rev}"));
+    };
+    let textconv = textconv::Textconv::new(repo)?;
+    let content = textconv.convert(path, Some(&hash), &blob.content)?;
+    textconv.save()?;
+    stdout.write_all(content.as_deref().unwrap_or(&blob.content))?;
+    Ok(())
+}
+
+/// Prints the content of a blob given as `<rev>:<path>`, converted by the
+/// smudge filter of the path like when checking it out.
+///
+/// Like checking out, a Git LFS pointer without a Git LFS client to smudge it
+/// is printed as is, with a warning to `stderr`.
+pub fn cat_file_filters(
+    repo: &Repo,
+    object_rev: &str,
+    stdout: &mut dyn io::Write,
+    stderr: &mut dyn io::Write,
+) -> Result<()> {
+    let Some((_, path)) = object_rev.split_once(':') else {
+        return Err(anyhow!(
+            "<object> required in the form <rev>:<path>, got '{object_rev}'"
+        ));
+    };
+    let Object::Blob(blob) = Object::from_rev(repo, object_rev)? else {
+        return Err(anyhow!("Not a blob: {object_rev}"));
+    };
+    let filters = filter::Filters::new(repo)?;
+    stdout.write_all(&filters.smudge(path, blob.content)?)?;
+    for warning in filters.take_warnings() {
+        writeln!(stderr, "{warning}")?;
     }
+    Ok(())
+}
+
+/// Prints the type of an object.
+pub fn cat_file_type(repo: &Repo, object_rev: &str, stdout: &mut dyn io::Write) -> Result<()> {
+    let (object_type, _) = object::read_header(repo, &Object::resolve_rev(repo, object_rev)?)?;
+    writeln!(stdout, "{object_type}")?;
+    Ok(())
+}
 
+/// Prints the size of an object in bytes.
+pub fn cat_file_size(repo: &Repo, object_rev: &str, stdout: &mut dyn io::Write) -> Result<()> {
+    let (_, size) = object::read_header(repo, &Object::resolve_rev(repo, object_rev)?)?;
+    writeln!(stdout, "{size}")?;
     Ok(())
 }
 
+/// Prints the history of a commit, one line per commit.
+///
+/// The history of an unborn HEAD is empty.
 pub fn log(repo: &Repo, object_rev: &str, stdout: &mut dyn io::Write) -> Result<()> {
-    let mut next_object_rev = Some(object_rev.to_string());
+    let options = LogOptions {
+        oneline: true,
+        ..Default::default()
+    };
+    log_with_options(repo, object_rev, &options, stdout)
+}
 
-    while let Some(this_rev) = &next_object_rev {
-        let current_object = Object::from_rev(repo, this_rev)?;
+#[derive(Debug, Default)]
+pub struct LogOptions {
+    /// Print one line per commit, with its subject and committer, instead
+    /// of [`LogOptions::format`].
+    pub oneline: bool,
+    /// The format of commits, git's `medium` by default.
+    pub format: pretty::Format,
+    /// Prefix commits with `<` or `>`, for the left or the right side of a
+    /// symmetric range.
+    pub left_right: bool,
+    /// Omit the commits of a symmetric range with an equivalent commit on
+    /// the other side, see [`patch_id::equivalent_commits`].
+    pub cherry_pick: bool,
+    /// Print commits before their parents, see
+    /// [`revwalk::RevWalk::set_topo_order`].
+    pub topo_order: bool,
+    /// Only follow the first parent of merges.
+    pub first_parent: bool,
+    /// Limits on the commits printed, like their number or their author.
+    pub limits: revwalk::Limits,
+    /// Only print the commits changing these paths, see
+    /// [`revwalk::RevWalk::set_paths`].
+    pub paths: Vec<String>,
+    /// Print full hashes instead of abbreviated ones.
+    pub full_hash: bool,
+    /// Pad or truncate subjects to this many columns, so that the
+    /// committers line up, with [`LogOptions::oneline`].
+    pub subject_width: Option<usize>,
+    /// Truncate committers to this many columns.
+    pub committer_width: Option<usize>,
+    /// Show the refs pointing at commits after their hashes, see
+    /// [`pretty::format_decorations`].
+    pub decorate: bool,
+    /// Color the hashes and the refs pointing at commits.
+    pub colors: Option<color::DecorateColors>,
+}
 
-        match current_object {
-            Object::Blob(_) => {
-                return Ok(());
+/// Prints the history of a commit like [`log`], or the commits of a
+/// symmetric range `<a>...<b>`, which are the commits reachable from either
+/// side but not both, newest first, see [`revwalk::RevWalk`].
+pub fn log_with_options(
+    repo: &Repo,
+    object_rev: &str,
+    options: &LogOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let mut first = true;
+    let abbreviator = object::Abbreviator::from_config(repo)?;
+    let mut print = |hash: &str, commit: &Commit, left: bool, stdout: &mut dyn io::Write| {
+        let object_hash = hash;
+        let side = match options.left_right {
+            true => format!("{} ", if left { '<' } else { '>' }),
+            false => String::new(),
+        };
+        let decorations = match options.decorate {
+            true => pretty::decorations(repo, hash)?,
+            false => vec![],
+        };
+        let decorations = pretty::format_decorations(&decorations, options.colors.as_ref());
+        let hash = match options.full_hash {
+            true => hash.to_string(),
+            false => abbreviator.abbreviate(hash)?,
+        };
+        if !options.oneline {
+            let is_first = std::mem::take(&mut first);
+            let colored = |text: String| match &options.colors {
+                Some(colors) => format!("{}{text}{}", colors.commit, color::RESET),
+                None => text,
+            };
+            match &options.format {
+                pretty::Format::Tformat(format) | pretty::Format::Format(format) => {
+                    let separated = matches!(options.format, pretty::Format::Format(_));
+                    if separated && !is_first {
+                        writeln!(stdout)?;
+                    }
+                    stdout.write_all(&pretty::format_commit(
+                        repo,
+                        &abbreviator,
+                        object_hash,
+                        commit,
+                        format,
+                    )?)?;
+                    if !separated {
+                        writeln!(stdout)?;
+                    }
+                }
+                pretty::Format::Oneline => {
+                    let subject = pretty::subject(&commit.message);
+                    writeln!(stdout, "{side}{}{decorations} {subject}", colored(hash))?;
+                }
+                format => {
+                    // Commits are separated by blank lines.
+                    if !is_first {
+                        writeln!(stdout)?;
+                    }
+                    let header = colored(format!("commit {side}{hash}"));
+                    writeln!(stdout, "{header}{decorations}")?;
+                    write!(
+                        stdout,
+                        "{}",
+                        pretty::format_details(&abbreviator, commit, format)?
+                    )?;
+                }
             }
-            Object::Tree(_) => {
-                return Ok(());
+            return anyhow::Ok(());
+        }
+        let hash = match &options.colors {
+            Some(colors) => format!("{}{hash}{}", colors.commit, color::RESET),
+            None => hash,
+        };
+        let subject = commit.message.lines().next().unwrap_or("");
+        let subject = match options.subject_width {
+            Some(columns) => width::pad(&width::truncate(subject, columns), columns),
+            None => subject.to_string(),
+        };
+        let committer = match options.committer_width {
+            Some(columns) => width::truncate(&commit.committer, columns),
+            None => Cow::Borrowed(commit.committer.as_str()),
+        };
+        writeln!(
+            stdout,
+            "{side}{hash}{decorations} - {subject} - \"{committer}\""
+        )?;
+        anyhow::Ok(())
+    };
+    if object_rev == "HEAD" && refs::unborn_branch(repo)?.is_some() {
+        return Ok(());
+    }
+    let revs = [object_rev.to_string()];
+    let commits = walk_revs_with(repo, &revs, options.cherry_pick, |walk| {
+        walk.set_topo_order(options.topo_order);
+        walk.set_first_parent(options.first_parent);
+        walk.set_limits(options.limits.clone());
+        walk.set_paths(&options.paths);
+    })?;
+    for (hash, commit, left) in commits {
+        print(&hash, &commit, left, stdout)?;
+    }
+    Ok(())
+}
+
+/// Returns whether the commit `ancestor` is an ancestor of `descendant`,
+/// after peeling tags.
+pub fn merge_base_is_ancestor(repo: &Repo, ancestor: &str, descendant: &str) -> Result<bool> {
+    let ancestor = Object::peel(repo, &Object::resolve_rev(repo, ancestor)?)?;
+    let descendant = Object::peel(repo, &Object::resolve_rev(repo, descendant)?)?;
+    graph::is_ancestor(repo, &ancestor, &descendant)
+}
+
+/// Checks out a branch like [`switch_branch`], or detaches HEAD at a commit
+/// like [`checkout_detach`] if `rev` isn't a branch.
+pub fn checkout(repo: &Repo, rev: &str, stdout: &mut dyn io::Write) -> Result<()> {
+    if refs::find_ref(repo, &format!("refs/heads/{rev}")).is_ok() {
+        switch_branch(repo, rev, stdout)
+    } else {
+        checkout_detach(repo, Some(rev), stdout)
+    }
+}
+
+/// Points HEAD at a branch, updating the index and the working tree from the
+/// commit of HEAD to the commit of the branch.
+///
+/// Like git, staged and unstaged changes are carried over and listed, unless
+/// their files differ between the two commits, in which case nothing is
+/// changed. Untracked files that would be overwritten or removed are refused
+/// too, and so is switching in the middle of a merge.
+pub fn switch_branch(repo: &Repo, branch: &str, stdout: &mut dyn io::Write) -> Result<()> {
+    refuse_while_merging(repo)?;
+    let name = format!("refs/heads/{branch}");
+    let target =
+        refs::find_ref(repo, &name).map_err(|_| match Object::resolve_rev(repo, branch) {
+            Ok(_) => anyhow!("a branch is expected, got '{branch}'"),
+            Err(_) => anyhow!("invalid reference: {branch}"),
+        })?;
+    let current = fs::canonicalize(&repo.root)?;
+    if let Some(path) = worktree::checked_out(repo, &name)?
+        .into_iter()
+        .find(|path| *path != current)
+    {
+        return Err(anyhow!(
+            "'{branch}' is already checked out at '{}'",
+            path.display()
+        ));
+    }
+    let previous = refs::head(repo)?;
+    switch_commit(
+        repo,
+        &target,
+        &format!("checkout {branch}"),
+        &format!("ref: {name}"),
+        stdout,
+    )?;
+    log_checkout(repo, &previous, &target, branch)?;
+    if previous == refs::Head::Branch(name) {
+        writeln!(stdout, "Already on '{branch}'")?;
+    } else {
+        writeln!(stdout, "Switched to branch '{branch}'")?;
+    }
+    Ok(())
+}
+
+/// Detaches HEAD at a commit, which defaults to the commit HEAD points to,
+/// updating the index and the working tree like [`switch_branch`].
+pub fn checkout_detach(repo: &Repo, rev: Option<&str>, stdout: &mut dyn io::Write) -> Result<()> {
+    refuse_while_merging(repo)?;
+    let current = refs::find_ref(repo, "HEAD").ok();
+    let target = match rev {
+        Some(rev) => Object::peel(repo, &Object::resolve_rev(repo, rev)?)?,
+        None if refs::unborn_branch(repo)?.is_some() => {
+            return Err(anyhow!("You are on a branch yet to be born"))
+        }
+        None => current
+            .clone()
+            .ok_or(anyhow!("HEAD does not point to a commit"))?,
+    };
+    let Object::Commit(commit) = Object::from_hash(repo, &target)? else {
+        return Err(anyhow!("Not a commit: {target}"));
+    };
+
+    let previous = refs::head(repo)?;
+    let operation = format!("checkout --detach {}", rev.unwrap_or("HEAD"));
+    switch_commit(repo, &target, &operation, &target, stdout)?;
+    log_checkout(repo, &previous, &target, rev.unwrap_or(&target))?;
+    writeln!(
+        stdout,
+        "HEAD is now at {} {}",
+        object::Abbreviator::from_config(repo)?.abbreviate(&target)?,
+        commit.message.lines().next().unwrap_or("")
+    )?;
+    Ok(())
+}
+
+/// Refuses to move HEAD while a merge waits to be concluded, since
+/// `MERGE_HEAD` would then be merged into the wrong commit.
+fn refuse_while_merging(repo: &Repo) -> Result<()> {
+    match merge::State::read(repo)? {
+        Some(state) if !state.merge_heads.is_empty() => {
+            Err(anyhow!("cannot switch branch while merging"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Restores the files below `pathspecs` from the tree of a commit, or from
+/// the index without `rev`, leaving HEAD where it is.
+///
+/// Files from a commit are written to both the index and the working tree,
+/// while files that are only in the index are kept. Local changes to the
+/// restored files are lost. Like git, every pathspec has to match a file.
+pub fn checkout_paths(repo: &Repo, rev: Option<&str>, pathspecs: &[String]) -> Result<()> {
+    let mut index = index::Index::lock(repo)?;
+    let mut sources = BTreeMap::new();
+    match rev {
+        Some(rev) => status::tree_files(repo, &rev_tree(repo, rev)?, "", &mut sources)?,
+        None => {
+            for entry in &index.entries {
+                sources.insert(entry.path.clone(), (entry.mode, entry.hash.clone()));
             }
-            Object::Commit(commit) => {
-                let commiter = commit.committer;
-                let first_line = commit.message.lines().next().unwrap_or("");
-                writeln!(
-                    stdout,
-                    "{hash} - {first_line} - \"{commiter}\"",
-                    hash = &this_rev[0..6]
-                )?;
-                if commit.parent.is_empty() {
-                    return Ok(());
+        }
+    }
+    for pathspec in pathspecs {
+        if !sources.keys().any(|path| matches_pathspec(path, pathspec)) {
+            return Err(anyhow!(
+                "pathspec '{pathspec}' did not match any file(s) known to git"
+            ));
+        }
+    }
+
+    let paths: Vec<&String> = sources
+        .keys()
+        .filter(|path| {
+            pathspecs
+                .iter()
+                .any(|pathspec| matches_pathspec(path, pathspec))
+        })
+        .collect();
+    if rev.is_none() {
+        if let Some(entry) = index
+            .entries
+            .iter()
+            .find(|entry| entry.stage != 0 && paths.contains(&&entry.path))
+        {
+            return Err(anyhow!("path '{}' is unmerged", entry.path));
+        }
+    }
+    let filters = filter::Filters::new(repo)?;
+    let modes = index::FileModes::load(repo)?;
+    let big_file_threshold = config::Config::load(repo)?.big_file_threshold()?;
+    for path in paths {
+        let (mode, hash) = sources[path].clone();
+        let entry = index::IndexEntry {
+            mode,
+            hash,
+            path: path.clone(),
+            ..Default::default()
+        };
+        // Files outside of the sparse checkout stay out of the working tree.
+        let skip_worktree = index
+            .entries
+            .iter()
+            .any(|entry| entry.path == *path && entry.skip_worktree);
+        let entry = match skip_worktree {
+            true => index::IndexEntry {
+                skip_worktree,
+                ..entry
+            },
+            false => checkout_entry(repo, &filters, modes, big_file_threshold, &entry)?,
+        };
+        add_index_entry(&mut index, entry);
+    }
+    index.write()
+}
+
+/// Moves the index and the working tree from the commit of HEAD to another
+/// commit, then sets HEAD to the raw value `head`, and lists the local
+/// changes that were carried over.
+fn switch_commit(
+    repo: &Repo,
+    target: &str,
+    operation: &str,
+    head: &str,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let target_tree = rev_tree(repo, target)?;
+    let refs = vec![journal::RefChange {
+        name: "HEAD".to_string(),
+        old: refs::read_ref(repo, "HEAD")?,
+        new: head.to_string(),
+    }];
+    for warning in move_to_tree(repo, &target_tree, operation, refs, "checkout")? {
+        writeln!(stdout, "{warning}")?;
+    }
+    for change in diff::diff_index(repo, &target_tree, false)? {
+        writeln!(stdout, "{}", diff::RawFormat::NameStatus.format(&change))?;
+    }
+    Ok(())
+}
+
+/// Moves the index and the working tree from the tree of HEAD to `tree`,
+/// carrying over local changes, then applies the ref changes.
+///
+/// The changes are recorded in a [`journal::Journal`] first, so that they can
+/// be recovered if they are interrupted. `command` is what errors say would
+/// lose local changes, like `checkout` or `merge`.
+///
+/// Returns the warnings of the checkout, see [`Unpacked::warnings`].
+fn move_to_tree(
+    repo: &Repo,
+    tree: &str,
+    operation: &str,
+    ref_changes: Vec<journal::RefChange>,
+    command: &str,
+) -> Result<Vec<String>> {
+    let mut trees = vec![BTreeMap::new(), BTreeMap::new()];
+    let old_tree = match refs::find_ref(repo, "HEAD") {
+        Ok(head) => Some(rev_tree(repo, &head)?),
+        Err(_) => None,
+    };
+    if let Some(tree) = &old_tree {
+        status::tree_files(repo, tree, "", &mut trees[0])?;
+    }
+    status::tree_files(repo, tree, "", &mut trees[1])?;
+
+    // The lock of the index an interrupted operation left must not hide it.
+    journal::Journal::check_interrupted(repo)?;
+    let mut index = index::Index::lock(repo)?;
+    let unmerged: BTreeSet<&str> = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage != 0)
+        .map(|entry| entry.path.as_str())
+        .collect();
+    if !unmerged.is_empty() {
+        let needs_merge: Vec<String> = unmerged
+            .iter()
+            .map(|path| format!("{path}: needs merge"))
+            .collect();
+        return Err(anyhow!(
+            "{}\nyou need to resolve your current index first",
+            needs_merge.join("\n")
+        ));
+    }
+
+    let journal = journal::Journal {
+        operation: operation.to_string(),
+        old_tree,
+        new_tree: tree.to_string(),
+        refs: ref_changes,
+    };
+    journal.begin(repo)?;
+    let Unpacked { rejected, warnings } = unpack_trees(repo, &mut index, &trees, true)?;
+    if !rejected.is_empty() {
+        journal::Journal::finish(repo)?;
+        let before = match command {
+            "checkout" => "switch branches",
+            command => command,
+        };
+        let section = |rejections: &[Rejection], message: &str, advice: &str| {
+            let paths: Vec<&str> = rejected
+                .iter()
+                .filter(|(_, rejection)| rejections.contains(rejection))
+                .map(|(path, _)| path.as_str())
+                .collect();
+            (!paths.is_empty()).then(|| format!("{message}\n\t{}\n{advice}", paths.join("\n\t")))
+        };
+        let sections = [
+            section(
+                &[Rejection::StagedChanges, Rejection::NotUptodate],
+                &format!(
+                    "Your local changes to the following files would be overwritten by {command}:"
+                ),
+                &format!("Please commit your changes or stash them before you {before}."),
+            ),
+            section(
+                &[Rejection::UntrackedOverwritten],
+                &format!(
+                    "The following untracked working tree files would be overwritten by {command}:"
+                ),
+                &format!("Please move or remove them before you {before}."),
+            ),
+            section(
+                &[Rejection::UntrackedRemoved],
+                &format!(
+                    "The following untracked working tree files would be removed by {command}:"
+                ),
+                &format!("Please move or remove them before you {before}."),
+            ),
+        ];
+        let sections: Vec<String> = sections.into_iter().flatten().collect();
+        return Err(anyhow!("{}\nAborting", sections.join("\n")));
+    }
+    index.write()?;
+    for change in &journal.refs {
+        journal::write_ref(repo, &change.name, &change.new)?;
+    }
+    journal::Journal::finish(repo)?;
+    Ok(warnings)
+}
+
+/// Recovers an operation interrupted by a crash or a power loss from its
+/// [`journal::Journal`], rolling it forward or back, or describes it without
+/// `recovery`.
+///
+/// Rolling forward writes the files that differ between the old and the new
+/// tree, keeping the other local changes, and points the refs at their new
+/// values. Rolling back restores the index from before the operation, its
+/// files that differ between the two trees, and the old values of the refs.
+pub fn recover(
+    repo: &Repo,
+    recovery: Option<journal::Recovery>,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let Some(journal) = journal::Journal::read(repo)? else {
+        writeln!(stdout, "No interrupted operation to recover")?;
+        return Ok(());
+    };
+    let Some(recovery) = recovery else {
+        writeln!(stdout, "An interrupted '{}' was found", journal.operation)?;
+        writeln!(
+            stdout,
+            "  (use \"good_git recover --continue\" to finish it or \"good_git recover --abort\" to undo it)"
+        )?;
+        return Ok(());
+    };
+    journal.remove_stale_locks(repo)?;
+
+    let mut old_files = BTreeMap::new();
+    if let Some(tree) = &journal.old_tree {
+        status::tree_files(repo, tree, "", &mut old_files)?;
+    }
+    let mut new_files = BTreeMap::new();
+    status::tree_files(repo, &journal.new_tree, "", &mut new_files)?;
+    let changed: BTreeSet<&String> = old_files
+        .keys()
+        .chain(new_files.keys())
+        .filter(|path| old_files.get(*path) != new_files.get(*path))
+        .collect();
+
+    let mut index = match journal::index_path(repo).exists() {
+        true => index::Index::from_file(&journal::index_path(repo))?,
+        false => index::Index::default(),
+    };
+    let filters = filter::Filters::new(repo)?;
+    let modes = index::FileModes::load(repo)?;
+    let big_file_threshold = config::Config::load(repo)?.big_file_threshold()?;
+    for path in changed {
+        let saved = index
+            .entries
+            .iter()
+            .find(|entry| entry.path == *path && entry.stage == 0)
+            .cloned();
+        let skip_worktree = saved.as_ref().is_some_and(|entry| entry.skip_worktree);
+        let version = match recovery {
+            journal::Recovery::Forward => {
+                new_files.get(path).map(|(mode, hash)| index::IndexEntry {
+                    mode: *mode,
+                    hash: hash.clone(),
+                    path: path.clone(),
+                    skip_worktree,
+                    ..Default::default()
+                })
+            }
+            journal::Recovery::Back => saved,
+        };
+        index.remove(path);
+        match version {
+            Some(entry) if entry.skip_worktree => {
+                add_index_entry(&mut index, entry);
+            }
+            Some(entry) => {
+                add_index_entry(
+                    &mut index,
+                    checkout_entry(repo, &filters, modes, big_file_threshold, &entry)?,
+                );
+            }
+            None if skip_worktree => {}
+            None => remove_worktree_file(repo, path)?,
+        }
+    }
+    index.write(repo)?;
+    for change in &journal.refs {
+        match (recovery, &change.old) {
+            (journal::Recovery::Forward, _) => journal::write_ref(repo, &change.name, &change.new)?,
+            (journal::Recovery::Back, Some(old)) => journal::write_ref(repo, &change.name, old)?,
+            (journal::Recovery::Back, None) => {
+                return Err(anyhow!("Unable to delete {}", change.name))
+            }
+        }
+    }
+    journal::Journal::finish(repo)?;
+    match recovery {
+        journal::Recovery::Forward => writeln!(stdout, "Finished '{}'", journal.operation)?,
+        journal::Recovery::Back => writeln!(stdout, "Undid '{}'", journal.operation)?,
+    }
+    Ok(())
+}
+
+/// Records a checkout in the reflog of HEAD, unless the committer is
+/// unknown.
+fn log_checkout(repo: &Repo, previous: &refs::Head, target: &str, to: &str) -> Result<()> {
+    let config = config::Config::load(repo)?;
+    let Ok(committer) = ident::Ident::new(&config, ident::Role::Committer) else {
+        return Ok(());
+    };
+    let (from, old) = match previous {
+        refs::Head::Branch(name) => (
+            name.strip_prefix("refs/heads/").unwrap_or(name).to_string(),
+            refs::find_ref(repo, name).unwrap_or(refs::ZERO_HASH.to_string()),
+        ),
+        refs::Head::Detached(hash) => (hash.clone(), hash.clone()),
+    };
+    refs::append_reflog(
+        repo,
+        "HEAD",
+        &old,
+        target,
+        &committer.to_string(),
+        &format!("checkout: moving from {from} to {to}"),
+    )
+}
+
+/// Points HEAD at a new branch with no commits, so that the next commit has
+/// no parents.
+///
+/// Like `git switch --orphan`, the index and the tracked files are cleared,
+/// unless `keep` is set, like `git checkout --orphan`. Untracked files are
+/// always left alone.
+pub fn checkout_orphan(
+    repo: &Repo,
+    branch: &str,
+    keep: bool,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let name = format!("refs/heads/{branch}");
+    refs::validate_name(&name).map_err(|_| anyhow!("'{branch}' is not a valid branch name"))?;
+    if refs::find_ref(repo, &name).is_ok() {
+        return Err(anyhow!("A branch named '{branch}' already exists"));
+    }
+
+    if !keep {
+        let changed: Vec<String> = status::Status::compute(repo)?
+            .paths
+            .into_iter()
+            .filter(|path| path.staged.is_some() || path.unstaged.is_some() || path.unmerged != 0)
+            .map(|path| path.path)
+            .collect();
+        if !changed.is_empty() {
+            return Err(anyhow!(
+                "Your local changes to the following files would be overwritten by checkout:\n\t{}\nPlease commit your changes or stash them before you switch branches.",
+                changed.join("\n\t")
+            ));
+        }
+        let mut index = index::Index::read(repo)?;
+        for entry in std::mem::take(&mut index.entries) {
+            if !entry.skip_worktree {
+                remove_worktree_file(repo, &entry.path)?;
+            }
+        }
+        index.cache_tree = None;
+        index.untracked_cache = None;
+        index.write(repo)?;
+    }
+
+    refs::update_symbolic_ref(repo, "HEAD", &name)?;
+    writeln!(stdout, "Switched to a new branch '{branch}'")?;
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct WorktreeAddOptions {
+    /// Create this branch at the commit and check it out, like `-b`.
+    pub new_branch: Option<String>,
+    /// Detach HEAD at the commit, even if it's a branch.
+    pub detach: bool,
+    /// Lock the new worktree.
+    pub lock: bool,
+}
+
+/// Adds a linked worktree at `path`, which must be missing or empty, and
+/// checks out a commit in it.
+///
+/// Like git, a branch given as `commit_ish` is checked out, while other
+/// commits detach HEAD. Without `commit_ish`, the branch named after the
+/// folder is checked out, and created at HEAD if needed. A branch can't be
+/// checked out in two worktrees.
+pub fn worktree_add(
+    repo: &Repo,
+    path: &Path,
+    commit_ish: Option<&str>,
+    options: &WorktreeAddOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let find_branch = |branch: &str| refs::find_ref(repo, &format!("refs/heads/{branch}")).ok();
+    let resolve = |rev: &str| {
+        Object::resolve_rev(repo, rev)
+            .and_then(|hash| Object::peel(repo, &hash))
+            .map_err(|_| anyhow!("invalid reference: {rev}"))
+    };
+    // The branch to check out, whether it has to be created, and the commit.
+    let (branch, create, target) = match (&options.new_branch, commit_ish) {
+        (Some(branch), _) => (
+            Some(branch.clone()),
+            true,
+            resolve(commit_ish.unwrap_or("HEAD"))?,
+        ),
+        (None, _) if options.detach => (None, false, resolve(commit_ish.unwrap_or("HEAD"))?),
+        (None, Some(rev)) => match find_branch(rev) {
+            Some(hash) => (Some(rev.to_string()), false, hash),
+            None => (None, false, resolve(rev)?),
+        },
+        (None, None) => {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or(anyhow!("Invalid worktree path: {}", path.display()))?;
+            match find_branch(name) {
+                Some(hash) => (Some(name.to_string()), false, hash),
+                None => (Some(name.to_string()), true, resolve("HEAD")?),
+            }
+        }
+    };
+    let Object::Commit(commit) = Object::from_hash(repo, &target)? else {
+        return Err(anyhow!("Not a commit: {target}"));
+    };
+    match (&branch, create) {
+        (Some(branch), true) => writeln!(stdout, "Preparing worktree (new branch '{branch}')")?,
+        (Some(branch), false) => writeln!(stdout, "Preparing worktree (checking out '{branch}')")?,
+        (None, _) => writeln!(
+            stdout,
+            "Preparing worktree (detached HEAD {})",
+            object::Abbreviator::from_config(repo)?.abbreviate(&target)?
+        )?,
+    }
+
+    if fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some()) || path.is_file() {
+        return Err(anyhow!("'{}' already exists", path.display()));
+    }
+    let head = match &branch {
+        Some(branch) => {
+            let name = format!("refs/heads/{branch}");
+            if create {
+                refs::validate_name(&name)
+                    .map_err(|_| anyhow!("'{branch}' is not a valid branch name"))?;
+                if find_branch(branch).is_some() {
+                    return Err(anyhow!("a branch named '{branch}' already exists"));
+                }
+            } else if let Some(path) = worktree::checked_out(repo, &name)?.first() {
+                return Err(anyhow!(
+                    "'{branch}' is already checked out at '{}'",
+                    path.display()
+                ));
+            }
+            format!("ref: {name}")
+        }
+        None => target.clone(),
+    };
+    if let (Some(branch), true) = (&branch, create) {
+        let name = format!("refs/heads/{branch}");
+        refs::update_ref(repo, &name, &target)?;
+        let config = config::Config::load(repo)?;
+        if let Ok(committer) = ident::Ident::new(&config, ident::Role::Committer) {
+            refs::append_reflog(
+                repo,
+                &name,
+                refs::ZERO_HASH,
+                &target,
+                &committer.to_string(),
+                &format!("branch: Created from {}", commit_ish.unwrap_or("HEAD")),
+            )?;
+        }
+    }
+
+    let checkout = || -> Result<()> {
+        let linked = worktree::create(repo, path, &head)?;
+        if options.lock {
+            fs::write(linked.git_dir().join("locked"), "")?;
+        }
+        if let Err(e) = switch_commit(&linked, &target, "worktree add", &head, &mut io::sink()) {
+            fs::remove_dir_all(&linked.root)?;
+            fs::remove_dir_all(linked.git_dir())?;
+            return Err(e);
+        }
+        Ok(())
+    };
+    if let Err(e) = checkout() {
+        // The branch created for the worktree goes with it.
+        if let (Some(branch), true) = (&branch, create) {
+            let name = format!("refs/heads/{branch}");
+            fs::remove_file(refs::ref_path(repo, &name))?;
+            let _ = fs::remove_file(repo.common_dir().join("logs").join(&name));
+        }
+        return Err(e);
+    }
+    writeln!(
+        stdout,
+        "HEAD is now at {} {}",
+        object::Abbreviator::from_config(repo)?.abbreviate(&target)?,
+        commit.message.lines().next().unwrap_or("")
+    )?;
+    Ok(())
+}
+
+/// Lists the worktrees with the commit and branch they have checked out,
+/// like `git worktree list`, or in the stable format of `--porcelain`.
+pub fn worktree_list(repo: &Repo, porcelain: bool, stdout: &mut dyn io::Write) -> Result<()> {
+    let worktrees: Vec<(worktree::Worktree, String)> = worktree::list(repo)?
+        .into_iter()
+        .map(|worktree| {
+            let hash =
+                refs::find_ref(&worktree.repo(), "HEAD").unwrap_or(refs::ZERO_HASH.to_string());
+            (worktree, hash)
+        })
+        .collect();
+    if porcelain {
+        for (worktree, hash) in &worktrees {
+            writeln!(stdout, "worktree {}", worktree.path.display())?;
+            writeln!(stdout, "HEAD {hash}")?;
+            match &worktree.head {
+                Some(refs::Head::Branch(name)) => writeln!(stdout, "branch {name}")?,
+                _ => writeln!(stdout, "detached")?,
+            }
+            match worktree.locked.as_deref() {
+                Some("") => writeln!(stdout, "locked")?,
+                Some(reason) => writeln!(stdout, "locked {reason}")?,
+                None => {}
+            }
+            writeln!(stdout)?;
+        }
+        return Ok(());
+    }
+
+    let abbreviator = object::Abbreviator::from_config(repo)?;
+    let abbrevs: Vec<String> = worktrees
+        .iter()
+        .map(|(_, hash)| match object::exists(repo, hash) {
+            true => abbreviator.abbreviate(hash),
+            false => Ok(hash[..7].to_string()),
+        })
+        .collect::<Result<_>>()?;
+    let paths: Vec<String> = worktrees
+        .iter()
+        .map(|(worktree, _)| worktree.path.display().to_string())
+        .collect();
+    let path_width = paths
+        .iter()
+        .map(|path| width::display_width(path))
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let abbrev_width = abbrevs.iter().map(String::len).max().unwrap_or(0);
+    for (((worktree, _), path), abbrev) in worktrees.iter().zip(&paths).zip(&abbrevs) {
+        let path = width::pad(path, path_width);
+        write!(stdout, "{path} {abbrev:abbrev_width$} ")?;
+        match &worktree.head {
+            Some(refs::Head::Branch(name)) => write!(
+                stdout,
+                "[{}]",
+                name.strip_prefix("refs/heads/").unwrap_or(name)
+            )?,
+            _ => write!(stdout, "(detached HEAD)")?,
+        }
+        if worktree.locked.is_some() {
+            write!(stdout, " locked")?;
+        }
+        writeln!(stdout)?;
+    }
+    Ok(())
+}
+
+/// Locks a linked worktree, so that it isn't removed, with an optional
+/// reason.
+pub fn worktree_lock(repo: &Repo, worktree: &str, reason: Option<&str>) -> Result<()> {
+    let found = worktree::find(repo, worktree)?;
+    let git_dir = found.git_dir(repo).ok_or(anyhow!(
+        "The main working tree cannot be locked or unlocked"
+    ))?;
+    match found.locked.as_deref() {
+        Some("") => return Err(anyhow!("'{worktree}' is already locked")),
+        Some(reason) => return Err(anyhow!("'{worktree}' is already locked, reason: {reason}")),
+        None => {}
+    }
+    let content = reason.map_or(String::new(), |reason| format!("{reason}\n"));
+    fs::write(git_dir.join("locked"), content)?;
+    Ok(())
+}
+
+/// Unlocks a worktree locked with [`worktree_lock`].
+pub fn worktree_unlock(repo: &Repo, worktree: &str) -> Result<()> {
+    let found = worktree::find(repo, worktree)?;
+    let git_dir = found.git_dir(repo).ok_or(anyhow!(
+        "The main working tree cannot be locked or unlocked"
+    ))?;
+    if found.locked.is_none() {
+        return Err(anyhow!("'{worktree}' is not locked"));
+    }
+    fs::remove_file(git_dir.join("locked"))?;
+    Ok(())
+}
+
+/// Deletes a linked worktree and its git folder.
+///
+/// Like git, worktrees with changes or untracked files are only removed
+/// with `force`, and locked worktrees with `force` at least 2.
+pub fn worktree_remove(repo: &Repo, worktree: &str, force: u8) -> Result<()> {
+    let found = worktree::find(repo, worktree)?;
+    let git_dir = found
+        .git_dir(repo)
+        .ok_or(anyhow!("'{worktree}' is a main working tree"))?;
+    match found.locked.as_deref() {
+        Some(_) if force >= 2 => {}
+        Some("") => {
+            return Err(anyhow!(
+                "cannot remove a locked working tree;\n\
+                 use 'remove -f -f' to override or unlock first"
+            ))
+        }
+        Some(reason) => {
+            return Err(anyhow!(
+                "cannot remove a locked working tree, lock reason: {reason}\n\
+                 use 'remove -f -f' to override or unlock first"
+            ))
+        }
+        None => {}
+    }
+    if force == 0 && found.path.exists() {
+        let status = status::Status::compute(&found.repo())?;
+        if !status.paths.is_empty() || !status.untracked.is_empty() {
+            return Err(anyhow!(
+                "'{worktree}' contains modified or untracked files, use --force to delete it"
+            ));
+        }
+    }
+    if found.path.exists() {
+        fs::remove_dir_all(&found.path)?;
+    }
+    fs::remove_dir_all(git_dir)?;
+    Ok(())
+}
+
+/// Writes the index as tree objects and prints the hash of the root tree.
+///
+/// Directories whose cache tree node is valid aren't written again. Unless
+/// `missing_ok` is set, all the objects in the index must exist.
+pub fn write_tree(repo: &Repo, missing_ok: bool, stdout: &mut dyn io::Write) -> Result<()> {
+    let mut index = index::Index::read(repo)?;
+    if !missing_ok {
+        // Submodules are commits from other repositories.
+        if let Some(entry) = index.entries.iter().find(|entry| {
+            entry.mode != 0o160000 && !entry.intent_to_add && !object::exists(repo, &entry.hash)
+        }) {
+            return Err(anyhow!(
+                "invalid object {:o} {} for '{}'",
+                entry.mode,
+                entry.hash,
+                entry.path
+            ));
+        }
+    }
+    let tree = index.write_tree(repo)?;
+    // Save the updated cache tree for the next time.
+    index.write(repo)?;
+    writeln!(stdout, "{tree}")?;
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct FsckOptions {
+    /// Only check that reachable objects exist, without hashing objects or
+    /// reading blobs.
+    pub connectivity_only: bool,
+    /// Print all unreachable objects instead of only the dangling ones.
+    pub unreachable: bool,
+}
+
+/// Checks the objects of the repository and their connectivity, printing
+/// errors to `stderr` and the missing and dangling objects, sorted by hash.
+///
+/// Returns the exit code, see [`fsck::Report::exit_code`].
+pub fn fsck(
+    repo: &Repo,
+    options: &FsckOptions,
+    stdout: &mut dyn io::Write,
+    stderr: &mut dyn io::Write,
+) -> Result<i32> {
+    let report = fsck::check(repo, options.connectivity_only)?;
+    for error in report.errors.iter().chain(&report.broken_refs) {
+        writeln!(stderr, "error: {error}")?;
+    }
+
+    let mut lines = BTreeMap::new();
+    for (hash, object_type) in &report.missing {
+        lines.insert(hash, ("missing", object_type));
+    }
+    for (hash, (object_type, dangling)) in &report.unreachable {
+        if options.unreachable {
+            lines.insert(hash, ("unreachable", object_type));
+        } else if *dangling {
+            lines.insert(hash, ("dangling", object_type));
+        }
+    }
+    for (hash, (state, object_type)) in lines {
+        writeln!(stdout, "{state} {object_type} {hash}")?;
+    }
+    Ok(report.exit_code())
+}
+
+#[derive(Debug, Default)]
+pub struct PushOptions {
+    /// Update remote refs even if it's not a fast-forward, like a `+` in front
+    /// of every refspec.
+    pub force: bool,
+    /// Make the remote branches the upstream of the pushed branches.
+    pub set_upstream: bool,
+    /// Show what would be pushed, without pushing it.
+    pub dry_run: bool,
+}
+
+/// Pushes local refs to a remote, and updates the matching remote-tracking
+/// branches.
+///
+/// Without a remote, the current branch's `branch.<name>.pushRemote`,
+/// `remote.pushDefault`, then `branch.<name>.remote` are used, defaulting to
+/// `origin`. Without refspecs, what is pushed depends on `push.default`, see
+/// [`remote::PushDefault`]. With `push.autoSetupRemote`, pushing a branch
+/// without upstream this way also sets its upstream.
+///
+/// Remote refs are only updated if it's a fast-forward, unless forced. Like
+/// git, a branch checked out in a remote with a working tree can't be updated
+/// unless `receive.denyCurrentBranch` allows it, hidden refs can't be updated,
+/// see [`remote::is_hidden`], and the push is refused if the objects to send
+/// are bigger than the remote's `receive.maxInputSize`.
+pub fn push(
+    repo: &Repo,
+    remote: Option<&str>,
+    refspecs: &[String],
+    options: &PushOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let config = config::Config::load(repo)?;
+    let branch = match refs::head(repo)? {
+        refs::Head::Branch(name) => name.strip_prefix("refs/heads/").map(str::to_string),
+        refs::Head::Detached(_) => None,
+    };
+    let branch_config = |key: &str| -> Option<String> {
+        let branch = branch.as_ref()?;
+        config
+            .get(&format!("branch.{branch}.{key}"))
+            .map(str::to_string)
+    };
+    let remote_name = match remote {
+        Some(remote) => remote.to_string(),
+        None => branch_config("pushRemote")
+            .or_else(|| config.get("remote.pushDefault").map(str::to_string))
+            .or_else(|| branch_config("remote"))
+            .unwrap_or_else(|| "origin".to_string()),
+    };
+    let remote = remote::Remote::open(repo, &config, &remote_name)?;
+
+    let mut set_upstream = options.set_upstream;
+    let refspecs = if refspecs.is_empty() {
+        default_push_refspecs(repo, &config, &remote, branch.as_deref(), &mut set_upstream)?
+    } else {
+        refspecs
+            .iter()
+            .map(|refspec| remote::Refspec::parse(refspec))
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let remote_config = config::Config::load(&remote.repo)?;
+    let deny_current_branch = !matches!(
+        remote_config.get("receive.denyCurrentBranch"),
+        Some("ignore" | "warn" | "false")
+    );
+    let remote_head = match remote.repo.is_bare() {
+        true => None,
+        false => match refs::head(&remote.repo)? {
+            refs::Head::Branch(name) => Some(name),
+            refs::Head::Detached(_) => None,
+        },
+    };
+
+    let mut updates = vec![];
+    for refspec in refspecs {
+        let mut update = PushUpdate::new(repo, &remote, &refspec, options.force)?;
+        // Hidden refs aren't advertised, so they look like new refs.
+        if remote::is_hidden(&remote_config, "receive", &update.dst) {
+            update.old = None;
+        }
+        if updates
+            .iter()
+            .any(|other: &PushUpdate| other.dst == update.dst && other.new != update.new)
+        {
+            return Err(anyhow!(
+                "multiple updates for ref '{}' not allowed",
+                update.dst
+            ));
+        }
+        updates.push(update);
+    }
+
+    let abbreviator = object::Abbreviator::from_config(repo)?;
+    let mut lines = vec![];
+    let mut rejected = false;
+    let mut accepted = vec![];
+    for update in &updates {
+        let (flag, summary, reason) = match &update.old {
+            Some(old) if *old == update.new => continue,
+            None => {
+                let summary = match update.dst.strip_prefix("refs/") {
+                    Some(name) if name.starts_with("heads/") => "[new branch]",
+                    Some(name) if name.starts_with("tags/") => "[new tag]",
+                    _ => "[new reference]",
+                };
+                ('*', summary.to_string(), "")
+            }
+            Some(old) => {
+                let old_abbrev = abbreviator.abbreviate(old)?;
+                let new_abbrev = abbreviator.abbreviate(&update.new)?;
+                let is_tag = update.dst.starts_with("refs/tags/");
+                let known = object::exists(repo, old);
+                if !is_tag && known && graph::is_ancestor(repo, old, &update.new)? {
+                    (' ', format!("{old_abbrev}..{new_abbrev}"), "")
+                } else if update.force {
+                    (
+                        '+',
+                        format!("{old_abbrev}...{new_abbrev}"),
+                        " (forced update)",
+                    )
                 } else {
-                    next_object_rev = Some(commit.parent.clone());
+                    let reason = match (is_tag, known) {
+                        (true, _) => " (already exists)",
+                        (false, false) => " (fetch first)",
+                        (false, true) => " (non-fast-forward)",
+                    };
+                    ('!', "[rejected]".to_string(), reason)
+                }
+            }
+        };
+        // The remote refuses to update its hidden refs, and its checked out
+        // branch, which would make its working tree and index out of date.
+        let remote_reason = if remote::is_hidden(&remote_config, "receive", &update.dst) {
+            Some(" (deny updating a hidden ref)")
+        } else if deny_current_branch && remote_head.as_ref() == Some(&update.dst) {
+            Some(" (branch is currently checked out)")
+        } else {
+            None
+        };
+        let (flag, summary, reason) = match remote_reason {
+            Some(reason) if flag != '!' => ('!', "[remote rejected]".to_string(), reason),
+            _ => (flag, summary, reason),
+        };
+        lines.push((flag, summary, update, reason));
+        match flag {
+            '!' => rejected = true,
+            _ => accepted.push(update),
+        }
+    }
+
+    // Like git's `receive.maxInputSize`, the remote refuses to receive too
+    // many objects at once.
+    let mut missing = vec![];
+    if !options.dry_run {
+        let tips: Vec<String> = accepted.iter().map(|update| update.new.clone()).collect();
+        missing = remote::missing_objects(repo, &remote.repo, &tips)?;
+        let max_input_size = remote_config.get_int("receive.maxInputSize")?;
+        if let Some(max_input_size) = max_input_size.filter(|size| *size > 0) {
+            if remote::stored_size(repo, &missing)? > max_input_size as u64 {
+                writeln!(stdout, "remote: fatal: pack exceeds maximum allowed size")?;
+                for (flag, summary, _, reason) in lines.iter_mut().filter(|line| line.0 != '!') {
+                    (*flag, *summary, *reason) =
+                        ('!', "[remote rejected]".to_string(), " (unpacker error)");
                 }
+                rejected = true;
+                accepted.clear();
+                missing.clear();
             }
         }
     }
+
+    if lines.is_empty() {
+        writeln!(stdout, "Everything up-to-date")?;
+    } else {
+        writeln!(stdout, "To {}", remote.url)?;
+        for (flag, summary, update, reason) in lines {
+            let src = refs::shorten(&update.src);
+            let dst = refs::shorten(&update.dst);
+            writeln!(stdout, " {flag} {summary:<17} {src} -> {dst}{reason}")?;
+        }
+    }
+
+    if !options.dry_run {
+        let committer = ident::Ident::new(&config, ident::Role::Committer)?.to_string();
+        let mut remote_transaction = refs::Transaction::new();
+        let mut local_transaction = refs::Transaction::new();
+        remote::copy_objects(repo, &remote.repo, missing.iter())?;
+        for update in &accepted {
+            let old = update.old.as_deref().unwrap_or(refs::ZERO_HASH);
+            remote_transaction.update(&update.dst, &update.new, Some(old), "push");
+            if let Some(tracking_ref) = remote.tracking_ref(&update.dst) {
+                local_transaction.update(&tracking_ref, &update.new, None, "update by push");
+            }
+        }
+        remote_transaction.commit(&remote.repo, Some(&committer))?;
+        local_transaction.commit(repo, Some(&committer))?;
+
+        // Up-to-date branches get their upstream set too.
+        let pushed = updates.iter().filter(|update| {
+            update.old.as_ref() == Some(&update.new)
+                || accepted.iter().any(|accepted| accepted.dst == update.dst)
+        });
+        for update in pushed.filter(|update| update.dst.starts_with("refs/heads/")) {
+            let (true, Some(name), Some(branch)) = (
+                set_upstream,
+                &remote.name,
+                update.src.strip_prefix("refs/heads/"),
+            ) else {
+                continue;
+            };
+            let path = repo.common_dir().join("config");
+            config::set_value(&path, &format!("branch.{branch}.remote"), name)?;
+            config::set_value(&path, &format!("branch.{branch}.merge"), &update.dst)?;
+            writeln!(
+                stdout,
+                "branch '{branch}' set up to track '{name}/{}'.",
+                refs::shorten(&update.dst)
+            )?;
+        }
+    }
+
+    if rejected {
+        return Err(anyhow!("failed to push some refs to '{}'", remote.url));
+    }
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// A remote ref to update with a local object.
+struct PushUpdate {
+    /// The local ref, or the rev if it's not a ref.
+    src: String,
+    dst: String,
+    /// What the remote ref points to, `None` if it doesn't exist.
+    old: Option<String>,
+    new: String,
+    force: bool,
+}
 
-    #[test]
+impl PushUpdate {
+    fn new(
+        repo: &Repo,
+        remote: &remote::Remote,
+        refspec: &remote::Refspec,
+        force: bool,
+    ) -> Result<PushUpdate> {
+        let src = match refspec.src.as_str() {
+            // Pushing `HEAD` pushes the current branch.
+            "HEAD" => match refs::head(repo)? {
+                refs::Head::Branch(name) => Some(name),
+                refs::Head::Detached(_) => None,
+            },
+            src => refs::expand(repo, src),
+        };
+        let (src, new) = match src {
+            Some(src) => {
+                let hash = refs::find_ref(repo, &src)?;
+                (src, hash)
+            }
+            None => match Object::resolve_rev(repo, &refspec.src) {
+                Ok(hash) => (refspec.src.clone(), hash),
+                Err(_) => return Err(anyhow!("src refspec {} does not match any", refspec.src)),
+            },
+        };
+        let dst = match &refspec.dst {
+            Some(dst) if dst.starts_with("refs/") => dst.clone(),
+            Some(dst) => match refs::expand(&remote.repo, dst) {
+                Some(dst) => dst,
+                None if src.starts_with("refs/tags/") => format!("refs/tags/{dst}"),
+                None => format!("refs/heads/{dst}"),
+            },
+            None if src.starts_with("refs/") => src.clone(),
+            None => {
+                return Err(anyhow!(
+                    "The destination you provided is not a full refname \
+                     (i.e., starting with \"refs/\") for {}",
+                    refspec.src
+                ))
+            }
+        };
+        refs::validate_name(&dst)?;
+        Ok(PushUpdate {
+            src,
+            old: refs::find_ref(&remote.repo, &dst).ok(),
+            dst,
+            new,
+            force: force || refspec.force,
+        })
+    }
+}
+
+/// Returns the refspecs to push when none are given, according to
+/// `push.default`, and sets `set_upstream` if `push.autoSetupRemote` applies.
+fn default_push_refspecs(
+    repo: &Repo,
+    config: &config::Config,
+    remote: &remote::Remote,
+    branch: Option<&str>,
+    set_upstream: &mut bool,
+) -> Result<Vec<remote::Refspec>> {
+    let push_default = remote::PushDefault::from_config(config)?;
+    let refspec = |src: String, dst: Option<String>| remote::Refspec {
+        force: false,
+        src,
+        dst,
+    };
+    match push_default {
+        remote::PushDefault::Nothing => {
+            return Err(anyhow!(
+                "You didn't specify any refspecs to push, and push.default is \"nothing\"."
+            ))
+        }
+        remote::PushDefault::Matching => {
+            let remote_refs = refs::read_all(&remote.repo)?;
+            let matching = refs::read_all(repo)?
+                .into_keys()
+                .filter(|name| name.starts_with("refs/heads/") && remote_refs.contains_key(name))
+                .map(|name| refspec(name, None))
+                .collect();
+            return Ok(matching);
+        }
+        _ => {}
+    }
+
+    let remote_name = remote.name.as_deref().unwrap_or(&remote.url);
+    let Some(branch) = branch else {
+        return Err(anyhow!(
+            "You are not currently on a branch.\n\
+             To push the history leading to the current (detached HEAD)\n\
+             state now, use\n\n    \
+             git push {remote_name} HEAD:<name-of-remote-branch>\n"
+        ));
+    };
+    let current = format!("refs/heads/{branch}");
+    let upstream = config.get(&format!("branch.{branch}.merge"));
+    if upstream.is_none() && config.get_bool("push.autoSetupRemote")? == Some(true) {
+        *set_upstream = true;
+        return Ok(vec![refspec(current, None)]);
+    }
+    // Whether the remote is the one the branch is fetched from.
+    let fetch_remote = config
+        .get(&format!("branch.{branch}.remote"))
+        .unwrap_or("origin");
+    let same_remote = remote.name.as_deref() == Some(fetch_remote);
+    let no_upstream = || {
+        anyhow!(
+            "The current branch {branch} has no upstream branch.\n\
+             To push the current branch and set the remote as upstream, use\n\n    \
+             git push --set-upstream {remote_name} {branch}\n\n\
+             To have this happen automatically for branches without a tracking\n\
+             upstream, see 'push.autoSetupRemote' in 'git help config'.\n"
+        )
+    };
+    match push_default {
+        remote::PushDefault::Upstream if !same_remote => Err(anyhow!(
+            "You are pushing to remote '{remote_name}', which is not the upstream of\n\
+             your current branch '{branch}', without telling me what to push\n\
+             to update which remote branch."
+        )),
+        remote::PushDefault::Upstream => {
+            let upstream = upstream.ok_or_else(no_upstream)?;
+            Ok(vec![refspec(current, Some(upstream.to_string()))])
+        }
+        remote::PushDefault::Simple if same_remote => match upstream {
+            None => Err(no_upstream()),
+            Some(upstream) if upstream != current => Err(anyhow!(
+                "The upstream branch of your current branch does not match\n\
+                 the name of your current branch.  To push to the upstream branch\n\
+                 on the remote, use\n\n    \
+                 git push {remote_name} HEAD:{}\n\n\
+                 To push to the branch of the same name on the remote, use\n\n    \
+                 git push {remote_name} HEAD\n",
+                refs::shorten(upstream)
+            )),
+            Some(_) => Ok(vec![refspec(current, None)]),
+        },
+        _ => Ok(vec![refspec(current, None)]),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FetchOptions {
+    /// Limit the history to this many commits from the fetched commits.
+    pub depth: Option<usize>,
+    /// Fetch this many more commits below the shallow commits.
+    pub deepen: Option<usize>,
+    /// Limit the history to the commits made after this date.
+    pub shallow_since: Option<String>,
+    /// Limit the history to the commits that can't be reached from these
+    /// remote refs.
+    pub shallow_exclude: Vec<String>,
+    /// Fetch the whole history of a shallow repository.
+    pub unshallow: bool,
+}
+
+/// Fetches refs from a remote, updates the matching local refs and writes
+/// them to `FETCH_HEAD`.
+///
+/// Without a remote, the current branch's `branch.<name>.remote` is used,
+/// defaulting to `origin`. Without refspecs, the refspecs of
+/// `remote.<name>.fetch` are used. Like git, tags of the remote pointing to
+/// fetched commits are fetched too. Refs hidden by the remote, see
+/// [`remote::is_hidden`], can't be fetched.
+///
+/// The fetched history can be limited by `options`, making the repository
+/// shallow, see [`remote::fetch_objects`]. Local refs are only updated if
+/// it's a fast-forward, unless forced.
+pub fn fetch(
+    repo: &Repo,
+    remote: Option<&str>,
+    refspecs: &[String],
+    options: &FetchOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    // Like git, reflog messages show the arguments.
+    let mut args = vec!["fetch".to_string()];
+    args.extend(options.depth.map(|depth| format!("--depth {depth}")));
+    args.extend(options.deepen.map(|deepen| format!("--deepen {deepen}")));
+    if let Some(date) = &options.shallow_since {
+        args.push(format!("--shallow-since {date}"));
+    }
+    for name in &options.shallow_exclude {
+        args.push(format!("--shallow-exclude {name}"));
+    }
+    if options.unshallow {
+        args.push("--unshallow".to_string());
+    }
+    args.extend(remote.map(str::to_string));
+    args.extend(refspecs.iter().cloned());
+    let action = args.join(" ");
+    let config = config::Config::load(repo)?;
+    let branch = match refs::head(repo)? {
+        refs::Head::Branch(name) => name.strip_prefix("refs/heads/").map(str::to_string),
+        refs::Head::Detached(_) => None,
+    };
+    let branch_config =
+        |key: &str| -> Option<&str> { config.get(&format!("branch.{}.{key}", branch.as_ref()?)) };
+    let remote_name = match remote {
+        Some(remote) => remote,
+        None => branch_config("remote").unwrap_or("origin"),
+    };
+    let remote = remote::Remote::open(repo, &config, remote_name)?;
+    // Hidden refs can't be fetched, as if they didn't exist.
+    let remote_config = config::Config::load(&remote.repo)?;
+    let mut remote_refs = refs::read_all(&remote.repo)?;
+    remote_refs.retain(|name, _| !remote::is_hidden(&remote_config, "uploadpack", name));
+    let mut shallow = shallow::read(repo)?;
+    let depth = fetch_depth(&remote, &remote_refs, options, !shallow.is_empty())?;
+
+    let configured_refspecs = match &remote.name {
+        Some(name) => {
+            let refspecs = config.get_all(&format!("remote.{name}.fetch"));
+            match refspecs.is_empty() {
+                true => vec![format!("+refs/heads/*:refs/remotes/{name}/*")],
+                false => refspecs.into_iter().map(str::to_string).collect(),
+            }
+        }
+        None => vec!["HEAD".to_string()],
+    };
+    let configured_refspecs = configured_refspecs
+        .iter()
+        .map(|refspec| remote::Refspec::parse(refspec))
+        .collect::<Result<Vec<_>>>()?;
+    // The branch to merge, for `git pull`.
+    let merge = match branch_config("remote").unwrap_or("origin") {
+        name if remote.name.as_deref() == Some(name) => branch_config("merge"),
+        _ => None,
+    };
+
+    let mut fetched: Vec<FetchedRef> = vec![];
+    if refspecs.is_empty() {
+        for refspec in &configured_refspecs {
+            fetched.extend(FetchedRef::matching(repo, &remote, &remote_refs, refspec)?);
+        }
+        // Without a configured remote, its HEAD is fetched to be merged.
+        for fetched in &mut fetched {
+            fetched.for_merge = remote.name.is_none() || merge == Some(fetched.src.as_str());
+        }
+    } else {
+        for refspec in refspecs {
+            let refspec = remote::Refspec::parse(refspec)?;
+            let matching = FetchedRef::matching(repo, &remote, &remote_refs, &refspec)?;
+            if refspec.is_pattern() {
+                fetched.extend(matching);
+                continue;
+            }
+            // Like git, the remote-tracking branches of the fetched refs are
+            // updated too.
+            for fetched_ref in matching {
+                let tracking_refs: Vec<_> = configured_refspecs
+                    .iter()
+                    .filter_map(|configured| {
+                        Some(FetchedRef {
+                            dst: Some(configured.map_pattern(&fetched_ref.src)?),
+                            force: configured.force,
+                            ..fetched_ref.clone()
+                        })
+                    })
+                    .collect();
+                fetched.push(FetchedRef {
+                    for_merge: true,
+                    ..fetched_ref
+                });
+                fetched.extend(tracking_refs);
+            }
+        }
+    }
+
+    // Copy the history of the fetched commits, then the tags and the other
+    // objects the refs point to.
+    let mut tips = vec![];
+    for fetched in &fetched {
+        let peeled = Object::peel(&remote.repo, &fetched.hash)?;
+        if object::read_header(&remote.repo, &peeled)?.0 == ObjectType::Commit {
+            tips.push(peeled);
+        }
+    }
+    let was_shallow = shallow.clone();
+    remote::fetch_objects(&remote.repo, repo, &tips, &depth, &mut shallow)?;
+    if shallow != was_shallow {
+        shallow::write(repo, &shallow)?;
+    }
+    for fetched in &fetched {
+        remote::send_objects(&remote.repo, repo, &fetched.hash)?;
+    }
+
+    // Tags pointing to fetched commits are followed when refs are stored.
+    if fetched.iter().any(|fetched| fetched.dst.is_some()) {
+        for name in remote_refs.keys() {
+            if !name.starts_with("refs/tags/")
+                || refs::find_ref(repo, name).is_ok()
+                || fetched
+                    .iter()
+                    .any(|fetched| fetched.dst.as_ref() == Some(name))
+            {
+                continue;
+            }
+            let hash = refs::find_ref(&remote.repo, name)?;
+            if object::exists(repo, &Object::peel(&remote.repo, &hash)?) {
+                remote::send_objects(&remote.repo, repo, &hash)?;
+                fetched.push(FetchedRef {
+                    src: name.clone(),
+                    dst: Some(name.clone()),
+                    hash,
+                    force: false,
+                    for_merge: false,
+                });
+            }
+        }
+    }
+
+    let width = fetched
+        .iter()
+        .map(|fetched| refs::shorten(&fetched.src).len())
+        .max()
+        .unwrap_or(0)
+        .max(10);
+    // Fetching doesn't need an identity, the reflogs are only written with
+    // one.
+    let committer = ident::Ident::new(&config, ident::Role::Committer)
+        .ok()
+        .map(|committer| committer.to_string());
+    let abbreviator = object::Abbreviator::new(repo, object::abbrev_len(&config)?);
+    let mut transaction = refs::Transaction::new();
+    let mut lines = vec![];
+    let mut rejected = false;
+    for fetched in &fetched {
+        let src = refs::shorten(&fetched.src);
+        let Some(dst) = &fetched.dst else {
+            let kind = match fetched.src.strip_prefix("refs/") {
+                Some(name) if name.starts_with("tags/") => "tag",
+                Some(name) if !name.starts_with("heads/") => "",
+                _ => "branch",
+            };
+            lines.push(format!(" * {kind:<17} {src:<width$} -> FETCH_HEAD"));
+            continue;
+        };
+        let old = refs::find_ref(repo, dst).ok();
+        let (flag, summary, reason, message) = match &old {
+            Some(old) if *old == fetched.hash => continue,
+            None => {
+                let summary = match dst.strip_prefix("refs/") {
+                    Some(name) if name.starts_with("tags/") => "[new tag]",
+                    Some(name) if name.starts_with("heads/") || name.starts_with("remotes/") => {
+                        "[new branch]"
+                    }
+                    _ => "[new ref]",
+                };
+                ('*', summary.to_string(), "", "storing head")
+            }
+            Some(old) => {
+                let old_abbrev = abbreviator.abbreviate(old)?;
+                let new_abbrev = abbreviator.abbreviate(&fetched.hash)?;
+                let is_tag = dst.starts_with("refs/tags/");
+                if !is_tag && graph::is_ancestor(repo, old, &fetched.hash)? {
+                    let summary = format!("{old_abbrev}..{new_abbrev}");
+                    (' ', summary, "", "fast-forward")
+                } else if fetched.force {
+                    let summary = format!("{old_abbrev}...{new_abbrev}");
+                    ('+', summary, "  (forced update)", "forced-update")
+                } else if is_tag {
+                    let reason = "  (would clobber existing tag)";
+                    ('!', "[rejected]".to_string(), reason, "")
+                } else {
+                    let reason = "  (non-fast-forward)";
+                    ('!', "[rejected]".to_string(), reason, "")
+                }
+            }
+        };
+        let short_dst = refs::shorten(dst);
+        lines.push(format!(
+            " {flag} {summary:<17} {src:<width$} -> {short_dst}{reason}"
+        ));
+        if flag == '!' {
+            rejected = true;
+            continue;
+        }
+        let old = old.as_deref().unwrap_or(refs::ZERO_HASH);
+        let message = format!("{action}: {message}");
+        transaction.update(dst, &fetched.hash, Some(old), &message);
+    }
+    transaction.commit(repo, committer.as_deref())?;
+
+    let mut fetch_head = lockfile::LockFile::acquire(&repo.git_dir().join("FETCH_HEAD"))?;
+    let (for_merge, not_for_merge): (Vec<_>, Vec<_>) =
+        fetched.iter().partition(|fetched| fetched.for_merge);
+    let mut written = HashSet::new();
+    for fetched in for_merge.into_iter().chain(not_for_merge) {
+        if !written.insert(&fetched.src) {
+            continue;
+        }
+        let description = match fetched.src.strip_prefix("refs/") {
+            Some(name) => match name.split_once('/') {
+                Some(("heads", branch)) => format!("branch '{branch}' of "),
+                Some(("tags", tag)) => format!("tag '{tag}' of "),
+                _ => format!("'{}' of ", fetched.src),
+            },
+            None => String::new(),
+        };
+        let merge = if fetched.for_merge {
+            ""
+        } else {
+            "not-for-merge"
+        };
+        writeln!(
+            fetch_head,
+            "{}\t{merge}\t{description}{}",
+            fetched.hash, remote.url
+        )?;
+    }
+    fetch_head.commit()?;
+
+    if !lines.is_empty() {
+        writeln!(stdout, "From {}", remote.url)?;
+        for line in lines {
+            writeln!(stdout, "{line}")?;
+        }
+    }
+    if rejected {
+        return Err(anyhow!("some local refs could not be updated"));
+    }
+    Ok(())
+}
+
+/// Returns how much history to fetch given the options of [`fetch`],
+/// resolving the excluded refs on the remote.
+fn fetch_depth(
+    remote: &remote::Remote,
+    remote_refs: &BTreeMap<String, String>,
+    options: &FetchOptions,
+    is_shallow: bool,
+) -> Result<remote::Depth> {
+    let given: Vec<&str> = [
+        (options.depth.is_some(), "--depth"),
+        (options.deepen.is_some(), "--deepen"),
+        (options.shallow_since.is_some(), "--shallow-since"),
+        (!options.shallow_exclude.is_empty(), "--shallow-exclude"),
+        (options.unshallow, "--unshallow"),
+    ]
+    .into_iter()
+    .filter_map(|(given, option)| given.then_some(option))
+    .collect();
+    if let [first, second, ..] = given[..] {
+        return Err(anyhow!(
+            "options '{first}' and '{second}' cannot be used together"
+        ));
+    }
+    if options.depth == Some(0) {
+        return Err(anyhow!("depth 0 is not a positive number"));
+    }
+    if options.unshallow && !is_shallow {
+        return Err(anyhow!(
+            "--unshallow on a complete repository does not make sense"
+        ));
+    }
+    let mut excluded = vec![];
+    for name in &options.shallow_exclude {
+        let hash = refs::expand(&remote.repo, name)
+            .filter(|name| remote_refs.contains_key(name))
+            .and_then(|name| refs::find_ref(&remote.repo, &name).ok())
+            .ok_or_else(|| anyhow!("couldn't find remote ref {name}"))?;
+        excluded.push(Object::peel(&remote.repo, &hash)?);
+    }
+    Ok(if let Some(depth) = options.depth {
+        remote::Depth::Commits(depth)
+    } else if let Some(deepen) = options.deepen {
+        remote::Depth::Deepen(deepen)
+    } else if let Some(date) = &options.shallow_since {
+        remote::Depth::Since(ident::parse_date(date)?.0)
+    } else if options.unshallow {
+        remote::Depth::Unshallow
+    } else if !excluded.is_empty() {
+        remote::Depth::Exclude(excluded)
+    } else {
+        remote::Depth::Full
+    })
+}
+
+/// A remote ref to fetch.
+#[derive(Clone)]
+struct FetchedRef {
+    /// The remote ref, or `HEAD`.
+    src: String,
+    /// The local ref to update, `None` to only write it to `FETCH_HEAD`.
+    dst: Option<String>,
+    hash: String,
+    force: bool,
+    /// Whether `git pull` would merge it.
+    for_merge: bool,
+}
+
+impl FetchedRef {
+    /// Returns the remote refs matched by a refspec.
+    fn matching(
+        repo: &Repo,
+        remote: &remote::Remote,
+        remote_refs: &BTreeMap<String, String>,
+        refspec: &remote::Refspec,
+    ) -> Result<Vec<FetchedRef>> {
+        let fetched_ref = |src: &str, dst: Option<String>| -> Result<FetchedRef> {
+            Ok(FetchedRef {
+                src: src.to_string(),
+                dst,
+                hash: refs::find_ref(&remote.repo, src)?,
+                force: refspec.force,
+                for_merge: false,
+            })
+        };
+        if refspec.is_pattern() {
+            return remote_refs
+                .keys()
+                .filter_map(|name| Some((name, refspec.map_pattern(name)?)))
+                .map(|(name, dst)| fetched_ref(name, Some(dst)))
+                .collect();
+        }
+        let src = match refspec.src.as_str() {
+            "HEAD" => Some("HEAD".to_string()),
+            src => refs::expand(&remote.repo, src),
+        };
+        let src = src
+            .filter(|src| remote_refs.contains_key(src))
+            .ok_or_else(|| anyhow!("couldn't find remote ref {}", refspec.src))?;
+        let dst = match &refspec.dst {
+            Some(dst) if dst.starts_with("refs/") => Some(dst.clone()),
+            Some(dst) => refs::expand(repo, dst).or(Some(format!("refs/heads/{dst}"))),
+            None => None,
+        };
+        Ok(vec![fetched_ref(&src, dst)?])
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReadTreeOptions {
+    /// Merge the trees with the index instead of replacing it.
+    pub merge: bool,
+    /// Update the working tree to match the new index.
+    pub update: bool,
+    /// Read the tree under this directory, keeping the current entries.
+    pub prefix: Option<String>,
+}
+
+/// Reads trees into the index.
+///
+/// By default, the index is replaced by the files of the trees, later trees
+/// taking precedence. With `prefix`, a single tree is added under a
+/// directory instead, which must not have entries yet.
+///
+/// With `merge`, a single tree replaces the index while keeping the stat
+/// data of unchanged entries, two trees move the index from the first to
+/// the second while keeping the staged changes, and three trees merge the
+/// second and the third given their merge base, the first, leaving unmerged
+/// stages for conflicts. See [`read_tree::two_way`] and
+/// [`read_tree::three_way`]. Paths whose staged or unstaged changes would
+/// be lost are refused.
+pub fn read_tree(repo: &Repo, trees: &[String], options: &ReadTreeOptions) -> Result<()> {
+    if options.update && !options.merge && options.prefix.is_none() {
+        return Err(anyhow!(
+            "-u is meaningless without -m, --reset, or --prefix"
+        ));
+    }
+    if trees.is_empty() || trees.len() > 3 {
+        return Err(anyhow!("Expected one to three trees, got {}", trees.len()));
+    }
+    if options.prefix.is_some() && trees.len() > 1 {
+        return Err(anyhow!("--prefix expects a single tree"));
+    }
+    let mut tree_entries = vec![];
+    for rev in trees {
+        let mut files = BTreeMap::new();
+        status::tree_files(repo, &rev_tree(repo, rev)?, "", &mut files)?;
+        tree_entries.push(files);
+    }
+
+    let mut index = index::Index::read(repo)?;
+    if let Some(prefix) = &options.prefix {
+        // `--prefix=dir` and `--prefix=dir/` are the same, and `--prefix=` is
+        // the top of the working tree.
+        let prefix = match prefix.trim_end_matches('/') {
+            "" => String::new(),
+            prefix => format!("{prefix}/"),
+        };
+        let existing: HashSet<&str> = index
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect();
+        let mut checkouts = vec![];
+        // The paths and their parent directories, which replace the
+        // entries they conflict with, like git.
+        let mut new_paths = HashSet::new();
+        let mut new_dirs = HashSet::new();
+        for (path, (mode, hash)) in &tree_entries[0] {
+            let path = format!("{prefix}{path}");
+            if existing.contains(path.as_str()) {
+                return Err(anyhow!(
+                    "Entry '{path}' overlaps with an existing entry.  Cannot bind."
+                ));
+            }
+            new_dirs.extend(path.match_indices('/').map(|(i, _)| path[..i].to_string()));
+            new_paths.insert(path.clone());
+            checkouts.push(index::IndexEntry {
+                mode: *mode,
+                hash: hash.clone(),
+                path,
+                ..Default::default()
+            });
+        }
+        let conflicting: Vec<String> = index
+            .entries
+            .iter()
+            .map(|entry| &entry.path)
+            .filter(|path| {
+                new_dirs.contains(path.as_str())
+                    || path
+                        .match_indices('/')
+                        .any(|(i, _)| new_paths.contains(&path[..i]))
+            })
+            .cloned()
+            .collect();
+        for path in conflicting {
+            index.remove(&path);
+        }
+        let filters = filter::Filters::new(repo)?;
+        let modes = index::FileModes::load(repo)?;
+        let big_file_threshold = config::Config::load(repo)?.big_file_threshold()?;
+        for entry in checkouts {
+            let entry = match options.update {
+                true => checkout_entry(repo, &filters, modes, big_file_threshold, &entry)?,
+                false => entry,
+            };
+            index.add(entry);
+        }
+        return index.write(repo);
+    }
+
+    if !options.merge {
+        let mut files = BTreeMap::new();
+        for tree_files in tree_entries {
+            files.extend(tree_files);
+        }
+        index.entries = files
+            .into_iter()
+            .map(|(path, (mode, hash))| index::IndexEntry {
+                mode,
+                hash,
+                path,
+                ..Default::default()
+            })
+            .collect();
+        index.cache_tree = None;
+        index.untracked_cache = None;
+        // Prime the cache tree, whose trees all exist.
+        index.write_tree(repo)?;
+        return index.write(repo);
+    }
+
+    if index.entries.iter().any(|entry| entry.stage != 0) {
+        return Err(anyhow!("You need to resolve your current index first"));
+    }
+    let rejected = unpack_trees(repo, &mut index, &tree_entries, options.update)?.rejected;
+    if let Some((path, rejection)) = rejected.first() {
+        return Err(anyhow!(match rejection {
+            Rejection::StagedChanges => {
+                format!("Entry '{path}' would be overwritten by merge. Cannot merge.")
+            }
+            Rejection::NotUptodate => format!("Entry '{path}' not uptodate. Cannot merge."),
+            Rejection::UntrackedRemoved => {
+                format!("Untracked working tree file '{path}' would be removed by merge.")
+            }
+            Rejection::UntrackedOverwritten => {
+                format!("Untracked working tree file '{path}' would be overwritten by merge.")
+            }
+        }));
+    }
+    index.write(repo)
+}
+
+/// Why [`unpack_trees`] refused to update a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rejection {
+    /// The changes staged in the index would be lost.
+    StagedChanges,
+    /// The changes in the working tree would be lost.
+    NotUptodate,
+    /// An untracked file would be removed.
+    UntrackedRemoved,
+    /// An untracked file would be overwritten.
+    UntrackedOverwritten,
+}
+
+/// What [`unpack_trees`] did.
+struct Unpacked {
+    /// The paths that were refused.
+    rejected: Vec<(String, Rejection)>,
+    /// The warnings about the files checked out, like the paths only
+    /// differing by case on a case-insensitive file system, of which only
+    /// the first is in the working tree.
+    warnings: Vec<String>,
+}
+
+/// Merges one to three trees into an index without unmerged entries, like
+/// `read-tree -m`, and updates the working tree with `update`. See
+/// [`read_tree`].
+///
+/// If some paths are rejected, the working tree is left alone, but `index`
+/// must not be written.
+fn unpack_trees(
+    repo: &Repo,
+    index: &mut index::Index,
+    tree_entries: &[BTreeMap<String, read_tree::Entry>],
+    update: bool,
+) -> Result<Unpacked> {
+    // When there is no index yet, the first of two trees isn't checked out
+    // so there is nothing to carry over.
+    let initial_checkout = !repo.git_dir().join("index").exists();
+    let modes = index::FileModes::load(repo)?;
+    let big_file_threshold = config::Config::load(repo)?.big_file_threshold()?;
+    let filters = filter::Filters::new(repo)?;
+    let current: BTreeMap<String, index::IndexEntry> = index
+        .entries
+        .iter()
+        .map(|entry| (entry.path.clone(), entry.clone()))
+        .collect();
+    let paths: BTreeSet<&String> = current
+        .keys()
+        .chain(tree_entries.iter().flat_map(|files| files.keys()))
+        .collect();
+
+    let mut rejected = vec![];
+    let mut removals = vec![];
+    let mut checkouts = vec![];
+    for path in paths {
+        let existing = current.get(path);
+        let index_entry = existing.map(|entry| (entry.mode, entry.hash.clone()));
+        let versions: Vec<Option<&read_tree::Entry>> =
+            tree_entries.iter().map(|files| files.get(path)).collect();
+        let merge = match versions[..] {
+            [tree] => Some(read_tree::one_way(index_entry.as_ref(), tree)),
+            [_, new] if initial_checkout => Some(read_tree::one_way(None, new)),
+            [old, new] => read_tree::two_way(index_entry.as_ref(), old, new),
+            [base, ours, theirs] => read_tree::three_way(index_entry.as_ref(), base, ours, theirs),
+            _ => unreachable!("the number of trees is checked above"),
+        };
+        let Some(merge) = merge else {
+            rejected.push((path.clone(), Rejection::StagedChanges));
+            continue;
+        };
+        if merge == read_tree::Merge::Keep {
+            continue;
+        }
+
+        // A path behind a symlink isn't in the working tree, the symlink is
+        // replaced by a folder when checking it out.
+        let full_path = repo.verified_path(path)?;
+        match existing {
+            Some(entry) if !worktree_matches(repo, &filters, entry, modes)? => {
+                rejected.push((path.clone(), Rejection::NotUptodate));
+                continue;
+            }
+            None if update
+                && matches!(merge, read_tree::Merge::Take(_))
+                && !repo.has_symlink_leading_path(path)
+                && is_in_the_way(&full_path, path, &current)? =>
+            {
+                let rejection = match merge {
+                    read_tree::Merge::Take(None) => Rejection::UntrackedRemoved,
+                    _ => Rejection::UntrackedOverwritten,
+                };
+                rejected.push((path.clone(), rejection));
+                continue;
+            }
+            _ => {}
+        }
+
+        index.remove(path);
+        match merge {
+            read_tree::Merge::Keep => {}
+            // Files outside of the sparse checkout stay out of the working
+            // tree.
+            read_tree::Merge::Take(Some((mode, hash))) => checkouts.push(index::IndexEntry {
+                mode,
+                hash,
+                path: path.clone(),
+                skip_worktree: existing.is_some_and(|entry| entry.skip_worktree),
+                ..Default::default()
+            }),
+            read_tree::Merge::Take(None) => {
+                if existing.is_some_and(|entry| !entry.skip_worktree) {
+                    removals.push(path.clone());
+                }
+            }
+            read_tree::Merge::Conflict(stages) => {
+                // The working tree is left as is.
+                for (stage, version) in (1..).zip(stages) {
+                    if let Some((mode, hash)) = version {
+                        index.add(index::IndexEntry {
+                            mode,
+                            hash,
+                            stage,
+                            path: path.clone(),
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // Untracked files in the way of the directories of new files would be
+    // lost too.
+    if update {
+        let mut in_the_way = BTreeSet::new();
+        for entry in checkouts.iter().filter(|entry| !entry.skip_worktree) {
+            let mut dir = entry.path.as_str();
+            while let Some((parent, _)) = dir.rsplit_once('/') {
+                dir = parent;
+                if !current.contains_key(dir)
+                    && fs::symlink_metadata(repo.worktree_path(dir)?)
+                        .is_ok_and(|metadata| !metadata.is_dir())
+                {
+                    in_the_way.insert(dir.to_string());
+                }
+            }
+        }
+        rejected.extend(
+            in_the_way
+                .into_iter()
+                .map(|path| (path, Rejection::UntrackedOverwritten)),
+        );
+    }
+    if !rejected.is_empty() {
+        return Ok(Unpacked {
+            rejected,
+            warnings: vec![],
+        });
+    }
+    if update {
+        for path in &removals {
+            remove_worktree_file(repo, path)?;
+        }
+    }
+    // On case-insensitive file systems, paths only differing by case share a
+    // file, which is only checked out for the first of them, instead of
+    // having its content replaced by the others.
+    let mut collided = BTreeSet::new();
+    let mut folded: HashMap<String, String> = HashMap::new();
+    if modes.ignore_case {
+        for entry in &index.entries {
+            folded
+                .entry(entry.path.to_ascii_lowercase())
+                .or_insert_with(|| entry.path.clone());
+        }
+    }
+    for entry in checkouts {
+        let mut other = None;
+        if modes.ignore_case {
+            let first = folded
+                .entry(entry.path.to_ascii_lowercase())
+                .or_insert_with(|| entry.path.clone());
+            if *first != entry.path {
+                other = Some(first.clone());
+            }
+        }
+        let entry = match update && !entry.skip_worktree {
+            true if other.is_some() => {
+                collided.extend(other);
+                collided.insert(entry.path.clone());
+                entry
+            }
+            true => checkout_entry(repo, &filters, modes, big_file_threshold, &entry)?,
+            false => entry,
+        };
+        index.add(entry);
+    }
+    let mut warnings = filters.take_warnings();
+    if !collided.is_empty() {
+        let paths: Vec<String> = collided.iter().map(|path| format!("  '{path}'")).collect();
+        warnings.push(format!(
+            "warning: the following paths have collided (e.g. case-sensitive paths\n\
+             on a case-insensitive filesystem) and only one from the same\n\
+             colliding group is in the working tree:\n\n{}",
+            paths.join("\n")
+        ));
+    }
+    Ok(Unpacked { rejected, warnings })
+}
+
+/// Returns whether an untracked file is where `path` is checked out or
+/// removed. A directory is only in the way if it has untracked files or a
+/// nested repository, the tracked files in it being removed.
+fn is_in_the_way(
+    full_path: &Path,
+    path: &str,
+    current: &BTreeMap<String, index::IndexEntry>,
+) -> Result<bool> {
+    let Ok(metadata) = fs::symlink_metadata(full_path) else {
+        return Ok(false);
+    };
+    if !metadata.is_dir() {
+        return Ok(true);
+    }
+    for entry in fs::read_dir(full_path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            return Ok(true);
+        };
+        let in_the_way = match entry.file_type()?.is_dir() {
+            true => {
+                name == ".git" || is_in_the_way(&entry.path(), &format!("{path}/{name}"), current)?
+            }
+            false => !current.contains_key(&format!("{path}/{name}")),
+        };
+        if in_the_way {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns whether the file of an index entry is missing or unchanged in the
+/// working tree, so it can be replaced without losing changes.
+///
+/// Files outside of the sparse checkout aren't checked, but files marked as
+/// unchanged are since they may have changes.
+fn worktree_matches(
+    repo: &Repo,
+    filters: &filter::Filters,
+    entry: &index::IndexEntry,
+    modes: index::FileModes,
+) -> Result<bool> {
+    if entry.skip_worktree {
+        return Ok(true);
+    }
+    let full_path = repo.worktree_path(&entry.path)?;
+    let metadata = match fs::symlink_metadata(&full_path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e.into()),
+    };
+    if entry.matches_stat(&metadata, modes) {
+        return Ok(true);
+    }
+    if metadata.is_dir() {
+        // A directory in place of a submodule is its checkout.
+        return Ok(entry.mode == 0o160000);
+    }
+    Ok(modes.mode(&metadata, Some(entry.mode)) == entry.mode
+        && index::worktree_blob_hash(filters, &entry.path, &full_path, &metadata)? == entry.hash)
+}
+
+/// Writes the file of an index entry to the working tree, replacing any file
+/// in the way, and returns the entry with the stat data of the new file.
+/// Blobs larger than `big_file_threshold` are streamed.
+///
+/// Callers must refuse to replace untracked files first, see
+/// [`unpack_trees`].
+fn checkout_entry(
+    repo: &Repo,
+    filters: &filter::Filters,
+    modes: index::FileModes,
+    big_file_threshold: u64,
+    entry: &index::IndexEntry,
+) -> Result<index::IndexEntry> {
+    // Files and symlinks in the way of the directories are replaced, from
+    // the top so that nothing is removed through a symlink.
+    repo.verified_path(&entry.path)?;
+    let mut dir = repo.root.clone();
+    if let Some((dirs, _)) = entry.path.rsplit_once('/') {
+        for component in dirs.split('/') {
+            dir.push(component);
+            if fs::symlink_metadata(&dir).is_ok_and(|metadata| !metadata.is_dir()) {
+                fs::remove_file(&dir)?;
+            }
+        }
+    }
+    let full_path = repo.worktree_path(&entry.path)?;
+    let parent = full_path.parent().expect("worktree paths have a parent");
+    fs::create_dir_all(parent)?;
+    match fs::symlink_metadata(&full_path) {
+        Ok(metadata) if metadata.is_dir() && entry.mode != 0o160000 => {
+            fs::remove_dir_all(&full_path)?
+        }
+        Ok(metadata) if !metadata.is_dir() => fs::remove_file(&full_path)?,
+        _ => {}
+    }
+
+    let create_file = || {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // Like git, the umask decides who may execute the file.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(if entry.mode & 0o100 != 0 {
+                0o777
+            } else {
+                0o666
+            });
+        }
+        options.open(&full_path)
+    };
+    if entry.mode == 0o160000 {
+        // Submodules are checked out separately.
+        fs::create_dir_all(&full_path)?;
+    } else if entry.mode != 0o120000
+        && object::read_header(repo, &entry.hash)?.1 as u64 > big_file_threshold
+        && filters.driver(&entry.path)?.is_none()
+    {
+        // Big files without a filter are streamed instead of being loaded
+        // into memory.
+        let (_, mut blob) = object::open_blob(repo, &entry.hash)?;
+        io::copy(&mut blob, &mut io::BufWriter::new(create_file()?))?;
+    } else {
+        let Object::Blob(blob) = Object::from_hash(repo, &entry.hash)? else {
+            return Err(anyhow!("Not a blob: {}", entry.hash));
+        };
+        if entry.mode == 0o120000 && modes.symlinks {
+            write_symlink(&blob.content, &full_path)?;
+        } else if entry.mode == 0o120000 {
+            // Without symlinks, the target is written to a regular file.
+            fs::write(&full_path, &blob.content)?;
+        } else {
+            let content = filters.smudge(&entry.path, blob.content)?;
+            create_file()?.write_all(&content)?;
+        }
+    }
+    let metadata = fs::symlink_metadata(&full_path)?;
+    Ok(index::IndexEntry {
+        mode: entry.mode,
+        ..index::IndexEntry::from_metadata(&entry.path, &entry.hash, &metadata)
+    })
+}
+
+/// Creates a symlink, or a file holding its target on platforms without
+/// symlinks, like git with `core.symlinks=false`.
+fn write_symlink(target: &[u8], path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), path)?;
+    }
+    #[cfg(not(unix))]
+    fs::write(path, target)?;
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct CommitOptions {
+    /// The message, or empty to take the message of the merge being
+    /// concluded.
+    pub message: String,
+    /// Allow a commit with the same tree as its parent.
+    pub allow_empty: bool,
+    /// Allow a commit with an empty message.
+    pub allow_empty_message: bool,
+    /// Replace the HEAD commit instead of adding a commit on top of it. An
+    /// empty `message` keeps its message.
+    pub amend: bool,
+    /// The author instead of the one from the config and the environment,
+    /// or of the amended or cherry-picked commit.
+    pub author: Option<ident::Ident>,
+    /// What the reflog says made the commit instead of `commit`, like
+    /// `cherry-pick`.
+    pub reflog_action: Option<String>,
+}
+
+/// Records the index as a new commit on top of HEAD.
+///
+/// The stat data of the index is refreshed first. The author, unless given,
+/// and the committer are read from the config and the environment, see
+/// [`ident::Ident::new`].
+/// The branch HEAD points to, or HEAD itself when detached, is updated in a
+/// ref transaction that fails if it moved in the meantime, and the update is
+/// recorded in the reflogs.
+///
+/// When amending, the new commit gets the parents and the author of the HEAD
+/// commit instead, and replaces it. When a merge stopped before its commit,
+/// see [`merge`], the commit concludes it, with the merged commits as other
+/// parents. So does a cherry-pick stopped at conflicts, see [`cherry_pick`],
+/// with the author of the picked commit.
+///
+/// Like git, this refuses to create a commit that doesn't change anything or
+/// has an empty message, unless explicitly allowed.
+pub fn commit(repo: &Repo, options: &CommitOptions, stdout: &mut dyn io::Write) -> Result<()> {
+    let merge_state = merge::State::read(repo)?;
+    let merging = merge_state
+        .as_ref()
+        .is_some_and(|state| !state.merge_heads.is_empty());
+    let cherry_pick_head = merge_state
+        .as_ref()
+        .and_then(|state| state.cherry_pick_head.as_deref());
+    if merging && options.amend {
+        return Err(anyhow!("You are in the middle of a merge -- cannot amend."));
+    }
+    if cherry_pick_head.is_some() && options.amend {
+        return Err(anyhow!(
+            "You are in the middle of a cherry-pick -- cannot amend."
+        ));
+    }
+    let mut index = index::Index::read(repo)?;
+    if index.entries.iter().any(|entry| entry.stage != 0) {
+        return Err(anyhow!(
+            "Committing is not possible because you have unmerged files."
+        ));
+    }
+    refresh_index(repo, &mut index)?;
+    let tree = index.write_tree(repo)?;
+    // Save the refreshed stat data and the updated cache tree for the next
+    // commit.
+    index.write(repo)?;
+
+    let head = refs::find_ref(repo, "HEAD").ok();
+    let amended = match (&head, options.amend) {
+        (Some(head), true) => match Object::from_hash(repo, head)? {
+            Object::Commit(commit) => Some(commit),
+            _ => return Err(anyhow!("HEAD is not a commit: {head}")),
+        },
+        (None, true) => return Err(anyhow!("You have nothing to amend.")),
+        (_, false) => None,
+    };
+    let mut parents: Vec<String> = match &amended {
+        Some(amended) => amended.parents.clone(),
+        None => head.iter().cloned().collect(),
+    };
+    if let Some(state) = &merge_state {
+        parents.extend(state.merge_heads.iter().cloned());
+    }
+    let is_empty = match parents.first() {
+        Some(parent) => {
+            let Object::Commit(parent_commit) = Object::from_hash(repo, parent)? else {
+                return Err(anyhow!("Parent is not a commit: {parent}"));
+            };
+            parent_commit.tree == tree
+        }
+        None => index.entries.is_empty(),
+    };
+    // Like git, a merge can be concluded, or amended, without changes.
+    let amends_merge = amended
+        .as_ref()
+        .is_some_and(|amended| amended.parents.len() > 1);
+    if is_empty && !merging && !amends_merge && !options.allow_empty {
+        return Err(anyhow!(if options.amend {
+            "You asked to amend the most recent commit, but doing so would make it empty \
+             (use --allow-empty to amend anyway)"
+        } else {
+            "Nothing to commit (use --allow-empty to create an empty commit)"
+        }));
+    }
+    let message = match (&amended, &merge_state) {
+        // Parsed messages lose their last newline.
+        (Some(amended), _) if options.message.is_empty() && !amended.message.is_empty() => {
+            format!("{}\n", amended.message)
+        }
+        (Some(_), _) if options.message.is_empty() => String::new(),
+        (_, Some(state)) if options.message.is_empty() => cleanup_message(&state.message),
+        _ => cleanup_message(&options.message),
+    };
+    if message.is_empty() && !options.allow_empty_message {
+        return Err(anyhow!(
+            "Aborting commit due to empty commit message (use --allow-empty-message to allow it)"
+        ));
+    }
+
+    let config = config::Config::load(repo)?;
+    let author = match (&options.author, &amended, cherry_pick_head) {
+        (Some(author), _, _) => author.to_string(),
+        (None, Some(amended), _) => amended.author.clone(),
+        (None, None, Some(picked)) => match Object::from_hash(repo, picked)? {
+            Object::Commit(picked) => picked.author,
+            _ => return Err(anyhow!("CHERRY_PICK_HEAD is not a commit: {picked}")),
+        },
+        (None, None, None) => ident::Ident::new(&config, ident::Role::Author)?.to_string(),
+    };
+    let committer = ident::Ident::new(&config, ident::Role::Committer)?.to_string();
+    let commit = Commit {
+        tree,
+        parents,
+        author,
+        committer: committer.clone(),
+        message,
+        ..Default::default()
+    };
+    let hash = object::write_checked_object(repo, ObjectType::Commit, &commit.to_bytes())?;
+
+    let subject = commit.message.lines().next().unwrap_or("");
+    let reflog_message = match (&head, options.amend, &options.reflog_action) {
+        (_, _, Some(action)) => format!("{action}: {subject}"),
+        (_, true, None) => format!("commit (amend): {subject}"),
+        (Some(_), false, None) if merging => format!("commit (merge): {subject}"),
+        (Some(_), false, None) if cherry_pick_head.is_some() => {
+            format!("commit (cherry-pick): {subject}")
+        }
+        (Some(_), false, None) => format!("commit: {subject}"),
+        (None, false, None) => format!("commit (initial): {subject}"),
+    };
+    let mut transaction = refs::Transaction::new();
+    transaction.update(
+        "HEAD",
+        &hash,
+        Some(head.as_deref().unwrap_or(refs::ZERO_HASH)),
+        &reflog_message,
+    );
+    transaction.commit(repo, Some(&committer))?;
+    rerere::rerere(repo, stdout)?;
+    if merge_state.is_some() {
+        merge::State::remove(repo)?;
+    }
+
+    let branch = match refs::head(repo)? {
+        refs::Head::Branch(name) => name
+            .strip_prefix("refs/heads/")
+            .unwrap_or(&name)
+            .to_string(),
+        refs::Head::Detached(_) => "detached HEAD".to_string(),
+    };
+    writeln!(
+        stdout,
+        "[{branch}{} {}] {subject}",
+        if head.is_none() { " (root-commit)" } else { "" },
+        object::Abbreviator::from_config(repo)?.abbreviate(&hash)?,
+    )?;
+    Ok(())
+}
+
+/// Cleans up a commit message like git's default `whitespace` mode: trailing
+/// whitespace and leading and trailing empty lines are removed, consecutive
+/// empty lines are collapsed and the message ends with a newline.
+fn cleanup_message(message: &str) -> String {
+    let mut cleaned = String::new();
+    let mut pending_empty_line = false;
+    for line in message.lines().map(str::trim_end) {
+        if line.is_empty() {
+            pending_empty_line = !cleaned.is_empty();
+            continue;
+        }
+        if pending_empty_line {
+            cleaned.push('\n');
+            pending_empty_line = false;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+#[derive(Debug, Default)]
+pub struct AddOptions {
+    /// Also stage new files and removals when no pathspec is given.
+    pub all: bool,
+    /// Only stage changes and removals of files that are already tracked.
+    pub update: bool,
+    /// Print the paths that are added and removed.
+    pub verbose: bool,
+    /// Also add files that are ignored.
+    pub force: bool,
+}
+
+/// Stages files in the index.
+///
+/// `pathspecs` are paths relative to the root of the working tree, where
+/// directories are added recursively. Like git, files missing from the working
+/// tree are removed from the index. Without pathspecs, `all` and `update` apply
+/// to the whole working tree. The files of entries marked as unchanged or
+/// outside of the sparse checkout are left alone, see [`update_index`].
+///
+/// Untracked files that are ignored are skipped unless `force` is set, and
+/// naming one fails after the other files are added.
+pub fn add(
+    repo: &Repo,
+    pathspecs: &[String],
+    options: &AddOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let pathspecs = match pathspecs {
+        [] if options.all || options.update => vec![String::new()],
+        [] => return Err(anyhow!("Nothing specified, nothing added.")),
+        pathspecs => pathspecs.to_vec(),
+    };
+    let mut index = index::Index::lock(repo)?;
+    let big_file_threshold = config::Config::load(repo)?.big_file_threshold()?;
+    let filters = filter::Filters::new(repo)?;
+    let mut ignore = ignore::Ignore::load(repo)?;
+    let modes = index::FileModes::load(repo)?;
+    let mut ignored_pathspecs = vec![];
+
+    for pathspec in &pathspecs {
+        let full_path = if pathspec.is_empty() {
+            repo.root.clone()
+        } else {
+            repo.worktree_path(pathspec)?
+        };
+        let mut files = vec![];
+        let mut is_dir = false;
+        match fs::symlink_metadata(&full_path) {
+            Ok(metadata) if metadata.is_dir() => {
+                is_dir = true;
+                collect_worktree_files(&full_path, pathspec, &mut files)?
+            }
+            Ok(_) => files.push(pathspec.clone()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let mut tracked: BTreeSet<String> = index
+            .entries
+            .iter()
+            .filter(|entry| matches_pathspec(&entry.path, pathspec))
+            .map(|entry| entry.path.clone())
+            .collect();
+        // On case-insensitive file systems, files are staged with the case
+        // of their entry, and are read with theirs.
+        let mut worktree_names = HashMap::new();
+        if modes.ignore_case {
+            let aliases = index.aliases();
+            for path in &mut files {
+                if let Some(alias) = aliases.get(path) {
+                    if aliases.contains(&alias) {
+                        tracked.insert(alias.clone());
+                    }
+                    worktree_names.insert(alias.clone(), std::mem::replace(path, alias));
+                }
+            }
+        }
+        if files.is_empty() && tracked.is_empty() {
+            return Err(anyhow!("pathspec '{pathspec}' did not match any files"));
+        }
+        if !options.force {
+            if !pathspec.is_empty() && tracked.is_empty() && ignore.is_ignored(pathspec, is_dir)? {
+                ignored_pathspecs.push(pathspec.clone());
+                continue;
+            }
+            let mut untracked_ignored = HashSet::new();
+            for path in &files {
+                if !tracked.contains(path) && ignore.is_ignored(path, false)? {
+                    untracked_ignored.insert(path.clone());
+                }
+            }
+            files.retain(|path| !untracked_ignored.contains(path));
+        }
+        // The files of these entries are left alone, like unchanged files.
+        let ignored: HashSet<&String> = index
+            .entries
+            .iter()
+            .filter(|entry| entry.ignores_worktree() && tracked.contains(&entry.path))
+            .map(|entry| &entry.path)
+            .collect();
+        if index
+            .entries
+            .iter()
+            .any(|entry| entry.skip_worktree && entry.path == *pathspec)
+        {
+            return Err(anyhow!(
+                "The following paths and/or pathspecs matched paths that exist\n\
+                 outside of your sparse-checkout definition, so will not be\n\
+                 updated in the index:\n{pathspec}"
+            ));
+        }
+
+        let files_set: HashSet<&String> = files.iter().collect();
+        let mut removed = vec![];
+        for path in &tracked {
+            if !files_set.contains(path) && !ignored.contains(path) {
+                removed.push(path.clone());
+            }
+        }
+        let mut added = vec![];
+        for path in &files {
+            if (options.update && !tracked.contains(path)) || ignored.contains(path) {
+                continue;
+            }
+            added.push(path.clone());
+        }
+        for path in &removed {
+            if index.remove(path) && options.verbose {
+                writeln!(stdout, "remove '{path}'")?;
+            }
+        }
+        let tracked_modes = index.modes();
+        for path in &added {
+            let name = worktree_names.get(path).unwrap_or(path);
+            let metadata = fs::symlink_metadata(repo.worktree_path(name)?)?;
+            let hash = write_worktree_blob(repo, &filters, name, &metadata, big_file_threshold)?;
+            let entry = modes.entry(&tracked_modes, path, &hash, &metadata);
+            if add_index_entry(&mut index, entry) && options.verbose {
+                writeln!(stdout, "add '{path}'")?;
+            }
+        }
+    }
+
+    index.write()?;
+    for warning in filters.take_warnings() {
+        writeln!(stdout, "{warning}")?;
+    }
+    if !ignored_pathspecs.is_empty() {
+        return Err(anyhow!(
+            "The following paths are ignored by one of your .gitignore files:\n{}\n\
+             hint: Use -f if you really want to add them.",
+            ignored_pathspecs.join("\n")
+        ));
+    }
+    Ok(())
+}
+
+/// The number of unchanged lines around the changes of hunks.
+const HUNK_CONTEXT: usize = 3;
+
+const ADD_PATCH_HELP: &str = "\
+y - stage this hunk
+n - do not stage this hunk
+q - quit; do not stage this hunk or any of the remaining ones
+a - stage this hunk and all later hunks in the file
+d - do not stage this hunk or any of the later hunks in the file
+? - print help
+";
+
+/// Returns the hunks of the unstaged changes to a tracked file, which can be
+/// staged one by one with [`stage_hunks`].
+///
+/// Only changes to the content of regular text files have hunks.
+pub fn worktree_hunks(repo: &Repo, path: &str) -> Result<Vec<add_patch::Hunk>> {
+    let index = index::Index::read(repo)?;
+    Ok(match unstaged_contents(repo, &index, path)? {
+        Some((staged, content)) => add_patch::hunks(&staged, &content, HUNK_CONTEXT),
+        None => vec![],
+    })
+}
+
+/// Stages the hunks from [`worktree_hunks`] for which `selected` is true,
+/// leaving the other changes unstaged.
+///
+/// Fails if the file or its staged version changed since the hunks were
+/// computed.
+pub fn stage_hunks(
+    repo: &Repo,
+    path: &str,
+    hunks: &[add_patch::Hunk],
+    selected: &[bool],
+) -> Result<()> {
+    if hunks.len() != selected.len() {
+        return Err(anyhow!(
+            "Expected a selection for each of the {} hunks",
+            hunks.len()
+        ));
+    }
+    let mut index = index::Index::read(repo)?;
+    let Some((staged, content)) = unstaged_contents(repo, &index, path)? else {
+        return Err(anyhow!("'{path}' has no unstaged changes"));
+    };
+    if add_patch::hunks(&staged, &content, HUNK_CONTEXT) != hunks {
+        return Err(anyhow!("'{path}' changed since its hunks were computed"));
+    }
+    stage_content(
+        repo,
+        &mut index,
+        path,
+        &content,
+        &add_patch::apply(&staged, hunks, selected),
+    )?;
+    index.write(repo)
+}
+
+/// Interactively stages the unstaged changes to tracked files hunk by hunk,
+/// like `git add -p`, reading the answers to the prompts from `stdin`.
+///
+/// Without pathspecs, all tracked files are considered. Deleted files are
+/// offered as a single deletion.
+pub fn add_patch(
+    repo: &Repo,
+    pathspecs: &[String],
+    stdin: &mut dyn io::BufRead,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let mut index = index::Index::read(repo)?;
+    let path_options = diff::DiffPathOptions::from_config(&config::Config::load(repo)?)?;
+    let paths: Vec<String> = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage == 0 && entry.mode & 0o170000 == 0o100000)
+        .filter(|entry| {
+            pathspecs.is_empty()
+                || pathspecs
+                    .iter()
+                    .any(|pathspec| matches_pathspec(&entry.path, pathspec))
+        })
+        .map(|entry| entry.path.clone())
+        .collect();
+
+    let mut ask = |prompt: &str, stdout: &mut dyn io::Write| -> Result<char> {
+        loop {
+            write!(stdout, "{prompt} [y,n,q,a,d,?]? ")?;
+            stdout.flush()?;
+            let mut answer = String::new();
+            if stdin.read_line(&mut answer)? == 0 {
+                writeln!(stdout)?;
+                return Ok('q');
+            }
+            match answer.trim().chars().next() {
+                Some(c @ ('y' | 'n' | 'q' | 'a' | 'd')) => return Ok(c),
+                _ => write!(stdout, "{ADD_PATCH_HELP}")?,
+            }
+        }
+    };
+
+    let mut any_changes = false;
+    for path in paths {
+        let full_path = repo.worktree_path(&path)?;
+        if !full_path.exists() {
+            let Object::Blob(blob) = Object::from_hash(repo, &index_entry_hash(&index, &path))?
+            else {
+                return Err(anyhow!("Not a blob: {path}"));
+            };
+            any_changes = true;
+            write!(stdout, "{}", path_options.header(Some(&path), None))?;
+            for hunk in add_patch::hunks(&blob.content, b"", HUNK_CONTEXT) {
+                write!(stdout, "{hunk}")?;
+            }
+            match ask("(1/1) Stage deletion", stdout)? {
+                'y' | 'a' => {
+                    index.remove(&path);
+                }
+                'q' => break,
+                _ => {}
+            }
+            continue;
+        }
+        let Some((staged, content)) = unstaged_contents(repo, &index, &path)? else {
+            continue;
+        };
+        let hunks = add_patch::hunks(&staged, &content, HUNK_CONTEXT);
+        if hunks.is_empty() {
+            continue;
+        }
+        any_changes = true;
+        write!(stdout, "{}", path_options.header(Some(&path), Some(&path)))?;
+        let mut selected = vec![false; hunks.len()];
+        let mut quit = false;
+        let mut i = 0;
+        while i < hunks.len() {
+            write!(stdout, "{}", hunks[i])?;
+            let prompt = format!("({}/{}) Stage this hunk", i + 1, hunks.len());
+            match ask(&prompt, stdout)? {
+                'y' => selected[i] = true,
+                'a' => {
+                    selected[i..].fill(true);
+                    break;
+                }
+                'd' => break,
+                'q' => {
+                    quit = true;
+                    break;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        if selected.contains(&true) {
+            let staged_content = add_patch::apply(&staged, &hunks, &selected);
+            stage_content(repo, &mut index, &path, &content, &staged_content)?;
+        }
+        if quit {
+            break;
+        }
+    }
+    if !any_changes {
+        writeln!(stdout, "No changes.")?;
+    }
+    index.write(repo)
+}
+
+fn index_entry_hash(index: &index::Index, path: &str) -> String {
+    index
+        .entries
+        .iter()
+        .find(|entry| entry.path == path && entry.stage == 0)
+        .map(|entry| entry.hash.clone())
+        .unwrap_or_default()
+}
+
+/// Returns the staged and the working tree contents of a tracked regular
+/// file, unless they're the same, the file is binary, no longer a regular
+/// file or ignored, see [`index::IndexEntry::ignores_worktree`].
+fn unstaged_contents(
+    repo: &Repo,
+    index: &index::Index,
+    path: &str,
+) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let Some(entry) = index
+        .entries
+        .iter()
+        .find(|entry| entry.path == path && entry.stage == 0)
+    else {
+        return Err(anyhow!("'{path}' is not tracked"));
+    };
+    if entry.ignores_worktree() {
+        return Ok(None);
+    }
+    let full_path = repo.worktree_path(path)?;
+    let metadata = match fs::symlink_metadata(&full_path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let modes = index::FileModes::load(repo)?;
+    if entry.mode & 0o170000 != 0o100000
+        || !metadata.is_file()
+        || entry.matches_stat(&metadata, modes)
+    {
+        return Ok(None);
+    }
+    let Object::Blob(blob) = Object::from_hash(repo, &entry.hash)? else {
+        return Err(anyhow!("Not a blob: {}", entry.hash));
+    };
+    let content = fs::read(&full_path)?;
+    let classifier = binary::BinaryClassifier::from_config(&config::Config::load(repo)?)?;
+    let unspecified = binary::TextAttribute::Unspecified;
+    if blob.content == content
+        || classifier.is_binary(&blob.content, blob.content.len() as u64, unspecified)
+        || classifier.is_binary(&content, content.len() as u64, unspecified)
+    {
+        return Ok(None);
+    }
+    Ok(Some((blob.content, content)))
+}
+
+/// Stages new content for a tracked file whose working tree content is
+/// `worktree_content`.
+///
+/// Unless everything is staged, the stat data of the entry is cleared so
+/// that the file isn't mistaken for unchanged.
+fn stage_content(
+    repo: &Repo,
+    index: &mut index::Index,
+    path: &str,
+    worktree_content: &[u8],
+    content: &[u8],
+) -> Result<()> {
+    let hash = object::write_checked_object(repo, ObjectType::Blob, content)?;
+    let Some(entry) = index
+        .entries
+        .iter_mut()
+        .find(|entry| entry.path == path && entry.stage == 0)
+    else {
+        return Err(anyhow!("'{path}' is not tracked"));
+    };
+    *entry = if content == worktree_content {
+        let metadata = fs::symlink_metadata(repo.worktree_path(path)?)?;
+        index::IndexEntry {
+            mode: entry.mode,
+            ..index::IndexEntry::from_metadata(path, &hash, &metadata)
+        }
+    } else {
+        index::IndexEntry {
+            mode: entry.mode,
+            hash,
+            path: path.to_string(),
+            ..Default::default()
+        }
+    };
+    if let Some(cache_tree) = &mut index.cache_tree {
+        cache_tree.invalidate_path(path);
+    }
+    Ok(())
+}
+
+/// Writes the blob of a file in the working tree, cleaned by its filter if
+/// it has one, and returns its hash. `metadata` must not follow symlinks.
+fn write_worktree_blob(
+    repo: &Repo,
+    filters: &filter::Filters,
+    path: &str,
+    metadata: &fs::Metadata,
+    big_file_threshold: u64,
+) -> Result<String> {
+    let full_path = repo.worktree_path(path)?;
+    if metadata.is_symlink() {
+        let target = fs::read_link(full_path)?;
+        let target = target.to_string_lossy();
+        object::write_checked_object(repo, ObjectType::Blob, target.as_bytes())
+    } else if filters.driver(path)?.is_some() {
+        let content = filters.clean(path, fs::read(full_path)?)?;
+        object::write_checked_object(repo, ObjectType::Blob, &content)
+    } else if metadata.len() > big_file_threshold {
+        let mut file = io::BufReader::new(fs::File::open(full_path)?);
+        object::write_blob_stream(Some(repo), metadata.len(), &mut file)
+    } else {
+        object::write_checked_object(repo, ObjectType::Blob, &fs::read(full_path)?)
+    }
+}
+
+/// Adds a stage 0 entry to the index, like [`index::Index::add`], replacing
+/// the entries it conflicts with.
+fn add_index_entry(index: &mut index::Index, entry: index::IndexEntry) -> bool {
+    let path = entry.path.clone();
+    // A file replaces a tracked directory with the same name, and the other
+    // way around.
+    for (i, _) in path.match_indices('/') {
+        index.remove(&path[..i]);
+    }
+    index.remove_dir(&path);
+    // Adding a file resolves its conflicts.
+    if index
+        .entries_for(&path)
+        .iter()
+        .any(|entry| entry.stage != 0)
+    {
+        index.remove(&path);
+    }
+    index.add(entry)
+}
+
+#[derive(Debug, Default)]
+pub struct UpdateIndexOptions {
+    /// Allow adding paths that aren't in the index yet.
+    pub add: bool,
+    /// Remove the paths that are missing from the working tree.
+    pub remove: bool,
+    /// Update the stat data of the entries whose files didn't change, and
+    /// report the others.
+    pub refresh: bool,
+    /// Entries to add as is, as `<mode>,<hash>,<path>`.
+    pub cacheinfo: Vec<String>,
+    /// Entries to add as is, in the `--index-info` format.
+    pub index_info: Option<String>,
+    /// Set or clear the assume-unchanged flag of the entries of the paths
+    /// instead of updating them.
+    pub assume_unchanged: Option<bool>,
+    /// Set or clear the skip-worktree flag of the entries of the paths
+    /// instead of updating them.
+    pub skip_worktree: Option<bool>,
+}
+
+/// Updates the index directly, without the safety checks of `add` and `rm`.
+///
+/// The entries of `index_info` are applied first, then those of `cacheinfo`,
+/// then the files at `paths` are added, updated or removed. Each line of
+/// `index_info` is either `<mode> <hash>\t<path>`, `<mode> <type>
+/// <hash>\t<path>` like the output of `ls-tree`, or `<mode> <hash>
+/// <stage>\t<path>` like the output of `ls-files --stage`. A mode of 0
+/// removes the path.
+///
+/// With `assume_unchanged` or `skip_worktree`, the entries of `paths` are
+/// only marked, which makes status, diffs, `add` and checkouts ignore their
+/// files in the working tree.
+///
+/// Returns `false` if `refresh` found files that need to be updated.
+pub fn update_index(
+    repo: &Repo,
+    paths: &[String],
+    options: &UpdateIndexOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<bool> {
+    let mut index = index::Index::read(repo)?;
+
+    for line in options.index_info.iter().flat_map(|info| info.lines()) {
+        let invalid = || anyhow!("malformed index info {line}");
+        let (fields, path) = line.split_once('\t').ok_or_else(invalid)?;
+        let fields: Vec<&str> = fields.split(' ').collect();
+        let (mode, hash, stage) = match fields[..] {
+            [mode, hash] => (mode, hash, "0"),
+            [mode, "blob" | "tree" | "commit", hash] => (mode, hash, "0"),
+            [mode, hash, stage] => (mode, hash, stage),
+            _ => return Err(invalid()),
+        };
+        let mode = u32::from_str_radix(mode, 8).map_err(|_| invalid())?;
+        let stage: u8 = stage.parse().map_err(|_| invalid())?;
+        if hash.len() != 40 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) || stage > 3 {
+            return Err(invalid());
+        }
+        if mode == 0 {
+            index.remove(path);
+            continue;
+        }
+        let entry = index::IndexEntry {
+            mode,
+            hash: hash.to_string(),
+            stage,
+            path: path.to_string(),
+            ..Default::default()
+        };
+        if stage == 0 {
+            add_index_entry(&mut index, entry);
+        } else {
+            // Unmerged stages replace the merged entry.
+            if index.entries.iter().any(|e| e.path == path && e.stage == 0) {
+                index.remove(path);
+            }
+            index.add(entry);
+        }
+    }
+
+    for cacheinfo in &options.cacheinfo {
+        let invalid = || anyhow!("option 'cacheinfo' expects <mode>,<sha1>,<path>");
+        let mut fields = cacheinfo.splitn(3, ',');
+        let (Some(mode), Some(hash), Some(path)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid());
+        };
+        let mode = u32::from_str_radix(mode, 8).map_err(|_| invalid())?;
+        if hash.len() != 40 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        if !options.add && !index.entries.iter().any(|entry| entry.path == path) {
+            return Err(anyhow!(
+                "{path}: cannot add to the index - missing --add option?"
+            ));
+        }
+        let entry = index::IndexEntry {
+            mode,
+            hash: hash.to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        add_index_entry(&mut index, entry);
+    }
+
+    let big_file_threshold = config::Config::load(repo)?.big_file_threshold()?;
+    let filters = filter::Filters::new(repo)?;
+    let modes = index::FileModes::load(repo)?;
+    let tracked_modes = index.modes();
+    for path in paths {
+        if options.assume_unchanged.is_some() || options.skip_worktree.is_some() {
+            let mut entries = index
+                .entries
+                .iter_mut()
+                .filter(|entry| entry.path == *path)
+                .peekable();
+            if entries.peek().is_none() {
+                return Err(anyhow!("Unable to mark file {path}"));
+            }
+            for entry in entries {
+                entry.assume_valid = options.assume_unchanged.unwrap_or(entry.assume_valid);
+                entry.skip_worktree = options.skip_worktree.unwrap_or(entry.skip_worktree);
+            }
+            continue;
+        }
+        let full_path = repo.worktree_path(path)?;
+        let metadata = match fs::symlink_metadata(&full_path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if !options.remove {
+                    return Err(anyhow!("{path}: does not exist and --remove not passed"));
+                }
+                index.remove(path);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if metadata.is_dir() {
+            return Err(anyhow!("{path}: is a directory - add files inside instead"));
+        }
+        if !options.add && !index.entries.iter().any(|entry| entry.path == *path) {
+            return Err(anyhow!(
+                "{path}: cannot add to the index - missing --add option?"
+            ));
+        }
+        let hash = write_worktree_blob(repo, &filters, path, &metadata, big_file_threshold)?;
+        let entry = modes.entry(&tracked_modes, path, &hash, &metadata);
+        add_index_entry(&mut index, entry);
+    }
+
+    let mut up_to_date = true;
+    if options.refresh {
+        for (path, reason) in refresh_index(repo, &mut index)? {
+            writeln!(stdout, "{path}: {reason}")?;
+            up_to_date = false;
+        }
+    }
+
+    index.write(repo)?;
+    Ok(up_to_date)
+}
+
+/// Updates the stat data of the index entries whose files didn't change, so
+/// they are known to be unchanged without reading them next time.
+///
+/// Returns the paths whose files changed, with `needs update`, and the
+/// unmerged paths, with `needs merge`.
+fn refresh_index(repo: &Repo, index: &mut index::Index) -> Result<Vec<(String, &'static str)>> {
+    let modes = index::FileModes::load(repo)?;
+    let filters = filter::Filters::new(repo)?;
+    let mut refreshed = vec![];
+    let mut reported = vec![];
+    for entry in &index.entries {
+        if entry.ignores_worktree() || entry.intent_to_add {
+            continue;
+        }
+        if entry.stage != 0 {
+            if !reported.iter().any(|(path, _)| *path == entry.path) {
+                reported.push((entry.path.clone(), "needs merge"));
+            }
+            continue;
+        }
+        let full_path = repo.worktree_path(&entry.path)?;
+        let unchanged = match fs::symlink_metadata(&full_path) {
+            Ok(metadata) if entry.matches_stat(&metadata, modes) => continue,
+            Ok(metadata) => {
+                let unchanged = !metadata.is_dir()
+                    && modes.mode(&metadata, Some(entry.mode)) == entry.mode
+                    && index::worktree_blob_hash(&filters, &entry.path, &full_path, &metadata)?
+                        == entry.hash;
+                unchanged.then_some(metadata)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        match unchanged {
+            Some(metadata) => refreshed.push(index::IndexEntry {
+                mode: entry.mode,
+                ..index::IndexEntry::from_metadata(&entry.path, &entry.hash, &metadata)
+            }),
+            None => reported.push((entry.path.clone(), "needs update")),
+        }
+    }
+    for entry in refreshed {
+        index.add(entry);
+    }
+    Ok(reported)
+}
+
+#[derive(Debug, Default)]
+pub struct RmOptions {
+    /// Only remove the paths from the index, keeping the files.
+    pub cached: bool,
+    /// Allow removing directories recursively.
+    pub recursive: bool,
+    /// Remove files even if they have changes that would be lost.
+    pub force: bool,
+    pub quiet: bool,
+}
+
+/// Removes paths from the index and from the working tree.
+///
+/// Like git, files are kept if removing them would lose changes: if they have
+/// local modifications or changes staged in the index, unless `force` is set.
+/// With `cached`, only files whose content would be lost are refused, when the
+/// index matches neither the file nor `HEAD`.
+pub fn rm(
+    repo: &Repo,
+    pathspecs: &[String],
+    options: &RmOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    if pathspecs.is_empty() {
+        return Err(anyhow!(
+            "No pathspec was given. Which files should I remove?"
+        ));
+    }
+    let mut index = index::Index::lock(repo)?;
+    let mut paths = BTreeSet::new();
+    for pathspec in pathspecs {
+        let matches: Vec<&str> = index
+            .entries
+            .iter()
+            .filter(|entry| matches_pathspec(&entry.path, pathspec))
+            .map(|entry| entry.path.as_str())
+            .collect();
+        if matches.is_empty() {
+            return Err(anyhow!("pathspec '{pathspec}' did not match any files"));
+        }
+        if !options.recursive && matches.iter().any(|path| path != pathspec) {
+            return Err(anyhow!("not removing '{pathspec}' recursively without -r"));
+        }
+        paths.extend(matches.into_iter().map(str::to_string));
+    }
+
+    if !options.force {
+        check_rm_changes(repo, &index, &paths, options.cached)?;
+    }
+
+    for path in &paths {
+        if !options.quiet {
+            writeln!(stdout, "rm '{path}'")?;
+        }
+        index.remove(path);
+    }
+    if !options.cached {
+        for path in &paths {
+            remove_worktree_file(repo, path)?;
+        }
+    }
+    index.write()
+}
+
+/// Removes a file from the working tree if it exists, along with the
+/// directories left empty, like git does.
+fn remove_worktree_file(repo: &Repo, path: &str) -> Result<()> {
+    let full_path = repo.worktree_path(path)?;
+    match fs::remove_file(&full_path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    let mut dir = full_path.parent();
+    while let Some(parent) = dir {
+        if parent == repo.root || fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}
+
+/// Returns an error listing the paths whose changes would be lost by `rm`.
+fn check_rm_changes(
+    repo: &Repo,
+    index: &index::Index,
+    paths: &BTreeSet<String>,
+    cached: bool,
+) -> Result<()> {
+    let mut head_blobs = vec![];
+    if let Ok(head) = refs::find_ref(repo, "HEAD") {
+        let Object::Commit(commit) = Object::from_hash(repo, &head)? else {
+            return Err(anyhow!("HEAD is not a commit: {head}"));
+        };
+        collect_tree_blobs(repo, &commit.tree, "", &mut head_blobs)?;
+    }
+    let head_blobs: HashMap<String, String> = head_blobs.into_iter().collect();
+    let modes = index::FileModes::load(repo)?;
+    let filters = filter::Filters::new(repo)?;
+
+    let mut both = vec![];
+    let mut staged = vec![];
+    let mut local = vec![];
+    for entry in index.entries.iter().filter(|e| paths.contains(&e.path)) {
+        let metadata = match fs::symlink_metadata(repo.worktree_path(&entry.path)?) {
+            Ok(metadata) => metadata,
+            // Nothing to lose if the file is already gone.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let staged_changes = head_blobs.get(&entry.path) != Some(&entry.hash);
+        let local_changes = metadata.is_dir()
+            || modes.mode(&metadata, Some(entry.mode)) != entry.mode
+            || index::worktree_blob_hash(
+                &filters,
+                &entry.path,
+                &repo.worktree_path(&entry.path)?,
+                &metadata,
+            )? != entry.hash;
+        if staged_changes && local_changes {
+            both.push(entry.path.as_str());
+        } else if !cached && staged_changes {
+            staged.push(entry.path.as_str());
+        } else if !cached && local_changes {
+            local.push(entry.path.as_str());
+        }
+    }
+
+    let mut errors = vec![];
+    let mut list = |paths: &[&str], description: &str, hint: &str| {
+        if paths.is_empty() {
+            return;
+        }
+        let subject = if paths.len() == 1 {
+            "file has"
+        } else {
+            "files have"
+        };
+        errors.push(format!(
+            "the following {subject} {description}:\n    {}\n({hint})",
+            paths.join("\n    ")
+        ));
+    };
+    list(
+        &both,
+        "staged content different from both the file and the HEAD",
+        "use -f to force removal",
+    );
+    let hint = "use --cached to keep the file, or -f to force removal";
+    list(&staged, "changes staged in the index", hint);
+    list(&local, "local modifications", hint);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(errors.join("\n")))
+    }
+}
+
+/// Which untracked files [`clean`] removes, depending on whether they are
+/// ignored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CleanIgnored {
+    /// Only the files that aren't ignored.
+    #[default]
+    Exclude,
+    /// Both ignored files and the others, `-x`.
+    Include,
+    /// Only the ignored files, `-X`.
+    Only,
+}
+
+#[derive(Debug, Default)]
+pub struct CleanOptions {
+    /// Only print what would be removed.
+    pub dry_run: bool,
+    /// Remove files even if `clean.requireForce` is set, which it is by
+    /// default.
+    pub force: bool,
+    /// Also remove untracked directories.
+    pub directories: bool,
+    pub ignored: CleanIgnored,
+}
+
+/// Removes the untracked files from the working tree.
+///
+/// Untracked directories are only removed with `directories`, and are listed
+/// once with a trailing `/` when all of their content is removed. Nested
+/// repositories are left alone.
+pub fn clean(repo: &Repo, options: &CleanOptions, stdout: &mut dyn io::Write) -> Result<()> {
+    if !options.dry_run && !options.force {
+        match config::Config::load(repo)?.get_bool("clean.requireForce")? {
+            Some(false) => {}
+            Some(true) => {
+                return Err(anyhow!(
+                    "clean.requireForce set to true and neither -i, -n, nor -f given; \
+                     refusing to clean"
+                ))
+            }
+            None => {
+                return Err(anyhow!(
+                    "clean.requireForce defaults to true and neither -i, -n, nor -f given; \
+                     refusing to clean"
+                ))
+            }
+        }
+    }
+    let index = index::Index::read(repo)?;
+    let mut cleaner = Cleaner {
+        tracked: index
+            .entries
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect(),
+        tracked_dirs: index
+            .entries
+            .iter()
+            .flat_map(|entry| {
+                let path = &entry.path;
+                path.match_indices('/').map(|(i, _)| path[..i].to_string())
+            })
+            .collect(),
+        ignore: ignore::Ignore::load(repo)?,
+        options,
+    };
+    let mut removed = vec![];
+    cleaner.collect(&repo.root, "", &mut removed)?;
+
+    for path in removed {
+        if options.dry_run {
+            writeln!(stdout, "Would remove {path}")?;
+            continue;
+        }
+        writeln!(stdout, "Removing {path}")?;
+        let full_path = repo.root.join(&path);
+        if path.ends_with('/') {
+            fs::remove_dir_all(full_path)?;
+        } else {
+            fs::remove_file(full_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Finds the files [`clean`] removes.
+struct Cleaner<'a> {
+    tracked: HashSet<String>,
+    tracked_dirs: HashSet<String>,
+    ignore: ignore::Ignore,
+    options: &'a CleanOptions,
+}
+
+impl Cleaner<'_> {
+    /// Collects the paths to remove in a directory, and returns whether
+    /// anything in it is kept.
+    fn collect(&mut self, dir: &Path, prefix: &str, removed: &mut Vec<String>) -> Result<bool> {
+        let mut entries: Vec<fs::DirEntry> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        let mut kept = false;
+        for entry in entries {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                kept = true;
+                continue;
+            };
+            let path = format!("{prefix}{name}");
+            let is_dir = entry.file_type()?.is_dir();
+            if path == ".git" || self.tracked.contains(&path) {
+                kept = true;
+            } else if is_dir && self.tracked_dirs.contains(&path) {
+                kept |= self.collect(&entry.path(), &format!("{path}/"), removed)?;
+            } else if !is_dir {
+                let ignored = self.ignore.is_ignored(&path, false)?;
+                if self.removes(ignored) {
+                    removed.push(path);
+                } else {
+                    kept = true;
+                }
+            } else if entry.path().join(".git").exists() {
+                kept = true;
+            } else {
+                kept |= self.collect_untracked_dir(&entry.path(), &path, removed)?;
+            }
+        }
+        Ok(kept)
+    }
+
+    /// Collects the paths to remove in a directory without tracked files, and
+    /// returns whether anything in it is kept.
+    fn collect_untracked_dir(
+        &mut self,
+        dir: &Path,
+        path: &str,
+        removed: &mut Vec<String>,
+    ) -> Result<bool> {
+        let prefix = format!("{path}/");
+        // Like git, a directory whose files are all ignored is an ignored
+        // directory.
+        let ignored = self.ignore.is_ignored(path, true)?
+            || (contains_files(dir)? && !status::has_files(dir, &prefix, &mut self.ignore)?);
+        if ignored {
+            if self.options.directories && self.options.ignored != CleanIgnored::Exclude {
+                removed.push(prefix);
+                return Ok(false);
+            }
+            return Ok(true);
+        }
+        // Since the directory isn't ignored, only ignored files are removed
+        // from it with `-X`, even without directories.
+        if self.options.ignored == CleanIgnored::Only {
+            self.collect(dir, &prefix, removed)?;
+            return Ok(true);
+        }
+        if !self.options.directories {
+            return Ok(true);
+        }
+        let mut dir_removed = vec![];
+        if self.collect(dir, &prefix, &mut dir_removed)? {
+            removed.extend(dir_removed);
+            return Ok(true);
+        }
+        removed.push(prefix);
+        Ok(false)
+    }
+
+    fn removes(&self, ignored: bool) -> bool {
+        match self.options.ignored {
+            CleanIgnored::Exclude => !ignored,
+            CleanIgnored::Include => true,
+            CleanIgnored::Only => ignored,
+        }
+    }
+}
+
+/// Returns whether a directory has files, in it or in its subdirectories.
+fn contains_files(dir: &Path) -> Result<bool> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() || contains_files(&entry.path())? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[derive(Debug, Default)]
+pub struct CheckIgnoreOptions {
+    /// Print the pattern that matches each path.
+    pub verbose: bool,
+    /// With `verbose`, also print the paths that no pattern matches.
+    pub non_matching: bool,
+    /// Also check the paths that are in the index, which are never ignored
+    /// otherwise.
+    pub no_index: bool,
+}
+
+/// Prints the paths that are ignored.
+///
+/// With `verbose`, each path is preceded by the pattern that decides whether
+/// it's ignored, as `<source>:<line>:<pattern>\t`, which includes the
+/// patterns that re-include paths with `!`. Returns whether a path was
+/// printed, not counting the non-matching ones.
+pub fn check_ignore(
+    repo: &Repo,
+    paths: &[String],
+    options: &CheckIgnoreOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<bool> {
+    let tracked: HashSet<String> = if options.no_index {
+        HashSet::new()
+    } else {
+        let index = index::Index::read(repo)?;
+        index.entries.into_iter().map(|entry| entry.path).collect()
+    };
+    let mut ignore = ignore::Ignore::load(repo)?;
+    let mut found = false;
+    for path in paths {
+        let is_dir = path.ends_with('/') || repo.root.join(path).is_dir();
+        let pattern = match tracked.contains(path.trim_end_matches('/')) {
+            true => None,
+            false => ignore.matching(path, is_dir)?,
+        };
+        let pattern = pattern.filter(|pattern| options.verbose || !pattern.negated);
+        match pattern {
+            Some(pattern) if options.verbose => {
+                found = true;
+                writeln!(
+                    stdout,
+                    "{}:{}:{}\t{path}",
+                    pattern.source, pattern.line, pattern.text
+                )?;
+            }
+            Some(_) => {
+                found = true;
+                writeln!(stdout, "{path}")?;
+            }
+            None if options.verbose && options.non_matching => writeln!(stdout, "::\t{path}")?,
+            None => {}
+        }
+    }
+    Ok(found)
+}
+
+/// Prints attributes of paths as `<path>: <attribute>: <value>`, the value
+/// being `set`, `unset`, `unspecified` or the value of the attribute.
+///
+/// Without `attributes`, all the attributes that aren't unspecified are
+/// printed.
+pub fn check_attr(
+    repo: &Repo,
+    attributes: &[String],
+    paths: &[String],
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let rules = attributes::Attributes::load(repo)?;
+    for path in paths {
+        let quoted = format::quote_path(path);
+        let mut lines = vec![];
+        if attributes.is_empty() {
+            for (name, value) in rules.all(path)? {
+                lines.push(format!("{quoted}: {name}: {value}"));
+            }
+        }
+        for name in attributes {
+            match rules.get(path, name)? {
+                Some(value) => lines.push(format!("{quoted}: {name}: {value}")),
+                None => lines.push(format!("{quoted}: {name}: unspecified")),
+            }
+        }
+        // The files read for the path may have had ignored lines.
+        for line in rules.take_warnings().iter().chain(&lines) {
+            writeln!(stdout, "{line}")?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct MvOptions {
+    /// Overwrite existing destination files.
+    pub force: bool,
+    /// Skip the moves that would fail instead of failing.
+    pub skip_errors: bool,
+    /// Only print what would be moved.
+    pub dry_run: bool,
+    pub verbose: bool,
+}
+
+/// Moves or renames files and directories, in the working tree and in the
+/// index.
+///
+/// With several sources, or if `destination` is an existing directory, the
+/// sources are moved into it. Paths are relative to the root of the working
+/// tree, where an empty path is the root itself.
+pub fn mv(
+    repo: &Repo,
+    sources: &[String],
+    destination: &str,
+    options: &MvOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let destination_is_dir = destination.is_empty()
+        || fs::symlink_metadata(repo.worktree_path(destination)?).is_ok_and(|m| m.is_dir());
+    if sources.len() > 1 && !destination_is_dir {
+        return Err(anyhow!("destination '{destination}' is not a directory"));
+    }
+    let mut index = index::Index::read(repo)?;
+
+    let mut moves: Vec<(&str, String)> = vec![];
+    for source in sources {
+        let target = if destination_is_dir {
+            let name = source.rsplit('/').next().unwrap_or(source);
+            if destination.is_empty() {
+                name.to_string()
+            } else {
+                format!("{destination}/{name}")
+            }
+        } else {
+            destination.to_string()
+        };
+        let error = check_mv(repo, &index, source, &target, &moves, options.force)?;
+        match error {
+            None => moves.push((source, target)),
+            Some(_) if options.skip_errors => {}
+            Some(error) => {
+                return Err(anyhow!("{error}, source={source}, destination={target}"));
+            }
+        }
+    }
+
+    for (source, target) in moves {
+        if options.verbose || options.dry_run {
+            writeln!(stdout, "Renaming {source} to {target}")?;
+        }
+        if options.dry_run {
+            continue;
+        }
+        fs::rename(repo.worktree_path(source)?, repo.worktree_path(&target)?)?;
+
+        index.remove(&target);
+        let moved: Vec<index::IndexEntry> = index
+            .entries
+            .iter()
+            .filter(|entry| matches_pathspec(&entry.path, source))
+            .cloned()
+            .collect();
+        for mut entry in moved {
+            index.remove(&entry.path);
+            entry.path = format!("{target}{}", &entry.path[source.len()..]);
+            index.add(entry);
+        }
+    }
+    index.write(repo)
+}
+
+/// Returns why a file or directory can't be moved, if it can't.
+fn check_mv(
+    repo: &Repo,
+    index: &index::Index,
+    source: &str,
+    target: &str,
+    moves: &[(&str, String)],
+    force: bool,
+) -> Result<Option<&'static str>> {
+    let source_metadata = match fs::symlink_metadata(repo.worktree_path(source)?) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some("bad source")),
+        Err(e) => return Err(e.into()),
+    };
+    if !index
+        .entries
+        .iter()
+        .any(|entry| matches_pathspec(&entry.path, source))
+    {
+        return Ok(Some("not under version control"));
+    }
+    if source_metadata.is_dir() && matches_pathspec(target, source) {
+        return Ok(Some("can not move directory into itself"));
+    }
+    let target_path = repo.worktree_path(target)?;
+    let target_metadata = fs::symlink_metadata(&target_path).ok();
+    let target_tracked = index.entries.iter().any(|entry| entry.path == target);
+    if target_metadata.is_some() || target_tracked {
+        // Only a file can overwrite another file.
+        let target_is_dir = target_metadata.is_some_and(|m| m.is_dir());
+        if !force || target_is_dir || source_metadata.is_dir() {
+            return Ok(Some("destination exists"));
+        }
+    }
+    if !target_path.parent().is_some_and(|parent| parent.is_dir()) {
+        return Ok(Some("destination directory does not exist"));
+    }
+    if moves.iter().any(|(_, other)| other == target) {
+        return Ok(Some("multiple sources for the same target"));
+    }
+    Ok(None)
+}
+
+/// Returns whether a path is a pathspec or is in the directory it names. An
+/// empty pathspec matches everything.
+///
+/// Like git, a pathspec with glob characters also matches the paths it
+/// matches as a [`wildmatch`] pattern, where `*` matches `/` too: `*.c`
+/// matches `src/main.c`.
+fn matches_pathspec(path: &str, pathspec: &str) -> bool {
+    let pathspec = pathspec.trim_end_matches('/');
+    pathspec.is_empty()
+        || path == pathspec
+        || path
+            .strip_prefix(pathspec)
+            .is_some_and(|rest| rest.starts_with('/'))
+        || (wildmatch::is_glob(pathspec)
+            && wildmatch::wildmatch(pathspec.as_bytes(), path.as_bytes(), Default::default()))
+}
+
+/// Collects the files below a directory of the working tree, as paths
+/// relative to the root.
+///
+/// The `.git` folder and nested repositories are skipped. Ignored files are
+/// collected too, the caller filters them unless they're added by force.
+fn collect_worktree_files(
+    dir: &std::path::Path,
+    relative_dir: &str,
+    files: &mut Vec<String>,
+) -> Result<()> {
+    let mut entries: Vec<fs::DirEntry> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if name == ".git" {
+            continue;
+        }
+        let path = if relative_dir.is_empty() {
+            name
+        } else {
+            format!("{relative_dir}/{name}")
+        };
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if !entry.path().join(".git").exists() {
+                collect_worktree_files(&entry.path(), &path, files)?;
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct GrepOptions {
+    /// Search the blobs in the index instead of the working tree.
+    pub cached: bool,
+    /// Search the blobs of a tree, or of the tree of a commit.
+    pub tree: Option<String>,
+    pub ignore_case: bool,
+    /// Select the lines that don't match.
+    pub invert_match: bool,
+    /// Match the pattern as a fixed string instead of a regex.
+    pub fixed_strings: bool,
+    pub line_number: bool,
+    /// Only print the names of the files that match.
+    pub files_with_matches: bool,
+    /// Print the number of matching lines of each file.
+    pub count: bool,
+    /// Search the output of the textconv drivers of files instead of their
+    /// content.
+    pub textconv: bool,
+}
+
+/// Prints the lines matching a pattern in the tracked files of the working
+/// tree, in the index or in a tree, and returns whether anything matched.
+///
+/// Only tracked files are searched in the working tree, so ignored files never
+/// are. Files are read and matched in parallel with `grep.threads` threads,
+/// and printed in the order of the index or tree.
+pub fn grep(
+    repo: &Repo,
+    pattern: &str,
+    pathspecs: &[String],
+    options: &GrepOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<bool> {
+    enum Source {
+        Blob(String),
+        Worktree { mode: u32 },
+    }
+
+    let matcher = grep::Matcher::new(
+        pattern,
+        options.fixed_strings,
+        options.ignore_case,
+        options.invert_match,
+    )?;
+    let config = config::Config::load(repo)?;
+    let classifier = binary::BinaryClassifier::from_config(&config)?;
+    let attributes = attributes::Attributes::load(repo)?;
+    let threads = grep::thread_count(&config)?;
+    let textconv = textconv::Textconv::new(repo)?;
+
+    let in_pathspecs = |path: &str| {
+        pathspecs.is_empty() || pathspecs.iter().any(|spec| matches_pathspec(path, spec))
+    };
+    // The name to print, the path and where to read the content from.
+    let mut files: Vec<(String, String, Source)> = vec![];
+    if let Some(rev) = &options.tree {
+        let mut hash = Object::peel(repo, &Object::resolve_rev(repo, rev)?)?;
+        if let Object::Commit(commit) = Object::from_hash(repo, &hash)? {
+            hash = commit.tree;
+        }
+        let mut blobs = vec![];
+        collect_tree_blobs(repo, &hash, "", &mut blobs)?;
+        files.extend(
+            blobs
+                .into_iter()
+                .filter(|(path, _)| in_pathspecs(path))
+                .map(|(path, hash)| (format!("{rev}:{path}"), path, Source::Blob(hash))),
+        );
+    } else {
+        let index = index::Index::read(repo)?;
+        let mut seen = HashSet::new();
+        for entry in index.entries {
+            // Submodules aren't searched, and unmerged paths only once.
+            if entry.mode == 0o160000 || !in_pathspecs(&entry.path) {
+                continue;
+            }
+            if options.cached {
+                if entry.stage == 0 {
+                    files.push((entry.path.clone(), entry.path, Source::Blob(entry.hash)));
+                }
+            } else if seen.insert(entry.path.clone()) {
+                let source = Source::Worktree { mode: entry.mode };
+                files.push((entry.path.clone(), entry.path, source));
+            }
+        }
+    }
+
+    let outputs = grep::parallel_map(&files, threads, |(name, path, source)| {
+        let content = match source {
+            Source::Blob(hash) => match Object::from_hash(repo, hash)? {
+                Object::Blob(blob) => blob.content,
+                _ => return Err(anyhow!("Not a blob: {hash}")),
+            },
+            Source::Worktree { mode } => {
+                let full_path = repo.worktree_path(path)?;
+                let content = if *mode == 0o120000 {
+                    fs::read_link(&full_path)
+                        .map(|target| target.to_string_lossy().into_owned().into_bytes())
+                } else {
+                    fs::read(&full_path)
+                };
+                match content {
+                    Ok(content) => content,
+                    // Deleted files are skipped.
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        };
+        let converted = match (options.textconv, source) {
+            (false, _) => None,
+            (true, Source::Blob(hash)) => textconv.convert(path, Some(hash), &content)?,
+            (true, Source::Worktree { .. }) => textconv.convert(path, None, &content)?,
+        };
+        let content = converted.unwrap_or(content);
+        let lines = matcher.matching_lines(&content);
+        let mut output = vec![];
+        if lines.is_empty() {
+            return Ok(output);
+        }
+        if options.count {
+            writeln!(output, "{name}:{}", lines.len())?;
+        } else if options.files_with_matches {
+            writeln!(output, "{name}")?;
+        } else if classifier.is_binary(
+            &content,
+            content.len() as u64,
+            binary::TextAttribute::for_path(&attributes, &config, path)?,
+        ) {
+            writeln!(output, "Binary file {name} matches")?;
+        } else {
+            for (number, line) in lines {
+                if options.line_number {
+                    write!(output, "{name}:{number}:")?;
+                } else {
+                    write!(output, "{name}:")?;
+                }
+                output.extend(line);
+                output.push(b'\n');
+            }
+        }
+        Ok(output)
+    });
+
+    textconv.save()?;
+    let mut found = false;
+    for output in outputs {
+        let output = output?;
+        found |= !output.is_empty();
+        stdout.write_all(&output)?;
+    }
+    Ok(found)
+}
+
+/// Collects the paths and hashes of the blobs in a tree, recursively.
+fn collect_tree_blobs(
+    repo: &Repo,
+    hash: &str,
+    prefix: &str,
+    blobs: &mut Vec<(String, String)>,
+) -> Result<()> {
+    let Object::Tree(tree) = Object::from_hash(repo, hash)? else {
+        return Err(anyhow!("Not a tree: {hash}"));
+    };
+    for file in tree.files {
+        let path = format!("{prefix}{}", file.name);
+        match file.object_type() {
+            ObjectType::Tree => collect_tree_blobs(repo, &file.hash, &format!("{path}/"), blobs)?,
+            ObjectType::Blob => blobs.push((path, file.hash)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct DifftoolOptions {
+    /// Compare with the index instead of the working tree.
+    pub cached: bool,
+    /// The tool to run instead of `diff.tool`.
+    pub tool: Option<String>,
+    /// A command to run with the two directories as arguments, instead of a
+    /// tool.
+    pub extcmd: Option<String>,
+}
+
+/// Shows a diff in a tool comparing directories, like `git difftool
+/// --dir-diff`.
+///
+/// With no commits, the index is compared with the working tree, with one
+/// commit the commit is compared with the working tree, and with two commits
+/// the first commit with the second. With `cached`, the index replaces the
+/// working tree, and HEAD is the default commit.
+///
+/// The files that differ are written to two temporary directories, and the
+/// `difftool.<tool>.cmd` command is run with the `LOCAL` and `REMOTE`
+/// environment variables set to them. Tools without a command are run as
+/// `<tool> "$LOCAL" "$REMOTE"`. Working tree files edited in the tool are
+/// then copied back, unless they also changed in the working tree.
+pub fn difftool(
+    repo: &Repo,
+    commits: &[String],
+    options: &DifftoolOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let config = config::Config::load(repo)?;
+    let command = match &options.extcmd {
+        Some(extcmd) => format!("{extcmd} \"$LOCAL\" \"$REMOTE\""),
+        None => {
+            let tool = options
+                .tool
+                .as_deref()
+                .or(config.get("diff.tool"))
+                .or(config.get("merge.tool"))
+                .ok_or_else(|| anyhow!("No diff tool configured, set diff.tool or use --tool"))?;
+            match config.get(&format!("difftool.{tool}.cmd")) {
+                Some(command) => command.to_string(),
+                None => format!("{tool} \"$LOCAL\" \"$REMOTE\""),
+            }
+        }
+    };
+
+    let new_side = match options.cached {
+        true => difftool::Side::Index,
+        false => difftool::Side::Worktree,
+    };
+    let (left, right) = match commits {
+        [] if options.cached => {
+            let tree = match refs::unborn_branch(repo)? {
+                Some(_) => object::write_checked_object(repo, ObjectType::Tree, &[])?,
+                None => rev_tree(repo, "HEAD")?,
+            };
+            (difftool::Side::Tree(tree), new_side)
+        }
+        [] => (difftool::Side::Index, new_side),
+        [commit] => (difftool::Side::Tree(rev_tree(repo, commit)?), new_side),
+        [old, new] => (
+            difftool::Side::Tree(rev_tree(repo, old)?),
+            difftool::Side::Tree(rev_tree(repo, new)?),
+        ),
+        _ => return Err(anyhow!("Expected at most two commits")),
+    };
+
+    let mut dir_diff = difftool::DirDiff::create(repo, &left, &right)?;
+    if dir_diff.paths.is_empty() {
+        return Ok(());
+    }
+    std::process::Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .env("LOCAL", &dir_diff.left)
+        .env("REMOTE", &dir_diff.right)
+        .status()?;
+
+    let both_changed = dir_diff.copy_back(repo)?;
+    for path in &both_changed {
+        writeln!(
+            stdout,
+            "warning: both files modified: '{}' and '{}'.\nwarning: working tree file has been left.",
+            repo.worktree_path(path)?.display(),
+            dir_diff.right.join(path).display()
+        )?;
+    }
+    if !both_changed.is_empty() {
+        writeln!(
+            stdout,
+            "warning: temporary files exist in '{}'.\nwarning: you may want to cleanup or recover these.",
+            dir_diff.keep().display()
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct DiffOptions {
+    /// Compare with the index instead of the working tree.
+    pub cached: bool,
+    pub format: diff::RawFormat,
+    /// Print a summary instead of raw changes, before the patches if any.
+    pub stat: Option<diff::StatFormat>,
+    /// Print patches instead of raw changes.
+    pub patch: Option<diff::PatchOptions>,
+    /// Print the full hashes of raw changes instead of abbreviating them.
+    pub no_abbrev: bool,
+}
+
+/// Shows changes like `git diff`.
+///
+/// With no revisions, the index is compared with the working tree, or HEAD
+/// with the index if `cached` is set. A single revision is compared with the
+/// working tree, or with the index if `cached` is set. Two revisions, or a
+/// `<rev1>..<rev2>` range, are compared with each other, and
+/// `<rev1>...<rev2>` compares the merge base of both with `<rev2>`. A missing
+/// side of a range is HEAD.
+pub fn diff(
+    repo: &Repo,
+    revs: &[String],
+    pathspecs: &[String],
+    options: &DiffOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let changes = match revs {
+        [] if options.cached => {
+            let tree = match refs::unborn_branch(repo)? {
+                Some(_) => object::write_checked_object(repo, ObjectType::Tree, &[])?,
+                None => rev_tree(repo, "HEAD")?,
+            };
+            diff::diff_index(repo, &tree, true)?
+        }
+        [] => diff::diff_files(repo)?,
+        [rev] => match range_trees(repo, rev)? {
+            Some((old, new)) => diff::diff_trees(repo, Some(&old), Some(&new), true)?,
+            None => diff::diff_index(repo, &rev_tree(repo, rev)?, options.cached)?,
+        },
+        [old, new] => {
+            let (old, new) = (rev_tree(repo, old)?, rev_tree(repo, new)?);
+            diff::diff_trees(repo, Some(&old), Some(&new), true)?
+        }
+        _ => {
+            return Err(anyhow!(
+                "Expected at most two revisions, got {}",
+                revs.len()
+            ))
+        }
+    };
+    let changes = diff::drop_stat_only_changes(&diff::ChangeReader::new(repo)?, changes)?;
+    let abbreviator = object::Abbreviator::from_config(repo)?;
+    let format = ChangeFormat {
+        abbrev: (!options.no_abbrev).then_some(&abbreviator),
+        ..ChangeFormat::new(options.format, &options.stat, &options.patch)
+    };
+    print_raw_changes(repo, &changes, pathspecs, &format, None, stdout)
+}
+
+/// Returns the trees to compare for a `<rev1>..<rev2>` or `<rev1>...<rev2>`
+/// range, `None` if `rev` isn't a range.
+fn range_trees(repo: &Repo, rev: &str) -> Result<Option<(String, String)>> {
+    let or_head = |rev: &str| match rev {
+        "" => "HEAD".to_string(),
+        rev => rev.to_string(),
+    };
+    if let Some((old, new)) = rev.split_once("...") {
+        let old = Object::peel(repo, &Object::resolve_rev(repo, &or_head(old))?)?;
+        let new = Object::peel(repo, &Object::resolve_rev(repo, &or_head(new))?)?;
+        let base = graph::merge_bases(repo, &old, &new)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("{rev}: no merge base"))?;
+        return Ok(Some((rev_tree(repo, &base)?, rev_tree(repo, &new)?)));
+    }
+    match rev.split_once("..") {
+        Some((old, new)) => Ok(Some((
+            rev_tree(repo, &or_head(old))?,
+            rev_tree(repo, &or_head(new))?,
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// Returns the tree of a tree-ish revision, peeling tags and commits.
+fn rev_tree(repo: &Repo, rev: &str) -> Result<String> {
+    let hash = Object::peel(repo, &Object::resolve_rev(repo, rev)?)?;
+    match Object::from_hash(repo, &hash)? {
+        Object::Commit(commit) => Ok(commit.tree),
+        _ => Ok(hash),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DiffTreeOptions {
+    /// Compare the files of directories instead of their trees.
+    pub recursive: bool,
+    /// Compare root commits with the empty tree instead of skipping them.
+    pub root: bool,
+    pub format: diff::RawFormat,
+    /// Print a summary instead of raw changes, before the patches if any.
+    pub stat: Option<diff::StatFormat>,
+    /// Print patches instead of raw changes.
+    pub patch: Option<diff::PatchOptions>,
+}
+
+/// Compares two trees, or a commit with its parent, and prints the changes
+/// like `git diff-tree`.
+///
+/// The changes of a commit are preceded by its hash, unless there are none.
+/// Merge commits are skipped, and so are root commits unless `root` is set.
+/// With `pathspecs`, only the changes below them are printed.
+pub fn diff_tree(
+    repo: &Repo,
+    revs: &[String],
+    pathspecs: &[String],
+    options: &DiffTreeOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    match revs {
+        [rev] => {
+            let hash = Object::peel(repo, &Object::resolve_rev(repo, rev)?)?;
+            diff_tree_commit(repo, &hash, None, pathspecs, options, stdout)
+        }
+        [old, new] => {
+            let changes = diff::diff_trees(
+                repo,
+                Some(&rev_tree(repo, old)?),
+                Some(&rev_tree(repo, new)?),
+                options.recursive,
+            )?;
+            print_raw_changes(
+                repo,
+                &changes,
+                pathspecs,
+                &ChangeFormat::new(options.format, &options.stat, &options.patch),
+                None,
+                stdout,
+            )
+        }
+        _ => Err(anyhow!("Expected one or two trees, got {}", revs.len())),
+    }
+}
+
+/// Like [`diff_tree`] for each line of `stdin`, which is either two trees,
+/// printed before their changes, or a commit optionally followed by the
+/// parents to compare it with instead of its own.
+pub fn diff_tree_stdin(
+    repo: &Repo,
+    stdin: &mut dyn io::BufRead,
+    pathspecs: &[String],
+    options: &DiffTreeOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let line = line.trim_end_matches('\n');
+        let hashes: Vec<String> = line
+            .split_whitespace()
+            .map(|rev| Object::resolve_rev(repo, rev))
+            .collect::<Result<_>>()?;
+        let Some((first, rest)) = hashes.split_first() else {
+            continue;
+        };
+        match Object::from_hash(repo, first)? {
+            Object::Tree(_) if rest.len() == 1 => {
+                let changes =
+                    diff::diff_trees(repo, Some(first), Some(&rest[0]), options.recursive)?;
+                print_raw_changes(
+                    repo,
+                    &changes,
+                    pathspecs,
+                    &ChangeFormat::new(options.format, &options.stat, &options.patch),
+                    Some(line),
+                    stdout,
+                )?;
+            }
+            Object::Commit(_) => {
+                let parents = (!rest.is_empty()).then(|| rest.to_vec());
+                diff_tree_commit(repo, first, parents, pathspecs, options, stdout)?;
+            }
+            _ => return Err(anyhow!("Expected a commit or two trees: {line}")),
+        }
+    }
+}
+
+/// Prints the changes of a commit from its parent, or from `parents`
+/// instead of its own parents.
+fn diff_tree_commit(
+    repo: &Repo,
+    hash: &str,
+    parents: Option<Vec<String>>,
+    pathspecs: &[String],
+    options: &DiffTreeOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let Object::Commit(commit) = Object::from_hash(repo, hash)? else {
+        return Err(anyhow!("Not a commit: {hash}"));
+    };
+    let parent_tree = match &parents.unwrap_or(commit.parents)[..] {
+        [] if options.root => None,
+        [parent] => Some(rev_tree(repo, parent)?),
+        _ => return Ok(()),
+    };
+    let changes = diff::diff_trees(
+        repo,
+        parent_tree.as_deref(),
+        Some(&commit.tree),
+        options.recursive,
+    )?;
+    print_raw_changes(
+        repo,
+        &changes,
+        pathspecs,
+        &ChangeFormat::new(options.format, &options.stat, &options.patch),
+        Some(hash),
+        stdout,
+    )
+}
+
+#[derive(Debug, Default)]
+pub struct DiffIndexOptions {
+    /// Compare the tree with the index instead of the working tree.
+    pub cached: bool,
+    pub format: diff::RawFormat,
+    /// Print a summary instead of raw changes, before the patches if any.
+    pub stat: Option<diff::StatFormat>,
+    /// Print patches instead of raw changes.
+    pub patch: Option<diff::PatchOptions>,
+}
+
+/// Compares a tree with the working tree, or with the index if `cached` is
+/// set, and prints the changes like `git diff-index`. See
+/// [`diff::diff_index`].
+pub fn diff_index(
+    repo: &Repo,
+    rev: &str,
+    pathspecs: &[String],
+    options: &DiffIndexOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let changes = diff::diff_index(repo, &rev_tree(repo, rev)?, options.cached)?;
+    print_raw_changes(
+        repo,
+        &changes,
+        pathspecs,
+        &ChangeFormat::new(options.format, &options.stat, &options.patch),
+        None,
+        stdout,
+    )
+}
+
+#[derive(Debug, Default)]
+pub struct DiffFilesOptions {
+    pub format: diff::RawFormat,
+    /// Print a summary instead of raw changes, before the patches if any.
+    pub stat: Option<diff::StatFormat>,
+    /// Print patches instead of raw changes.
+    pub patch: Option<diff::PatchOptions>,
+}
+
+/// Compares the index with the working tree and prints the changes like `git
+/// diff-files`.
+pub fn diff_files(
+    repo: &Repo,
+    pathspecs: &[String],
+    options: &DiffFilesOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let changes = diff::diff_files(repo)?;
+    let format = ChangeFormat::new(options.format, &options.stat, &options.patch);
+    print_raw_changes(repo, &changes, pathspecs, &format, None, stdout)
+}
+
+/// How [`print_raw_changes`] prints changes: as patches with patch options,
+/// after a summary with a stat format, and as raw changes with neither.
+struct ChangeFormat<'a> {
+    raw: diff::RawFormat,
+    /// Abbreviates the hashes of raw changes, like porcelain commands.
+    abbrev: Option<&'a object::Abbreviator<'a>>,
+    stat: Option<&'a diff::StatFormat>,
+    patch: Option<&'a diff::PatchOptions>,
+}
+
+impl<'a> ChangeFormat<'a> {
+    fn new(
+        raw: diff::RawFormat,
+        stat: &'a Option<diff::StatFormat>,
+        patch: &'a Option<diff::PatchOptions>,
+    ) -> Self {
+        ChangeFormat {
+            raw,
+            abbrev: None,
+            stat: stat.as_ref(),
+            patch: patch.as_ref(),
+        }
+    }
+}
+
+/// Prints the changes below `pathspecs`, if any, after `header` if there are
+/// some.
+fn print_raw_changes(
+    repo: &Repo,
+    changes: &[diff::RawChange],
+    pathspecs: &[String],
+    format: &ChangeFormat,
+    header: Option<&str>,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let changes: Vec<&diff::RawChange> = changes
+        .iter()
+        .filter(|change| {
+            pathspecs.is_empty()
+                || pathspecs
+                    .iter()
+                    .any(|pathspec| matches_pathspec(&change.path, pathspec))
+        })
+        .collect();
+    if let (Some(header), false) = (header, changes.is_empty()) {
+        writeln!(stdout, "{header}")?;
+    }
+    let reader = diff::ChangeReader::new(repo)?;
+    if let Some(stat) = format.stat {
+        let stats = changes
+            .iter()
+            .map(|change| diff::FileStat::compute(&reader, change))
+            .collect::<Result<Vec<_>>>()?;
+        diff::write_stats(&stats, stat, stdout)?;
+        if format.patch.is_some() && !changes.is_empty() {
+            writeln!(stdout)?;
+        }
+    }
+    for change in changes {
+        match format.patch {
+            Some(patch) => diff::write_patch(&reader, change, patch, stdout)?,
+            None if format.stat.is_some() => {}
+            None => match (format.raw, format.abbrev) {
+                (diff::RawFormat::Raw, Some(abbreviator)) => {
+                    writeln!(stdout, "{}", change.to_abbreviated_string(abbreviator)?)?
+                }
+                _ => writeln!(stdout, "{}", format.raw.format(change))?,
+            },
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct ApplyOptions {
+    /// Apply the patches to the index only, leaving the working tree alone.
+    pub cached: bool,
+    /// Apply the patches to both the index and the working tree, whose
+    /// patched files must match the index.
+    pub index: bool,
+    /// Only check that the patches apply.
+    pub check: bool,
+    /// Apply the patches in reverse.
+    pub reverse: bool,
+    /// The number of leading components removed from the paths of the
+    /// patches, 1 by default for their `a/` and `b/` prefixes.
+    pub strip: usize,
+    /// Let hunks that don't match lose context lines down to this many on
+    /// each side, see [`apply::apply_hunks`].
+    pub context: Option<usize>,
+    /// Don't print why hunks don't apply.
+    pub quiet: bool,
+}
+
+impl Default for ApplyOptions {
+    fn default() -> Self {
+        ApplyOptions {
+            cached: false,
+            index: false,
+            check: false,
+            reverse: false,
+            strip: 1,
+            context: None,
+            quiet: false,
+        }
+    }
+}
+
+/// Applies the file patches of a diff to the working tree, to the index with
+/// `cached`, or to both with `index`, see [`apply::parse`].
+///
+/// Like git, either every patch applies or nothing changes: all of them are
+/// applied in memory first, each one to the result of the previous ones for
+/// the same path. New files must not exist yet, and deleted files must be
+/// empty once patched. Files are read and written through their filters.
+///
+/// Why hunks don't apply, and paths whose mode isn't the one the patch
+/// expects, are reported to `stderr`.
+pub fn apply(
+    repo: &Repo,
+    patch: &[u8],
+    options: &ApplyOptions,
+    stderr: &mut dyn io::Write,
+) -> Result<()> {
+    let mut patches = apply::parse(patch, options.strip)?;
+    if patches.is_empty() {
+        return Err(anyhow!("No valid patches in input"));
+    }
+    if options.reverse {
+        patches = patches
+            .into_iter()
+            .map(apply::FilePatch::reverse)
+            .collect::<Result<_>>()?;
+    }
+
+    let mut index = index::Index::read(repo)?;
+    let filters = filter::Filters::new(repo)?;
+    let modes = index::FileModes::load(repo)?;
+    let from_index = options.cached || options.index;
+    let target = match from_index {
+        true => "index",
+        false => "working directory",
+    };
+    // The content and mode of each path once patched, `None` if removed.
+    let mut results: BTreeMap<String, Option<(Vec<u8>, u32)>> = BTreeMap::new();
+    for patch in &patches {
+        let current = |path: &String| match results.get(path) {
+            Some(result) => Ok(result.clone()),
+            None => read_apply_target(repo, &index, &filters, modes, path, from_index),
+        };
+        let old = match &patch.old_path {
+            Some(path) => match current(path)? {
+                Some(old) => Some(old),
+                None if from_index => {
+                    return Err(anyhow!("{path}: does not exist in index"));
+                }
+                None => return Err(anyhow!("{path}: No such file or directory")),
+            },
+            None => None,
+        };
+        if let Some(path) = &patch.new_path {
+            if patch.old_path.as_ref() != Some(path) && current(path)?.is_some() {
+                return Err(anyhow!("{path}: already exists in {target}"));
+            }
+        }
+        if let (true, Some(path)) = (options.index, &patch.old_path) {
+            // Paths patched before were checked then.
+            let entry = index
+                .entries
+                .iter()
+                .find(|entry| entry.path == *path && entry.stage == 0)
+                .filter(|_| !results.contains_key(path));
+            if let Some(entry) = entry {
+                if !worktree_matches(repo, &filters, entry, modes)? {
+                    return Err(anyhow!("{path}: does not match index"));
+                }
+            }
+        }
+        if let (Some(path), Some((_, mode)), Some(expected)) =
+            (&patch.old_path, &old, patch.old_mode)
+        {
+            if *mode != expected {
+                writeln!(
+                    stderr,
+                    "warning: {path} has type {mode:o}, expected {expected:o}"
+                )?;
+            }
+        }
+
+        let (old_content, old_mode) = old.unwrap_or((vec![], 0o100644));
+        let content = match patch.apply(repo, &old_content, options.context) {
+            Ok(content) => content,
+            Err(e) => {
+                if !options.quiet {
+                    writeln!(stderr, "error: {e}")?;
+                }
+                return Err(anyhow!("{}: patch does not apply", patch.path()));
+            }
+        };
+        if patch.new_path.is_none() && !content.is_empty() {
+            return Err(anyhow!(
+                "{}: removal patch leaves file contents",
+                patch.path()
+            ));
+        }
+        if let Some(path) = &patch.old_path {
+            if !patch.copy {
+                results.insert(path.clone(), None);
+            }
+        }
+        if let Some(path) = &patch.new_path {
+            let mode = patch.new_mode.unwrap_or(old_mode);
+            results.insert(path.clone(), Some((content, mode)));
+        }
+    }
+    if options.check {
+        return Ok(());
+    }
+
+    // Removed files go first, since new files may replace their directories.
+    let (removed, written): (Vec<_>, Vec<_>) = results
+        .into_iter()
+        .partition(|(_, result)| result.is_none());
+    for (path, _) in removed {
+        if from_index {
+            index.remove(&path);
+        }
+        if !options.cached {
+            remove_worktree_file(repo, &path)?;
+        }
+    }
+    for (path, result) in written {
+        let (content, mode) = result.expect("removed files were partitioned out");
+        let hash = match from_index {
+            true => Some(object::write_checked_object(
+                repo,
+                ObjectType::Blob,
+                &content,
+            )?),
+            false => None,
+        };
+        if !options.cached {
+            write_apply_result(repo, &filters, modes, &path, content, mode)?;
+        }
+        if let Some(hash) = hash {
+            // With the working tree, the entry gets the stat data of its
+            // new file.
+            let entry = match options.index {
+                true => {
+                    let metadata = fs::symlink_metadata(repo.worktree_path(&path)?)?;
+                    index::IndexEntry::from_metadata(&path, &hash, &metadata)
+                }
+                false => Default::default(),
+            };
+            add_index_entry(
+                &mut index,
+                index::IndexEntry {
+                    mode,
+                    hash,
+                    path,
+                    ..entry
+                },
+            );
+        }
+    }
+    if from_index {
+        index.write(repo)?;
+    }
+    Ok(())
+}
+
+/// Returns the content and mode of a file that patches apply to, from the
+/// index with `cached` or else from the working tree, cleaned by its filter,
+/// or `None` if there's no such file.
+fn read_apply_target(
+    repo: &Repo,
+    index: &index::Index,
+    filters: &filter::Filters,
+    modes: index::FileModes,
+    path: &str,
+    cached: bool,
+) -> Result<Option<(Vec<u8>, u32)>> {
+    let entry = index
+        .entries
+        .iter()
+        .find(|entry| entry.path == path && entry.stage == 0);
+    if cached {
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+        let Object::Blob(blob) = Object::from_hash(repo, &entry.hash)? else {
+            return Err(anyhow!("Not a blob: {}", entry.hash));
+        };
+        return Ok(Some((blob.content, entry.mode)));
+    }
+    let full_path = repo.worktree_path(path)?;
+    let metadata = match fs::symlink_metadata(&full_path) {
+        Ok(metadata) if metadata.is_dir() => return Ok(None),
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mode = modes.mode(&metadata, entry.map(|entry| entry.mode));
+    let content = match metadata.is_symlink() {
+        true => fs::read_link(&full_path)?
+            .to_string_lossy()
+            .into_owned()
+            .into_bytes(),
+        false => filters.clean(path, fs::read(&full_path)?)?,
+    };
+    Ok(Some((content, mode)))
+}
+
+/// Writes the patched content of a file to the working tree, replacing the
+/// file, and creating its directories.
+fn write_apply_result(
+    repo: &Repo,
+    filters: &filter::Filters,
+    modes: index::FileModes,
+    path: &str,
+    content: Vec<u8>,
+    mode: u32,
+) -> Result<()> {
+    let full_path = repo.worktree_path(path)?;
+    let parent = full_path.parent().expect("worktree paths have a parent");
+    fs::create_dir_all(parent)?;
+    match fs::symlink_metadata(&full_path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(&full_path)?,
+        Ok(_) => fs::remove_file(&full_path)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    if mode == 0o120000 && modes.symlinks {
+        return write_symlink(&content, &full_path);
+    }
+    let content = match mode {
+        0o120000 => content,
+        _ => filters.smudge(path, content)?,
+    };
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(if mode & 0o100 != 0 { 0o777 } else { 0o666 });
+    }
+    options.open(&full_path)?.write_all(&content)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct FormatPatchOptions {
+    /// Only the last this many commits, like `-<n>`.
+    pub max_count: Option<usize>,
+    /// Number the patches in their subjects, like `[PATCH 1/2]`. By default
+    /// they're numbered unless there's only one.
+    pub numbered: Option<bool>,
+    /// The number of the first patch, 1 by default.
+    pub start_number: usize,
+    /// What the subjects start with in brackets, `PATCH` by default.
+    pub subject_prefix: String,
+    /// What follows the `-- ` line ending each patch, if anything.
+    pub signature: Option<String>,
+    /// Where to write the patch files, the current directory by default.
+    pub output_directory: Option<std::path::PathBuf>,
+    /// Print the patches as an mbox instead of writing them to files.
+    pub stdout: bool,
+}
+
+impl Default for FormatPatchOptions {
+    fn default() -> Self {
+        FormatPatchOptions {
+            max_count: None,
+            numbered: None,
+            start_number: 1,
+            subject_prefix: "PATCH".to_string(),
+            signature: None,
+            output_directory: None,
+            stdout: false,
+        }
+    }
+}
+
+/// Writes the commits of `revs` as mails, oldest first, one file per
+/// commit, printing the paths of the files, see
+/// [`format_patch::write_mail`]. Renamed files are shown as such, see
+/// [`diff::detect_renames`].
+///
+/// Like git, a single rev `<since>` stands for `<since>..HEAD`, unless
+/// `max_count` is set, in which case it's where the commits are taken from.
+/// Merges are skipped, and so are commits that change nothing, though they
+/// keep their number and, like git, an empty file.
+pub fn format_patch(
+    repo: &Repo,
+    revs: &[String],
+    options: &FormatPatchOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let revs = match revs {
+        [] if options.max_count.is_none() => return Ok(()),
+        [] => vec!["HEAD".to_string()],
+        [since] if options.max_count.is_none() && !since.contains("..") => {
+            vec![format!("^{since}"), "HEAD".to_string()]
+        }
+        revs => revs.to_vec(),
+    };
+    let mut commits: Vec<(String, Commit)> = walk_revs(repo, &revs, false)?
+        .into_iter()
+        .filter(|(_, commit, _)| commit.parents.len() <= 1)
+        .map(|(hash, commit, _)| (hash, commit))
+        .take(options.max_count.unwrap_or(usize::MAX))
+        .collect();
+    commits.reverse();
+
+    let total = commits.len();
+    let numbered = options.numbered.unwrap_or(total > 1);
+    if let Some(dir) = &options.output_directory {
+        fs::create_dir_all(dir)?;
+    }
+    let mut printed = false;
+    for (i, (hash, commit)) in commits.iter().enumerate() {
+        let changes = diff::detect_renames(repo, format_patch::commit_changes(repo, commit)?)?;
+        let number = options.start_number + i;
+        let prefix = match numbered {
+            true => format!(
+                "{} {number}/{}",
+                options.subject_prefix,
+                options.start_number + total - 1
+            ),
+            false => options.subject_prefix.clone(),
+        };
+        let mut mail = vec![];
+        if !changes.is_empty() {
+            format_patch::write_mail(
+                repo,
+                hash,
+                commit,
+                &changes,
+                prefix.trim_start(),
+                options.signature.as_deref(),
+                &mut mail,
+            )?;
+        }
+        if options.stdout {
+            if mail.is_empty() {
+                continue;
+            }
+            // Like git, mails are separated by an empty line.
+            if printed {
+                writeln!(stdout)?;
+            }
+            stdout.write_all(&mail)?;
+            printed = true;
+            continue;
+        }
+        let name = format_patch::file_name(number, &pretty::subject(&commit.message));
+        let path = match &options.output_directory {
+            Some(dir) => dir.join(name),
+            None => std::path::PathBuf::from(name),
+        };
+        fs::write(&path, mail)?;
+        writeln!(stdout, "{}", path.display())?;
+    }
+    Ok(())
+}
+
+/// Prints the commits of `head`, HEAD by default, that aren't in
+/// `upstream`, oldest first, like `git cherry`: `-` before the commits with
+/// an equivalent commit in `upstream`, with the same patch ID, and `+`
+/// before the others, then their hashes, and their subjects if `verbose`.
+///
+/// `upstream` defaults to the upstream branch of the current branch, and
+/// with `limit`, the commits of `limit` aren't printed. Like git, merges are
+/// skipped, and nothing is printed if `head` and `upstream` are the same.
+pub fn cherry(
+    repo: &Repo,
+    upstream: Option<&str>,
+    head: Option<&str>,
+    limit: Option<&str>,
+    verbose: bool,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let upstream = match upstream {
+        Some(upstream) => upstream.to_string(),
+        None => upstream_branch(repo)?.ok_or_else(|| {
+            anyhow!("Could not find a tracked remote branch, please specify <upstream> manually.")
+        })?,
+    };
+    let head = head.unwrap_or("HEAD");
+    let resolve = |rev: &str| {
+        Object::resolve_rev(repo, rev)
+            .and_then(|hash| Object::peel(repo, &hash))
+            .map_err(|_| anyhow!("unknown commit {rev}"))
+    };
+    let (head, upstream) = (resolve(head)?, resolve(&upstream)?);
+    if head == upstream {
+        return Ok(());
+    }
+
+    let classifier = classifier(repo)?;
+    let mut upstream_ids = HashSet::new();
+    for (_, commit, _) in walk_revs(repo, &[upstream.clone(), format!("^{head}")], false)? {
+        if let Some(id) = patch_id::commit_patch_id(repo, &classifier, &commit)? {
+            upstream_ids.insert(id);
+        }
+    }
+    let mut revs = vec![head, format!("^{upstream}")];
+    if let Some(limit) = limit {
+        revs.push(format!("^{}", resolve(limit)?));
+    }
+    let mut commits = walk_revs(repo, &revs, false)?;
+    commits.reverse();
+    for (hash, commit, _) in commits {
+        if commit.parents.len() > 1 {
+            continue;
+        }
+        let sign = match patch_id::commit_patch_id(repo, &classifier, &commit)? {
+            Some(id) if upstream_ids.contains(&id) => '-',
+            _ => '+',
+        };
+        match verbose {
+            true => writeln!(stdout, "{sign} {hash} {}", pretty::subject(&commit.message))?,
+            false => writeln!(stdout, "{sign} {hash}")?,
+        }
+    }
+    Ok(())
+}
+
+/// Returns the upstream branch of the current branch, from
+/// `branch.<name>.remote` and `branch.<name>.merge`: the remote-tracking
+/// branch the merged branch is fetched to, or the merged branch itself for
+/// the remote `.`, the repository itself.
+fn upstream_branch(repo: &Repo) -> Result<Option<String>> {
+    let config = config::Config::load(repo)?;
+    let refs::Head::Branch(branch) = refs::head(repo)? else {
+        return Ok(None);
+    };
+    let branch = branch.strip_prefix("refs/heads/").unwrap_or(&branch);
+    let branch_config = |key: &str| config.get(&format!("branch.{branch}.{key}"));
+    let (Some(remote), Some(merge)) = (branch_config("remote"), branch_config("merge")) else {
+        return Ok(None);
+    };
+    if remote == "." {
+        return Ok(Some(merge.to_string()));
+    }
+    let refspecs = config.get_all(&format!("remote.{remote}.fetch"));
+    for refspec in refspecs {
+        let refspec = remote::Refspec::parse(refspec)?;
+        let tracking = match refspec.is_pattern() {
+            true => refspec.map_pattern(merge),
+            false => (refspec.src == merge)
+                .then(|| refspec.dst.clone())
+                .flatten(),
+        };
+        if tracking.is_some() {
+            return Ok(tracking);
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Debug)]
+pub struct RangeDiffOptions {
+    /// The percentage of the size of a commit's changes that leaving it
+    /// without a pair costs, compared to the size of the diff with its pair,
+    /// 60 by default. Higher values pair more commits.
+    pub creation_factor: usize,
+}
+
+impl Default for RangeDiffOptions {
+    fn default() -> Self {
+        RangeDiffOptions {
+            creation_factor: 60,
+        }
+    }
+}
+
+/// Compares two versions of a series of commits, given as ranges like
+/// `<base>..<branch>`, pairing their commits and showing how the pairs
+/// differ, see [`range_diff::write_range_diff`].
+pub fn range_diff(
+    repo: &Repo,
+    old_range: &str,
+    new_range: &str,
+    options: &RangeDiffOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let old = range_diff::read_patches(repo, old_range)?;
+    let new = range_diff::read_patches(repo, new_range)?;
+    range_diff::write_range_diff(repo, &old, &new, options.creation_factor, stdout)
+}
+
+/// How [`blame`] shows the blamed lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlameFormat {
+    /// For people, see [`blame::write_blame`].
+    #[default]
+    Default,
+    /// For other programs, like `--porcelain`, see
+    /// [`blame::write_porcelain`].
+    Porcelain,
+    /// Like [`BlameFormat::Porcelain`] with the details of the commit of
+    /// every line, like `--line-porcelain`.
+    LinePorcelain,
+}
+
+#[derive(Debug, Default)]
+pub struct BlameOptions {
+    /// The ranges of lines to blame, like `-L 10,20`, see
+    /// [`blame::parse_range`]. All the lines if empty.
+    pub ranges: Vec<String>,
+    /// Ignore whitespace when comparing lines, like `-w`.
+    pub ignore_whitespace: bool,
+    /// How the blamed lines are shown.
+    pub format: BlameFormat,
+}
+
+/// Shows the commit that last changed each line of a file, like `git
+/// blame`: the file of a rev, or of the working tree, whose changes are
+/// blamed on a commit on top of HEAD, see [`blame::blame`].
+pub fn blame(
+    repo: &Repo,
+    rev: Option<&str>,
+    path: &str,
+    options: &BlameOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let origin = match rev {
+        Some(rev) => {
+            let hash = Object::peel(repo, &Object::resolve_rev(repo, rev)?)?;
+            blame::Origin::from_commit(repo, &hash, path)?
+                .ok_or_else(|| anyhow!("no such path {path} in {rev}"))?
+        }
+        None => blame::Origin::working_tree(repo, path)?,
+    };
+    let lines = origin.line_count();
+    let ranges = match options.ranges.is_empty() {
+        true => vec![(1, lines)],
+        false => options
+            .ranges
+            .iter()
+            .map(|range| blame::parse_range(range, path, lines))
+            .collect::<Result<_>>()?,
+    };
+    let blame = blame::blame(repo, origin, &ranges, options.ignore_whitespace)?;
+    match options.format {
+        BlameFormat::Default => blame::write_blame(repo, &blame, stdout),
+        BlameFormat::Porcelain => blame::write_porcelain(&blame, false, stdout),
+        BlameFormat::LinePorcelain => blame::write_porcelain(&blame, true, stdout),
+    }
+}
+
+/// Whether [`merge`] may fast-forward, like `--ff`, `--no-ff` and
+/// `--ff-only`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FastForward {
+    /// Fast-forward when possible, otherwise create a merge commit.
+    #[default]
+    Allow,
+    /// Always create a merge commit.
+    Never,
+    /// Only fast-forward, refusing to merge otherwise.
+    Only,
+}
+
+impl FastForward {
+    /// Returns the default of `merge.ff`.
+    pub fn from_config(config: &config::Config) -> Result<FastForward> {
+        if config.get("merge.ff") == Some("only") {
+            return Ok(FastForward::Only);
+        }
+        Ok(match config.get_bool("merge.ff")? {
+            Some(false) => FastForward::Never,
+            _ => FastForward::Allow,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MergeOptions {
+    pub fast_forward: FastForward,
+    /// The message of the merge commit, instead of one naming what is
+    /// merged, like `Merge branch 'topic'`.
+    pub message: Option<String>,
+    /// Stage the merged changes without committing them or recording the
+    /// merge, like `--squash`.
+    pub squash: bool,
+    /// Stop before creating the merge commit, like `--no-commit`.
+    pub no_commit: bool,
+}
+
+/// Merges a commit into HEAD, like `git merge`.
+///
+/// If HEAD is an ancestor of the commit, HEAD is fast-forwarded to it, unless
+/// `fast_forward` says otherwise. Otherwise they are merged given their merge
+/// bases, see [`merge::merge_commits`], and a clean result is
+/// committed with both commits as parents. The staged changes must not
+/// differ from HEAD, and local changes to the files the merge changes are
+/// refused, like git.
+///
+/// Returns whether the merge is clean, otherwise the conflicts are left in the
+/// index and the working tree, and the merge is concluded by [`commit`], see
+/// [`merge::State`]. So are merges with `no_commit` that aren't
+/// fast-forwards, and merges with `squash`, whose commit has a single parent
+/// and a message listing the merged commits.
+///
+/// Like git, the commit HEAD was at is saved in `ORIG_HEAD`.
+pub fn merge(
+    repo: &Repo,
+    rev: &str,
+    options: &MergeOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<bool> {
+    if index::Index::read(repo)?
+        .entries
+        .iter()
+        .any(|entry| entry.stage != 0)
+    {
+        return Err(anyhow!(
+            "Merging is not possible because you have unmerged files."
+        ));
+    }
+    if options.squash && options.fast_forward == FastForward::Never {
+        return Err(anyhow!("You cannot combine --squash with --no-ff."));
+    }
+    if let Some(state) = merge::State::read(repo)? {
+        let unconcluded = match state.cherry_pick_head {
+            Some(_) => "cherry-pick (CHERRY_PICK_HEAD exists)",
+            None if !state.merge_heads.is_empty() => "merge (MERGE_HEAD exists)",
+            None => "",
+        };
+        if !unconcluded.is_empty() {
+            return Err(anyhow!(
+                "You have not concluded your {unconcluded}.\n\
+                 Please, commit your changes before you merge."
+            ));
+        }
+    }
+    let theirs = Object::resolve_rev(repo, rev)
+        .and_then(|hash| Object::peel(repo, &hash))
+        .map_err(|_| anyhow!("{rev} - not something we can merge"))?;
+    let head_ref = match refs::head(repo)? {
+        refs::Head::Branch(name) => name,
+        refs::Head::Detached(_) => "HEAD".to_string(),
+    };
+    let operation = format!("merge {rev}");
+    let Ok(head) = refs::find_ref(repo, "HEAD") else {
+        // Merging into an unborn branch checks the commit out.
+        if options.squash {
+            return Err(anyhow!("Squash commit into empty head not supported yet"));
+        }
+        if options.fast_forward == FastForward::Never {
+            return Err(anyhow!(
+                "Non-fast-forward commit does not make sense into an empty head"
+            ));
+        }
+        let change = journal::RefChange {
+            name: head_ref,
+            old: None,
+            new: theirs.clone(),
+        };
+        move_to_tree(
+            repo,
+            &rev_tree(repo, &theirs)?,
+            &operation,
+            vec![change.clone()],
+            "merge",
+        )?;
+        log_ref_change(repo, &change, &format!("{operation}: Fast-forward"))?;
+        return Ok(true);
+    };
+    refs::update_ref(repo, "ORIG_HEAD", &head)?;
+
+    let abbreviator = object::Abbreviator::from_config(repo)?;
+    let bases = graph::merge_bases(repo, &head, &theirs)?;
+    if bases.contains(&theirs) {
+        writeln!(stdout, "Already up to date.")?;
+        return Ok(true);
+    }
+    let head_tree = rev_tree(repo, &head)?;
+    let theirs_tree = rev_tree(repo, &theirs)?;
+    if bases == [head.clone()] && options.fast_forward != FastForward::Never {
+        writeln!(
+            stdout,
+            "Updating {}..{}",
+            abbreviator.abbreviate(&head)?,
+            abbreviator.abbreviate(&theirs)?
+        )?;
+        if options.squash {
+            move_to_tree(repo, &theirs_tree, &operation, vec![], "merge")?;
+            let state = merge::State {
+                merge_heads: vec![],
+                message: squash_message(repo, &head, &theirs)?,
+                no_ff: false,
+                auto_merge: None,
+                cherry_pick_head: None,
+                revert_head: None,
+                rebase_head: None,
+            };
+            state.write(repo)?;
+            writeln!(stdout, "Fast-forward\nSquash commit -- not updating HEAD")?;
+        } else {
+            let change = journal::RefChange {
+                name: head_ref,
+                old: Some(head),
+                new: theirs,
+            };
+            move_to_tree(
+                repo,
+                &theirs_tree,
+                &operation,
+                vec![change.clone()],
+                "merge",
+            )?;
+            log_ref_change(repo, &change, &format!("{operation}: Fast-forward"))?;
+            writeln!(stdout, "Fast-forward")?;
+        }
+        write_diffstat(repo, &head_tree, &theirs_tree, stdout)?;
+        return Ok(true);
+    }
+    if options.fast_forward == FastForward::Only {
+        return Err(anyhow!("Not possible to fast-forward, aborting."));
+    }
+    let Some(base) = bases.first() else {
+        return Err(anyhow!("refusing to merge unrelated histories"));
+    };
+    let staged: Vec<String> = diff::diff_index(repo, &head_tree, true)?
+        .into_iter()
+        .map(|change| change.path)
+        .collect();
+    if !staged.is_empty() {
+        return Err(anyhow!(
+            "Your local changes to the following files would be overwritten by merge:\n\t{}\n\
+             Please commit your changes or stash them before you merge.\nAborting",
+            staged.join("\n\t")
+        ));
+    }
+
+    let config = config::Config::load(repo)?;
+    let file_options = merge_file::MergeOptions {
+        style: merge_file::ConflictStyle::from_config(&config)?,
+        labels: [
+            Some("HEAD".to_string()),
+            Some(abbreviator.abbreviate(base)?),
+            Some(rev.to_string()),
+        ],
+        ..Default::default()
+    };
+    let merged = merge::merge_commits(repo, &bases, &head, &theirs, &file_options)?;
+    for message in &merged.messages {
+        writeln!(stdout, "{message}")?;
+    }
+    let message = match &options.message {
+        _ if options.squash => squash_message(repo, &head, &theirs)?,
+        Some(message) => cleanup_message(message),
+        None => format!("{}\n", merge_message(repo, rev, &head_ref)),
+    };
+    let clean = merged.conflicts.is_empty();
+    if !clean || options.squash || options.no_commit {
+        checkout_merged(repo, &merged, &operation, vec![], "merge", stdout)?;
+        // The conflicts are listed in comments for the message of the
+        // commit concluding the merge.
+        let mut message = message;
+        if !clean {
+            message.push_str("\n# Conflicts:\n");
+            for path in merged.conflicts.keys() {
+                message.push_str(&format!("#\t{path}\n"));
+            }
+        }
+        let state = merge::State {
+            merge_heads: if options.squash { vec![] } else { vec![theirs] },
+            message,
+            no_ff: options.fast_forward == FastForward::Never,
+            auto_merge: Some(merged.tree.clone()),
+            cherry_pick_head: None,
+            revert_head: None,
+            rebase_head: None,
+        };
+        state.write(repo)?;
+        if clean {
+            writeln!(
+                stdout,
+                "Automatic merge went well; stopped before committing as requested"
+            )?;
+        }
+        if options.squash {
+            writeln!(stdout, "Squash commit -- not updating HEAD")?;
+        }
+        if !clean {
+            writeln!(
+                stdout,
+                "Automatic merge failed; fix conflicts and then commit the result."
+            )?;
+        }
+        return Ok(clean);
+    }
+
+    let commit = Commit {
+        tree: merged.tree.clone(),
+        parents: vec![head.clone(), theirs],
+        author: ident::Ident::new(&config, ident::Role::Author)?.to_string(),
+        committer: ident::Ident::new(&config, ident::Role::Committer)?.to_string(),
+        message,
+        ..Default::default()
+    };
+    let hash = object::write_checked_object(repo, ObjectType::Commit, &commit.to_bytes())?;
+    let change = journal::RefChange {
+        name: head_ref,
+        old: Some(head),
+        new: hash,
+    };
+    checkout_merged(
+        repo,
+        &merged,
+        &operation,
+        vec![change.clone()],
+        "merge",
+        stdout,
+    )?;
+    log_ref_change(
+        repo,
+        &change,
+        &format!("{operation}: Merge made by the 'ort' strategy."),
+    )?;
+    writeln!(stdout, "Merge made by the 'ort' strategy.")?;
+    write_diffstat(repo, &head_tree, &merged.tree, stdout)?;
+    Ok(true)
+}
+
+/// Abandons a merge stopped before its commit, like `git merge --abort`,
+/// resetting the index, and the files that differ from it, to HEAD. Other
+/// local changes are kept.
+pub fn merge_abort(repo: &Repo) -> Result<()> {
+    match merge::State::read(repo)? {
+        Some(state) if !state.merge_heads.is_empty() => {}
+        _ => return Err(anyhow!("There is no merge to abort (MERGE_HEAD missing).")),
+    }
+    rerere::clear(repo)?;
+    let head = refs::find_ref(repo, "HEAD").ok();
+    reset_to_orig_head(repo, head.as_deref(), "merge --abort")?;
+    merge::State::remove(repo)
+}
+
+/// Returns the message of a squash merge, which lists the merged commits
+/// like `git log`.
+fn squash_message(repo: &Repo, head: &str, theirs: &str) -> Result<String> {
+    let abbreviator = object::Abbreviator::from_config(repo)?;
+    let mut message = "Squashed commit of the following:\n".to_string();
+    for (hash, commit, _) in walk_revs(repo, &[format!("{head}..{theirs}")], false)? {
+        let format = "\ncommit %H%nAuthor: %an <%ae>%nDate:   %ad%n%n";
+        message.push_str(&String::from_utf8_lossy(&pretty::format_commit(
+            repo,
+            &abbreviator,
+            &hash,
+            &commit,
+            format,
+        )?));
+        for line in commit.message.lines() {
+            match line.is_empty() {
+                true => message.push('\n'),
+                false => message.push_str(&format!("    {line}\n")),
+            }
+        }
+    }
+    Ok(message)
+}
+
+/// Returns the default message of a merge commit, like `Merge branch
+/// 'topic'` or `Merge commit '1234567' into topic`. Like git, merges into
+/// `main` and `master` don't say so.
+fn merge_message(repo: &Repo, rev: &str, head_ref: &str) -> String {
+    let exists = |prefix: &str| refs::find_ref(repo, &format!("{prefix}{rev}")).is_ok();
+    let merged = if exists("refs/heads/") {
+        format!("branch '{rev}'")
+    } else if exists("refs/tags/") {
+        format!("tag '{rev}'")
+    } else if exists("refs/remotes/") {
+        format!("remote-tracking branch '{rev}'")
+    } else {
+        format!("commit '{rev}'")
+    };
+    match head_ref.strip_prefix("refs/heads/") {
+        Some("main" | "master") => format!("Merge {merged}"),
+        Some(branch) => format!("Merge {merged} into {branch}"),
+        None => format!("Merge {merged} into HEAD"),
+    }
+}
+
+/// Checks out the result of [`merge::merge_trees`] like [`move_to_tree`],
+/// then replaces the index entries of the conflicted paths with their base,
+/// our and their versions, at stages 1 to 3, and resolves them again if
+/// their resolutions were recorded, see [`rerere::rerere`].
+fn checkout_merged(
+    repo: &Repo,
+    merged: &merge::Merged,
+    operation: &str,
+    ref_changes: Vec<journal::RefChange>,
+    command: &str,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    move_to_tree(repo, &merged.tree, operation, ref_changes, command)?;
+    if merged.conflicts.is_empty() {
+        return Ok(());
+    }
+    let mut index = index::Index::read(repo)?;
+    for (path, versions) in &merged.conflicts {
+        index.remove(path);
+        for (stage, version) in (1..).zip(versions) {
+            if let Some((mode, hash)) = version {
+                index.add(index::IndexEntry {
+                    mode: *mode,
+                    hash: hash.clone(),
+                    stage,
+                    path: path.clone(),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+    index.write(repo)?;
+    rerere::rerere(repo, stdout)
+}
+
+/// Records a ref moved by [`move_to_tree`] in its reflog, and in the reflog
+/// of HEAD when it's HEAD's branch.
+fn log_ref_change(repo: &Repo, change: &journal::RefChange, message: &str) -> Result<()> {
+    let config = config::Config::load(repo)?;
+    let committer = ident::Ident::new(&config, ident::Role::Committer)?.to_string();
+    let old = change.old.as_deref().unwrap_or(refs::ZERO_HASH);
+    refs::append_reflog(repo, &change.name, old, &change.new, &committer, message)?;
+    if change.name != "HEAD" {
+        refs::append_reflog(repo, "HEAD", old, &change.new, &committer, message)?;
+    }
+    Ok(())
+}
+
+/// Prints the stat and the summary of the changes from the tree `old` to the
+/// tree `new`, with renames, like after a merge.
+fn write_diffstat(repo: &Repo, old: &str, new: &str, stdout: &mut dyn io::Write) -> Result<()> {
+    let changes = diff::diff_trees(repo, Some(old), Some(new), true)?;
+    let changes = diff::detect_renames(repo, changes)?;
+    let reader = diff::ChangeReader::new(repo)?;
+    let stats = changes
+        .iter()
+        .map(|change| diff::FileStat::compute(&reader, change))
+        .collect::<Result<Vec<_>>>()?;
+    diff::write_stats(&stats, &diff::StatFormat::Stat(Default::default()), stdout)?;
+    diff::write_summary(&changes, stdout)
+}
+
+#[derive(Debug, Default)]
+pub struct CherryPickOptions {
+    /// Apply the changes to the index and the working tree without
+    /// committing them.
+    pub no_commit: bool,
+}
+
+/// How to go on with a cherry-pick or a revert stopped at a commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequencerResume {
+    /// Commit the changes of the commit, with the conflicts resolved and
+    /// staged, and go on with the others.
+    Continue,
+    /// Drop the commit and go on with the others.
+    Skip,
+    /// Stop and go back to where HEAD was before.
+    Abort,
+}
+
+/// Applies the changes of commits to HEAD and commits them with their author
+/// and message, like `git cherry-pick`. Ranges like `A..B` pick their commits
+/// oldest first.
+///
+/// The changes of a commit are merged from its parent, see
+/// [`merge::merge_trees`], and the index must not have other changes. If
+/// they conflict, or the commit becomes empty, the cherry-pick stops there
+/// with a [`merge::State`], and the commits left in a [`sequencer::State`],
+/// so that it can be resumed with [`cherry_pick_resume`].
+pub fn cherry_pick(
+    repo: &Repo,
+    revs: &[String],
+    options: &CherryPickOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let mut commits = vec![];
+    for rev in revs {
+        if rev.contains("..") {
+            let range = walk_revs(repo, &[rev.clone()], false)?;
+            commits.extend(range.into_iter().rev().map(|(hash, _, _)| hash));
+        } else {
+            commits
+                .push(Object::resolve_rev(repo, rev).and_then(|hash| Object::peel(repo, &hash))?);
+        }
+    }
+    start_sequencer(
+        repo,
+        sequencer::Action::Pick,
+        commits,
+        options.no_commit,
+        stdout,
+    )
+}
+
+/// Resumes a cherry-pick stopped at a commit, see [`resume_sequencer`].
+pub fn cherry_pick_resume(
+    repo: &Repo,
+    resume: SequencerResume,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    resume_sequencer(repo, sequencer::Action::Pick, resume, stdout)
+}
+
+#[derive(Debug, Default)]
+pub struct RevertOptions {
+    /// Apply the inverse changes to the index and the working tree without
+    /// committing them.
+    pub no_commit: bool,
+}
+
+/// Applies the inverse of the changes of commits to HEAD and commits them,
+/// like `git revert`, with a message like `Revert "<subject>"` that names
+/// the reverted commit. Ranges like `A..B` revert their commits newest first.
+///
+/// The changes of a commit are merged from the commit to its parent, see
+/// [`merge::merge_trees`], and the index must not have other changes. If
+/// they conflict the revert stops there like [`cherry_pick`], so that it can
+/// be resumed with [`revert_resume`], or concluded by [`commit`].
+pub fn revert(
+    repo: &Repo,
+    revs: &[String],
+    options: &RevertOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let mut commits = vec![];
+    for rev in revs {
+        if rev.contains("..") {
+            let range = walk_revs(repo, &[rev.clone()], false)?;
+            commits.extend(range.into_iter().map(|(hash, _, _)| hash));
+        } else {
+            commits
+                .push(Object::resolve_rev(repo, rev).and_then(|hash| Object::peel(repo, &hash))?);
+        }
+    }
+    start_sequencer(
+        repo,
+        sequencer::Action::Revert,
+        commits,
+        options.no_commit,
+        stdout,
+    )
+}
+
+/// Resumes a revert stopped at a commit, see [`resume_sequencer`].
+pub fn revert_resume(
+    repo: &Repo,
+    resume: SequencerResume,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    resume_sequencer(repo, sequencer::Action::Revert, resume, stdout)
+}
+
+/// Applies commits with an action, saving a [`sequencer::State`] when there
+/// are several of them.
+fn start_sequencer(
+    repo: &Repo,
+    action: sequencer::Action,
+    commits: Vec<String>,
+    no_commit: bool,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let command = action.command();
+    if sequencer::State::read(repo)?.is_some() {
+        return Err(anyhow!(
+            "{command} is already in progress\n\
+             hint: try \"git {command} (--continue | --abort | --quit)\""
+        ));
+    }
+    let head = refs::find_ref(repo, "HEAD").ok();
+    let state = sequencer::State {
+        head: head.clone(),
+        todo: commits.into_iter().map(|hash| (action, hash)).collect(),
+        abort_safety: head,
+        no_commit,
+    };
+    match state.todo.as_slice() {
+        [] => Err(anyhow!("empty commit set passed")),
+        // Like git, a single commit is applied without a sequencer state.
+        [(action, commit)] => apply_commit(repo, *action, commit, no_commit, stdout),
+        _ => {
+            state.write(repo)?;
+            apply_todo(repo, state, stdout)
+        }
+    }
+}
+
+/// Resumes a cherry-pick or a revert stopped at a commit.
+///
+/// Skipping the commit or aborting resets the index, and the working tree
+/// files that differ from it, to HEAD or to where HEAD was before, unless
+/// HEAD moved since the last commit. Other local changes are kept.
+fn resume_sequencer(
+    repo: &Repo,
+    action: sequencer::Action,
+    resume: SequencerResume,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let command = action.command();
+    let sequencer = sequencer::State::read(repo)?;
+    // Like git, skipping needs a commit stopped by the same command, unlike
+    // continuing and aborting.
+    let stopped = merge::State::read(repo)?.filter(|state| match (resume, action) {
+        (SequencerResume::Skip, sequencer::Action::Pick) => state.cherry_pick_head.is_some(),
+        (SequencerResume::Skip, sequencer::Action::Revert) => state.revert_head.is_some(),
+        _ => state.cherry_pick_head.is_some() || state.revert_head.is_some(),
+    });
+    if sequencer.is_none() && stopped.is_none() {
+        return Err(match resume {
+            SequencerResume::Skip => anyhow!("no {command} in progress"),
+            _ => anyhow!("no cherry-pick or revert in progress"),
+        });
+    }
+    if let Some((other, _)) = sequencer.as_ref().and_then(|state| state.todo.first()) {
+        if *other != action && resume != SequencerResume::Abort {
+            return Err(anyhow!("cannot {command} during a {}.", other.command()));
+        }
+    }
+    let head = refs::find_ref(repo, "HEAD").ok();
+    match (resume, stopped) {
+        // Like git, the comments listing the conflicts are dropped from the
+        // message.
+        (SequencerResume::Continue, Some(state)) => {
+            let options = CommitOptions {
+                message: strip_comments(&state.message),
+                ..Default::default()
+            };
+            commit(repo, &options, stdout)?;
+        }
+        // The commit was already made by hand.
+        (SequencerResume::Continue, None) => {}
+        (SequencerResume::Skip, _) => {
+            rerere::clear(repo)?;
+            let tree = match &head {
+                Some(head) => Some(rev_tree(repo, head)?),
+                None => None,
+            };
+            reset_index_changes(repo, tree.as_deref())?;
+            merge::State::remove(repo)?;
+        }
+        (SequencerResume::Abort, _) => {
+            rerere::clear(repo)?;
+            let reflog_message = format!("{command} --abort");
+            let Some(state) = sequencer else {
+                reset_to_orig_head(repo, head.as_deref(), &reflog_message)?;
+                return merge::State::remove(repo);
+            };
+            if head == state.abort_safety {
+                reset_to_orig_head(repo, state.head.as_deref(), &reflog_message)?;
+            } else {
+                writeln!(
+                    stdout,
+                    "warning: You seem to have moved HEAD. Not rewinding, check your HEAD!"
+                )?;
+            }
+            merge::State::remove(repo)?;
+            return sequencer::State::remove(repo);
+        }
+    }
+    let Some(mut state) = sequencer else {
+        return Ok(());
+    };
+    if !state.todo.is_empty() {
+        state.todo.remove(0);
+    }
+    state.abort_safety = refs::find_ref(repo, "HEAD").ok();
+    state.write(repo)?;
+    apply_todo(repo, state, stdout)
+}
+
+/// Applies the commits left of a cherry-pick or a revert, stopping at a
+/// commit that doesn't apply cleanly, and removes the state at the end.
+fn apply_todo(repo: &Repo, mut state: sequencer::State, stdout: &mut dyn io::Write) -> Result<()> {
+    while let Some((action, hash)) = state.todo.first().cloned() {
+        apply_commit(repo, action, &hash, state.no_commit, stdout)?;
+        state.todo.remove(0);
+        state.abort_safety = refs::find_ref(repo, "HEAD").ok();
+        state.write(repo)?;
+    }
+    sequencer::State::remove(repo)
+}
+
+/// Merges the changes of a commit, or their inverse for a revert, into HEAD,
+/// or into the index with `no_commit`, and commits them unless they conflict
+/// or are empty.
+fn apply_commit(
+    repo: &Repo,
+    action: sequencer::Action,
+    hash: &str,
+    no_commit: bool,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let command = action.command();
+    let Object::Commit(applied) = Object::from_hash(repo, hash)? else {
+        return Err(anyhow!("{hash} is not a commit"));
+    };
+    if applied.parents.len() > 1 {
+        return Err(anyhow!(
+            "commit {hash} is a merge but no -m option was given."
+        ));
+    }
+    let mut index = index::Index::read(repo)?;
+    if index.entries.iter().any(|entry| entry.stage != 0) {
+        let doing = match action {
+            sequencer::Action::Pick => "Cherry-picking",
+            sequencer::Action::Revert => "Reverting",
+        };
+        return Err(anyhow!(
+            "{doing} is not possible because you have unmerged files."
+        ));
+    }
+    let head_tree = match refs::find_ref(repo, "HEAD") {
+        Ok(head) => rev_tree(repo, &head)?,
+        Err(_) => object::write_checked_object(repo, ObjectType::Tree, &[])?,
+    };
+    // Without committing, the changes are added to the staged ones.
+    let ours = if no_commit {
+        index.write_tree(repo)?
+    } else if !diff::diff_index(repo, &head_tree, true)?.is_empty() {
+        return Err(anyhow!(
+            "your local changes would be overwritten by {command}.\n\
+             hint: commit your changes or stash them to proceed."
+        ));
+    } else {
+        head_tree.clone()
+    };
+
+    let merged = merge_commit_changes(repo, action, hash, &applied, &ours, command, stdout)?;
+    let abbrev = object::Abbreviator::from_config(repo)?.abbreviate(hash)?;
+    let subject = applied.message.lines().next().unwrap_or("");
+    let mut message = match action {
+        sequencer::Action::Pick => format!("{}\n", applied.message),
+        sequencer::Action::Revert => {
+            format!("Revert \"{subject}\"\n\nThis reverts commit {hash}.\n")
+        }
+    };
+    if !merged.conflicts.is_empty() {
+        message.push_str("\n# Conflicts:\n");
+        for path in merged.conflicts.keys() {
+            message.push_str(&format!("#\t{path}\n"));
+        }
+    }
+    let state = merge::State {
+        merge_heads: vec![],
+        message: message.clone(),
+        no_ff: false,
+        auto_merge: Some(merged.tree.clone()),
+        cherry_pick_head: (action == sequencer::Action::Pick).then(|| hash.to_string()),
+        revert_head: (action == sequencer::Action::Revert).then(|| hash.to_string()),
+        rebase_head: None,
+    };
+    let could_not = match action {
+        sequencer::Action::Pick => format!("could not apply {abbrev}... {subject}"),
+        sequencer::Action::Revert => format!("could not revert {abbrev}... {subject}"),
+    };
+    // Like git, a revert is recorded even without committing, for the message
+    // of the commit.
+    if no_commit {
+        if action == sequencer::Action::Revert {
+            state.write(repo)?;
+        }
+        if merged.conflicts.is_empty() {
+            return Ok(());
+        }
+        return Err(anyhow!(
+            "{could_not}\n\
+             hint: after resolving the conflicts, mark the corrected paths\n\
+             hint: with 'git add <paths>' or 'git rm <paths>'"
+        ));
+    }
+    if !merged.conflicts.is_empty() {
+        state.write(repo)?;
+        return Err(anyhow!(
+            "{could_not}\n\
+             hint: After resolving the conflicts, mark them with\n\
+             hint: \"git add/rm <pathspec>\", then run\n\
+             hint: \"git {command} --continue\".\n\
+             hint: You can instead skip this commit with \"git {command} --skip\".\n\
+             hint: To abort and get back to the state before \"git {command}\",\n\
+             hint: run \"git {command} --abort\"."
+        ));
+    }
+    if merged.tree == head_tree {
+        return Err(match action {
+            sequencer::Action::Pick => {
+                state.write(repo)?;
+                anyhow!(
+                    "The previous cherry-pick is now empty, possibly due to conflict resolution.\n\
+                     If you wish to commit it anyway, use:\n\n    \
+                     git commit --allow-empty\n\n\
+                     Otherwise, please use 'git cherry-pick --skip'"
+                )
+            }
+            sequencer::Action::Revert => anyhow!("nothing to commit, working tree clean"),
+        });
+    }
+    // A cherry-pick keeps the author of the commit.
+    let options = CommitOptions {
+        message,
+        author: match action {
+            sequencer::Action::Pick => ident::Ident::parse(&applied.author),
+            sequencer::Action::Revert => None,
+        },
+        reflog_action: Some(action.command().to_string()),
+        ..Default::default()
+    };
+    commit(repo, &options, stdout)
+}
+
+/// Merges the changes of a commit, or their inverse for a revert, into the
+/// tree `ours`, see [`merge::merge_trees`], and checks out the result like
+/// [`checkout_merged`]. Conflicts are labeled `HEAD` and with the commit.
+fn merge_commit_changes(
+    repo: &Repo,
+    action: sequencer::Action,
+    hash: &str,
+    applied: &Commit,
+    ours: &str,
+    command: &str,
+    stdout: &mut dyn io::Write,
+) -> Result<merge::Merged> {
+    let parent_tree = match applied.parents.first() {
+        Some(parent) => Some(rev_tree(repo, parent)?),
+        None => None,
+    };
+    let abbrev = object::Abbreviator::from_config(repo)?.abbreviate(hash)?;
+    let subject = applied.message.lines().next().unwrap_or("");
+    let parent_label = format!("parent of {abbrev} ({subject})");
+    let commit_label = format!("{abbrev} ({subject})");
+    let empty_tree = || object::write_checked_object(repo, ObjectType::Tree, &[]);
+    let (base, theirs, [base_label, theirs_label]) = match action {
+        sequencer::Action::Pick => (
+            parent_tree,
+            applied.tree.clone(),
+            [parent_label, commit_label],
+        ),
+        sequencer::Action::Revert => (
+            Some(applied.tree.clone()),
+            parent_tree.map_or_else(empty_tree, Ok)?,
+            [commit_label, parent_label],
+        ),
+    };
+    let config = config::Config::load(repo)?;
+    let options = merge_file::MergeOptions {
+        style: merge_file::ConflictStyle::from_config(&config)?,
+        labels: [
+            Some("HEAD".to_string()),
+            Some(base_label),
+            Some(theirs_label),
+        ],
+        ..Default::default()
+    };
+    let merged = merge::merge_trees(repo, base.as_deref(), ours, &theirs, &options)?;
+    for message in &merged.messages {
+        writeln!(stdout, "{message}")?;
+    }
+    checkout_merged(
+        repo,
+        &merged,
+        &format!("{command} {abbrev}"),
+        vec![],
+        command,
+        stdout,
+    )?;
+    Ok(merged)
+}
+
+#[derive(Debug, Default)]
+pub struct RebaseOptions {
+    /// Let the user edit the list of the commits to pick first, see
+    /// [`rebase::parse_todo`].
+    pub interactive: bool,
+}
+
+/// How to go on with a rebase stopped at a commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebaseResume {
+    /// Commit the changes of the commit, with the conflicts resolved and
+    /// staged, or amend the commit stopped at with the staged changes, and
+    /// go on with the other instructions.
+    Continue,
+    /// Drop the commit and go on with the other instructions.
+    Skip,
+    /// Stop and check out the branch as it was before.
+    Abort,
+}
+
+/// The help at the end of the todo list of an interactive rebase.
+const REBASE_TODO_HELP: &str = "\
+#
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\" but keep only the previous
+#                    commit's log message
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+#
+";
+
+/// Replays the commits of HEAD that aren't in `upstream` on top of it, like
+/// `git rebase`, then points HEAD's branch at the last one and checks it out
+/// again.
+///
+/// The commits are picked oldest first on a detached HEAD, like
+/// [`cherry_pick`], with their author and message. Merges, and commits
+/// whose changes are already in `upstream`, see [`patch_id`], are left out,
+/// and commits that become empty are dropped. If a commit conflicts, the
+/// rebase stops there with a [`merge::State`], and the instructions left in
+/// a [`rebase::State`], so that it can be resumed with [`rebase_resume`].
+///
+/// An interactive rebase lets the user edit the list of the commits to pick
+/// first, with the editor of `GIT_SEQUENCE_EDITOR` or `sequence.editor`,
+/// see [`rebase_with_todo`].
+///
+/// Like git, the index and the working tree must not have local changes, and
+/// the commit HEAD was at is saved in `ORIG_HEAD`.
+pub fn rebase(
+    repo: &Repo,
+    upstream: &str,
+    options: &RebaseOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let head = check_rebase_start(repo)?;
+    let onto = rebase_upstream(repo, upstream)?;
+    if !options.interactive && graph::is_ancestor(repo, &onto, &head)? {
+        let branch = match refs::head(repo)? {
+            refs::Head::Branch(name) => refs::shorten(&name).to_string(),
+            refs::Head::Detached(_) => "HEAD".to_string(),
+        };
+        writeln!(stdout, "Current branch {branch} is up to date.")?;
+        return Ok(());
+    }
+
+    let todo: Vec<rebase::Instruction> = walk_revs(repo, &[format!("{onto}...{head}")], true)?
+        .into_iter()
+        .rev()
+        .filter(|(_, commit, left)| !left && commit.parents.len() <= 1)
+        .map(|(hash, _, _)| rebase::Instruction::new(rebase::Command::Pick, &hash))
+        .collect();
+    if !options.interactive {
+        return start_rebase(repo, upstream, &onto, todo, false, stdout);
+    }
+    let todo = edit_rebase_todo(repo, &onto, &head, &todo)?;
+    start_rebase(repo, upstream, &onto, todo, true, stdout)
+}
+
+/// Rebases HEAD onto `upstream` like an interactive [`rebase`], following
+/// the instructions of a todo list instead of letting the user edit it.
+///
+/// `pick` commits a commit with its author and message, `reword` lets the
+/// user edit its message first, and `edit` stops after it so that it can be
+/// amended. `squash` and `fixup` meld a commit into the previous one, with
+/// both messages, which the user edits after the last of them, or with the
+/// previous message only. `drop` leaves a commit out.
+pub fn rebase_with_todo(
+    repo: &Repo,
+    upstream: &str,
+    todo: Vec<rebase::Instruction>,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    check_rebase_start(repo)?;
+    let onto = rebase_upstream(repo, upstream)?;
+    check_rebase_todo(&todo)?;
+    start_rebase(repo, upstream, &onto, todo, true, stdout)
+}
+
+/// Checks that the todo list of a rebase doesn't start by melding a commit
+/// into the previous one.
+fn check_rebase_todo(todo: &[rebase::Instruction]) -> Result<()> {
+    match todo.first().filter(|first| first.command.is_fixup()) {
+        Some(first) => Err(anyhow!(
+            "cannot '{}' without a previous commit",
+            first.command.name()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Checks that no rebase is in progress and that the index and the working
+/// tree have no local changes, returning the commit of HEAD.
+fn check_rebase_start(repo: &Repo) -> Result<String> {
+    if rebase::dir(repo).exists() {
+        return Err(anyhow!(
+            "It seems that there is already a rebase-merge directory, and\n\
+             I wonder if you are in the middle of another rebase.  If that is the\n\
+             case, please try\n\
+             \tgit rebase (--continue | --abort | --skip)\n\
+             If that is not the case, please\n\
+             \trm -fr \"{}\"\n\
+             and run me again.  I am stopping in case you still have something\n\
+             valuable there.",
+            rebase::dir(repo).display()
+        ));
+    }
+    let head =
+        refs::find_ref(repo, "HEAD").map_err(|_| anyhow!("HEAD does not point to a commit"))?;
+    if index::Index::read(repo)?
+        .entries
+        .iter()
+        .any(|entry| entry.stage != 0)
+    {
+        return Err(anyhow!("you need to resolve your current index first"));
+    }
+    if !diff::diff_files(repo)?.is_empty() {
+        return Err(anyhow!(
+            "cannot rebase: You have unstaged changes.\nPlease commit or stash them."
+        ));
+    }
+    if !diff::diff_index(repo, &rev_tree(repo, &head)?, true)?.is_empty() {
+        return Err(anyhow!(
+            "cannot rebase: Your index contains uncommitted changes.\n\
+             Please commit or stash them."
+        ));
+    }
+    Ok(head)
+}
+
+fn rebase_upstream(repo: &Repo, upstream: &str) -> Result<String> {
+    Object::resolve_rev(repo, upstream)
+        .and_then(|hash| Object::peel(repo, &hash))
+        .map_err(|_| anyhow!("invalid upstream '{upstream}'"))
+}
+
+/// Lets the user edit the todo list of an interactive rebase with their
+/// sequence editor, see [`launch_editor`]. The rebase doesn't start if they
+/// remove every instruction.
+fn edit_rebase_todo(
+    repo: &Repo,
+    onto: &str,
+    head: &str,
+    todo: &[rebase::Instruction],
+) -> Result<Vec<rebase::Instruction>> {
+    let abbreviator = object::Abbreviator::from_config(repo)?;
+    let onto_abbrev = abbreviator.abbreviate(onto)?;
+    let mut text = match todo.is_empty() {
+        true => "noop\n".to_string(),
+        false => rebase::format_todo(repo, todo, true)?,
+    };
+    let commands = match todo.len().max(1) {
+        1 => "1 command".to_string(),
+        n => format!("{n} commands"),
+    };
+    text.push_str(&format!(
+        "\n# Rebase {onto_abbrev}..{} onto {onto_abbrev} ({commands})\n{REBASE_TODO_HELP}",
+        abbreviator.abbreviate(head)?
+    ));
+    let path = rebase::dir(repo).join("git-rebase-todo");
+    fs::create_dir_all(rebase::dir(repo))?;
+    fs::write(&path, text)?;
+    let edited = launch_editor(repo, &path, true)
+        .and_then(|_| rebase::parse_todo(repo, &fs::read_to_string(&path)?))
+        .and_then(|todo| check_rebase_todo(&todo).map(|_| todo));
+    match edited {
+        Ok(todo) if !todo.is_empty() => Ok(todo),
+        edited => {
+            rebase::State::remove(repo)?;
+            edited.and(Err(anyhow!("nothing to do")))
+        }
+    }
+}
+
+/// Detaches HEAD at `onto`, and starts following the instructions of a
+/// rebase from there.
+fn start_rebase(
+    repo: &Repo,
+    upstream: &str,
+    onto: &str,
+    todo: Vec<rebase::Instruction>,
+    interactive: bool,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let head = refs::find_ref(repo, "HEAD")?;
+    let head_name = match refs::head(repo)? {
+        refs::Head::Branch(name) => Some(name),
+        refs::Head::Detached(_) => None,
+    };
+    refs::update_ref(repo, "ORIG_HEAD", &head)?;
+    let change = journal::RefChange {
+        name: "HEAD".to_string(),
+        old: refs::read_ref(repo, "HEAD")?,
+        new: onto.to_string(),
+    };
+    move_to_tree(
+        repo,
+        &rev_tree(repo, onto)?,
+        &format!("rebase {upstream}"),
+        vec![change],
+        "rebase",
+    )?;
+    let state = rebase::State {
+        head_name,
+        onto: onto.to_string(),
+        orig_head: head.clone(),
+        interactive,
+        done: vec![],
+        todo,
+        stopped: None,
+        amend: None,
+        fixups: None,
+    };
+    state.write(repo)?;
+    let change = journal::RefChange {
+        name: "HEAD".to_string(),
+        old: Some(head),
+        new: onto.to_string(),
+    };
+    log_ref_change(
+        repo,
+        &change,
+        &format!("rebase (start): checkout {upstream}"),
+    )?;
+    rebase_todo(repo, state, stdout)
+}
+
+/// Resumes a rebase stopped at a commit.
+///
+/// Skipping the commit resets the index, and the working tree files that
+/// differ from it, to HEAD. Aborting resets them to where the branch was
+/// before, and checks it out again.
+pub fn rebase_resume(repo: &Repo, resume: RebaseResume, stdout: &mut dyn io::Write) -> Result<()> {
+    let Some(mut state) = rebase::State::read(repo)? else {
+        return Err(anyhow!("No rebase in progress?"));
+    };
+    let head = refs::find_ref(repo, "HEAD")?;
+    match resume {
+        RebaseResume::Continue => {
+            let mut index = index::Index::read(repo)?;
+            let unmerged: BTreeSet<&str> = index
+                .entries
+                .iter()
+                .filter(|entry| entry.stage != 0)
+                .map(|entry| entry.path.as_str())
+                .collect();
+            if !unmerged.is_empty() {
+                let needs_merge: Vec<String> = unmerged
+                    .iter()
+                    .map(|path| format!("{path}: needs merge"))
+                    .collect();
+                return Err(anyhow!(
+                    "{}\nYou must edit all merge conflicts and then\n\
+                     mark them as resolved using git add",
+                    needs_merge.join("\n")
+                ));
+            }
+            let changed = index.write_tree(repo)? != rev_tree(repo, &head)?;
+            let stopped = state.stopped.take();
+            if state.amend.take().is_some() {
+                // The staged changes amend the commit stopped at.
+                if changed {
+                    let options = CommitOptions {
+                        amend: true,
+                        reflog_action: Some("rebase (continue)".to_string()),
+                        ..Default::default()
+                    };
+                    commit(repo, &options, stdout)?;
+                }
+            } else if let Some(stopped) = stopped {
+                // The commit is dropped if the resolved changes are empty, or
+                // were already committed by hand.
+                let instruction = state
+                    .done
+                    .last()
+                    .cloned()
+                    .unwrap_or(rebase::Instruction::new(rebase::Command::Pick, &stopped));
+                if changed || instruction.command.is_fixup() {
+                    commit_rebase_instruction(
+                        repo,
+                        &mut state,
+                        &instruction,
+                        "rebase (continue)",
+                        false,
+                        stdout,
+                    )?;
+                }
+                merge::State::remove(repo)?;
+            }
+            rebase_todo(repo, state, stdout)
+        }
+        RebaseResume::Skip => {
+            rerere::clear(repo)?;
+            reset_index_changes(repo, Some(&rev_tree(repo, &head)?))?;
+            merge::State::remove(repo)?;
+            state.stopped = None;
+            state.amend = None;
+            rebase_todo(repo, state, stdout)
+        }
+        RebaseResume::Abort => {
+            rerere::clear(repo)?;
+            reset_index_changes(repo, Some(&rev_tree(repo, &state.orig_head)?))?;
+            let head_name = state.head_name.as_deref().unwrap_or("HEAD");
+            match &state.head_name {
+                Some(branch) => refs::update_symbolic_ref(repo, "HEAD", branch)?,
+                None => refs::update_ref(repo, "HEAD", &state.orig_head)?,
+            }
+            let change = journal::RefChange {
+                name: "HEAD".to_string(),
+                old: Some(head),
+                new: state.orig_head,
+            };
+            log_ref_change(
+                repo,
+                &change,
+                &format!("rebase (abort): returning to {head_name}"),
+            )?;
+            merge::State::remove(repo)?;
+            rebase::State::remove(repo)
+        }
+    }
+}
+
+/// Follows the instructions left of a rebase, stopping at a commit that
+/// conflicts or is to be edited, then points the branch being rebased at
+/// HEAD and checks it out again.
+fn rebase_todo(repo: &Repo, mut state: rebase::State, stdout: &mut dyn io::Write) -> Result<()> {
+    while !state.todo.is_empty() {
+        let instruction = state.todo.remove(0);
+        state.done.push(instruction.clone());
+        if !instruction.command.is_fixup() {
+            state.fixups = None;
+        }
+        state.write(repo)?;
+        if instruction.command == rebase::Command::Drop {
+            continue;
+        }
+        rebase_pick(repo, &mut state, &instruction, stdout)?;
+        if instruction.command == rebase::Command::Edit {
+            let Object::Commit(picked) = Object::from_hash(repo, &instruction.commit)? else {
+                return Err(anyhow!("{} is not a commit", instruction.commit));
+            };
+            state.stopped = Some(instruction.commit.clone());
+            state.amend = Some(refs::find_ref(repo, "HEAD")?);
+            state.write(repo)?;
+            writeln!(
+                stdout,
+                "Stopped at {}...  {}\n\
+                 You can amend the commit now, with\n\n  \
+                 git commit --amend \n\n\
+                 Once you are satisfied with your changes, run\n\n  \
+                 git rebase --continue",
+                object::Abbreviator::from_config(repo)?.abbreviate(&instruction.commit)?,
+                picked.message.lines().next().unwrap_or("")
+            )?;
+            return Ok(());
+        }
+    }
+
+    let head = refs::find_ref(repo, "HEAD")?;
+    let Some(branch) = state.head_name else {
+        rebase::State::remove(repo)?;
+        writeln!(stdout, "Successfully rebased and updated detached HEAD.")?;
+        return Ok(());
+    };
+    let branch_change = journal::RefChange {
+        name: branch.clone(),
+        old: refs::read_ref(repo, &branch)?,
+        new: head.clone(),
+    };
+    let changes = vec![
+        branch_change.clone(),
+        journal::RefChange {
+            name: "HEAD".to_string(),
+            old: refs::read_ref(repo, "HEAD")?,
+            new: format!("ref: {branch}"),
+        },
+    ];
+    move_to_tree(
+        repo,
+        &rev_tree(repo, &head)?,
+        "rebase (finish)",
+        changes,
+        "rebase",
+    )?;
+    // The branch and HEAD are logged apart, like git.
+    let config = config::Config::load(repo)?;
+    let committer = ident::Ident::new(&config, ident::Role::Committer)?.to_string();
+    refs::append_reflog(
+        repo,
+        &branch,
+        branch_change.old.as_deref().unwrap_or(refs::ZERO_HASH),
+        &head,
+        &committer,
+        &format!("rebase (finish): {branch} onto {}", state.onto),
+    )?;
+    refs::append_reflog(
+        repo,
+        "HEAD",
+        &head,
+        &head,
+        &committer,
+        &format!("rebase (finish): returning to {branch}"),
+    )?;
+    rebase::State::remove(repo)?;
+    writeln!(stdout, "Successfully rebased and updated {branch}.")?;
+    Ok(())
+}
+
+/// Merges the changes of the commit of an instruction into HEAD like
+/// [`apply_commit`], and commits them, see [`commit_rebase_instruction`],
+/// or stops there if they conflict. Picked commits that become empty are
+/// dropped.
+fn rebase_pick(
+    repo: &Repo,
+    state: &mut rebase::State,
+    instruction: &rebase::Instruction,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let hash = &instruction.commit;
+    let Object::Commit(picked) = Object::from_hash(repo, hash)? else {
+        return Err(anyhow!("{hash} is not a commit"));
+    };
+    let head_tree = rev_tree(repo, &refs::find_ref(repo, "HEAD")?)?;
+    // Like git, what the merge did is only told when it conflicts.
+    let mut output = vec![];
+    let merged = merge_commit_changes(
+        repo,
+        sequencer::Action::Pick,
+        hash,
+        &picked,
+        &head_tree,
+        "rebase",
+        &mut output,
+    )?;
+    if instruction.command.is_fixup() {
+        add_rebase_fixup(repo, state, instruction, &picked)?;
+    }
+    if !merged.conflicts.is_empty() {
+        stdout.write_all(&output)?;
+        let mut message = format!("{}\n", picked.message);
+        message.push_str("\n# Conflicts:\n");
+        for path in merged.conflicts.keys() {
+            message.push_str(&format!("#\t{path}\n"));
+        }
+        let merge_state = merge::State {
+            merge_heads: vec![],
+            message,
+            no_ff: false,
+            auto_merge: Some(merged.tree.clone()),
+            cherry_pick_head: None,
+            revert_head: None,
+            rebase_head: Some(hash.to_string()),
+        };
+        merge_state.write(repo)?;
+        state.stopped = Some(hash.to_string());
+        state.write(repo)?;
+        let abbrev = object::Abbreviator::from_config(repo)?.abbreviate(hash)?;
+        let subject = picked.message.lines().next().unwrap_or("");
+        return Err(anyhow!(
+            "could not apply {abbrev}... {subject}\n\
+             hint: Resolve all conflicts manually, mark them as resolved with\n\
+             hint: \"git add/rm <conflicted_files>\", then run \"git rebase --continue\".\n\
+             hint: You can instead skip this commit: run \"git rebase --skip\".\n\
+             hint: To abort and get back to the state before \"git rebase\", run \
+             \"git rebase --abort\"."
+        ));
+    }
+    // Like git, commits that were empty to begin with are kept.
+    let was_empty = match picked.parents.first() {
+        Some(parent) => rev_tree(repo, parent)? == picked.tree,
+        None => false,
+    };
+    if merged.tree == head_tree && !was_empty && !instruction.command.is_fixup() {
+        return Ok(());
+    }
+    let reflog_action = format!("rebase ({})", instruction.command.name());
+    commit_rebase_instruction(repo, state, instruction, &reflog_action, true, stdout)
+}
+
+/// Adds the message of the commit of a `squash` or a `fixup` to the message
+/// of the commits melded into HEAD, like git: numbered in comments, and
+/// commented out for a fixup.
+fn add_rebase_fixup(
+    repo: &Repo,
+    state: &mut rebase::State,
+    instruction: &rebase::Instruction,
+    picked: &Commit,
+) -> Result<()> {
+    let mut fixups = match state.fixups.take() {
+        Some(fixups) => fixups,
+        None => {
+            let Object::Commit(head) = Object::from_rev(repo, "HEAD")? else {
+                return Err(anyhow!("HEAD is not a commit"));
+            };
+            rebase::Fixups {
+                instructions: vec![],
+                message: format!(
+                    "# This is a combination of 2 commits.\n\
+                     # This is the 1st commit message:\n\n{}\n",
+                    head.message
+                ),
+            }
+        }
+    };
+    fixups.instructions.push(instruction.clone());
+    let number = fixups.instructions.len() + 1;
+    let message = match instruction.command {
+        rebase::Command::Squash => {
+            format!(
+                "\n# This is the commit message #{number}:\n\n{}\n",
+                picked.message
+            )
+        }
+        _ => {
+            let commented: Vec<String> = picked
+                .message
+                .lines()
+                .map(|line| match line {
+                    "" => "#".to_string(),
+                    line => format!("# {line}"),
+                })
+                .collect();
+            format!(
+                "\n# The commit message #{number} will be skipped:\n\n{}\n",
+                commented.join("\n")
+            )
+        }
+    };
+    let rest = fixups.message.split_once('\n').map_or("", |(_, rest)| rest);
+    fixups.message = format!("# This is a combination of {number} commits.\n{rest}{message}");
+    state.fixups = Some(fixups);
+    Ok(())
+}
+
+/// Commits the merged changes of the commit of a rebase instruction, with
+/// its author and message, or the message edited by the user for `reword`.
+/// `squash` and `fixup` amend HEAD instead, and the user edits the message
+/// after the last of them if there were squashes.
+///
+/// Like git, the summary of the commit is only printed when `quiet` is
+/// false or the user edited the message.
+fn commit_rebase_instruction(
+    repo: &Repo,
+    state: &mut rebase::State,
+    instruction: &rebase::Instruction,
+    reflog_action: &str,
+    quiet: bool,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let Object::Commit(picked) = Object::from_hash(repo, &instruction.commit)? else {
+        return Err(anyhow!("{} is not a commit", instruction.commit));
+    };
+    // Empty commits were dropped already, unless they were empty to begin
+    // with.
+    let mut options = CommitOptions {
+        allow_empty: true,
+        author: ident::Ident::parse(&picked.author),
+        reflog_action: Some(reflog_action.to_string()),
+        ..Default::default()
+    };
+    let mut edited = false;
+    match (instruction.command, &state.fixups) {
+        (rebase::Command::Squash | rebase::Command::Fixup, Some(fixups)) => {
+            let last = !state
+                .todo
+                .first()
+                .is_some_and(|next| next.command.is_fixup());
+            let squashed = fixups
+                .instructions
+                .iter()
+                .any(|fixup| fixup.command == rebase::Command::Squash);
+            edited = last && squashed;
+            options.message = match edited {
+                true => edit_message(repo, &fixups.message)?,
+                false => strip_comments(&fixups.message),
+            };
+            options.amend = true;
+            options.author = None;
+            if last {
+                state.fixups = None;
+            }
+        }
+        (rebase::Command::Reword, _) => {
+            options.message = edit_message(repo, &format!("{}\n", picked.message))?;
+            edited = true;
+        }
+        _ => options.message = format!("{}\n", picked.message),
+    }
+    match quiet && !edited {
+        true => commit(repo, &options, &mut io::sink()),
+        false => commit(repo, &options, stdout),
+    }
+}
+
+/// Lets the user edit a commit message with their editor, see
+/// [`launch_editor`], and returns it without comments.
+fn edit_message(repo: &Repo, message: &str) -> Result<String> {
+    let path = repo.git_dir().join("COMMIT_EDITMSG");
+    fs::write(
+        &path,
+        format!(
+            "{message}\n\
+             # Please enter the commit message for your changes. Lines starting\n\
+             # with '#' will be ignored, and an empty message aborts the commit.\n"
+        ),
+    )?;
+    launch_editor(repo, &path, false)?;
+    Ok(strip_comments(&fs::read_to_string(&path)?))
+}
+
+/// Removes the lines of a message starting with `#`.
+fn strip_comments(message: &str) -> String {
+    message
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Runs the user's editor on a file like git: the command of `GIT_EDITOR`,
+/// `core.editor`, `VISUAL` or `EDITOR`, or `vi`, with `GIT_SEQUENCE_EDITOR`
+/// and `sequence.editor` first for the todo list of a rebase. The editor
+/// `:` leaves the file as is.
+fn launch_editor(repo: &Repo, path: &Path, sequence: bool) -> Result<()> {
+    let config = config::Config::load(repo)?;
+    let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    let config = |key: &str| config.get(key).map(str::to_string);
+    let sequence_editor = match sequence {
+        true => env("GIT_SEQUENCE_EDITOR").or_else(|| config("sequence.editor")),
+        false => None,
+    };
+    let editor = sequence_editor
+        .or_else(|| env("GIT_EDITOR"))
+        .or_else(|| config("core.editor"))
+        .or_else(|| env("VISUAL"))
+        .or_else(|| env("EDITOR"))
+        .unwrap_or_else(|| "vi".to_string());
+    if editor == ":" {
+        return Ok(());
+    }
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("There was a problem with the editor '{editor}'."));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct AmOptions {
+    /// When a patch doesn't apply, merge it into HEAD from the blobs it was
+    /// made from, named by its `index` lines.
+    pub three_way: bool,
+}
+
+/// How to go on with an `am` session stopped at a patch that didn't apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmResume {
+    /// Commit the patch, applied by hand and staged, and apply the others.
+    Continue,
+    /// Drop the patch and apply the others.
+    Skip,
+    /// Stop the session and go back to where HEAD was before.
+    Abort,
+}
+
+/// Applies the patches of the mails of an mbox and commits them, like
+/// `git am`, with their author, date and message, see
+/// [`mailinfo::MailInfo::parse`].
+///
+/// Patches are applied to both the index and the working tree, see
+/// [`apply`]. If one doesn't apply, the session stops there and its state is
+/// kept in an [`am::State`], so that it can be resumed with [`am_resume`]
+/// once the patch is applied by hand.
+pub fn am(repo: &Repo, mbox: &str, options: &AmOptions, stdout: &mut dyn io::Write) -> Result<()> {
+    if am::State::read(repo)?.is_some() {
+        return Err(anyhow!(
+            "previous rebase directory {} still exists but mbox given.",
+            am::dir(repo).display()
+        ));
+    }
+    let mails = mailinfo::split_mbox(mbox, false);
+    if mails.is_empty() {
+        return Err(anyhow!("Patch format detection failed."));
+    }
+    let orig_head = refs::find_ref(repo, "HEAD").ok();
+    let state = am::State::start(repo, &mails, options.three_way, orig_head)?;
+    am_apply_mails(repo, state, stdout)
+}
+
+/// Resumes an `am` session stopped at a patch that didn't apply.
+///
+/// Skipping the patch or aborting the session resets the index, and the
+/// working tree files that differ from it, to HEAD or to where HEAD was
+/// before the session. Other local changes are kept.
+pub fn am_resume(repo: &Repo, resume: AmResume, stdout: &mut dyn io::Write) -> Result<()> {
+    let Some(mut state) = am::State::read(repo)? else {
+        return Err(anyhow!(
+            "Resolve operation not in progress, we are not resuming."
+        ));
+    };
+    match resume {
+        AmResume::Continue => {
+            let info = mailinfo::MailInfo::parse(&state.mail(repo)?, false)?;
+            let index = index::Index::read(repo)?;
+            if index.entries.iter().any(|entry| entry.stage != 0) {
+                return Err(anyhow!(
+                    "You still have unmerged paths in your index.\n\
+                     You should 'git add' each file with resolved conflicts to mark them as such."
+                ));
+            }
+            let head_tree = rev_tree(repo, "HEAD")?;
+            if diff::diff_index(repo, &head_tree, true)?.is_empty() {
+                return Err(anyhow!(
+                    "No changes - did you forget to use 'git add'?\n\
+                     If there is nothing left to stage, chances are that something else\n\
+                     already introduced the same changes; you might want to skip this patch."
+                ));
+            }
+            writeln!(stdout, "Applying: {}", info.subject)?;
+            am_commit(repo, &info)?;
+        }
+        AmResume::Skip => {
+            rerere::clear(repo)?;
+            let head_tree = rev_tree(repo, "HEAD")?;
+            reset_index_changes(repo, Some(&head_tree))?;
+        }
+        AmResume::Abort => {
+            rerere::clear(repo)?;
+            // Like git, commits made since the session stopped are kept.
+            if refs::find_ref(repo, "HEAD").ok() == state.abort_safety {
+                reset_to_orig_head(repo, state.orig_head.as_deref(), "am --abort")?;
+            } else {
+                writeln!(
+                    stdout,
+                    "You seem to have moved HEAD since the last 'am' failure.\n\
+                     Not rewinding to ORIG_HEAD"
+                )?;
+            }
+            return am::State::remove(repo);
+        }
+    }
+    state.next += 1;
+    state.abort_safety = refs::find_ref(repo, "HEAD").ok();
+    state.write(repo)?;
+    am_apply_mails(repo, state, stdout)
+}
+
+/// Applies and commits the mails of an `am` session from the next one,
+/// stopping at a patch that doesn't apply, and removes the state at the end.
+fn am_apply_mails(repo: &Repo, mut state: am::State, stdout: &mut dyn io::Write) -> Result<()> {
+    while state.next <= state.last {
+        let info = mailinfo::MailInfo::parse(&state.mail(repo)?, false)?;
+        am::State::write_patch(repo, &info.patch)?;
+        let stopped = |reason: &str| {
+            anyhow!(
+                "{reason}Patch failed at {:04} {}\n\
+                 When you have resolved this problem, run \"git am --continue\".\n\
+                 If you prefer to skip this patch, run \"git am --skip\" instead.\n\
+                 To restore the original branch and stop patching, run \"git am --abort\".",
+                state.next,
+                info.subject
+            )
+        };
+        if info.patch.trim().is_empty() {
+            return Err(stopped("Patch is empty.\n"));
+        }
+        // Like git, the index must not have changes that would be committed
+        // with the patch.
+        if let Ok(head) = refs::find_ref(repo, "HEAD") {
+            let dirty: Vec<String> = diff::diff_index(repo, &rev_tree(repo, &head)?, true)?
+                .into_iter()
+                .map(|change| change.path)
+                .collect();
+            if !dirty.is_empty() {
+                return Err(anyhow!(
+                    "Dirty index: cannot apply patches (dirty: {})",
+                    dirty.join(" ")
+                ));
+            }
+        }
+
+        writeln!(stdout, "Applying: {}", info.subject)?;
+        // Like git, errors are only shown when there is no fallback.
+        let options = ApplyOptions {
+            index: true,
+            quiet: state.three_way,
+            ..Default::default()
+        };
+        if let Err(e) = apply(repo, info.patch.as_bytes(), &options, stdout) {
+            if !state.three_way {
+                return Err(stopped(&format!("error: {e}\n")));
+            }
+            match am_three_way(repo, &info, stdout) {
+                Ok(true) => {}
+                Ok(false) => return Err(stopped("error: Failed to merge in the changes.\n")),
+                Err(e) => return Err(stopped(&format!("error: {e}\n"))),
+            }
+        }
+        am_commit(repo, &info)?;
+        state.next += 1;
+        state.abort_safety = refs::find_ref(repo, "HEAD").ok();
+        state.write(repo)?;
+    }
+    am::State::remove(repo)
+}
+
+/// Merges the patch of a mail into HEAD, like `git am --3way`: its preimage
+/// is made from the blobs of its `index` lines, which must be in the
+/// repository, and its postimage by applying it to them. The other paths
+/// are left alone.
+///
+/// Returns whether the merge is clean, otherwise the conflicts are left in the
+/// index.
+fn am_three_way(
+    repo: &Repo,
+    info: &mailinfo::MailInfo,
+    stdout: &mut dyn io::Write,
+) -> Result<bool> {
+    let patches = apply::parse(info.patch.as_bytes(), 1)?;
+    writeln!(stdout, "Using index info to reconstruct a base tree...")?;
+    let mut base = index::Index::default();
+    let mut theirs: BTreeMap<String, Option<(u32, String)>> = BTreeMap::new();
+    let lacks_blobs = || anyhow!("Repository lacks necessary blobs to fall back on 3-way merge.");
+    for patch in &patches {
+        let old = match &patch.old_path {
+            Some(path) => {
+                let hash = patch
+                    .old_hash
+                    .as_deref()
+                    .and_then(|hash| Object::resolve_rev(repo, hash).ok())
+                    .ok_or_else(lacks_blobs)?;
+                let Ok(Object::Blob(blob)) = Object::from_hash(repo, &hash) else {
+                    return Err(lacks_blobs());
+                };
+                let mode = patch.old_mode.unwrap_or(0o100644);
+                base.add(index::IndexEntry {
+                    mode,
+                    hash: hash.clone(),
+                    path: path.clone(),
+                    ..Default::default()
+                });
+                theirs.insert(path.clone(), Some((mode, hash)));
+                Some((blob.content, mode))
+            }
+            None => None,
+        };
+        let (old_content, old_mode) = old.unwrap_or((vec![], 0o100644));
+        let content = patch.apply(repo, &old_content, None).map_err(|_| {
+            anyhow!(
+                "Did you hand edit your patch?\n\
+                 It does not apply to blobs recorded in its index."
+            )
+        })?;
+        if let (Some(path), false) = (&patch.old_path, patch.copy) {
+            theirs.insert(path.clone(), None);
+        }
+        if let Some(path) = &patch.new_path {
+            let hash = object::write_checked_object(repo, ObjectType::Blob, &content)?;
+            theirs.insert(
+                path.clone(),
+                Some((patch.new_mode.unwrap_or(old_mode), hash)),
+            );
+        }
+    }
+    let mut theirs_index = index::Index::default();
+    for (path, entry) in theirs {
+        if let Some((mode, hash)) = entry {
+            theirs_index.add(index::IndexEntry {
+                mode,
+                hash,
+                path,
+                ..Default::default()
+            });
+        }
+    }
+    let base_tree = base.write_tree(repo)?;
+    let theirs_tree = theirs_index.write_tree(repo)?;
+    let head_tree = rev_tree(repo, "HEAD")?;
+
+    writeln!(stdout, "Falling back to patching base and 3-way merge...")?;
+    let config = config::Config::load(repo)?;
+    let options = merge_file::MergeOptions {
+        style: merge_file::ConflictStyle::from_config(&config)?,
+        labels: [
+            Some("HEAD".to_string()),
+            Some("constructed merge base".to_string()),
+            Some(info.subject.clone()),
+        ],
+        ..Default::default()
+    };
+    let merged = merge::merge_trees(repo, Some(&base_tree), &head_tree, &theirs_tree, &options)?;
+    for message in &merged.messages {
+        writeln!(stdout, "{message}")?;
+    }
+    checkout_merged(repo, &merged, "am --3way", vec![], "merge", stdout)?;
+    Ok(merged.conflicts.is_empty())
+}
+
+/// Commits the index with the author, date and message of a mail, or the
+/// current date if it has none.
+fn am_commit(repo: &Repo, info: &mailinfo::MailInfo) -> Result<()> {
+    let (timestamp, timezone) = match info.date.is_empty() {
+        true => {
+            let config = config::Config::load(repo)?;
+            let now = ident::Ident::new(&config, ident::Role::Author)?;
+            (now.timestamp, now.timezone)
+        }
+        false => ident::parse_date(&info.date)?,
+    };
+    let message = match info.message.is_empty() {
+        true => info.subject.clone(),
+        false => format!("{}\n\n{}", info.subject, info.message),
+    };
+    let options = CommitOptions {
+        message,
+        author: Some(ident::Ident {
+            name: info.author.clone(),
+            email: info.email.clone(),
+            timestamp,
+            timezone,
+        }),
+        ..Default::default()
+    };
+    commit(repo, &options, &mut io::sink())
+}
+
+/// Resets the index, and the files that differ from it, and HEAD to where
+/// HEAD was before an operation, like [`reset_index_changes`]. HEAD's branch
+/// becomes unborn again if it was.
+fn reset_to_orig_head(repo: &Repo, orig_head: Option<&str>, reflog_message: &str) -> Result<()> {
+    let target = match orig_head {
+        Some(orig_head) => Some(rev_tree(repo, orig_head)?),
+        None => None,
+    };
+    reset_index_changes(repo, target.as_deref())?;
+    match (orig_head, refs::find_ref(repo, "HEAD").ok()) {
+        (Some(orig_head), Some(head)) if orig_head != head => {
+            let config = config::Config::load(repo)?;
+            let committer = ident::Ident::new(&config, ident::Role::Committer)?;
+            let mut transaction = refs::Transaction::new();
+            transaction.update("HEAD", orig_head, Some(&head), reflog_message);
+            transaction.commit(repo, Some(&committer.to_string()))?;
+        }
+        (None, Some(_)) => {
+            if let refs::Head::Branch(branch) = refs::head(repo)? {
+                fs::remove_file(refs::ref_path(repo, &branch))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Resets the index to a tree, or empties it, and the files of the working
+/// tree whose entries change or are unmerged. Files whose entries don't
+/// change keep their local changes.
+fn reset_index_changes(repo: &Repo, tree: Option<&str>) -> Result<()> {
+    let mut files = BTreeMap::new();
+    if let Some(tree) = tree {
+        status::tree_files(repo, tree, "", &mut files)?;
+    }
+    let mut index = index::Index::lock(repo)?;
+    let changed: BTreeSet<String> = index
+        .entries
+        .iter()
+        .filter(|entry| {
+            entry.stage != 0 || files.get(&entry.path) != Some(&(entry.mode, entry.hash.clone()))
+        })
+        .map(|entry| entry.path.clone())
+        .chain(
+            files
+                .keys()
+                .filter(|path| {
+                    !index
+                        .entries
+                        .iter()
+                        .any(|entry| entry.path == **path && entry.stage == 0)
+                })
+                .cloned(),
+        )
+        .collect();
+    let filters = filter::Filters::new(repo)?;
+    let modes = index::FileModes::load(repo)?;
+    let big_file_threshold = config::Config::load(repo)?.big_file_threshold()?;
+    for path in changed {
+        index.remove(&path);
+        match files.get(&path) {
+            Some((mode, hash)) => {
+                let entry = index::IndexEntry {
+                    mode: *mode,
+                    hash: hash.clone(),
+                    path,
+                    ..Default::default()
+                };
+                add_index_entry(
+                    &mut index,
+                    checkout_entry(repo, &filters, modes, big_file_threshold, &entry)?,
+                );
+            }
+            None => remove_worktree_file(repo, &path)?,
+        }
+    }
+    index.write()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum StatusFormat {
+    /// For humans, with hints.
+    #[default]
+    Long,
+    /// Stable `XY path` lines for scripts.
+    PorcelainV1,
+    /// Stable lines for scripts, with modes and hashes.
+    PorcelainV2,
+}
+
+#[derive(Debug, Default)]
+pub struct StatusOptions {
+    pub format: StatusFormat,
+    /// Show the branch in porcelain formats.
+    pub branch: bool,
+    /// Terminate entries with NUL instead of newlines in porcelain formats,
+    /// without quoting paths.
+    pub null_terminated: bool,
+    /// Color the paths of the long format, and a detached HEAD.
+    pub colors: Option<color::StatusColors>,
+}
+
+/// Prints the changes staged in the index, the changes in the working tree and
+/// the untracked files.
+///
+/// In the long format, operations in progress, like a merge or a rebase, are
+/// described first. See [`print_porcelain_v1`] and [`print_porcelain_v2`] for
+/// the porcelain formats.
+pub fn status(repo: &Repo, options: &StatusOptions, stdout: &mut dyn io::Write) -> Result<()> {
+    use status::{Change, Operation};
+
+    let status = status::Status::compute(repo)?;
+    match options.format {
+        StatusFormat::Long => {}
+        StatusFormat::PorcelainV1 => return print_porcelain_v1(repo, &status, options, stdout),
+        StatusFormat::PorcelainV2 => return print_porcelain_v2(repo, &status, options, stdout),
+    }
+    let operations = status::operations_in_progress(repo)?;
+    let has_unmerged = status.has_unmerged();
+    let paint = |color: fn(&color::StatusColors) -> &String, text: String| match &options.colors {
+        Some(colors) if !color(colors).is_empty() => {
+            format!("{}{text}{}", color(colors), color::RESET)
+        }
+        _ => text,
+    };
+    let no_branch = |text: &str| paint(|colors| &colors.no_branch, text.to_string());
+
+    let rebase_onto = operations.iter().find_map(|operation| match operation {
+        Operation::Rebase {
+            interactive, onto, ..
+        } => Some((*interactive, onto.as_deref().unwrap_or_default())),
+        _ => None,
+    });
+    match (rebase_onto, refs::head(repo)?) {
+        (Some((true, onto)), _) => writeln!(
+            stdout,
+            "{}{onto}",
+            no_branch("interactive rebase in progress; onto ")
+        )?,
+        (Some((false, onto)), _) => {
+            writeln!(stdout, "{}{onto}", no_branch("rebase in progress; onto "))?
+        }
+        (None, refs::Head::Branch(name)) => writeln!(
+            stdout,
+            "On branch {}",
+            name.strip_prefix("refs/heads/").unwrap_or(&name)
+        )?,
+        (None, refs::Head::Detached(hash)) => writeln!(
+            stdout,
+            "{}{}",
+            no_branch("HEAD detached at "),
+            object::Abbreviator::from_config(repo)?.abbreviate(&hash)?
+        )?,
+    }
+    if status.initial {
+        writeln!(stdout, "\nNo commits yet\n")?;
+    }
+    for operation in &operations {
+        status::print_operation(operation, has_unmerged, stdout)?;
+    }
+
+    let label = |change: Change| match change {
+        Change::Added => "new file:",
+        Change::Modified => "modified:",
+        Change::Deleted => "deleted:",
+        Change::TypeChanged => "typechange:",
+        Change::Renamed => "renamed:",
+    };
+    // Like git, there are no hints to unstage the changes of a merge, a
+    // cherry-pick or a revert.
+    let from_commit = !operations.iter().any(|operation| {
+        matches!(
+            operation,
+            Operation::Merge | Operation::CherryPick { .. } | Operation::Revert { .. }
+        )
+    });
+    if status.has_staged_changes() {
+        writeln!(stdout, "Changes to be committed:")?;
+        if from_commit && status.initial {
+            writeln!(stdout, "  (use \"git rm --cached <file>...\" to unstage)")?;
+        } else if from_commit {
+            writeln!(
+                stdout,
+                "  (use \"git restore --staged <file>...\" to unstage)"
+            )?;
+        }
+        for path in &status.paths {
+            let Some(change) = path.staged else {
+                continue;
+            };
+            let line = match &path.head_path {
+                Some(head_path) => format!("{:<12}{head_path} -> {}", label(change), path.path),
+                None => format!("{:<12}{}", label(change), path.path),
+            };
+            writeln!(stdout, "\t{}", paint(|colors| &colors.added, line))?;
+        }
+        writeln!(stdout)?;
+    }
+
+    if has_unmerged {
+        writeln!(stdout, "Unmerged paths:")?;
+        if status.initial {
+            writeln!(stdout, "  (use \"git rm --cached <file>...\" to unstage)")?;
+        } else if from_commit {
+            writeln!(
+                stdout,
+                "  (use \"git restore --staged <file>...\" to unstage)"
+            )?;
+        }
+        let both_deleted = status.paths.iter().any(|path| path.unmerged == 1);
+        let deleted_modified = status
+            .paths
+            .iter()
+            .any(|path| [3, 5].contains(&path.unmerged));
+        let not_deleted = status
+            .paths
+            .iter()
+            .any(|path| path.unmerged != 0 && path.unmerged != 1);
+        if !both_deleted && !deleted_modified {
+            writeln!(stdout, "  (use \"git add <file>...\" to mark resolution)")?;
+        } else if both_deleted && !deleted_modified && !not_deleted {
+            writeln!(stdout, "  (use \"git rm <file>...\" to mark resolution)")?;
+        } else {
+            writeln!(
+                stdout,
+                "  (use \"git add/rm <file>...\" as appropriate to mark resolution)"
+            )?;
+        }
+        for path in status.paths.iter().filter(|path| path.unmerged != 0) {
+            let label = match path.unmerged {
+                1 => "both deleted:",
+                2 => "added by us:",
+                3 => "deleted by them:",
+                4 => "added by them:",
+                5 => "deleted by us:",
+                6 => "both added:",
+                _ => "both modified:",
+            };
+            let line = format!("{label:<17}{}", format::quote_path(&path.path));
+            writeln!(stdout, "\t{}", paint(|colors| &colors.unmerged, line))?;
+        }
+        writeln!(stdout)?;
+    }
+
+    let unstaged: Vec<_> = status
+        .paths
+        .iter()
+        .filter_map(|path| Some((path.unstaged?, &path.path)))
+        .collect();
+    if !unstaged.is_empty() {
+        writeln!(stdout, "Changes not staged for commit:")?;
+        if unstaged
+            .iter()
+            .any(|(change, _)| *change == Change::Deleted)
+        {
+            writeln!(
+                stdout,
+                "  (use \"git add/rm <file>...\" to update what will be committed)"
+            )?;
+        } else {
+            writeln!(
+                stdout,
+                "  (use \"git add <file>...\" to update what will be committed)"
+            )?;
+        }
+        writeln!(
+            stdout,
+            "  (use \"git restore <file>...\" to discard changes in working directory)"
+        )?;
+        for (change, path) in &unstaged {
+            let line = format!("{:<12}{}", label(*change), format::quote_path(path));
+            writeln!(stdout, "\t{}", paint(|colors| &colors.changed, line))?;
+        }
+        writeln!(stdout)?;
+    }
+
+    if !status.lfs_pointers.is_empty() {
+        writeln!(stdout, "Git LFS pointers not checked out:")?;
+        writeln!(
+            stdout,
+            "  (install Git LFS and use \"git lfs pull\" to check out their content)"
+        )?;
+        for path in &status.lfs_pointers {
+            let line = format::quote_path(path);
+            writeln!(stdout, "\t{}", paint(|colors| &colors.changed, line))?;
+        }
+        writeln!(stdout)?;
+    }
+
+    if !status.untracked.is_empty() {
+        writeln!(stdout, "Untracked files:")?;
+        writeln!(
+            stdout,
+            "  (use \"git add <file>...\" to include in what will be committed)"
+        )?;
+        for path in &status.untracked {
+            let line = format::quote_path(path);
+            writeln!(stdout, "\t{}", paint(|colors| &colors.untracked, line))?;
+        }
+        writeln!(stdout)?;
+    }
+
+    if !status.has_staged_changes() {
+        if !unstaged.is_empty() || has_unmerged {
+            writeln!(
+                stdout,
+                "no changes added to commit (use \"git add\" and/or \"git commit -a\")"
+            )?;
+        } else if !status.untracked.is_empty() {
+            writeln!(
+                stdout,
+                "nothing added to commit but untracked files present (use \"git add\" to track)"
+            )?;
+        } else if status.initial {
+            writeln!(
+                stdout,
+                "nothing to commit (create/copy files and use \"git add\" to track)"
+            )?;
+        } else {
+            writeln!(stdout, "nothing to commit, working tree clean")?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the status like `git status --porcelain`: a `XY path` line per
+/// changed path, where `X` is the change in the index and `Y` the change in the
+/// working tree, `XY orig -> path` for renames and `?? path` for untracked
+/// files. With `branch`, a `## branch` line comes first.
+///
+/// With `null_terminated`, lines end with NUL, paths aren't quoted and
+/// renames are written as `XY path` and `orig`, in that order.
+fn print_porcelain_v1(
+    repo: &Repo,
+    status: &status::Status,
+    options: &StatusOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let end = if options.null_terminated { '\0' } else { '\n' };
+    let quote = |path: &str| {
+        if options.null_terminated {
+            path.to_string()
+        } else {
+            format::quote_path(path)
+        }
+    };
+    if options.branch {
+        match refs::head(repo)? {
+            refs::Head::Branch(name) => {
+                let name = name.strip_prefix("refs/heads/").unwrap_or(&name);
+                if status.initial {
+                    write!(stdout, "## No commits yet on {name}{end}")?;
+                } else {
+                    write!(stdout, "## {name}{end}")?;
+                }
+            }
+            refs::Head::Detached(_) => write!(stdout, "## HEAD (no branch){end}")?,
+        }
+    }
+    for path in &status.paths {
+        let code = match path.unmerged_code() {
+            Some(code) => code.to_string(),
+            None => {
+                let letter = |change: Option<status::Change>| change.map_or(' ', |c| c.code());
+                format!("{}{}", letter(path.staged), letter(path.unstaged))
+            }
+        };
+        match &path.head_path {
+            Some(head_path) if options.null_terminated => {
+                write!(stdout, "{code} {}{end}{}{end}", path.path, head_path)?
+            }
+            Some(head_path) => write!(
+                stdout,
+                "{code} {} -> {}{end}",
+                quote(head_path),
+                quote(&path.path)
+            )?,
+            None => write!(stdout, "{code} {}{end}", quote(&path.path))?,
+        }
+    }
+    for path in &status.untracked {
+        write!(stdout, "?? {}{end}", quote(path))?;
+    }
+    Ok(())
+}
+
+/// Prints the status like `git status --porcelain=v2`, with a line per path:
+///
+/// - `1 XY sub mH mI mW hH hI path` for changed paths, with the modes in HEAD,
+///   the index and the working tree and the hashes in HEAD and the index.
+/// - `2 XY sub mH mI mW hH hI R100 path<tab>orig` for renames.
+/// - `u XY sub m1 m2 m3 mW h1 h2 h3 path` for unmerged paths.
+/// - `? path` for untracked files.
+///
+/// Unchanged is `.` in `XY`. With `branch`, `# branch.oid` and `# branch.head`
+/// headers come first, then `# branch.upstream` and `# branch.ab +ahead
+/// -behind` if the branch has an upstream, the latter only if it exists.
+fn print_porcelain_v2(
+    repo: &Repo,
+    status: &status::Status,
+    options: &StatusOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+    let end = if options.null_terminated { '\0' } else { '\n' };
+    let quote = |path: &str| {
+        if options.null_terminated {
+            path.to_string()
+        } else {
+            format::quote_path(path)
+        }
+    };
+    if options.branch {
+        let oid = status.head.as_deref().unwrap_or("(initial)");
+        write!(stdout, "# branch.oid {oid}{end}")?;
+        match refs::head(repo)? {
+            refs::Head::Branch(name) => {
+                let name = name.strip_prefix("refs/heads/").unwrap_or(&name);
+                write!(stdout, "# branch.head {name}{end}")?;
+                if let (Some(head), Some(upstream)) = (&status.head, upstream_branch(repo)?) {
+                    let short = ["refs/heads/", "refs/remotes/"]
+                        .iter()
+                        .find_map(|prefix| upstream.strip_prefix(prefix))
+                        .unwrap_or(&upstream);
+                    write!(stdout, "# branch.upstream {short}{end}")?;
+                    if let Ok(upstream) = refs::find_ref(repo, &upstream) {
+                        let ahead = count_commits(repo, head, &upstream)?;
+                        let behind = count_commits(repo, &upstream, head)?;
+                        write!(stdout, "# branch.ab +{ahead} -{behind}{end}")?;
+                    }
+                }
+            }
+            refs::Head::Detached(_) => write!(stdout, "# branch.head (detached){end}")?,
+        }
+    }
+
+    let mode = |entry: &Option<(u32, String)>| entry.as_ref().map_or(0, |(mode, _)| *mode);
+    let hash = |entry: &Option<(u32, String)>| {
+        entry
+            .as_ref()
+            .map_or(NULL_HASH.to_string(), |(_, hash)| hash.clone())
+    };
+    for path in &status.paths {
+        let mode_worktree = path.worktree_mode.unwrap_or(0);
+        let is_submodule = [&path.head, &path.index]
+            .into_iter()
+            .chain(&path.stages)
+            .any(|entry| mode(entry) == 0o160000);
+        let submodule = if is_submodule { "S..." } else { "N..." };
+        if let Some(code) = path.unmerged_code() {
+            let [base, ours, theirs] = &path.stages;
+            write!(
+                stdout,
+                "u {code} {submodule} {:06o} {:06o} {:06o} {mode_worktree:06o} {} {} {} {}{end}",
+                mode(base),
+                mode(ours),
+                mode(theirs),
+                hash(base),
+                hash(ours),
+                hash(theirs),
+                quote(&path.path),
+            )?;
+            continue;
+        }
+        let letter = |change: Option<status::Change>| change.map_or('.', |c| c.code());
+        write!(
+            stdout,
+            "{} {}{} {submodule} {:06o} {:06o} {mode_worktree:06o} {} {}",
+            if path.head_path.is_some() { '2' } else { '1' },
+            letter(path.staged),
+            letter(path.unstaged),
+            mode(&path.head),
+            mode(&path.index),
+            hash(&path.head),
+            hash(&path.index),
+        )?;
+        match &path.head_path {
+            Some(head_path) if options.null_terminated => {
+                write!(stdout, " R100 {}{end}{}{end}", path.path, head_path)?
+            }
+            Some(head_path) => write!(
+                stdout,
+                " R100 {}\t{}{end}",
+                quote(&path.path),
+                quote(head_path)
+            )?,
+            None => write!(stdout, " {}{end}", quote(&path.path))?,
+        }
+    }
+    for path in &status.untracked {
+        write!(stdout, "? {}{end}", quote(path))?;
+    }
+    Ok(())
+}
+
+/// Prints the paths in the index, or with `stage`, also their mode, hash and
+/// merge stage.
+pub fn ls_files(repo: &Repo, stage: bool, stdout: &mut dyn io::Write) -> Result<()> {
+    for entry in index::Index::read(repo)?.entries {
+        if stage {
+            writeln!(
+                stdout,
+                "{:o} {} {}\t{}",
+                entry.mode, entry.hash, entry.stage, entry.path
+            )?;
+        } else {
+            writeln!(stdout, "{}", entry.path)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct ArchiveOptions {
+    pub format: archive::Format,
+    /// Prepended to the paths in the archive, like `project-1.0/`.
+    pub prefix: String,
+}
+
+/// Writes an archive of a tree, or of the tree of a commit or tag, like `git
+/// archive`.
+///
+/// The entries get the commit date as their modification time, and the
+/// archive records the commit. The entries of a tree get the current time.
+pub fn archive(
+    repo: &Repo,
+    tree_ish: &str,
+    options: &ArchiveOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let hash = Object::peel(repo, &Object::resolve_rev(repo, tree_ish)?)?;
+    let (tree, commit, mtime) = match Object::from_hash(repo, &hash)? {
+        Object::Commit(commit) => (
+            commit.tree.clone(),
+            Some(hash),
+            commit.committer_timestamp(),
+        ),
+        Object::Tree(_) => {
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+            (hash, None, now.as_secs() as i64)
+        }
+        _ => return Err(anyhow!("not a tree object: {tree_ish}")),
+    };
+    let archive = archive::Archive {
+        repo,
+        tree,
+        commit,
+        mtime,
+        prefix: options.prefix.clone(),
+    };
+    archive.write(options.format, stdout)
+}
+
+#[derive(Debug, Default)]
+pub struct LsTreeOptions {
+    /// Recurse into subtrees.
+    pub recursive: bool,
+    /// Only show trees.
+    pub trees_only: bool,
+    /// Also show the size of blobs.
+    pub long: bool,
+    pub name_only: bool,
+    /// A format string overriding the options above, see [`ls_tree`].
+    pub format: Option<String>,
+}
+
+/// Lists the contents of a tree, or of the tree of a commit.
+///
+/// The format supports the `%(objectmode)`, `%(objecttype)`, `%(objectname)`,
+/// `%(objectsize)`, `%(objectsize:padded)` and `%(path)` placeholders. The
+/// size of trees and submodules is shown as `-`.
+pub fn ls_tree(
+    repo: &Repo,
+    rev: &str,
+    options: &LsTreeOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let mut hash = Object::peel(repo, &Object::resolve_rev(repo, rev)?)?;
+    if let Object::Commit(commit) = Object::from_hash(repo, &hash)? {
+        hash = commit.tree;
+    }
+    let format = match &options.format {
+        Some(format) => format.as_str(),
+        None if options.name_only => "%(path)",
+        None if options.long => {
+            "%(objectmode) %(objecttype) %(objectname) %(objectsize:padded)%x09%(path)"
+        }
+        None => "%(objectmode) %(objecttype) %(objectname)%x09%(path)",
+    };
+    ls_tree_entries(repo, &hash, "", options, format, stdout)
+}
+
+fn ls_tree_entries(
+    repo: &Repo,
+    hash: &str,
+    prefix: &str,
+    options: &LsTreeOptions,
+    format: &str,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let Object::Tree(tree) = Object::from_hash(repo, hash)? else {
+        return Err(anyhow!("Not a tree: {hash}"));
+    };
+    for file in tree.files {
+        let object_type = file.object_type();
+        let path = format!("{prefix}{}", file.name);
+        let is_tree = object_type == ObjectType::Tree;
+        // Like git, -r only shows the trees it recurses into when combined with -d.
+        let show = if is_tree {
+            !options.recursive || options.trees_only
+        } else {
+            !options.trees_only
+        };
+        if show {
+            print_ls_tree_entry(repo, &file, &path, format, stdout)?;
+        }
+        if is_tree && options.recursive {
+            ls_tree_entries(
+                repo,
+                &file.hash,
+                &format!("{path}/"),
+                options,
+                format,
+                stdout,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn print_ls_tree_entry(
+    repo: &Repo,
+    file: &object::File,
+    path: &str,
+    format: &str,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let object_type = file.object_type();
+    let size = || -> Result<String> {
+        if object_type == ObjectType::Blob {
+            Ok(object::read_header(repo, &file.hash)?.1.to_string())
+        } else {
+            Ok("-".to_string())
+        }
+    };
+    let line = format::expand(format, |name| {
+        Ok(Some(match name {
+            "objectmode" => format!("{:0>6}", file.mode),
+            "objecttype" => object_type.to_string(),
+            "objectname" => file.hash.clone(),
+            "objectsize" => size()?,
+            "objectsize:padded" => format!("{:>7}", size()?),
+            "path" => path.to_string(),
+            _ => return Ok(None),
+        }))
+    })?;
+    stdout.write_all(&line)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Prints the commit graph reachable from a rev as JSON, for use by graphical
+/// frontends. See [`graph::to_json`] for the format.
+pub fn log_json_graph(repo: &Repo, object_rev: &str, stdout: &mut dyn io::Write) -> Result<()> {
+    let layers = if object_rev == "HEAD" && refs::unborn_branch(repo)?.is_some() {
+        vec![]
+    } else {
+        graph::commit_graph(repo, &[Object::resolve_rev(repo, object_rev)?])?
+    };
+    writeln!(stdout, "{}", graph::to_json(&layers))?;
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct RevListOptions {
+    /// Also list the trees and blobs of the commits, with their paths.
+    pub objects: bool,
+    /// Start from all refs and HEAD.
+    pub all: bool,
+    pub missing: revwalk::Missing,
+    /// Prefix commits with `<` or `>`, for the left or the right side of a
+    /// symmetric range.
+    pub left_right: bool,
+    /// Omit the commits of a symmetric range with an equivalent commit on
+    /// the other side, see [`patch_id::equivalent_commits`].
+    pub cherry_pick: bool,
+    /// List commits before their parents, see
+    /// [`revwalk::RevWalk::set_topo_order`].
+    pub topo_order: bool,
+}
+
+/// Returns the number of commits reachable from `tip` but not from `hidden`.
+fn count_commits(repo: &Repo, tip: &str, hidden: &str) -> Result<usize> {
+    let mut walk = revwalk::RevWalk::new(repo, Default::default())?;
+    // Like in rev_list, hidden commits are queued first, so they're walked
+    // first among commits with the same date.
+    walk.hide(hidden)?;
+    walk.push(tip)?;
+    walk.try_fold(0, |count, next| next.map(|_| count + 1))
+}
+
+/// Lists the commits reachable from `revs` newest first, excluding those
+/// reachable from the revs prefixed with `^`.
+///
+/// A range `<a>..<b>` stands for `^<a> <b>`, and a symmetric range
+/// `<a>...<b>` for `<a> <b>` excluding their merge bases, where `<a>` is the
+/// left side. A missing side is HEAD.
+///
+/// With [`revwalk::Missing::Print`], the missing objects are listed last,
+/// prefixed with `?`.
+pub fn rev_list(
+    repo: &Repo,
+    revs: &[String],
+    options: &RevListOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let RevSpecs {
+        mut tips,
+        left,
+        hidden,
+    } = RevSpecs::parse(repo, revs)?;
+    if options.all {
+        tips.extend(refs::list(repo)?.into_iter().map(|(_, hash)| hash));
+        tips.extend(refs::find_ref(repo, "HEAD").ok());
+    }
+
+    let mut walk = revwalk::RevWalk::new(repo, options.missing)?;
+    walk.set_topo_order(options.topo_order);
+    // (hash, name) of the annotated tags peeled to find the commits, which
+    // are objects too.
+    let mut tags = vec![];
+    let mut peel = |hash: String, hidden: bool| -> Result<String> {
+        let mut hash = hash;
+        while object::exists(repo, &hash) {
+            let Object::Tag(tag) = Object::from_hash(repo, &hash)? else {
+                break;
+            };
+            if !hidden && !tags.iter().any(|(tag_hash, _)| *tag_hash == hash) {
+                tags.push((hash, tag.tag));
+            }
+            hash = tag.object;
+        }
+        Ok(hash)
+    };
+    for hash in hidden {
+        walk.hide(&peel(hash, true)?)?;
+    }
+    for hash in left {
+        walk.push_left(&peel(hash, false)?)?;
+    }
+    for hash in tips {
+        let hash = peel(hash, false)?;
+        if !object::exists(repo, &hash) || object::read_header(repo, &hash)?.0 == ObjectType::Commit
+        {
+            walk.push(&hash)?;
+        }
+    }
+
+    let mut listed = vec![];
+    while let Some(next) = walk.next() {
+        let (hash, commit) = next?;
+        let left = walk.is_left(&hash);
+        listed.push((hash, commit, left));
+    }
+    let omitted = match options.cherry_pick {
+        true => patch_id::equivalent_commits(repo, &classifier(repo)?, &listed)?,
+        false => HashSet::new(),
+    };
+    let mut commits = vec![];
+    for (hash, commit, left) in listed {
+        if omitted.contains(&hash) {
+            continue;
+        }
+        match (options.left_right, left) {
+            (false, _) => writeln!(stdout, "{hash}")?,
+            (true, true) => writeln!(stdout, "<{hash}")?,
+            (true, false) => writeln!(stdout, ">{hash}")?,
+        }
+        if options.objects {
+            commits.push(commit);
+        }
+    }
+    if options.objects {
+        for (hash, name) in tags {
+            writeln!(stdout, "{hash} {name}")?;
+        }
+    }
+    for commit in &commits {
+        for (hash, path) in walk.objects(commit)? {
+            writeln!(stdout, "{hash} {path}")?;
+        }
+    }
+    for hash in walk.missing_objects() {
+        writeln!(stdout, "?{hash}")?;
+    }
+    Ok(())
+}
+
+/// The commits to walk for a list of revs, see [`rev_list`].
+#[derive(Default)]
+struct RevSpecs {
+    tips: Vec<String>,
+    /// The left sides of symmetric ranges.
+    left: Vec<String>,
+    hidden: Vec<String>,
+}
+
+impl RevSpecs {
+    fn parse(repo: &Repo, revs: &[String]) -> Result<RevSpecs> {
+        let mut specs = RevSpecs::default();
+        let resolve = |rev: &str| match rev {
+            "" => Object::resolve_rev(repo, "HEAD"),
+            rev => Object::resolve_rev(repo, rev),
+        };
+        for rev in revs {
+            if let Some((a, b)) = rev.split_once("...") {
+                let (a, b) = (resolve(a)?, resolve(b)?);
+                let (a_commit, b_commit) = (Object::peel(repo, &a)?, Object::peel(repo, &b)?);
+                specs
+                    .hidden
+                    .extend(graph::merge_bases(repo, &a_commit, &b_commit)?);
+                specs.left.push(a);
+                specs.tips.push(b);
+                continue;
+            }
+            if let Some((a, b)) = rev.split_once("..") {
+                specs.hidden.push(resolve(a)?);
+                specs.tips.push(resolve(b)?);
+                continue;
+            }
+            match rev.strip_prefix('^') {
+                Some(rev) => specs.hidden.push(Object::resolve_rev(repo, rev)?),
+                None => specs.tips.push(Object::resolve_rev(repo, rev)?),
+            }
+        }
+        Ok(specs)
+    }
+}
+
+/// Walks the commits of `revs` like [`rev_list`], returning (hash, commit,
+/// whether it's on the left side) tuples.
+fn walk_revs(
+    repo: &Repo,
+    revs: &[String],
+    cherry_pick: bool,
+) -> Result<Vec<(String, Commit, bool)>> {
+    walk_revs_with(repo, revs, cherry_pick, |_| {})
+}
+
+/// Walks the commits of `revs` like [`walk_revs`], configuring the walk
+/// first, like its order.
+fn walk_revs_with(
+    repo: &Repo,
+    revs: &[String],
+    cherry_pick: bool,
+    configure: impl FnOnce(&mut revwalk::RevWalk),
+) -> Result<Vec<(String, Commit, bool)>> {
+    let specs = RevSpecs::parse(repo, revs)?;
+    let mut walk = revwalk::RevWalk::new(repo, Default::default())?;
+    configure(&mut walk);
+    walk.prefetch(revwalk::DEFAULT_PREFETCH);
+    for hash in specs.hidden {
+        walk.hide(&Object::peel(repo, &hash)?)?;
+    }
+    for hash in specs.left {
+        walk.push_left(&Object::peel(repo, &hash)?)?;
+    }
+    for hash in specs.tips {
+        walk.push(&Object::peel(repo, &hash)?)?;
+    }
+    let mut commits = vec![];
+    while let Some(next) = walk.next() {
+        let (hash, commit) = next?;
+        let left = walk.is_left(&hash);
+        commits.push((hash, commit, left));
+    }
+    if cherry_pick {
+        let omitted = patch_id::equivalent_commits(repo, &classifier(repo)?, &commits)?;
+        commits.retain(|(hash, _, _)| !omitted.contains(hash));
+    }
+    Ok(commits)
+}
+
+/// Returns the binary classifier of a repository's config.
+fn classifier(repo: &Repo) -> Result<binary::BinaryClassifier> {
+    binary::BinaryClassifier::from_config(&config::Config::load(repo)?)
+}
+
+/// Prints a symbolic name for each rev, like `main~4` or `tags/v1.2~1^2`.
+///
+/// Revs that can't be reached from any ref are named `undefined`.
+pub fn name_rev(repo: &Repo, revs: &[String], stdout: &mut dyn io::Write) -> Result<()> {
+    let names = name_commits(&repo.snapshot()?)?;
+    for rev in revs {
+        let hash = Object::resolve_rev(repo, rev)?;
+        let name = names.get(&hash).map_or("undefined".to_string(), |name| {
+            if name.generation == 0 {
+                name.base.clone()
+            } else {
+                format!("{}~{}", name.base, name.generation)
+            }
+        });
+        writeln!(stdout, "{rev} {name}")?;
+    }
+    Ok(())
+}
+
+struct RevName {
+    /// The ref name, possibly followed by a path to a merge parent, e.g. `main~2^2`.
+    base: String,
+    /// Number of first-parent steps from `base`.
+    generation: usize,
+    /// Total number of steps from the ref.
+    distance: usize,
+    from_tag: bool,
+}
+
+impl RevName {
+    /// Follows git's preference: names from tags win, then shorter distances.
+    fn is_better_than(&self, other: &RevName) -> bool {
+        (self.from_tag, other.distance) > (other.from_tag, self.distance)
+    }
+}
+
+/// Finds the best symbolic name for every commit reachable from a ref.
+fn name_commits(snapshot: &snapshot::Snapshot) -> Result<HashMap<String, RevName>> {
+    let mut names: HashMap<String, RevName> = HashMap::new();
+    for (ref_name, hash) in snapshot.list() {
+        let from_tag = ref_name.starts_with("refs/tags/");
+        let base = ref_name
+            .strip_prefix("refs/heads/")
+            .or_else(|| ref_name.strip_prefix("refs/"))
+            .unwrap_or(&ref_name)
+            .to_string();
+        let Ok(hash) = snapshot.peel(&hash) else {
+            continue;
+        };
+
+        let mut pending = vec![(
+            hash,
+            RevName {
+                base,
+                generation: 0,
+                distance: 0,
+                from_tag,
+            },
+        )];
+        while let Some((hash, name)) = pending.pop() {
+            if names
+                .get(&hash)
+                .is_some_and(|existing| !name.is_better_than(existing))
+            {
+                continue;
+            }
+            let Ok(Object::Commit(commit)) = snapshot.object(&hash) else {
+                continue;
+            };
+            for (i, parent) in commit.parents.into_iter().enumerate() {
+                let parent_name = if i == 0 {
+                    RevName {
+                        base: name.base.clone(),
+                        generation: name.generation + 1,
+                        distance: name.distance + 1,
+                        from_tag,
+                    }
+                } else {
+                    let base = if name.generation == 0 {
+                        format!("{}^{}", name.base, i + 1)
+                    } else {
+                        format!("{}~{}^{}", name.base, name.generation, i + 1)
+                    };
+                    RevName {
+                        base,
+                        generation: 0,
+                        distance: name.distance + 1,
+                        from_tag,
+                    }
+                };
+                pending.push((parent, parent_name));
+            }
+            names.insert(hash, name);
+        }
+    }
+    Ok(names)
+}
+
+/// Lists the branches like `git branch`, marking the current one with `*`.
+///
+/// A detached HEAD is listed first, as `(HEAD detached at <commit>)`.
+pub fn branch_list(
+    repo: &Repo,
+    columns: &column::ColumnOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let head = refs::head(repo)?;
+    let mut items = vec![];
+    if let refs::Head::Detached(hash) = &head {
+        items.push(format!(
+            "* (HEAD detached at {})",
+            object::Abbreviator::from_config(repo)?.abbreviate(hash)?
+        ));
+    }
+    for (name, _) in refs::list(repo)? {
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            let marker = if head == refs::Head::Branch(name.clone()) {
+                '*'
+            } else {
+                ' '
+            };
+            items.push(format!("{marker} {branch}"));
+        }
+    }
+    column::print(&items, columns, stdout)
+}
+
+#[derive(Debug, Default)]
+pub struct TagListOptions {
+    /// Only list the tags matching one of these [`wildmatch`] patterns, like
+    /// `v1.*`.
+    pub patterns: Vec<String>,
+    /// Match the patterns ignoring case.
+    pub ignore_case: bool,
+}
+
+/// Lists the tags like `git tag`.
+pub fn tag_list(
+    repo: &Repo,
+    options: &TagListOptions,
+    columns: &column::ColumnOptions,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let flags = wildmatch::Flags {
+        pathname: false,
+        casefold: options.ignore_case,
+    };
+    let items: Vec<String> = refs::list(repo)?
+        .into_iter()
+        .filter_map(|(name, _)| name.strip_prefix("refs/tags/").map(str::to_string))
+        .filter(|tag| {
+            options.patterns.is_empty()
+                || options
+                    .patterns
+                    .iter()
+                    .any(|pattern| wildmatch::wildmatch(pattern.as_bytes(), tag.as_bytes(), flags))
+        })
+        .collect();
+    column::print(&items, columns, stdout)
+}
+
+#[derive(Default)]
+pub struct ShowRefOptions {
+    /// Only show refs under `refs/heads`.
+    pub heads: bool,
+    /// Only show refs under `refs/tags`.
+    pub tags: bool,
+    /// Require the patterns to be exact ref names, and fail if one is missing.
+    pub verify: bool,
+    /// Also show what annotated tags point to, as `<refname>^{}`.
+    pub dereference: bool,
+    pub patterns: Vec<String>,
+}
+
+/// Lists refs in a repository.
+///
+/// A pattern matches a ref if it matches a complete trailing part of the ref
+/// name, e.g. `main` matches both `refs/heads/main` and
+/// `refs/remotes/origin/main`. An error is returned if no ref matches.
+pub fn show_ref(repo: &Repo, options: &ShowRefOptions, stdout: &mut dyn io::Write) -> Result<()> {
+    let mut matches = vec![];
+    if options.verify {
+        if options.patterns.is_empty() {
+            return Err(anyhow!("--verify requires a reference"));
+        }
+        for pattern in &options.patterns {
+            let hash = (pattern == "HEAD" || pattern.starts_with("refs/"))
+                .then(|| refs::find_ref(repo, pattern).ok())
+                .flatten()
+                .ok_or_else(|| anyhow!("'{pattern}' - not a valid ref"))?;
+            matches.push((pattern.clone(), hash));
+        }
+    } else {
+        for (name, hash) in refs::list(repo)? {
+            let in_selected_folder = (!options.heads && !options.tags)
+                || (options.heads && name.starts_with("refs/heads/"))
+                || (options.tags && name.starts_with("refs/tags/"));
+            let matches_pattern = options.patterns.is_empty()
+                || options
+                    .patterns
+                    .iter()
+                    .any(|pattern| name == *pattern || name.ends_with(&format!("/{pattern}")));
+            if in_selected_folder && matches_pattern {
+                matches.push((name, hash));
+            }
+        }
+        if matches.is_empty() {
+            return Err(anyhow!("No matching refs found"));
+        }
+    }
+
+    for (name, hash) in matches {
+        writeln!(stdout, "{hash} {name}")?;
+        if options.dereference {
+            let peeled = Object::peel(repo, &hash)?;
+            if peeled != hash {
+                writeln!(stdout, "{peeled} {name}^{{}}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints statistics about a repository.
+///
+/// Commits and contributors are counted by walking the history reachable from
+/// all refs, while object counts and blob sizes come from the object database.
+/// Both are read from the same [`snapshot::Snapshot`], so they agree even if
+/// another process commits meanwhile.
+pub fn stats(repo: &Repo, stdout: &mut dyn io::Write) -> Result<()> {
+    let snapshot = repo.snapshot()?;
+    let commits = reachable_commits(&snapshot)?;
+
+    let mut contributors = HashSet::new();
+    let mut tree_depths = HashMap::new();
+    let mut max_tree_depth = 0;
+    for (_, commit) in &commits {
+        // Identities are "Name <email> timestamp tz", only keep "Name <email>".
+        let author = match commit.author.split_once('>') {
+            Some((identity, _)) => format!("{identity}>"),
+            None => commit.author.clone(),
+        };
+        contributors.insert(author);
+        max_tree_depth = max_tree_depth.max(tree_depth(repo, &commit.tree, &mut tree_depths)?);
+    }
+
+    let mut object_counts = BTreeMap::new();
+    let mut blob_sizes = vec![];
+    let hashes = snapshot.hashes()?;
+    for hash in &hashes {
+        let (object_type, size) = object::read_header(repo, hash)?;
+        if object_type == object::ObjectType::Blob {
+            blob_sizes.push((size, hash));
+        }
+        *object_counts.entry(object_type.as_str()).or_insert(0) += 1;
+    }
+    blob_sizes.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+
+    writeln!(stdout, "commits: {}", commits.len())?;
+    writeln!(stdout, "contributors: {}", contributors.len())?;
+    writeln!(stdout, "objects:")?;
+    for (object_type, count) in object_counts {
+        writeln!(stdout, "  {object_type}: {count}")?;
+    }
+    writeln!(stdout, "largest blobs:")?;
+    for (size, hash) in blob_sizes.iter().take(5) {
+        writeln!(stdout, "  {hash} {size}")?;
+    }
+    writeln!(stdout, "max tree depth: {max_tree_depth}")?;
+    Ok(())
+}
+
+/// Prints the largest blobs reachable from any ref, together with the paths
+/// they appear at in history.
+pub fn analyze_large_blobs(repo: &Repo, count: usize, stdout: &mut dyn io::Write) -> Result<()> {
+    let mut blob_paths: HashMap<String, Vec<String>> = HashMap::new();
+    let mut visited_trees = HashSet::new();
+    for (_, commit) in reachable_commits(&repo.snapshot()?)? {
+        collect_blob_paths(repo, &commit.tree, "", &mut visited_trees, &mut blob_paths)?;
+    }
+
+    let mut blobs = vec![];
+    for (hash, mut paths) in blob_paths {
+        let (_, size) = object::read_header(repo, &hash)?;
+        paths.sort();
+        paths.dedup();
+        blobs.push((size, hash, paths));
+    }
+    blobs.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    for (size, hash, paths) in blobs.iter().take(count) {
+        writeln!(stdout, "{hash} {size} {}", paths.join(", "))?;
+    }
+    Ok(())
+}
+
+/// Records the path of every blob reachable from a tree.
+///
+/// Trees are only visited once per path prefix, since identical subtrees are
+/// very common across commits.
+fn collect_blob_paths(
+    repo: &Repo,
+    hash: &str,
+    prefix: &str,
+    visited: &mut HashSet<(String, String)>,
+    blob_paths: &mut HashMap<String, Vec<String>>,
+) -> Result<()> {
+    if !visited.insert((hash.to_string(), prefix.to_string())) {
+        return Ok(());
+    }
+    let Object::Tree(tree) = Object::from_hash(repo, hash)? else {
+        return Err(anyhow!("Expected a tree: {hash}"));
+    };
+    for file in tree.files {
+        let path = format!("{prefix}{}", file.name);
+        match file.type_str() {
+            "tree" => {
+                collect_blob_paths(repo, &file.hash, &format!("{path}/"), visited, blob_paths)?
+            }
+            "blob" | "symlink" => blob_paths.entry(file.hash).or_default().push(path),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Returns all commits reachable from HEAD and the refs in a snapshot.
+fn reachable_commits(snapshot: &snapshot::Snapshot) -> Result<Vec<(String, Commit)>> {
+    let mut pending: Vec<String> = snapshot.list().into_iter().map(|(_, hash)| hash).collect();
+    pending.extend(snapshot.find_ref("HEAD").ok());
+
+    let mut seen = HashSet::new();
+    let mut commits = vec![];
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        let Object::Commit(commit) = snapshot.object(&hash)? else {
+            continue;
+        };
+        pending.extend(commit.parents.iter().cloned());
+        commits.push((hash, commit));
+    }
+    Ok(commits)
+}
+
+/// Returns the depth of a tree, where a tree without subtrees has depth 1.
+fn tree_depth(repo: &Repo, hash: &str, cache: &mut HashMap<String, usize>) -> Result<usize> {
+    if let Some(depth) = cache.get(hash) {
+        return Ok(*depth);
+    }
+    let Object::Tree(tree) = Object::from_hash(repo, hash)? else {
+        return Err(anyhow!("Expected a tree: {hash}"));
+    };
+    let mut depth = 1;
+    for file in tree.files.iter().filter(|file| file.type_str() == "tree") {
+        depth = depth.max(1 + tree_depth(repo, &file.hash, cache)?);
+    }
+    cache.insert(hash.to_string(), depth);
+    Ok(depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
     fn test_init_repo() {
         let tmpdir = tempfile::tempdir().unwrap();
         let path = tmpdir.path().to_path_buf();
@@ -137,6 +8377,93 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_init_repo_with_template() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let template = tmpdir.path().join("template");
+        fs::create_dir_all(template.join("hooks")).unwrap();
+        fs::create_dir_all(template.join("info")).unwrap();
+        fs::write(template.join("hooks/pre-commit.sample"), "#!/bin/sh\n").unwrap();
+        fs::write(template.join("info/exclude"), "*.o\n").unwrap();
+        fs::write(template.join("HEAD"), "not copied over HEAD").unwrap();
+
+        let path = tmpdir.path().join("repo");
+        let mut stdout = Vec::new();
+        init_repo_with_template(&Repo::new(&path), "main", Some(&template), &mut stdout).unwrap();
+        assert_eq!(
+            fs::read_to_string(path.join(".git/hooks/pre-commit.sample")).unwrap(),
+            "#!/bin/sh\n"
+        );
+        assert_eq!(
+            fs::read_to_string(path.join(".git/info/exclude")).unwrap(),
+            "*.o\n"
+        );
+        assert_eq!(
+            fs::read_to_string(path.join(".git/HEAD")).unwrap(),
+            "ref: refs/heads/main"
+        );
+        assert!(!path.join(".git/description").exists());
+
+        // A missing template directory is only a warning.
+        let path = tmpdir.path().join("other");
+        let missing = tmpdir.path().join("missing");
+        stdout.clear();
+        init_repo_with_template(&Repo::new(&path), "main", Some(&missing), &mut stdout).unwrap();
+        assert!(String::from_utf8(stdout).unwrap().ends_with(&format!(
+            "warning: templates not found in {}\n",
+            missing.display()
+        )));
+        assert!(path.join(".git/objects").is_dir());
+    }
+
+    #[test]
+    fn test_init_repo_without_template() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().to_path_buf();
+        init_repo_with_template(
+            &Repo::new(&path),
+            "main",
+            Some(std::path::Path::new("")),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert!(!path.join(".git/info/exclude").exists());
+        assert!(path.join(".git/objects").is_dir());
+    }
+
+    #[test]
+    fn test_init_repo_rejects_invalid_branch_name() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().to_path_buf();
+        let err = init_repo(&Repo::new(&path), "bad..branch").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid ref name 'refs/heads/bad..branch': cannot contain '..'"
+        );
+        assert!(!path.join(".git").exists());
+    }
+
+    #[test]
+    fn test_cleanup_message() {
+        assert_eq!(cleanup_message("subject"), "subject\n");
+        assert_eq!(
+            cleanup_message("\n\nsubject  \n\n\n\nbody\t\n\n"),
+            "subject\n\nbody\n"
+        );
+        assert_eq!(cleanup_message(" \n\t\n"), "");
+    }
+
+    #[test]
+    fn test_matches_pathspec() {
+        assert!(matches_pathspec("src/main.c", ""));
+        assert!(matches_pathspec("src/main.c", "src"));
+        assert!(matches_pathspec("src/main.c", "src/"));
+        assert!(!matches_pathspec("src2/main.c", "src"));
+        assert!(matches_pathspec("src/main.c", "*.c"));
+        assert!(matches_pathspec("src/main.c", "src/m?in.[ch]"));
+        assert!(!matches_pathspec("src/main.h", "*.c"));
+    }
+
     #[test]
     fn test_hash_object() {
         let mut stdout = Vec::new();
@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// A file that is written by writing to `<path>.lock` and then renaming it.
+///
+/// This is how git updates refs and the index: readers never see a partially
+/// written file, and creating the lock fails if another process holds it.
+/// The lock is removed if it's dropped without being committed.
+pub struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<fs::File>,
+}
+
+impl LockFile {
+    pub fn acquire(path: &Path) -> Result<LockFile> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|e| anyhow!("Unable to create {}: {e}", lock_path.display()))?;
+        Ok(LockFile {
+            path: path.to_path_buf(),
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    /// Replaces the file with what was written to the lock. If that fails,
+    /// the lock is removed when dropped.
+    pub fn commit(mut self) -> Result<()> {
+        self.file
+            .as_ref()
+            .expect("only taken when committing")
+            .sync_all()?;
+        fs::rename(&self.lock_path, &self.path)?;
+        self.file.take();
+        Ok(())
+    }
+}
+
+impl Write for LockFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file
+            .as_mut()
+            .expect("only taken when committing")
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file
+            .as_mut()
+            .expect("only taken when committing")
+            .flush()
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_file_commit() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("file");
+        fs::write(&path, "old").unwrap();
+
+        let mut lock = LockFile::acquire(&path).unwrap();
+        lock.write_all(b"new").unwrap();
+        assert!(LockFile::acquire(&path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        lock.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!tmpdir.path().join("file.lock").exists());
+    }
+
+    #[test]
+    fn test_lock_file_is_removed_on_drop() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("file");
+
+        let mut lock = LockFile::acquire(&path).unwrap();
+        lock.write_all(b"new").unwrap();
+        drop(lock);
+
+        assert!(!path.exists());
+        assert!(!tmpdir.path().join("file.lock").exists());
+    }
+
+    #[test]
+    fn test_lock_file_is_removed_on_failed_commit() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("dir");
+        fs::create_dir_all(path.join("file")).unwrap();
+
+        let mut lock = LockFile::acquire(&path).unwrap();
+        lock.write_all(b"new").unwrap();
+        assert!(lock.commit().is_err());
+
+        assert!(path.is_dir());
+        assert!(!tmpdir.path().join("dir.lock").exists());
+    }
+}
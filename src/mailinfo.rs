@@ -0,0 +1,511 @@
+use anyhow::{anyhow, Result};
+
+/// The author, subject and message of a patch sent by email, and the patch
+/// itself.
+#[derive(Debug, Default, PartialEq)]
+pub struct MailInfo {
+    pub author: String,
+    pub email: String,
+    pub date: String,
+    pub subject: String,
+    /// The commit message without its subject, with a trailing newline unless
+    /// empty.
+    pub message: String,
+    /// The patch, starting at the `---` separator or at the first diff header.
+    pub patch: String,
+}
+
+impl MailInfo {
+    /// Parses a mail like `git mailinfo`.
+    ///
+    /// At the start of the body, `From:`, `Subject:` and `Date:` lines
+    /// override the headers. With `scissors`, everything above a scissors line
+    /// like `-- >8 --` is dropped, and the in-body headers may follow it.
+    // TODO: decode quoted-printable and base64 bodies.
+    pub fn parse(mail: &str, scissors: bool) -> Result<MailInfo> {
+        // The headers end at the first empty line, which may end with CRLF.
+        let mut header_len = mail.len();
+        let mut body = "";
+        let mut offset = 0;
+        for line in mail.split_inclusive('\n') {
+            if line.trim_end_matches(['\r', '\n']).is_empty() {
+                header_len = offset;
+                body = &mail[offset + line.len()..];
+                break;
+            }
+            offset += line.len();
+        }
+        let headers = &mail[..header_len];
+        let mut info = MailInfo::default();
+        for (name, value) in unfold_headers(headers) {
+            info.set_header(&name, &value);
+        }
+
+        let mut lines = body.split_inclusive('\n');
+        let mut message = vec![];
+        let mut in_body_headers = true;
+        while let Some(line) = lines.next() {
+            let text = line.trim_end_matches(['\r', '\n']);
+            if in_body_headers {
+                if text.trim().is_empty() && message.is_empty() {
+                    continue;
+                }
+                if let Some((name, value)) = text.split_once(':') {
+                    if ["From", "Subject", "Date"].contains(&name) {
+                        info.set_header(name, value.trim());
+                        continue;
+                    }
+                }
+                in_body_headers = false;
+            }
+            if scissors && is_scissors_line(text) {
+                message.clear();
+                in_body_headers = true;
+                continue;
+            }
+            if is_patch_break(text) {
+                info.patch = std::iter::once(line).chain(lines).collect();
+                break;
+            }
+            message.push(text);
+        }
+        while message.last().is_some_and(|line| line.trim().is_empty()) {
+            message.pop();
+        }
+        while message.first().is_some_and(|line| line.trim().is_empty()) {
+            message.remove(0);
+        }
+        info.message = message.iter().flat_map(|line| [line, "\n"]).collect();
+
+        if info.email.is_empty() {
+            return Err(anyhow!("Patch does not have a valid e-mail address"));
+        }
+        Ok(info)
+    }
+
+    /// Sets a header, decoding its RFC 2047 words.
+    fn set_header(&mut self, name: &str, value: &str) {
+        let value = &decode_words(value);
+        match name.to_ascii_lowercase().as_str() {
+            "from" => (self.author, self.email) = parse_from(value),
+            "subject" => self.subject = cleanup_subject(value),
+            "date" => self.date = value.to_string(),
+            _ => {}
+        }
+    }
+}
+
+/// Splits an mbox into mails, at the `From ` lines that start each of them.
+/// Data that doesn't start with a `From ` line is a single mail.
+///
+/// Unless `keep_cr` is set, the `\r` of CRLF line endings is removed, so
+/// that patches sent through mailers that use them still apply.
+pub fn split_mbox(data: &str, keep_cr: bool) -> Vec<String> {
+    let mut mails = vec![];
+    let mut mail = String::new();
+    for line in data.split_inclusive('\n') {
+        let line = match line.strip_suffix("\r\n") {
+            Some(line) if !keep_cr => format!("{line}\n"),
+            _ => line.to_string(),
+        };
+        if is_from_line(&line) {
+            if !mail.trim().is_empty() {
+                mails.push(std::mem::take(&mut mail));
+            }
+            mail.clear();
+            continue;
+        }
+        mail.push_str(&line);
+    }
+    if !mail.trim().is_empty() {
+        mails.push(mail);
+    }
+    mails
+}
+
+/// Returns whether a line starts a mail in an mbox, like git: it's a `From `
+/// line ending with a time and a year, such as
+/// `From 1234 Mon Sep 17 00:00:00 2001`.
+fn is_from_line(line: &str) -> bool {
+    let line = line.trim_end();
+    if line.len() < 20 || !line.starts_with("From ") {
+        return false;
+    }
+    let Some((time, year)) = line.rsplit_once(' ') else {
+        return false;
+    };
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let time: Vec<&str> = time
+        .rsplit(' ')
+        .next()
+        .unwrap_or_default()
+        .split(':')
+        .collect();
+    time.len() == 3
+        && time.iter().all(|part| part.len() == 2 && is_digits(part))
+        && is_digits(year)
+        && year.parse::<u32>().is_ok_and(|year| year > 90)
+}
+
+/// Returns whether a line is a scissors line, like git: it has a scissors mark
+/// (`>8`, `8<`, `>%` or `%<`) in a perforation of dashes at least 8 characters
+/// long, where the perforation takes more than a third of the line.
+pub fn is_scissors_line(line: &str) -> bool {
+    let line = line.as_bytes();
+    let mut scissors = 0;
+    let mut gap = 0;
+    let mut perforation = 0;
+    let mut in_perforation = false;
+    let mut first_nonblank = None;
+    let mut last_nonblank = None;
+    let mut i = 0;
+    while i < line.len() {
+        let c = line[i];
+        if c.is_ascii_whitespace() {
+            if in_perforation {
+                perforation += 1;
+                gap += 1;
+            }
+            i += 1;
+            continue;
+        }
+        last_nonblank = Some(i);
+        first_nonblank.get_or_insert(i);
+        if c == b'-' {
+            in_perforation = true;
+            perforation += 1;
+        } else if [b">8", b"8<", b">%", b"%<"]
+            .iter()
+            .any(|mark| line[i..].starts_with(*mark))
+        {
+            in_perforation = true;
+            perforation += 2;
+            scissors += 2;
+            i += 1;
+        } else {
+            in_perforation = false;
+        }
+        i += 1;
+    }
+    let visible = match (first_nonblank, last_nonblank) {
+        (Some(first), Some(last)) => last - first + 1,
+        _ => 0,
+    };
+    scissors > 0 && 8 <= visible && visible < perforation * 3 && gap * 2 < perforation
+}
+
+/// Fixes the whitespace errors that the patch adds, like
+/// `git apply --whitespace=fix`: trailing whitespace is removed from the added
+/// lines, but not their line endings. Context and removed lines are kept so
+/// the patch still applies.
+pub fn fix_whitespace(patch: &str) -> String {
+    let mut fixed = String::with_capacity(patch.len());
+    let mut in_hunk = false;
+    for line in patch.split_inclusive('\n') {
+        if line.starts_with("@@ ") {
+            in_hunk = true;
+        } else if !line.starts_with([' ', '+', '-', '\\']) {
+            in_hunk = false;
+        }
+        match line.strip_prefix('+') {
+            Some(added) if in_hunk => {
+                // CRLF line endings are kept, for patches applied with --keep-cr.
+                let ending = ["\r\n", "\n"]
+                    .into_iter()
+                    .find(|ending| added.ends_with(ending))
+                    .unwrap_or_default();
+                fixed.push('+');
+                fixed.push_str(added.trim_end());
+                fixed.push_str(ending);
+            }
+            _ => fixed.push_str(line),
+        }
+    }
+    fixed
+}
+
+/// Returns whether a line of the body starts the patch: a diff header or a
+/// `---` separator.
+fn is_patch_break(line: &str) -> bool {
+    if line.starts_with("diff -") || line.starts_with("Index: ") {
+        return true;
+    }
+    let Some(rest) = line.strip_prefix("---") else {
+        return false;
+    };
+    // `--- <filename>` or a line of only `---`.
+    rest.starts_with(' ') && rest[1..].starts_with(|c: char| !c.is_whitespace())
+        || rest.trim().is_empty()
+}
+
+/// Returns the headers with their continuation lines joined.
+fn unfold_headers(headers: &str) -> Vec<(String, String)> {
+    let mut unfolded: Vec<(String, String)> = vec![];
+    for line in headers.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = unfolded.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            unfolded.push((name.to_string(), value.trim().to_string()));
+        }
+    }
+    unfolded
+}
+
+/// Decodes the RFC 2047 words of a header, like `=?UTF-8?q?Caf=C3=A9?=`,
+/// in the `q` or `b` encoding of UTF-8 or ISO-8859-1. Whitespace between
+/// encoded words is dropped, and invalid words are kept as is.
+pub fn decode_words(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let Some(word) = decode_word(&rest[start..]) else {
+            decoded.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            after_word = false;
+            continue;
+        };
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            decoded.push_str(between);
+        }
+        decoded.push_str(&word.0);
+        rest = &rest[start + word.1..];
+        after_word = true;
+    }
+    decoded + rest
+}
+
+/// Decodes the encoded word `s` starts with, returning its text and its
+/// length.
+fn decode_word(s: &str) -> Option<(String, usize)> {
+    let mut parts = s[2..].splitn(3, '?');
+    let (charset, encoding, rest) = (parts.next()?, parts.next()?, parts.next()?);
+    let end = rest.find("?=")?;
+    let text = &rest[..end];
+    if text.contains(char::is_whitespace) {
+        return None;
+    }
+    let bytes = match encoding {
+        "q" | "Q" => {
+            let mut bytes = vec![];
+            let mut chars = text.bytes();
+            while let Some(b) = chars.next() {
+                match b {
+                    b'_' => bytes.push(b' '),
+                    b'=' => {
+                        let hex = [chars.next()?, chars.next()?];
+                        bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+                    }
+                    b => bytes.push(b),
+                }
+            }
+            bytes
+        }
+        "b" | "B" => decode_base64(text)?,
+        _ => return None,
+    };
+    let text = match charset.to_ascii_lowercase().as_str() {
+        "iso-8859-1" | "latin1" => bytes.iter().map(|&b| b as char).collect(),
+        _ => String::from_utf8_lossy(&bytes).into_owned(),
+    };
+    Some((text, s.len() - rest.len() + end + "?=".len()))
+}
+
+/// Decodes base64, with optional padding.
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut bytes = vec![];
+    let mut bits = 0u32;
+    let mut count = 0;
+    for b in text.trim_end_matches('=').bytes() {
+        let value = ALPHABET.iter().position(|&c| c == b)? as u32;
+        bits = bits << 6 | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// Returns the name and the email address of a `From:` header, in the
+/// `Name <email>` or `email (Name)` form. The email is used as the name if
+/// there is none.
+fn parse_from(from: &str) -> (String, String) {
+    let (name, email) = if let Some((name, rest)) = from.split_once('<') {
+        (name, rest.split('>').next().unwrap_or_default())
+    } else if let Some((email, rest)) = from.split_once('(') {
+        (rest.trim_end().trim_end_matches(')'), email)
+    } else {
+        ("", from)
+    };
+    let name = name.trim();
+    // Quoted names may have escaped characters.
+    let name = match name
+        .strip_prefix('"')
+        .and_then(|name| name.strip_suffix('"'))
+    {
+        Some(quoted) => unescape(quoted),
+        None => name.to_string(),
+    };
+    let name = name.trim();
+    let email = email.trim();
+    let name = if name.is_empty() { email } else { name };
+    (name.to_string(), email.to_string())
+}
+
+/// Removes the backslashes escaping characters in a quoted string.
+fn unescape(quoted: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Removes the `Re:` and `[PATCH ...]` prefixes of a subject, and collapses
+/// its whitespace.
+fn cleanup_subject(subject: &str) -> String {
+    let mut subject = subject;
+    loop {
+        subject = subject.trim_start_matches([' ', '\t', ':']);
+        if subject.len() >= 3 && subject[..3].eq_ignore_ascii_case("re:") {
+            subject = &subject[3..];
+        } else if let Some(rest) = subject.strip_prefix('[') {
+            match rest.find(']') {
+                Some(end) => subject = &rest[end + 1..],
+                None => break,
+            }
+        } else {
+            break;
+        }
+    }
+    subject.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIL: &str = "From: \"Jane Doe\" <jane@example.com>
+Date: Tue, 1 Oct 2024 10:00:00 +0200
+Subject: [PATCH v2 1/2] Re: fix the
+ frobnicator
+
+Hi all, this is the discussion.
+
+-- >8 --
+Subject: Fix the frobnicator for real
+
+Body of the message.
+---
+ a | 2 +-
+diff --git a/a b/a
+";
+
+    #[test]
+    fn test_parse() {
+        let info = MailInfo::parse(MAIL, false).unwrap();
+        assert_eq!(info.author, "Jane Doe");
+        assert_eq!(info.email, "jane@example.com");
+        assert_eq!(info.date, "Tue, 1 Oct 2024 10:00:00 +0200");
+        assert_eq!(info.subject, "fix the frobnicator");
+        assert!(info
+            .message
+            .starts_with("Hi all, this is the discussion.\n\n-- >8 --\n"));
+        assert_eq!(info.patch, "---\n a | 2 +-\ndiff --git a/a b/a\n");
+
+        let info = MailInfo::parse(MAIL, true).unwrap();
+        assert_eq!(info.subject, "Fix the frobnicator for real");
+        assert_eq!(info.message, "Body of the message.\n");
+    }
+
+    #[test]
+    fn test_parse_from() {
+        assert_eq!(
+            parse_from("Jane <jane@example.com>"),
+            ("Jane".to_string(), "jane@example.com".to_string())
+        );
+        assert_eq!(
+            parse_from("jane@example.com (Jane Doe)"),
+            ("Jane Doe".to_string(), "jane@example.com".to_string())
+        );
+        assert_eq!(
+            parse_from("jane@example.com"),
+            (
+                "jane@example.com".to_string(),
+                "jane@example.com".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_decode_words() {
+        assert_eq!(
+            decode_words("=?UTF-8?q?Caf=C3=A9:=20na=C3=AFve?=\n =?UTF-8?q?_test?= done"),
+            "Caf\u{e9}: na\u{ef}ve test done"
+        );
+        assert_eq!(decode_words("=?utf-8?B?SsO2cmc=?="), "J\u{f6}rg");
+        assert_eq!(
+            decode_words("=?ISO-8859-1?Q?J=F6rg?= <j@b>"),
+            "J\u{f6}rg <j@b>"
+        );
+        assert_eq!(decode_words("a =? b =?x?z?y?= c"), "a =? b =?x?z?y?= c");
+        assert_eq!(
+            parse_from("\"Dr. \\\"Q\\\" Who\" <a@b>"),
+            ("Dr. \"Q\" Who".to_string(), "a@b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_is_scissors_line() {
+        assert!(is_scissors_line("-- >8 --"));
+        assert!(is_scissors_line("------------ 8< ------------"));
+        assert!(is_scissors_line("-- >8 -- cut here -- >8 --"));
+        assert!(!is_scissors_line("-- >8"));
+        assert!(!is_scissors_line("--------"));
+        assert!(!is_scissors_line(
+            "-- >8 -- this is not really a scissors line"
+        ));
+    }
+
+    #[test]
+    fn test_split_mbox() {
+        let mbox = "From 1 Mon Sep 17 00:00:00 2001\r\nSubject: a\r\n\r\nFrom me\r\n\r\n\
+                    From 2 Mon Sep 17 00:00:00 2001\nSubject: b\n\nbody\n";
+        assert_eq!(
+            split_mbox(mbox, false),
+            ["Subject: a\n\nFrom me\n\n", "Subject: b\n\nbody\n"]
+        );
+        assert_eq!(
+            split_mbox(mbox, true)[0],
+            "Subject: a\r\n\r\nFrom me\r\n\r\n"
+        );
+        assert_eq!(
+            split_mbox("Subject: a\n\nbody\n", false),
+            ["Subject: a\n\nbody\n"]
+        );
+    }
+
+    #[test]
+    fn test_fix_whitespace() {
+        let patch = "--- a/a \n+++ b/a\n@@ -1,2 +1,2 @@\n context \n-old \n+new \t\n+ \n";
+        assert_eq!(
+            fix_whitespace(patch),
+            "--- a/a \n+++ b/a\n@@ -1,2 +1,2 @@\n context \n-old \n+new\n+\n"
+        );
+        assert_eq!(
+            fix_whitespace("@@ -1 +1 @@\n+crlf \r\n"),
+            "@@ -1 +1 @@\n+crlf\r\n"
+        );
+    }
+}
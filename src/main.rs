@@ -1,9 +1,9 @@
 use anyhow::{anyhow, Result};
-use good_git::{hash_object, repo::Repo};
-use std::{fs, path::Path, path::PathBuf};
+use good_git::repo::Repo;
+use std::{path::Path, path::PathBuf};
 
-use clap::{Args, Parser, Subcommand};
-use std::io;
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand};
+use std::io::{self, IsTerminal, Read};
 
 #[derive(Parser)]
 #[command(version)]
@@ -24,81 +24,2853 @@ enum Commands {
     /// Prints contents of an object.
     CatFile(CatFileArgs),
 
+    /// Print the entries of a pack index read from stdin.
+    ShowIndex,
+
+    /// Display the lines of stdin in columns.
+    Column(ColumnArgs),
+
     /// Show a log of the history.
     Log(LogArgs),
+
+    /// Ensures that a reference name is well formed.
+    CheckRefFormat(CheckRefFormatArgs),
+
+    /// Show statistics about the repository.
+    Stats,
+
+    /// Analyze the history of the repository.
+    Analyze(AnalyzeArgs),
+
+    /// List references in the repository.
+    ShowRef(ShowRefArgs),
+
+    /// List branches.
+    Branch(BranchArgs),
+
+    /// List tags.
+    Tag(TagArgs),
+
+    /// Find symbolic names for revs.
+    NameRev(NameRevArgs),
+
+    /// List commits in reverse chronological order.
+    RevList(RevListArgs),
+
+    /// Find common ancestors of commits.
+    MergeBase(MergeBaseArgs),
+
+    /// Switch to another commit.
+    Checkout(CheckoutArgs),
+
+    /// Switch to another branch.
+    Switch(SwitchArgs),
+
+    /// Manage linked worktrees.
+    Worktree(WorktreeArgs),
+
+    /// Show the files in the index.
+    LsFiles(LsFilesArgs),
+
+    /// List the contents of a tree.
+    LsTree(LsTreeArgs),
+
+    /// Record the index as a new commit.
+    Commit(CommitArgs),
+
+    /// Add files to the index.
+    Add(AddArgs),
+
+    /// Print lines matching a pattern.
+    Grep(GrepArgs),
+
+    /// Show the commit that last changed each line of a file.
+    Blame(BlameArgs),
+
+    /// Show changes between commits, the index and the working tree.
+    Diff(DiffArgs),
+
+    /// Show changes in a tool comparing directories.
+    Difftool(DifftoolArgs),
+
+    /// Compare two trees, or a commit with its parent.
+    DiffTree(DiffTreeArgs),
+
+    /// Compare a tree with the working tree or the index.
+    DiffIndex(DiffIndexArgs),
+
+    /// Compare the index with the working tree.
+    DiffFiles(DiffFilesArgs),
+
+    /// Remove files from the working tree and the index.
+    Rm(RmArgs),
+
+    /// Move or rename files and directories.
+    Mv(MvArgs),
+
+    /// Remove untracked files from the working tree.
+    Clean(CleanArgs),
+
+    /// Show which paths are ignored, and by which pattern.
+    CheckIgnore(CheckIgnoreArgs),
+
+    /// Show the gitattributes of paths.
+    CheckAttr(CheckAttrArgs),
+
+    /// Show the working tree status.
+    Status(StatusArgs),
+
+    /// Register file contents or raw entries in the index.
+    UpdateIndex(UpdateIndexArgs),
+
+    /// Create a tree object from the index.
+    WriteTree(WriteTreeArgs),
+
+    /// Read trees into the index, optionally merging them.
+    ReadTree(ReadTreeArgs),
+
+    /// Verify the objects of the repository and report missing and dangling
+    /// objects.
+    Fsck(FsckArgs),
+
+    /// Update remote refs along with the objects they need.
+    Push(PushArgs),
+
+    /// Download objects and refs from a remote.
+    Fetch(FetchArgs),
+
+    /// Extract the commit message, authorship and patch from a mail read from
+    /// stdin.
+    Mailinfo(MailinfoArgs),
+
+    /// Apply patches to the working tree or the index.
+    Apply(ApplyArgs),
+
+    /// Write commits as patches to send by email.
+    FormatPatch(FormatPatchArgs),
+
+    /// Apply the patches of a mailbox and commit them.
+    Am(AmArgs),
+
+    /// Compute the IDs of the patches read from stdin, which ignore
+    /// whitespace and line numbers.
+    PatchId(PatchIdArgs),
+
+    /// Find the commits not applied upstream yet.
+    Cherry(CherryArgs),
+
+    /// Compare two versions of a series of commits.
+    RangeDiff(RangeDiffArgs),
+
+    /// Merge the changes from a base file to another file into the current
+    /// file.
+    MergeFile(MergeFileArgs),
+
+    /// Join the history of a commit into the current branch.
+    Merge(MergeArgs),
+
+    /// Apply the changes of commits to the current branch.
+    CherryPick(CherryPickArgs),
+
+    /// Undo the changes of commits with new commits.
+    Revert(RevertArgs),
+
+    /// Replay the commits of the current branch on top of another commit.
+    Rebase(RebaseArgs),
+
+    /// Create an archive of the files of a tree.
+    Archive(ArchiveArgs),
+
+    /// Finish or undo an operation that was interrupted by a crash.
+    Recover(RecoverArgs),
+}
+
+#[derive(Args)]
+struct InitArgs {
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    #[arg(default_value = "master")]
+    branch: String,
+
+    /// Copy the files of this directory into the git directory.
+    #[arg(long)]
+    template: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct HashObjectArgs {
+    /// Write the object into the object database.
+    #[arg(short)]
+    write: bool,
+
+    /// The type of the object: blob, tree, commit or tag.
+    #[arg(short = 't', value_name = "type")]
+    object_type: Option<good_git::object::ObjectType>,
+
+    /// Hash the content even if it isn't a well formed object of its type.
+    #[arg(long)]
+    literally: bool,
+
+    /// Read the object from stdin instead of from a file.
+    #[arg(long)]
+    stdin: bool,
+
+    #[arg(required_unless_present("stdin"))]
+    file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct CatFileArgs {
+    /// Show the object type instead of the content.
+    #[arg(short = 't', conflicts_with = "size")]
+    object_type: bool,
+
+    /// Show the object size instead of the content.
+    #[arg(short)]
+    size: bool,
+
+    /// Show the content of a `<rev>:<path>` blob converted by the textconv
+    /// driver of the path.
+    #[arg(long, conflicts_with_all = ["object_type", "size"])]
+    textconv: bool,
+
+    /// Show the content of a `<rev>:<path>` blob converted by the smudge
+    /// filter of the path, as it would be checked out.
+    #[arg(long, conflicts_with_all = ["object_type", "size", "textconv"])]
+    filters: bool,
+
+    object: String,
+}
+
+#[derive(Args)]
+struct LogArgs {
+    /// Print the commit graph as JSON, split into topological layers.
+    #[arg(long)]
+    json_graph: bool,
+
+    /// Mark commits of a symmetric range with `<` or `>` for their side.
+    #[arg(long)]
+    left_right: bool,
+
+    /// Omit commits of a symmetric range with an equivalent change on the
+    /// other side.
+    #[arg(long)]
+    cherry_pick: bool,
+
+    /// Show commits before their parents, without intermixing lines of
+    /// history.
+    #[arg(long)]
+    topo_order: bool,
+
+    /// Only follow the first parent of merges.
+    #[arg(long)]
+    first_parent: bool,
+
+    /// Show at most N commits.
+    #[arg(short = 'n', long, value_name = "N")]
+    max_count: Option<usize>,
+
+    /// Show commits more recent than a date, like `2005-04-07` or
+    /// `2 weeks ago`.
+    #[arg(long, visible_alias = "after", value_name = "DATE")]
+    since: Option<String>,
+
+    /// Show commits older than a date.
+    #[arg(long, visible_alias = "before", value_name = "DATE")]
+    until: Option<String>,
+
+    /// Show commits whose author matches a regex, or one of several.
+    #[arg(long, value_name = "PATTERN")]
+    author: Vec<String>,
+
+    /// Show commits whose committer matches a regex, or one of several.
+    #[arg(long, value_name = "PATTERN")]
+    committer: Vec<String>,
+
+    /// Show commits whose message matches a regex, or one of several.
+    #[arg(long, value_name = "PATTERN")]
+    grep: Vec<String>,
+
+    /// Show one line per commit, with its abbreviated hash, subject and
+    /// committer.
+    #[arg(long, conflicts_with_all = ["pretty", "format"])]
+    oneline: bool,
+
+    /// Show commits in a format: oneline, short, medium, full, fuller, raw,
+    /// `format:<placeholders>` or `tformat:<placeholders>`.
+    #[arg(long, value_name = "FORMAT", conflicts_with = "format")]
+    pretty: Option<String>,
+
+    /// Show commits in a format, like `--pretty`, where placeholders alone,
+    /// like `%h %s (%an)`, are each followed by a newline.
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<String>,
+
+    /// Show abbreviated commit hashes, the default with `--oneline`.
+    #[arg(long, overrides_with = "no_abbrev_commit")]
+    abbrev_commit: bool,
+
+    /// Show full commit hashes.
+    #[arg(long, overrides_with = "abbrev_commit")]
+    no_abbrev_commit: bool,
+
+    /// Pad or truncate subjects to N columns, so that committers line up.
+    #[arg(long, value_name = "N")]
+    subject_width: Option<usize>,
+
+    /// Truncate committers to N columns.
+    #[arg(long, value_name = "N")]
+    committer_width: Option<usize>,
+
+    /// Show the refs pointing at commits.
+    #[arg(long)]
+    decorate: bool,
+
+    #[command(flatten)]
+    color: ColorFlags,
+
+    /// A commit, or a symmetric range `<a>...<b>`.
+    #[arg(default_value = "HEAD")]
+    object: String,
+
+    /// Only show the commits changing these paths.
+    #[arg(last = true)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct AnalyzeArgs {
+    /// Show the N largest blobs in history and the paths referencing them.
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    large_blobs: usize,
+}
+
+#[derive(Args)]
+struct BranchArgs {
+    #[command(flatten)]
+    columns: ColumnFlags,
+}
+
+#[derive(Args)]
+struct TagArgs {
+    /// List the tags, only those matching a pattern if any is given.
+    #[arg(short, long)]
+    list: bool,
+
+    /// Match the patterns ignoring case.
+    #[arg(short, long)]
+    ignore_case: bool,
+
+    /// Patterns like `v1.*` that the listed tags must match.
+    #[arg(requires = "list")]
+    patterns: Vec<String>,
+
+    #[command(flatten)]
+    columns: ColumnFlags,
+}
+
+#[derive(Args)]
+struct ColumnFlags {
+    /// Lay out the list in columns, with options like those of
+    /// `column.ui`, e.g. `row,dense`.
+    #[arg(
+        long,
+        value_name = "OPTIONS",
+        num_args = 0..=1,
+        require_equals = true,
+        overrides_with = "no_column"
+    )]
+    column: Option<Option<String>>,
+
+    #[arg(long, overrides_with = "column")]
+    no_column: bool,
+}
+
+impl ColumnFlags {
+    /// Returns how to print the list of a command, from `column.ui`,
+    /// `column.<command>` and the flags.
+    fn options(
+        &self,
+        config: &good_git::config::Config,
+        command: &str,
+        padding: usize,
+    ) -> Result<good_git::column::ColumnOptions> {
+        let mut mode = good_git::column::Mode::from_config(config, command)?;
+        if self.no_column {
+            mode.enable = good_git::column::Enable::Never;
+        } else if let Some(options) = &self.column {
+            column_flag(&mut mode, options.as_deref())?;
+        }
+        Ok(good_git::column::ColumnOptions {
+            mode: mode.resolve_auto(io::stdout().is_terminal()),
+            width: good_git::column::terminal_width(),
+            padding,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Args)]
+struct ColumnArgs {
+    /// Also read the layout from `column.<NAME>`, after `column.ui`.
+    #[arg(long, value_name = "NAME")]
+    command: Option<String>,
+
+    /// The layout, with options like those of `column.ui`.
+    #[arg(long, value_name = "OPTIONS")]
+    mode: Option<String>,
+
+    /// The width of the output, instead of the width of the terminal.
+    #[arg(long)]
+    width: Option<usize>,
+
+    /// Printed before each line.
+    #[arg(long, default_value = "")]
+    indent: String,
+
+    /// Printed after each line.
+    #[arg(long, default_value = "\n")]
+    nl: String,
+
+    /// The number of spaces between columns.
+    #[arg(long, default_value_t = 1)]
+    padding: usize,
+}
+
+#[derive(Args)]
+struct ShowRefArgs {
+    /// Only show branches.
+    #[arg(long)]
+    heads: bool,
+
+    /// Only show tags.
+    #[arg(long)]
+    tags: bool,
+
+    /// Only show refs exactly matching the given patterns.
+    #[arg(long)]
+    verify: bool,
+
+    /// Also show the objects annotated tags point to.
+    #[arg(short, long)]
+    dereference: bool,
+
+    patterns: Vec<String>,
+}
+
+#[derive(Args)]
+struct NameRevArgs {
+    #[arg(required = true)]
+    revs: Vec<String>,
+}
+
+#[derive(Args)]
+struct RevListArgs {
+    /// Also list the trees and blobs of the commits.
+    #[arg(long)]
+    objects: bool,
+
+    /// Start from all refs and HEAD.
+    #[arg(long)]
+    all: bool,
+
+    /// What to do with missing objects: `error`, `allow-any`, or `print` to
+    /// list them prefixed with `?`.
+    #[arg(long, default_value = "error")]
+    missing: good_git::revwalk::Missing,
+
+    /// Mark commits of symmetric ranges with `<` or `>` for their side.
+    #[arg(long)]
+    left_right: bool,
+
+    /// Omit commits of symmetric ranges with an equivalent change on the
+    /// other side.
+    #[arg(long)]
+    cherry_pick: bool,
+
+    /// List commits before their parents, without intermixing lines of
+    /// history.
+    #[arg(long)]
+    topo_order: bool,
+
+    /// Commits to start from, to exclude with a `^` prefix, or symmetric
+    /// ranges `<a>...<b>`.
+    revs: Vec<String>,
+}
+
+#[derive(Args)]
+struct MergeBaseArgs {
+    /// Exit with 0 if the first commit is an ancestor of the second, 1
+    /// otherwise.
+    #[arg(long, required = true)]
+    is_ancestor: bool,
+
+    commit: String,
+
+    other: String,
+}
+
+#[derive(Args)]
+struct CheckoutArgs {
+    /// Detach HEAD at the commit instead of checking out a branch.
+    #[arg(long)]
+    detach: bool,
+
+    /// Create a branch with no history, keeping the index and working tree.
+    #[arg(long, value_name = "BRANCH", conflicts_with_all = ["detach", "rev"])]
+    orphan: Option<String>,
+
+    rev: Option<String>,
+
+    /// Restore these paths from the commit, or from the index without one,
+    /// instead of switching.
+    #[arg(last = true, conflicts_with_all = ["detach", "orphan"])]
+    pathspecs: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct SwitchArgs {
+    /// Create a branch with no history, clearing the index and the tracked
+    /// files.
+    #[arg(long, value_name = "BRANCH", conflicts_with = "branch")]
+    orphan: Option<String>,
+
+    branch: Option<String>,
+}
+
+#[derive(Args)]
+struct WorktreeArgs {
+    #[command(subcommand)]
+    command: WorktreeCommands,
+}
+
+#[derive(Subcommand)]
+enum WorktreeCommands {
+    /// Create a worktree and check out a commit in it.
+    Add(WorktreeAddArgs),
+
+    /// List the worktrees.
+    List(WorktreeListArgs),
+
+    /// Prevent a worktree from being removed.
+    Lock(WorktreeLockArgs),
+
+    /// Unlock a worktree.
+    Unlock(WorktreeUnlockArgs),
+
+    /// Delete a worktree.
+    Remove(WorktreeRemoveArgs),
+}
+
+#[derive(Args)]
+struct WorktreeAddArgs {
+    /// Create a new branch at the commit and check it out.
+    #[arg(short = 'b', value_name = "NEW_BRANCH")]
+    new_branch: Option<String>,
+
+    /// Detach HEAD at the commit, even if it's a branch.
+    #[arg(long, conflicts_with = "new_branch")]
+    detach: bool,
+
+    /// Lock the worktree after creating it.
+    #[arg(long)]
+    lock: bool,
+
+    path: PathBuf,
+
+    commit_ish: Option<String>,
+}
+
+#[derive(Args)]
+struct WorktreeListArgs {
+    /// Print the worktrees in a format for scripts.
+    #[arg(long)]
+    porcelain: bool,
+}
+
+#[derive(Args)]
+struct WorktreeLockArgs {
+    /// Why the worktree is locked.
+    #[arg(long)]
+    reason: Option<String>,
+
+    worktree: String,
+}
+
+#[derive(Args)]
+struct WorktreeUnlockArgs {
+    worktree: String,
+}
+
+#[derive(Args)]
+struct WorktreeRemoveArgs {
+    /// Remove worktrees with local changes, and locked worktrees when given
+    /// twice.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    force: u8,
+
+    worktree: String,
+}
+
+#[derive(Args)]
+struct LsFilesArgs {
+    /// Show the mode, hash and stage of each file.
+    #[arg(short, long)]
+    stage: bool,
+}
+
+#[derive(Args)]
+struct LsTreeArgs {
+    /// Recurse into subtrees.
+    #[arg(short)]
+    recursive: bool,
+
+    /// Only show trees.
+    #[arg(short = 'd')]
+    trees_only: bool,
+
+    /// Show the size of blobs.
+    #[arg(short, long)]
+    long: bool,
+
+    /// Only show the paths.
+    #[arg(long, conflicts_with = "long")]
+    name_only: bool,
+
+    /// Format entries with placeholders like %(objectname) and %(path).
+    #[arg(long, conflicts_with_all = ["long", "name_only"])]
+    format: Option<String>,
+
+    tree: String,
+}
+
+#[derive(Args)]
+struct CommitArgs {
+    /// The commit message. Several messages are joined as paragraphs.
+    #[arg(short, long, required_unless_present_any = ["amend", "no_edit"])]
+    message: Vec<String>,
+
+    /// Take the message of the merge being concluded as is.
+    #[arg(long)]
+    no_edit: bool,
+
+    /// Replace the HEAD commit, keeping its message unless one is given.
+    #[arg(long)]
+    amend: bool,
+
+    /// Allow a commit that doesn't change anything.
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// Allow a commit with an empty message.
+    #[arg(long)]
+    allow_empty_message: bool,
+}
+
+#[derive(Args)]
+struct AddArgs {
+    /// Stage all changes, including new and removed files.
+    #[arg(short = 'A', long, conflicts_with = "update")]
+    all: bool,
+
+    /// Only stage changes to tracked files.
+    #[arg(short, long)]
+    update: bool,
+
+    /// Print the added and removed files.
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Choose the hunks of the changes to tracked files to stage.
+    #[arg(short, long, conflicts_with_all = ["all", "update"])]
+    patch: bool,
+
+    /// Allow adding ignored files.
+    #[arg(short, long)]
+    force: bool,
+
+    pathspecs: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct RawFormatArgs {
+    /// Show only the names of the changed files.
+    #[arg(long, conflicts_with = "name_status")]
+    name_only: bool,
+
+    /// Show only the names and the status letters of the changed files.
+    #[arg(long)]
+    name_status: bool,
+
+    /// Show the changes as patches.
+    #[arg(short = 'p', short_alias = 'u', long)]
+    patch: bool,
+
+    /// Show patches with this many unchanged lines around changes.
+    #[arg(short = 'U', long, value_name = "N")]
+    unified: Option<usize>,
+
+    /// Show patches, with binary patches for binary files, which apply can
+    /// apply.
+    #[arg(long)]
+    binary: bool,
+
+    /// Treat all files as text.
+    #[arg(short = 'a', long)]
+    text: bool,
+
+    /// Show patches with changed words instead of lines, marked like
+    /// [-this-]{+that+} with plain, colored with color, or on lines of their
+    /// own with porcelain.
+    #[arg(
+        long,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "plain"
+    )]
+    word_diff: Option<String>,
+
+    /// Show word diffs where words are the matches of REGEX.
+    #[arg(long, value_name = "REGEX")]
+    word_diff_regex: Option<String>,
+
+    /// Show the paths of patches without the a/ and b/ prefixes.
+    #[arg(long, conflicts_with_all = ["src_prefix", "dst_prefix"])]
+    no_prefix: bool,
+
+    /// Show the old paths of patches with PREFIX instead of a/.
+    #[arg(long, value_name = "PREFIX")]
+    src_prefix: Option<String>,
+
+    /// Show the new paths of patches with PREFIX instead of b/.
+    #[arg(long, value_name = "PREFIX")]
+    dst_prefix: Option<String>,
+
+    /// Only show the patches of the changes in PATH, or in the current
+    /// directory, with paths relative to it.
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    relative: Option<String>,
+
+    /// Show colored word diffs, where words are the matches of REGEX if any.
+    #[arg(
+        long,
+        value_name = "REGEX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    color_words: Option<String>,
+
+    /// Show the number of changed lines of each file with a histogram, in
+    /// lines of at most WIDTH columns, with paths of at most NAME-WIDTH.
+    #[arg(
+        long,
+        value_name = "WIDTH[,NAME-WIDTH]",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        conflicts_with_all = ["numstat", "shortstat"]
+    )]
+    stat: Option<String>,
+
+    /// Like --stat, with the maximum width of the paths.
+    #[arg(long, value_name = "WIDTH")]
+    stat_name_width: Option<usize>,
+
+    /// Like --stat, with the maximum width of the histograms.
+    #[arg(long, value_name = "WIDTH")]
+    stat_graph_width: Option<usize>,
+
+    /// Show the numbers of added and deleted lines of each file.
+    #[arg(long, conflicts_with = "shortstat")]
+    numstat: bool,
+
+    /// Show only the total numbers of changed files and lines.
+    #[arg(long)]
+    shortstat: bool,
+
+    #[command(flatten)]
+    color: ColorFlags,
+}
+
+impl RawFormatArgs {
+    fn format(&self) -> good_git::diff::RawFormat {
+        if self.name_only {
+            good_git::diff::RawFormat::NameOnly
+        } else if self.name_status {
+            good_git::diff::RawFormat::NameStatus
+        } else {
+            good_git::diff::RawFormat::Raw
+        }
+    }
+
+    /// Returns how to show patches, if they are requested.
+    ///
+    /// They are colored with `--color`, or as set by `color.diff` or
+    /// `color.ui` for porcelain commands.
+    fn patch(&self, repo: &Repo, porcelain: bool) -> Result<Option<good_git::diff::PatchOptions>> {
+        if !self.patch && self.unified.is_none() && !self.binary {
+            return Ok(None);
+        }
+        self.patch_options(repo, porcelain).map(Some)
+    }
+
+    /// Returns the summary to show, if one is requested, colored like
+    /// [`RawFormatArgs::patch`].
+    fn stat(&self, repo: &Repo, porcelain: bool) -> Result<Option<good_git::diff::StatFormat>> {
+        if self.numstat {
+            return Ok(Some(good_git::diff::StatFormat::Numstat));
+        } else if self.shortstat {
+            return Ok(Some(good_git::diff::StatFormat::Shortstat));
+        } else if self.stat.is_none()
+            && self.stat_name_width.is_none()
+            && self.stat_graph_width.is_none()
+        {
+            return Ok(None);
+        }
+        let config = good_git::config::Config::load(repo)?;
+        let mut options = good_git::diff::StatOptions {
+            width: good_git::column::terminal_width(),
+            name_width: self.stat_name_width,
+            graph_width: match self.stat_graph_width {
+                Some(width) => Some(width),
+                None => config
+                    .get_int("diff.statGraphWidth")?
+                    .map(usize::try_from)
+                    .transpose()?,
+            },
+            colors: self.colors(&config, porcelain)?.map(Box::new),
+        };
+        let widths = self.stat.as_deref().unwrap_or_default();
+        let mut widths = widths.split(',').filter(|width| !width.is_empty());
+        if let Some(width) = widths.next() {
+            options.width = width.parse()?;
+        }
+        if let Some(name_width) = widths.next() {
+            options.name_width = Some(name_width.parse()?);
+        }
+        Ok(Some(good_git::diff::StatFormat::Stat(options)))
+    }
+
+    fn patch_options(&self, repo: &Repo, porcelain: bool) -> Result<good_git::diff::PatchOptions> {
+        let config = good_git::config::Config::load(repo)?;
+        let mut options = good_git::diff::PatchOptions::from_config(&config)?;
+        options.colors = self.colors(&config, porcelain)?;
+        if let Some(context) = self.unified {
+            options.context = context;
+        }
+        options.binary = self.binary;
+        options.text = self.text;
+        options.word_diff = match (&self.color_words, &self.word_diff) {
+            (Some(_), _) => Some(good_git::word_diff::WordDiffMode::Color),
+            (None, Some(mode)) => good_git::word_diff::WordDiffMode::parse(mode)?,
+            (None, None) if self.word_diff_regex.is_some() => {
+                Some(good_git::word_diff::WordDiffMode::Plain)
+            }
+            (None, None) => None,
+        };
+        options.word_regex = match &self.color_words {
+            Some(regex) if !regex.is_empty() => Some(regex.clone()),
+            _ => self.word_diff_regex.clone(),
+        };
+        if self.no_prefix {
+            options.paths.src_prefix.clear();
+            options.paths.dst_prefix.clear();
+        }
+        if let Some(prefix) = &self.src_prefix {
+            options.paths.src_prefix = prefix.clone();
+        }
+        if let Some(prefix) = &self.dst_prefix {
+            options.paths.dst_prefix = prefix.clone();
+        }
+        options.paths.relative = match self.relative.as_deref() {
+            Some("") => Some(repo.relative_path(&std::env::current_dir()?)?),
+            relative => relative.map(str::to_string),
+        };
+        Ok(options)
+    }
+
+    /// Returns the colors of patches and summaries, if they are colored.
+    fn colors(
+        &self,
+        config: &good_git::config::Config,
+        porcelain: bool,
+    ) -> Result<Option<good_git::color::DiffColors>> {
+        // Like git, --color-words colors everything.
+        let when = if self.color_words.is_some() {
+            good_git::color::When::Always
+        } else if porcelain {
+            self.color.when(config, "diff")?
+        } else {
+            self.color.flag()?.unwrap_or(good_git::color::When::Never)
+        };
+        match when.enabled(io::stdout().is_terminal()) {
+            true => good_git::color::DiffColors::from_config(config).map(Some),
+            false => Ok(None),
+        }
+    }
+}
+
+/// `--color` and `--no-color`.
+#[derive(Args)]
+struct ColorFlags {
+    /// Color the output: always, never, or auto, only on terminals, the
+    /// default without WHEN.
+    #[arg(
+        long,
+        value_name = "WHEN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "always",
+        overrides_with = "no_color"
+    )]
+    color: Option<String>,
+
+    #[arg(long, overrides_with = "color")]
+    no_color: bool,
+}
+
+impl ColorFlags {
+    /// Returns when to color the output as set by the flags, if they are
+    /// given.
+    fn flag(&self) -> Result<Option<good_git::color::When>> {
+        if self.no_color {
+            return Ok(Some(good_git::color::When::Never));
+        }
+        self.color
+            .as_deref()
+            .map(good_git::color::When::parse)
+            .transpose()
+    }
+
+    /// Returns when to color the output of a command, from the flags, or
+    /// else from `color.<command>` and `color.ui`.
+    fn when(
+        &self,
+        config: &good_git::config::Config,
+        command: &str,
+    ) -> Result<good_git::color::When> {
+        match self.flag()? {
+            Some(when) => Ok(when),
+            None => good_git::color::When::from_config(config, command),
+        }
+    }
+}
+
+#[derive(Args)]
+struct DiffTreeArgs {
+    /// Compare the files of directories instead of their trees.
+    #[arg(short)]
+    recursive: bool,
+
+    /// Compare root commits with the empty tree.
+    #[arg(long)]
+    root: bool,
+
+    /// Read commits, or pairs of trees, from stdin, one per line.
+    #[arg(long, conflicts_with = "revs")]
+    stdin: bool,
+
+    #[command(flatten)]
+    format: RawFormatArgs,
+
+    /// A commit, or two trees.
+    #[arg(required_unless_present = "stdin", num_args = 1..=2)]
+    revs: Vec<String>,
+
+    #[arg(last = true)]
+    pathspecs: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct DiffIndexArgs {
+    /// Compare with the index instead of the working tree.
+    #[arg(long)]
+    cached: bool,
+
+    #[command(flatten)]
+    format: RawFormatArgs,
+
+    tree: String,
+
+    pathspecs: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct DiffFilesArgs {
+    #[command(flatten)]
+    format: RawFormatArgs,
+
+    pathspecs: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    /// Compare with the index instead of the working tree.
+    #[arg(long, visible_alias = "staged")]
+    cached: bool,
+
+    /// Show raw changes instead of patches.
+    #[arg(long)]
+    raw: bool,
+
+    /// Show the full hashes of raw changes.
+    #[arg(long)]
+    no_abbrev: bool,
+
+    #[command(flatten)]
+    format: RawFormatArgs,
+
+    /// Up to two revisions, or a range, then paths. Without `--`, the
+    /// arguments from the first one that isn't a revision are paths.
+    args: Vec<String>,
+
+    /// The paths, when revisions are given before `--`.
+    #[arg(last = true)]
+    pathspecs: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct DifftoolArgs {
+    /// Compare with the index instead of the working tree.
+    #[arg(long, visible_alias = "staged")]
+    cached: bool,
+
+    /// The tool to run instead of `diff.tool`.
+    #[arg(short, long)]
+    tool: Option<String>,
+
+    /// A command to run with the two directories as arguments.
+    #[arg(short = 'x', long)]
+    extcmd: Option<String>,
+
+    #[arg(num_args = 0..=2)]
+    commits: Vec<String>,
+}
+
+#[derive(Args)]
+struct GrepArgs {
+    /// Search the index instead of the working tree.
+    #[arg(long, conflicts_with = "tree")]
+    cached: bool,
+
+    #[arg(short, long)]
+    ignore_case: bool,
+
+    /// Select non-matching lines.
+    #[arg(short = 'v', long)]
+    invert_match: bool,
+
+    /// Match the pattern as a fixed string.
+    #[arg(short = 'F', long)]
+    fixed_strings: bool,
+
+    /// Prefix lines with their line number.
+    #[arg(short = 'n', long)]
+    line_number: bool,
+
+    /// Only print the names of matching files.
+    #[arg(short = 'l', long, visible_alias = "name-only")]
+    files_with_matches: bool,
+
+    /// Print the number of matching lines per file.
+    #[arg(short, long)]
+    count: bool,
+
+    /// Search the output of textconv drivers instead of the file contents.
+    #[arg(long)]
+    textconv: bool,
+
+    pattern: String,
+
+    /// A tree or commit to search instead of the working tree.
+    tree: Option<String>,
+
+    #[arg(last = true)]
+    pathspecs: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct BlameArgs {
+    /// Only blame the lines of a range, like `10,20`, `10,+5` or `10,-5`.
+    #[arg(short = 'L', value_name = "RANGE")]
+    ranges: Vec<String>,
+
+    /// Ignore whitespace when comparing lines.
+    #[arg(short = 'w')]
+    ignore_whitespace: bool,
+
+    /// Show the blamed lines in a format for other programs.
+    #[arg(long, group = "format")]
+    porcelain: bool,
+
+    /// Like `--porcelain`, with the details of the commit of every line.
+    #[arg(long, group = "format")]
+    line_porcelain: bool,
+
+    /// An optional revision, then the file, from the working tree if no
+    /// revision is given.
+    #[arg(required = true, num_args = 1..=2)]
+    args: Vec<String>,
+
+    /// The file, when a revision is given before `--`.
+    #[arg(last = true)]
+    path: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct RmArgs {
+    /// Only remove the files from the index.
+    #[arg(long)]
+    cached: bool,
+
+    /// Remove directories recursively.
+    #[arg(short)]
+    recursive: bool,
+
+    /// Remove files even if they have changes.
+    #[arg(short, long)]
+    force: bool,
+
+    #[arg(short, long)]
+    quiet: bool,
+
+    #[arg(required = true)]
+    pathspecs: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct CleanArgs {
+    /// Only show what would be removed.
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Remove the files even if clean.requireForce is set.
+    #[arg(short, long)]
+    force: bool,
+
+    /// Also remove untracked directories.
+    #[arg(short)]
+    directories: bool,
+
+    /// Also remove ignored files.
+    #[arg(short = 'x', conflicts_with = "only_ignored")]
+    ignored: bool,
+
+    /// Only remove ignored files.
+    #[arg(short = 'X')]
+    only_ignored: bool,
+}
+
+#[derive(Args)]
+struct CheckIgnoreArgs {
+    /// Show the pattern that matches each path.
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Also show the paths that don't match any pattern.
+    #[arg(short, long, requires = "verbose")]
+    non_matching: bool,
+
+    /// Don't skip the paths that are in the index.
+    #[arg(long)]
+    no_index: bool,
+
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct CheckAttrArgs {
+    /// Show all the attributes that are set, unset or have a value.
+    #[arg(short, long)]
+    all: bool,
+
+    /// The attributes to show, then the paths. Without `--`, the first
+    /// argument is the attribute, unless `--all` is given.
+    args: Vec<String>,
+
+    /// The paths, when attributes are given before `--`.
+    #[arg(last = true)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct UpdateIndexArgs {
+    /// Add the files that aren't in the index yet.
+    #[arg(long)]
+    add: bool,
+
+    /// Remove the files that are in the index but not in the working tree.
+    #[arg(long)]
+    remove: bool,
+
+    /// Refresh the stat data of unchanged files and list the changed ones.
+    #[arg(long)]
+    refresh: bool,
+
+    /// Add an entry directly, without a file in the working tree.
+    #[arg(long, value_name = "MODE>,<HASH>,<PATH")]
+    cacheinfo: Vec<String>,
+
+    /// Read entries to add from stdin, in the format of ls-tree or
+    /// ls-files --stage.
+    #[arg(long)]
+    index_info: bool,
+
+    /// Mark the paths as unchanged, so their files aren't checked for
+    /// changes.
+    #[arg(long, overrides_with = "no_assume_unchanged")]
+    assume_unchanged: bool,
+
+    /// Clear the assume-unchanged mark of the paths.
+    #[arg(long, overrides_with = "assume_unchanged")]
+    no_assume_unchanged: bool,
+
+    /// Mark the paths as outside of the sparse checkout, so their files are
+    /// neither checked for changes nor checked out.
+    #[arg(long, overrides_with = "no_skip_worktree")]
+    skip_worktree: bool,
+
+    /// Clear the skip-worktree mark of the paths.
+    #[arg(long, overrides_with = "skip_worktree")]
+    no_skip_worktree: bool,
+
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct WriteTreeArgs {
+    /// Allow objects missing from the repository.
+    #[arg(long)]
+    missing_ok: bool,
+}
+
+#[derive(Args)]
+struct FsckArgs {
+    /// Only check that reachable objects exist, without validating their
+    /// content.
+    #[arg(long)]
+    connectivity_only: bool,
+    /// Print all unreachable objects, not only the dangling ones.
+    #[arg(long)]
+    unreachable: bool,
+}
+
+#[derive(Args)]
+struct PushArgs {
+    /// The remote to push to, as a name or a URL.
+    remote: Option<String>,
+    /// The refs to push, as `[+]<src>[:<dst>]`.
+    refspecs: Vec<String>,
+    /// Update remote refs even if it's not a fast-forward.
+    #[arg(short, long)]
+    force: bool,
+    /// Make the remote branches the upstream of the pushed branches.
+    #[arg(short = 'u', long)]
+    set_upstream: bool,
+    /// Show what would be pushed, without pushing it.
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct FetchArgs {
+    /// The remote to fetch from, as a name or a URL.
+    remote: Option<String>,
+    /// The refs to fetch, as `[+]<src>[:<dst>]`.
+    refspecs: Vec<String>,
+    /// Limit the history to this many commits from the fetched commits.
+    #[arg(long)]
+    depth: Option<usize>,
+    /// Fetch this many more commits below the shallow commits.
+    #[arg(long)]
+    deepen: Option<usize>,
+    /// Limit the history to the commits made after this date.
+    #[arg(long)]
+    shallow_since: Option<String>,
+    /// Limit the history to the commits that can't be reached from this
+    /// remote ref.
+    #[arg(long)]
+    shallow_exclude: Vec<String>,
+    /// Fetch the whole history of a shallow repository.
+    #[arg(long)]
+    unshallow: bool,
+}
+
+#[derive(Args)]
+struct ReadTreeArgs {
+    /// Merge the trees with the index: one tree to read it, two to move from
+    /// the first to the second, three to merge the last two given their
+    /// merge base.
+    #[arg(short)]
+    merge: bool,
+
+    /// Update the working tree with the result.
+    #[arg(short)]
+    update: bool,
+
+    /// Read the tree under this directory, keeping the current entries.
+    #[arg(long)]
+    prefix: Option<String>,
+
+    #[arg(required = true, num_args = 1..=3)]
+    trees: Vec<String>,
+}
+
+#[derive(Args)]
+struct StatusArgs {
+    /// Give the output in a stable format for scripts.
+    #[arg(
+        long,
+        value_name = "VERSION",
+        num_args = 0..=1,
+        default_missing_value = "v1",
+        value_parser = ["v1", "v2"]
+    )]
+    porcelain: Option<String>,
+
+    /// Show the branch, in porcelain formats.
+    #[arg(short, long)]
+    branch: bool,
+
+    /// Terminate entries with NUL.
+    #[arg(short = 'z')]
+    null_terminated: bool,
+}
+
+#[derive(Args)]
+struct MvArgs {
+    /// Overwrite existing files.
+    #[arg(short, long)]
+    force: bool,
+
+    /// Skip moves that would fail.
+    #[arg(short = 'k')]
+    skip_errors: bool,
+
+    /// Only show what would be moved.
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// The sources, followed by the destination.
+    #[arg(required = true, num_args = 2..)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct MailinfoArgs {
+    /// Drop the message above a scissors line.
+    #[arg(long)]
+    scissors: bool,
+
+    /// Keep the CR of CRLF line endings.
+    #[arg(long)]
+    keep_cr: bool,
+
+    /// Remove trailing whitespace from the lines added by the patch.
+    #[arg(long, value_name = "ACTION", value_parser = ["nowarn", "warn", "fix"])]
+    whitespace: Option<String>,
+
+    /// Where to write the commit message.
+    msg: PathBuf,
+
+    /// Where to write the patch.
+    patch: PathBuf,
+}
+
+#[derive(Args)]
+struct ApplyArgs {
+    /// Apply the patches to the index without touching the working tree.
+    #[arg(long)]
+    cached: bool,
+
+    /// Apply the patches to both the index and the working tree.
+    #[arg(long, conflicts_with = "cached")]
+    index: bool,
+
+    /// Only check that the patches apply, without applying them.
+    #[arg(long)]
+    check: bool,
+
+    /// Apply the patches in reverse.
+    #[arg(short = 'R', long)]
+    reverse: bool,
+
+    /// Remove this many leading components from the paths of the patches.
+    #[arg(short = 'p', value_name = "N", default_value_t = 1)]
+    strip: usize,
+
+    /// Let hunks match with only this many context lines on each side.
+    #[arg(short = 'C', value_name = "N")]
+    context: Option<usize>,
+
+    /// Don't print why hunks don't apply.
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// The patch files, or stdin if there are none or for `-`.
+    patches: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct FormatPatchArgs {
+    /// Print the patches as an mbox instead of writing them to files.
+    #[arg(long)]
+    stdout: bool,
+
+    /// Write the patch files to this directory.
+    #[arg(short, long, value_name = "DIR")]
+    output_directory: Option<PathBuf>,
+
+    /// Number the patches even if there's only one.
+    #[arg(short, long)]
+    numbered: bool,
+
+    /// Never number the patches.
+    #[arg(short = 'N', long, conflicts_with = "numbered")]
+    no_numbered: bool,
+
+    /// The number of the first patch.
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    start_number: usize,
+
+    /// What the subjects start with in brackets.
+    #[arg(long, value_name = "PREFIX", default_value = "PATCH")]
+    subject_prefix: String,
+
+    /// The signature ending each patch, `format.signature` or the version by
+    /// default.
+    #[arg(long)]
+    signature: Option<String>,
+
+    /// Don't end the patches with a signature.
+    #[arg(long, conflicts_with = "signature")]
+    no_signature: bool,
+
+    /// `<since>` for the commits after it, a range, or `-<n>` for the last n
+    /// commits.
+    #[arg(allow_negative_numbers = true)]
+    revs: Vec<String>,
 }
 
 #[derive(Args)]
-struct InitArgs {
-    #[arg(default_value = ".")]
-    path: PathBuf,
+struct AmArgs {
+    /// Fall back to a three-way merge when a patch doesn't apply.
+    #[arg(short = '3', long = "3way")]
+    three_way: bool,
 
-    #[arg(default_value = "master")]
-    branch: String,
+    /// Commit the patch once applied by hand, and apply the others.
+    #[arg(long = "continue", group = "resume")]
+    continue_: bool,
+
+    /// Skip the patch that didn't apply.
+    #[arg(long, group = "resume")]
+    skip: bool,
+
+    /// Stop and restore the original branch.
+    #[arg(long, group = "resume")]
+    abort: bool,
+
+    /// The mailboxes, or stdin if there are none or for `-`.
+    #[arg(conflicts_with = "resume")]
+    mbox: Vec<PathBuf>,
 }
 
 #[derive(Args)]
-struct HashObjectArgs {
-    /// Write the object into the object database.
-    #[arg(short)]
-    write: bool,
+struct PatchIdArgs {
+    /// Hash each file on its own so that the ID doesn't depend on their
+    /// order, `patchid.stable` by default.
+    #[arg(long, group = "mode")]
+    stable: bool,
 
-    /// Read the object from stdin instead of from a file.
+    /// Hash the files together, like older versions of git.
+    #[arg(long, group = "mode")]
+    unstable: bool,
+
+    /// Don't ignore whitespace, which implies `--stable`,
+    /// `patchid.verbatim` by default.
+    #[arg(long, group = "mode")]
+    verbatim: bool,
+}
+
+#[derive(Args)]
+struct CherryArgs {
+    /// Print the subjects of the commits too.
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// The branch to look for the commits in, the upstream branch by
+    /// default.
+    upstream: Option<String>,
+
+    /// The branch with the commits, HEAD by default.
+    head: Option<String>,
+
+    /// Don't report the commits of this commit.
+    limit: Option<String>,
+}
+
+#[derive(Args)]
+struct RangeDiffArgs {
+    /// How readily commits are paired, as a percentage.
+    #[arg(long, value_name = "FACTOR", default_value_t = 60)]
+    creation_factor: usize,
+
+    /// `<range1> <range2>`, `<rev1>...<rev2>`, or `<base> <rev1> <rev2>`.
+    #[arg(required = true, num_args = 1..=3)]
+    revs: Vec<String>,
+}
+
+#[derive(Args)]
+struct MergeFileArgs {
+    /// Write the result to stdout instead of the current file.
+    #[arg(short = 'p', long)]
+    stdout: bool,
+
+    /// Show the base lines of conflicts too.
+    #[arg(long, group = "style")]
+    diff3: bool,
+
+    /// Like `--diff3`, with the lines both sides start or end with moved out
+    /// of conflicts.
+    #[arg(long, group = "style")]
+    zdiff3: bool,
+
+    /// Resolve conflicts with our lines.
+    #[arg(long, group = "favor")]
+    ours: bool,
+
+    /// Resolve conflicts with their lines.
+    #[arg(long, group = "favor")]
+    theirs: bool,
+
+    /// Resolve conflicts with both sides' lines.
+    #[arg(long, group = "favor")]
+    union: bool,
+
+    #[arg(long, value_name = "N", default_value_t = good_git::merge_file::DEFAULT_MARKER_SIZE)]
+    marker_size: usize,
+
+    /// The names of the current, base and other files in conflict markers,
+    /// instead of their paths.
+    #[arg(short = 'L', value_name = "LABEL")]
+    labels: Vec<String>,
+
+    current: PathBuf,
+
+    base: PathBuf,
+
+    other: PathBuf,
+}
+
+#[derive(Args)]
+struct MergeArgs {
+    /// Create a merge commit even when the merge could fast-forward.
+    #[arg(long, group = "fast_forward")]
+    no_ff: bool,
+
+    /// Refuse to merge unless it fast-forwards.
+    #[arg(long, group = "fast_forward")]
+    ff_only: bool,
+
+    /// The message of the merge commit.
+    #[arg(short, long)]
+    message: Option<String>,
+
+    /// Stage the merged changes without committing them or recording the
+    /// merge.
+    #[arg(long, conflicts_with = "no_ff")]
+    squash: bool,
+
+    /// Stop before creating the merge commit.
     #[arg(long)]
-    stdin: bool,
+    no_commit: bool,
 
-    #[arg(required_unless_present("stdin"))]
-    file: Option<PathBuf>,
+    /// Abandon the merge in progress and go back to HEAD.
+    #[arg(long, conflicts_with_all = ["no_ff", "ff_only", "message", "squash", "no_commit", "rev"])]
+    abort: bool,
+
+    #[arg(required_unless_present = "abort")]
+    rev: Option<String>,
 }
 
 #[derive(Args)]
-struct CatFileArgs {
-    object: String,
+struct CherryPickArgs {
+    /// Apply the changes without committing them.
+    #[arg(short = 'n', long)]
+    no_commit: bool,
+
+    /// Commit the resolved conflicts, and pick the other commits.
+    #[arg(long = "continue", group = "resume")]
+    continue_: bool,
+
+    /// Skip the commit that didn't apply.
+    #[arg(long, group = "resume")]
+    skip: bool,
+
+    /// Stop and restore the original branch.
+    #[arg(long, group = "resume")]
+    abort: bool,
+
+    /// The commits, or ranges like `A..B`.
+    #[arg(required_unless_present = "resume", conflicts_with = "resume")]
+    revs: Vec<String>,
 }
 
 #[derive(Args)]
-struct LogArgs {
-    object: String,
+struct RevertArgs {
+    /// Apply the inverse changes without committing them.
+    #[arg(short = 'n', long)]
+    no_commit: bool,
+
+    /// Commit the resolved conflicts, and revert the other commits.
+    #[arg(long = "continue", group = "resume")]
+    continue_: bool,
+
+    /// Skip the commit that didn't revert.
+    #[arg(long, group = "resume")]
+    skip: bool,
+
+    /// Stop and restore the original branch.
+    #[arg(long, group = "resume")]
+    abort: bool,
+
+    /// The commits, or ranges like `A..B`.
+    #[arg(required_unless_present = "resume", conflicts_with = "resume")]
+    revs: Vec<String>,
+}
+
+#[derive(Args)]
+struct RebaseArgs {
+    /// Edit the list of the commits to pick first.
+    #[arg(short, long, conflicts_with = "resume")]
+    interactive: bool,
+
+    /// Commit the resolved conflicts, or amend the commit, and go on.
+    #[arg(long = "continue", group = "resume")]
+    continue_: bool,
+
+    /// Skip the commit that didn't apply.
+    #[arg(long, group = "resume")]
+    skip: bool,
+
+    /// Stop and restore the original branch.
+    #[arg(long, group = "resume")]
+    abort: bool,
+
+    /// The commit to replay the commits on, whose commits are left out.
+    #[arg(required_unless_present = "resume", conflicts_with = "resume")]
+    upstream: Option<String>,
+}
+
+#[derive(Args)]
+struct ArchiveArgs {
+    /// The format of the archive, `tar` or `zip`. Defaults to the format of
+    /// the extension of the output file, or `tar`.
+    #[arg(long)]
+    format: Option<good_git::archive::Format>,
+
+    /// Prepend a path to the paths in the archive, like `project-1.0/`.
+    #[arg(long, default_value = "")]
+    prefix: String,
+
+    /// Write the archive to a file instead of stdout.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// The commit, tag or tree to archive.
+    tree_ish: String,
+}
+
+#[derive(Args)]
+struct RecoverArgs {
+    /// Roll the operation forward, finishing it.
+    #[arg(long = "continue", conflicts_with = "abort")]
+    continue_: bool,
+
+    /// Roll the operation back, undoing what it did.
+    #[arg(long)]
+    abort: bool,
+}
+
+#[derive(Args)]
+struct CheckRefFormatArgs {
+    refname: String,
+}
+
+/// Finds the repository containing the current directory, making sure it's
+/// safe to operate on.
+fn find_repo() -> Result<Repo> {
+    Repo::from_dir(Path::new("."))?.ok_or_else(|| anyhow!("Could not find a valid git repository"))
+}
+
+/// Finds the repository containing the current directory for commands that
+/// also work outside of one. Like git, a repository that isn't safe to
+/// operate on is ignored.
+fn find_optional_repo() -> Option<Repo> {
+    Repo::from_dir(Path::new(".")).ok().flatten()
+}
+
+/// Returns the value of a pair of `--<flag>` and `--no-<flag>` options, if
+/// either was given.
+fn flag(set: bool, clear: bool) -> Option<bool> {
+    match (set, clear) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// Applies `--column[=<options>]`, which enables columns unless the
+/// options say otherwise.
+fn column_flag(mode: &mut good_git::column::Mode, options: Option<&str>) -> Result<()> {
+    mode.enable = good_git::column::Enable::Always;
+    if let Some(options) = options {
+        mode.parse(options)?;
+    }
+    Ok(())
+}
+
+/// Handles an unknown command by suggesting similar ones, and running the
+/// most similar one if `help.autocorrect` allows it.
+fn autocorrect(error: clap::Error) -> Result<Cli> {
+    use good_git::autocorrect::Autocorrect;
+
+    let mut args: Vec<String> = std::env::args().collect();
+    let (Some(unknown), Some(config)) = (
+        args.get(1).cloned(),
+        match find_optional_repo() {
+            Some(repo) => good_git::config::Config::load(&repo).ok(),
+            None => good_git::config::Config::load_global().ok(),
+        },
+    ) else {
+        error.exit();
+    };
+    let autocorrect = Autocorrect::from_config(&config)?;
+    let cli = Cli::command();
+    let commands: Vec<&str> = cli.get_subcommands().map(|c| c.get_name()).collect();
+    let suggestions = match autocorrect {
+        Autocorrect::Never => vec![],
+        _ => good_git::autocorrect::similar_commands(&unknown, &commands),
+    };
+
+    if let [command] = suggestions[..] {
+        let run = match autocorrect {
+            Autocorrect::Never | Autocorrect::Show => false,
+            Autocorrect::Immediate => {
+                eprintln!("WARNING: You called a good_git command named '{unknown}', which does not exist.\nContinuing under the assumption that you meant '{command}'.");
+                true
+            }
+            Autocorrect::Delay(delay) => {
+                eprintln!("WARNING: You called a good_git command named '{unknown}', which does not exist.\nContinuing in {:.1} seconds, assuming that you meant '{command}'.", delay.as_secs_f64());
+                std::thread::sleep(delay);
+                true
+            }
+            Autocorrect::Prompt => {
+                eprint!("WARNING: You called a good_git command named '{unknown}', which does not exist.\nRun '{command}' instead [y/N]? ");
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer)?;
+                matches!(answer.trim(), "y" | "Y" | "yes")
+            }
+        };
+        if run {
+            args[1] = command.to_string();
+            return Ok(Cli::parse_from(args));
+        }
+    }
+
+    eprintln!("good_git: '{unknown}' is not a good_git command. See 'good_git --help'.");
+    match suggestions.len() {
+        0 => {}
+        1 => eprintln!("\nThe most similar command is"),
+        _ => eprintln!("\nThe most similar commands are"),
+    }
+    for suggestion in suggestions {
+        eprintln!("\t{suggestion}");
+    }
+    std::process::exit(1);
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) if e.kind() == ErrorKind::InvalidSubcommand => autocorrect(e)?,
+        Err(e) => e.exit(),
+    };
 
     match &cli.command {
         Commands::Init(init_args) => {
-            good_git::init_repo(&Repo::new(&init_args.path), &init_args.branch)?;
+            good_git::init_repo_with_template(
+                &Repo::new(&init_args.path),
+                &init_args.branch,
+                init_args.template.as_deref(),
+                &mut io::stdout(),
+            )?;
         }
         Commands::HashObject(hash_object_args) => {
-            let repo = Repo::from_dir(Path::new("."));
+            let repo;
             let mode = if hash_object_args.write {
-                good_git::HashObjectMode::Write(
-                    repo.as_ref()
-                        .ok_or_else(|| anyhow!("Could not find a valid git repository"))?,
-                )
+                repo = find_repo()?;
+                good_git::HashObjectMode::Write(&repo)
             } else {
                 good_git::HashObjectMode::HashOnly
             };
 
+            let options = good_git::HashObjectOptions {
+                object_type: hash_object_args.object_type,
+                literally: hash_object_args.literally,
+            };
             if hash_object_args.stdin {
-                hash_object(mode, &mut io::stdin(), &mut io::stdout())?;
+                good_git::hash_object_with_options(
+                    mode,
+                    &mut io::stdin(),
+                    &options,
+                    &mut io::stdout(),
+                )?;
             } else {
                 let f = hash_object_args
                     .file
                     .clone()
                     .expect("<file> is required when --stdin isn't set");
-                let f = fs::File::open(f)?;
-                hash_object(mode, &mut io::BufReader::new(f), &mut io::stdout())?;
+                good_git::hash_file_with_options(mode, &f, &options, &mut io::stdout())?;
             }
         }
+        Commands::ShowIndex => {
+            good_git::show_index(&mut io::stdin(), &mut io::stdout())?;
+        }
+        Commands::Column(column_args) => {
+            let mut mode = match &column_args.command {
+                Some(command) => {
+                    let config = match find_optional_repo() {
+                        Some(repo) => good_git::config::Config::load(&repo)?,
+                        None => good_git::config::Config::load_global()?,
+                    };
+                    good_git::column::Mode::from_config(&config, command)?
+                }
+                None => Default::default(),
+            };
+            if let Some(options) = &column_args.mode {
+                column_flag(&mut mode, Some(options))?;
+            }
+            let options = good_git::column::ColumnOptions {
+                mode: mode.resolve_auto(io::stdout().is_terminal()),
+                width: column_args
+                    .width
+                    .unwrap_or_else(good_git::column::terminal_width),
+                indent: column_args.indent.clone(),
+                padding: column_args.padding,
+                nl: column_args.nl.clone(),
+            };
+            let items = io::stdin().lines().collect::<io::Result<Vec<_>>>()?;
+            good_git::column::print(&items, &options, &mut io::stdout())?;
+        }
         Commands::CatFile(cat_file_args) => {
-            let repo = Repo::from_dir(Path::new("."))
-                .ok_or_else(|| anyhow!("Could not find a valid git repository"))?;
-            good_git::cat_file(&repo, &cat_file_args.object, &mut io::stdout())?;
+            let repo = find_repo()?;
+            if cat_file_args.object_type {
+                good_git::cat_file_type(&repo, &cat_file_args.object, &mut io::stdout())?;
+            } else if cat_file_args.size {
+                good_git::cat_file_size(&repo, &cat_file_args.object, &mut io::stdout())?;
+            } else if cat_file_args.textconv {
+                good_git::cat_file_textconv(&repo, &cat_file_args.object, &mut io::stdout())?;
+            } else if cat_file_args.filters {
+                good_git::cat_file_filters(
+                    &repo,
+                    &cat_file_args.object,
+                    &mut io::stdout(),
+                    &mut io::stderr(),
+                )?;
+            } else {
+                good_git::cat_file(&repo, &cat_file_args.object, &mut io::stdout())?;
+            }
         }
         Commands::Log(log_args) => {
-            let repo = Repo::from_dir(Path::new("."))
-                .ok_or_else(|| anyhow!("Could not find a valid git repository"))?;
-            good_git::log(&repo, &log_args.object, &mut io::stdout())?;
+            let repo = find_repo()?;
+            if log_args.json_graph {
+                good_git::log_json_graph(&repo, &log_args.object, &mut io::stdout())?;
+            } else {
+                let config = good_git::config::Config::load(&repo)?;
+                // Like git, logs are colored like diffs.
+                let when = log_args.color.when(&config, "diff")?;
+                let format = match log_args.pretty.as_ref().or(log_args.format.as_ref()) {
+                    Some(format) => good_git::pretty::Format::parse(format)?,
+                    None => Default::default(),
+                };
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)?
+                    .as_secs() as i64;
+                let date = |date: &Option<String>| {
+                    date.as_deref()
+                        .map(|date| good_git::ident::parse_approxidate(date, now))
+                        .transpose()
+                };
+                let regexes = |patterns: &[String]| {
+                    patterns
+                        .iter()
+                        .map(|pattern| regex::Regex::new(pattern))
+                        .collect::<Result<Vec<_>, _>>()
+                };
+                let cwd = std::env::current_dir()?;
+                let paths = log_args
+                    .paths
+                    .iter()
+                    .map(|path| repo.relative_path(&cwd.join(path)))
+                    .collect::<Result<Vec<_>>>()?;
+                let limits = good_git::revwalk::Limits {
+                    max_count: log_args.max_count,
+                    since: date(&log_args.since)?,
+                    until: date(&log_args.until)?,
+                    authors: regexes(&log_args.author)?,
+                    committers: regexes(&log_args.committer)?,
+                    messages: regexes(&log_args.grep)?,
+                };
+                let options = good_git::LogOptions {
+                    oneline: log_args.oneline,
+                    format,
+                    left_right: log_args.left_right,
+                    cherry_pick: log_args.cherry_pick,
+                    topo_order: log_args.topo_order,
+                    first_parent: log_args.first_parent,
+                    limits,
+                    paths,
+                    full_hash: log_args.no_abbrev_commit
+                        || !(log_args.oneline || log_args.abbrev_commit),
+                    subject_width: log_args.subject_width,
+                    committer_width: log_args.committer_width,
+                    decorate: log_args.decorate,
+                    colors: match when.enabled(io::stdout().is_terminal()) {
+                        true => Some(good_git::color::DecorateColors::from_config(&config)?),
+                        false => None,
+                    },
+                };
+                good_git::log_with_options(&repo, &log_args.object, &options, &mut io::stdout())?;
+            }
+        }
+        Commands::Branch(branch_args) => {
+            let repo = find_repo()?;
+            let config = good_git::config::Config::load(&repo)?;
+            let columns = branch_args.columns.options(&config, "branch", 1)?;
+            good_git::branch_list(&repo, &columns, &mut io::stdout())?;
+        }
+        Commands::Tag(tag_args) => {
+            let repo = find_repo()?;
+            let config = good_git::config::Config::load(&repo)?;
+            let columns = tag_args.columns.options(&config, "tag", 2)?;
+            let options = good_git::TagListOptions {
+                patterns: tag_args.patterns.clone(),
+                ignore_case: tag_args.ignore_case,
+            };
+            good_git::tag_list(&repo, &options, &columns, &mut io::stdout())?;
+        }
+        Commands::ShowRef(show_ref_args) => {
+            let repo = find_repo()?;
+            let options = good_git::ShowRefOptions {
+                heads: show_ref_args.heads,
+                tags: show_ref_args.tags,
+                verify: show_ref_args.verify,
+                dereference: show_ref_args.dereference,
+                patterns: show_ref_args.patterns.clone(),
+            };
+            good_git::show_ref(&repo, &options, &mut io::stdout())?;
+        }
+        Commands::NameRev(name_rev_args) => {
+            let repo = find_repo()?;
+            good_git::name_rev(&repo, &name_rev_args.revs, &mut io::stdout())?;
+        }
+        Commands::RevList(rev_list_args) => {
+            let repo = find_repo()?;
+            let options = good_git::RevListOptions {
+                objects: rev_list_args.objects,
+                all: rev_list_args.all,
+                missing: rev_list_args.missing,
+                left_right: rev_list_args.left_right,
+                cherry_pick: rev_list_args.cherry_pick,
+                topo_order: rev_list_args.topo_order,
+            };
+            good_git::rev_list(&repo, &rev_list_args.revs, &options, &mut io::stdout())?;
+        }
+        Commands::MergeBase(merge_base_args) => {
+            let repo = find_repo()?;
+            if !good_git::merge_base_is_ancestor(
+                &repo,
+                &merge_base_args.commit,
+                &merge_base_args.other,
+            )? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Checkout(checkout_args) => {
+            let repo = find_repo()?;
+            if !checkout_args.pathspecs.is_empty() {
+                let cwd = std::env::current_dir()?;
+                let pathspecs = checkout_args
+                    .pathspecs
+                    .iter()
+                    .map(|path| repo.relative_path(&cwd.join(path)))
+                    .collect::<Result<Vec<_>>>()?;
+                good_git::checkout_paths(&repo, checkout_args.rev.as_deref(), &pathspecs)?;
+            } else if let Some(branch) = &checkout_args.orphan {
+                good_git::checkout_orphan(&repo, branch, true, &mut io::stdout())?;
+            } else if checkout_args.detach {
+                good_git::checkout_detach(&repo, checkout_args.rev.as_deref(), &mut io::stdout())?;
+            } else if let Some(rev) = &checkout_args.rev {
+                good_git::checkout(&repo, rev, &mut io::stdout())?;
+            } else {
+                return Err(anyhow!(
+                    "Only checkout <branch>, --detach and --orphan are supported"
+                ));
+            }
+        }
+        Commands::Switch(switch_args) => {
+            let repo = find_repo()?;
+            if let Some(branch) = &switch_args.orphan {
+                good_git::checkout_orphan(&repo, branch, false, &mut io::stdout())?;
+            } else if let Some(branch) = &switch_args.branch {
+                good_git::switch_branch(&repo, branch, &mut io::stdout())?;
+            } else {
+                return Err(anyhow!("Only switch <branch> and --orphan are supported"));
+            }
+        }
+        Commands::Worktree(worktree_args) => {
+            let repo = find_repo()?;
+            match &worktree_args.command {
+                WorktreeCommands::Add(add_args) => {
+                    let options = good_git::WorktreeAddOptions {
+                        new_branch: add_args.new_branch.clone(),
+                        detach: add_args.detach,
+                        lock: add_args.lock,
+                    };
+                    good_git::worktree_add(
+                        &repo,
+                        &add_args.path,
+                        add_args.commit_ish.as_deref(),
+                        &options,
+                        &mut io::stdout(),
+                    )?;
+                }
+                WorktreeCommands::List(list_args) => {
+                    good_git::worktree_list(&repo, list_args.porcelain, &mut io::stdout())?;
+                }
+                WorktreeCommands::Lock(lock_args) => {
+                    good_git::worktree_lock(
+                        &repo,
+                        &lock_args.worktree,
+                        lock_args.reason.as_deref(),
+                    )?;
+                }
+                WorktreeCommands::Unlock(unlock_args) => {
+                    good_git::worktree_unlock(&repo, &unlock_args.worktree)?;
+                }
+                WorktreeCommands::Remove(remove_args) => {
+                    good_git::worktree_remove(&repo, &remove_args.worktree, remove_args.force)?;
+                }
+            }
+        }
+        Commands::LsFiles(ls_files_args) => {
+            let repo = find_repo()?;
+            good_git::ls_files(&repo, ls_files_args.stage, &mut io::stdout())?;
+        }
+        Commands::LsTree(ls_tree_args) => {
+            let repo = find_repo()?;
+            let options = good_git::LsTreeOptions {
+                recursive: ls_tree_args.recursive,
+                trees_only: ls_tree_args.trees_only,
+                long: ls_tree_args.long,
+                name_only: ls_tree_args.name_only,
+                format: ls_tree_args.format.clone(),
+            };
+            good_git::ls_tree(&repo, &ls_tree_args.tree, &options, &mut io::stdout())?;
+        }
+        Commands::Commit(commit_args) => {
+            let repo = find_repo()?;
+            let options = good_git::CommitOptions {
+                message: commit_args.message.join("\n\n"),
+                allow_empty: commit_args.allow_empty,
+                allow_empty_message: commit_args.allow_empty_message,
+                amend: commit_args.amend,
+                author: None,
+                reflog_action: None,
+            };
+            good_git::commit(&repo, &options, &mut io::stdout())?;
+        }
+        Commands::Add(add_args) => {
+            let repo = find_repo()?;
+            let cwd = std::env::current_dir()?;
+            let pathspecs = add_args
+                .pathspecs
+                .iter()
+                .map(|path| repo.relative_path(&cwd.join(path)))
+                .collect::<Result<Vec<_>>>()?;
+            if add_args.patch {
+                let stdin = &mut io::stdin().lock();
+                good_git::add_patch(&repo, &pathspecs, stdin, &mut io::stdout())?;
+            } else {
+                let options = good_git::AddOptions {
+                    all: add_args.all,
+                    update: add_args.update,
+                    verbose: add_args.verbose,
+                    force: add_args.force,
+                };
+                good_git::add(&repo, &pathspecs, &options, &mut io::stdout())?;
+            }
+        }
+        Commands::Diff(diff_args) => {
+            let repo = find_repo()?;
+            let is_rev = |arg: &str| {
+                let sides = arg.split_once("...").or(arg.split_once(".."));
+                let (old, new) = sides.unwrap_or((arg, ""));
+                !arg.is_empty()
+                    && [old, new].iter().all(|rev| {
+                        rev.is_empty() || good_git::object::Object::resolve_rev(&repo, rev).is_ok()
+                    })
+            };
+            let cwd = std::env::current_dir()?;
+            let mut revs = diff_args.args.clone();
+            let mut paths = diff_args.pathspecs.clone();
+            let first_path = revs.iter().position(|arg| !is_rev(arg));
+            if let Some(first_path) = first_path.filter(|_| paths.is_empty()) {
+                paths = revs
+                    .split_off(first_path)
+                    .into_iter()
+                    .map(PathBuf::from)
+                    .collect();
+                // Like git, paths that aren't separated by `--` must exist,
+                // so that a mistyped revision isn't taken for a path.
+                let missing = paths.iter().find(|path| {
+                    let path = path.to_string_lossy();
+                    !good_git::wildmatch::is_glob(&path)
+                        && cwd.join(&*path).symlink_metadata().is_err()
+                });
+                if let Some(path) = missing {
+                    return Err(anyhow!(
+                        "ambiguous argument '{}': unknown revision or path not in the working tree.\n\
+                         Use '--' to separate paths from revisions, like this:\n\
+                         'good_git <command> [<revision>...] -- [<file>...]'",
+                        path.display()
+                    ));
+                }
+            }
+            let pathspecs = paths
+                .iter()
+                .map(|path| repo.relative_path(&cwd.join(path)))
+                .collect::<Result<Vec<_>>>()?;
+            let format = &diff_args.format;
+            let stat = format.stat(&repo, true)?;
+            let patch =
+                match diff_args.raw || format.name_only || format.name_status || stat.is_some() {
+                    true => format.patch(&repo, true)?,
+                    false => Some(format.patch_options(&repo, true)?),
+                };
+            let options = good_git::DiffOptions {
+                cached: diff_args.cached,
+                format: format.format(),
+                stat,
+                patch,
+                no_abbrev: diff_args.no_abbrev,
+            };
+            good_git::diff(&repo, &revs, &pathspecs, &options, &mut io::stdout())?;
+        }
+        Commands::Difftool(difftool_args) => {
+            let repo = find_repo()?;
+            let options = good_git::DifftoolOptions {
+                cached: difftool_args.cached,
+                tool: difftool_args.tool.clone(),
+                extcmd: difftool_args.extcmd.clone(),
+            };
+            good_git::difftool(&repo, &difftool_args.commits, &options, &mut io::stdout())?;
+        }
+        Commands::Grep(grep_args) => {
+            let repo = find_repo()?;
+            let cwd = std::env::current_dir()?;
+            let pathspecs = grep_args
+                .pathspecs
+                .iter()
+                .map(|path| repo.relative_path(&cwd.join(path)))
+                .collect::<Result<Vec<_>>>()?;
+            let options = good_git::GrepOptions {
+                cached: grep_args.cached,
+                tree: grep_args.tree.clone(),
+                ignore_case: grep_args.ignore_case,
+                invert_match: grep_args.invert_match,
+                fixed_strings: grep_args.fixed_strings,
+                line_number: grep_args.line_number,
+                files_with_matches: grep_args.files_with_matches,
+                count: grep_args.count,
+                textconv: grep_args.textconv,
+            };
+            let found = good_git::grep(
+                &repo,
+                &grep_args.pattern,
+                &pathspecs,
+                &options,
+                &mut io::stdout(),
+            )?;
+            if !found {
+                std::process::exit(1);
+            }
+        }
+        Commands::Blame(blame_args) => {
+            let repo = find_repo()?;
+            let (rev, path) = match (&blame_args.args[..], &blame_args.path) {
+                ([rev], Some(path)) => (Some(rev), path.clone()),
+                ([path], None) => (None, PathBuf::from(path)),
+                ([rev, path], None) => (Some(rev), PathBuf::from(path)),
+                _ => return Err(anyhow!("too many arguments")),
+            };
+            let path = repo.relative_path(&std::env::current_dir()?.join(path))?;
+            let format = match blame_args {
+                BlameArgs {
+                    porcelain: true, ..
+                } => good_git::BlameFormat::Porcelain,
+                BlameArgs {
+                    line_porcelain: true,
+                    ..
+                } => good_git::BlameFormat::LinePorcelain,
+                _ => good_git::BlameFormat::Default,
+            };
+            let options = good_git::BlameOptions {
+                ranges: blame_args.ranges.clone(),
+                ignore_whitespace: blame_args.ignore_whitespace,
+                format,
+            };
+            let rev = rev.map(String::as_str);
+            good_git::blame(&repo, rev, &path, &options, &mut io::stdout())?;
+        }
+        Commands::Rm(rm_args) => {
+            let repo = find_repo()?;
+            let cwd = std::env::current_dir()?;
+            let pathspecs = rm_args
+                .pathspecs
+                .iter()
+                .map(|path| repo.relative_path(&cwd.join(path)))
+                .collect::<Result<Vec<_>>>()?;
+            let options = good_git::RmOptions {
+                cached: rm_args.cached,
+                recursive: rm_args.recursive,
+                force: rm_args.force,
+                quiet: rm_args.quiet,
+            };
+            good_git::rm(&repo, &pathspecs, &options, &mut io::stdout())?;
+        }
+        Commands::Clean(clean_args) => {
+            let repo = find_repo()?;
+            let ignored = if clean_args.ignored {
+                good_git::CleanIgnored::Include
+            } else if clean_args.only_ignored {
+                good_git::CleanIgnored::Only
+            } else {
+                good_git::CleanIgnored::Exclude
+            };
+            let options = good_git::CleanOptions {
+                dry_run: clean_args.dry_run,
+                force: clean_args.force,
+                directories: clean_args.directories,
+                ignored,
+            };
+            good_git::clean(&repo, &options, &mut io::stdout())?;
+        }
+        Commands::CheckIgnore(check_ignore_args) => {
+            let repo = find_repo()?;
+            let cwd = std::env::current_dir()?;
+            let paths = check_ignore_args
+                .paths
+                .iter()
+                .map(|path| repo.relative_path(&cwd.join(path)))
+                .collect::<Result<Vec<_>>>()?;
+            let options = good_git::CheckIgnoreOptions {
+                verbose: check_ignore_args.verbose,
+                non_matching: check_ignore_args.non_matching,
+                no_index: check_ignore_args.no_index,
+            };
+            if !good_git::check_ignore(&repo, &paths, &options, &mut io::stdout())? {
+                std::process::exit(1);
+            }
+        }
+        Commands::CheckAttr(check_attr_args) => {
+            let repo = find_repo()?;
+            let mut args = check_attr_args.args.clone();
+            let mut paths: Vec<PathBuf> = check_attr_args.paths.clone();
+            if check_attr_args.all {
+                paths.splice(0..0, args.drain(..).map(PathBuf::from));
+            } else if paths.is_empty() && !args.is_empty() {
+                paths = args.split_off(1).into_iter().map(PathBuf::from).collect();
+            }
+            if args.is_empty() && !check_attr_args.all {
+                return Err(anyhow!("No attribute specified"));
+            }
+            let cwd = std::env::current_dir()?;
+            let paths = paths
+                .iter()
+                .map(|path| {
+                    let relative = repo.relative_path(&cwd.join(path))?;
+                    // A trailing slash looks the path up as a directory.
+                    match path.to_string_lossy().ends_with('/') {
+                        true => Ok(format!("{relative}/")),
+                        false => Ok(relative),
+                    }
+                })
+                .collect::<Result<Vec<_>>>()?;
+            good_git::check_attr(&repo, &args, &paths, &mut io::stdout())?;
+        }
+        Commands::UpdateIndex(update_index_args) => {
+            let repo = find_repo()?;
+            let cwd = std::env::current_dir()?;
+            let paths = update_index_args
+                .paths
+                .iter()
+                .map(|path| repo.relative_path(&cwd.join(path)))
+                .collect::<Result<Vec<_>>>()?;
+            let index_info = if update_index_args.index_info {
+                let mut index_info = String::new();
+                io::stdin().read_to_string(&mut index_info)?;
+                Some(index_info)
+            } else {
+                None
+            };
+            let options = good_git::UpdateIndexOptions {
+                add: update_index_args.add,
+                remove: update_index_args.remove,
+                refresh: update_index_args.refresh,
+                cacheinfo: update_index_args.cacheinfo.clone(),
+                index_info,
+                assume_unchanged: flag(
+                    update_index_args.assume_unchanged,
+                    update_index_args.no_assume_unchanged,
+                ),
+                skip_worktree: flag(
+                    update_index_args.skip_worktree,
+                    update_index_args.no_skip_worktree,
+                ),
+            };
+            if !good_git::update_index(&repo, &paths, &options, &mut io::stdout())? {
+                std::process::exit(1);
+            }
+        }
+        Commands::WriteTree(write_tree_args) => {
+            let repo = find_repo()?;
+            good_git::write_tree(&repo, write_tree_args.missing_ok, &mut io::stdout())?;
+        }
+        Commands::Fsck(fsck_args) => {
+            let repo = find_repo()?;
+            let options = good_git::FsckOptions {
+                connectivity_only: fsck_args.connectivity_only,
+                unreachable: fsck_args.unreachable,
+            };
+            let code = good_git::fsck(&repo, &options, &mut io::stdout(), &mut io::stderr())?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Commands::Push(push_args) => {
+            let repo = find_repo()?;
+            let options = good_git::PushOptions {
+                force: push_args.force,
+                set_upstream: push_args.set_upstream,
+                dry_run: push_args.dry_run,
+            };
+            let remote = push_args.remote.as_deref();
+            good_git::push(
+                &repo,
+                remote,
+                &push_args.refspecs,
+                &options,
+                &mut io::stdout(),
+            )?;
+        }
+        Commands::Fetch(fetch_args) => {
+            let repo = find_repo()?;
+            let options = good_git::FetchOptions {
+                depth: fetch_args.depth,
+                deepen: fetch_args.deepen,
+                shallow_since: fetch_args.shallow_since.clone(),
+                shallow_exclude: fetch_args.shallow_exclude.clone(),
+                unshallow: fetch_args.unshallow,
+            };
+            let remote = fetch_args.remote.as_deref();
+            good_git::fetch(
+                &repo,
+                remote,
+                &fetch_args.refspecs,
+                &options,
+                &mut io::stdout(),
+            )?;
+        }
+        Commands::ReadTree(read_tree_args) => {
+            let repo = find_repo()?;
+            let options = good_git::ReadTreeOptions {
+                merge: read_tree_args.merge,
+                update: read_tree_args.update,
+                prefix: read_tree_args.prefix.clone(),
+            };
+            good_git::read_tree(&repo, &read_tree_args.trees, &options)?;
+        }
+        Commands::DiffTree(diff_tree_args) => {
+            let repo = find_repo()?;
+            let cwd = std::env::current_dir()?;
+            let pathspecs = diff_tree_args
+                .pathspecs
+                .iter()
+                .map(|path| repo.relative_path(&cwd.join(path)))
+                .collect::<Result<Vec<_>>>()?;
+            let options = good_git::DiffTreeOptions {
+                recursive: diff_tree_args.recursive,
+                root: diff_tree_args.root,
+                format: diff_tree_args.format.format(),
+                stat: diff_tree_args.format.stat(&repo, false)?,
+                patch: diff_tree_args.format.patch(&repo, false)?,
+            };
+            if diff_tree_args.stdin {
+                let stdin = &mut io::stdin().lock();
+                good_git::diff_tree_stdin(&repo, stdin, &pathspecs, &options, &mut io::stdout())?;
+            } else {
+                let revs = &diff_tree_args.revs;
+                good_git::diff_tree(&repo, revs, &pathspecs, &options, &mut io::stdout())?;
+            }
+        }
+        Commands::DiffIndex(diff_index_args) => {
+            let repo = find_repo()?;
+            let cwd = std::env::current_dir()?;
+            let pathspecs = diff_index_args
+                .pathspecs
+                .iter()
+                .map(|path| repo.relative_path(&cwd.join(path)))
+                .collect::<Result<Vec<_>>>()?;
+            let options = good_git::DiffIndexOptions {
+                cached: diff_index_args.cached,
+                format: diff_index_args.format.format(),
+                stat: diff_index_args.format.stat(&repo, false)?,
+                patch: diff_index_args.format.patch(&repo, false)?,
+            };
+            good_git::diff_index(
+                &repo,
+                &diff_index_args.tree,
+                &pathspecs,
+                &options,
+                &mut io::stdout(),
+            )?;
+        }
+        Commands::DiffFiles(diff_files_args) => {
+            let repo = find_repo()?;
+            let cwd = std::env::current_dir()?;
+            let pathspecs = diff_files_args
+                .pathspecs
+                .iter()
+                .map(|path| repo.relative_path(&cwd.join(path)))
+                .collect::<Result<Vec<_>>>()?;
+            let options = good_git::DiffFilesOptions {
+                format: diff_files_args.format.format(),
+                stat: diff_files_args.format.stat(&repo, false)?,
+                patch: diff_files_args.format.patch(&repo, false)?,
+            };
+            good_git::diff_files(&repo, &pathspecs, &options, &mut io::stdout())?;
+        }
+        Commands::Status(status_args) => {
+            let repo = find_repo()?;
+            let format = match status_args.porcelain.as_deref() {
+                Some("v1") => good_git::StatusFormat::PorcelainV1,
+                Some(_) => good_git::StatusFormat::PorcelainV2,
+                // Like git, -z implies --porcelain.
+                None if status_args.null_terminated => good_git::StatusFormat::PorcelainV1,
+                None => good_git::StatusFormat::Long,
+            };
+            let config = good_git::config::Config::load(&repo)?;
+            let when = good_git::color::When::from_config(&config, "status")?;
+            let options = good_git::StatusOptions {
+                format,
+                branch: status_args.branch,
+                null_terminated: status_args.null_terminated,
+                colors: match when.enabled(io::stdout().is_terminal()) {
+                    true => Some(good_git::color::StatusColors::from_config(&config)?),
+                    false => None,
+                },
+            };
+            good_git::status(&repo, &options, &mut io::stdout())?;
+        }
+        Commands::Mv(mv_args) => {
+            let repo = find_repo()?;
+            let cwd = std::env::current_dir()?;
+            let mut paths = mv_args
+                .paths
+                .iter()
+                .map(|path| repo.relative_path(&cwd.join(path)))
+                .collect::<Result<Vec<_>>>()?;
+            let destination = paths.pop().expect("clap requires two paths");
+            let options = good_git::MvOptions {
+                force: mv_args.force,
+                skip_errors: mv_args.skip_errors,
+                dry_run: mv_args.dry_run,
+                verbose: mv_args.verbose,
+            };
+            good_git::mv(&repo, &paths, &destination, &options, &mut io::stdout())?;
+        }
+        Commands::Mailinfo(mailinfo_args) => {
+            let mut mail = String::new();
+            io::stdin().read_to_string(&mut mail)?;
+            let mail = good_git::mailinfo::split_mbox(&mail, mailinfo_args.keep_cr)
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("Empty mail"))?;
+            let info = good_git::mailinfo::MailInfo::parse(&mail, mailinfo_args.scissors)?;
+            let patch = if mailinfo_args.whitespace.as_deref() == Some("fix") {
+                good_git::mailinfo::fix_whitespace(&info.patch)
+            } else {
+                info.patch
+            };
+            std::fs::write(&mailinfo_args.msg, &info.message)?;
+            std::fs::write(&mailinfo_args.patch, patch)?;
+            println!(
+                "Author: {}\nEmail: {}\nSubject: {}\nDate: {}\n",
+                info.author, info.email, info.subject, info.date
+            );
+        }
+        Commands::Apply(apply_args) => {
+            let repo = find_repo()?;
+            let mut patch = vec![];
+            if apply_args.patches.is_empty() {
+                io::stdin().read_to_end(&mut patch)?;
+            }
+            for path in &apply_args.patches {
+                match path.to_str() {
+                    Some("-") => io::stdin().read_to_end(&mut patch)?,
+                    _ => std::fs::File::open(path)
+                        .map_err(|e| anyhow!("can't open patch '{}': {e}", path.display()))?
+                        .read_to_end(&mut patch)?,
+                };
+            }
+            let options = good_git::ApplyOptions {
+                cached: apply_args.cached,
+                index: apply_args.index,
+                check: apply_args.check,
+                reverse: apply_args.reverse,
+                strip: apply_args.strip,
+                context: apply_args.context,
+                quiet: apply_args.quiet,
+            };
+            good_git::apply(&repo, &patch, &options, &mut io::stderr())?;
+        }
+        Commands::FormatPatch(format_patch_args) => {
+            let repo = find_repo()?;
+            let config = good_git::config::Config::load(&repo)?;
+            let (counts, revs): (Vec<&String>, Vec<&String>) = format_patch_args
+                .revs
+                .iter()
+                .partition(|rev| rev.starts_with('-'));
+            let max_count = counts
+                .last()
+                .map(|count| {
+                    count[1..]
+                        .parse()
+                        .map_err(|_| anyhow!("unknown option: {count}"))
+                })
+                .transpose()?;
+            let signature = match (&format_patch_args.signature, format_patch_args.no_signature) {
+                (_, true) => None,
+                (Some(signature), _) => Some(signature.clone()),
+                (None, _) => Some(
+                    config
+                        .get("format.signature")
+                        .unwrap_or(env!("CARGO_PKG_VERSION"))
+                        .to_string(),
+                ),
+            };
+            let options = good_git::FormatPatchOptions {
+                max_count,
+                numbered: match (format_patch_args.numbered, format_patch_args.no_numbered) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                },
+                start_number: format_patch_args.start_number,
+                subject_prefix: format_patch_args.subject_prefix.clone(),
+                signature: signature.filter(|signature| !signature.is_empty()),
+                output_directory: format_patch_args.output_directory.clone(),
+                stdout: format_patch_args.stdout,
+            };
+            let revs: Vec<String> = revs.into_iter().cloned().collect();
+            good_git::format_patch(&repo, &revs, &options, &mut io::stdout())?;
+        }
+        Commands::Am(am_args) => {
+            let repo = find_repo()?;
+            let resume = match (am_args.continue_, am_args.skip, am_args.abort) {
+                (true, _, _) => Some(good_git::AmResume::Continue),
+                (_, true, _) => Some(good_git::AmResume::Skip),
+                (_, _, true) => Some(good_git::AmResume::Abort),
+                _ => None,
+            };
+            if let Some(resume) = resume {
+                good_git::am_resume(&repo, resume, &mut io::stdout())?;
+            } else {
+                let mut mbox = String::new();
+                if am_args.mbox.is_empty() {
+                    io::stdin().read_to_string(&mut mbox)?;
+                }
+                for path in &am_args.mbox {
+                    match path.to_str() {
+                        Some("-") => io::stdin().read_to_string(&mut mbox)?,
+                        _ => {
+                            let mail = std::fs::read_to_string(path)
+                                .map_err(|e| anyhow!("could not open '{}': {e}", path.display()))?;
+                            if mail.is_empty() {
+                                return Err(anyhow!("empty mbox: '{}'", path.display()));
+                            }
+                            mbox.push_str(&mail);
+                            mail.len()
+                        }
+                    };
+                }
+                let options = good_git::AmOptions {
+                    three_way: am_args.three_way,
+                };
+                good_git::am(&repo, &mbox, &options, &mut io::stdout())?;
+            }
+        }
+        Commands::PatchId(patch_id_args) => {
+            let config = match find_optional_repo() {
+                Some(repo) => good_git::config::Config::load(&repo)?,
+                None => good_git::config::Config::load_global()?,
+            };
+            let (stable, verbatim) = match patch_id_args {
+                PatchIdArgs { stable: true, .. } => (true, false),
+                PatchIdArgs { unstable: true, .. } => (false, false),
+                PatchIdArgs { verbatim: true, .. } => (true, true),
+                _ => (
+                    config.get_bool("patchid.stable")? == Some(true),
+                    config.get_bool("patchid.verbatim")? == Some(true),
+                ),
+            };
+            let mut diff = vec![];
+            io::stdin().read_to_end(&mut diff)?;
+            for (id, commit) in good_git::patch_id::patch_ids(&diff, stable, verbatim) {
+                println!("{id} {commit}");
+            }
+        }
+        Commands::Cherry(cherry_args) => {
+            let repo = find_repo()?;
+            good_git::cherry(
+                &repo,
+                cherry_args.upstream.as_deref(),
+                cherry_args.head.as_deref(),
+                cherry_args.limit.as_deref(),
+                cherry_args.verbose,
+                &mut io::stdout(),
+            )?;
+        }
+        Commands::RangeDiff(range_diff_args) => {
+            let repo = find_repo()?;
+            let (old_range, new_range) = match &range_diff_args.revs[..] {
+                [rev] => {
+                    let (a, b) = rev
+                        .split_once("...")
+                        .ok_or_else(|| anyhow!("single arg format must be symmetric range"))?;
+                    let a = if a.is_empty() { "HEAD" } else { a };
+                    let b = if b.is_empty() { "HEAD" } else { b };
+                    (format!("{b}..{a}"), format!("{a}..{b}"))
+                }
+                [old_range, new_range] => {
+                    for range in [old_range, new_range] {
+                        if !range.contains("..") {
+                            return Err(anyhow!("not a commit range: '{range}'"));
+                        }
+                    }
+                    (old_range.clone(), new_range.clone())
+                }
+                [base, old, new] => (format!("{base}..{old}"), format!("{base}..{new}")),
+                _ => unreachable!("clap checks the number of revs"),
+            };
+            let options = good_git::RangeDiffOptions {
+                creation_factor: range_diff_args.creation_factor,
+            };
+            good_git::range_diff(&repo, &old_range, &new_range, &options, &mut io::stdout())?;
+        }
+        Commands::MergeFile(merge_file_args) => {
+            use good_git::merge_file::{ConflictStyle, Favor, Level, MergeOptions};
+
+            if merge_file_args.labels.len() > 3 {
+                return Err(anyhow!("too many labels on the command line"));
+            }
+            let config = match find_optional_repo() {
+                Some(repo) => good_git::config::Config::load(&repo)?,
+                None => good_git::config::Config::load_global()?,
+            };
+            let paths = [
+                &merge_file_args.current,
+                &merge_file_args.base,
+                &merge_file_args.other,
+            ];
+            let classifier = good_git::binary::BinaryClassifier::new(u64::MAX);
+            let mut contents = vec![];
+            for path in paths {
+                let content = std::fs::read(path)
+                    .map_err(|e| anyhow!("failed to read '{}': {e}", path.display()))?;
+                let attribute = good_git::binary::TextAttribute::Unspecified;
+                if classifier.is_binary(&content, content.len() as u64, attribute) {
+                    return Err(anyhow!("Cannot merge binary files: {}", path.display()));
+                }
+                contents.push(content);
+            }
+            let mut labels = paths.map(|path| Some(path.display().to_string()));
+            for (label, name) in labels.iter_mut().zip(&merge_file_args.labels) {
+                *label = Some(name.clone());
+            }
+            let [ours, base, theirs] = labels;
+            let options = MergeOptions {
+                level: Level::ZealousAlnum,
+                style: match merge_file_args {
+                    MergeFileArgs { diff3: true, .. } => ConflictStyle::Diff3,
+                    MergeFileArgs { zdiff3: true, .. } => ConflictStyle::ZealousDiff3,
+                    _ => ConflictStyle::from_config(&config)?,
+                },
+                favor: match merge_file_args {
+                    MergeFileArgs { ours: true, .. } => Some(Favor::Ours),
+                    MergeFileArgs { theirs: true, .. } => Some(Favor::Theirs),
+                    MergeFileArgs { union: true, .. } => Some(Favor::Union),
+                    _ => None,
+                },
+                marker_size: merge_file_args.marker_size,
+                labels: [ours, base, theirs],
+            };
+            let (merged, conflicts) =
+                good_git::merge_file::merge(&contents[1], &contents[0], &contents[2], &options);
+            if merge_file_args.stdout {
+                io::Write::write_all(&mut io::stdout(), &merged)?;
+            } else {
+                std::fs::write(&merge_file_args.current, merged)?;
+            }
+            if conflicts > 0 {
+                std::process::exit(conflicts.min(127) as i32);
+            }
+        }
+        Commands::Merge(merge_args) => {
+            let repo = find_repo()?;
+            let Some(rev) = &merge_args.rev else {
+                return good_git::merge_abort(&repo);
+            };
+            let fast_forward = match merge_args {
+                MergeArgs { no_ff: true, .. } => good_git::FastForward::Never,
+                MergeArgs { ff_only: true, .. } => good_git::FastForward::Only,
+                _ => good_git::FastForward::from_config(&good_git::config::Config::load(&repo)?)?,
+            };
+            let options = good_git::MergeOptions {
+                fast_forward,
+                message: merge_args.message.clone(),
+                squash: merge_args.squash,
+                no_commit: merge_args.no_commit,
+            };
+            if !good_git::merge(&repo, rev, &options, &mut io::stdout())? {
+                std::process::exit(1);
+            }
+        }
+        Commands::CherryPick(cherry_pick_args) => {
+            let repo = find_repo()?;
+            let resume = match cherry_pick_args {
+                CherryPickArgs {
+                    continue_: true, ..
+                } => Some(good_git::SequencerResume::Continue),
+                CherryPickArgs { skip: true, .. } => Some(good_git::SequencerResume::Skip),
+                CherryPickArgs { abort: true, .. } => Some(good_git::SequencerResume::Abort),
+                _ => None,
+            };
+            if let Some(resume) = resume {
+                good_git::cherry_pick_resume(&repo, resume, &mut io::stdout())?;
+            } else {
+                let options = good_git::CherryPickOptions {
+                    no_commit: cherry_pick_args.no_commit,
+                };
+                good_git::cherry_pick(&repo, &cherry_pick_args.revs, &options, &mut io::stdout())?;
+            }
+        }
+        Commands::Revert(revert_args) => {
+            let repo = find_repo()?;
+            let resume = match revert_args {
+                RevertArgs {
+                    continue_: true, ..
+                } => Some(good_git::SequencerResume::Continue),
+                RevertArgs { skip: true, .. } => Some(good_git::SequencerResume::Skip),
+                RevertArgs { abort: true, .. } => Some(good_git::SequencerResume::Abort),
+                _ => None,
+            };
+            if let Some(resume) = resume {
+                good_git::revert_resume(&repo, resume, &mut io::stdout())?;
+            } else {
+                let options = good_git::RevertOptions {
+                    no_commit: revert_args.no_commit,
+                };
+                good_git::revert(&repo, &revert_args.revs, &options, &mut io::stdout())?;
+            }
+        }
+        Commands::Rebase(rebase_args) => {
+            let repo = find_repo()?;
+            let resume = match rebase_args {
+                RebaseArgs {
+                    continue_: true, ..
+                } => Some(good_git::RebaseResume::Continue),
+                RebaseArgs { skip: true, .. } => Some(good_git::RebaseResume::Skip),
+                RebaseArgs { abort: true, .. } => Some(good_git::RebaseResume::Abort),
+                _ => None,
+            };
+            match (resume, &rebase_args.upstream) {
+                (Some(resume), _) => good_git::rebase_resume(&repo, resume, &mut io::stdout())?,
+                (None, Some(upstream)) => {
+                    let options = good_git::RebaseOptions {
+                        interactive: rebase_args.interactive,
+                    };
+                    good_git::rebase(&repo, upstream, &options, &mut io::stdout())?
+                }
+                (None, None) => unreachable!("clap requires an upstream"),
+            }
+        }
+        Commands::Archive(archive_args) => {
+            let repo = find_repo()?;
+            let format = archive_args.format.unwrap_or_else(|| {
+                match archive_args
+                    .output
+                    .as_ref()
+                    .and_then(|path| path.extension())
+                {
+                    Some(extension) if extension == "zip" => good_git::archive::Format::Zip,
+                    _ => good_git::archive::Format::Tar,
+                }
+            });
+            let options = good_git::ArchiveOptions {
+                format,
+                prefix: archive_args.prefix.clone(),
+            };
+            let mut output: Box<dyn io::Write> = match &archive_args.output {
+                Some(path) => Box::new(io::BufWriter::new(std::fs::File::create(path)?)),
+                None => Box::new(io::BufWriter::new(io::stdout().lock())),
+            };
+            good_git::archive(&repo, &archive_args.tree_ish, &options, &mut output)?;
+        }
+        Commands::Recover(recover_args) => {
+            let repo = find_repo()?;
+            let recovery = match (recover_args.continue_, recover_args.abort) {
+                (true, _) => Some(good_git::journal::Recovery::Forward),
+                (_, true) => Some(good_git::journal::Recovery::Back),
+                _ => None,
+            };
+            good_git::recover(&repo, recovery, &mut io::stdout())?;
+        }
+        Commands::CheckRefFormat(check_ref_format_args) => {
+            good_git::refs::validate_name(&check_ref_format_args.refname)?;
+        }
+        Commands::Stats => {
+            let repo = find_repo()?;
+            good_git::stats(&repo, &mut io::stdout())?;
+        }
+        Commands::Analyze(analyze_args) => {
+            let repo = find_repo()?;
+            good_git::analyze_large_blobs(&repo, analyze_args.large_blobs, &mut io::stdout())?;
         }
     }
     Ok(())
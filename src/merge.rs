@@ -0,0 +1,894 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+};
+
+use crate::{
+    binary::{BinaryClassifier, TextAttribute},
+    config::Config,
+    graph,
+    index::{Index, IndexEntry},
+    merge_file::{self, Favor},
+    object::{self, Object, ObjectType},
+    read_tree::Entry,
+    rename,
+    repo::Repo,
+    status,
+};
+
+/// The labels of the sides of merges of merge bases, like git's.
+const TEMPORARY_LABELS: [&str; 2] = ["Temporary merge branch 1", "Temporary merge branch 2"];
+
+/// The label of a merge base made by merging merge bases, like git's.
+const MERGED_BASES_LABEL: &str = "merged common ancestors";
+
+/// The result of [`merge_trees`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merged {
+    /// The merged tree. Files whose contents conflict have conflict markers,
+    /// and files that a side deleted have the version of the other side.
+    pub tree: String,
+    /// The base, our and their versions of the paths that conflict.
+    pub conflicts: BTreeMap<String, [Option<Entry>; 3]>,
+    /// What happened to the paths that both sides changed, like
+    /// `Auto-merging <path>` and `CONFLICT (content): ...`, by path.
+    pub messages: Vec<String>,
+}
+
+/// The state of a merge stopped before its commit, kept in `.git` like
+/// git's: the commits being merged in `MERGE_HEAD`, the message of the merge
+/// commit in `MERGE_MSG`, `no-ff` in `MERGE_MODE` when a merge commit was
+/// asked for, and the merged tree with its conflict markers in `AUTO_MERGE`.
+///
+/// A squash merge records no commits, and its message is in `SQUASH_MSG`
+/// instead. A cherry-pick records the commit it picks in `CHERRY_PICK_HEAD`
+/// instead of `MERGE_HEAD`, a revert the commit it reverts in `REVERT_HEAD`,
+/// and a rebase the commit it stopped at in `REBASE_HEAD`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    /// The commits being merged, none for a squash merge, a cherry-pick, a
+    /// revert or a rebase.
+    pub merge_heads: Vec<String>,
+    pub message: String,
+    pub no_ff: bool,
+    pub auto_merge: Option<String>,
+    /// The commit being cherry-picked, whose author the commit keeps.
+    pub cherry_pick_head: Option<String>,
+    /// The commit being reverted.
+    pub revert_head: Option<String>,
+    /// The commit a rebase stopped at.
+    pub rebase_head: Option<String>,
+}
+
+impl State {
+    /// Reads the state of the merge in progress, if any.
+    pub fn read(repo: &Repo) -> Result<Option<State>> {
+        let read = |name: &str| match fs::read_to_string(repo.git_dir().join(name)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+        let cherry_pick_head = read("CHERRY_PICK_HEAD")?.map(|hash| hash.trim().to_string());
+        let revert_head = read("REVERT_HEAD")?.map(|hash| hash.trim().to_string());
+        let rebase_head = read("REBASE_HEAD")?.map(|hash| hash.trim().to_string());
+        let (merge_heads, message) = match read("MERGE_HEAD")? {
+            Some(merge_heads) => (
+                merge_heads.lines().map(str::to_string).collect(),
+                read("MERGE_MSG")?,
+            ),
+            None if [&cherry_pick_head, &revert_head, &rebase_head]
+                .iter()
+                .any(|hash| hash.is_some()) =>
+            {
+                (vec![], read("MERGE_MSG")?)
+            }
+            None => match read("SQUASH_MSG")? {
+                Some(message) => (vec![], Some(message)),
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(State {
+            merge_heads,
+            message: message.unwrap_or_default(),
+            no_ff: read("MERGE_MODE")?.is_some_and(|mode| mode.trim() == "no-ff"),
+            auto_merge: read("AUTO_MERGE")?.map(|tree| tree.trim().to_string()),
+            cherry_pick_head,
+            revert_head,
+            rebase_head,
+        }))
+    }
+
+    /// Saves the state, with `MERGE_HEAD`, `CHERRY_PICK_HEAD`, `REVERT_HEAD`
+    /// or `REBASE_HEAD` last so that it's only found once complete.
+    pub fn write(&self, repo: &Repo) -> Result<()> {
+        let git_dir = repo.git_dir();
+        match &self.auto_merge {
+            Some(tree) => fs::write(git_dir.join("AUTO_MERGE"), format!("{tree}\n"))?,
+            None => {
+                let _ = fs::remove_file(git_dir.join("AUTO_MERGE"));
+            }
+        }
+        let picked = [
+            ("CHERRY_PICK_HEAD", &self.cherry_pick_head),
+            ("REVERT_HEAD", &self.revert_head),
+            ("REBASE_HEAD", &self.rebase_head),
+        ];
+        if picked.iter().any(|(_, hash)| hash.is_some()) {
+            fs::write(git_dir.join("MERGE_MSG"), &self.message)?;
+            for (name, hash) in picked {
+                if let Some(hash) = hash {
+                    fs::write(git_dir.join(name), format!("{hash}\n"))?;
+                }
+            }
+            return Ok(());
+        }
+        if self.merge_heads.is_empty() {
+            fs::write(git_dir.join("SQUASH_MSG"), &self.message)?;
+            return Ok(());
+        }
+        fs::write(git_dir.join("MERGE_MSG"), &self.message)?;
+        fs::write(
+            git_dir.join("MERGE_MODE"),
+            if self.no_ff { "no-ff" } else { "" },
+        )?;
+        let mut merge_heads = self.merge_heads.join("\n");
+        merge_heads.push('\n');
+        fs::write(git_dir.join("MERGE_HEAD"), merge_heads)?;
+        Ok(())
+    }
+
+    /// Removes the state once the merge is concluded, `MERGE_HEAD` first.
+    pub fn remove(repo: &Repo) -> Result<()> {
+        let names = [
+            "MERGE_HEAD",
+            "CHERRY_PICK_HEAD",
+            "REVERT_HEAD",
+            "REBASE_HEAD",
+            "MERGE_MSG",
+            "MERGE_MODE",
+            "SQUASH_MSG",
+            "AUTO_MERGE",
+        ];
+        for name in names {
+            match fs::remove_file(repo.git_dir().join(name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merges two commits given their merge bases, like git's `ort` strategy.
+///
+/// Several merge bases are merged first, recursively, into a virtual merge
+/// base labeled `merged common ancestors`, conflicts included. The trees of
+/// the commits are then merged from its tree, see [`merge_trees`].
+pub fn merge_commits(
+    repo: &Repo,
+    bases: &[String],
+    ours: &str,
+    theirs: &str,
+    options: &merge_file::MergeOptions,
+) -> Result<Merged> {
+    let mut options = options.clone();
+    let base = match bases {
+        [] => None,
+        [base] => Some(commit_tree(repo, base)?),
+        _ => {
+            options.labels[1] = Some(MERGED_BASES_LABEL.to_string());
+            Some(merge_bases(repo, bases, &options, 1)?)
+        }
+    };
+    let (ours, theirs) = (commit_tree(repo, ours)?, commit_tree(repo, theirs)?);
+    TreeMerge::new(repo, &options, 0)?.merge(base.as_deref(), &ours, &theirs)
+}
+
+/// Merges the trees `ours` and `theirs` given the tree of their merge base,
+/// like git's `ort` strategy, without touching the index or the working
+/// tree.
+///
+/// Paths that only one side changed take its version, at the path the side
+/// renamed it to, if any, see [`rename::find_renames`]. Files that both sides
+/// changed are merged line by line with `options`, see [`merge_file::merge`],
+/// whose labels name our side, the base and their side in conflict markers
+/// and messages. Files in the way of directories are moved next to them, to
+/// their path followed by `~` and the label of their side.
+pub fn merge_trees(
+    repo: &Repo,
+    base: Option<&str>,
+    ours: &str,
+    theirs: &str,
+    options: &merge_file::MergeOptions,
+) -> Result<Merged> {
+    TreeMerge::new(repo, options, 0)?.merge(base, ours, theirs)
+}
+
+/// Merges merge bases, oldest first, into the tree of a virtual merge base.
+///
+/// These merges are `depth` deep in merges of merge bases, so their conflict
+/// markers are `2 * depth` longer than those of the merges they're for.
+fn merge_bases(
+    repo: &Repo,
+    bases: &[String],
+    options: &merge_file::MergeOptions,
+    depth: usize,
+) -> Result<String> {
+    let Some((first, others)) = bases.split_last() else {
+        return Err(anyhow!("No merge bases to merge"));
+    };
+    let mut merged = vec![first.clone()];
+    let mut tree = commit_tree(repo, first)?;
+    for next in others.iter().rev() {
+        // The merge of the bases so far is a commit whose parents are them.
+        let inner_bases = graph::merge_bases_many(repo, &merged, next)?;
+        let (base, base_label) = match inner_bases.as_slice() {
+            [] => (None, None),
+            [base] => (
+                Some(commit_tree(repo, base)?),
+                Some(object::Abbreviator::from_config(repo)?.abbreviate(base)?),
+            ),
+            _ => (
+                Some(merge_bases(repo, &inner_bases, options, depth + 1)?),
+                Some(MERGED_BASES_LABEL.to_string()),
+            ),
+        };
+        let mut inner_options = options.clone();
+        inner_options.marker_size += 2 * depth;
+        inner_options.labels = [
+            Some(TEMPORARY_LABELS[0].to_string()),
+            base_label,
+            Some(TEMPORARY_LABELS[1].to_string()),
+        ];
+        let next_tree = commit_tree(repo, next)?;
+        tree = TreeMerge::new(repo, &inner_options, depth)?
+            .merge(base.as_deref(), &tree, &next_tree)?
+            .tree;
+        merged.push(next.clone());
+    }
+    Ok(tree)
+}
+
+fn commit_tree(repo: &Repo, hash: &str) -> Result<String> {
+    match Object::from_hash(repo, hash)? {
+        Object::Commit(commit) => Ok(commit.tree),
+        _ => Err(anyhow!("Not a commit: {hash}")),
+    }
+}
+
+/// A merge of trees in progress, see [`merge_trees`]. Sides are numbered
+/// like stages: 0 for the base, 1 for ours and 2 for theirs.
+struct TreeMerge<'a> {
+    repo: &'a Repo,
+    options: &'a merge_file::MergeOptions,
+    classifier: BinaryClassifier,
+    /// How deep the merge is in merges of merge bases, whose conflicts
+    /// other than those of contents take the version of the base.
+    depth: usize,
+    result: BTreeMap<String, Entry>,
+    conflicts: BTreeMap<String, [Option<Entry>; 3]>,
+    messages: BTreeMap<String, Vec<String>>,
+}
+
+impl<'a> TreeMerge<'a> {
+    fn new(
+        repo: &'a Repo,
+        options: &'a merge_file::MergeOptions,
+        depth: usize,
+    ) -> Result<TreeMerge<'a>> {
+        Ok(TreeMerge {
+            repo,
+            options,
+            classifier: BinaryClassifier::from_config(&Config::load(repo)?)?,
+            depth,
+            result: BTreeMap::new(),
+            conflicts: BTreeMap::new(),
+            messages: BTreeMap::new(),
+        })
+    }
+
+    fn merge(mut self, base: Option<&str>, ours: &str, theirs: &str) -> Result<Merged> {
+        let mut files: [BTreeMap<String, Entry>; 3] = Default::default();
+        for (tree, files) in [base, Some(ours), Some(theirs)].into_iter().zip(&mut files) {
+            if let Some(tree) = tree {
+                status::tree_files(self.repo, tree, "", files)?;
+            }
+        }
+        let sides = files.clone();
+
+        // The renames of each side, from the paths it deleted to those it
+        // added.
+        let mut renames: [BTreeMap<String, String>; 2] = Default::default();
+        for (side, renames) in (1..).zip(&mut renames) {
+            let changed = |from: usize, to: usize| -> BTreeMap<String, Entry> {
+                files[from]
+                    .iter()
+                    .filter(|(path, _)| !files[to].contains_key(*path))
+                    .map(|(path, entry)| (path.clone(), entry.clone()))
+                    .collect()
+            };
+            let (deleted, added) = (changed(0, side), changed(side, 0));
+            let min_score = rename::DEFAULT_MIN_SCORE;
+            for rename in rename::find_renames(self.repo, &deleted, &added, min_score)? {
+                renames.insert(rename.old_path, rename.new_path);
+            }
+        }
+        let renamed: BTreeSet<String> = renames.iter().flat_map(|r| r.keys().cloned()).collect();
+        for path in renamed {
+            let [ours, theirs] = renames.each_ref().map(|renames| renames.get(&path));
+            self.merge_renamed(&mut files, &path, ours, theirs)?;
+        }
+
+        let paths: BTreeSet<&String> = files.iter().flat_map(|files| files.keys()).collect();
+        for path in paths {
+            let [base, ours, theirs] = files.each_ref().map(|files| files.get(path));
+            self.merge_path(path, base, ours, theirs)?;
+        }
+        self.move_files_in_the_way(&sides);
+
+        let mut index = Index::default();
+        for (path, (mode, hash)) in &self.result {
+            // The paths are in order, so entries can be pushed as is.
+            index.entries.push(IndexEntry {
+                mode: *mode,
+                hash: hash.clone(),
+                path: path.clone(),
+                ..Default::default()
+            });
+        }
+        Ok(Merged {
+            tree: index.write_tree(self.repo)?,
+            conflicts: self.conflicts,
+            messages: self.messages.into_values().flatten().collect(),
+        })
+    }
+
+    /// Merges the versions of a path that no side renamed.
+    fn merge_path(
+        &mut self,
+        path: &str,
+        base: Option<&Entry>,
+        ours: Option<&Entry>,
+        theirs: Option<&Entry>,
+    ) -> Result<()> {
+        let entry = if ours == theirs || base == theirs {
+            ours.cloned()
+        } else if base == ours {
+            theirs.cloned()
+        } else if let (Some(ours), Some(theirs)) = (ours, theirs) {
+            let (entry, clean) = self.merge_contents(path, [path; 3], base, ours, theirs)?;
+            if !clean {
+                let stages = [base.cloned(), Some(ours.clone()), Some(theirs.clone())];
+                self.conflicts.insert(path.to_string(), stages);
+            }
+            Some(entry)
+        } else {
+            // One side deleted the path and the other changed it, whose
+            // version is kept.
+            let (deleted, modified) = match ours {
+                Some(_) => (self.label(2), self.label(1)),
+                None => (self.label(1), self.label(2)),
+            };
+            self.message(
+                path,
+                format!(
+                    "CONFLICT (modify/delete): {path} deleted in {deleted} and modified in \
+                     {modified}.  Version {modified} of {path} left in tree."
+                ),
+            );
+            if self.depth > 0 {
+                base.cloned()
+            } else {
+                let stages = [base.cloned(), ours.cloned(), theirs.cloned()];
+                self.conflicts.insert(path.to_string(), stages);
+                ours.or(theirs).cloned()
+            }
+        };
+        if let Some(entry) = entry {
+            self.result.insert(path.to_string(), entry);
+        }
+        Ok(())
+    }
+
+    /// Merges the versions of a path of the base that a side renamed, taking
+    /// them out of `files`.
+    fn merge_renamed(
+        &mut self,
+        files: &mut [BTreeMap<String, Entry>; 3],
+        path: &str,
+        ours: Option<&String>,
+        theirs: Option<&String>,
+    ) -> Result<()> {
+        let Some(base) = files[0].remove(path) else {
+            return Ok(());
+        };
+        let (side, new_path) = match (ours, theirs) {
+            (Some(ours_path), Some(theirs_path)) if ours_path != theirs_path => {
+                return self.merge_renamed_twice(files, path, base, ours_path, theirs_path);
+            }
+            (Some(new_path), _) => (1, new_path),
+            (None, Some(new_path)) => (2, new_path),
+            (None, None) => return Ok(()),
+        };
+        let other = 3 - side;
+        let Some(renamed) = files[side].remove(new_path) else {
+            return Ok(());
+        };
+        let kept = match (ours, theirs) {
+            (Some(_), Some(_)) => files[other].remove(new_path),
+            _ => files[other].remove(path),
+        };
+        let Some(kept) = kept else {
+            self.message(
+                new_path,
+                format!(
+                    "CONFLICT (rename/delete): {path} renamed to {new_path} in {}, but deleted \
+                     in {}.",
+                    self.label(side),
+                    self.label(other)
+                ),
+            );
+            if renamed.1 != base.1 {
+                self.message(
+                    new_path,
+                    format!(
+                        "CONFLICT (modify/delete): {new_path} deleted in {} and modified in {}.  \
+                         Version {} of {new_path} left in tree.",
+                        self.label(other),
+                        self.label(side),
+                        self.label(side)
+                    ),
+                );
+            }
+            if self.depth > 0 {
+                self.result.insert(path.to_string(), base);
+            } else {
+                let mut stages = [Some(base), None, None];
+                stages[side] = Some(renamed.clone());
+                self.conflicts.insert(new_path.clone(), stages);
+                self.result.insert(new_path.clone(), renamed);
+            }
+            return Ok(());
+        };
+
+        let mut stages = [Some(base), None, None];
+        stages[side] = Some(renamed);
+        stages[other] = Some(kept);
+        let mut names = [path; 3];
+        names[side] = new_path;
+        if ours.is_some() && theirs.is_some() {
+            names[other] = new_path;
+        }
+        let [base, Some(ours), Some(theirs)] = &stages else {
+            return Ok(());
+        };
+        let (mut entry, clean) =
+            self.merge_contents(new_path, names, base.as_ref(), ours, theirs)?;
+        if !clean {
+            self.conflicts.insert(new_path.clone(), stages.clone());
+        }
+        // The other side added a file where this one renamed to.
+        if let Some(added) = files[other].remove(new_path) {
+            let mut stages = [None, None, None];
+            stages[side] = Some(entry);
+            stages[other] = Some(added);
+            let [_, Some(ours), Some(theirs)] = &stages else {
+                return Ok(());
+            };
+            let (merged, clean) =
+                self.merge_contents(new_path, [new_path; 3], None, ours, theirs)?;
+            entry = merged;
+            if !clean {
+                self.conflicts.insert(new_path.clone(), stages.clone());
+            }
+        }
+        self.result.insert(new_path.clone(), entry);
+        Ok(())
+    }
+
+    /// Merges the versions of a path that both sides renamed, to different
+    /// paths, which both get the merged file.
+    fn merge_renamed_twice(
+        &mut self,
+        files: &mut [BTreeMap<String, Entry>; 3],
+        path: &str,
+        base: Entry,
+        ours_path: &str,
+        theirs_path: &str,
+    ) -> Result<()> {
+        let (Some(ours), Some(theirs)) = (files[1].remove(ours_path), files[2].remove(theirs_path))
+        else {
+            return Ok(());
+        };
+        let names = [path, ours_path, theirs_path];
+        let (entry, _) = self.merge_contents(path, names, Some(&base), &ours, &theirs)?;
+        self.message(
+            path,
+            format!(
+                "CONFLICT (rename/rename): {path} renamed to {ours_path} in {} and to \
+                 {theirs_path} in {}.",
+                self.label(1),
+                self.label(2)
+            ),
+        );
+        if self.depth > 0 {
+            self.result.insert(path.to_string(), base);
+            return Ok(());
+        }
+        self.conflicts
+            .insert(path.to_string(), [Some(base), None, None]);
+        self.conflicts
+            .insert(ours_path.to_string(), [None, Some(ours), None]);
+        self.conflicts
+            .insert(theirs_path.to_string(), [None, None, Some(theirs)]);
+        self.result.insert(ours_path.to_string(), entry.clone());
+        self.result.insert(theirs_path.to_string(), entry);
+        Ok(())
+    }
+
+    /// Moves the files of the result that are in the way of directories to
+    /// their path followed by `~` and the label of their side.
+    fn move_files_in_the_way(&mut self, sides: &[BTreeMap<String, Entry>; 3]) {
+        let directories: BTreeSet<String> = self
+            .result
+            .keys()
+            .flat_map(|path| path.match_indices('/').map(|(i, _)| path[..i].to_string()))
+            .collect();
+        for path in directories {
+            let Some(entry) = self.result.remove(&path) else {
+                continue;
+            };
+            let side = if sides[1].get(&path) == Some(&entry) {
+                1
+            } else {
+                2
+            };
+            let new_path = format!("{path}~{}", self.label(side));
+            self.message(
+                &path,
+                format!(
+                    "CONFLICT (file/directory): directory in the way of {path} from {}; \
+                     moving it to {new_path} instead.",
+                    self.label(side)
+                ),
+            );
+            let stages = self.conflicts.remove(&path).unwrap_or_else(|| {
+                let mut stages = [None, None, None];
+                stages[side] = Some(entry.clone());
+                stages
+            });
+            self.conflicts.insert(new_path.clone(), stages);
+            self.result.insert(new_path, entry);
+        }
+    }
+
+    /// Returns the merged version of a file that both sides changed, and
+    /// whether it merged cleanly. `names` are the paths of the base, our and
+    /// their versions, which conflict markers show when they differ.
+    fn merge_contents(
+        &mut self,
+        path: &str,
+        names: [&str; 3],
+        base: Option<&Entry>,
+        ours: &Entry,
+        theirs: &Entry,
+    ) -> Result<(Entry, bool)> {
+        if ours == theirs || base == Some(theirs) {
+            return Ok((ours.clone(), true));
+        } else if base == Some(ours) {
+            return Ok((theirs.clone(), true));
+        }
+        self.message(path, format!("Auto-merging {path}"));
+        let (entry, clean) = self.merge_files(path, names, base, ours, theirs)?;
+        if !clean {
+            let kind = if base.is_some() { "content" } else { "add/add" };
+            self.message(path, format!("CONFLICT ({kind}): Merge conflict in {path}"));
+        }
+        Ok((entry, clean))
+    }
+
+    /// Merges files line by line. Conflicts of regular files have conflict
+    /// markers, otherwise our version is kept, or the base's in merges of
+    /// merge bases.
+    fn merge_files(
+        &mut self,
+        path: &str,
+        names: [&str; 3],
+        base: Option<&Entry>,
+        ours: &Entry,
+        theirs: &Entry,
+    ) -> Result<(Entry, bool)> {
+        let depth = self.depth;
+        let unmerged = || match (depth, base) {
+            (1.., Some(base)) => (base.clone(), false),
+            _ => (ours.clone(), false),
+        };
+        let is_file = |(mode, _): &Entry| *mode == 0o100644 || *mode == 0o100755;
+        if !is_file(ours) || !is_file(theirs) {
+            return Ok(unmerged());
+        }
+        // The side that changed the mode decides it.
+        let mode = match base {
+            Some((mode, _)) if *mode == ours.0 => theirs.0,
+            _ => ours.0,
+        };
+        let contents = [base, Some(ours), Some(theirs)].map(|entry| match entry {
+            Some((_, hash)) => self.read_blob(hash),
+            None => Ok(vec![]),
+        });
+        let [base_content, ours_content, theirs_content] = contents;
+        let (base_content, ours_content, theirs_content) =
+            (base_content?, ours_content?, theirs_content?);
+
+        let mut options = self.options.clone();
+        if names.iter().any(|name| *name != names[0]) {
+            for (label, name) in options
+                .labels
+                .iter_mut()
+                .zip([names[1], names[0], names[2]])
+            {
+                *label = Some(format!("{}:{name}", label.as_deref().unwrap_or_default()));
+            }
+        }
+        let is_binary = |content: &[u8]| {
+            let size = content.len() as u64;
+            self.classifier
+                .is_binary(content, size, TextAttribute::Unspecified)
+        };
+        if is_binary(&base_content) || is_binary(&ours_content) || is_binary(&theirs_content) {
+            return Ok(match options.favor {
+                Some(Favor::Ours) => ((mode, ours.1.clone()), true),
+                Some(Favor::Theirs) => ((mode, theirs.1.clone()), true),
+                _ if self.depth > 0 => unmerged(),
+                _ => {
+                    let [ours_label, _, theirs_label] = options
+                        .labels
+                        .each_ref()
+                        .map(|label| label.as_deref().unwrap_or_default());
+                    self.message(
+                        path,
+                        format!(
+                            "warning: Cannot merge binary files: {path} \
+                             ({ours_label} vs. {theirs_label})"
+                        ),
+                    );
+                    unmerged()
+                }
+            });
+        }
+        let (content, conflicts) =
+            merge_file::merge(&base_content, &ours_content, &theirs_content, &options);
+        let hash = object::write_checked_object(self.repo, ObjectType::Blob, &content)?;
+        Ok(((mode, hash), conflicts == 0))
+    }
+
+    fn read_blob(&self, hash: &str) -> Result<Vec<u8>> {
+        match Object::from_hash(self.repo, hash)? {
+            Object::Blob(blob) => Ok(blob.content),
+            _ => Err(anyhow!("Not a blob: {hash}")),
+        }
+    }
+
+    /// Returns the label of a side.
+    fn label(&self, side: usize) -> &'a str {
+        let index = [1, 0, 2][side];
+        self.options.labels[index].as_deref().unwrap_or_default()
+    }
+
+    fn message(&mut self, path: &str, message: String) {
+        self.messages
+            .entry(path.to_string())
+            .or_default()
+            .push(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        fs::create_dir_all(repo.git_dir()).unwrap();
+        assert_eq!(State::read(&repo).unwrap(), None);
+
+        let state = State {
+            merge_heads: vec!["a".repeat(40)],
+            message: "Merge branch 'topic'\n\n# Conflicts:\n#\ta.txt\n".to_string(),
+            no_ff: true,
+            auto_merge: Some("b".repeat(40)),
+            cherry_pick_head: None,
+            revert_head: None,
+            rebase_head: None,
+        };
+        state.write(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&state));
+        assert_eq!(
+            fs::read_to_string(repo.git_dir().join("MERGE_HEAD")).unwrap(),
+            format!("{}\n", "a".repeat(40))
+        );
+
+        State::remove(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap(), None);
+        assert!(!repo.git_dir().join("MERGE_MSG").exists());
+
+        let squash = State {
+            merge_heads: vec![],
+            message: "Squashed commit of the following:\n".to_string(),
+            no_ff: false,
+            auto_merge: None,
+            cherry_pick_head: None,
+            revert_head: None,
+            rebase_head: None,
+        };
+        squash.write(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&squash));
+        assert!(!repo.git_dir().join("MERGE_HEAD").exists());
+        State::remove(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap(), None);
+
+        let cherry_pick = State {
+            merge_heads: vec![],
+            message: "Change a.txt\n\n# Conflicts:\n#\ta.txt\n".to_string(),
+            no_ff: false,
+            auto_merge: Some("b".repeat(40)),
+            cherry_pick_head: Some("c".repeat(40)),
+            revert_head: None,
+            rebase_head: None,
+        };
+        cherry_pick.write(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&cherry_pick));
+        assert!(!repo.git_dir().join("SQUASH_MSG").exists());
+        State::remove(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap(), None);
+
+        let revert = State {
+            cherry_pick_head: None,
+            revert_head: Some("c".repeat(40)),
+            ..cherry_pick.clone()
+        };
+        revert.write(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&revert));
+        State::remove(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap(), None);
+
+        let rebase = State {
+            cherry_pick_head: None,
+            rebase_head: Some("c".repeat(40)),
+            ..cherry_pick
+        };
+        rebase.write(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&rebase));
+        State::remove(&repo).unwrap();
+        assert!(!repo.git_dir().join("REBASE_HEAD").exists());
+    }
+
+    #[test]
+    fn test_merge_trees() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let tree = |files: &[(&str, &str)]| {
+            let mut index = Index::default();
+            for (path, content) in files {
+                index.add(IndexEntry {
+                    mode: 0o100644,
+                    hash: object::write_object(&repo, ObjectType::Blob, content.as_bytes())
+                        .unwrap(),
+                    path: path.to_string(),
+                    ..Default::default()
+                });
+            }
+            index.write_tree(&repo).unwrap()
+        };
+        let lines = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let base = tree(&[("a", lines), ("b", "b\n")]);
+        // We rename and change `a`, they change it and add a file where `b`
+        // is, which we make a directory.
+        let ours = tree(&[("c", "1\n2\nthree\n4\n5\n6\n7\n8\n9\n"), ("b/d", "d\n")]);
+        let theirs = tree(&[("a", "1\n2\n3\n4\n5\n6\n7\neight\n9\n"), ("b", "B\n")]);
+        let options = merge_file::MergeOptions {
+            labels: [
+                Some("ours".into()),
+                Some("base".into()),
+                Some("theirs".into()),
+            ],
+            ..Default::default()
+        };
+        let merged = merge_trees(&repo, Some(&base), &ours, &theirs, &options).unwrap();
+        assert_eq!(
+            merged.messages,
+            [
+                "CONFLICT (modify/delete): b deleted in ours and modified in theirs.  \
+                 Version theirs of b left in tree.",
+                "CONFLICT (file/directory): directory in the way of b from theirs; \
+                 moving it to b~theirs instead.",
+                "Auto-merging c",
+            ]
+        );
+        let expected = tree(&[
+            ("b/d", "d\n"),
+            ("b~theirs", "B\n"),
+            ("c", "1\n2\nthree\n4\n5\n6\n7\neight\n9\n"),
+        ]);
+        assert_eq!(merged.tree, expected);
+        assert_eq!(merged.conflicts.keys().collect::<Vec<_>>(), ["b~theirs"]);
+
+        // Binary files aren't merged, and ours is left in the tree.
+        let base = tree(&[("a.bin", "\0base")]);
+        let ours = tree(&[("a.bin", "\0ours")]);
+        let theirs = tree(&[("a.bin", "\0theirs")]);
+        let merged = merge_trees(&repo, Some(&base), &ours, &theirs, &options).unwrap();
+        assert_eq!(
+            merged.messages,
+            [
+                "Auto-merging a.bin",
+                "warning: Cannot merge binary files: a.bin (ours vs. theirs)",
+                "CONFLICT (content): Merge conflict in a.bin",
+            ]
+        );
+        assert_eq!(merged.tree, ours);
+    }
+
+    #[test]
+    fn test_merge_commits_with_merge_bases() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let mut time = 0;
+        let mut commit = |content: &str, parents: &[&String]| {
+            let blob = object::write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            let mut index = Index::default();
+            index.add(IndexEntry {
+                mode: 0o100644,
+                hash: blob,
+                path: "a".to_string(),
+                ..Default::default()
+            });
+            time += 1;
+            let commit = object::Commit {
+                tree: index.write_tree(&repo).unwrap(),
+                parents: parents.iter().map(|parent| parent.to_string()).collect(),
+                author: format!("A <a@example.com> {time} +0000"),
+                committer: format!("A <a@example.com> {time} +0000"),
+                message: "Commit\n".to_string(),
+                ..Default::default()
+            };
+            object::write_object(&repo, ObjectType::Commit, &commit.to_bytes()).unwrap()
+        };
+        // Both sides merged both sides of a conflict, each resolving it
+        // its own way.
+        let root = commit("1\n2\n3\n4\n5\n6\n7\n8\n9\n", &[]);
+        let left = commit("1\nleft\n3\n4\n5\n6\n7\n8\n9\n", &[&root]);
+        let right = commit("1\nright\n3\n4\n5\n6\n7\n8\n9\n", &[&root]);
+        let ours = commit("1\nleft\n3\n4\n5\n6\n7\n8\nnine\n", &[&left, &right]);
+        let theirs = commit("1\nright\n3\n4\nfive\n6\n7\n8\n9\n", &[&right, &left]);
+
+        let bases = graph::merge_bases(&repo, &ours, &theirs).unwrap();
+        assert_eq!(bases, [right.clone(), left.clone()]);
+        let options = merge_file::MergeOptions {
+            labels: [Some("ours".into()), None, Some("theirs".into())],
+            ..Default::default()
+        };
+        let merged = merge_commits(&repo, &bases, &ours, &theirs, &options).unwrap();
+        assert_eq!(
+            merged.messages,
+            ["Auto-merging a", "CONFLICT (content): Merge conflict in a"]
+        );
+        let Object::Tree(tree) = Object::from_hash(&repo, &merged.tree).unwrap() else {
+            panic!("Not a tree");
+        };
+        let Object::Blob(blob) = Object::from_hash(&repo, &tree.files[0].hash).unwrap() else {
+            panic!("Not a blob");
+        };
+        // The virtual merge base has the conflict, with longer markers, so
+        // the changes of both sides to it conflict too.
+        assert_eq!(
+            String::from_utf8(blob.content).unwrap(),
+            "1\n<<<<<<< ours\nleft\n=======\nright\n>>>>>>> theirs\n3\n4\nfive\n6\n7\n8\nnine\n"
+        );
+    }
+}
@@ -0,0 +1,717 @@
+use anyhow::{anyhow, Result};
+
+use crate::{config::Config, xdiff};
+
+/// The length of conflict markers unless told otherwise.
+pub const DEFAULT_MARKER_SIZE: usize = 7;
+
+/// How conflicts are shown, like git's `merge.conflictStyle`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictStyle {
+    /// Our and their lines, between `<<<<<<<`, `=======` and `>>>>>>>`.
+    #[default]
+    Merge,
+    /// The lines of the base too, after a `|||||||` marker. Conflicts aren't
+    /// made smaller, so that the base lines make sense.
+    Diff3,
+    /// Like `Diff3`, but with the lines both sides start or end with moved
+    /// out of conflicts.
+    ZealousDiff3,
+}
+
+impl ConflictStyle {
+    /// Returns the style named `style` in the config.
+    pub fn parse(style: &str) -> Option<ConflictStyle> {
+        match style {
+            "merge" => Some(ConflictStyle::Merge),
+            "diff3" => Some(ConflictStyle::Diff3),
+            "zdiff3" => Some(ConflictStyle::ZealousDiff3),
+            _ => None,
+        }
+    }
+
+    /// Returns the style of `merge.conflictStyle`.
+    pub fn from_config(config: &Config) -> Result<ConflictStyle> {
+        match config.get("merge.conflictStyle") {
+            Some(value) => ConflictStyle::parse(value)
+                .ok_or_else(|| anyhow!("unknown style '{value}' given for 'merge.conflictstyle'")),
+            None => Ok(ConflictStyle::default()),
+        }
+    }
+}
+
+/// How hard the merge tries to make conflicts smaller, like git's merge
+/// levels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Changes of both sides to overlapping lines conflict, even if they're
+    /// the same.
+    Minimal,
+    /// Only different changes to the same lines conflict.
+    Eager,
+    /// The lines both sides changed the same way are moved out of conflicts,
+    /// and conflicts less than 4 lines apart are joined.
+    #[default]
+    Zealous,
+    /// Like `Zealous`, also joining conflicts further apart when the lines
+    /// between them have no letters or digits, like `git merge-file`.
+    ZealousAlnum,
+}
+
+/// Which side resolves conflicts, like `--ours`, `--theirs` and `--union`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Favor {
+    Ours,
+    Theirs,
+    /// Both sides, ours first.
+    Union,
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    pub level: Level,
+    pub style: ConflictStyle,
+    /// Resolve conflicts with a side instead of leaving markers.
+    pub favor: Option<Favor>,
+    pub marker_size: usize,
+    /// The names after the markers of our side, of the base and of their
+    /// side.
+    pub labels: [Option<String>; 3],
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            level: Level::default(),
+            style: ConflictStyle::default(),
+            favor: None,
+            marker_size: DEFAULT_MARKER_SIZE,
+            labels: [None, None, None],
+        }
+    }
+}
+
+/// What a part of the merge takes, like the modes of git's `xdmerge_t`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resolution {
+    Conflict,
+    /// Only we changed these lines.
+    Ours,
+    /// Only they changed these lines.
+    Theirs,
+    /// Both sides' lines, from `--union`.
+    Union,
+    /// Both sides changed these lines the same way, found when refining
+    /// conflicts.
+    Same,
+}
+
+/// A part of the merge, with where its lines are in the base (`i0`), in our
+/// version (`i1`) and in theirs (`i2`), and how many there are.
+#[derive(Debug, Clone, Copy)]
+struct Hunk {
+    resolution: Resolution,
+    i0: isize,
+    chg0: isize,
+    i1: isize,
+    chg1: isize,
+    i2: isize,
+    chg2: isize,
+}
+
+/// A run of lines changed from `old` (`i1`) to `new` (`i2`).
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    i1: isize,
+    chg1: isize,
+    i2: isize,
+    chg2: isize,
+}
+
+/// Merges the changes from `base` to `ours` and from `base` to `theirs`,
+/// like git's `xdl_merge`, so that results and conflicts are the same as
+/// git's.
+///
+/// Returns the merged content, with markers around the conflicts unless
+/// a side is favored, and the number of conflicts.
+pub fn merge(base: &[u8], ours: &[u8], theirs: &[u8], options: &MergeOptions) -> (Vec<u8>, usize) {
+    let [base, ours_lines, theirs_lines] = [base, ours, theirs].map(split_lines);
+    let ours_changes = changes(&base, &ours_lines);
+    let theirs_changes = changes(&base, &theirs_lines);
+    if ours_changes.is_empty() {
+        return (theirs.to_vec(), 0);
+    }
+    if theirs_changes.is_empty() {
+        return (ours.to_vec(), 0);
+    }
+    let files = Files {
+        base,
+        ours: ours_lines,
+        theirs: theirs_lines,
+    };
+
+    // The base lines shown with diff3 wouldn't match smaller conflicts.
+    let level = match options.style {
+        ConflictStyle::Diff3 => options.level.min(Level::Eager),
+        _ => options.level,
+    };
+    let mut hunks = hunks(&files, &ours_changes, &theirs_changes, level);
+    if options.style == ConflictStyle::ZealousDiff3 {
+        trim_conflicts(&files, &mut hunks);
+    } else if level >= Level::Zealous {
+        hunks = refine_conflicts(&files, hunks);
+        simplify_non_conflicts(&files, &mut hunks, level > Level::Zealous);
+    }
+
+    let mut merged = vec![];
+    let mut conflicts = 0;
+    let mut i = 0;
+    for hunk in &mut hunks {
+        if let (Resolution::Conflict, Some(favor)) = (hunk.resolution, options.favor) {
+            hunk.resolution = match favor {
+                Favor::Ours => Resolution::Ours,
+                Favor::Theirs => Resolution::Theirs,
+                Favor::Union => Resolution::Union,
+            };
+        }
+        match hunk.resolution {
+            Resolution::Conflict => {
+                conflicts += 1;
+                write_conflict(&files, hunk, i, options, &mut merged);
+            }
+            // The lines are the same as ours.
+            Resolution::Same => continue,
+            resolution => {
+                copy(&files.ours, i, hunk.i1 - i, false, false, &mut merged);
+                let union = resolution == Resolution::Union;
+                if resolution != Resolution::Theirs {
+                    let needs_cr = needs_cr(&files, hunk);
+                    copy(
+                        &files.ours,
+                        hunk.i1,
+                        hunk.chg1,
+                        needs_cr,
+                        union,
+                        &mut merged,
+                    );
+                }
+                if resolution != Resolution::Ours {
+                    copy(&files.theirs, hunk.i2, hunk.chg2, false, false, &mut merged);
+                }
+            }
+        }
+        i = hunk.i1 + hunk.chg1;
+    }
+    let rest = files.ours.len() as isize - i;
+    copy(&files.ours, i, rest, false, false, &mut merged);
+    (merged, conflicts)
+}
+
+/// The lines of the three versions, with their line ends.
+struct Files<'a> {
+    base: Vec<&'a [u8]>,
+    ours: Vec<&'a [u8]>,
+    theirs: Vec<&'a [u8]>,
+}
+
+fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Returns the runs of lines changed from `old` to `new`, in order.
+fn changes(old: &[&[u8]], new: &[&[u8]]) -> Vec<Change> {
+    let (removed, added) = xdiff::diff(old, new, false);
+    let mut changes = vec![];
+    let (mut i1, mut i2) = (0, 0);
+    while i1 < old.len() || i2 < new.len() {
+        if removed.get(i1) != Some(&true) && added.get(i2) != Some(&true) {
+            i1 += 1;
+            i2 += 1;
+            continue;
+        }
+        let (start1, start2) = (i1, i2);
+        while removed.get(i1) == Some(&true) {
+            i1 += 1;
+        }
+        while added.get(i2) == Some(&true) {
+            i2 += 1;
+        }
+        changes.push(Change {
+            i1: start1 as isize,
+            chg1: (i1 - start1) as isize,
+            i2: start2 as isize,
+            chg2: (i2 - start2) as isize,
+        });
+    }
+    changes
+}
+
+/// Goes through our and their changes in order, taking those only one side
+/// made and making conflicts of those overlapping, like git's
+/// `xdl_do_merge`.
+fn hunks(files: &Files, ours: &[Change], theirs: &[Change], level: Level) -> Vec<Hunk> {
+    let mut hunks = vec![];
+    let (mut a, mut b) = (0, 0);
+    while let (Some(x1), Some(x2)) = (ours.get(a), theirs.get(b)) {
+        if x1.i1 + x1.chg1 < x2.i1 {
+            let hunk = Hunk {
+                resolution: Resolution::Ours,
+                i0: x1.i1,
+                chg0: x1.chg1,
+                i1: x1.i2,
+                chg1: x1.chg2,
+                i2: x2.i2 - x2.i1 + x1.i1,
+                chg2: x1.chg1,
+            };
+            append(&mut hunks, hunk);
+            a += 1;
+            continue;
+        }
+        if x2.i1 + x2.chg1 < x1.i1 {
+            let hunk = Hunk {
+                resolution: Resolution::Theirs,
+                i0: x2.i1,
+                chg0: x2.chg1,
+                i1: x1.i2 - x1.i1 + x2.i1,
+                chg1: x2.chg1,
+                i2: x2.i2,
+                chg2: x2.chg2,
+            };
+            append(&mut hunks, hunk);
+            b += 1;
+            continue;
+        }
+        let same = level != Level::Minimal
+            && x1.i1 == x2.i1
+            && x1.chg1 == x2.chg1
+            && x1.chg2 == x2.chg2
+            && lines(&files.ours, x1.i2, x1.chg2) == lines(&files.theirs, x2.i2, x2.chg2);
+        if !same {
+            // The conflict spans both changes, with the lines around the
+            // shorter one.
+            let off = x1.i1 - x2.i1;
+            let ffo = off + x1.chg1 - x2.chg1;
+            let (mut i0, mut i1, mut i2) = (x1.i1, x1.i2, x2.i2);
+            if off > 0 {
+                i0 -= off;
+                i1 -= off;
+            } else {
+                i2 += off;
+            }
+            let mut chg0 = x1.i1 + x1.chg1 - i0;
+            let mut chg1 = x1.i2 + x1.chg2 - i1;
+            let mut chg2 = x2.i2 + x2.chg2 - i2;
+            if ffo < 0 {
+                chg0 -= ffo;
+                chg1 -= ffo;
+            } else {
+                chg2 += ffo;
+            }
+            let hunk = Hunk {
+                resolution: Resolution::Conflict,
+                i0,
+                chg0,
+                i1,
+                chg1,
+                i2,
+                chg2,
+            };
+            append(&mut hunks, hunk);
+        }
+        let end1 = x1.i1 + x1.chg1;
+        let end2 = x2.i1 + x2.chg1;
+        if end1 >= end2 {
+            b += 1;
+        }
+        if end2 >= end1 {
+            a += 1;
+        }
+    }
+    let (base, ours_len, theirs_len) = (
+        files.base.len() as isize,
+        files.ours.len() as isize,
+        files.theirs.len() as isize,
+    );
+    for x1 in &ours[a..] {
+        let hunk = Hunk {
+            resolution: Resolution::Ours,
+            i0: x1.i1,
+            chg0: x1.chg1,
+            i1: x1.i2,
+            chg1: x1.chg2,
+            i2: x1.i1 + theirs_len - base,
+            chg2: x1.chg1,
+        };
+        append(&mut hunks, hunk);
+    }
+    for x2 in &theirs[b..] {
+        let hunk = Hunk {
+            resolution: Resolution::Theirs,
+            i0: x2.i1,
+            chg0: x2.chg1,
+            i1: x2.i1 + ours_len - base,
+            chg1: x2.chg1,
+            i2: x2.i2,
+            chg2: x2.chg2,
+        };
+        append(&mut hunks, hunk);
+    }
+    hunks
+}
+
+/// Adds a hunk, or extends the last one if they touch, making it a conflict
+/// if they resolve differently.
+fn append(hunks: &mut Vec<Hunk>, hunk: Hunk) {
+    match hunks.last_mut() {
+        Some(last) if hunk.i1 <= last.i1 + last.chg1 || hunk.i2 <= last.i2 + last.chg2 => {
+            if hunk.resolution != last.resolution {
+                last.resolution = Resolution::Conflict;
+            }
+            last.chg0 = hunk.i0 + hunk.chg0 - last.i0;
+            last.chg1 = hunk.i1 + hunk.chg1 - last.i1;
+            last.chg2 = hunk.i2 + hunk.chg2 - last.i2;
+        }
+        _ => hunks.push(hunk),
+    }
+}
+
+/// Makes conflicts smaller by diffing our lines with theirs: the lines they
+/// have in common are taken out, which may split conflicts, and conflicts
+/// where both sides are the same go away.
+///
+/// Like git, the base lines of the conflicts aren't updated, which is why
+/// this isn't done with diff3.
+fn refine_conflicts(files: &Files, hunks: Vec<Hunk>) -> Vec<Hunk> {
+    let mut refined = vec![];
+    for mut hunk in hunks {
+        if hunk.resolution != Resolution::Conflict || hunk.chg1 == 0 || hunk.chg2 == 0 {
+            refined.push(hunk);
+            continue;
+        }
+        let changes = changes(
+            lines(&files.ours, hunk.i1, hunk.chg1),
+            lines(&files.theirs, hunk.i2, hunk.chg2),
+        );
+        if changes.is_empty() {
+            hunk.resolution = Resolution::Same;
+            refined.push(hunk);
+            continue;
+        }
+        for change in changes {
+            refined.push(Hunk {
+                i1: hunk.i1 + change.i1,
+                chg1: change.chg1,
+                i2: hunk.i2 + change.i2,
+                chg2: change.chg2,
+                ..hunk
+            });
+        }
+    }
+    refined
+}
+
+/// Joins conflicts with less than 4 lines between them, which is shorter
+/// than showing them apart, or with `alnum` those with only lines without
+/// letters or digits between them.
+fn simplify_non_conflicts(files: &Files, hunks: &mut Vec<Hunk>, alnum: bool) {
+    let mut m = 0;
+    while m + 1 < hunks.len() {
+        let (hunk, next) = (hunks[m], hunks[m + 1]);
+        let begin = hunk.i1 + hunk.chg1;
+        let end = next.i1;
+        let has_alnum = || {
+            lines(&files.ours, begin, end - begin)
+                .iter()
+                .any(|line| line.iter().any(u8::is_ascii_alphanumeric))
+        };
+        if hunk.resolution != Resolution::Conflict
+            || next.resolution != Resolution::Conflict
+            || (end - begin > 3 && (!alnum || has_alnum()))
+        {
+            m += 1;
+        } else {
+            hunks[m].chg1 = next.i1 + next.chg1 - hunk.i1;
+            hunks[m].chg2 = next.i2 + next.chg2 - hunk.i2;
+            hunks.remove(m + 1);
+        }
+    }
+}
+
+/// Takes the lines both sides start or end with out of conflicts, for
+/// zdiff3.
+fn trim_conflicts(files: &Files, hunks: &mut [Hunk]) {
+    for hunk in hunks {
+        if hunk.resolution != Resolution::Conflict {
+            continue;
+        }
+        while hunk.chg1 > 0
+            && hunk.chg2 > 0
+            && files.ours[hunk.i1 as usize] == files.theirs[hunk.i2 as usize]
+        {
+            hunk.chg1 -= 1;
+            hunk.chg2 -= 1;
+            hunk.i1 += 1;
+            hunk.i2 += 1;
+        }
+        while hunk.chg1 > 0
+            && hunk.chg2 > 0
+            && files.ours[(hunk.i1 + hunk.chg1 - 1) as usize]
+                == files.theirs[(hunk.i2 + hunk.chg2 - 1) as usize]
+        {
+            hunk.chg1 -= 1;
+            hunk.chg2 -= 1;
+        }
+    }
+}
+
+/// Writes our lines up to a conflict, then the conflict between markers.
+fn write_conflict(files: &Files, hunk: &Hunk, i: isize, options: &MergeOptions, out: &mut Vec<u8>) {
+    let needs_cr = needs_cr(files, hunk);
+    let marker = |out: &mut Vec<u8>, c: u8, label: Option<&str>| {
+        out.extend(std::iter::repeat(c).take(options.marker_size));
+        if let Some(label) = label {
+            out.push(b' ');
+            out.extend_from_slice(label.as_bytes());
+        }
+        if needs_cr {
+            out.push(b'\r');
+        }
+        out.push(b'\n');
+    };
+    let [ours_label, base_label, theirs_label] = options.labels.each_ref().map(Option::as_deref);
+
+    copy(&files.ours, i, hunk.i1 - i, false, false, out);
+    marker(out, b'<', ours_label);
+    copy(&files.ours, hunk.i1, hunk.chg1, needs_cr, true, out);
+    if options.style != ConflictStyle::Merge {
+        marker(out, b'|', base_label);
+        copy(&files.base, hunk.i0, hunk.chg0, needs_cr, true, out);
+    }
+    marker(out, b'=', None);
+    copy(&files.theirs, hunk.i2, hunk.chg2, needs_cr, true, out);
+    marker(out, b'>', theirs_label);
+}
+
+/// Returns `count` lines from the `i`th.
+fn lines<'a, 'b>(lines: &'a [&'b [u8]], i: isize, count: isize) -> &'a [&'b [u8]] {
+    &lines[i as usize..(i + count) as usize]
+}
+
+/// Appends `count` lines from the `i`th, with a line end after the last one
+/// if it has none and `add_newline`.
+fn copy(
+    from: &[&[u8]],
+    i: isize,
+    count: isize,
+    needs_cr: bool,
+    add_newline: bool,
+    out: &mut Vec<u8>,
+) {
+    if count < 1 {
+        return;
+    }
+    let lines = lines(from, i, count);
+    for line in lines {
+        out.extend_from_slice(line);
+    }
+    if add_newline && !lines[lines.len() - 1].ends_with(b"\n") {
+        if needs_cr {
+            out.push(b'\r');
+        }
+        out.push(b'\n');
+    }
+}
+
+/// Returns whether the lines added around a hunk should end with CRLF: if
+/// the lines before it on both sides do, or else the first ones, and the
+/// first line of the base.
+fn needs_cr(files: &Files, hunk: &Hunk) -> bool {
+    let before = |i: isize| (i - 1).max(0) as usize;
+    let mut needs_cr = ends_with_crlf(&files.ours, before(hunk.i1));
+    if needs_cr != Some(false) {
+        needs_cr = ends_with_crlf(&files.theirs, before(hunk.i2));
+    }
+    if needs_cr != Some(false) {
+        needs_cr = ends_with_crlf(&files.base, 0);
+    }
+    needs_cr == Some(true)
+}
+
+/// Returns whether the `i`th line ends with CRLF, going by the line before
+/// if it's the last line and has no line end, `None` if that can't be
+/// told.
+fn ends_with_crlf(lines: &[&[u8]], i: usize) -> Option<bool> {
+    if i + 1 < lines.len() {
+        return Some(lines[i].ends_with(b"\r\n"));
+    }
+    let line = lines.get(i)?;
+    if line.ends_with(b"\n") {
+        return Some(line.ends_with(b"\r\n"));
+    }
+    match i {
+        0 => None,
+        _ => Some(lines[i - 1].ends_with(b"\r\n")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge_str(base: &str, ours: &str, theirs: &str, options: &MergeOptions) -> (String, usize) {
+        let (merged, conflicts) =
+            merge(base.as_bytes(), ours.as_bytes(), theirs.as_bytes(), options);
+        (String::from_utf8(merged).unwrap(), conflicts)
+    }
+
+    #[test]
+    fn test_merge() {
+        let labels = [
+            Some("ours".to_string()),
+            Some("base".to_string()),
+            Some("theirs".to_string()),
+        ];
+        let options = MergeOptions {
+            labels: labels.clone(),
+            ..Default::default()
+        };
+        let base = "1\n2\n3\n4\n5\n6\n7\n8\n";
+
+        // Changes to different lines merge cleanly.
+        assert_eq!(
+            merge_str(
+                base,
+                "1\nTWO\n3\n4\n5\n6\n7\n8\n",
+                "1\n2\n3\n4\n5\n6\n7\nEIGHT\n",
+                &options
+            ),
+            ("1\nTWO\n3\n4\n5\n6\n7\nEIGHT\n".to_string(), 0)
+        );
+        // So do the same changes.
+        assert_eq!(
+            merge_str(
+                base,
+                "1\n2\nx\n4\n5\n6\n7\n8\n",
+                "1\n2\nx\n4\n5\n6\n7\n8\n9\n",
+                &options
+            ),
+            ("1\n2\nx\n4\n5\n6\n7\n8\n9\n".to_string(), 0)
+        );
+
+        let ours = "1\nzwei\n3\n4\n5\n6\n7\n8\n9\n";
+        let theirs = "1\nTWO\n3\n4\n5\n6\n7\nEIGHT\n";
+        assert_eq!(
+            merge_str(base, ours, theirs, &options),
+            (
+                "1\n<<<<<<< ours\nzwei\n=======\nTWO\n>>>>>>> theirs\n3\n4\n5\n6\n7\n\
+                 <<<<<<< ours\n8\n9\n=======\nEIGHT\n>>>>>>> theirs\n"
+                    .to_string(),
+                2
+            )
+        );
+        let diff3 = MergeOptions {
+            style: ConflictStyle::Diff3,
+            marker_size: 3,
+            labels: labels.clone(),
+            ..Default::default()
+        };
+        assert_eq!(
+            merge_str(base, ours, theirs, &diff3),
+            (
+                "1\n<<< ours\nzwei\n||| base\n2\n===\nTWO\n>>> theirs\n3\n4\n5\n6\n7\n\
+                 <<< ours\n8\n9\n||| base\n8\n===\nEIGHT\n>>> theirs\n"
+                    .to_string(),
+                2
+            )
+        );
+        let union = MergeOptions {
+            favor: Some(Favor::Union),
+            ..Default::default()
+        };
+        assert_eq!(
+            merge_str(base, ours, theirs, &union),
+            ("1\nzwei\nTWO\n3\n4\n5\n6\n7\n8\n9\nEIGHT\n".to_string(), 0)
+        );
+        let theirs_favored = MergeOptions {
+            favor: Some(Favor::Theirs),
+            ..Default::default()
+        };
+        assert_eq!(
+            merge_str(base, ours, theirs, &theirs_favored),
+            (theirs.to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn test_merge_refines_conflicts() {
+        // The lines both sides added are taken out of the conflict.
+        let base = "a\nb\nc\n";
+        let ours = "a\nx\n1\n2\n3\n4\ny\nc\n";
+        let theirs = "a\nX\n1\n2\n3\n4\nY\nc\n";
+        let options = MergeOptions::default();
+        assert_eq!(
+            merge_str(base, ours, theirs, &options),
+            (
+                "a\n<<<<<<<\nx\n=======\nX\n>>>>>>>\n1\n2\n3\n4\n\
+                 <<<<<<<\ny\n=======\nY\n>>>>>>>\nc\n"
+                    .to_string(),
+                2
+            )
+        );
+        // Unless there are less than 4 of them.
+        assert_eq!(
+            merge_str(base, "a\nx\nsame\ny\nc\n", "a\nX\nsame\nY\nc\n", &options),
+            (
+                "a\n<<<<<<<\nx\nsame\ny\n=======\nX\nsame\nY\n>>>>>>>\nc\n".to_string(),
+                1
+            )
+        );
+        // Lines without letters or digits between conflicts are moved into
+        // them with `ZealousAlnum`.
+        let base = "a\n{\n}\n{\n}\nb\n";
+        let ours = "x\n{\n}\n{\n}\ny\n";
+        let theirs = "X\n{\n}\n{\n}\nY\n";
+        let alnum = MergeOptions {
+            level: Level::ZealousAlnum,
+            ..Default::default()
+        };
+        assert_eq!(merge_str(base, ours, theirs, &options).1, 2);
+        assert_eq!(
+            merge_str(base, ours, theirs, &alnum),
+            (
+                "<<<<<<<\nx\n{\n}\n{\n}\ny\n=======\nX\n{\n}\n{\n}\nY\n>>>>>>>\n".to_string(),
+                1
+            )
+        );
+
+        let zdiff3 = MergeOptions {
+            style: ConflictStyle::ZealousDiff3,
+            ..Default::default()
+        };
+        assert_eq!(
+            merge_str(
+                "a\nb\nc\n",
+                "a\nsame\nx\nend\nc\n",
+                "a\nsame\nX\nend\nc\n",
+                &zdiff3
+            )
+            .0,
+            "a\nsame\n<<<<<<<\nx\n|||||||\nb\n=======\nX\n>>>>>>>\nend\nc\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_line_ends() {
+        let options = MergeOptions::default();
+        // Markers end like the lines around them.
+        assert_eq!(
+            merge_str("a\r\nb\r\n", "a\r\nx\r\n", "a\r\ny\r\n", &options).0,
+            "a\r\n<<<<<<<\r\nx\r\n=======\r\ny\r\n>>>>>>>\r\n"
+        );
+        // Lines without line ends get one before a marker.
+        assert_eq!(
+            merge_str("a\nb", "a\nx", "a\ny", &options).0,
+            "a\n<<<<<<<\nx\n=======\ny\n>>>>>>>\n"
+        );
+    }
+}
@@ -1,9 +1,21 @@
 use anyhow::{anyhow, Context, Result};
 use flate2::read::ZlibDecoder;
 use sha1::{Digest, Sha1};
-use std::{fs, io::prelude::*};
-
-use crate::repo::Repo;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs,
+    io::{self, prelude::*},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{config::Config, fsck, refs, repo::Repo};
+
+/// The default and minimum lengths of abbreviated hashes.
+const DEFAULT_ABBREV_LEN: usize = 7;
+const MIN_ABBREV_LEN: usize = 4;
 
 #[derive(Debug)]
 pub struct Blob {
@@ -34,6 +46,32 @@ impl Tree {
     pub fn new(files: Vec<File>) -> Tree {
         Tree { files }
     }
+
+    /// Serializes the tree without the object header.
+    ///
+    /// Entries are sorted the way git expects: by name, with trees compared
+    /// as if their name ended with a `/`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let sort_key = |file: &File| {
+            let mut key = file.name.as_bytes().to_vec();
+            if file.object_type() == ObjectType::Tree {
+                key.push(b'/');
+            }
+            key
+        };
+        let mut files: Vec<&File> = self.files.iter().collect();
+        files.sort_by_cached_key(|file| sort_key(file));
+
+        let mut s = vec![];
+        for file in files {
+            s.extend(file.mode.as_bytes());
+            s.push(b' ');
+            s.extend(file.name.as_bytes());
+            s.push(0);
+            s.extend(hex::decode(&file.hash).expect("tree entries have valid hashes"));
+        }
+        s
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -60,15 +98,24 @@ impl File {
             _ => "unknown",
         }
     }
+
+    /// Returns the type of the object the entry points to. Submodules point
+    /// to commits.
+    pub fn object_type(&self) -> ObjectType {
+        match self.mode.as_str() {
+            "40000" => ObjectType::Tree,
+            "160000" => ObjectType::Commit,
+            _ => ObjectType::Blob,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct Commit {
     // Git seems to only consider the following standard headers:
     // https://github.com/git/git/blob/7b0defb3915eaa0bd118f0996e8c00b4eb2dc1ca/commit.c#L1442
-    // TOOD: support merge commits.
     pub tree: String,
-    pub parent: String,
+    pub parents: Vec<String>,
     pub author: String,
     pub committer: String,
     pub encoding: String,
@@ -76,11 +123,90 @@ pub struct Commit {
     pub message: String,
 }
 
+impl Commit {
+    /// Serializes the commit without the object header.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut s = format!("tree {}\n", self.tree);
+        for parent in &self.parents {
+            s.push_str(&format!("parent {parent}\n"));
+        }
+        s.push_str(&format!("author {}\n", self.author));
+        s.push_str(&format!("committer {}\n", self.committer));
+        if !self.encoding.is_empty() {
+            s.push_str(&format!("encoding {}\n", self.encoding));
+        }
+        s.push('\n');
+        s.push_str(&self.message);
+        s.into_bytes()
+    }
+
+    /// Returns the commit date, in seconds since the epoch, or 0 if the
+    /// committer line is malformed.
+    pub fn committer_timestamp(&self) -> i64 {
+        let mut fields = self.committer.rsplitn(3, ' ');
+        fields.next();
+        fields
+            .next()
+            .and_then(|timestamp| timestamp.parse().ok())
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Tag {
+    pub object: String,
+    pub object_type: String,
+    pub tag: String,
+    pub tagger: String,
+
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ObjectType {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+}
+
+impl ObjectType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectType::Blob => "blob",
+            ObjectType::Tree => "tree",
+            ObjectType::Commit => "commit",
+            ObjectType::Tag => "tag",
+        }
+    }
+}
+
+impl std::str::FromStr for ObjectType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<ObjectType> {
+        match s {
+            "blob" => Ok(ObjectType::Blob),
+            "tree" => Ok(ObjectType::Tree),
+            "commit" => Ok(ObjectType::Commit),
+            "tag" => Ok(ObjectType::Tag),
+            _ => Err(anyhow!("Unknown object type")),
+        }
+    }
+}
+
+impl std::fmt::Display for ObjectType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug)]
 pub enum Object {
     Blob(Blob),
     Tree(Tree),
     Commit(Commit),
+    Tag(Tag),
 }
 
 impl Object {
@@ -103,16 +229,23 @@ impl Object {
                 let mut files = vec![];
                 while !content.is_empty() {
                     let mut mode = vec![];
-                    let mode_size = content
+                    content
                         .read_until(b' ', &mut mode)
                         .context("Failed to read mode")?;
-                    let mode = std::str::from_utf8(&mode[..mode_size - 1])?;
+                    if mode.pop() != Some(b' ') {
+                        return Err(anyhow!("Failed to read mode"));
+                    }
+                    let mode = std::str::from_utf8(&mode)?;
 
                     let mut name = vec![];
-                    let name_size = content
+                    content
                         .read_until(b'\0', &mut name)
                         .context("Failed to read file name")?;
-                    let name = std::str::from_utf8(&name[..name_size - 1])?;
+                    if name.pop() != Some(b'\0') {
+                        return Err(anyhow!("Failed to read file name"));
+                    }
+                    let name = std::str::from_utf8(&name)?;
+                    validate_tree_entry_name(name)?;
 
                     let mut hash = [0_u8; 20];
                     content
@@ -152,7 +285,9 @@ impl Object {
                     if key == "tree" {
                         commit.tree = value;
                     } else if key == "parent" {
-                        commit.parent = value;
+                        if !value.is_empty() {
+                            commit.parents.push(value);
+                        }
                     } else if key == "author" {
                         commit.author = value;
                     } else if key == "committer" {
@@ -166,6 +301,35 @@ impl Object {
 
                 Ok(Object::Commit(commit))
             }
+            "tag" => {
+                let content_str = std::str::from_utf8(content)?;
+                let mut lines = content_str.lines();
+
+                let mut tag = Tag::default();
+
+                // Same format as commits:
+                // [key] [value]
+                // ...
+                // <empty line>
+                // [tag message]
+                while let Some(line) = lines.next() {
+                    if line.is_empty() {
+                        tag.message = lines.collect::<Vec<_>>().join("\n");
+                        break;
+                    }
+                    let (key, value) = line.split_once(' ').ok_or(anyhow!("Invalid line"))?;
+                    let value = value.to_string();
+                    match key {
+                        "object" => tag.object = value,
+                        "type" => tag.object_type = value,
+                        "tag" => tag.tag = value,
+                        "tagger" => tag.tagger = value,
+                        _ => {}
+                    }
+                }
+
+                Ok(Object::Tag(tag))
+            }
             _ => Err(anyhow!("Unknown object type")),
         }
     }
@@ -181,27 +345,95 @@ impl Object {
 
     /// Returns an object from a hash in a git repository.
     pub fn from_hash(repo: &Repo, hash: &str) -> Result<Object> {
-        let (short_hash, long_hash) = hash.split_at_checked(2).ok_or(anyhow!("Invalid hash"))?;
-        let path = repo
-            .git_dir()
-            .join("objects")
-            .join(short_hash)
-            .join(long_hash);
-        Object::from_file(&path)
+        Object::from_file(&object_path(repo, hash)?)
+    }
+
+    /// Follows annotated tags until an object that isn't a tag is found.
+    ///
+    /// Returns the hash of that object, which is the hash itself if it
+    /// doesn't point to a tag.
+    pub fn peel(repo: &Repo, hash: &str) -> Result<String> {
+        let mut hash = hash.to_string();
+        while let Object::Tag(tag) = Object::from_hash(repo, &hash)? {
+            hash = tag.object;
+        }
+        Ok(hash)
+    }
+
+    /// Returns the hashes of all loose objects in a git repository.
+    pub fn all_hashes(repo: &Repo) -> Result<Vec<String>> {
+        let mut hashes = vec![];
+        let objects_dir = repo.common_dir().join("objects");
+        for dir in fs::read_dir(objects_dir)? {
+            let dir = dir?;
+            let dir_name = dir.file_name();
+            let Some(prefix) = dir_name.to_str() else {
+                continue;
+            };
+            if prefix.len() != 2 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+                continue;
+            }
+            for entry in fs::read_dir(dir.path())? {
+                if let Some(rest) = entry?.file_name().to_str() {
+                    hashes.push(format!("{prefix}{rest}"));
+                }
+            }
+        }
+        hashes.sort();
+        Ok(hashes)
     }
 
     /// Returns an object from a rev in a git repository.
     ///
-    /// A rev can be a hash (long or short), a branch or a tag.
+    /// See [`Object::resolve_rev`] for what a rev can be.
+    pub fn from_rev(repo: &Repo, rev: &str) -> Result<Object> {
+        Object::from_hash(repo, &Object::resolve_rev(repo, rev)?)
+    }
+
+    /// Returns the full hash of a rev in a git repository.
+    ///
+    /// A rev can be a hash (long or short), a branch or a tag, or
+    /// `<rev>:<path>` for the object at a path in the tree of a rev.
     /// If no matches are found, an error is returned.
     /// And error is also returned if the rev is ambiguous.
-    pub fn from_rev(repo: &Repo, rev: &str) -> Result<Object> {
+    pub fn resolve_rev(repo: &Repo, rev: &str) -> Result<String> {
+        if let Some((rev, path)) = rev.split_once(':') {
+            let mut hash = Object::peel(repo, &Object::resolve_rev(repo, rev)?)?;
+            if let Object::Commit(commit) = Object::from_hash(repo, &hash)? {
+                hash = commit.tree;
+            }
+            for name in path.split('/').filter(|name| !name.is_empty()) {
+                let Object::Tree(tree) = Object::from_hash(repo, &hash)? else {
+                    return Err(anyhow!("Path '{path}' does not exist in '{rev}'"));
+                };
+                hash = tree
+                    .files
+                    .into_iter()
+                    .find(|file| file.name == name)
+                    .ok_or(anyhow!("Path '{path}' does not exist in '{rev}'"))?
+                    .hash;
+            }
+            return Ok(hash);
+        }
+        // Like git, refs take precedence over hashes.
+        if let Some(name) = refs::expand(repo, rev) {
+            return refs::find_ref(repo, &name);
+        }
+        if rev == "HEAD" {
+            if let Some(branch) = refs::unborn_branch(repo)? {
+                let branch = branch.strip_prefix("refs/heads/").unwrap_or(&branch);
+                return Err(anyhow!(
+                    "Your current branch '{branch}' does not have any commits yet"
+                ));
+            }
+        }
+
         let mut candidates: Vec<String> = vec![];
 
         // Check if this is a hash
-        if rev.len() >= 4 {
+        if rev.len() >= 4 && rev.bytes().all(|b| b.is_ascii_hexdigit()) {
             let (short_hash, long_hash) = rev.split_at(2);
-            let path = repo.git_dir().join("objects").join(short_hash);
+            let path = repo.common_dir().join("objects").join(short_hash);
 
             if path.exists() {
                 for entry in fs::read_dir(path)? {
@@ -217,10 +449,8 @@ impl Object {
             }
         }
 
-        // TODO: Check if this is a branch or a tag
-
         match candidates.len() {
-            1 => Ok(Object::from_hash(repo, &candidates[0])?),
+            1 => Ok(candidates.remove(0)),
             0 => Err(anyhow!("Object not found")),
             _ => Err(anyhow!("Ambiguous reference: {:?}", candidates)),
         }
@@ -232,14 +462,14 @@ impl Object {
     ///
     /// Returns the type, object size and the index where the header ends.
     fn parse_header(s: &[u8]) -> Result<(String, usize, usize)> {
-        let space_index = s
-            .iter()
-            .position(|&x| x == b' ')
-            .ok_or(anyhow!("Incorrect header format"))?;
         let null_index = s
             .iter()
             .position(|&x| x == b'\0')
             .ok_or(anyhow!("Incorrect header format"))?;
+        let space_index = s[..null_index]
+            .iter()
+            .position(|&x| x == b' ')
+            .ok_or(anyhow!("Incorrect header format"))?;
         let object_type = std::str::from_utf8(&s[..space_index])?;
         let object_size = std::str::from_utf8(&s[space_index + 1..null_index])?;
         let object_size = object_size.parse::<usize>()?;
@@ -247,6 +477,287 @@ impl Object {
     }
 }
 
+/// Returns the path of a loose object.
+pub(crate) fn object_path(repo: &Repo, hash: &str) -> Result<std::path::PathBuf> {
+    let (short_hash, long_hash) = hash.split_at_checked(2).ok_or(anyhow!("Invalid hash"))?;
+    Ok(repo
+        .common_dir()
+        .join("objects")
+        .join(short_hash)
+        .join(long_hash))
+}
+
+/// Returns whether an object is in the repository.
+pub fn exists(repo: &Repo, hash: &str) -> bool {
+    object_path(repo, hash).is_ok_and(|path| path.is_file())
+}
+
+/// Opens a loose object and reads its header, returning the object type, the
+/// size and a decoder positioned at the start of the content.
+fn open_object(
+    repo: &Repo,
+    hash: &str,
+) -> Result<(ObjectType, usize, ZlibDecoder<io::BufReader<fs::File>>)> {
+    let file = fs::File::open(object_path(repo, hash)?).context("Could not read from file")?;
+    let mut z = ZlibDecoder::new(io::BufReader::new(file));
+
+    // The header is short, so read it byte by byte to not inflate any content.
+    let mut header = vec![];
+    while header.last() != Some(&0) {
+        if header.len() > 32 {
+            return Err(anyhow!("Incorrect header format"));
+        }
+        let mut byte = [0_u8; 1];
+        z.read_exact(&mut byte)?;
+        header.push(byte[0]);
+    }
+    let (object_type, size, _) = Object::parse_header(&header)?;
+    Ok((object_type.parse()?, size, z))
+}
+
+/// Returns the type and size of an object, without decompressing all of it.
+pub fn read_header(repo: &Repo, hash: &str) -> Result<(ObjectType, usize)> {
+    let (object_type, size, _) = open_object(repo, hash)?;
+    Ok((object_type, size))
+}
+
+/// Opens a blob for reading, returning its size and a reader for its content.
+///
+/// Unlike [`Object::from_hash`] the content is never fully loaded into
+/// memory, which matters for blobs larger than `core.bigFileThreshold`.
+pub fn open_blob(repo: &Repo, hash: &str) -> Result<(usize, Box<dyn io::Read>)> {
+    let (object_type, size, z) = open_object(repo, hash)?;
+    if object_type != ObjectType::Blob {
+        return Err(anyhow!("Expected a blob: {hash}"));
+    }
+    Ok((size, Box::new(z.take(size as u64))))
+}
+
+/// Hashes a blob of `size` bytes read from `reader` and, if a repository is
+/// given, writes it to the object database. Returns the hash of the blob.
+///
+/// The content is streamed, so this can be used for blobs of any size.
+pub fn write_blob_stream(
+    repo: Option<&Repo>,
+    size: u64,
+    reader: &mut dyn io::Read,
+) -> Result<String> {
+    let header = format!("blob {size}\0");
+    let mut hasher = Sha1::new();
+    hasher.update(header.as_bytes());
+
+    let mut writer = match repo {
+        Some(repo) => {
+            let (tmp, file) = TempObject::create(repo)?;
+            let mut encoder = flate2::write::ZlibEncoder::new(
+                io::BufWriter::new(file),
+                flate2::Compression::default(),
+            );
+            encoder.write_all(header.as_bytes())?;
+            Some((tmp, encoder))
+        }
+        None => None,
+    };
+
+    let mut buffer = vec![0_u8; 64 * 1024];
+    let mut total = 0_u64;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        total += read as u64;
+        hasher.update(&buffer[..read]);
+        if let Some((_, encoder)) = &mut writer {
+            encoder.write_all(&buffer[..read])?;
+        }
+    }
+    let hash = hex::encode(hasher.finalize());
+
+    if let Some((tmp, encoder)) = writer {
+        encoder.finish()?.flush()?;
+        if total != size {
+            return Err(anyhow!("Expected {size} bytes but read {total}"));
+        }
+        let path = object_path(repo.expect("repo is set when writing"), &hash)?;
+        fs::create_dir_all(path.parent().expect("objects have a parent folder"))?;
+        tmp.rename(&path)?;
+    } else if total != size {
+        return Err(anyhow!("Expected {size} bytes but read {total}"));
+    }
+
+    Ok(hash)
+}
+
+/// Writes an object to the object database and returns its hash.
+///
+/// Nothing is written if the object already exists.
+pub fn write_object(repo: &Repo, object_type: ObjectType, content: &[u8]) -> Result<String> {
+    let mut data = format!("{object_type} {}\0", content.len()).into_bytes();
+    data.extend(content);
+    let hash = hash(&data);
+    let path = object_path(repo, &hash)?;
+    if path.exists() {
+        return Ok(hash);
+    }
+
+    fs::create_dir_all(path.parent().expect("objects have a parent folder"))?;
+    let (tmp, file) = TempObject::create(repo)?;
+    let mut encoder =
+        flate2::write::ZlibEncoder::new(io::BufWriter::new(file), flate2::Compression::default());
+    encoder.write_all(&data)?;
+    encoder.finish()?.flush()?;
+    tmp.rename(&path)?;
+    Ok(hash)
+}
+
+/// Like [`write_object`], but checks that the content is well formed first
+/// with [`fsck::check_object`], so that malformed objects aren't created by
+/// mistake.
+pub fn write_checked_object(
+    repo: &Repo,
+    object_type: ObjectType,
+    content: &[u8],
+) -> Result<String> {
+    fsck::check_object(object_type, content).map_err(|e| anyhow!("object fails fsck: {e}"))?;
+    write_object(repo, object_type, content)
+}
+
+/// A file an object is written to before it's renamed into place, which is
+/// removed if it's dropped before, when writing fails.
+struct TempObject {
+    path: std::path::PathBuf,
+    renamed: bool,
+}
+
+impl TempObject {
+    /// Creates the file at a unique path.
+    fn create(repo: &Repo) -> Result<(TempObject, fs::File)> {
+        static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = repo.common_dir().join("objects").join(format!(
+            "tmp_obj_{}_{}",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::File::create(&path)?;
+        Ok((
+            TempObject {
+                path,
+                renamed: false,
+            },
+            file,
+        ))
+    }
+
+    fn rename(mut self, path: &std::path::Path) -> Result<()> {
+        fs::rename(&self.path, path)?;
+        self.renamed = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempObject {
+    fn drop(&mut self) {
+        if !self.renamed {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Makes sure a tree entry can't be used to write outside of its folder,
+/// or into the `.git` folder, when it's checked out.
+fn validate_tree_entry_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.contains('/')
+        || name.eq_ignore_ascii_case(".git")
+    {
+        return Err(anyhow!("Invalid tree entry name: {name:?}"));
+    }
+    Ok(())
+}
+
+/// Returns the minimum length of abbreviated hashes, from `core.abbrev` (7 by
+/// default). `no` never abbreviates them.
+pub fn abbrev_len(config: &Config) -> Result<usize> {
+    Ok(match config.get("core.abbrev") {
+        None | Some("auto") => DEFAULT_ABBREV_LEN,
+        Some("no") => usize::MAX,
+        Some(_) => {
+            let len = config.get_int("core.abbrev")?.unwrap_or_default();
+            usize::try_from(len)?.max(MIN_ABBREV_LEN)
+        }
+    })
+}
+
+/// Abbreviates hashes to their shortest prefix that is unambiguous in a
+/// repository.
+///
+/// Only loose objects are considered since packfiles aren't supported yet.
+/// Each fan-out folder is listed once, the first time a hash in it is
+/// abbreviated, so objects written after that aren't taken into account.
+pub struct Abbreviator<'r> {
+    repo: &'r Repo,
+    min_len: usize,
+    folders: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl<'r> Abbreviator<'r> {
+    /// Creates an abbreviator whose prefixes are never shorter than `min_len`.
+    pub fn new(repo: &'r Repo, min_len: usize) -> Self {
+        Abbreviator {
+            repo,
+            min_len,
+            folders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates an abbreviator with the minimum length of `core.abbrev`.
+    pub fn from_config(repo: &'r Repo) -> Result<Self> {
+        Ok(Abbreviator::new(repo, abbrev_len(&Config::load(repo)?)?))
+    }
+
+    /// Returns the shortest prefix of `hash` that is unambiguous, and not
+    /// shorter than the minimum length.
+    pub fn abbreviate(&self, hash: &str) -> Result<String> {
+        // Only objects in the same fan-out folder can share a prefix.
+        let (short_hash, long_hash) = hash.split_at_checked(2).ok_or(anyhow!("Invalid hash"))?;
+        let mut folders = self.folders.lock().unwrap();
+        let others = match folders.entry(short_hash.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(self.list_folder(short_hash)?),
+        };
+        let mut common_len = 0;
+        for other in others.iter().filter(|&other| other != long_hash) {
+            let shared = other
+                .chars()
+                .zip(long_hash.chars())
+                .take_while(|(a, b)| a == b)
+                .count();
+            common_len = common_len.max(2 + shared);
+        }
+
+        let len = (common_len + 1).max(self.min_len).min(hash.len());
+        Ok(hash[..len].to_string())
+    }
+
+    /// Lists the rest of the hashes of the loose objects in a fan-out folder.
+    fn list_folder(&self, short_hash: &str) -> Result<Vec<String>> {
+        let dir = self.repo.common_dir().join("objects").join(short_hash);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut others = vec![];
+        for entry in fs::read_dir(dir)? {
+            if let Ok(other) = entry?.file_name().into_string() {
+                others.push(other);
+            }
+        }
+        Ok(others)
+    }
+}
+
 pub fn hash(s: &[u8]) -> String {
     let mut hasher = Sha1::new();
     hasher.update(s);
@@ -339,7 +850,7 @@ This commit adds a good git client
             panic!("Expected a commit");
         };
         assert_eq!(commit.tree, "abc123");
-        assert_eq!(commit.parent, "987xyz");
+        assert_eq!(commit.parents, vec!["987xyz"]);
         assert_eq!(commit.author, "good_git <good@git.com> 1234 +0100");
         assert_eq!(commit.committer, "");
         assert_eq!(
@@ -348,6 +859,27 @@ This commit adds a good git client
         );
     }
 
+    #[test]
+    fn test_object_from_bytes_for_tag() {
+        let s = b"tag 90\0\
+object abc123
+type commit
+tag v1.0
+tagger good_git <good@git.com> 1234 +0100
+
+Release 1.0
+";
+        let object = Object::from_bytes(s.as_ref()).unwrap();
+        let Object::Tag(tag) = object else {
+            panic!("Expected a tag");
+        };
+        assert_eq!(tag.object, "abc123");
+        assert_eq!(tag.object_type, "commit");
+        assert_eq!(tag.tag, "v1.0");
+        assert_eq!(tag.tagger, "good_git <good@git.com> 1234 +0100");
+        assert_eq!(tag.message, "Release 1.0");
+    }
+
     #[test]
     fn test_object_from_bytes_for_commit_with_incorrect_format() {
         let s = b"commit 18\0\
@@ -365,6 +897,21 @@ parent";
         assert_eq!(err, "Failed to read hash");
     }
 
+    #[test]
+    fn test_object_from_bytes_for_tree_rejects_dangerous_names() {
+        let tree = |name: &str| {
+            let mut s = format!("100644 {name}\0").into_bytes();
+            s.extend([0_u8; 20]);
+            [format!("tree {}\0", s.len()).into_bytes(), s].concat()
+        };
+        for name in ["..", ".", ".GIT", "a/b", ""] {
+            let err = Object::from_bytes(&tree(name)).unwrap_err().to_string();
+            assert_eq!(err, format!("Invalid tree entry name: {name:?}"));
+        }
+        // Only NTFS treats them as separators, which checkouts check.
+        assert!(Object::from_bytes(&tree("..\\x")).is_ok());
+    }
+
     #[test]
     fn test_object_from_bytes_incorrect_header_size() {
         let s = b"blob 0\0hi";
@@ -385,4 +932,52 @@ parent";
         let s = b"blob 16\0what is up, doc?";
         assert_eq!(hash(s), "bd9dbf5aae1a3862dd1526723246b20206e5fc37");
     }
+
+    #[test]
+    fn test_write_blob_stream_removes_temporary_file_on_error() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = crate::repo::Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let objects = || {
+            std::fs::read_dir(repo.git_dir().join("objects"))
+                .unwrap()
+                .count()
+        };
+        let before = objects();
+
+        let error = super::write_blob_stream(Some(&repo), 10, &mut b"short".as_slice());
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "Expected 10 bytes but read 5"
+        );
+        assert_eq!(objects(), before);
+    }
+
+    #[test]
+    fn test_abbreviator() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = crate::repo::Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let folder = repo.git_dir().join("objects/ab");
+        std::fs::create_dir_all(&folder).unwrap();
+        let hash = format!("abcdef12{}", "0".repeat(32));
+        std::fs::write(folder.join(&hash[2..]), "").unwrap();
+        std::fs::write(folder.join(format!("cdef12{}", "1".repeat(32))), "").unwrap();
+
+        let abbreviator = super::Abbreviator::new(&repo, 4);
+        assert_eq!(abbreviator.abbreviate(&hash).unwrap(), "abcdef120");
+        // The folder was listed once, new objects in it aren't seen.
+        std::fs::write(folder.join(format!("cdef120{}", "2".repeat(31))), "").unwrap();
+        assert_eq!(abbreviator.abbreviate(&hash).unwrap(), "abcdef120");
+        assert_eq!(
+            super::Abbreviator::new(&repo, 4).abbreviate(&hash).unwrap(),
+            "abcdef1200"
+        );
+        assert_eq!(
+            super::Abbreviator::new(&repo, usize::MAX)
+                .abbreviate(&hash)
+                .unwrap(),
+            hash
+        );
+    }
 }
@@ -0,0 +1,261 @@
+use anyhow::{anyhow, Result};
+
+/// The first bytes of a version 2 or later pack index. Version 1 indexes
+/// start directly with the fan-out table, which can't start like this.
+const MAGIC: &[u8; 4] = b"\xfftOc";
+
+/// An offset with this bit set is an index into the table of 64-bit offsets.
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+
+/// A pack index, the `.idx` file next to a `.pack` file, which maps the
+/// hashes of the objects in the pack to their offset in it.
+///
+/// See https://git-scm.com/docs/pack-format for the format. Checksums are
+/// read but not verified, so that corrupt indexes can still be inspected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackIndex {
+    /// 1 or 2.
+    pub version: u32,
+    /// The entries, sorted by hash.
+    pub entries: Vec<PackIndexEntry>,
+    /// The checksum of the pack.
+    pub pack_checksum: String,
+    /// The checksum of the index itself.
+    pub checksum: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackIndexEntry {
+    pub hash: String,
+    /// Where the object starts in the pack.
+    pub offset: u64,
+    /// The CRC32 of the packed object, only in version 2 indexes.
+    pub crc32: Option<u32>,
+}
+
+impl PackIndex {
+    pub fn from_bytes(s: &[u8]) -> Result<PackIndex> {
+        let truncated = || anyhow!("Pack index is truncated");
+        let (version, rest) = match s.strip_prefix(MAGIC) {
+            Some(rest) => {
+                let version = read_u32(rest, 0).ok_or_else(truncated)?;
+                if version != 2 {
+                    return Err(anyhow!("Unsupported pack index version: {version}"));
+                }
+                (version, &rest[4..])
+            }
+            None => (1, s),
+        };
+
+        let mut fanout = [0_u32; 256];
+        for (i, count) in fanout.iter_mut().enumerate() {
+            *count = read_u32(rest, i * 4).ok_or_else(truncated)?;
+        }
+        if fanout.windows(2).any(|counts| counts[0] > counts[1]) {
+            return Err(anyhow!("Pack index fan-out table is not sorted"));
+        }
+        let count = fanout[255] as usize;
+        let table = &rest[256 * 4..];
+
+        // Check the size before allocating anything for the entries.
+        let entry_size = if version == 1 { 24 } else { 20 + 4 + 4 };
+        let min_size = count
+            .checked_mul(entry_size)
+            .and_then(|size| size.checked_add(40))
+            .ok_or_else(truncated)?;
+        if table.len() < min_size {
+            return Err(truncated());
+        }
+
+        let mut entries = Vec::with_capacity(count);
+        let trailer = if version == 1 {
+            for i in 0..count {
+                let entry = &table[i * 24..(i + 1) * 24];
+                entries.push(PackIndexEntry {
+                    hash: hex::encode(&entry[4..]),
+                    offset: u64::from(read_u32(entry, 0).ok_or_else(truncated)?),
+                    crc32: None,
+                });
+            }
+            &table[count * 24..]
+        } else {
+            let (hashes, rest) = table.split_at(count * 20);
+            let (crcs, rest) = rest.split_at(count * 4);
+            let (offsets, rest) = rest.split_at(count * 4);
+            let large_offset_count = (0..count)
+                .filter_map(|i| read_u32(offsets, i * 4))
+                .filter(|offset| offset & LARGE_OFFSET_FLAG != 0)
+                .count();
+            if rest.len() < large_offset_count * 8 + 40 {
+                return Err(truncated());
+            }
+            let (large_offsets, rest) = rest.split_at(large_offset_count * 8);
+            for i in 0..count {
+                let offset = read_u32(offsets, i * 4).ok_or_else(truncated)?;
+                let offset = if offset & LARGE_OFFSET_FLAG != 0 {
+                    let position = (offset & !LARGE_OFFSET_FLAG) as usize;
+                    read_u64(large_offsets, position * 8)
+                        .ok_or(anyhow!("Invalid large offset in pack index"))?
+                } else {
+                    u64::from(offset)
+                };
+                entries.push(PackIndexEntry {
+                    hash: hex::encode(&hashes[i * 20..(i + 1) * 20]),
+                    offset,
+                    crc32: read_u32(crcs, i * 4),
+                });
+            }
+            rest
+        };
+        if trailer.len() != 40 {
+            return Err(anyhow!("Pack index has unexpected trailing data"));
+        }
+        if entries.windows(2).any(|pair| pair[0].hash >= pair[1].hash) {
+            return Err(anyhow!("Pack index entries are not sorted"));
+        }
+        // Entry i has the first byte b if fanout[b - 1] <= i < fanout[b].
+        for (i, entry) in entries.iter().enumerate() {
+            let first_byte = usize::from_str_radix(&entry.hash[..2], 16)?;
+            let start = first_byte.checked_sub(1).map_or(0, |b| fanout[b]) as usize;
+            if i < start || i >= fanout[first_byte] as usize {
+                return Err(anyhow!(
+                    "Pack index fan-out table doesn't match its entries"
+                ));
+            }
+        }
+
+        Ok(PackIndex {
+            version,
+            entries,
+            pack_checksum: hex::encode(&trailer[..20]),
+            checksum: hex::encode(&trailer[20..]),
+        })
+    }
+
+    /// Returns the entry of an object, if it's in the pack.
+    pub fn find(&self, hash: &str) -> Option<&PackIndexEntry> {
+        self.entries
+            .binary_search_by(|entry| entry.hash.as_str().cmp(hash))
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+}
+
+fn read_u32(s: &[u8], at: usize) -> Option<u32> {
+    let bytes = s.get(at..at.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(s: &[u8], at: usize) -> Option<u64> {
+    let bytes = s.get(at..at.checked_add(8)?)?;
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a version 2 index with (hash, offset, crc32) entries sorted by
+    /// hash.
+    fn write_v2(entries: &[(&str, u64, u32)]) -> Vec<u8> {
+        let mut s = MAGIC.to_vec();
+        s.extend(2_u32.to_be_bytes());
+        for byte in 0..=255_u8 {
+            let count = entries
+                .iter()
+                .filter(|(hash, _, _)| hex::decode(&hash[..2]).unwrap()[0] <= byte)
+                .count();
+            s.extend((count as u32).to_be_bytes());
+        }
+        for (hash, _, _) in entries {
+            s.extend(hex::decode(hash).unwrap());
+        }
+        for (_, _, crc) in entries {
+            s.extend(crc.to_be_bytes());
+        }
+        let mut large_offsets = vec![];
+        for (_, offset, _) in entries {
+            if *offset >= u64::from(LARGE_OFFSET_FLAG) {
+                let position = (large_offsets.len() / 8) as u32;
+                s.extend((position | LARGE_OFFSET_FLAG).to_be_bytes());
+                large_offsets.extend(offset.to_be_bytes());
+            } else {
+                s.extend((*offset as u32).to_be_bytes());
+            }
+        }
+        s.extend(large_offsets);
+        s.extend([0xaa; 20]);
+        s.extend([0xbb; 20]);
+        s
+    }
+
+    #[test]
+    fn test_from_bytes_v2() {
+        let a = "0a".repeat(20);
+        let b = "f0".repeat(20);
+        let index =
+            PackIndex::from_bytes(&write_v2(&[(&a, 12, 0x1234), (&b, 1 << 33, 7)])).unwrap();
+        assert_eq!(index.version, 2);
+        assert_eq!(
+            index.entries,
+            vec![
+                PackIndexEntry {
+                    hash: a.clone(),
+                    offset: 12,
+                    crc32: Some(0x1234)
+                },
+                PackIndexEntry {
+                    hash: b.clone(),
+                    offset: 1 << 33,
+                    crc32: Some(7)
+                },
+            ]
+        );
+        assert_eq!(index.pack_checksum, "aa".repeat(20));
+        assert_eq!(index.find(&b).unwrap().offset, 1 << 33);
+        assert!(index.find(&"00".repeat(20)).is_none());
+    }
+
+    #[test]
+    fn test_from_bytes_v1() {
+        let hash = "0a".repeat(20);
+        let mut s = vec![];
+        for byte in 0..=255_u32 {
+            s.extend(u32::from(byte >= 0x0a).to_be_bytes());
+        }
+        s.extend(12_u32.to_be_bytes());
+        s.extend(hex::decode(&hash).unwrap());
+        s.extend([0; 40]);
+        let index = PackIndex::from_bytes(&s).unwrap();
+        assert_eq!(index.version, 1);
+        assert_eq!(index.entries[0].hash, hash);
+        assert_eq!(index.entries[0].offset, 12);
+        assert_eq!(index.entries[0].crc32, None);
+    }
+
+    #[test]
+    fn test_from_bytes_invalid() {
+        let a = "0a".repeat(20);
+        let s = write_v2(&[(&a, 12, 0)]);
+        assert_eq!(
+            PackIndex::from_bytes(&s[..s.len() - 1])
+                .unwrap_err()
+                .to_string(),
+            "Pack index is truncated"
+        );
+
+        // A huge count in the fan-out table doesn't allocate anything.
+        let mut s = MAGIC.to_vec();
+        s.extend(2_u32.to_be_bytes());
+        s.extend([0xff; 256 * 4]);
+        assert!(PackIndex::from_bytes(&s).is_err());
+
+        // The entry doesn't start with 0x0b.
+        let mut s = write_v2(&[(&a, 12, 0)]);
+        s[8 + 0x0a * 4..8 + 0x0b * 4].copy_from_slice(&0_u32.to_be_bytes());
+        assert_eq!(
+            PackIndex::from_bytes(&s).unwrap_err().to_string(),
+            "Pack index fan-out table doesn't match its entries"
+        );
+    }
+}
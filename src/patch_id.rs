@@ -0,0 +1,414 @@
+use anyhow::{anyhow, Result};
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    add_patch::{self, Line},
+    binary::{BinaryClassifier, TextAttribute},
+    diff,
+    object::{Commit, Object},
+    refs::ZERO_HASH,
+    repo::Repo,
+};
+
+/// Hashes patches for their patch IDs, ignoring whitespace unless
+/// `verbatim`.
+///
+/// The files of a patch are hashed together, or with `stable` each on its
+/// own with the hashes summed, so that the ID doesn't depend on their order.
+struct Hasher {
+    file: Sha1,
+    sum: [u8; 20],
+    stable: bool,
+    verbatim: bool,
+}
+
+impl Hasher {
+    fn new(stable: bool, verbatim: bool) -> Hasher {
+        Hasher {
+            file: Sha1::new(),
+            sum: [0; 20],
+            stable,
+            verbatim,
+        }
+    }
+
+    /// Hashes `s`, returning the number of bytes hashed.
+    fn update(&mut self, s: &[u8]) -> usize {
+        match self.verbatim {
+            true => {
+                self.file.update(s);
+                s.len()
+            }
+            false => {
+                // Like git, only spaces, tabs and line endings are whitespace.
+                let s: Vec<u8> = s
+                    .iter()
+                    .copied()
+                    .filter(|c| !matches!(c, b' ' | b'\t' | b'\n' | b'\r'))
+                    .collect();
+                self.file.update(&s);
+                s.len()
+            }
+        }
+    }
+
+    /// Ends the hash of a file, if they're hashed on their own.
+    fn end_file(&mut self) {
+        if self.stable {
+            self.flush();
+        }
+    }
+
+    /// Adds the hash so far to the sum, like git's `flush_one_hunk`.
+    fn flush(&mut self) {
+        let hash = std::mem::take(&mut self.file).finalize();
+        let mut carry = 0u16;
+        for (sum, byte) in self.sum.iter_mut().zip(hash) {
+            carry += u16::from(*sum) + u16::from(byte);
+            *sum = carry as u8;
+            carry >>= 8;
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush();
+        hex::encode(self.sum)
+    }
+}
+
+/// Returns the patch ID of a commit: a hash of its diff with its parent
+/// that ignores whitespace and line numbers, so that a commit and its
+/// cherry-picks usually have the same one. Merge commits have none.
+///
+/// Like git's, the ID is stable and computed from a diff without rename
+/// detection. For commits that modify text files, it's the one
+/// `git show <commit> | git patch-id --stable` prints.
+pub fn commit_patch_id(
+    repo: &Repo,
+    classifier: &BinaryClassifier,
+    commit: &Commit,
+) -> Result<Option<String>> {
+    let parent_tree = match &commit.parents[..] {
+        [] => None,
+        [parent] => match Object::from_hash(repo, parent)? {
+            Object::Commit(parent) => Some(parent.tree),
+            _ => return Err(anyhow!("Not a commit: {parent}")),
+        },
+        _ => return Ok(None),
+    };
+
+    let mut hasher = Hasher::new(true, false);
+    let changes = diff::diff_trees(repo, parent_tree.as_deref(), Some(&commit.tree), true)?;
+    for (i, change) in changes.iter().enumerate() {
+        if i > 0 {
+            hasher.end_file();
+        }
+        let path = change.path.as_bytes();
+        hasher.update(b"diff --git a/");
+        hasher.update(path);
+        hasher.update(b"b/");
+        hasher.update(path);
+        let modes = match (&change.old, &change.new) {
+            (None, Some((mode, _))) => format!("new file mode {mode:06o}"),
+            (Some((mode, _)), None) => format!("deleted file mode {mode:06o}"),
+            (Some((old_mode, _)), Some((new_mode, _))) if old_mode != new_mode => {
+                format!("old mode {old_mode:06o}new mode {new_mode:06o}")
+            }
+            _ => String::new(),
+        };
+        hasher.update(modes.as_bytes());
+
+        let is_binary = |side: &Option<(u32, String)>| match side {
+            Some((mode, hash)) if *mode != 0o160000 => {
+                classifier.is_binary_blob(repo, hash, TextAttribute::Unspecified)
+            }
+            _ => Ok(false),
+        };
+        if is_binary(&change.old)? || is_binary(&change.new)? {
+            for side in [&change.old, &change.new] {
+                let hash = side.as_ref().map_or(ZERO_HASH, |(_, hash)| hash.as_str());
+                hasher.update(hash.as_bytes());
+            }
+            continue;
+        }
+        let side = |side: &Option<(u32, String)>, prefix: &str| match side {
+            Some(_) => [prefix.as_bytes(), path].concat(),
+            None => b"/dev/null".to_vec(),
+        };
+        hasher.update(b"--- ");
+        hasher.update(&side(&change.old, "a/"));
+        hasher.update(b"+++ ");
+        hasher.update(&side(&change.new, "b/"));
+        let (old, new) = (content(repo, &change.old)?, content(repo, &change.new)?);
+        for hunk in add_patch::hunks(&old, &new, 3) {
+            for line in hunk.lines {
+                let (prefix, text) = match line {
+                    Line::Context(text) => (b' ', text),
+                    Line::Removed(text) => (b'-', text),
+                    Line::Added(text) => (b'+', text),
+                };
+                hasher.update(&[prefix]);
+                hasher.update(&text);
+            }
+        }
+    }
+    Ok(Some(hasher.finish()))
+}
+
+/// Returns the patch IDs of the patches in `diff`, like `git patch-id`
+/// reading the output of `git log -p` or `git format-patch`, each with the
+/// commit on the `commit`, `From` or `diff-tree` line before the patch, or
+/// [`ZERO_HASH`] if there's none.
+///
+/// With `stable`, the files of a patch are hashed on their own so that the
+/// ID doesn't depend on their order, and with `verbatim`, whitespace isn't
+/// ignored.
+pub fn patch_ids(diff: &[u8], stable: bool, verbatim: bool) -> Vec<(String, String)> {
+    let mut ids = vec![];
+    let mut lines = diff.split_inclusive(|&b| b == b'\n').peekable();
+    let mut commit = ZERO_HASH.to_string();
+    while lines.peek().is_some() {
+        let (id, next) = next_patch_id(&mut lines, Hasher::new(stable, verbatim));
+        if let Some(id) = id {
+            ids.push((id, commit));
+        }
+        commit = next.unwrap_or_else(|| ZERO_HASH.to_string());
+    }
+    ids
+}
+
+/// Reads the lines of a patch, like git's `get_one_patchid`, returning its
+/// ID if it has changes, and the commit of the next patch if it stopped at
+/// one.
+fn next_patch_id<'a>(
+    lines: &mut impl Iterator<Item = &'a [u8]>,
+    mut hasher: Hasher,
+) -> (Option<String>, Option<String>) {
+    let mut len = 0;
+    // The lines left in the hunk on each side, -1 in the header of a file.
+    let (mut before, mut after) = (-1, -1);
+    let mut is_binary = false;
+    let (mut pre_image, mut post_image): (&[u8], &[u8]) = (b"", b"");
+    for line in lines {
+        let rest = ["diff-tree ", "commit ", "From "]
+            .iter()
+            .find_map(|prefix| line.strip_prefix(prefix.as_bytes()));
+        // Lines like `\ No newline at end of file`.
+        if rest.is_none() && line.starts_with(b"\\ ") && line.len() > 12 {
+            if hasher.verbatim {
+                hasher.update(line);
+            }
+            continue;
+        }
+        let hash = rest.unwrap_or(line).get(..40);
+        if let Some(hash) = hash.filter(|hash| hash.iter().all(u8::is_ascii_hexdigit)) {
+            let next = String::from_utf8_lossy(hash).to_ascii_lowercase();
+            return (finish(hasher, len), Some(next));
+        }
+
+        // Skips the message up to the first file.
+        if len == 0 && !line.starts_with(b"diff ") {
+            continue;
+        }
+
+        if before == -1 {
+            if line.starts_with(b"GIT binary patch") || line.starts_with(b"Binary files") {
+                // Binary files are only hashed by the IDs of their blobs.
+                is_binary = true;
+                before = 0;
+                hasher.update(pre_image);
+                hasher.update(post_image);
+                hasher.end_file();
+                continue;
+            } else if line.starts_with(b"index ") {
+                if let Some(dots) = find(line, b"..") {
+                    let end = find(&line[dots..], b" ")
+                        .map_or(line.len() - 1, |space| dots + space)
+                        .max(dots + 2);
+                    let truncate = |hash: &'a [u8]| &hash[..hash.len().min(40)];
+                    pre_image = truncate(&line["index ".len()..dots]);
+                    post_image = truncate(&line[dots + 2..end]);
+                }
+                continue;
+            } else if line.starts_with(b"--- ") {
+                (before, after) = (1, 1);
+            } else if !line.first().is_some_and(u8::is_ascii_alphabetic) {
+                break;
+            }
+        }
+
+        if is_binary {
+            if line.starts_with(b"diff ") {
+                is_binary = false;
+                before = -1;
+            }
+            continue;
+        }
+
+        if before == 0 && after == 0 {
+            if line.starts_with(b"@@ -") {
+                (before, after) = scan_hunk_header(line);
+                continue;
+            }
+            // A line after the hunks of the last file ends the patch.
+            if !line.starts_with(b"diff ") {
+                break;
+            }
+            hasher.end_file();
+            (before, after) = (-1, -1);
+        }
+
+        match line.first() {
+            Some(b'-') => before -= 1,
+            Some(b'+') => after -= 1,
+            Some(b' ') => (before, after) = (before - 1, after - 1),
+            _ => {}
+        }
+        len += hasher.update(line);
+    }
+    (finish(hasher, len), None)
+}
+
+/// Returns the hash of a patch of `len` bytes, if it's not empty.
+fn finish(hasher: Hasher, len: usize) -> Option<String> {
+    (len > 0).then(|| hasher.finish())
+}
+
+/// Returns the position of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Returns the numbers of lines of the old and new side of a hunk from its
+/// header, 1 when left out.
+fn scan_hunk_header(line: &[u8]) -> (i64, i64) {
+    let at = |s: &[u8], i: usize| s.get(i).copied().unwrap_or_default();
+    let digits = |s: &[u8]| s.iter().take_while(|b| b.is_ascii_digit()).count();
+    let number = |s: &[u8]| {
+        std::str::from_utf8(&s[..digits(s)])
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(0)
+    };
+
+    let mut old = &line["@@ -".len()..];
+    let mut n = digits(old);
+    let mut before = 1;
+    if at(old, n) == b',' {
+        old = &old[n + 1..];
+        before = number(old);
+        n = digits(old);
+    }
+    // Like git, a malformed header leaves no lines on the new side.
+    if n == 0 || at(old, n) != b' ' || at(old, n + 1) != b'+' {
+        return (before, 0);
+    }
+    let new = &old[n + 2..];
+    let n = digits(new);
+    match at(new, n) == b',' {
+        true => (before, number(&new[n + 1..])),
+        false => (before, 1),
+    }
+}
+
+/// Returns the content of one side of a change as shown in diffs, where
+/// submodules are the commit they point to.
+fn content(repo: &Repo, side: &Option<(u32, String)>) -> Result<Vec<u8>> {
+    match side {
+        None => Ok(vec![]),
+        Some((0o160000, hash)) => Ok(format!("Subproject commit {hash}\n").into_bytes()),
+        Some((_, hash)) => match Object::from_hash(repo, hash)? {
+            Object::Blob(blob) => Ok(blob.content),
+            _ => Err(anyhow!("Not a blob: {hash}")),
+        },
+    }
+}
+
+/// Returns the commits of a symmetric difference that have an equivalent
+/// commit, with the same patch ID, on the other side, like git's
+/// `--cherry-pick`.
+///
+/// Commits are (hash, commit, whether it's on the left side) tuples.
+pub fn equivalent_commits(
+    repo: &Repo,
+    classifier: &BinaryClassifier,
+    commits: &[(String, Commit, bool)],
+) -> Result<HashSet<String>> {
+    let mut ids = HashMap::new();
+    // The patch IDs of each side.
+    let mut sides = [HashSet::new(), HashSet::new()];
+    for (hash, commit, left) in commits {
+        if let Some(id) = commit_patch_id(repo, classifier, commit)? {
+            sides[usize::from(*left)].insert(id.clone());
+            ids.insert(hash, (id, *left));
+        }
+    }
+    Ok(ids
+        .into_iter()
+        .filter(|(_, (id, left))| sides[usize::from(!*left)].contains(id))
+        .map(|(hash, _)| hash.clone())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_ids() {
+        let patch = |commit: &str, diff: &str| format!("commit {commit}\n\n    Message\n\n{diff}");
+        let first = "diff --git a/a.txt b/a.txt\n\
+                     index 1111111..2222222 100644\n\
+                     --- a/a.txt\n\
+                     +++ b/a.txt\n\
+                     @@ -1,2 +1,2 @@\n \
+                     one\n\
+                     -two\n\
+                     +2\n";
+        let second = "diff --git a/b.txt b/b.txt\n\
+                      new file mode 100644\n\
+                      index 0000000..3333333\n\
+                      --- /dev/null\n\
+                      +++ b/b.txt\n\
+                      @@ -0,0 +1 @@\n\
+                      +b\n";
+        let log = patch(&"a".repeat(40), &format!("{first}{second}"))
+            + &patch(&"B".repeat(40), &format!("{second}{first}"))
+            + &patch(&"c".repeat(40), "");
+        let ids = patch_ids(log.as_bytes(), true, false);
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids[0].1, "a".repeat(40));
+        assert_eq!(ids[1].1, "b".repeat(40));
+        // Stable IDs don't depend on the order of the files.
+        assert_eq!(ids[0].0, ids[1].0);
+        let ids = patch_ids(log.as_bytes(), false, false);
+        assert_ne!(ids[0].0, ids[1].0);
+
+        // Whitespace and line numbers are ignored, unless verbatim.
+        let moved = first
+            .replace("-1,2 +1,2", "-10,2 +10,2")
+            .replace("+2", "+ 2");
+        let id = |diff: &str, verbatim| patch_ids(diff.as_bytes(), true, verbatim)[0].clone();
+        assert_eq!(id(first, false), id(&moved, false));
+        assert_ne!(id(first, true), id(&moved, true));
+        assert_eq!(id(first, false).1, ZERO_HASH);
+
+        // Binary files are hashed by the IDs of their blobs.
+        let binary = "diff --git a/c.bin b/c.bin\n\
+                      index 4444444..5555555 100644\n\
+                      Binary files a/c.bin and b/c.bin differ\n";
+        let other = binary.replace("5555555", "6666666");
+        assert_ne!(id(binary, false), id(&other, false));
+    }
+
+    #[test]
+    fn test_scan_hunk_header() {
+        assert_eq!(scan_hunk_header(b"@@ -1,2 +3,4 @@\n"), (2, 4));
+        assert_eq!(scan_hunk_header(b"@@ -1 +3 @@ fn\n"), (1, 1));
+        assert_eq!(scan_hunk_header(b"@@ -0,0 +1 @@\n"), (0, 1));
+        assert_eq!(scan_hunk_header(b"@@ -5,3 @@\n"), (3, 0));
+    }
+}
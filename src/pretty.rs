@@ -0,0 +1,419 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+use crate::{
+    color::{DecorateColors, RESET},
+    ident::{DateFormat, Ident},
+    object::{Abbreviator, Commit, Object},
+    refs,
+    repo::Repo,
+};
+
+/// Expands the placeholders of a commit format, like `%h %s (%an)`, as in
+/// git's `--format` and `$Format:...$` of `export-subst`.
+///
+/// Supported are `%H`, `%h`, `%T`, `%t`, `%P` and `%p` for hashes, `%an`,
+/// `%ae`, `%ad`, `%aD`, `%at`, `%ai` and `%aI` for the author and their
+/// `%c` equivalents for the committer, `%s`, `%b` and `%B` for the message,
+/// `%d` and `%D` for the refs pointing at the commit, `%n`, `%%` and `%xNN`.
+/// Colors, `%C(...)`, `%Cred`, `%Cgreen`, `%Cblue` and `%Creset`, expand to
+/// nothing, and so does the encoding `%e`, commits being UTF-8. Like git,
+/// unknown placeholders are kept as is.
+///
+/// Abbreviated hashes are shortened by `abbreviator`.
+pub fn format_commit(
+    repo: &Repo,
+    abbreviator: &Abbreviator,
+    hash: &str,
+    commit: &Commit,
+    format: &str,
+) -> Result<Vec<u8>> {
+    let mut output = vec![];
+    let mut rest = format;
+    while let Some(index) = rest.find('%') {
+        output.extend(rest[..index].as_bytes());
+        rest = &rest[index + 1..];
+        let (expansion, length) = expand(repo, abbreviator, hash, commit, rest)?;
+        match expansion {
+            Some(expansion) => {
+                output.extend(expansion);
+                rest = &rest[length..];
+            }
+            None => output.push(b'%'),
+        }
+    }
+    output.extend(rest.as_bytes());
+    Ok(output)
+}
+
+/// Expands the placeholder at the start of `s`, after its `%`, returning the
+/// expansion and the length of the placeholder.
+fn expand(
+    repo: &Repo,
+    abbreviator: &Abbreviator,
+    hash: &str,
+    commit: &Commit,
+    s: &str,
+) -> Result<(Option<Vec<u8>>, usize)> {
+    let text = |text: String| (Some(text.into_bytes()), 1);
+    let mut chars = s.chars();
+    let Some(placeholder) = chars.next() else {
+        return Ok((None, 0));
+    };
+    Ok(match placeholder {
+        'H' => text(hash.to_string()),
+        'h' => text(abbreviator.abbreviate(hash)?),
+        'T' => text(commit.tree.clone()),
+        't' => text(abbreviator.abbreviate(&commit.tree)?),
+        'P' => text(commit.parents.join(" ")),
+        'p' => {
+            let parents: Result<Vec<String>> = commit
+                .parents
+                .iter()
+                .map(|parent| abbreviator.abbreviate(parent))
+                .collect();
+            text(parents?.join(" "))
+        }
+        'a' | 'c' => {
+            let ident = match placeholder {
+                'a' => &commit.author,
+                _ => &commit.committer,
+            };
+            let Some(ident) = Ident::parse(ident) else {
+                return Ok((None, 0));
+            };
+            let value = match chars.next() {
+                Some('n') => ident.name.clone(),
+                Some('e') => ident.email.clone(),
+                Some('d') => ident.date(DateFormat::Default),
+                Some('D') => ident.date(DateFormat::Rfc2822),
+                Some('t') => ident.timestamp.to_string(),
+                Some('i') => ident.date(DateFormat::Iso),
+                Some('I') => ident.date(DateFormat::IsoStrict),
+                _ => return Ok((None, 0)),
+            };
+            (Some(value.into_bytes()), 2)
+        }
+        's' => text(subject(&commit.message)),
+        // Parsed messages lose their final newline.
+        'b' => text(with_newline(body(&commit.message))),
+        'B' => text(with_newline(&commit.message)),
+        'd' => text(format_decorations(&decorations(repo, hash)?, None)),
+        'D' => {
+            let names: Vec<String> = decorations(repo, hash)?
+                .iter()
+                .map(Decoration::to_string)
+                .collect();
+            text(names.join(", "))
+        }
+        'e' => text(String::new()),
+        'C' => match s[1..].strip_prefix('(') {
+            Some(color) => match color.find(')') {
+                Some(end) => (Some(vec![]), end + 3),
+                None => (None, 0),
+            },
+            None => match ["red", "green", "blue", "reset"]
+                .into_iter()
+                .find(|name| s[1..].starts_with(name))
+            {
+                Some(name) => (Some(vec![]), name.len() + 1),
+                None => (None, 0),
+            },
+        },
+        'n' => text("\n".to_string()),
+        '%' => text("%".to_string()),
+        'x' => match s.get(1..3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) => (Some(vec![byte]), 3),
+            None => (None, 0),
+        },
+        _ => (None, 0),
+    })
+}
+
+/// Returns the subject of a commit message: its first paragraph, on one
+/// line.
+pub fn subject(message: &str) -> String {
+    message
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the body of a commit message: what follows the subject and the
+/// blank lines after it.
+pub fn body(message: &str) -> &str {
+    let mut in_subject = false;
+    let mut after_subject = false;
+    let mut offset = 0;
+    for line in message.split_inclusive('\n') {
+        let is_blank = line.trim().is_empty();
+        if after_subject && !is_blank {
+            return &message[offset..];
+        }
+        in_subject |= !is_blank;
+        after_subject |= in_subject && is_blank;
+        offset += line.len();
+    }
+    ""
+}
+
+fn with_newline(text: &str) -> String {
+    match text.is_empty() || text.ends_with('\n') {
+        true => text.to_string(),
+        false => format!("{text}\n"),
+    }
+}
+
+/// A format of `git log --pretty`: one of git's named formats, or
+/// placeholders expanded by [`format_commit`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The hash and the subject on one line.
+    Oneline,
+    /// The author and the subject.
+    Short,
+    /// The author, their date and the message.
+    #[default]
+    Medium,
+    /// The author, the committer and the message.
+    Full,
+    /// The author, the committer, their dates and the message.
+    Fuller,
+    /// The headers of the commit object as they are, and the message.
+    Raw,
+    /// Placeholders, with a newline after each commit, like `tformat:`.
+    Tformat(String),
+    /// Placeholders, with a newline between commits, like `format:`.
+    Format(String),
+}
+
+impl Format {
+    /// Parses the argument of `--pretty` like git: a named format,
+    /// `format:<placeholders>`, `tformat:<placeholders>`, or placeholders
+    /// alone, which are like `tformat:`.
+    pub fn parse(s: &str) -> Result<Format> {
+        Ok(match s {
+            "oneline" => Format::Oneline,
+            "short" => Format::Short,
+            "medium" => Format::Medium,
+            "full" => Format::Full,
+            "fuller" => Format::Fuller,
+            "raw" => Format::Raw,
+            _ => match s.split_once(':') {
+                Some(("format", format)) => Format::Format(format.to_string()),
+                Some(("tformat", format)) => Format::Tformat(format.to_string()),
+                _ if s.contains('%') => Format::Tformat(s.to_string()),
+                _ => return Err(anyhow!("invalid --pretty format: {s}")),
+            },
+        })
+    }
+}
+
+/// Formats what follows the `commit` line in one of git's named formats
+/// spanning several lines: the headers, then a blank line and the message
+/// indented by four spaces, without its trailing blank lines, or only its
+/// subject for [`Format::Short`]. Nothing for the other formats.
+pub fn format_details(
+    abbreviator: &Abbreviator,
+    commit: &Commit,
+    format: &Format,
+) -> Result<String> {
+    let mut output = String::new();
+    if commit.parents.len() > 1 && *format != Format::Raw {
+        let parents: Result<Vec<String>> = commit
+            .parents
+            .iter()
+            .map(|parent| abbreviator.abbreviate(parent))
+            .collect();
+        output.push_str(&format!("Merge: {}\n", parents?.join(" ")));
+    }
+    // Identities whose date can't be parsed are shown as they are.
+    let ident = |label: &str, ident: &str, date: Option<&str>| match Ident::parse(ident) {
+        Some(ident) => {
+            let mut line = format!("{label}{} <{}>\n", ident.name, ident.email);
+            if let Some(label) = date {
+                line.push_str(&format!("{label}{}\n", ident.date(DateFormat::Default)));
+            }
+            line
+        }
+        None => format!("{label}{ident}\n"),
+    };
+    match format {
+        Format::Short => output.push_str(&ident("Author: ", &commit.author, None)),
+        Format::Medium => output.push_str(&ident("Author: ", &commit.author, Some("Date:   "))),
+        Format::Full => {
+            output.push_str(&ident("Author: ", &commit.author, None));
+            output.push_str(&ident("Commit: ", &commit.committer, None));
+        }
+        Format::Fuller => {
+            output.push_str(&ident("Author:     ", &commit.author, Some("AuthorDate: ")));
+            output.push_str(&ident(
+                "Commit:     ",
+                &commit.committer,
+                Some("CommitDate: "),
+            ));
+        }
+        Format::Raw => {
+            output.push_str(&format!("tree {}\n", commit.tree));
+            for parent in &commit.parents {
+                output.push_str(&format!("parent {parent}\n"));
+            }
+            output.push_str(&format!("author {}\n", commit.author));
+            output.push_str(&format!("committer {}\n", commit.committer));
+        }
+        Format::Oneline | Format::Tformat(_) | Format::Format(_) => return Ok(output),
+    }
+    output.push('\n');
+    let lines: Vec<&str> = commit.message.lines().map(str::trim_end).collect();
+    let start = lines
+        .iter()
+        .position(|line| !line.is_empty())
+        .unwrap_or(lines.len());
+    let end = match format {
+        Format::Short => lines[start..]
+            .iter()
+            .position(|line| line.is_empty())
+            .map_or(lines.len(), |i| start + i),
+        _ => lines
+            .iter()
+            .rposition(|line| !line.is_empty())
+            .map_or(start, |i| i + 1),
+    };
+    for line in &lines[start..end] {
+        output.push_str(&format!("    {line}\n"));
+    }
+    Ok(output)
+}
+
+/// A ref pointing at a commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoration {
+    /// HEAD, with the branch it points to if it's on a branch.
+    Head(Option<String>),
+    /// Another ref, by its full name.
+    Ref(String),
+}
+
+impl fmt::Display for Decoration {
+    /// Formats the decoration like `%D`: `HEAD -> main`, `HEAD`, `tag: v1`
+    /// or the short name of another ref.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decoration::Head(Some(branch)) => write!(f, "HEAD -> {}", refs::shorten(branch)),
+            Decoration::Head(None) => write!(f, "HEAD"),
+            Decoration::Ref(name) if name.starts_with("refs/tags/") => {
+                write!(f, "tag: {}", refs::shorten(name))
+            }
+            Decoration::Ref(name) => write!(f, "{}", refs::shorten(name)),
+        }
+    }
+}
+
+/// Returns the refs pointing at a commit, like `%D`: HEAD first, with the
+/// branch it points to, then the other refs in reverse order.
+pub fn decorations(repo: &Repo, hash: &str) -> Result<Vec<Decoration>> {
+    let mut decorations = vec![];
+    let mut current = None;
+    let head = refs::head(repo)?;
+    for (name, target) in refs::list(repo)?.into_iter().rev() {
+        if Object::peel(repo, &target).ok().as_deref() != Some(hash) {
+            continue;
+        }
+        if head == refs::Head::Branch(name.clone()) {
+            current = Some(name);
+        } else {
+            decorations.push(Decoration::Ref(name));
+        }
+    }
+    match current {
+        Some(current) => decorations.insert(0, Decoration::Head(Some(current))),
+        None if refs::find_ref(repo, "HEAD").is_ok_and(|target| target == hash) => {
+            decorations.insert(0, Decoration::Head(None))
+        }
+        None => {}
+    }
+    Ok(decorations)
+}
+
+/// Formats decorations like `%d`, ` (HEAD -> main, tag: v1)`, or nothing
+/// if there are none.
+///
+/// With colors, like git's `log --decorate`, the parentheses and commas are
+/// in the color of commits and each ref in the color of its kind.
+pub fn format_decorations(decorations: &[Decoration], colors: Option<&DecorateColors>) -> String {
+    if decorations.is_empty() {
+        return String::new();
+    }
+    let Some(colors) = colors else {
+        let names: Vec<String> = decorations.iter().map(Decoration::to_string).collect();
+        return format!(" ({})", names.join(", "));
+    };
+    let ref_color = |name: &str| {
+        let kinds = [
+            ("refs/heads/", &colors.branch),
+            ("refs/remotes/", &colors.remote_branch),
+            ("refs/tags/", &colors.tag),
+            ("refs/stash", &colors.stash),
+        ];
+        kinds
+            .into_iter()
+            .find(|(prefix, _)| name.starts_with(prefix))
+            .map_or(&colors.other, |(_, color)| color)
+    };
+    let punctuation = |text: &str| format!("{}{text}{RESET}", colors.commit);
+    let names: Vec<String> = decorations
+        .iter()
+        .map(|decoration| match decoration {
+            Decoration::Head(Some(branch)) => format!(
+                "{}HEAD -> {RESET}{}{}{RESET}",
+                colors.head,
+                ref_color(branch),
+                refs::shorten(branch)
+            ),
+            Decoration::Head(None) => format!("{}HEAD{RESET}", colors.head),
+            Decoration::Ref(name) => format!("{}{decoration}{RESET}", ref_color(name)),
+        })
+        .collect();
+    format!(
+        "{}{}{}",
+        punctuation(" ("),
+        names.join(&punctuation(", ")),
+        punctuation(")")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subject_and_body() {
+        let message = "\nFirst line\nsecond line\n\n\nBody\n\nMore";
+        assert_eq!(subject(message), "First line second line");
+        assert_eq!(body(message), "Body\n\nMore");
+        assert_eq!(subject("One"), "One");
+        assert_eq!(body("One"), "");
+        assert_eq!(body("One\n\n"), "");
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(Format::parse("fuller").unwrap(), Format::Fuller);
+        assert_eq!(
+            Format::parse("format:%h: %s").unwrap(),
+            Format::Format("%h: %s".to_string())
+        );
+        assert_eq!(
+            Format::parse("tformat:%h").unwrap(),
+            Format::Tformat("%h".to_string())
+        );
+        assert_eq!(
+            Format::parse("%h %s").unwrap(),
+            Format::Tformat("%h %s".to_string())
+        );
+        assert!(Format::parse("bogus").is_err());
+    }
+}
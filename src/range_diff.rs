@@ -0,0 +1,607 @@
+use anyhow::{anyhow, Result};
+use std::{cmp::Ordering, collections::HashMap, io};
+
+use crate::{
+    add_patch::{self, Line},
+    diff::{self, ChangeReader, DiffPathOptions, PatchOptions, RawChange},
+    format_patch,
+    ident::Ident,
+    object::{self, Commit},
+    pretty,
+    repo::Repo,
+    width,
+};
+
+/// The cost of pairing commits that must not be paired.
+const COST_MAX: i64 = 1 << 16;
+
+/// A commit of a range as range-diff compares it, like git's: its author,
+/// message and changes as a patch without line numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub hash: String,
+    pub subject: String,
+    /// The text compared to the patches of the other range.
+    pub text: Vec<u8>,
+    /// Where the changes start in the text, after the author and message.
+    diff_offset: usize,
+    /// The number of lines of the changes.
+    diff_size: usize,
+}
+
+impl Patch {
+    /// Returns the patch of a commit, made of sections like ` ## Metadata ##`
+    /// with the author, ` ## Commit message ##` with the message indented
+    /// like in `git log`, and ` ## path ##` with the hunks of each file.
+    ///
+    /// Hunk headers are `@@ path: function`, or only `@@` for hunks outside
+    /// any function, so that moving changes around doesn't change patches.
+    pub fn new(repo: &Repo, reader: &ChangeReader, hash: &str, commit: &Commit) -> Result<Patch> {
+        let author = Ident::parse(&commit.author)
+            .ok_or_else(|| anyhow!("Invalid author in {hash}: {}", commit.author))?;
+        let mut text = format!(
+            " ## Metadata ##\nAuthor: {} <{}>\n\n ## Commit message ##\n",
+            author.name, author.email
+        )
+        .into_bytes();
+        for line in message_lines(&commit.message) {
+            let line = format!("    {}", expand_tabs(line));
+            text.extend(line.trim_end_matches(is_space).as_bytes());
+            text.push(b'\n');
+        }
+
+        let options = PatchOptions {
+            paths: DiffPathOptions {
+                src_prefix: String::new(),
+                dst_prefix: String::new(),
+                relative: None,
+            },
+            ..Default::default()
+        };
+        let (mut diff_offset, mut diff_size) = (0, 0);
+        for change in format_patch::commit_changes(repo, commit)? {
+            // Like in patches, type changes are a deletion and an addition.
+            let changes = match change.status {
+                'T' => vec![
+                    RawChange {
+                        new: None,
+                        status: 'D',
+                        ..change.clone()
+                    },
+                    RawChange {
+                        old: None,
+                        status: 'A',
+                        ..change
+                    },
+                ],
+                _ => vec![change],
+            };
+            for change in changes {
+                text.push(b'\n');
+                if diff_offset == 0 {
+                    diff_offset = text.len();
+                }
+                let path = &change.path;
+                let section = match (&change.old, &change.new) {
+                    (None, _) => format!("{path} (new)"),
+                    (_, None) => format!("{path} (deleted)"),
+                    (Some((old_mode, _)), Some((new_mode, _))) if old_mode != new_mode => {
+                        format!("{path} (mode change {old_mode:06o} => {new_mode:06o})")
+                    }
+                    _ => path.clone(),
+                };
+                text.extend(format!(" ## {section} ##\n").as_bytes());
+                diff_size += 1;
+
+                let mut patch = vec![];
+                diff::write_patch(reader, &change, &options, &mut patch)?;
+                let body = patch
+                    .split_inclusive(|&b| b == b'\n')
+                    .skip_while(|line| !line.starts_with(b"@@ ") && !line.starts_with(b"Binary"));
+                for line in body {
+                    let line = line.strip_suffix(b"\n").unwrap_or(line);
+                    match line.first() {
+                        Some(b'@') => {
+                            // Only the function is kept from `@@ -1,2 +1,2 @@`.
+                            text.extend(b"@@");
+                            let function = line[2..]
+                                .windows(2)
+                                .position(|window| window == b"@@")
+                                .map_or(&b""[..], |end| &line[end + 4..]);
+                            if !function.is_empty() {
+                                text.extend(format!(" {path}:").as_bytes());
+                            }
+                            text.extend(function);
+                        }
+                        Some(b'+' | b'-' | b' ') => text.extend(line),
+                        _ => {
+                            text.push(b' ');
+                            text.extend(line);
+                        }
+                    }
+                    text.push(b'\n');
+                    diff_size += 1;
+                }
+            }
+        }
+        Ok(Patch {
+            hash: hash.to_string(),
+            subject: pretty::subject(&commit.message),
+            text,
+            diff_offset,
+            diff_size,
+        })
+    }
+
+    /// Returns the changes of the patch, or the whole patch if the commit
+    /// changes nothing.
+    fn diff(&self) -> &[u8] {
+        &self.text[self.diff_offset..]
+    }
+}
+
+/// Returns the patches of the commits of a range like `<a>..<b>`, oldest
+/// first, without merges.
+pub fn read_patches(repo: &Repo, range: &str) -> Result<Vec<Patch>> {
+    let reader = ChangeReader::new(repo)?;
+    let mut commits = crate::walk_revs(repo, &[range.to_string()], false)?;
+    commits.retain(|(_, commit, _)| commit.parents.len() <= 1);
+    commits
+        .iter()
+        .rev()
+        .map(|(hash, commit, _)| Patch::new(repo, &reader, hash, commit))
+        .collect()
+}
+
+/// Writes how the patches of two versions of a series of commits differ,
+/// like `git range-diff`.
+///
+/// Patches are paired by how little they differ, see [`correspondences`],
+/// and shown in the order of the new patches, with the old patches that
+/// have no pair after the patches before them. Each pair is shown as a line
+/// like `1:  abc1234 ! 1:  def5678 Subject`, with `=` for the same patch, `!`
+/// for patches that differ, followed by the diff between them, and `<` or `>`
+/// for patches that only are in the old or the new range.
+pub fn write_range_diff(
+    repo: &Repo,
+    old: &[Patch],
+    new: &[Patch],
+    creation_factor: usize,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let (old_matches, new_matches) = correspondences(old, new, creation_factor);
+    let width = (1 + old.len().max(new.len())).to_string().len();
+    let abbreviator = object::Abbreviator::from_config(repo)?;
+    let mut dashes = None;
+    let mut header = |old: Option<(usize, &Patch)>, new: Option<(usize, &Patch)>| -> Result<()> {
+        let Some((_, first)) = old.or(new) else {
+            return Ok(());
+        };
+        let dashes = match &dashes {
+            Some(dashes) => dashes,
+            None => dashes.insert("-".repeat(abbreviator.abbreviate(&first.hash)?.len())),
+        };
+        let side = |side: Option<(usize, &Patch)>| match side {
+            Some((i, patch)) => Ok(format!(
+                "{:>width$}:  {}",
+                i + 1,
+                abbreviator.abbreviate(&patch.hash)?
+            )),
+            None => anyhow::Ok(format!("{:>width$}:  {dashes}", "-")),
+        };
+        let status = match (old, new) {
+            (_, None) => '<',
+            (None, _) => '>',
+            (Some((_, old)), Some((_, new))) if old.text != new.text => '!',
+            _ => '=',
+        };
+        writeln!(
+            stdout,
+            "{} {status} {} {}",
+            side(old)?,
+            side(new)?,
+            first.subject
+        )?;
+        if let (Some((_, old)), Some((_, new)), '!') = (old, new, status) {
+            write_patch_diff(old, new, stdout)?;
+        }
+        Ok(())
+    };
+
+    let mut shown = vec![false; old.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        while i < old.len() && shown[i] {
+            i += 1;
+        }
+        // The old patches without a pair, once the patches before are shown.
+        if i < old.len() && old_matches[i].is_none() {
+            header(Some((i, &old[i])), None)?;
+            i += 1;
+            continue;
+        }
+        while j < new.len() && new_matches[j].is_none() {
+            header(None, Some((j, &new[j])))?;
+            j += 1;
+        }
+        if let Some(matching) = new_matches.get(j).copied().flatten() {
+            header(Some((matching, &old[matching])), Some((j, &new[j])))?;
+            shown[matching] = true;
+            j += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the diff between two patches, indented, with the sections of the
+/// patches as functions and no line numbers in the hunk headers.
+fn write_patch_diff(old: &Patch, new: &Patch, stdout: &mut dyn io::Write) -> Result<()> {
+    for hunk in add_patch::hunks_with(&old.text, &new.text, 3, true, &section) {
+        let mut header = b"    @@".to_vec();
+        if !hunk.function.is_empty() {
+            header.push(b' ');
+            header.extend(&hunk.function);
+        }
+        header.push(b'\n');
+        stdout.write_all(&header)?;
+        for line in &hunk.lines {
+            let (prefix, text) = match line {
+                Line::Context(text) => (" ", text),
+                Line::Removed(text) => ("-", text),
+                Line::Added(text) => ("+", text),
+            };
+            write!(stdout, "    {prefix}")?;
+            stdout.write_all(text)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the name of the section a line of a patch starts, like
+/// `Metadata` for ` ## Metadata ##` or `path: function` for hunk headers,
+/// like git's funcname patterns for range-diff.
+fn section(line: &[u8]) -> Option<Vec<u8>> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let name = match line.strip_prefix(b" ## ") {
+        Some(rest) if rest.len() >= 3 && rest.ends_with(b" ##") => &rest[..rest.len() - 3],
+        _ => match line.get(1..).and_then(|rest| rest.strip_prefix(b"@@ ")) {
+            Some(name) => name,
+            None => line.strip_prefix(b"@@ ")?,
+        },
+    };
+    let name = &name[..name.len().min(80)];
+    Some(name.trim_ascii_end().to_vec())
+}
+
+/// Returns the lines of a commit message as `git log` shows them, without
+/// the blank lines around.
+fn message_lines(message: &str) -> Vec<&str> {
+    let is_blank = |line: &&str| line.trim_matches(is_space).is_empty();
+    let mut lines: Vec<&str> = message.split('\n').skip_while(is_blank).collect();
+    while lines.last().is_some_and(is_blank) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Returns a line with its tabs expanded to the next multiple of 8 columns,
+/// like `git log` does.
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::new();
+    let mut column = 0;
+    for c in line.chars() {
+        match c {
+            '\t' => {
+                let spaces = 8 - column % 8;
+                expanded.extend(std::iter::repeat(' ').take(spaces));
+                column += spaces;
+            }
+            c => {
+                expanded.push(c);
+                column += width::char_width(c);
+            }
+        }
+    }
+    expanded
+}
+
+/// Returns whether a character is whitespace for C's `isspace`.
+fn is_space(c: char) -> bool {
+    c.is_ascii_whitespace() || c == '\x0b'
+}
+
+/// Pairs the patches of two ranges, returning the index of the pair of each
+/// old patch and each new patch, if any, like git's `get_correspondences`.
+///
+/// Patches with the same changes are paired first. The others are paired so
+/// that the diffs between paired patches are the smallest, where leaving a
+/// patch without a pair costs `creation_factor` percent of its size.
+pub fn correspondences(
+    old: &[Patch],
+    new: &[Patch],
+    creation_factor: usize,
+) -> (Vec<Option<usize>>, Vec<Option<usize>>) {
+    let mut old_matches = vec![None; old.len()];
+    let mut new_matches = vec![None; new.len()];
+    // Like git, the last of the old patches with the same changes is paired
+    // first.
+    let mut same: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (i, patch) in old.iter().enumerate() {
+        same.entry(patch.diff()).or_default().push(i);
+    }
+    for (j, patch) in new.iter().enumerate() {
+        if let Some(i) = same.get_mut(patch.diff()).and_then(Vec::pop) {
+            old_matches[i] = Some(j);
+            new_matches[j] = Some(i);
+        }
+    }
+
+    // The cost of pairing the ith column, an old patch or none, with the
+    // jth row, a new patch or none, is at `i + n * j`.
+    let n = old.len() + new.len();
+    let mut cost = vec![0; n * n];
+    let creation_cost = |patch: &Patch, matching: Option<usize>| match matching {
+        Some(_) => COST_MAX,
+        None => (patch.diff_size * creation_factor / 100) as i64,
+    };
+    for (i, old_patch) in old.iter().enumerate() {
+        for (j, new_patch) in new.iter().enumerate() {
+            cost[i + n * j] = match (old_matches[i], new_matches[j]) {
+                (Some(matching), _) if matching == j => 0,
+                (None, None) => diff_size(old_patch.diff(), new_patch.diff()),
+                _ => COST_MAX,
+            };
+        }
+        for j in new.len()..n {
+            cost[i + n * j] = creation_cost(old_patch, old_matches[i]);
+        }
+    }
+    for (j, new_patch) in new.iter().enumerate() {
+        for i in old.len()..n {
+            cost[i + n * j] = creation_cost(new_patch, new_matches[j]);
+        }
+    }
+
+    let old_to_new = compute_assignment(n, &cost);
+    for (i, &j) in old_to_new.iter().take(old.len()).enumerate() {
+        if j < new.len() {
+            old_matches[i] = Some(j);
+            new_matches[j] = Some(i);
+        }
+    }
+    (old_matches, new_matches)
+}
+
+/// Returns the number of lines of the diff between the changes of two
+/// patches, hunk headers included.
+fn diff_size(old: &[u8], new: &[u8]) -> i64 {
+    add_patch::hunks_with(old, new, 3, false, &|_| None)
+        .iter()
+        .map(|hunk| 1 + hunk.lines.len() as i64)
+        .sum()
+}
+
+/// Returns the row assigned to each column of an `n` by `n` cost matrix so
+/// that the total cost is the lowest, where `cost[column + n * row]` is the
+/// cost of assigning a column to a row.
+///
+/// This is the algorithm of Jonker and Volgenant, ported from git's
+/// `compute_assignment` so that commits are paired the same way, including
+/// when its search ends early and the assignment isn't the cheapest.
+fn compute_assignment(n: usize, cost: &[i64]) -> Vec<usize> {
+    if n < 2 {
+        return vec![0; n];
+    }
+    let cost = |column: usize, row: usize| cost[column + n * row];
+    // Unassigned columns and rows are -1.
+    let mut column_to_row: Vec<isize> = vec![-1; n];
+    let mut row_to_column: Vec<isize> = vec![-1; n];
+    let mut v = vec![0; n];
+
+    // Column reduction, where rows assigned more than once are marked as
+    // -2 - column.
+    for j in (0..n).rev() {
+        let i1 = (1..n).fold(0, |i1, i| match cost(j, i1) > cost(j, i) {
+            true => i,
+            false => i1,
+        });
+        v[j] = cost(j, i1);
+        if row_to_column[i1] == -1 {
+            row_to_column[i1] = j as isize;
+            column_to_row[j] = i1 as isize;
+        } else {
+            if row_to_column[i1] >= 0 {
+                row_to_column[i1] = -2 - row_to_column[i1];
+            }
+            column_to_row[j] = -1;
+        }
+    }
+
+    // Reduction transfer.
+    let mut free_rows = vec![];
+    for (i, column) in row_to_column.iter_mut().enumerate() {
+        match (*column).cmp(&-1) {
+            Ordering::Equal => free_rows.push(i),
+            Ordering::Less => *column = -2 - *column,
+            Ordering::Greater => {
+                let j1 = *column as usize;
+                let min = (0..n)
+                    .filter(|&j| j != j1)
+                    .map(|j| cost(j, i) - v[j])
+                    .min()
+                    .unwrap_or_default();
+                v[j1] -= min;
+            }
+        }
+    }
+    if free_rows.is_empty() {
+        return column_to_row.into_iter().map(|i| i as usize).collect();
+    }
+
+    // Augmenting row reduction.
+    for _ in 0..2 {
+        let saved_free_count = free_rows.len();
+        let mut free_count = 0;
+        let mut k = 0;
+        while k < saved_free_count {
+            let i = free_rows[k];
+            k += 1;
+            let (mut j1, mut j2) = (0, None);
+            let (mut u1, mut u2) = (cost(0, i) - v[0], i64::MAX);
+            for (j, v) in v.iter().enumerate().skip(1) {
+                let c = cost(j, i) - v;
+                if u2 > c {
+                    if u1 < c {
+                        u2 = c;
+                        j2 = Some(j);
+                    } else {
+                        u2 = u1;
+                        u1 = c;
+                        j2 = Some(j1);
+                        j1 = j;
+                    }
+                }
+            }
+            let j2 = match j2 {
+                Some(j2) => j2,
+                None => {
+                    u2 = u1;
+                    j1
+                }
+            };
+
+            let mut i0 = column_to_row[j1];
+            if u1 < u2 {
+                v[j1] -= u2 - u1;
+            } else if i0 >= 0 {
+                j1 = j2;
+                i0 = column_to_row[j1];
+            }
+            if i0 >= 0 {
+                if u1 < u2 {
+                    k -= 1;
+                    free_rows[k] = i0 as usize;
+                } else {
+                    free_rows[free_count] = i0 as usize;
+                    free_count += 1;
+                }
+            }
+            row_to_column[i] = j1 as isize;
+            column_to_row[j1] = i as isize;
+        }
+        free_rows.truncate(free_count);
+    }
+
+    // Augmentation, along the shortest alternating path from each free row.
+    for &i1 in &free_rows {
+        let mut d: Vec<i64> = (0..n).map(|j| cost(j, i1) - v[j]).collect();
+        let mut pred = vec![i1; n];
+        let mut columns: Vec<usize> = (0..n).collect();
+        let (mut low, mut up) = (0, 0);
+        let mut last;
+        let mut min;
+        // Like git, an unassigned column at the minimum distance ends the
+        // search at the last column looked at, which isn't always it.
+        let mut j = None;
+        'search: loop {
+            last = low;
+            min = d[columns[up]];
+            up += 1;
+            let start = up;
+            for k in start..n {
+                j = Some(columns[k]);
+                let c = d[columns[k]];
+                if c <= min {
+                    if c < min {
+                        up = low;
+                        min = c;
+                    }
+                    columns[k] = columns[up];
+                    columns[up] = j.unwrap_or_default();
+                    up += 1;
+                }
+            }
+            if columns[low..up].iter().any(|&j| column_to_row[j] == -1) {
+                break;
+            }
+
+            // Scans the rows of the columns at the minimum distance.
+            while low != up {
+                let j1 = columns[low];
+                low += 1;
+                let i = column_to_row[j1] as usize;
+                let u1 = cost(j1, i) - v[j1] - min;
+                let start = up;
+                for k in start..n {
+                    let column = columns[k];
+                    j = Some(column);
+                    let c = cost(column, i) - v[column] - u1;
+                    if c < d[column] {
+                        d[column] = c;
+                        pred[column] = i;
+                        if c == min {
+                            if column_to_row[column] == -1 {
+                                break 'search;
+                            }
+                            columns[k] = columns[up];
+                            columns[up] = column;
+                            up += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        for &j1 in &columns[..last] {
+            v[j1] += d[j1] - min;
+        }
+        let mut j = j.expect("a column ends the search");
+        loop {
+            let i = pred[j];
+            column_to_row[j] = i as isize;
+            let next = row_to_column[i];
+            row_to_column[i] = j as isize;
+            if i == i1 {
+                break;
+            }
+            j = next as usize;
+        }
+    }
+    column_to_row.into_iter().map(|i| i as usize).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_assignment() {
+        // Rows by columns.
+        let cost = [[4, 1, 3], [2, 0, 5], [3, 2, 2]];
+        let flat: Vec<i64> = (0..9).map(|k| cost[k / 3][k % 3]).collect();
+        assert_eq!(compute_assignment(3, &flat), [1, 0, 2]);
+        assert_eq!(compute_assignment(1, &[5]), [0]);
+    }
+
+    #[test]
+    fn test_section() {
+        assert_eq!(section(b" ## Metadata ##\n"), Some(b"Metadata".to_vec()));
+        assert_eq!(
+            section(b"@@ a.txt: fn main() \n"),
+            Some(b"a.txt: fn main()".to_vec())
+        );
+        assert_eq!(section(b"+@@ b.txt: x\n"), Some(b"b.txt: x".to_vec()));
+        assert_eq!(section(b"@@\n"), None);
+        assert_eq!(section(b" context\n"), None);
+    }
+
+    #[test]
+    fn test_message_lines() {
+        assert_eq!(
+            message_lines("\nSubject\n\n\tBody\n\n"),
+            ["Subject", "", "\tBody"]
+        );
+        assert_eq!(expand_tabs("a\tb\t"), "a       b       ");
+    }
+}
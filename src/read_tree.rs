@@ -0,0 +1,166 @@
+/// The mode and hash of a version of a path, `None` meaning the path is
+/// missing.
+pub type Entry = (u32, String);
+
+/// The result of merging the versions of a path in the index and in the
+/// trees given to `read-tree -m`, with the rules of git's `unpack-trees.c`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Merge {
+    /// Keep the index entry as is, with its stat data.
+    Keep,
+    /// Use this version, or remove the path if there is none.
+    Take(Option<Entry>),
+    /// Leave the path unmerged with its base, ours and theirs versions.
+    Conflict([Option<Entry>; 3]),
+}
+
+/// Keeps the index entry if it's already the result, so that its stat data
+/// isn't lost.
+fn take(index: Option<&Entry>, result: Option<&Entry>) -> Merge {
+    if index == result {
+        Merge::Keep
+    } else {
+        Merge::Take(result.cloned())
+    }
+}
+
+/// Reads a tree into the index, keeping the entries that didn't change.
+pub fn one_way(index: Option<&Entry>, tree: Option<&Entry>) -> Merge {
+    take(index, tree)
+}
+
+/// Moves the index from the `old` tree to the `new` tree, carrying over the
+/// changes staged on top of `old`, like switching branches.
+///
+/// Returns `None` if the path has staged changes that the move would lose.
+pub fn two_way(index: Option<&Entry>, old: Option<&Entry>, new: Option<&Entry>) -> Option<Merge> {
+    let Some(current) = index else {
+        // A staged deletion stays deleted if the path didn't change.
+        return match (old, new) {
+            (Some(old), Some(new)) if old == new => Some(Merge::Keep),
+            (Some(_), Some(_)) => None,
+            _ => Some(take(None, new)),
+        };
+    };
+    if new == Some(current) || (old.is_some() && old == new) || (old.is_none() && new.is_none()) {
+        Some(Merge::Keep)
+    } else if old == Some(current) {
+        Some(Merge::Take(new.cloned()))
+    } else {
+        None
+    }
+}
+
+/// Merges `ours` and `theirs` given their merge `base`, resolving only the
+/// trivial cases: when one side didn't change or both made the same change.
+/// The index must match `ours`, unless it already holds the result.
+///
+/// Returns `None` if the index has changes that the merge would lose.
+pub fn three_way(
+    index: Option<&Entry>,
+    base: Option<&Entry>,
+    ours: Option<&Entry>,
+    theirs: Option<&Entry>,
+) -> Option<Merge> {
+    // A side "matches" the base when it didn't change it, which only makes
+    // sense when the sides differ.
+    let (ours_match, theirs_match) = if ours != theirs {
+        (base == ours, base == theirs)
+    } else {
+        (false, false)
+    };
+
+    // Only theirs changed, and the index may already hold their version.
+    if theirs.is_some() && ours_match && !theirs_match {
+        if index.is_some() && index != theirs && index != ours {
+            return None;
+        }
+        return Some(take(index, theirs));
+    }
+    if index.is_some() && index != ours {
+        return None;
+    }
+    if ours.is_some() && (ours == theirs || (theirs_match && !ours_match)) {
+        return Some(take(index, ours));
+    }
+    if ours.is_none() && theirs.is_none() && base.is_none() {
+        return Some(Merge::Take(None));
+    }
+    Some(Merge::Conflict(
+        [base, ours, theirs].map(|entry| entry.cloned()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(hash: &str) -> Option<Entry> {
+        Some((0o100644, hash.to_string()))
+    }
+
+    #[test]
+    fn test_two_way() {
+        let (a, b, c) = (entry("a"), entry("b"), entry("c"));
+        let two_way = |index: &Option<Entry>, old: &Option<Entry>, new: &Option<Entry>| {
+            two_way(index.as_ref(), old.as_ref(), new.as_ref())
+        };
+        // Unchanged paths are switched to the new version.
+        assert_eq!(two_way(&a, &a, &b), Some(Merge::Take(b.clone())));
+        assert_eq!(two_way(&a, &a, &None), Some(Merge::Take(None)));
+        assert_eq!(two_way(&None, &None, &b), Some(Merge::Take(b.clone())));
+        // Staged changes are kept when the path didn't change.
+        assert_eq!(two_way(&c, &a, &a), Some(Merge::Keep));
+        assert_eq!(two_way(&None, &a, &a), Some(Merge::Keep));
+        assert_eq!(two_way(&c, &None, &None), Some(Merge::Keep));
+        assert_eq!(two_way(&b, &a, &b), Some(Merge::Keep));
+        // Otherwise they would be lost.
+        assert_eq!(two_way(&c, &a, &b), None);
+        assert_eq!(two_way(&None, &a, &b), None);
+        assert_eq!(two_way(&c, &None, &b), None);
+    }
+
+    #[test]
+    fn test_three_way() {
+        let (a, b, c) = (entry("a"), entry("b"), entry("c"));
+        let three_way = |index: &Option<Entry>,
+                         base: &Option<Entry>,
+                         ours: &Option<Entry>,
+                         theirs: &Option<Entry>| {
+            three_way(
+                index.as_ref(),
+                base.as_ref(),
+                ours.as_ref(),
+                theirs.as_ref(),
+            )
+        };
+        // One side changed.
+        assert_eq!(three_way(&a, &a, &a, &b), Some(Merge::Take(b.clone())));
+        assert_eq!(three_way(&b, &a, &a, &b), Some(Merge::Keep));
+        assert_eq!(three_way(&b, &a, &b, &a), Some(Merge::Keep));
+        assert_eq!(
+            three_way(&None, &None, &None, &b),
+            Some(Merge::Take(b.clone()))
+        );
+        assert_eq!(three_way(&b, &None, &b, &None), Some(Merge::Keep));
+        // Both sides made the same change.
+        assert_eq!(three_way(&b, &a, &b, &b), Some(Merge::Keep));
+        // Conflicting changes, including deletions.
+        assert_eq!(
+            three_way(&b, &a, &b, &c),
+            Some(Merge::Conflict([a.clone(), b.clone(), c.clone()]))
+        );
+        assert_eq!(
+            three_way(&a, &a, &a, &None),
+            Some(Merge::Conflict([a.clone(), a.clone(), None]))
+        );
+        assert_eq!(
+            three_way(&None, &a, &None, &None),
+            Some(Merge::Conflict([a.clone(), None, None]))
+        );
+        // The index must match ours.
+        assert_eq!(three_way(&c, &a, &a, &b), None);
+        assert_eq!(three_way(&c, &a, &b, &b), None);
+        assert_eq!(three_way(&c, &None, &None, &b), None);
+    }
+}
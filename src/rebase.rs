@@ -0,0 +1,374 @@
+use anyhow::{anyhow, Result};
+use std::{fs, path::PathBuf};
+
+use crate::{
+    object::{self, Object},
+    repo::Repo,
+};
+
+/// What an [`Instruction`] of a todo list does with its commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Pick the commit.
+    Pick,
+    /// Pick the commit, and edit its message.
+    Reword,
+    /// Pick the commit, and stop to amend it.
+    Edit,
+    /// Meld the commit into the previous one, with both messages.
+    Squash,
+    /// Meld the commit into the previous one, keeping its message.
+    Fixup,
+    /// Leave the commit out.
+    Drop,
+}
+
+impl Command {
+    /// The name of the command in a todo list.
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Pick => "pick",
+            Command::Reword => "reword",
+            Command::Edit => "edit",
+            Command::Squash => "squash",
+            Command::Fixup => "fixup",
+            Command::Drop => "drop",
+        }
+    }
+
+    /// Parses the name of a command, or its first letter.
+    pub fn parse(name: &str) -> Option<Command> {
+        match name {
+            "pick" | "p" => Some(Command::Pick),
+            "reword" | "r" => Some(Command::Reword),
+            "edit" | "e" => Some(Command::Edit),
+            "squash" | "s" => Some(Command::Squash),
+            "fixup" | "f" => Some(Command::Fixup),
+            "drop" | "d" => Some(Command::Drop),
+            _ => None,
+        }
+    }
+
+    /// Whether the command melds its commit into the previous one.
+    pub fn is_fixup(self) -> bool {
+        matches!(self, Command::Squash | Command::Fixup)
+    }
+}
+
+/// A line of a todo list, like `pick <hash> <subject>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub command: Command,
+    pub commit: String,
+}
+
+impl Instruction {
+    pub fn new(command: Command, commit: &str) -> Instruction {
+        Instruction {
+            command,
+            commit: commit.to_string(),
+        }
+    }
+}
+
+/// Parses a todo list like git's: a command and a commit on each line, with
+/// anything after the commit ignored, like its subject. Empty lines,
+/// comments starting with `#` and `noop` are skipped.
+pub fn parse_todo(repo: &Repo, todo: &str) -> Result<Vec<Instruction>> {
+    let mut instructions = vec![];
+    for (number, line) in (1..).zip(todo.lines()) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line == "noop" {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let command = words.next().and_then(Command::parse);
+        let commit = words
+            .next()
+            .and_then(|rev| Object::resolve_rev(repo, rev).ok())
+            .and_then(|hash| Object::peel(repo, &hash).ok());
+        let (Some(command), Some(commit)) = (command, commit) else {
+            return Err(anyhow!("invalid line {number}: {line}"));
+        };
+        instructions.push(Instruction { command, commit });
+    }
+    Ok(instructions)
+}
+
+/// Formats instructions as a todo list, with the subjects of their commits,
+/// and their hashes abbreviated or not.
+pub fn format_todo(repo: &Repo, instructions: &[Instruction], abbreviate: bool) -> Result<String> {
+    let abbreviator = object::Abbreviator::from_config(repo)?;
+    let mut todo = String::new();
+    for instruction in instructions {
+        let subject = match Object::from_hash(repo, &instruction.commit)? {
+            Object::Commit(commit) => commit.message.lines().next().unwrap_or("").to_string(),
+            _ => String::new(),
+        };
+        let hash = match abbreviate {
+            true => abbreviator.abbreviate(&instruction.commit)?,
+            false => instruction.commit.clone(),
+        };
+        todo.push_str(&format!(
+            "{} {hash} {subject}\n",
+            instruction.command.name()
+        ));
+    }
+    Ok(todo)
+}
+
+/// The squashes and fixups melded into the same commit so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixups {
+    pub instructions: Vec<Instruction>,
+    /// The message of the melded commit, with comments numbering the
+    /// messages of the commits, and those of fixups commented out.
+    pub message: String,
+}
+
+/// The state of a rebase in progress, kept in `.git/rebase-merge` like
+/// git's: the branch being rebased in `head-name`, the commit it's rebased
+/// onto in `onto`, where the branch was before in `orig-head`, and the
+/// instructions done and left to do in `done` and `git-rebase-todo`,
+/// counted in `msgnum` and `end`, see [`parse_todo`]. An `interactive` file
+/// tells an interactive rebase apart.
+///
+/// The commit it stopped at is in `stopped-sha`, and its state is a
+/// [`crate::merge::State`], unless it stopped to amend the commit in
+/// `amend`. The squashes and fixups in progress are in `current-fixups`,
+/// with their message in `message-squash`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    /// The branch being rebased, like `refs/heads/topic`, unless HEAD was
+    /// detached.
+    pub head_name: Option<String>,
+    pub onto: String,
+    /// The commit HEAD was at before, to go back to when aborting.
+    pub orig_head: String,
+    pub interactive: bool,
+    /// The instructions done, the last one being the one it stopped at.
+    pub done: Vec<Instruction>,
+    /// The instructions left to do.
+    pub todo: Vec<Instruction>,
+    /// The commit it stopped at, if any.
+    pub stopped: Option<String>,
+    /// The commit made by an `edit` instruction it stopped to amend.
+    pub amend: Option<String>,
+    pub fixups: Option<Fixups>,
+}
+
+impl State {
+    /// Reads the state of the rebase in progress, if any.
+    pub fn read(repo: &Repo) -> Result<Option<State>> {
+        let read = |name: &str| match fs::read_to_string(dir(repo).join(name)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+        let Some(todo) = read("git-rebase-todo")? else {
+            return Ok(None);
+        };
+        let value = |name: &str| -> Result<String> {
+            read(name)?
+                .map(|value| value.trim().to_string())
+                .ok_or_else(|| anyhow!("Invalid rebase state in {}", dir(repo).display()))
+        };
+        let hash = |name: &str| -> Result<Option<String>> {
+            Ok(read(name)?.map(|hash| hash.trim().to_string()))
+        };
+        let fixups = match (read("current-fixups")?, read("message-squash")?) {
+            (Some(fixups), Some(message)) => Some(Fixups {
+                instructions: parse_todo(repo, &fixups)?,
+                message,
+            }),
+            _ => None,
+        };
+        Ok(Some(State {
+            head_name: Some(value("head-name")?).filter(|name| name != "detached HEAD"),
+            onto: value("onto")?,
+            orig_head: value("orig-head")?,
+            interactive: dir(repo).join("interactive").exists(),
+            done: parse_todo(repo, &read("done")?.unwrap_or_default())?,
+            todo: parse_todo(repo, &todo)?,
+            stopped: hash("stopped-sha")?,
+            amend: hash("amend")?,
+            fixups,
+        }))
+    }
+
+    /// Saves the state, with the instructions left to do last so that it's
+    /// only found once complete.
+    pub fn write(&self, repo: &Repo) -> Result<()> {
+        fs::create_dir_all(dir(repo))?;
+        let head_name = self.head_name.as_deref().unwrap_or("detached HEAD");
+        fs::write(dir(repo).join("head-name"), format!("{head_name}\n"))?;
+        fs::write(dir(repo).join("onto"), format!("{}\n", self.onto))?;
+        fs::write(dir(repo).join("orig-head"), format!("{}\n", self.orig_head))?;
+        fs::write(dir(repo).join("msgnum"), format!("{}\n", self.done.len()))?;
+        fs::write(
+            dir(repo).join("end"),
+            format!("{}\n", self.done.len() + self.todo.len()),
+        )?;
+        let write_optional = |name: &str, value: Option<String>| -> Result<()> {
+            match value {
+                Some(value) => fs::write(dir(repo).join(name), value)?,
+                None => {
+                    let _ = fs::remove_file(dir(repo).join(name));
+                }
+            }
+            Ok(())
+        };
+        write_optional("interactive", self.interactive.then(String::new))?;
+        let hash = |hash: &Option<String>| hash.as_ref().map(|hash| format!("{hash}\n"));
+        write_optional("stopped-sha", hash(&self.stopped))?;
+        write_optional("amend", hash(&self.amend))?;
+        let fixups = match &self.fixups {
+            Some(fixups) => Some(format_todo(repo, &fixups.instructions, false)?),
+            None => None,
+        };
+        write_optional("current-fixups", fixups)?;
+        write_optional(
+            "message-squash",
+            self.fixups.as_ref().map(|fixups| fixups.message.clone()),
+        )?;
+        fs::write(
+            dir(repo).join("done"),
+            format_todo(repo, &self.done, false)?,
+        )?;
+        fs::write(
+            dir(repo).join("git-rebase-todo"),
+            format_todo(repo, &self.todo, false)?,
+        )?;
+        Ok(())
+    }
+
+    /// Removes the state once the rebase is over.
+    pub fn remove(repo: &Repo) -> Result<()> {
+        match fs::remove_dir_all(dir(repo)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The directory of the state, `.git/rebase-merge`.
+pub fn dir(repo: &Repo) -> PathBuf {
+    repo.git_dir().join("rebase-merge")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Commit, ObjectType};
+
+    fn commit(repo: &Repo, message: &str) -> String {
+        let commit = Commit {
+            tree: object::write_object(repo, ObjectType::Tree, &[]).unwrap(),
+            author: "A U Thor <author@example.com> 1 +0000".to_string(),
+            committer: "A U Thor <author@example.com> 1 +0000".to_string(),
+            message: message.to_string(),
+            ..Default::default()
+        };
+        object::write_object(repo, ObjectType::Commit, &commit.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_todo() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let (a, b) = (commit(&repo, "First\n"), commit(&repo, "Second\n"));
+        let abbreviator = object::Abbreviator::from_config(&repo).unwrap();
+        let abbrev = abbreviator.abbreviate(&a).unwrap();
+
+        let todo = format!("# Comment\nnoop\n\nr {abbrev} Anything\n  squash {b}\n");
+        assert_eq!(
+            parse_todo(&repo, &todo).unwrap(),
+            [
+                Instruction::new(Command::Reword, &a),
+                Instruction::new(Command::Squash, &b)
+            ]
+        );
+        let instructions = [
+            Instruction::new(Command::Fixup, &a),
+            Instruction::new(Command::Drop, &b),
+        ];
+        let todo = format_todo(&repo, &instructions, true).unwrap();
+        assert_eq!(
+            todo,
+            format!(
+                "fixup {abbrev} First\ndrop {} Second\n",
+                abbreviator.abbreviate(&b).unwrap()
+            )
+        );
+        assert_eq!(parse_todo(&repo, &todo).unwrap(), instructions);
+
+        let error = |todo: &str| parse_todo(&repo, todo).unwrap_err().to_string();
+        assert_eq!(
+            error(&format!("pick {a}\nfrob {a} First")),
+            format!("invalid line 2: frob {a} First")
+        );
+        assert_eq!(
+            error("pick 1234567 One"),
+            "invalid line 1: pick 1234567 One"
+        );
+        assert_eq!(error("edit"), "invalid line 1: edit");
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        assert_eq!(State::read(&repo).unwrap(), None);
+
+        let (a, b, c) = (
+            commit(&repo, "First\n"),
+            commit(&repo, "Second\n"),
+            commit(&repo, "Third\n"),
+        );
+        let mut state = State {
+            head_name: Some("refs/heads/topic".to_string()),
+            onto: c.clone(),
+            orig_head: b.clone(),
+            interactive: false,
+            done: vec![Instruction::new(Command::Pick, &a)],
+            todo: vec![Instruction::new(Command::Pick, &b)],
+            stopped: Some(a.clone()),
+            amend: None,
+            fixups: None,
+        };
+        state.write(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&state));
+        assert_eq!(
+            fs::read_to_string(dir(&repo).join("git-rebase-todo")).unwrap(),
+            format!("pick {b} Second\n")
+        );
+        assert_eq!(fs::read_to_string(dir(&repo).join("end")).unwrap(), "2\n");
+
+        state.done.push(state.todo.remove(0));
+        state.done[1].command = Command::Squash;
+        state.head_name = None;
+        state.interactive = true;
+        state.stopped = None;
+        state.amend = Some(c.clone());
+        state.fixups = Some(Fixups {
+            instructions: vec![state.done[1].clone()],
+            message: "# This is a combination of 2 commits.\n".to_string(),
+        });
+        state.write(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&state));
+        assert_eq!(
+            fs::read_to_string(dir(&repo).join("head-name")).unwrap(),
+            "detached HEAD\n"
+        );
+
+        state.interactive = false;
+        state.fixups = None;
+        state.write(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&state));
+
+        State::remove(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap(), None);
+    }
+}
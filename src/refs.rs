@@ -0,0 +1,759 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::Config, lockfile::LockFile, repo::Repo};
+
+/// What HEAD points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Head {
+    /// HEAD is a symbolic ref to a branch, e.g. `refs/heads/main`.
+    /// The branch doesn't exist until it has a commit.
+    Branch(String),
+    /// HEAD points directly to a commit.
+    Detached(String),
+}
+
+impl Head {
+    /// Parses the raw value of HEAD.
+    pub(crate) fn from_value(value: &str) -> Head {
+        match value.strip_prefix("ref: ") {
+            Some(target) => Head::Branch(target.to_string()),
+            None => Head::Detached(value.to_string()),
+        }
+    }
+}
+
+/// Returns what HEAD points to.
+pub fn head(repo: &Repo) -> Result<Head> {
+    let value = read_ref(repo, "HEAD")?.ok_or(anyhow!("HEAD not found"))?;
+    Ok(Head::from_value(&value))
+}
+
+/// Returns the branch HEAD points to if it has no commits yet, like right
+/// after `init` or on an orphan branch.
+pub fn unborn_branch(repo: &Repo) -> Result<Option<String>> {
+    match head(repo)? {
+        Head::Branch(name) if read_ref(repo, &name)?.is_none() => Ok(Some(name)),
+        _ => Ok(None),
+    }
+}
+
+/// Expands a short ref name into a full one, e.g. `main` into `refs/heads/main`.
+///
+/// The same rules as git are used, in order: `<name>` for names like `HEAD`
+/// or `main-worktree/HEAD`, then `refs/<name>`, `refs/tags/<name>`, `refs/heads/<name>`,
+/// `refs/remotes/<name>` and `refs/remotes/<name>/HEAD`.
+pub fn expand(repo: &Repo, name: &str) -> Option<String> {
+    let is_special = ["refs/", "main-worktree/", "worktrees/"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+        || name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+    let candidates = [
+        is_special.then(|| name.to_string()),
+        Some(format!("refs/{name}")),
+        Some(format!("refs/tags/{name}")),
+        Some(format!("refs/heads/{name}")),
+        Some(format!("refs/remotes/{name}")),
+        Some(format!("refs/remotes/{name}/HEAD")),
+    ];
+    candidates
+        .into_iter()
+        .flatten()
+        .filter(|candidate| validate_name(candidate).is_ok())
+        .find(|candidate| find_ref(repo, candidate).is_ok())
+}
+
+/// Shortens a full ref name for display, e.g. `refs/heads/main` into `main`.
+pub fn shorten(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Points a ref directly at a hash, creating it if needed.
+///
+/// Symbolic refs are not followed, so updating `HEAD` detaches it.
+pub fn update_ref(repo: &Repo, name: &str, hash: &str) -> Result<()> {
+    validate_name(name)?;
+    write_ref_file(repo, name, hash)
+}
+
+/// Makes `name` a symbolic ref pointing to `target`, e.g. `HEAD` to `refs/heads/main`.
+pub fn update_symbolic_ref(repo: &Repo, name: &str, target: &str) -> Result<()> {
+    validate_name(name)?;
+    validate_name(target)?;
+    write_ref_file(repo, name, &format!("ref: {target}"))
+}
+
+/// Writes a loose ref through a lock file, so concurrent readers never see
+/// a partially written ref and concurrent writers fail instead of racing.
+fn write_ref_file(repo: &Repo, name: &str, value: &str) -> Result<()> {
+    let mut lock = LockFile::acquire(&ref_path(repo, name))?;
+    lock.write_all(format!("{value}\n").as_bytes())?;
+    lock.commit()
+}
+
+/// The hash used for missing refs in reflogs and in [`Transaction::update`].
+pub const ZERO_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// Ref updates applied together, like git's ref transactions.
+///
+/// All the refs are locked and checked against their expected values before
+/// any of them is written, so that a concurrent update makes the whole
+/// transaction fail instead of being lost. Updates are recorded in the
+/// reflogs, see [`append_reflog`].
+#[derive(Debug, Default)]
+pub struct Transaction {
+    updates: Vec<RefUpdate>,
+}
+
+#[derive(Debug)]
+struct RefUpdate {
+    name: String,
+    new: String,
+    old: Option<String>,
+    message: String,
+}
+
+impl Transaction {
+    pub fn new() -> Transaction {
+        Transaction::default()
+    }
+
+    /// Points a ref at `new`, following symbolic refs, so that updating
+    /// `HEAD` updates its branch and both reflogs.
+    ///
+    /// If `old` is set, the ref must currently point to it, or not exist if
+    /// it's [`ZERO_HASH`].
+    pub fn update(&mut self, name: &str, new: &str, old: Option<&str>, message: &str) {
+        self.updates.push(RefUpdate {
+            name: name.to_string(),
+            new: new.to_string(),
+            old: old.map(str::to_string),
+            message: message.to_string(),
+        });
+    }
+
+    /// Applies the updates, `committer` being who made them for the reflogs,
+    /// like `Name <email> 1234567890 +0000`. Without a committer, because
+    /// the identity is unknown, the updates aren't logged.
+    pub fn commit(self, repo: &Repo, committer: Option<&str>) -> Result<()> {
+        let cannot_lock = |update: &RefUpdate, reason: String| {
+            anyhow!("cannot lock ref '{}': {reason}", update.name)
+        };
+        let mut locked = vec![];
+        for update in &self.updates {
+            // The refs whose reflogs record the update, from the symbolic ref
+            // to the ref it points to.
+            let mut logged = vec![update.name.clone()];
+            let mut target = update.name.clone();
+            loop {
+                validate_name(&target)?;
+                let Some(value) = read_ref(repo, &target)? else {
+                    break;
+                };
+                match value.strip_prefix("ref: ") {
+                    Some(next) if logged.len() < 5 => {
+                        target = next.to_string();
+                        logged.push(target.clone());
+                    }
+                    Some(_) => return Err(anyhow!("Too many levels of symbolic refs")),
+                    None => break,
+                }
+            }
+            let lock = LockFile::acquire(&ref_path(repo, &target))
+                .map_err(|e| cannot_lock(update, e.to_string()))?;
+            locked.push((update, logged, target, lock));
+        }
+
+        // The values are only read once every ref is locked, so that they
+        // can't change between the check and the write.
+        let mut verified = vec![];
+        for (update, logged, target, lock) in locked {
+            let cannot_lock = |reason: String| cannot_lock(update, reason);
+            let current = read_ref(repo, &target)?;
+            if current
+                .as_deref()
+                .is_some_and(|value| value.starts_with("ref: "))
+            {
+                return Err(cannot_lock(format!("'{target}' became a symbolic ref")));
+            }
+            match (update.old.as_deref(), current.as_deref()) {
+                (None, _) => {}
+                (Some(ZERO_HASH), None) => {}
+                (Some(ZERO_HASH), Some(_)) => {
+                    return Err(cannot_lock("reference already exists".to_string()))
+                }
+                (Some(_), None) => {
+                    return Err(cannot_lock(format!(
+                        "unable to resolve reference '{target}'"
+                    )))
+                }
+                (Some(old), Some(current)) if old != current => {
+                    return Err(cannot_lock(format!("is at {current} but expected {old}")))
+                }
+                _ => {}
+            }
+            verified.push((update, logged, current, lock));
+        }
+
+        for (update, logged, current, mut lock) in verified {
+            lock.write_all(format!("{}\n", update.new).as_bytes())?;
+            lock.commit()?;
+            let old = current.as_deref().unwrap_or(ZERO_HASH);
+            let Some(committer) = committer else {
+                continue;
+            };
+            for name in logged {
+                append_reflog(repo, &name, old, &update.new, committer, &update.message)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Appends an entry to the reflog of a ref at `.git/logs/<name>`, as
+/// `<old> <new> <committer>\t<message>`.
+///
+/// Like git, only refs that already have a reflog are logged, and HEAD,
+/// branches, remote-tracking branches and notes unless
+/// `core.logAllRefUpdates` is false. With `core.logAllRefUpdates=always`,
+/// all refs are logged.
+pub fn append_reflog(
+    repo: &Repo,
+    name: &str,
+    old: &str,
+    new: &str,
+    committer: &str,
+    message: &str,
+) -> Result<()> {
+    let (dir, name_in_dir) = ref_location(repo, name);
+    let path = dir.join("logs").join(name_in_dir);
+    if !path.exists() {
+        let config = Config::load(repo)?;
+        let log_all = match config.get("core.logAllRefUpdates") {
+            Some(value) if value.eq_ignore_ascii_case("always") => true,
+            _ if config.get_bool("core.logAllRefUpdates")? == Some(false) => false,
+            _ => {
+                name == "HEAD"
+                    || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+                        .iter()
+                        .any(|prefix| name.starts_with(prefix))
+            }
+        };
+        if !log_all {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    // A reflog entry is a single line.
+    let message = message.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut line = format!("{old} {new} {committer}");
+    if !message.is_empty() {
+        line.push('\t');
+        line.push_str(&message);
+    }
+    line.push('\n');
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Resolves a ref name (e.g. `HEAD` or `refs/heads/main`) to a hash.
+///
+/// Symbolic refs are followed until a hash is found. Both loose refs and
+/// `packed-refs` are consulted, with loose refs taking precedence.
+pub fn find_ref(repo: &Repo, name: &str) -> Result<String> {
+    resolve(name, |name| read_ref(repo, name))
+}
+
+/// Follows symbolic refs from `name` until a hash is found, reading raw ref
+/// values with `read`.
+pub(crate) fn resolve(name: &str, read: impl Fn(&str) -> Result<Option<String>>) -> Result<String> {
+    let mut name = name.to_string();
+    // Git limits the depth of symbolic refs to 5 as well.
+    for _ in 0..5 {
+        validate_name(&name)?;
+        let value = read(&name)?.ok_or(anyhow!("Reference not found: {name}"))?;
+        match value.strip_prefix("ref: ") {
+            Some(target) => name = target.to_string(),
+            None => return Ok(value),
+        }
+    }
+    Err(anyhow!("Too many levels of symbolic refs"))
+}
+
+/// Returns all refs under `refs/` as (name, hash) pairs, sorted by name.
+///
+/// Symbolic refs are resolved, and refs that cannot be resolved are skipped.
+pub fn list(repo: &Repo) -> Result<Vec<(String, String)>> {
+    let mut names = vec![];
+    collect_worktree_refs(repo, &mut names)?;
+    for (name, _) in read_packed_refs(repo)? {
+        names.push(name);
+    }
+    names.sort();
+    names.dedup();
+
+    Ok(names
+        .into_iter()
+        .filter_map(|name| find_ref(repo, &name).ok().map(|hash| (name, hash)))
+        .collect())
+}
+
+/// Reads the raw values of `HEAD`, the other `*HEAD` refs at the top of the
+/// git folder and all refs under `refs/`, without following symbolic refs.
+///
+/// Loose refs are read before `packed-refs`. Since `pack-refs` writes
+/// `packed-refs` before deleting the loose refs it packed, a ref being packed
+/// concurrently is still found.
+pub(crate) fn read_all(repo: &Repo) -> Result<BTreeMap<String, String>> {
+    let mut names = vec![];
+    for entry in fs::read_dir(repo.git_dir())? {
+        let entry = entry?;
+        if let Some(name) = entry.file_name().to_str() {
+            if name.ends_with("HEAD") && entry.file_type()?.is_file() {
+                names.push(name.to_string());
+            }
+        }
+    }
+    collect_worktree_refs(repo, &mut names)?;
+
+    let mut values = BTreeMap::new();
+    for name in names {
+        if let Some(value) = read_loose_ref(repo, &name)? {
+            values.insert(name, value);
+        }
+    }
+    for (name, hash) in read_packed_refs(repo)? {
+        values.entry(name).or_insert(hash);
+    }
+    Ok(values)
+}
+
+/// Reads the raw value of a ref, without following symbolic refs.
+pub fn read_ref(repo: &Repo, name: &str) -> Result<Option<String>> {
+    if let Some(value) = read_loose_ref(repo, name)? {
+        return Ok(Some(value));
+    }
+    Ok(read_packed_refs(repo)?
+        .into_iter()
+        .find(|(packed_name, _)| packed_name == name)
+        .map(|(_, hash)| hash))
+}
+
+/// Reads the raw value of a loose ref.
+///
+/// Loose refs that are symlinks are only followed if they stay within the
+/// git folder, since a malicious repository could otherwise use them to read
+/// arbitrary files.
+fn read_loose_ref(repo: &Repo, name: &str) -> Result<Option<String>> {
+    let (dir, name_in_dir) = ref_location(repo, name);
+    let path = dir.join(name_in_dir);
+    if path.is_symlink() {
+        let target = fs::canonicalize(&path)?;
+        if !target.starts_with(fs::canonicalize(dir)?) {
+            return Err(anyhow!(
+                "Ref {name} is a symlink pointing outside the repository"
+            ));
+        }
+    }
+    if !path.is_file() {
+        return Ok(None);
+    }
+    match fs::read_to_string(path) {
+        Ok(value) => Ok(Some(value.trim().to_string())),
+        // The ref was deleted or packed since it was checked.
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Reads `packed-refs`, returning (name, hash) pairs.
+///
+/// The format is one `[hash] [name]` per line, with optional comment lines
+/// starting with `#` and peeled tag lines starting with `^`. Per-worktree
+/// refs are never packed, since `packed-refs` is shared, so they're skipped.
+fn read_packed_refs(repo: &Repo) -> Result<Vec<(String, String)>> {
+    let path = repo.common_dir().join("packed-refs");
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+        .filter_map(|line| line.split_once(' '))
+        .filter(|(_, name)| !is_per_worktree(name))
+        .map(|(hash, name)| (name.to_string(), hash.to_string()))
+        .collect())
+}
+
+/// The prefixes of the refs under `refs/` that belong to a worktree.
+const PER_WORKTREE_PREFIXES: [&str; 3] = ["refs/bisect/", "refs/worktree/", "refs/rewritten/"];
+
+/// Returns whether a ref belongs to a worktree rather than being shared by
+/// all worktrees: the refs at the top of the git folder, like `HEAD` or
+/// `MERGE_HEAD`, and the refs under `refs/bisect/`, `refs/worktree/` and
+/// `refs/rewritten/`, like git.
+pub fn is_per_worktree(name: &str) -> bool {
+    !name.contains('/')
+        || PER_WORKTREE_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+/// Returns the folder with the file and reflog of a ref, and the name of the
+/// ref in that folder.
+///
+/// Per-worktree refs are in the git folder of the worktree, see
+/// [`is_per_worktree`], the others in the common git folder. Like git, the
+/// per-worktree refs of other worktrees can be named
+/// `main-worktree/<ref>` and `worktrees/<worktree>/<ref>`.
+fn ref_location<'a>(repo: &Repo, name: &'a str) -> (PathBuf, &'a str) {
+    if let Some(rest) = name.strip_prefix("main-worktree/") {
+        if is_per_worktree(rest) {
+            return (repo.common_dir(), rest);
+        }
+    }
+    let other_worktree = name
+        .strip_prefix("worktrees/")
+        .and_then(|rest| rest.split_once('/'));
+    if let Some((worktree, rest)) = other_worktree {
+        if is_per_worktree(rest) {
+            return (repo.common_dir().join("worktrees").join(worktree), rest);
+        }
+    }
+    match is_per_worktree(name) {
+        true => (repo.git_dir(), name),
+        false => (repo.common_dir(), name),
+    }
+}
+
+/// Returns the path of the file of a ref.
+pub(crate) fn ref_path(repo: &Repo, name: &str) -> PathBuf {
+    let (dir, name) = ref_location(repo, name);
+    dir.join(name)
+}
+
+/// Collects the names of the loose refs under `refs/` of a worktree: the
+/// shared refs and its own per-worktree refs.
+fn collect_worktree_refs(repo: &Repo, names: &mut Vec<String>) -> Result<()> {
+    let mut shared = vec![];
+    collect_loose_refs(&repo.common_dir(), "refs", &mut shared)?;
+    names.extend(shared.into_iter().filter(|name| !is_per_worktree(name)));
+    for prefix in PER_WORKTREE_PREFIXES {
+        collect_loose_refs(&repo.git_dir(), prefix.trim_end_matches('/'), names)?;
+    }
+    Ok(())
+}
+
+fn collect_loose_refs(git_dir: &Path, name: &str, names: &mut Vec<String>) -> Result<()> {
+    let path = git_dir.join(name);
+    if !path.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let Some(file_name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let child = format!("{name}/{file_name}");
+        if entry.file_type()?.is_dir() {
+            collect_loose_refs(git_dir, &child, names)?;
+        } else {
+            names.push(child);
+        }
+    }
+    Ok(())
+}
+
+/// Validates a full ref name (e.g. `refs/heads/main`) using git's rules.
+///
+/// See `git help check-ref-format` for the full list. The rules enforced are:
+/// - no component may begin with `.` or end with `.lock`
+/// - no `..` anywhere
+/// - no ASCII control characters, space, `~`, `^`, `:`, `?`, `*`, `[` or `\`
+/// - may not begin or end with `/`, or contain `//`
+/// - may not end with `.`
+/// - may not contain `@{` and may not be the single character `@`
+pub fn validate_name(name: &str) -> Result<()> {
+    let invalid = |reason: &str| Err(anyhow!("Invalid ref name '{name}': {reason}"));
+
+    if name.is_empty() {
+        return invalid("empty name");
+    }
+    if name == "@" {
+        return invalid("cannot be '@'");
+    }
+    if name.starts_with('/') || name.ends_with('/') {
+        return invalid("cannot begin or end with '/'");
+    }
+    if name.ends_with('.') {
+        return invalid("cannot end with '.'");
+    }
+    if name.contains("..") {
+        return invalid("cannot contain '..'");
+    }
+    if name.contains("@{") {
+        return invalid("cannot contain '@{'");
+    }
+    if name.contains("//") {
+        return invalid("cannot contain '//'");
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| c.is_ascii_control() || " ~^:?*[\\".contains(*c))
+    {
+        return invalid(&format!("cannot contain {c:?}"));
+    }
+    for component in name.split('/') {
+        if component.starts_with('.') {
+            return invalid("components cannot begin with '.'");
+        }
+        if component.ends_with(".lock") {
+            return invalid("components cannot end with '.lock'");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name_accepts_valid_names() {
+        for name in ["refs/heads/main", "refs/tags/v1.2.3", "HEAD", "a/b-c_d/e"] {
+            assert!(validate_name(name).is_ok(), "{name} should be valid");
+        }
+    }
+
+    #[test]
+    fn test_validate_name_rejects_invalid_names() {
+        for name in [
+            "",
+            "@",
+            "/refs/heads/main",
+            "refs/heads/main/",
+            "refs/heads/main.",
+            "refs/heads/a..b",
+            "refs/heads/a@{1}",
+            "refs//heads",
+            "refs/heads/.hidden",
+            "refs/heads/main.lock",
+            "refs/heads/with space",
+            "refs/heads/tab\t",
+            "refs/heads/star*",
+            "refs/heads/back\\slash",
+        ] {
+            assert!(validate_name(name).is_err(), "{name:?} should be invalid");
+        }
+    }
+
+    fn write_ref(repo: &Repo, name: &str, value: &str) {
+        let path = repo.git_dir().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, format!("{value}\n")).unwrap();
+    }
+
+    #[test]
+    fn test_find_ref_follows_symbolic_and_packed_refs() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        write_ref(&repo, "HEAD", "ref: refs/heads/main");
+        write_ref(
+            &repo,
+            "packed-refs",
+            "# pack-refs with: peeled\n\
+             aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa refs/heads/main\n\
+             bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb refs/tags/v1\n\
+             ^cccccccccccccccccccccccccccccccccccccccc",
+        );
+
+        assert_eq!(
+            find_ref(&repo, "HEAD").unwrap(),
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"
+        );
+
+        // Loose refs take precedence over packed refs.
+        write_ref(
+            &repo,
+            "refs/heads/main",
+            "dddddddddddddddddddddddddddddddddddddddd",
+        );
+        assert_eq!(
+            find_ref(&repo, "HEAD").unwrap(),
+            "dddddddddddddddddddddddddddddddddddddddd"
+        );
+        assert_eq!(
+            list(&repo).unwrap(),
+            vec![
+                (
+                    "refs/heads/main".to_string(),
+                    "dddddddddddddddddddddddddddddddddddddddd".to_string()
+                ),
+                (
+                    "refs/tags/v1".to_string(),
+                    "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            find_ref(&repo, "refs/heads/nope").unwrap_err().to_string(),
+            "Reference not found: refs/heads/nope"
+        );
+    }
+
+    #[test]
+    fn test_head_and_detached_head() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        update_symbolic_ref(&repo, "HEAD", "refs/heads/main").unwrap();
+        assert_eq!(
+            head(&repo).unwrap(),
+            Head::Branch("refs/heads/main".to_string())
+        );
+
+        update_ref(&repo, "HEAD", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").unwrap();
+        assert_eq!(
+            head(&repo).unwrap(),
+            Head::Detached("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_string())
+        );
+        assert_eq!(
+            find_ref(&repo, "HEAD").unwrap(),
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"
+        );
+        assert!(!repo.git_dir().join("HEAD.lock").exists());
+    }
+
+    #[test]
+    fn test_transaction() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        write_ref(&repo, "HEAD", "ref: refs/heads/main");
+        let committer = "Alice <alice@example.com> 1234 +0000";
+        let (a, b) = ("a".repeat(40), "b".repeat(40));
+
+        // Updating HEAD updates its branch, and logs both.
+        let mut transaction = Transaction::new();
+        transaction.update("HEAD", &a, Some(ZERO_HASH), "commit (initial): A");
+        transaction.commit(&repo, Some(committer)).unwrap();
+        assert_eq!(find_ref(&repo, "refs/heads/main").unwrap(), a);
+        assert_eq!(
+            head(&repo).unwrap(),
+            Head::Branch("refs/heads/main".to_string())
+        );
+        let entry = format!("{ZERO_HASH} {a} {committer}\tcommit (initial): A\n");
+        for log in ["logs/HEAD", "logs/refs/heads/main"] {
+            assert_eq!(fs::read_to_string(repo.git_dir().join(log)).unwrap(), entry);
+        }
+
+        // Nothing is updated if a ref moved.
+        let mut transaction = Transaction::new();
+        transaction.update("refs/heads/other", &b, None, "");
+        transaction.update("HEAD", &b, Some(&b), "commit: B");
+        assert_eq!(
+            transaction
+                .commit(&repo, Some(committer))
+                .unwrap_err()
+                .to_string(),
+            format!("cannot lock ref 'HEAD': is at {a} but expected {b}")
+        );
+        assert!(find_ref(&repo, "refs/heads/other").is_err());
+        assert!(!repo.git_dir().join("refs/heads/other.lock").exists());
+
+        let mut transaction = Transaction::new();
+        transaction.update("refs/heads/main", &b, Some(ZERO_HASH), "");
+        assert_eq!(
+            transaction
+                .commit(&repo, Some(committer))
+                .unwrap_err()
+                .to_string(),
+            "cannot lock ref 'refs/heads/main': reference already exists"
+        );
+
+        // Tags aren't logged by default.
+        let mut transaction = Transaction::new();
+        transaction.update("refs/tags/v1", &b, None, "tag");
+        transaction.commit(&repo, Some(committer)).unwrap();
+        assert!(!repo.git_dir().join("logs/refs/tags/v1").exists());
+    }
+
+    #[test]
+    fn test_update_ref_fails_if_locked() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        write_ref(&repo, "refs/heads/main.lock", "");
+        assert!(update_ref(&repo, "refs/heads/main", "aaaa").is_err());
+        assert!(update_ref(&repo, "refs/heads/../../oops", "aaaa").is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        write_ref(&repo, "HEAD", "ref: refs/heads/main");
+        write_ref(&repo, "refs/heads/main", "aaaa");
+        write_ref(&repo, "refs/tags/v1", "bbbb");
+        write_ref(
+            &repo,
+            "refs/remotes/origin/HEAD",
+            "ref: refs/remotes/origin/main",
+        );
+        write_ref(&repo, "refs/remotes/origin/main", "cccc");
+
+        assert_eq!(expand(&repo, "HEAD").unwrap(), "HEAD");
+        assert_eq!(expand(&repo, "main").unwrap(), "refs/heads/main");
+        assert_eq!(expand(&repo, "v1").unwrap(), "refs/tags/v1");
+        assert_eq!(expand(&repo, "origin").unwrap(), "refs/remotes/origin/HEAD");
+        assert_eq!(expand(&repo, "config"), None);
+        assert_eq!(expand(&repo, "../config"), None);
+    }
+
+    #[test]
+    fn test_find_ref_rejects_refs_escaping_the_repository() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(&tmpdir.path().join("repo"));
+        write_ref(&repo, "HEAD", "ref: refs/heads/../../../secret");
+        fs::write(tmpdir.path().join("secret"), "aaaa").unwrap();
+        assert_eq!(
+            find_ref(&repo, "HEAD").unwrap_err().to_string(),
+            "Invalid ref name 'refs/heads/../../../secret': cannot contain '..'"
+        );
+
+        #[cfg(unix)]
+        {
+            fs::create_dir_all(repo.git_dir().join("refs/heads")).unwrap();
+            std::os::unix::fs::symlink(
+                tmpdir.path().join("secret"),
+                repo.git_dir().join("refs/heads/evil"),
+            )
+            .unwrap();
+            assert_eq!(
+                find_ref(&repo, "refs/heads/evil").unwrap_err().to_string(),
+                "Ref refs/heads/evil is a symlink pointing outside the repository"
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_name_error_message() {
+        assert_eq!(
+            validate_name("refs/heads/a..b").unwrap_err().to_string(),
+            "Invalid ref name 'refs/heads/a..b': cannot contain '..'"
+        );
+    }
+}
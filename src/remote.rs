@@ -0,0 +1,530 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+};
+
+use crate::{
+    config::Config,
+    object::{self, Object, ObjectType},
+    repo::Repo,
+};
+
+/// A repository to push to or fetch from, named in the config or given by
+/// URL.
+#[derive(Debug)]
+pub struct Remote {
+    /// The name of the remote, `None` when a URL was given.
+    pub name: Option<String>,
+    /// The URL, as configured or given.
+    pub url: String,
+    pub repo: Repo,
+}
+
+impl Remote {
+    /// Opens a remote by name, from `remote.<name>.url`, or by URL.
+    ///
+    /// Only local repositories are supported, as paths or `file://` URLs.
+    /// Relative paths are relative to the root of the working tree.
+    pub fn open(repo: &Repo, config: &Config, remote: &str) -> Result<Remote> {
+        let not_a_repository = || anyhow!("'{remote}' does not appear to be a git repository");
+        let (name, url) = match config.get(&format!("remote.{remote}.url")) {
+            Some(url) => (Some(remote.to_string()), url.to_string()),
+            None => (None, remote.to_string()),
+        };
+        let path = match url.split_once("://") {
+            Some(("file", path)) => path,
+            Some(_) => return Err(anyhow!("Unsupported URL: {url}")),
+            None => url.as_str(),
+        };
+        check_protocol(config, "file")?;
+        let path = repo.root.join(path);
+        let remote_repo = if path.join(".git").exists() {
+            Repo::new(&path)
+        } else if path.join("HEAD").is_file() && path.join("objects").is_dir() {
+            Repo::bare(&path)
+        } else {
+            return Err(not_a_repository());
+        };
+        Ok(Remote {
+            name,
+            url,
+            repo: remote_repo,
+        })
+    }
+
+    /// Returns the remote-tracking branch of a branch of the remote, like
+    /// `refs/remotes/origin/main` for `refs/heads/main`. Remotes given by URL
+    /// have none.
+    pub fn tracking_ref(&self, remote_ref: &str) -> Option<String> {
+        let name = self.name.as_ref()?;
+        let branch = remote_ref.strip_prefix("refs/heads/")?;
+        Some(format!("refs/remotes/{name}/{branch}"))
+    }
+}
+
+/// Fails unless a transport protocol is allowed by `protocol.<name>.allow`,
+/// or else `protocol.allow`: `always`, `never`, or `user` to only allow it
+/// when `GIT_PROTOCOL_FROM_USER` isn't `0`, so that commands run on behalf of
+/// another repository can be restricted. Like git, `file` defaults to `user`.
+fn check_protocol(config: &Config, protocol: &str) -> Result<()> {
+    let key = format!("protocol.{protocol}.allow");
+    let (key, value) = match config.get(&key) {
+        Some(value) => (key.as_str(), value),
+        None => (
+            "protocol.allow",
+            config.get("protocol.allow").unwrap_or("user"),
+        ),
+    };
+    let allowed = match value {
+        "always" => true,
+        "never" => false,
+        "user" => std::env::var("GIT_PROTOCOL_FROM_USER").map_or(true, |value| value != "0"),
+        _ => return Err(anyhow!("unknown value for config '{key}': {value}")),
+    };
+    match allowed {
+        true => Ok(()),
+        false => Err(anyhow!("transport '{protocol}' not allowed")),
+    }
+}
+
+/// Returns whether a repository hides a ref from other repositories, by
+/// `transfer.hideRefs` or `<section>.hideRefs`, the section being
+/// `uploadpack` for fetches and `receive` for pushes.
+///
+/// Each value hides the refs it's a prefix of, up to a `/`, or shows them
+/// again if it starts with `!`. The last matching value wins.
+pub fn is_hidden(config: &Config, section: &str, name: &str) -> bool {
+    let mut values = config.get_all("transfer.hideRefs");
+    values.extend(config.get_all(&format!("{section}.hideRefs")));
+    values
+        .iter()
+        .rev()
+        .find_map(|value| {
+            let (hide, prefix) = match value.strip_prefix('!') {
+                Some(prefix) => (false, prefix),
+                None => (true, *value),
+            };
+            // `^` marks full ref names, which are the names here since
+            // namespaces aren't supported.
+            let prefix = prefix.strip_prefix('^').unwrap_or(prefix);
+            let rest = name.strip_prefix(prefix.trim_end_matches('/'))?;
+            (rest.is_empty() || rest.starts_with('/')).then_some(hide)
+        })
+        .unwrap_or(false)
+}
+
+/// A refspec, `[+]<src>[:<dst>]`: what to push or fetch and which ref to
+/// update with it, `+` allowing updates that aren't fast-forwards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+    pub force: bool,
+    pub src: String,
+    /// The ref to update, `None` to update the ref with the name of `src`
+    /// when pushing.
+    pub dst: Option<String>,
+}
+
+impl Refspec {
+    pub fn parse(refspec: &str) -> Result<Refspec> {
+        let (force, rest) = match refspec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, refspec),
+        };
+        let (src, dst) = match rest.split_once(':') {
+            Some((src, dst)) => (src, Some(dst.to_string())),
+            None => (rest, None),
+        };
+        if src.is_empty() {
+            return Err(anyhow!("Deleting remote refs is not supported: {refspec}"));
+        }
+        if dst.as_deref() == Some("") {
+            return Err(anyhow!("Invalid refspec '{refspec}'"));
+        }
+        Ok(Refspec {
+            force,
+            src: src.to_string(),
+            dst,
+        })
+    }
+
+    /// Returns whether the refspec is a pattern like
+    /// `refs/heads/*:refs/remotes/origin/*`, which matches many refs.
+    pub fn is_pattern(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    /// Returns the destination of a ref matched by a pattern, where the `*`
+    /// of the destination is replaced by what the `*` of the source matched,
+    /// or `None` if the ref doesn't match.
+    pub fn map_pattern(&self, name: &str) -> Option<String> {
+        let (prefix, suffix) = self.src.split_once('*')?;
+        let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+        let dst = self.dst.as_deref().unwrap_or(&self.src);
+        Some(dst.replacen('*', matched, 1))
+    }
+}
+
+/// What `push` pushes when no refspec is given, from `push.default`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PushDefault {
+    /// Nothing, refspecs must be given.
+    Nothing,
+    /// The current branch, to the branch with the same name.
+    Current,
+    /// The current branch, to its upstream branch.
+    Upstream,
+    /// The current branch, to its upstream branch if it has the same name
+    /// when pushing to the remote it comes from, and like `Current` when
+    /// pushing to another remote.
+    #[default]
+    Simple,
+    /// All the branches that have a branch with the same name on the remote.
+    Matching,
+}
+
+impl PushDefault {
+    pub fn from_config(config: &Config) -> Result<PushDefault> {
+        match config.get("push.default") {
+            None | Some("simple") => Ok(PushDefault::Simple),
+            Some("nothing") => Ok(PushDefault::Nothing),
+            Some("current") => Ok(PushDefault::Current),
+            // `tracking` is the deprecated name of `upstream`.
+            Some("upstream" | "tracking") => Ok(PushDefault::Upstream),
+            Some("matching") => Ok(PushDefault::Matching),
+            Some(value) => Err(anyhow!("malformed value for push.default: {value}")),
+        }
+    }
+}
+
+/// Copies the objects reachable from `tip` that the remote doesn't have to
+/// it, and returns how many were copied.
+pub fn send_objects(repo: &Repo, remote: &Repo, tip: &str) -> Result<usize> {
+    let missing = missing_objects(repo, remote, &[tip.to_string()])?;
+    copy_objects(repo, remote, missing.iter())?;
+    Ok(missing.len())
+}
+
+/// Returns the objects reachable from `tips` that the remote doesn't have.
+///
+/// The remote is assumed to have the whole history of the commits it has,
+/// so they aren't walked. Objects come before the objects pointing to them,
+/// so that copying them in order never leaves a commit without its history,
+/// even if interrupted.
+pub fn missing_objects(repo: &Repo, remote: &Repo, tips: &[String]) -> Result<Vec<String>> {
+    let mut pending = tips.to_vec();
+    let mut seen = HashSet::new();
+    let mut missing = vec![];
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash.clone()) || object::exists(remote, &hash) {
+            continue;
+        }
+        // Blobs don't point to anything, so they aren't read.
+        if object::read_header(repo, &hash)?.0 != ObjectType::Blob {
+            match Object::from_hash(repo, &hash)? {
+                Object::Commit(commit) => {
+                    pending.push(commit.tree);
+                    pending.extend(commit.parents);
+                }
+                Object::Tree(tree) => pending.extend(
+                    tree.files
+                        .into_iter()
+                        // Submodules are commits of another repository.
+                        .filter(|file| file.mode != "160000")
+                        .map(|file| file.hash),
+                ),
+                Object::Tag(tag) => pending.push(tag.object),
+                Object::Blob(_) => {}
+            }
+        }
+        missing.push(hash);
+    }
+    missing.reverse();
+    Ok(missing)
+}
+
+/// Returns the size of objects as stored, compressed, in a repository.
+pub fn stored_size(repo: &Repo, hashes: &[String]) -> Result<u64> {
+    let mut size = 0;
+    for hash in hashes {
+        size += fs::metadata(object::object_path(repo, hash)?)?.len();
+    }
+    Ok(size)
+}
+
+/// How much history [`fetch_objects`] fetches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Depth {
+    /// All of it, except below the shallow commits of a shallow repository.
+    #[default]
+    Full,
+    /// Up to this many commits from each fetched commit, like `--depth`.
+    Commits(usize),
+    /// This many more commits below the current shallow commits, like
+    /// `--deepen`.
+    Deepen(usize),
+    /// The commits made at or after a timestamp, like `--shallow-since`.
+    Since(i64),
+    /// The commits that can't be reached from these commits of the remote,
+    /// like `--shallow-exclude`.
+    Exclude(Vec<String>),
+    /// All of it, including below the shallow commits, like `--unshallow`.
+    Unshallow,
+}
+
+/// Copies the commits reachable from `tips` that the repository doesn't
+/// have from the remote, with their trees, as limited by `depth`, and
+/// updates the shallow commits of the repository. Returns how many objects
+/// were copied.
+///
+/// The commits whose parents aren't copied become shallow, and the shallow
+/// commits whose parents are copied stop being shallow. Like git, shallow
+/// commits that are out of the requested depth stay shallow.
+pub fn fetch_objects(
+    remote: &Repo,
+    repo: &Repo,
+    tips: &[String],
+    depth: &Depth,
+    shallow: &mut HashSet<String>,
+) -> Result<usize> {
+    let excluded = match depth {
+        Depth::Exclude(hashes) => reachable_commits(remote, hashes)?,
+        _ => HashSet::new(),
+    };
+    let mut timestamps = HashMap::new();
+    let mut timestamp = |hash: &str| -> Result<i64> {
+        if let Some(timestamp) = timestamps.get(hash) {
+            return Ok(*timestamp);
+        }
+        let Object::Commit(commit) = Object::from_hash(remote, hash)? else {
+            return Err(anyhow!("Not a commit: {hash}"));
+        };
+        timestamps.insert(hash.to_string(), commit.committer_timestamp());
+        Ok(commit.committer_timestamp())
+    };
+
+    // The number of generations of parents to fetch below each commit,
+    // `None` for all of them. A commit is visited again if it's reached
+    // with more generations to fetch.
+    let mut visited: HashMap<String, Option<usize>> = HashMap::new();
+    // Deepening starts from the shallow commits, which may not be reachable
+    // from new commits. Dates and excluded refs can deepen them too.
+    let mut tips = tips.to_vec();
+    if !matches!(depth, Depth::Full | Depth::Commits(_)) {
+        tips.extend(shallow.iter().cloned());
+    }
+    let mut pending: VecDeque<(String, Option<usize>)> = tips
+        .into_iter()
+        .map(|tip| match depth {
+            Depth::Commits(commits) => (tip, Some(commits.saturating_sub(1))),
+            _ => (tip, None),
+        })
+        .collect();
+    let mut missing = vec![];
+    let mut seen_objects = HashSet::new();
+    while let Some((hash, mut generations)) = pending.pop_front() {
+        let has_commit = object::exists(repo, &hash);
+        if has_commit && !shallow.contains(&hash) {
+            continue;
+        }
+        if has_commit {
+            generations = match depth {
+                Depth::Full => Some(0),
+                Depth::Deepen(commits) => Some(*commits),
+                _ => generations,
+            };
+        }
+        let more = |previous: &Option<usize>| match (previous, generations) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(previous), Some(generations)) => generations > *previous,
+        };
+        if visited.get(&hash).is_some_and(|previous| !more(previous)) {
+            continue;
+        }
+        visited.insert(hash.clone(), generations);
+
+        let Object::Commit(commit) = Object::from_hash(remote, &hash)? else {
+            return Err(anyhow!("Not a commit: {hash}"));
+        };
+        if !has_commit && seen_objects.insert(hash.clone()) {
+            missing.push(hash.clone());
+            collect_tree(remote, repo, &commit.tree, &mut seen_objects, &mut missing)?;
+        }
+        let mut fetch_parents = generations != Some(0);
+        for parent in &commit.parents {
+            fetch_parents &= match depth {
+                Depth::Since(since) => timestamp(parent)? >= *since,
+                Depth::Exclude(_) => !excluded.contains(parent),
+                _ => true,
+            };
+        }
+        if !fetch_parents {
+            if !commit.parents.is_empty() {
+                shallow.insert(hash);
+            }
+            continue;
+        }
+        shallow.remove(&hash);
+        let generations = generations.map(|generations| generations.saturating_sub(1));
+        pending.extend(
+            commit
+                .parents
+                .into_iter()
+                .map(|parent| (parent, generations)),
+        );
+    }
+
+    copy_objects(remote, repo, missing.iter().rev())?;
+    Ok(missing.len())
+}
+
+/// Returns the commits reachable from some commits.
+fn reachable_commits(repo: &Repo, tips: &[String]) -> Result<HashSet<String>> {
+    let mut reachable = HashSet::new();
+    let mut pending = tips.to_vec();
+    while let Some(hash) = pending.pop() {
+        if !reachable.insert(hash.clone()) {
+            continue;
+        }
+        if let Object::Commit(commit) = Object::from_hash(repo, &hash)? {
+            pending.extend(commit.parents);
+        }
+    }
+    Ok(reachable)
+}
+
+/// Adds the objects of a tree that `repo` doesn't have to `missing`, trees
+/// before the objects they contain.
+fn collect_tree(
+    remote: &Repo,
+    repo: &Repo,
+    hash: &str,
+    seen: &mut HashSet<String>,
+    missing: &mut Vec<String>,
+) -> Result<()> {
+    if !seen.insert(hash.to_string()) || object::exists(repo, hash) {
+        return Ok(());
+    }
+    missing.push(hash.to_string());
+    let Object::Tree(tree) = Object::from_hash(remote, hash)? else {
+        return Err(anyhow!("Not a tree: {hash}"));
+    };
+    for file in tree.files {
+        match file.object_type() {
+            ObjectType::Tree => collect_tree(remote, repo, &file.hash, seen, missing)?,
+            ObjectType::Blob if seen.insert(file.hash.clone()) => {
+                if !object::exists(repo, &file.hash) {
+                    missing.push(file.hash);
+                }
+            }
+            // Submodules are commits of another repository.
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Copies loose objects from one repository to another.
+pub(crate) fn copy_objects<'a>(
+    from: &Repo,
+    to: &Repo,
+    hashes: impl Iterator<Item = &'a String>,
+) -> Result<()> {
+    for hash in hashes {
+        let path = object::object_path(to, hash)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Copy to a temporary file first, so that the object never appears
+        // partially written.
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::copy(object::object_path(from, hash)?, &tmp_path)?;
+        fs::rename(&tmp_path, &path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_refspec() {
+        assert_eq!(
+            Refspec::parse("main").unwrap(),
+            Refspec {
+                force: false,
+                src: "main".to_string(),
+                dst: None,
+            }
+        );
+        assert_eq!(
+            Refspec::parse("+HEAD:refs/heads/other").unwrap(),
+            Refspec {
+                force: true,
+                src: "HEAD".to_string(),
+                dst: Some("refs/heads/other".to_string()),
+            }
+        );
+        let pattern = Refspec::parse("+refs/heads/*:refs/remotes/origin/*").unwrap();
+        assert!(pattern.is_pattern());
+        assert_eq!(
+            pattern.map_pattern("refs/heads/feature/a").as_deref(),
+            Some("refs/remotes/origin/feature/a")
+        );
+        assert_eq!(pattern.map_pattern("refs/tags/v1"), None);
+        assert!(Refspec::parse(":main").is_err());
+        assert!(Refspec::parse("main:").is_err());
+    }
+
+    #[test]
+    fn test_is_hidden() {
+        let config = Config::parse(
+            "[transfer]\n\thideRefs = refs/heads/hidden/\n\thideRefs = refs/heads/k\n\
+             [uploadpack]\n\thideRefs = !refs/heads/hidden/shown\n",
+        )
+        .unwrap();
+        assert!(is_hidden(&config, "uploadpack", "refs/heads/hidden/x"));
+        assert!(is_hidden(&config, "uploadpack", "refs/heads/k"));
+        assert!(!is_hidden(&config, "uploadpack", "refs/heads/keep"));
+        assert!(!is_hidden(&config, "uploadpack", "refs/heads/hidden/shown"));
+        assert!(is_hidden(&config, "receive", "refs/heads/hidden/shown"));
+        assert!(!is_hidden(&config, "receive", "refs/heads/main"));
+    }
+
+    #[test]
+    fn test_check_protocol() {
+        let config = Config::parse("[protocol]\n\tallow = never\n").unwrap();
+        assert_eq!(
+            check_protocol(&config, "file").unwrap_err().to_string(),
+            "transport 'file' not allowed"
+        );
+        let config =
+            Config::parse("[protocol]\n\tallow = never\n[protocol \"file\"]\n\tallow = always\n")
+                .unwrap();
+        assert!(check_protocol(&config, "file").is_ok());
+        let config = Config::parse("[protocol \"file\"]\n\tallow = bogus\n").unwrap();
+        assert_eq!(
+            check_protocol(&config, "file").unwrap_err().to_string(),
+            "unknown value for config 'protocol.file.allow': bogus"
+        );
+    }
+
+    #[test]
+    fn test_push_default() {
+        let push_default = |config: &str| PushDefault::from_config(&Config::parse(config).unwrap());
+        assert_eq!(push_default("").unwrap(), PushDefault::Simple);
+        assert_eq!(
+            push_default("[push]\n\tdefault = tracking").unwrap(),
+            PushDefault::Upstream
+        );
+        assert_eq!(
+            push_default("[push]\n\tdefault = other")
+                .unwrap_err()
+                .to_string(),
+            "malformed value for push.default: other"
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    object::{Blob, Object},
+    read_tree::Entry,
+    repo::Repo,
+};
+
+/// The score of identical files, like git's.
+pub const MAX_SCORE: u64 = 60000;
+
+/// The score files need to be a rename by default, 50%.
+pub const DEFAULT_MIN_SCORE: u64 = MAX_SCORE / 2;
+
+/// Lines are hashed in spans of at most this many bytes.
+const MAX_SPAN: usize = 64;
+
+/// Span hashes are taken modulo this prime.
+const HASH_BASE: u32 = 107927;
+
+/// A rename found by [`find_renames`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub old_path: String,
+    pub new_path: String,
+    /// How similar the contents are, from 0 to [`MAX_SCORE`].
+    pub score: u64,
+}
+
+/// Pairs deleted files with added files whose contents are similar, like
+/// git's rename detection: first the files with identical contents,
+/// preferring the same file name, then the remaining pairs with a score of
+/// at least `min_score`, best first. A file is paired at most once.
+///
+/// Empty files and files of different types aren't paired.
+pub fn find_renames(
+    repo: &Repo,
+    deleted: &BTreeMap<String, Entry>,
+    added: &BTreeMap<String, Entry>,
+    min_score: u64,
+) -> Result<Vec<Rename>> {
+    let is_blob = |(mode, _): &Entry| *mode != 0o040000 && *mode != 0o160000;
+    let empty_blob = Blob::new(vec![]).hash();
+    let candidates = |files: &BTreeMap<String, Entry>| -> Vec<(String, Entry)> {
+        files
+            .iter()
+            .filter(|(_, entry)| is_blob(entry) && entry.1 != empty_blob)
+            .map(|(path, entry)| (path.clone(), entry.clone()))
+            .collect()
+    };
+    let mut sources = candidates(deleted);
+    let mut destinations = candidates(added);
+    let mut renames = vec![];
+
+    // Identical contents first.
+    destinations.retain(|(new_path, (new_mode, new_hash))| {
+        let same = |(_, (mode, hash)): &&(String, Entry)| {
+            hash == new_hash && is_regular(*mode) == is_regular(*new_mode)
+        };
+        let found = sources
+            .iter()
+            .filter(same)
+            .find(|(old_path, _)| basename(old_path) == basename(new_path))
+            .or_else(|| sources.iter().find(same))
+            .map(|(old_path, _)| old_path.clone());
+        let Some(old_path) = found else {
+            return true;
+        };
+        sources.retain(|(path, _)| *path != old_path);
+        renames.push(Rename {
+            old_path,
+            new_path: new_path.clone(),
+            score: MAX_SCORE,
+        });
+        false
+    });
+    if sources.is_empty() || destinations.is_empty() {
+        return Ok(renames);
+    }
+
+    let read = |hash: &str| -> Result<(usize, Spans)> {
+        let content = match Object::from_hash(repo, hash)? {
+            Object::Blob(blob) => blob.content,
+            _ => vec![],
+        };
+        Ok((content.len(), Spans::new(&content)))
+    };
+    let sources = sources
+        .into_iter()
+        .map(|(path, entry)| Ok((path, entry.0, read(&entry.1)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut scores = vec![];
+    for (new_index, (new_path, (new_mode, new_hash))) in destinations.iter().enumerate() {
+        let (new_size, new_spans) = read(new_hash)?;
+        for (old_index, (old_path, old_mode, (old_size, old_spans))) in sources.iter().enumerate() {
+            if is_regular(*old_mode) != is_regular(*new_mode) {
+                continue;
+            }
+            let score = similarity((*old_size, old_spans), (new_size, &new_spans), min_score);
+            if score >= min_score {
+                let same_name = basename(old_path) == basename(new_path);
+                scores.push((score, same_name, new_index, old_index));
+            }
+        }
+    }
+    // The best scores first, then files keeping their name, in order.
+    scores.sort_by_key(|&(score, same_name, new_index, old_index)| {
+        (std::cmp::Reverse((score, same_name)), new_index, old_index)
+    });
+    let mut used_sources = vec![false; sources.len()];
+    let mut used_destinations = vec![false; destinations.len()];
+    for (score, _, new_index, old_index) in scores {
+        if used_sources[old_index] || used_destinations[new_index] {
+            continue;
+        }
+        used_sources[old_index] = true;
+        used_destinations[new_index] = true;
+        renames.push(Rename {
+            old_path: sources[old_index].0.clone(),
+            new_path: destinations[new_index].0.clone(),
+            score,
+        });
+    }
+    Ok(renames)
+}
+
+/// Returns how similar two contents are, from 0 to [`MAX_SCORE`]: the
+/// number of bytes of the old content that the new one kept, in spans of
+/// lines, over the size of the bigger one. Contents whose sizes are too far
+/// apart to reach `min_score` score 0.
+fn similarity(old: (usize, &Spans), new: (usize, &Spans), min_score: u64) -> u64 {
+    let (old_size, new_size) = (old.0 as u64, new.0 as u64);
+    let max_size = old_size.max(new_size);
+    let delta_size = max_size - old_size.min(new_size);
+    if max_size * (MAX_SCORE - min_score) < delta_size * MAX_SCORE {
+        return 0;
+    }
+    if max_size == 0 {
+        return MAX_SCORE;
+    }
+    let copied: u64 = old
+        .1
+         .0
+        .iter()
+        .map(|(hash, count)| (*count).min(new.1 .0.get(hash).copied().unwrap_or(0)))
+        .sum();
+    copied * MAX_SCORE / max_size
+}
+
+/// The number of bytes of a content in spans with the same hash, like git's
+/// diffcore-delta. Spans end at newlines or after [`MAX_SPAN`] bytes, and
+/// CRs before LFs are ignored in text.
+struct Spans(HashMap<u32, u64>);
+
+impl Spans {
+    fn new(content: &[u8]) -> Spans {
+        let is_text = !content[..content.len().min(8000)].contains(&0);
+        let mut spans = HashMap::new();
+        let (mut accum1, mut accum2, mut length) = (0u32, 0u32, 0);
+        let mut add = |accum1: u32, accum2: u32, length: usize| {
+            let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASH_BASE;
+            *spans.entry(hash).or_insert(0) += length as u64;
+        };
+        for (i, &c) in content.iter().enumerate() {
+            if is_text && c == b'\r' && content.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+            let old_accum1 = accum1;
+            accum1 = (accum1 << 7) ^ (accum2 >> 25);
+            accum2 = (accum2 << 7) ^ (old_accum1 >> 25);
+            accum1 = accum1.wrapping_add(c as u32);
+            length += 1;
+            if length < MAX_SPAN && c != b'\n' {
+                continue;
+            }
+            add(accum1, accum2, length);
+            (accum1, accum2, length) = (0, 0, 0);
+        }
+        if length > 0 {
+            add(accum1, accum2, length);
+        }
+        Spans(spans)
+    }
+}
+
+fn is_regular(mode: u32) -> bool {
+    mode & 0o170000 == 0o100000
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity() {
+        let score = |old: &[u8], new: &[u8]| {
+            similarity(
+                (old.len(), &Spans::new(old)),
+                (new.len(), &Spans::new(new)),
+                DEFAULT_MIN_SCORE,
+            )
+        };
+        let content = b"one\ntwo\nthree\nfour\n";
+        assert_eq!(score(content, content), MAX_SCORE);
+        // CRs of CRLF line endings are left out of the spans, like additions.
+        assert_eq!(
+            score(content, b"one\r\ntwo\r\nthree\r\nfour\r\n"),
+            19 * MAX_SCORE / 23
+        );
+        // 14 bytes of the 19 are kept.
+        assert_eq!(
+            score(content, b"one\ntwo\nthree\nFOUR\n"),
+            14 * MAX_SCORE / 19
+        );
+        // Too different in size to be a rename.
+        assert_eq!(score(content, b"one\n"), 0);
+    }
+}
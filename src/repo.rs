@@ -1,31 +1,312 @@
-use std::fs;
+use anyhow::{anyhow, Result};
+use std::{fs, path::Path, sync::OnceLock};
+
+use crate::{config::Config, snapshot::Snapshot};
 
 static GIT_FOLDER_NAME: &str = ".git";
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Repo {
     pub root: std::path::PathBuf,
+    git_dir: std::path::PathBuf,
+    common_dir: std::path::PathBuf,
+    /// `core.protectNTFS` and `core.protectHFS`, read on first use.
+    protections: OnceLock<Protections>,
+}
+
+/// Which file systems' rules paths in the working tree must follow so that
+/// they can't be used to write into the `.git` folder.
+#[derive(Debug, Clone, Copy)]
+struct Protections {
+    ntfs: bool,
+    hfs: bool,
 }
 
 impl Repo {
+    /// Opens the repository of a working tree. When `.git` is a file, as in
+    /// linked worktrees, it points to the git folder with a `gitdir:` line.
     pub fn new(root: &std::path::Path) -> Self {
+        let dot_git = root.join(GIT_FOLDER_NAME);
+        let git_dir = match read_path_file(&dot_git, "gitdir: ") {
+            Some(git_dir) => root.join(git_dir),
+            None => dot_git,
+        };
+        // The git folder of a linked worktree has a `commondir` file
+        // pointing to the git folder of the main worktree.
+        let common_dir = match read_path_file(&git_dir.join("commondir"), "") {
+            Some(common_dir) => {
+                let common_dir = git_dir.join(common_dir);
+                fs::canonicalize(&common_dir).unwrap_or(common_dir)
+            }
+            None => git_dir.clone(),
+        };
         Repo {
             root: root.to_path_buf(),
+            git_dir,
+            common_dir,
+            protections: OnceLock::new(),
         }
     }
 
-    pub fn from_dir(path: &std::path::Path) -> Option<Self> {
-        let path = fs::canonicalize(path).ok()?;
-        let git_dir = path.ancestors().find(|&d| {
+    /// Opens a bare repository, which has no working tree: its root is the
+    /// git folder itself.
+    pub fn bare(git_dir: &std::path::Path) -> Self {
+        Repo {
+            root: git_dir.to_path_buf(),
+            git_dir: git_dir.to_path_buf(),
+            common_dir: git_dir.to_path_buf(),
+            protections: OnceLock::new(),
+        }
+    }
+
+    /// Finds the repository containing `path`, if any.
+    ///
+    /// Fails if the repository isn't safe to operate on, see
+    /// [`Repo::check_safe_directory`], so that nothing reads its config.
+    pub fn from_dir(path: &std::path::Path) -> Result<Option<Self>> {
+        let Ok(path) = fs::canonicalize(path) else {
+            return Ok(None);
+        };
+        let root = path.ancestors().find(|&d| {
             let git_dir = d.join(GIT_FOLDER_NAME);
             git_dir.exists()
         });
-        git_dir.map(Repo::new)
+        let Some(root) = root else {
+            return Ok(None);
+        };
+        let repo = Repo::new(root);
+        repo.check_safe_directory()?;
+        Ok(Some(repo))
     }
 
+    /// Returns the git folder of the working tree, with its HEAD and index.
     pub fn git_dir(&self) -> std::path::PathBuf {
-        self.root.join(GIT_FOLDER_NAME)
+        self.git_dir.clone()
+    }
+
+    /// Returns the git folder shared by all the worktrees of the repository,
+    /// with the objects, refs and config. It's the git folder of the main
+    /// worktree.
+    pub fn common_dir(&self) -> std::path::PathBuf {
+        self.common_dir.clone()
+    }
+
+    /// Returns whether the repository has no working tree.
+    pub fn is_bare(&self) -> bool {
+        self.root == self.git_dir
+    }
+
+    /// Returns whether this is a linked worktree rather than the main one.
+    pub fn is_linked_worktree(&self) -> bool {
+        self.git_dir != self.common_dir
+    }
+
+    /// Reads the refs and lists the objects of the repository once, so that
+    /// later reads through the returned [`Snapshot`] are consistent with each
+    /// other.
+    pub fn snapshot(&self) -> Result<Snapshot<'_>> {
+        Snapshot::new(self)
+    }
+
+    /// Returns the path in the working tree for a path from a tree or the index.
+    ///
+    /// Fails if the path could be used to write outside of the working tree or
+    /// into the `.git` folder: absolute paths, `..` and `.git` components, and
+    /// paths going through a symlink are all rejected. So are the other names
+    /// of `.git` on NTFS and HFS+ with `core.protectNTFS` and
+    /// `core.protectHFS`.
+    pub fn worktree_path(&self, path: &str) -> Result<std::path::PathBuf> {
+        let full_path = self.verified_path(path)?;
+        if self.has_symlink_leading_path(path) {
+            return Err(anyhow!(
+                "Refusing to write outside of the working tree: {path:?}"
+            ));
+        }
+        Ok(full_path)
     }
+
+    /// Returns the path in the working tree for a path from a tree or the
+    /// index, like [`Repo::worktree_path`], without checking for symlinks.
+    ///
+    /// The path may lead through a symlink, so it must not be written to
+    /// before the symlink is removed.
+    pub fn verified_path(&self, path: &str) -> Result<std::path::PathBuf> {
+        let protections = match self.protections.get() {
+            Some(protections) => *protections,
+            None => {
+                let config = Config::load(self)?;
+                let protections = Protections {
+                    ntfs: config.get_bool("core.protectNTFS")?.unwrap_or(false),
+                    hfs: config.get_bool("core.protectHFS")?.unwrap_or(false),
+                };
+                *self.protections.get_or_init(|| protections)
+            }
+        };
+        let mut full_path = self.root.clone();
+        for component in path.split('/') {
+            if component.is_empty()
+                || component == "."
+                || component == ".."
+                || component.eq_ignore_ascii_case(GIT_FOLDER_NAME)
+                || (protections.ntfs && is_ntfs_unsafe(component))
+                || (protections.hfs && is_hfs_dot_git(component))
+            {
+                return Err(anyhow!(
+                    "Refusing to write outside of the working tree: {path:?}"
+                ));
+            }
+            full_path.push(component);
+        }
+        Ok(full_path)
+    }
+
+    /// Returns whether one of the folders of a path in the working tree is a
+    /// symlink, so that the path is outside of the working tree.
+    pub fn has_symlink_leading_path(&self, path: &str) -> bool {
+        let mut full_path = self.root.clone();
+        let mut components = path.split('/').peekable();
+        while let Some(component) = components.next() {
+            if components.peek().is_none() {
+                break;
+            }
+            full_path.push(component);
+            if full_path.is_symlink() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns the path of a file in the working tree relative to its root,
+    /// with `/` separators, as used in the index. An empty string is the
+    /// root itself.
+    ///
+    /// `path` must be absolute. It doesn't have to exist.
+    pub fn relative_path(&self, path: &Path) -> Result<String> {
+        let mut normalized = std::path::PathBuf::new();
+        for component in path.components() {
+            match component {
+                std::path::Component::CurDir => {}
+                std::path::Component::ParentDir => {
+                    normalized.pop();
+                }
+                component => normalized.push(component),
+            }
+        }
+        let relative = normalized
+            .strip_prefix(&self.root)
+            .map_err(|_| anyhow!("'{}' is outside repository", path.display()))?;
+        let components: Option<Vec<&str>> = relative
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect();
+        let components = components.ok_or(anyhow!("Invalid path: {}", path.display()))?;
+        Ok(components.join("/"))
+    }
+
+    /// Returns an error if the repository is owned by another user, unless it
+    /// is trusted through `safe.directory` in the system or global config.
+    ///
+    /// This mirrors git's mitigation for CVE-2022-24765, where someone could
+    /// create a repository in a shared directory, like `/tmp`, with config
+    /// that runs arbitrary commands for other users.
+    pub fn check_safe_directory(&self) -> Result<()> {
+        if is_owned_by_current_user(&self.root)? && is_owned_by_current_user(&self.git_dir())? {
+            return Ok(());
+        }
+
+        let config = Config::load_global()?;
+        if is_listed_as_safe(&config.get_all("safe.directory"), &self.root) {
+            return Ok(());
+        }
+
+        let path = self.root.display();
+        Err(anyhow!(
+            "detected dubious ownership in repository at '{path}'\n\
+             The repository is owned by another user. To trust it, run:\n\n\
+             \tgit config --global --add safe.directory {path}"
+        ))
+    }
+}
+
+/// Returns whether a path component can't be written on NTFS: it contains
+/// `\\`, a folder separator, or `:`, which names an alternate data stream,
+/// or it's another name of `.git`, with trailing dots or spaces or as the
+/// short name `git~1`.
+fn is_ntfs_unsafe(component: &str) -> bool {
+    component.contains(['\\', ':'])
+        || component
+            .trim_end_matches(['.', ' '])
+            .eq_ignore_ascii_case(GIT_FOLDER_NAME)
+        || component.eq_ignore_ascii_case("git~1")
+}
+
+/// Returns whether a path component is `.git` on HFS+, which ignores some
+/// Unicode code points in names.
+fn is_hfs_dot_git(component: &str) -> bool {
+    let name: String = component
+        .chars()
+        .filter(|&c| {
+            !matches!(c,
+                '\u{200c}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{206a}'..='\u{206f}' | '\u{feff}')
+        })
+        .collect();
+    name.eq_ignore_ascii_case(GIT_FOLDER_NAME)
+}
+
+/// Reads a file containing a single path after `prefix`, like the `.git`
+/// file of a linked worktree. Returns `None` if it's not such a file.
+fn read_path_file(path: &Path, prefix: &str) -> Option<std::path::PathBuf> {
+    if !path.is_file() {
+        return None;
+    }
+    let content = fs::read_to_string(path).ok()?;
+    let value = content
+        .trim_end_matches(['\n', '\r'])
+        .strip_prefix(prefix)?;
+    Some(std::path::PathBuf::from(value))
+}
+
+#[cfg(unix)]
+fn is_owned_by_current_user(path: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let owner = fs::metadata(path)?.uid();
+    // SAFETY: geteuid has no preconditions and can't fail.
+    let mut uid = unsafe { libc::geteuid() };
+    // Like git, allow root to operate on the repository of the user who ran sudo.
+    if uid == 0 {
+        if let Some(sudo_uid) = std::env::var("SUDO_UID").ok().and_then(|u| u.parse().ok()) {
+            uid = sudo_uid;
+        }
+    }
+    Ok(owner == uid)
+}
+
+#[cfg(not(unix))]
+fn is_owned_by_current_user(_path: &Path) -> Result<bool> {
+    // TODO: compare the owner SID on Windows.
+    Ok(true)
+}
+
+/// Checks `path` against the values of `safe.directory`.
+///
+/// The values are processed in order: `*` trusts all paths, a path ending in
+/// `/*` trusts everything below it, and an empty value resets the list.
+fn is_listed_as_safe(values: &[&str], path: &Path) -> bool {
+    let mut safe = false;
+    for value in values {
+        if value.is_empty() {
+            safe = false;
+        } else if *value == "*" {
+            safe = true;
+        } else if let Some(prefix) = value.strip_suffix("/*") {
+            safe |= path.starts_with(prefix);
+        } else {
+            safe |= path == Path::new(value.trim_end_matches('/'));
+        }
+    }
+    safe
 }
 
 #[cfg(test)]
@@ -46,9 +327,101 @@ mod tests {
         let cwd_dir = tmpdir.join("hello/from/nested/dir");
         std::fs::create_dir_all(&cwd_dir).unwrap();
 
-        let repo = Repo::from_dir(&cwd_dir).unwrap();
+        let repo = Repo::from_dir(&cwd_dir).unwrap().unwrap();
 
         assert_eq!(tmpdir, repo.root);
         assert_eq!(git_dir, repo.git_dir());
     }
+
+    #[test]
+    fn test_worktree_path() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        assert_eq!(
+            repo.worktree_path("a/b.txt").unwrap(),
+            tmpdir.path().join("a").join("b.txt")
+        );
+        for path in [
+            "",
+            "/etc/passwd",
+            "a/../../b",
+            "./a",
+            ".git/config",
+            "a/.GIT/x",
+        ] {
+            assert!(
+                repo.worktree_path(path).is_err(),
+                "{path} should be rejected"
+            );
+        }
+
+        // Names only unsafe on NTFS or HFS+ are only rejected when protecting
+        // them.
+        let ntfs = ["C:/x", "a\\b", ".git. /x", "GIT~1/config"];
+        let hfs = ".g\u{200c}it/config";
+        for path in ntfs.iter().chain([&hfs]) {
+            assert!(
+                repo.worktree_path(path).is_ok(),
+                "{path} should be accepted"
+            );
+        }
+        crate::init_repo(&repo, "main").unwrap();
+        let config = repo.git_dir().join("config");
+        crate::config::set_value(&config, "core.protectNTFS", "true").unwrap();
+        crate::config::set_value(&config, "core.protectHFS", "true").unwrap();
+        let repo = Repo::new(tmpdir.path());
+        for path in ntfs.iter().chain([&hfs]) {
+            assert!(
+                repo.worktree_path(path).is_err(),
+                "{path} should be rejected"
+            );
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("/etc", tmpdir.path().join("link")).unwrap();
+            assert!(repo.worktree_path("link/passwd").is_err());
+        }
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let repo = Repo::new(Path::new("/repo"));
+        assert_eq!(
+            repo.relative_path(Path::new("/repo/a/b.txt")).unwrap(),
+            "a/b.txt"
+        );
+        assert_eq!(
+            repo.relative_path(Path::new("/repo/a/../c/./d")).unwrap(),
+            "c/d"
+        );
+        assert_eq!(repo.relative_path(Path::new("/repo/")).unwrap(), "");
+        assert_eq!(
+            repo.relative_path(Path::new("/repo/../other"))
+                .unwrap_err()
+                .to_string(),
+            "'/repo/../other' is outside repository"
+        );
+    }
+
+    #[test]
+    fn test_is_listed_as_safe() {
+        let path = Path::new("/home/alice/repo");
+        assert!(!is_listed_as_safe(&[], path));
+        assert!(is_listed_as_safe(&["/home/alice/repo"], path));
+        assert!(is_listed_as_safe(&["/home/alice/repo/"], path));
+        assert!(is_listed_as_safe(&["*"], path));
+        assert!(is_listed_as_safe(&["/home/alice/*"], path));
+        assert!(!is_listed_as_safe(&["/home/bob/*"], path));
+        assert!(!is_listed_as_safe(&["/home/alice/repo2"], path));
+        assert!(!is_listed_as_safe(&["*", ""], path));
+        assert!(is_listed_as_safe(&["", "/home/alice/repo"], path));
+    }
+
+    #[test]
+    fn test_check_safe_directory_for_own_repo() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmpdir.path().join(GIT_FOLDER_NAME)).unwrap();
+        Repo::new(tmpdir.path()).check_safe_directory().unwrap();
+    }
 }
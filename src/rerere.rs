@@ -0,0 +1,363 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::PathBuf,
+};
+
+use crate::{config::Config, index::Index, merge_file, object, repo::Repo};
+
+/// Whether conflict resolutions are recorded and replayed: `rerere.enabled`,
+/// or, like git, whether `.git/rr-cache` exists when it isn't set.
+pub fn enabled(repo: &Repo) -> Result<bool> {
+    let config = Config::load(repo)?;
+    Ok(config
+        .get_bool("rerere.enabled")?
+        .unwrap_or_else(|| cache_dir(repo).is_dir()))
+}
+
+/// Normalizes the conflicts of a file like git's rerere, so that the same
+/// conflict is recognized whatever the labels of its markers, the order of
+/// its sides and its base version: each conflict becomes our and their
+/// sides, smaller first, between markers without labels.
+///
+/// Returns the normalized content and the ID of the conflicts, the hash of
+/// their sides, or `None` if the file has no conflicts. Conflicts with
+/// markers out of order are an error.
+pub fn normalize(content: &[u8], marker_size: usize) -> Result<Option<(Vec<u8>, String)>> {
+    let mut lines = content.split_inclusive(|&c| c == b'\n');
+    let mut normalized = vec![];
+    let mut sides = vec![];
+    let mut conflicts = 0;
+    while let Some(line) = lines.next() {
+        if !is_marker(line, b'<', marker_size) {
+            normalized.extend_from_slice(line);
+            continue;
+        }
+        let conflict = normalize_conflict(&mut lines, marker_size, Some(&mut sides))
+            .ok_or_else(|| anyhow!("could not parse conflict hunks"))?;
+        normalized.extend(conflict);
+        conflicts += 1;
+    }
+    Ok((conflicts > 0).then(|| (normalized, object::hash(&sides))))
+}
+
+#[derive(PartialEq)]
+enum Hunk {
+    Ours,
+    Base,
+    Theirs,
+}
+
+/// Normalizes a conflict after its `<` marker, adding its sides to `sides`
+/// for the ID. Conflicts nested in a side are normalized too, and kept in
+/// the side.
+fn normalize_conflict<'a>(
+    lines: &mut impl Iterator<Item = &'a [u8]>,
+    marker_size: usize,
+    sides: Option<&mut Vec<u8>>,
+) -> Option<Vec<u8>> {
+    let (mut one, mut two) = (vec![], vec![]);
+    let mut hunk = Hunk::Ours;
+    while let Some(line) = lines.next() {
+        if is_marker(line, b'<', marker_size) {
+            let nested = normalize_conflict(lines, marker_size, None)?;
+            match hunk {
+                Hunk::Ours => one.extend(nested),
+                _ => two.extend(nested),
+            }
+        } else if is_marker(line, b'|', marker_size) {
+            if hunk != Hunk::Ours {
+                return None;
+            }
+            hunk = Hunk::Base;
+        } else if is_marker(line, b'=', marker_size) {
+            if hunk == Hunk::Theirs {
+                return None;
+            }
+            hunk = Hunk::Theirs;
+        } else if is_marker(line, b'>', marker_size) {
+            if hunk != Hunk::Theirs {
+                return None;
+            }
+            if one > two {
+                std::mem::swap(&mut one, &mut two);
+            }
+            if let Some(sides) = sides {
+                sides.extend(&one);
+                sides.push(0);
+                sides.extend(&two);
+                sides.push(0);
+            }
+            let marker = |c: u8| {
+                let mut marker = vec![c; marker_size];
+                marker.push(b'\n');
+                marker
+            };
+            return Some([marker(b'<'), one, marker(b'='), two, marker(b'>')].concat());
+        } else {
+            match hunk {
+                Hunk::Ours => one.extend_from_slice(line),
+                Hunk::Base => {}
+                Hunk::Theirs => two.extend_from_slice(line),
+            }
+        }
+    }
+    None
+}
+
+/// Whether a line is a conflict marker of `c`s: followed by a space and a
+/// label for `<` and `>`, or by any whitespace for the others.
+fn is_marker(line: &[u8], c: u8, marker_size: usize) -> bool {
+    if line.len() <= marker_size || line[..marker_size].iter().any(|&b| b != c) {
+        return false;
+    }
+    match c {
+        b'<' | b'>' => line[marker_size] == b' ',
+        _ => line[marker_size].is_ascii_whitespace(),
+    }
+}
+
+/// A conflict of a path being resolved, with the variant of its ID whose
+/// preimage was recorded, if any yet.
+type Conflict = (String, Option<usize>);
+
+/// Records and replays the resolutions of the conflicts of the working
+/// tree, like `git rerere`, if enabled.
+///
+/// The new conflicts, paths with our and their versions in the index, are
+/// listed in `.git/MERGE_RR` with their ID. Each ID has a directory in
+/// `.git/rr-cache` where the normalized conflicted file is its "preimage",
+/// and the file once resolved its "postimage". A conflict with a recorded
+/// resolution is resolved again by merging the changes from its preimage
+/// to its postimage into the file, otherwise its preimage is recorded, and
+/// its postimage is once the file has no more conflicts. Different
+/// resolutions of conflicts with the same ID are variants, numbered after
+/// the first.
+pub fn rerere(repo: &Repo, stdout: &mut dyn io::Write) -> Result<()> {
+    if !enabled(repo)? {
+        return Ok(());
+    }
+    let mut merge_rr = read_merge_rr(repo)?;
+    let index = Index::read(repo)?;
+    let stages = |stage: u8| -> BTreeSet<&str> {
+        index
+            .entries
+            .iter()
+            .filter(|entry| entry.stage == stage)
+            .map(|entry| entry.path.as_str())
+            .collect()
+    };
+    let marker_size = merge_file::DEFAULT_MARKER_SIZE;
+    for path in stages(2).intersection(&stages(3)) {
+        if merge_rr.contains_key(*path) {
+            continue;
+        }
+        let Ok(content) = fs::read(repo.root.join(path)) else {
+            continue;
+        };
+        if let Ok(Some((_, id))) = normalize(&content, marker_size) {
+            fs::create_dir_all(cache_dir(repo).join(&id))?;
+            merge_rr.insert(path.to_string(), (id, None));
+        }
+    }
+
+    let mut unresolved = BTreeMap::new();
+    for (path, (id, variant)) in merge_rr {
+        let file = repo.root.join(&path);
+        let normalized = fs::read(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| normalize(&content, marker_size));
+        // Has the user resolved it already?
+        if let (Some(variant), Ok(None)) = (variant, &normalized) {
+            fs::copy(&file, image(repo, &id, "postimage", variant))?;
+            writeln!(stdout, "Recorded resolution for '{path}'.")?;
+            continue;
+        }
+        let Ok(Some((normalized, _))) = normalized else {
+            if variant.is_some() {
+                unresolved.insert(path, (id, variant));
+            }
+            continue;
+        };
+        // Does a recorded resolution apply cleanly?
+        let variants = variants(repo, &id)?;
+        let mut replayed = false;
+        for (other, has_postimage) in variants.iter().enumerate() {
+            if *has_postimage != Some(true) {
+                continue;
+            }
+            let preimage = fs::read(image(repo, &id, "preimage", other))?;
+            let postimage = fs::read(image(repo, &id, "postimage", other))?;
+            let options = merge_file::MergeOptions::default();
+            let (merged, conflicts) =
+                merge_file::merge(&preimage, &normalized, &postimage, &options);
+            if conflicts > 0 {
+                continue;
+            }
+            fs::write(&file, merged)?;
+            if let Some(variant) = variant.filter(|variant| *variant != other) {
+                remove_variant(repo, &id, variant)?;
+            }
+            writeln!(stdout, "Resolved '{path}' using previous resolution.")?;
+            replayed = true;
+            break;
+        }
+        if replayed {
+            continue;
+        }
+        // None applies, the preimage of a new variant is recorded.
+        let variant = variant.unwrap_or_else(|| {
+            variants
+                .iter()
+                .position(Option::is_none)
+                .unwrap_or(variants.len())
+        });
+        fs::create_dir_all(cache_dir(repo).join(&id))?;
+        fs::write(image(repo, &id, "preimage", variant), normalized)?;
+        let postimage = image(repo, &id, "postimage", variant);
+        if postimage.exists() {
+            fs::remove_file(postimage)?;
+        }
+        writeln!(stdout, "Recorded preimage for '{path}'")?;
+        unresolved.insert(path, (id, Some(variant)));
+    }
+    write_merge_rr(repo, &unresolved)
+}
+
+/// Forgets the conflicts being resolved, and the preimages recorded for
+/// those that weren't, like `git rerere clear`.
+pub fn clear(repo: &Repo) -> Result<()> {
+    for (id, variant) in read_merge_rr(repo)?.into_values() {
+        if let Some(variant) = variant {
+            if !image(repo, &id, "postimage", variant).exists() {
+                remove_variant(repo, &id, variant)?;
+            }
+        }
+    }
+    match fs::remove_file(merge_rr_path(repo)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the variants of an ID by number: `None` for the numbers without
+/// a preimage, or whether they have a postimage.
+fn variants(repo: &Repo, id: &str) -> Result<Vec<Option<bool>>> {
+    let mut variants = vec![];
+    let entries = match fs::read_dir(cache_dir(repo).join(id)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(variants),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        let variant = match name.strip_prefix("preimage") {
+            Some("") => 0,
+            Some(suffix) => match suffix.strip_prefix('.').map(str::parse) {
+                Some(Ok(variant)) => variant,
+                _ => continue,
+            },
+            None => continue,
+        };
+        if variants.len() <= variant {
+            variants.resize(variant + 1, None);
+        }
+        variants[variant] = Some(image(repo, id, "postimage", variant).exists());
+    }
+    Ok(variants)
+}
+
+/// Removes the images of a variant, and the directory of its ID if no
+/// variant is left.
+fn remove_variant(repo: &Repo, id: &str, variant: usize) -> Result<()> {
+    for name in ["preimage", "postimage"] {
+        match fs::remove_file(image(repo, id, name, variant)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    // Fails if it isn't empty.
+    let _ = fs::remove_dir(cache_dir(repo).join(id));
+    Ok(())
+}
+
+/// The path of an image of a variant, like `preimage` or `postimage.1`.
+fn image(repo: &Repo, id: &str, name: &str, variant: usize) -> PathBuf {
+    let name = match variant {
+        0 => name.to_string(),
+        variant => format!("{name}.{variant}"),
+    };
+    cache_dir(repo).join(id).join(name)
+}
+
+/// Reads the conflicts being resolved from `.git/MERGE_RR`, whose entries
+/// are an ID, with the number of its variant after a dot unless it's the
+/// first, a tab and a path, ending with a NUL.
+fn read_merge_rr(repo: &Repo) -> Result<BTreeMap<String, Conflict>> {
+    let content = match fs::read_to_string(merge_rr_path(repo)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    content
+        .split_terminator('\0')
+        .map(|entry| {
+            let invalid = || anyhow!("corrupt MERGE_RR");
+            let (id, path) = entry.split_once('\t').ok_or_else(invalid)?;
+            let (id, variant) = match id.split_once('.') {
+                Some((id, variant)) => (id, variant.parse().map_err(|_| invalid())?),
+                None => (id, 0),
+            };
+            Ok((path.to_string(), (id.to_string(), Some(variant))))
+        })
+        .collect()
+}
+
+fn write_merge_rr(repo: &Repo, merge_rr: &BTreeMap<String, Conflict>) -> Result<()> {
+    let mut content = String::new();
+    for (path, (id, variant)) in merge_rr {
+        match variant {
+            Some(0) | None => content.push_str(id),
+            Some(variant) => content.push_str(&format!("{id}.{variant}")),
+        }
+        content.push_str(&format!("\t{path}\0"));
+    }
+    fs::write(merge_rr_path(repo), content)?;
+    Ok(())
+}
+
+fn merge_rr_path(repo: &Repo) -> PathBuf {
+    repo.git_dir().join("MERGE_RR")
+}
+
+/// The directory of the recorded conflicts, `.git/rr-cache`, shared by the
+/// worktrees.
+pub fn cache_dir(repo: &Repo) -> PathBuf {
+    repo.common_dir().join("rr-cache")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        // The ID of the conflict of "B main" and "B topic" in git.
+        let ours = b"a\n<<<<<<< HEAD\nB main\n=======\nB topic\n>>>>>>> topic\nc\n";
+        let (normalized, id) = normalize(ours, 7).unwrap().unwrap();
+        assert_eq!(
+            String::from_utf8(normalized).unwrap(),
+            "a\n<<<<<<<\nB main\n=======\nB topic\n>>>>>>>\nc\n"
+        );
+        assert_eq!(id, "e76a0ed7329bf72860667ee2c8d40b21a3eaed7e");
+
+        // The sides are sorted, and the base is left out.
+        let theirs =
+            b"a\n<<<<<<< ours\nB topic\n||||||| base\nb\n=======\nB main\n>>>>>>> theirs\nc\n";
+        assert_eq!(normalize(theirs, 7).unwrap().unwrap().1, id);
+
+        assert_eq!(normalize(b"a\n=======\nb\n", 7).unwrap(), None);
+        assert!(normalize(b"<<<<<<< ours\na\n>>>>>>> theirs\n", 7).is_err());
+    }
+}
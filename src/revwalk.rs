@@ -0,0 +1,1053 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+    str::FromStr,
+    sync::mpsc,
+    thread,
+};
+
+use crate::{
+    ident::Ident,
+    object::{self, Commit, Object, ObjectType},
+    repo::Repo,
+    shallow,
+};
+
+/// What to do with objects missing from the repository during a walk, like
+/// git's `--missing` option. Objects are expected to be missing in partial
+/// clones, and auditing a repository needs to list them instead of stopping
+/// at the first one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Missing {
+    /// Fail on the first missing object.
+    #[default]
+    Error,
+    /// Skip missing objects.
+    AllowAny,
+    /// Skip missing objects and collect them, see
+    /// [`RevWalk::missing_objects`].
+    Print,
+}
+
+impl FromStr for Missing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Missing> {
+        match s {
+            "error" => Ok(Missing::Error),
+            "allow-any" => Ok(Missing::AllowAny),
+            "print" => Ok(Missing::Print),
+            _ => Err(anyhow!("invalid value for '--missing': '{s}'")),
+        }
+    }
+}
+
+/// Limits on the commits returned by a [`RevWalk`], like git's commit
+/// limiting options.
+#[derive(Debug, Default, Clone)]
+pub struct Limits {
+    /// Return at most this many commits, like `-n`.
+    pub max_count: Option<usize>,
+    /// Skip the commits older than this, by commit date, and stop walking
+    /// their parents, like `--since`.
+    pub since: Option<i64>,
+    /// Skip the commits newer than this, like `--until`.
+    pub until: Option<i64>,
+    /// Only return commits whose author, as `Name <email>`, matches one of
+    /// these, like `--author`.
+    pub authors: Vec<Regex>,
+    /// Only return commits whose committer matches one of these, like
+    /// `--committer`.
+    pub committers: Vec<Regex>,
+    /// Only return commits whose message matches one of these, like
+    /// `--grep`.
+    pub messages: Vec<Regex>,
+}
+
+impl Limits {
+    /// Returns whether a commit is returned, apart from the number of
+    /// commits and `since`, which are handled by the walk.
+    fn matches(&self, commit: &Commit) -> bool {
+        let matches = |regexes: &[Regex], text: &str| {
+            regexes.is_empty() || regexes.iter().any(|regex| regex.is_match(text))
+        };
+        // Like git, dates aren't matched.
+        let ident = |ident: &str| match Ident::parse(ident) {
+            Some(ident) => format!("{} <{}>", ident.name, ident.email),
+            None => ident.to_string(),
+        };
+        self.until
+            .is_none_or(|until| commit.committer_timestamp() <= until)
+            && matches(&self.authors, &ident(&commit.author))
+            && matches(&self.committers, &ident(&commit.committer))
+            && matches(&self.messages, &commit.message)
+    }
+}
+
+/// The number of commits decoded ahead of a walk by default, see
+/// [`RevWalk::prefetch`].
+pub const DEFAULT_PREFETCH: usize = 32;
+
+/// A walk of the commits reachable from some commits, newest first by
+/// commit date, skipping the commits reachable from hidden commits.
+///
+/// With [`RevWalk::set_topo_order`], the whole walk is done first and
+/// sorted so that commits come before their parents.
+///
+/// With [`RevWalk::set_paths`], only the commits changing some paths are
+/// returned, and the history is simplified like git's by default.
+pub struct RevWalk<'a> {
+    repo: &'a Repo,
+    missing: Missing,
+    /// The commits to visit, by commit date then in the order they were
+    /// found.
+    queue: BinaryHeap<(i64, Reverse<usize>, String, CommitBox)>,
+    /// The commits in the queue.
+    queued: HashSet<String>,
+    /// The number of commits in the queue that aren't hidden, the walk
+    /// ending when there are none left.
+    interesting: usize,
+    found: usize,
+    seen: HashSet<String>,
+    /// The hidden commits and the commits found to be reachable from them
+    /// so far, and the trees and blobs of the hidden commits that bound the
+    /// walk.
+    hidden: HashSet<String>,
+    /// Whether commits were hidden, in which case the walk is done before
+    /// returning commits, so that commits aren't returned before they're
+    /// found to be reachable from hidden ones.
+    limited: bool,
+    /// The commits reachable from the commits pushed with
+    /// [`RevWalk::push_left`].
+    left: HashSet<String>,
+    /// The trees and blobs listed so far by [`RevWalk::objects`].
+    seen_objects: HashSet<String>,
+    missing_objects: Vec<String>,
+    prefetcher: Option<Prefetcher>,
+    /// The shallow commits of the repository, whose parents are ignored.
+    shallow: HashSet<String>,
+    topo_order: bool,
+    first_parent: bool,
+    limits: Limits,
+    /// The paths whose history is walked, all of them if empty.
+    paths: Vec<String>,
+    /// The commits walked in which the paths are the same as in a parent,
+    /// which aren't returned.
+    treesame: HashSet<String>,
+    /// The number of commits returned so far.
+    returned: usize,
+    /// The commits sorted in topological order, once walked.
+    sorted: Option<VecDeque<(String, Commit)>>,
+}
+
+/// A commit in the queue, which is ordered by the other fields.
+struct CommitBox(Commit);
+
+impl PartialEq for CommitBox {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for CommitBox {}
+
+impl PartialOrd for CommitBox {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CommitBox {
+    fn cmp(&self, _: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl<'a> RevWalk<'a> {
+    pub fn new(repo: &'a Repo, missing: Missing) -> Result<RevWalk<'a>> {
+        Ok(RevWalk {
+            repo,
+            missing,
+            queue: BinaryHeap::new(),
+            queued: HashSet::new(),
+            interesting: 0,
+            found: 0,
+            seen: HashSet::new(),
+            hidden: HashSet::new(),
+            limited: false,
+            left: HashSet::new(),
+            seen_objects: HashSet::new(),
+            missing_objects: vec![],
+            prefetcher: None,
+            shallow: shallow::read(repo)?,
+            topo_order: false,
+            first_parent: false,
+            limits: Limits::default(),
+            paths: vec![],
+            treesame: HashSet::new(),
+            returned: 0,
+            sorted: None,
+        })
+    }
+
+    /// Returns commits before their parents, like `--topo-order`: among the
+    /// commits whose children were all returned, the ones found last first,
+    /// so that the lines of history aren't intermixed.
+    pub fn set_topo_order(&mut self, topo_order: bool) {
+        self.topo_order = topo_order;
+    }
+
+    /// Only returns the commits within some limits, see [`Limits`].
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Only returns the commits in which some paths, files or directories,
+    /// differ from their parents, like `git log -- <paths>`.
+    ///
+    /// Like git without `--full-history`, a merge whose paths are the same
+    /// as in one of its parents is only walked through the first such
+    /// parent, which becomes its only parent.
+    pub fn set_paths(&mut self, paths: &[String]) {
+        self.paths = paths
+            .iter()
+            .map(|path| path.trim_end_matches('/').to_string())
+            .collect();
+    }
+
+    /// Only follows the first parent of commits, like `--first-parent`.
+    /// Must be set before [`RevWalk::prefetch`].
+    pub fn set_first_parent(&mut self, first_parent: bool) {
+        self.first_parent = first_parent;
+    }
+
+    /// Reads and decodes up to `depth` commits ahead of the walk in a
+    /// background thread, so that inflating them overlaps with whatever is
+    /// done with the commits already returned.
+    ///
+    /// The order of the walk doesn't change. Commits that can't be read in
+    /// the background are read again by the walk, which handles missing
+    /// ones as usual.
+    pub fn prefetch(&mut self, depth: usize) {
+        let mut prefetcher = Prefetcher::new(self.repo, depth, self.first_parent);
+        for (_, _, _, CommitBox(commit)) in &self.queue {
+            for parent in &commit.parents {
+                prefetcher.request(parent);
+            }
+        }
+        self.prefetcher = Some(prefetcher);
+    }
+
+    /// Adds a commit to start from.
+    pub fn push(&mut self, hash: &str) -> Result<()> {
+        if self.hidden.contains(hash) || !self.seen.insert(hash.to_string()) {
+            return Ok(());
+        }
+        if let Some(commit) = self.read_commit(hash, || anyhow!("bad object {hash}"))? {
+            self.enqueue(hash.to_string(), commit);
+        }
+        Ok(())
+    }
+
+    /// Adds a commit to start from, on the left side of a symmetric
+    /// difference like `<left>...<right>`, see [`RevWalk::is_left`].
+    pub fn push_left(&mut self, hash: &str) -> Result<()> {
+        if !self.seen.contains(hash) {
+            self.left.insert(hash.to_string());
+        }
+        self.push(hash)
+    }
+
+    /// Returns whether a commit was reached from a commit pushed with
+    /// [`RevWalk::push_left`], rather than with [`RevWalk::push`].
+    ///
+    /// The commits reachable from both sides should be hidden, by hiding
+    /// their merge bases.
+    pub fn is_left(&self, hash: &str) -> bool {
+        self.left.contains(hash)
+    }
+
+    /// Skips the commits reachable from a commit, and the trees and blobs
+    /// of the hidden commits that bound the walk.
+    ///
+    /// Like git, the hidden commits are walked with the others, by date, and
+    /// the walk stops once only hidden commits are left, rather than walking
+    /// the whole history they reach.
+    pub fn hide(&mut self, hash: &str) -> Result<()> {
+        self.limited = true;
+        if !self.mark_hidden(hash) {
+            return Ok(());
+        }
+        let Some(commit) = self.read_commit(hash, || anyhow!("bad object {hash}"))? else {
+            return Ok(());
+        };
+        self.hide_tree(&commit.tree)?;
+        if self.seen.insert(hash.to_string()) {
+            self.enqueue(hash.to_string(), commit);
+        }
+        Ok(())
+    }
+
+    /// Marks a commit as hidden, returning false if it already was.
+    fn mark_hidden(&mut self, hash: &str) -> bool {
+        if !self.hidden.insert(hash.to_string()) {
+            return false;
+        }
+        if self.queued.contains(hash) {
+            self.interesting -= 1;
+        }
+        true
+    }
+
+    /// Hides the parents of a hidden commit, queueing the ones not found
+    /// yet so that their parents are hidden in turn.
+    fn hide_parents(&mut self, commit: &Commit) -> Result<()> {
+        for parent in &commit.parents {
+            self.mark_hidden(parent);
+            if !self.seen.insert(parent.clone()) {
+                continue;
+            }
+            if let Some(parent_commit) =
+                self.read_commit(parent, || anyhow!("bad object {parent}"))?
+            {
+                self.enqueue(parent.clone(), parent_commit);
+            }
+        }
+        Ok(())
+    }
+
+    fn hide_tree(&mut self, hash: &str) -> Result<()> {
+        if !self.hidden.insert(hash.to_string()) {
+            return Ok(());
+        }
+        let Some(Object::Tree(tree)) = self.read_object(hash, ObjectType::Tree)? else {
+            return Ok(());
+        };
+        for file in tree.files {
+            match file.object_type() {
+                ObjectType::Tree => self.hide_tree(&file.hash)?,
+                ObjectType::Blob => {
+                    self.hidden.insert(file.hash);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the trees and blobs of a commit that weren't listed for
+    /// previous commits or hidden, as (hash, path) pairs, the root tree
+    /// having an empty path. Trees come before their entries.
+    pub fn objects(&mut self, commit: &Commit) -> Result<Vec<(String, String)>> {
+        let mut objects = vec![];
+        self.tree_objects(&commit.tree, "", &mut objects)?;
+        Ok(objects)
+    }
+
+    fn tree_objects(
+        &mut self,
+        hash: &str,
+        path: &str,
+        objects: &mut Vec<(String, String)>,
+    ) -> Result<()> {
+        if self.hidden.contains(hash) || !self.seen_objects.insert(hash.to_string()) {
+            return Ok(());
+        }
+        let Some(Object::Tree(tree)) = self.read_object(hash, ObjectType::Tree)? else {
+            return Ok(());
+        };
+        objects.push((hash.to_string(), path.to_string()));
+        for file in tree.files {
+            let file_path = match path {
+                "" => file.name.clone(),
+                _ => format!("{path}/{}", file.name),
+            };
+            match file.object_type() {
+                ObjectType::Tree => self.tree_objects(&file.hash, &file_path, objects)?,
+                ObjectType::Blob => {
+                    if self.hidden.contains(&file.hash)
+                        || !self.seen_objects.insert(file.hash.clone())
+                    {
+                        continue;
+                    }
+                    if self.check_exists(&file.hash, ObjectType::Blob)? {
+                        objects.push((file.hash, file_path));
+                    }
+                }
+                // Submodule commits are in other repositories.
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The missing objects found so far, with [`Missing::Print`].
+    pub fn missing_objects(&self) -> &[String] {
+        &self.missing_objects
+    }
+
+    fn enqueue(&mut self, hash: String, commit: Commit) {
+        if let Some(prefetcher) = &mut self.prefetcher {
+            for parent in &commit.parents {
+                prefetcher.request(parent);
+            }
+        }
+        self.found += 1;
+        if !self.hidden.contains(&hash) {
+            self.interesting += 1;
+        }
+        self.queued.insert(hash.clone());
+        let timestamp = commit.committer_timestamp();
+        self.queue
+            .push((timestamp, Reverse(self.found), hash, CommitBox(commit)));
+    }
+
+    /// Returns whether an object exists, or fails if missing objects aren't
+    /// allowed.
+    fn check_exists(&mut self, hash: &str, object_type: ObjectType) -> Result<bool> {
+        if object::exists(self.repo, hash) {
+            return Ok(true);
+        }
+        match self.missing {
+            Missing::Error => Err(anyhow!("missing {} object '{hash}'", object_type.as_str())),
+            Missing::AllowAny => Ok(false),
+            Missing::Print => {
+                self.missing_objects.push(hash.to_string());
+                Ok(false)
+            }
+        }
+    }
+
+    fn read_object(&mut self, hash: &str, object_type: ObjectType) -> Result<Option<Object>> {
+        if !self.check_exists(hash, object_type)? {
+            return Ok(None);
+        }
+        Object::from_hash(self.repo, hash).map(Some)
+    }
+
+    /// Removes the parents of shallow commits, which weren't fetched.
+    fn graft(&self, hash: &str, mut commit: Commit) -> Commit {
+        if self.shallow.contains(hash) {
+            commit.parents.clear();
+        }
+        commit
+    }
+
+    /// Reads a commit, failing with `error` if it's missing and missing
+    /// objects aren't allowed.
+    fn read_commit(
+        &mut self,
+        hash: &str,
+        error: impl FnOnce() -> anyhow::Error,
+    ) -> Result<Option<Commit>> {
+        if !object::exists(self.repo, hash) && self.missing == Missing::Error {
+            return Err(error());
+        }
+        match self.read_object(hash, ObjectType::Commit)? {
+            Some(Object::Commit(commit)) => Ok(Some(self.graft(hash, commit))),
+            Some(_) => Err(anyhow!("Not a commit: {hash}")),
+            None => Ok(None),
+        }
+    }
+}
+
+impl RevWalk<'_> {
+    /// Returns the next commit by commit date, queueing its parents.
+    fn next_by_date(&mut self) -> Option<Result<(String, Commit)>> {
+        let (_, _, hash, CommitBox(mut commit)) = loop {
+            if self.interesting == 0 {
+                return None;
+            }
+            let next = self.queue.pop()?;
+            self.queued.remove(&next.2);
+            if self.hidden.contains(&next.2) {
+                if let Err(e) = self.hide_parents(&next.3 .0) {
+                    return Some(Err(e));
+                }
+                continue;
+            }
+            self.interesting -= 1;
+            if self.limits.since.is_none_or(|since| next.0 >= since) {
+                break next;
+            }
+        };
+        if !self.paths.is_empty() {
+            match self.simplify(&mut commit) {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.treesame.insert(hash.clone());
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        let parents = match self.first_parent {
+            true => &commit.parents[..commit.parents.len().min(1)],
+            false => &commit.parents[..],
+        };
+        for parent in parents {
+            if self.hidden.contains(parent) || !self.seen.insert(parent.clone()) {
+                continue;
+            }
+            if self.left.contains(&hash) {
+                self.left.insert(parent.clone());
+            }
+            let prefetched = self
+                .prefetcher
+                .as_mut()
+                .and_then(|prefetcher| prefetcher.get(parent));
+            let error = || anyhow!("Failed to traverse parents of commit {hash}");
+            let parent_commit = match prefetched {
+                Some(parent_commit) => Ok(Some(self.graft(parent, parent_commit))),
+                None => self.read_commit(parent, error),
+            };
+            match parent_commit {
+                Ok(Some(parent_commit)) => self.enqueue(parent.clone(), parent_commit),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok((hash, commit)))
+    }
+
+    /// Hides the trees and blobs of the hidden parents of the walked
+    /// commits, like git's edges, so that [`RevWalk::objects`] doesn't list
+    /// the objects they have.
+    fn hide_edges(&mut self, commits: &[(String, Commit)]) -> Result<()> {
+        for (_, commit) in commits {
+            for parent in &commit.parents {
+                if !self.hidden.contains(parent) {
+                    continue;
+                }
+                if let Some(parent_commit) =
+                    self.read_commit(parent, || anyhow!("bad object {parent}"))?
+                {
+                    self.hide_tree(&parent_commit.tree)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares the paths of a commit with its parents, keeping only the
+    /// first parent with the same paths that isn't hidden, if any. Returns
+    /// whether the paths changed, compared to nothing for root commits.
+    fn simplify(&mut self, commit: &mut Commit) -> Result<bool> {
+        if commit.parents.is_empty() {
+            return self.paths_differ(&commit.tree, None);
+        }
+        let parents = match self.first_parent {
+            true => &commit.parents[..1],
+            false => &commit.parents[..],
+        };
+        let mut changed = false;
+        for parent in parents {
+            let Some(parent_commit) =
+                self.read_commit(parent, || anyhow!("bad object {parent}"))?
+            else {
+                continue;
+            };
+            if !self.paths_differ(&commit.tree, Some(&parent_commit.tree))? {
+                if !self.hidden.contains(parent) {
+                    commit.parents = vec![parent.clone()];
+                    return Ok(false);
+                }
+            } else {
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Returns whether the walked paths differ between two trees, a missing
+    /// tree having none of them.
+    fn paths_differ(&mut self, tree: &str, other: Option<&str>) -> Result<bool> {
+        for path in self.paths.clone() {
+            let hash = self.path_hash(tree, &path)?;
+            let other_hash = match other {
+                Some(other) => self.path_hash(other, &path)?,
+                None => None,
+            };
+            if hash != other_hash {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the hash of the tree or blob at a path of a tree, if any.
+    fn path_hash(&mut self, tree: &str, path: &str) -> Result<Option<String>> {
+        let mut hash = tree.to_string();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let Some(Object::Tree(tree)) = self.read_object(&hash, ObjectType::Tree)? else {
+                return Ok(None);
+            };
+            match tree.files.into_iter().find(|file| file.name == name) {
+                Some(file) => hash = file.hash,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(hash))
+    }
+}
+
+impl Iterator for RevWalk<'_> {
+    type Item = Result<(String, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if (self.topo_order || self.limited) && self.sorted.is_none() {
+            let mut commits = vec![];
+            while let Some(next) = self.next_by_date() {
+                match next {
+                    Ok(commit) => commits.push(commit),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            if let Err(e) = self.hide_edges(&commits) {
+                return Some(Err(e));
+            }
+            self.sorted = Some(match self.topo_order {
+                true => sort_topo(commits, self.first_parent),
+                false => commits.into(),
+            });
+        }
+        loop {
+            if self
+                .limits
+                .max_count
+                .is_some_and(|max| self.returned >= max)
+            {
+                return None;
+            }
+            let (hash, commit) = match &mut self.sorted {
+                Some(sorted) => sorted.pop_front()?,
+                None => match self.next_by_date()? {
+                    Ok(next) => next,
+                    Err(e) => return Some(Err(e)),
+                },
+            };
+            // Commits are sorted before they're filtered, like git.
+            if !self.treesame.contains(&hash) && self.limits.matches(&commit) {
+                self.returned += 1;
+                return Some(Ok((hash, commit)));
+            }
+        }
+    }
+}
+
+/// Sorts commits so that they come before their parents, like git's graph
+/// order: starting from the commits without children, in their order, a
+/// commit whose children were all sorted is pushed on a stack, so that each
+/// line of history is finished before the next.
+fn sort_topo(commits: Vec<(String, Commit)>, first_parent: bool) -> VecDeque<(String, Commit)> {
+    let parents = |commit: &Commit| match first_parent {
+        true => commit.parents.len().min(1),
+        false => commit.parents.len(),
+    };
+    // One more than the number of children left to sort, and 0 once
+    // sorted, like git.
+    let mut indegrees: HashMap<&str, usize> =
+        commits.iter().map(|(hash, _)| (hash.as_str(), 1)).collect();
+    for (_, commit) in &commits {
+        for parent in &commit.parents[..parents(commit)] {
+            if let Some(indegree) = indegrees.get_mut(parent.as_str()) {
+                *indegree += 1;
+            }
+        }
+    }
+    let index: HashMap<&str, usize> = commits
+        .iter()
+        .enumerate()
+        .map(|(i, (hash, _))| (hash.as_str(), i))
+        .collect();
+    let mut stack: Vec<usize> = (0..commits.len())
+        .rev()
+        .filter(|&i| indegrees[commits[i].0.as_str()] == 1)
+        .collect();
+    let mut order = vec![];
+    while let Some(i) = stack.pop() {
+        let commit = &commits[i].1;
+        for parent in &commit.parents[..parents(commit)] {
+            let Some(indegree) = indegrees.get_mut(parent.as_str()) else {
+                continue;
+            };
+            if *indegree == 0 {
+                continue;
+            }
+            *indegree -= 1;
+            if *indegree == 1 {
+                stack.push(index[parent.as_str()]);
+            }
+        }
+        indegrees.insert(commits[i].0.as_str(), 0);
+        order.push(i);
+    }
+    let mut commits: Vec<Option<(String, Commit)>> = commits.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .filter_map(|i| commits[i].take())
+        .collect()
+}
+
+/// Reads and decodes commits in a background thread: the ones requested
+/// first, then their ancestors while the bounded queue of decoded commits
+/// has room.
+pub(crate) struct Prefetcher {
+    requests: mpsc::SyncSender<String>,
+    /// The decoded commits, or `None` for objects that couldn't be read as
+    /// commits.
+    results: mpsc::Receiver<(String, Option<Commit>)>,
+    /// The commits received while waiting for another one.
+    ready: HashMap<String, Option<Commit>>,
+    /// The commits received so far.
+    received: HashSet<String>,
+}
+
+impl Prefetcher {
+    /// Starts the thread, which only follows the first parent of commits
+    /// with `first_parent`. Both the requests and the decoded commits are
+    /// queued up to `depth`.
+    pub(crate) fn new(repo: &Repo, depth: usize, first_parent: bool) -> Prefetcher {
+        let (requests, requests_receiver) = mpsc::sync_channel::<String>(depth);
+        let (results_sender, results) = mpsc::sync_channel(depth);
+        let repo = repo.clone();
+        thread::spawn(move || {
+            let mut requested = VecDeque::new();
+            let mut ancestors = VecDeque::new();
+            let mut done = HashSet::new();
+            loop {
+                requested.extend(requests_receiver.try_iter());
+                let hash = match requested.pop_front().or_else(|| ancestors.pop_front()) {
+                    Some(hash) => hash,
+                    // Stop when the prefetcher is dropped.
+                    None => match requests_receiver.recv() {
+                        Ok(hash) => hash,
+                        Err(_) => return,
+                    },
+                };
+                if !done.insert(hash.clone()) {
+                    continue;
+                }
+                let commit = match Object::from_hash(&repo, &hash) {
+                    Ok(Object::Commit(commit)) => Some(commit),
+                    _ => None,
+                };
+                if let Some(commit) = &commit {
+                    let parents = match first_parent {
+                        true => &commit.parents[..commit.parents.len().min(1)],
+                        false => &commit.parents[..],
+                    };
+                    ancestors.extend(parents.iter().filter(|p| !done.contains(*p)).cloned());
+                }
+                if results_sender.send((hash, commit)).is_err() {
+                    return;
+                }
+            }
+        });
+        Prefetcher {
+            requests,
+            results,
+            ready: HashMap::new(),
+            received: HashSet::new(),
+        }
+    }
+
+    /// Asks for a commit to be decoded before the ancestors of the ones
+    /// requested so far. Returns false if the queue of requests is full, or
+    /// if the commit was already received.
+    pub(crate) fn request(&mut self, hash: &str) -> bool {
+        // Waiting for room could deadlock with the thread waiting for room
+        // for its results. The thread only stops once the prefetcher is
+        // dropped.
+        !self.received.contains(hash) && self.requests.try_send(hash.to_string()).is_ok()
+    }
+
+    /// Returns a commit, waiting for the thread to decode it. Returns `None`
+    /// if it couldn't, and the caller should read the object itself.
+    ///
+    /// Each commit can only be taken once.
+    pub(crate) fn get(&mut self, hash: &str) -> Option<Commit> {
+        if self.received.contains(hash) {
+            return self.ready.remove(hash).flatten();
+        }
+        if !self.request(hash) {
+            return None;
+        }
+        while let Ok((decoded, commit)) = self.results.recv() {
+            self.received.insert(decoded.clone());
+            if decoded == hash {
+                return commit;
+            }
+            self.ready.insert(decoded, commit);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_commit(repo: &Repo, tree: &str, parents: &[&str], timestamp: i64) -> String {
+        let mut content = format!("tree {tree}\n");
+        for parent in parents {
+            content.push_str(&format!("parent {parent}\n"));
+        }
+        content.push_str(&format!(
+            "author A <a@a> {timestamp} +0000\n\
+             committer A <a@a> {timestamp} +0000\n\nmessage\n"
+        ));
+        object::write_object(repo, ObjectType::Commit, content.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_rev_walk() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let tree = object::write_object(&repo, ObjectType::Tree, b"").unwrap();
+        let root = write_commit(&repo, &tree, &[], 100);
+        let old = write_commit(&repo, &tree, &[&root], 200);
+        let new = write_commit(&repo, &tree, &[&root], 300);
+        let merge = write_commit(&repo, &tree, &[&old, &new], 400);
+
+        let walk = |tips: &[&String], hidden: &[&String]| {
+            let mut walks = vec![];
+            for prefetch in [None, Some(1), Some(DEFAULT_PREFETCH)] {
+                let mut walk = RevWalk::new(&repo, Missing::Error).unwrap();
+                if let Some(depth) = prefetch {
+                    walk.prefetch(depth);
+                }
+                for hash in hidden {
+                    walk.hide(hash).unwrap();
+                }
+                for hash in tips {
+                    walk.push(hash).unwrap();
+                }
+                walks.push(walk.map(|next| next.unwrap().0).collect::<Vec<String>>());
+            }
+            // Prefetching doesn't change the walk.
+            assert!(walks.iter().all(|hashes| *hashes == walks[0]));
+            walks.remove(0)
+        };
+        // Newest first, even across parents.
+        assert_eq!(
+            walk(&[&merge], &[]),
+            [&merge, &new, &old, &root].map(String::clone)
+        );
+        assert_eq!(
+            walk(&[&old, &new], &[]),
+            [&new, &old, &root].map(String::clone)
+        );
+        assert_eq!(walk(&[&merge], &[&old]), [&merge, &new].map(String::clone));
+
+        // The sides of `old...new` stop at their merge base.
+        let mut walk = RevWalk::new(&repo, Missing::Error).unwrap();
+        walk.hide(&root).unwrap();
+        walk.push_left(&old).unwrap();
+        walk.push(&new).unwrap();
+        let hashes: Vec<String> = walk.by_ref().map(|next| next.unwrap().0).collect();
+        let sides: Vec<(&String, bool)> = hashes
+            .iter()
+            .map(|hash| (hash, walk.is_left(hash)))
+            .collect();
+        assert_eq!(sides, [(&new, false), (&old, true)]);
+
+        let mut walk = RevWalk::new(&repo, Missing::Error).unwrap();
+        walk.hide(&new).unwrap();
+        walk.push(&merge).unwrap();
+        let commits: Vec<_> = walk.by_ref().map(|next| next.unwrap().1).collect();
+        // The empty tree is hidden with the commits reachable from `new`.
+        assert!(walk.objects(&commits[0]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_hide_is_lazy() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let tree = object::write_object(&repo, ObjectType::Tree, b"").unwrap();
+        // The history below the hidden commit is missing, and isn't read.
+        let old = write_commit(
+            &repo,
+            &tree,
+            &["1111111111111111111111111111111111111111"],
+            100,
+        );
+        let hidden = write_commit(&repo, &tree, &[&old], 200);
+        let new = write_commit(&repo, &tree, &[&hidden], 300);
+        let side = write_commit(&repo, &tree, &[&old], 400);
+
+        let mut walk = RevWalk::new(&repo, Missing::Error).unwrap();
+        walk.hide(&hidden).unwrap();
+        walk.push(&new).unwrap();
+        let hashes: Vec<String> = walk.map(|next| next.unwrap().0).collect();
+        assert_eq!(hashes, [new.clone()]);
+
+        // Commits found before they're known to be hidden aren't returned.
+        let mut walk = RevWalk::new(&repo, Missing::AllowAny).unwrap();
+        walk.hide(&hidden).unwrap();
+        walk.push(&side).unwrap();
+        let hashes: Vec<String> = walk.map(|next| next.unwrap().0).collect();
+        assert_eq!(hashes, [side]);
+    }
+
+    #[test]
+    fn test_prefetch_bare() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        crate::init_repo(&Repo::new(tmpdir.path()), "main").unwrap();
+        // The thread reads the objects of the walked repository, which
+        // doesn't have a working tree.
+        let repo = Repo::bare(&tmpdir.path().join(".git"));
+        let tree = object::write_object(&repo, ObjectType::Tree, b"").unwrap();
+        let root = write_commit(&repo, &tree, &[], 100);
+        let child = write_commit(&repo, &tree, &[&root], 200);
+
+        let mut prefetcher = Prefetcher::new(&repo, 1, false);
+        assert!(prefetcher.get(&child).is_some());
+        assert!(prefetcher.get(&root).is_some());
+        assert!(prefetcher.get(&"0".repeat(40)).is_none());
+    }
+
+    #[test]
+    fn test_topo_order() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let tree = object::write_object(&repo, ObjectType::Tree, b"").unwrap();
+        let root = write_commit(&repo, &tree, &[], 100);
+        let side = write_commit(&repo, &tree, &[&root], 200);
+        let main = write_commit(&repo, &tree, &[&root], 300);
+        let more_side = write_commit(&repo, &tree, &[&side], 400);
+        // Committed with a clock behind, before its parent by date.
+        let skewed = write_commit(&repo, &tree, &[&main], 50);
+        let merge = write_commit(&repo, &tree, &[&skewed, &more_side], 500);
+
+        let walk = |topo_order: bool, first_parent: bool| {
+            let mut walk = RevWalk::new(&repo, Missing::Error).unwrap();
+            walk.set_topo_order(topo_order);
+            walk.set_first_parent(first_parent);
+            walk.push(&merge).unwrap();
+            walk.map(|next| next.unwrap().0).collect::<Vec<String>>()
+        };
+        // By date, the root comes before commits reachable from the skewed
+        // one.
+        assert_eq!(
+            walk(false, false),
+            [&merge, &more_side, &side, &root, &skewed, &main].map(String::clone)
+        );
+        // Each line of history is finished before the next, the one of the
+        // last parent first.
+        assert_eq!(
+            walk(true, false),
+            [&merge, &more_side, &side, &skewed, &main, &root].map(String::clone)
+        );
+        assert_eq!(
+            walk(true, true),
+            [&merge, &skewed, &main, &root].map(String::clone)
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let tree = object::write_object(&repo, ObjectType::Tree, b"").unwrap();
+        let root = write_commit(&repo, &tree, &[], 100);
+        let old = write_commit(&repo, &tree, &[&root], 200);
+        let new = write_commit(&repo, &tree, &[&old], 300);
+        let newest = write_commit(&repo, &tree, &[&new], 400);
+
+        let walk = |limits: Limits| {
+            let mut walk = RevWalk::new(&repo, Missing::Error).unwrap();
+            walk.set_limits(limits);
+            walk.push(&newest).unwrap();
+            walk.map(|next| next.unwrap().0).collect::<Vec<String>>()
+        };
+        let limits = Limits {
+            max_count: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(walk(limits), [&newest, &new].map(String::clone));
+        let limits = Limits {
+            since: Some(200),
+            until: Some(300),
+            ..Default::default()
+        };
+        assert_eq!(walk(limits), [&new, &old].map(String::clone));
+        // Dates aren't matched.
+        let limits = Limits {
+            authors: vec![Regex::new("^A <a@a>$").unwrap()],
+            committers: vec![Regex::new("1").unwrap(), Regex::new("A").unwrap()],
+            messages: vec![Regex::new("^mess").unwrap()],
+            max_count: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(walk(limits), [&newest, &new, &old].map(String::clone));
+        let limits = Limits {
+            authors: vec![Regex::new("300").unwrap()],
+            ..Default::default()
+        };
+        assert!(walk(limits).is_empty());
+    }
+
+    #[test]
+    fn test_paths() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let tree = |files: &[(&str, &str)]| {
+            let files = files
+                .iter()
+                .map(|(name, content)| object::File {
+                    mode: "100644".to_string(),
+                    hash: object::write_object(&repo, ObjectType::Blob, content.as_bytes())
+                        .unwrap(),
+                    name: name.to_string(),
+                })
+                .collect();
+            let tree = object::Tree::new(files);
+            object::write_object(&repo, ObjectType::Tree, &tree.to_bytes()).unwrap()
+        };
+        let root = write_commit(&repo, &tree(&[("a", "1")]), &[], 100);
+        let main = write_commit(&repo, &tree(&[("a", "1"), ("b", "1")]), &[&root], 200);
+        let side = write_commit(&repo, &tree(&[("a", "2")]), &[&root], 300);
+        let merge = write_commit(
+            &repo,
+            &tree(&[("a", "2"), ("b", "1")]),
+            &[&main, &side],
+            400,
+        );
+        let both = write_commit(&repo, &tree(&[("a", "3"), ("b", "3")]), &[&merge], 500);
+
+        let walk = |paths: &[&str]| {
+            let mut walk = RevWalk::new(&repo, Missing::Error).unwrap();
+            walk.set_paths(
+                &paths
+                    .iter()
+                    .map(|path| path.to_string())
+                    .collect::<Vec<_>>(),
+            );
+            walk.push(&both).unwrap();
+            walk.map(|next| next.unwrap().0).collect::<Vec<String>>()
+        };
+        assert_eq!(walk(&[]).len(), 5);
+        // The merge is only walked through the parent it took the path from.
+        assert_eq!(walk(&["a"]), [&both, &side, &root].map(String::clone));
+        assert_eq!(walk(&["b/"]), [&both, &main].map(String::clone));
+        assert_eq!(
+            walk(&["a", "b"]),
+            [&both, &merge, &side, &main, &root].map(String::clone)
+        );
+        assert!(walk(&["c"]).is_empty());
+    }
+
+    #[test]
+    fn test_missing_from_str() {
+        assert_eq!("print".parse::<Missing>().unwrap(), Missing::Print);
+        assert_eq!("allow-any".parse::<Missing>().unwrap(), Missing::AllowAny);
+        assert!("allow-promisor".parse::<Missing>().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Result};
+use std::{fs, path::PathBuf};
+
+use crate::{
+    object::{self, Object},
+    repo::Repo,
+};
+
+/// What is done with a commit of a [`State`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Apply its changes, like `git cherry-pick`.
+    Pick,
+    /// Apply the inverse of its changes, like `git revert`.
+    Revert,
+}
+
+impl Action {
+    /// The git command doing it, `cherry-pick` or `revert`.
+    pub fn command(self) -> &'static str {
+        match self {
+            Action::Pick => "cherry-pick",
+            Action::Revert => "revert",
+        }
+    }
+}
+
+/// The state of a cherry-pick or a revert of several commits stopped at one
+/// of them, kept in `.git/sequencer` like git's: the commits left, the one it
+/// stopped at first, in `todo` with lines like `pick <hash> <subject>`, where
+/// HEAD was before in `head`, and where HEAD was after the last commit in
+/// `abort-safety`.
+///
+/// The state of the commit it stopped at is a [`crate::merge::State`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    /// The commit HEAD was at before, if any, to go back to when aborting.
+    pub head: Option<String>,
+    /// The commits left, with what to do with them.
+    pub todo: Vec<(Action, String)>,
+    /// Where HEAD was after the last commit, so that aborting doesn't undo
+    /// commits made since.
+    pub abort_safety: Option<String>,
+    /// Whether the changes are only applied to the index and the working
+    /// tree, without committing them.
+    pub no_commit: bool,
+}
+
+impl State {
+    /// Reads the state of the cherry-pick or revert in progress, if any.
+    pub fn read(repo: &Repo) -> Result<Option<State>> {
+        let read = |name: &str| match fs::read_to_string(dir(repo).join(name)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+        let Some(todo) = read("todo")? else {
+            return Ok(None);
+        };
+        let todo = todo
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (action, hash) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["pick" | "p", hash, ..] => (Action::Pick, hash),
+                    ["revert", hash, ..] => (Action::Revert, hash),
+                    _ => return Err(anyhow!("invalid line in {}: {line}", dir(repo).display())),
+                };
+                Ok((action, Object::resolve_rev(repo, hash)?))
+            })
+            .collect::<Result<_>>()?;
+        let hash = |value: Option<String>| value.map(|value| value.trim().to_string());
+        Ok(Some(State {
+            head: hash(read("head")?).filter(|head| !head.is_empty()),
+            todo,
+            abort_safety: hash(read("abort-safety")?).filter(|head| !head.is_empty()),
+            no_commit: read("opts")?.is_some_and(|opts| opts.contains("no-commit = true")),
+        }))
+    }
+
+    /// Saves the state, with the commits left to pick last so that it's only
+    /// found once complete.
+    pub fn write(&self, repo: &Repo) -> Result<()> {
+        fs::create_dir_all(dir(repo))?;
+        let head = |hash: &Option<String>| format!("{}\n", hash.as_deref().unwrap_or(""));
+        fs::write(dir(repo).join("head"), head(&self.head))?;
+        fs::write(dir(repo).join("abort-safety"), head(&self.abort_safety))?;
+        match self.no_commit {
+            true => fs::write(dir(repo).join("opts"), "[options]\n\tno-commit = true\n")?,
+            false => {
+                let _ = fs::remove_file(dir(repo).join("opts"));
+            }
+        }
+        let abbreviator = object::Abbreviator::from_config(repo)?;
+        let mut todo = String::new();
+        for (action, hash) in &self.todo {
+            let subject = match Object::from_hash(repo, hash)? {
+                Object::Commit(commit) => commit.message.lines().next().unwrap_or("").to_string(),
+                _ => String::new(),
+            };
+            let name = match action {
+                Action::Pick => "pick",
+                Action::Revert => "revert",
+            };
+            todo.push_str(&format!(
+                "{name} {} {subject}\n",
+                abbreviator.abbreviate(hash)?
+            ));
+        }
+        fs::write(dir(repo).join("todo"), todo)?;
+        Ok(())
+    }
+
+    /// Removes the state once the cherry-pick or revert is over.
+    pub fn remove(repo: &Repo) -> Result<()> {
+        match fs::remove_dir_all(dir(repo)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The directory of the state, `.git/sequencer`.
+pub fn dir(repo: &Repo) -> PathBuf {
+    repo.git_dir().join("sequencer")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Commit, ObjectType};
+
+    #[test]
+    fn test_write_and_read() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        assert_eq!(State::read(&repo).unwrap(), None);
+
+        let commit = |message: &str| {
+            let commit = Commit {
+                tree: object::write_object(&repo, ObjectType::Tree, &[]).unwrap(),
+                author: "A U Thor <author@example.com> 1 +0000".to_string(),
+                committer: "A U Thor <author@example.com> 1 +0000".to_string(),
+                message: message.to_string(),
+                ..Default::default()
+            };
+            object::write_object(&repo, ObjectType::Commit, &commit.to_bytes()).unwrap()
+        };
+        let (a, b) = (commit("First\n"), commit("Second\n"));
+        let mut state = State {
+            head: Some(a.clone()),
+            todo: vec![(Action::Pick, a.clone()), (Action::Revert, b.clone())],
+            abort_safety: Some(a.clone()),
+            no_commit: false,
+        };
+        state.write(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&state));
+        let abbreviator = object::Abbreviator::from_config(&repo).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir(&repo).join("todo")).unwrap(),
+            format!(
+                "pick {} First\nrevert {} Second\n",
+                abbreviator.abbreviate(&a).unwrap(),
+                abbreviator.abbreviate(&b).unwrap()
+            )
+        );
+
+        state.todo.remove(0);
+        state.head = None;
+        state.no_commit = true;
+        state.write(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap().as_ref(), Some(&state));
+
+        State::remove(&repo).unwrap();
+        assert_eq!(State::read(&repo).unwrap(), None);
+    }
+}
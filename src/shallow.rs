@@ -0,0 +1,62 @@
+use anyhow::Result;
+use std::{collections::HashSet, fs, io::Write};
+
+use crate::{lockfile::LockFile, repo::Repo};
+
+/// Returns the shallow commits of a repository, listed in `.git/shallow`:
+/// the commits whose parents weren't fetched. Walks treat them as having no
+/// parents.
+pub fn read(repo: &Repo) -> Result<HashSet<String>> {
+    match fs::read_to_string(repo.common_dir().join("shallow")) {
+        Ok(content) => Ok(content.lines().map(str::to_string).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes the shallow commits of a repository, sorted like git. Without
+/// shallow commits, `.git/shallow` is removed and the repository is complete
+/// again.
+pub fn write(repo: &Repo, shallow: &HashSet<String>) -> Result<()> {
+    let path = repo.common_dir().join("shallow");
+    let mut lock = LockFile::acquire(&path)?;
+    if shallow.is_empty() {
+        // Removing the file while holding the lock keeps concurrent writers
+        // out.
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => return Ok(()),
+        }
+    }
+    let mut hashes: Vec<&String> = shallow.iter().collect();
+    hashes.sort();
+    for hash in hashes {
+        writeln!(lock, "{hash}")?;
+    }
+    lock.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        fs::create_dir_all(repo.git_dir()).unwrap();
+        assert!(read(&repo).unwrap().is_empty());
+
+        let shallow: HashSet<String> = ["b".repeat(40), "a".repeat(40)].into();
+        write(&repo, &shallow).unwrap();
+        assert_eq!(
+            fs::read_to_string(repo.git_dir().join("shallow")).unwrap(),
+            format!("{}\n{}\n", "a".repeat(40), "b".repeat(40))
+        );
+        assert_eq!(read(&repo).unwrap(), shallow);
+
+        write(&repo, &HashSet::new()).unwrap();
+        assert!(!repo.git_dir().join("shallow").exists());
+        assert!(!repo.git_dir().join("shallow.lock").exists());
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{object::Object, refs, repo::Repo};
+
+/// A read-only view of a repository as it was when [`Repo::snapshot`] was
+/// called.
+///
+/// Operations that read many refs and objects can use a snapshot to see a
+/// consistent repository while another process commits, updates refs or
+/// packs them: refs keep the values they had, and objects written since are
+/// not visible, so the view never mixes states.
+///
+/// Objects are immutable and never rewritten, so only their hashes are
+/// listed when the snapshot is taken, and they're read lazily. An object
+/// that is pruned from the object database after the snapshot is taken
+/// can't be read anymore.
+pub struct Snapshot<'a> {
+    repo: &'a Repo,
+    /// The raw values of the refs, as in their files.
+    refs: BTreeMap<String, String>,
+    /// The hashes of the objects when the snapshot was taken.
+    objects: BTreeSet<String>,
+}
+
+impl<'a> Snapshot<'a> {
+    pub(crate) fn new(repo: &'a Repo) -> Result<Snapshot<'a>> {
+        // Refs are read first, so the objects they point to are listed.
+        let refs = refs::read_all(repo)?;
+        Ok(Snapshot {
+            repo,
+            refs,
+            objects: Object::all_hashes(repo)?.into_iter().collect(),
+        })
+    }
+
+    pub fn repo(&self) -> &'a Repo {
+        self.repo
+    }
+
+    /// Returns what HEAD points to, see [`refs::head`].
+    pub fn head(&self) -> Result<refs::Head> {
+        let value = self.refs.get("HEAD").ok_or(anyhow!("HEAD not found"))?;
+        Ok(refs::Head::from_value(value))
+    }
+
+    /// Resolves a ref name to a hash, see [`refs::find_ref`].
+    pub fn find_ref(&self, name: &str) -> Result<String> {
+        refs::resolve(name, |name| Ok(self.refs.get(name).cloned()))
+    }
+
+    /// Returns all refs under `refs/`, see [`refs::list`].
+    pub fn list(&self) -> Vec<(String, String)> {
+        self.refs
+            .keys()
+            .filter(|name| name.starts_with("refs/"))
+            .filter_map(|name| self.find_ref(name).ok().map(|hash| (name.clone(), hash)))
+            .collect()
+    }
+
+    /// Returns whether an object is in the snapshot.
+    pub fn contains(&self, hash: &str) -> bool {
+        self.objects.contains(hash)
+    }
+
+    /// Returns the hashes of all objects in the snapshot, sorted.
+    pub fn hashes(&self) -> Result<Vec<String>> {
+        Ok(self.objects.iter().cloned().collect())
+    }
+
+    /// Returns an object, if it is in the snapshot.
+    pub fn object(&self, hash: &str) -> Result<Object> {
+        if !self.contains(hash) {
+            return Err(anyhow!("Object not found: {hash}"));
+        }
+        Object::from_hash(self.repo, hash)
+    }
+
+    /// Follows annotated tags, see [`Object::peel`].
+    pub fn peel(&self, hash: &str) -> Result<String> {
+        let mut hash = hash.to_string();
+        while let Object::Tag(tag) = self.object(&hash)? {
+            hash = tag.object;
+        }
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{self, ObjectType};
+    use std::{fs, time::SystemTime};
+
+    #[test]
+    fn test_snapshot_is_not_affected_by_later_changes() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmpdir.path());
+        crate::init_repo(&repo, "main").unwrap();
+        let a = object::write_object(&repo, ObjectType::Blob, b"a\n").unwrap();
+        refs::update_ref(&repo, "refs/heads/main", &a).unwrap();
+        refs::update_ref(&repo, "refs/tags/v1", &a).unwrap();
+
+        let snapshot = repo.snapshot().unwrap();
+
+        // Another process writes an object, updates main and packs the tag.
+        // The file time of the object doesn't matter, even if it's earlier.
+        let b = object::write_object(&repo, ObjectType::Blob, b"b\n").unwrap();
+        let set_modified = |time: SystemTime| {
+            fs::File::options()
+                .write(true)
+                .open(object::object_path(&repo, &b).unwrap())
+                .unwrap()
+                .set_modified(time)
+                .unwrap()
+        };
+        set_modified(SystemTime::UNIX_EPOCH);
+        refs::update_ref(&repo, "refs/heads/main", &b).unwrap();
+        std::fs::write(
+            repo.git_dir().join("packed-refs"),
+            format!("{b} refs/tags/v1\n"),
+        )
+        .unwrap();
+        std::fs::remove_file(repo.git_dir().join("refs/tags/v1")).unwrap();
+
+        assert_eq!(
+            snapshot.head().unwrap(),
+            refs::Head::Branch("refs/heads/main".to_string())
+        );
+        assert_eq!(snapshot.find_ref("HEAD").unwrap(), a);
+        assert_eq!(
+            snapshot.list(),
+            vec![
+                ("refs/heads/main".to_string(), a.clone()),
+                ("refs/tags/v1".to_string(), a.clone()),
+            ]
+        );
+        assert!(snapshot.object(&a).is_ok());
+        assert_eq!(
+            snapshot.object(&b).unwrap_err().to_string(),
+            format!("Object not found: {b}")
+        );
+        assert_eq!(snapshot.hashes().unwrap(), vec![a.clone()]);
+
+        let snapshot = repo.snapshot().unwrap();
+        assert_eq!(snapshot.find_ref("refs/tags/v1").unwrap(), b);
+        assert!(snapshot.contains(&b));
+    }
+}
@@ -0,0 +1,968 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    path::Path,
+};
+
+use crate::{
+    attributes::{AttributeValue, Attributes},
+    config::Config,
+    filter::Filters,
+    ignore::{self, Ignore},
+    index::{self, Index},
+    journal::Journal,
+    lfs,
+    object::{self, Object},
+    refs,
+    repo::Repo,
+    untracked_cache::{
+        self, ExcludeFile, StatData, UntrackedCache, UntrackedDir, STATUS_DIR_FLAGS,
+    },
+};
+
+/// How a path changed between HEAD and the index, or between the index and
+/// the working tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    /// The file became a symlink or a submodule, or the other way around.
+    TypeChanged,
+    /// Only between HEAD and the index, for exact renames.
+    Renamed,
+}
+
+impl Change {
+    /// Returns the letter for the change in short and porcelain formats.
+    pub fn code(self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::TypeChanged => 'T',
+            Change::Renamed => 'R',
+        }
+    }
+}
+
+/// The status of a tracked path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathStatus {
+    pub path: String,
+    /// The path in HEAD, for renames.
+    pub head_path: Option<String>,
+    /// The change between HEAD and the index.
+    pub staged: Option<Change>,
+    /// The change between the index and the working tree.
+    pub unstaged: Option<Change>,
+    /// The stages of an unmerged path, where stage n is bit n - 1, or 0.
+    pub unmerged: u8,
+    /// The mode and hash of the stages of an unmerged path.
+    pub stages: [Option<(u32, String)>; 3],
+    /// The mode and hash in HEAD.
+    pub head: Option<(u32, String)>,
+    /// The mode and hash in the index.
+    pub index: Option<(u32, String)>,
+    /// The mode in the working tree.
+    pub worktree_mode: Option<u32>,
+}
+
+impl PathStatus {
+    /// Returns the two letters that describe the conflict of an unmerged path
+    /// in short and porcelain formats, e.g. `UU` when both sides modified it.
+    pub fn unmerged_code(&self) -> Option<&'static str> {
+        Some(match self.unmerged {
+            0 => return None,
+            1 => "DD",
+            2 => "AU",
+            3 => "UD",
+            4 => "UA",
+            5 => "DU",
+            6 => "AA",
+            _ => "UU",
+        })
+    }
+}
+
+/// The differences between HEAD, the index and the working tree.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// The changed paths, sorted.
+    pub paths: Vec<PathStatus>,
+    /// The untracked files, sorted, where directories without tracked files are
+    /// listed once with a trailing `/`.
+    pub untracked: Vec<String>,
+    /// The tracked files with the `filter=lfs` attribute that are Git LFS
+    /// pointers in the working tree, sorted: their content wasn't checked
+    /// out.
+    pub lfs_pointers: Vec<String>,
+    /// Whether HEAD is a branch without commits.
+    pub initial: bool,
+    /// The commit of HEAD.
+    pub head: Option<String>,
+}
+
+impl Status {
+    /// Compares HEAD with the index and the index with the working tree, and
+    /// lists the untracked files.
+    ///
+    /// Files whose stat data matches their index entry are assumed to be
+    /// unchanged. Renames are only detected when the content is the same.
+    /// Ignored files aren't listed as untracked.
+    pub fn compute(repo: &Repo) -> Result<Status> {
+        let head = match refs::find_ref(repo, "HEAD") {
+            Ok(hash) => Some(hash),
+            Err(_) if matches!(refs::head(repo)?, refs::Head::Branch(_)) => None,
+            Err(e) => return Err(e),
+        };
+        let mut head_files = BTreeMap::new();
+        if let Some(head) = &head {
+            let Object::Commit(commit) = Object::from_hash(repo, head)? else {
+                return Err(anyhow!("HEAD is not a commit: {head}"));
+            };
+            tree_files(repo, &commit.tree, "", &mut head_files)?;
+        }
+        let mut index = Index::read(repo)?;
+        let modes = index::FileModes::load(repo)?;
+        let filters = Filters::new(repo)?;
+
+        let mut paths: BTreeMap<String, PathStatus> = BTreeMap::new();
+        for entry in &index.entries {
+            if entry.stage > 0 {
+                let status = status_of(&mut paths, &head_files, &entry.path);
+                status.unmerged |= 1 << (entry.stage - 1);
+                status.stages[entry.stage as usize - 1] = Some((entry.mode, entry.hash.clone()));
+                if status.worktree_mode.is_none() {
+                    let full_path = repo.worktree_path(&entry.path)?;
+                    status.worktree_mode = fs::symlink_metadata(full_path)
+                        .ok()
+                        .map(|metadata| modes.mode(&metadata, Some(entry.mode)));
+                }
+                continue;
+            }
+            let staged = match head_files.get(&entry.path) {
+                _ if entry.intent_to_add => None,
+                None => Some(Change::Added),
+                Some((mode, _)) if *mode >> 12 != entry.mode >> 12 => Some(Change::TypeChanged),
+                Some((mode, hash)) if *mode != entry.mode || *hash != entry.hash => {
+                    Some(Change::Modified)
+                }
+                Some(_) => None,
+            };
+            let (unstaged, worktree_mode) = worktree_change(repo, &filters, entry, modes)?;
+            if staged.is_some() || unstaged.is_some() {
+                let status = status_of(&mut paths, &head_files, &entry.path);
+                status.staged = staged;
+                status.unstaged = unstaged;
+                status.index = Some((entry.mode, entry.hash.clone()));
+                status.worktree_mode = worktree_mode;
+            }
+        }
+        let tracked: HashSet<&str> = index.entries.iter().map(|e| e.path.as_str()).collect();
+        let deleted: Vec<&String> = head_files
+            .keys()
+            .filter(|path| !tracked.contains(path.as_str()))
+            .collect();
+        for path in deleted {
+            status_of(&mut paths, &head_files, path).staged = Some(Change::Deleted);
+        }
+        detect_renames(&mut paths);
+
+        // On case-insensitive file systems, files are tracked whatever their
+        // case.
+        let fold = |path: &str| match modes.ignore_case {
+            true => path.to_ascii_lowercase(),
+            false => path.to_string(),
+        };
+        let tracked: HashSet<String> = index.entries.iter().map(|e| fold(&e.path)).collect();
+        let tracked_dirs: HashSet<String> = index
+            .entries
+            .iter()
+            .flat_map(|entry| {
+                entry
+                    .path
+                    .match_indices('/')
+                    .map(|(i, _)| fold(&entry.path[..i]))
+            })
+            .collect();
+        let mut ignore = Ignore::load(repo)?;
+        let mut cache = load_untracked_cache(repo, &index)?;
+        let mut walk = UntrackedWalk {
+            tracked: &tracked,
+            tracked_dirs: &tracked_dirs,
+            ignore_case: modes.ignore_case,
+            ignore: &mut ignore,
+            cache: cache.as_mut(),
+            untracked: vec![],
+        };
+        walk.collect(&repo.root, "", true)?;
+        let untracked = walk.untracked;
+        // Like git, the cache is saved if the index isn't locked.
+        if cache != index.untracked_cache {
+            index.untracked_cache = cache;
+            let _ = index.write(repo);
+        }
+
+        let lfs_pointers = lfs_pointers(repo, &index)?;
+
+        Ok(Status {
+            paths: paths.into_values().collect(),
+            untracked,
+            lfs_pointers,
+            initial: head.is_none(),
+            head,
+        })
+    }
+
+    pub fn has_staged_changes(&self) -> bool {
+        self.paths.iter().any(|path| path.staged.is_some())
+    }
+
+    pub fn has_unmerged(&self) -> bool {
+        self.paths.iter().any(|path| path.unmerged != 0)
+    }
+}
+
+/// Returns the status of a path, adding it if needed.
+fn status_of<'a>(
+    paths: &'a mut BTreeMap<String, PathStatus>,
+    head_files: &BTreeMap<String, (u32, String)>,
+    path: &str,
+) -> &'a mut PathStatus {
+    paths.entry(path.to_string()).or_insert_with(|| PathStatus {
+        path: path.to_string(),
+        head_path: None,
+        staged: None,
+        unstaged: None,
+        unmerged: 0,
+        stages: Default::default(),
+        head: head_files.get(path).cloned(),
+        index: None,
+        worktree_mode: None,
+    })
+}
+
+/// Returns the change of an index entry in the working tree, and the mode of
+/// the file there. Files whose stat data changed are compared once cleaned.
+pub(crate) fn worktree_change(
+    repo: &Repo,
+    filters: &Filters,
+    entry: &index::IndexEntry,
+    modes: index::FileModes,
+) -> Result<(Option<Change>, Option<u32>)> {
+    // Submodules aren't checked, and files outside of a sparse checkout or
+    // marked as unchanged aren't either.
+    if entry.mode == 0o160000 || entry.ignores_worktree() {
+        return Ok((None, Some(entry.mode)));
+    }
+    let full_path = repo.worktree_path(&entry.path)?;
+    let metadata = match fs::symlink_metadata(&full_path) {
+        Ok(metadata) if !metadata.is_dir() => metadata,
+        Ok(_) => return Ok((Some(Change::Deleted), None)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Some(Change::Deleted), None)),
+        Err(e) => return Err(e.into()),
+    };
+    let mode = modes.mode(&metadata, Some(entry.mode));
+    let change = if entry.intent_to_add {
+        Some(Change::Added)
+    } else if mode >> 12 != entry.mode >> 12 {
+        Some(Change::TypeChanged)
+    } else if entry.matches_stat(&metadata, modes) {
+        None
+    } else if mode != entry.mode
+        || index::worktree_blob_hash(filters, &entry.path, &full_path, &metadata)? != entry.hash
+    {
+        Some(Change::Modified)
+    } else {
+        None
+    };
+    Ok((change, Some(mode)))
+}
+
+/// Returns the tracked files with the `filter=lfs` attribute that are Git LFS
+/// pointers in the working tree.
+fn lfs_pointers(repo: &Repo, index: &Index) -> Result<Vec<String>> {
+    let attributes = Attributes::load(repo)?;
+    let mut pointers = vec![];
+    for entry in &index.entries {
+        if entry.stage > 0 || entry.mode >> 12 != 0o10 || entry.ignores_worktree() {
+            continue;
+        }
+        if attributes.get(&entry.path, "filter")? != Some(AttributeValue::Value("lfs".into())) {
+            continue;
+        }
+        let full_path = repo.worktree_path(&entry.path)?;
+        // Only small files are read, as bigger ones can't be pointers.
+        match fs::symlink_metadata(&full_path) {
+            Ok(metadata) if metadata.is_file() && metadata.len() <= lfs::MAX_POINTER_SIZE => {}
+            _ => continue,
+        }
+        if lfs::Pointer::parse(&fs::read(&full_path)?).is_some() {
+            pointers.push(entry.path.clone());
+        }
+    }
+    Ok(pointers)
+}
+
+/// Turns the pairs of a deleted and an added path with the same content into
+/// renames.
+fn detect_renames(paths: &mut BTreeMap<String, PathStatus>) {
+    let mut deleted: Vec<(String, (u32, String))> = paths
+        .values()
+        .filter(|status| status.staged == Some(Change::Deleted) && status.unstaged.is_none())
+        .filter_map(|status| Some((status.path.clone(), status.head.clone()?)))
+        .collect();
+    let mut renamed_from = HashSet::new();
+    for status in paths.values_mut() {
+        if status.staged != Some(Change::Added) {
+            continue;
+        }
+        let Some(index) = &status.index else {
+            continue;
+        };
+        if let Some(i) = deleted.iter().position(|(_, head)| head == index) {
+            let (path, head) = deleted.remove(i);
+            status.staged = Some(Change::Renamed);
+            status.head = Some(head);
+            renamed_from.insert(path.clone());
+            status.head_path = Some(path);
+        }
+    }
+    paths.retain(|path, _| !renamed_from.contains(path));
+}
+
+/// Collects the mode and hash of the files in a tree, recursively.
+pub fn tree_files(
+    repo: &Repo,
+    hash: &str,
+    prefix: &str,
+    files: &mut BTreeMap<String, (u32, String)>,
+) -> Result<()> {
+    let Object::Tree(tree) = Object::from_hash(repo, hash)? else {
+        return Err(anyhow!("Not a tree: {hash}"));
+    };
+    for file in tree.files {
+        let path = format!("{prefix}{}", file.name);
+        if file.object_type() == object::ObjectType::Tree {
+            tree_files(repo, &file.hash, &format!("{path}/"), files)?;
+        } else {
+            let mode = u32::from_str_radix(&file.mode, 8)?;
+            files.insert(path, (mode, file.hash));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the untracked cache to use, like git: with `core.untrackedCache`
+/// a cache is made if the index has none, when it's false the cache of the
+/// index is dropped, and by default it's only used if the index has one. A
+/// cache that can't be used here is replaced by an empty one.
+fn load_untracked_cache(repo: &Repo, index: &Index) -> Result<Option<UntrackedCache>> {
+    let config = Config::load(repo)?;
+    let enabled = match config.get("core.untrackedCache") {
+        Some(value) if value.eq_ignore_ascii_case("keep") => None,
+        _ => config.get_bool("core.untrackedCache")?,
+    };
+    if enabled == Some(false) || (enabled.is_none() && index.untracked_cache.is_none()) {
+        return Ok(None);
+    }
+    let info_exclude = ExcludeFile::load(Some(&repo.common_dir().join("info/exclude")))?;
+    let excludes_file = ExcludeFile::load(ignore::excludes_file(&config).as_deref())?;
+    Ok(Some(match &index.untracked_cache {
+        Some(cache) if cache.is_usable(&repo.root, &info_exclude, &excludes_file) => cache.clone(),
+        _ => UntrackedCache {
+            info_exclude,
+            excludes_file,
+            dir_flags: STATUS_DIR_FLAGS,
+            ..UntrackedCache::new(&repo.root)
+        },
+    }))
+}
+
+/// Lists the untracked files that aren't ignored, reading only the
+/// directories that changed since they were cached, if there is a cache.
+struct UntrackedWalk<'a> {
+    tracked: &'a HashSet<String>,
+    tracked_dirs: &'a HashSet<String>,
+    /// Whether the tracked paths are lowercase, and files are looked up in
+    /// lowercase.
+    ignore_case: bool,
+    ignore: &'a mut Ignore,
+    cache: Option<&'a mut UntrackedCache>,
+    /// The untracked files found so far.
+    untracked: Vec<String>,
+}
+
+impl UntrackedWalk<'_> {
+    /// Collects the untracked files of a directory. Directories without
+    /// tracked files are listed with a trailing `/` instead of their files,
+    /// if they have any.
+    ///
+    /// The cached files of the directory are used if neither it, nor the
+    /// directories checked for files below it, nor the `.gitignore` files
+    /// of its parents, which `rules_unchanged` tells, and its own changed.
+    fn collect(&mut self, dir: &Path, prefix: &str, rules_unchanged: bool) -> Result<()> {
+        let Some(cache) = self.cache.as_deref() else {
+            return self.scan(dir, prefix, None);
+        };
+        let path = prefix.trim_end_matches('/');
+        // The directory is stat'ed before being read, so that changes made
+        // while it's read are seen next time.
+        let stat = StatData::from_metadata(&fs::metadata(dir)?);
+        let exclude_hash = untracked_cache::exclude_hash(dir)?;
+        let cached = cache
+            .dir(path)
+            .filter(|cached| rules_unchanged && cached.exclude_hash == exclude_hash);
+        let rules_unchanged = cached.is_some();
+        let valid = match cached {
+            Some(cached) if cached.stat == Some(stat) && !cached.check_only => {
+                check_only_unchanged(cached, dir)?
+            }
+            _ => false,
+        };
+        if !valid {
+            return self.scan(dir, prefix, Some((stat, exclude_hash, rules_unchanged)));
+        }
+        let cached = cached.expect("valid entries are cached");
+        // (name, whether it's a tracked directory), in the order of a scan.
+        let mut names: Vec<(String, bool)> = cached
+            .untracked
+            .iter()
+            .map(|name| (name.clone(), false))
+            .chain(
+                cached
+                    .subdirs
+                    .iter()
+                    .filter(|subdir| !subdir.check_only)
+                    .map(|subdir| (subdir.name.clone(), true)),
+            )
+            .collect();
+        names.sort_by(|(a, _), (b, _)| a.trim_end_matches('/').cmp(b.trim_end_matches('/')));
+        for (name, tracked_dir) in names {
+            match tracked_dir {
+                true => self.collect(&dir.join(&name), &format!("{prefix}{name}/"), true)?,
+                false => self.untracked.push(format!("{prefix}{name}")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a directory for [`UntrackedWalk::collect`], and caches its
+    /// untracked files with its stat data and `.gitignore` hash if given.
+    fn scan(
+        &mut self,
+        dir: &Path,
+        prefix: &str,
+        cache_entry: Option<(StatData, Option<String>, bool)>,
+    ) -> Result<()> {
+        let rules_unchanged = cache_entry
+            .as_ref()
+            .is_some_and(|(_, _, unchanged)| *unchanged);
+        let mut names = vec![];
+        let mut subdirs = HashSet::new();
+        let mut entries: Vec<fs::DirEntry> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let path = format!("{prefix}{name}");
+            let key = match self.ignore_case {
+                true => path.to_ascii_lowercase(),
+                false => path.clone(),
+            };
+            if path == ".git" || self.tracked.contains(&key) {
+                continue;
+            }
+            let is_dir = entry.file_type()?.is_dir();
+            if self.tracked_dirs.contains(&key) {
+                subdirs.insert(name);
+                self.collect(&entry.path(), &format!("{path}/"), rules_unchanged)?;
+            } else if self.ignore.is_ignored(&path, is_dir)? {
+                continue;
+            } else if !is_dir {
+                self.untracked.push(path);
+                names.push(name);
+            } else {
+                if self.cache.is_some() {
+                    subdirs.insert(name.clone());
+                }
+                if self.has_files(&entry.path(), &format!("{path}/"))? {
+                    self.untracked.push(format!("{path}/"));
+                    names.push(format!("{name}/"));
+                }
+            }
+        }
+        if let (Some(cache), Some((stat, exclude_hash, _))) =
+            (self.cache.as_deref_mut(), cache_entry)
+        {
+            let path = prefix.trim_end_matches('/');
+            cache.update(path, stat, exclude_hash, names);
+            cache.prune(path, &subdirs);
+        }
+        Ok(())
+    }
+
+    /// Returns whether a directory has files that aren't ignored, like
+    /// [`has_files`], caching the directories read as check-only.
+    fn has_files(&mut self, dir: &Path, prefix: &str) -> Result<bool> {
+        if self.cache.is_none() {
+            return has_files(dir, prefix, self.ignore);
+        }
+        let stat = StatData::from_metadata(&fs::metadata(dir)?);
+        let exclude_hash = untracked_cache::exclude_hash(dir)?;
+        let mut found = None;
+        let mut subdirs = HashSet::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let path = format!("{prefix}{name}");
+            let is_dir = entry.file_type()?.is_dir();
+            if self.ignore.is_ignored(&path, is_dir)? {
+                continue;
+            }
+            if !is_dir {
+                found = Some(name);
+                break;
+            }
+            subdirs.insert(name.clone());
+            if self.has_files(&entry.path(), &format!("{path}/"))? {
+                found = Some(format!("{name}/"));
+                break;
+            }
+        }
+        let has_files = found.is_some();
+        let cache = self.cache.as_deref_mut().expect("checked above");
+        let path = prefix.trim_end_matches('/');
+        cache.update_check_only(path, stat, exclude_hash, found);
+        cache.prune(path, &subdirs);
+        Ok(has_files)
+    }
+}
+
+/// Returns whether the directories checked for files below a cached
+/// directory, and their `.gitignore` files, are unchanged.
+fn check_only_unchanged(cached: &UntrackedDir, dir: &Path) -> Result<bool> {
+    for subdir in cached.subdirs.iter().filter(|subdir| subdir.check_only) {
+        let full_path = dir.join(&subdir.name);
+        let stat = match fs::symlink_metadata(&full_path) {
+            Ok(metadata) if metadata.is_dir() => StatData::from_metadata(&metadata),
+            _ => return Ok(false),
+        };
+        if subdir.stat != Some(stat)
+            || subdir.exclude_hash != untracked_cache::exclude_hash(&full_path)?
+            || !check_only_unchanged(subdir, &full_path)?
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Returns whether a directory has files that aren't ignored, in it or in its
+/// subdirectories.
+pub(crate) fn has_files(dir: &Path, prefix: &str, ignore: &mut Ignore) -> Result<bool> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let path = format!("{prefix}{name}");
+        let is_dir = entry.file_type()?.is_dir();
+        if ignore.is_ignored(&path, is_dir)? {
+            continue;
+        }
+        if !is_dir || has_files(&entry.path(), &format!("{path}/"), ignore)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// An operation that stopped, usually because of conflicts, and waits for the
+/// user to continue or abort it.
+#[derive(Debug, PartialEq)]
+pub enum Operation {
+    /// An operation interrupted by a crash, see [`crate::journal::Journal`].
+    Interrupted {
+        operation: String,
+    },
+    Merge,
+    /// `git am`, which stopped on a patch.
+    Am {
+        empty_patch: bool,
+    },
+    Rebase {
+        interactive: bool,
+        /// The short name of the branch being rebased, unless it's detached.
+        branch: Option<String>,
+        /// The abbreviated hash of the commit it is rebased on.
+        onto: Option<String>,
+        /// Whether an interactive rebase stopped to edit a commit, rather than
+        /// because of conflicts.
+        editing: bool,
+        /// The commands of an interactive rebase that are done, and the ones
+        /// left to do, with abbreviated hashes.
+        done: Vec<String>,
+        todo: Vec<String>,
+    },
+    CherryPick {
+        commit: Option<String>,
+    },
+    Revert {
+        commit: Option<String>,
+    },
+    Bisect {
+        branch: Option<String>,
+    },
+}
+
+/// Returns the operations in progress, by reading their state files in the git
+/// directory.
+///
+/// At most one of merge, am, rebase, cherry-pick and revert is returned, since
+/// each of them prevents the others, but a bisection can be in progress at the
+/// same time and comes last. An interrupted operation comes first.
+pub fn operations_in_progress(repo: &Repo) -> Result<Vec<Operation>> {
+    let git_dir = repo.git_dir();
+    let read = |name: &str| -> Result<Option<String>> {
+        match fs::read_to_string(git_dir.join(name)) {
+            Ok(content) => Ok(Some(content.trim().to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    };
+    let abbreviator = object::Abbreviator::from_config(repo)?;
+    let abbreviate = |hash: String| abbreviator.abbreviate(&hash).unwrap_or(hash);
+    let short_branch = |name: String| match name.strip_prefix("refs/heads/") {
+        Some(branch) => branch.to_string(),
+        None => name,
+    };
+
+    let mut operations = vec![];
+    if let Some(journal) = Journal::read(repo)? {
+        operations.push(Operation::Interrupted {
+            operation: journal.operation,
+        });
+    }
+    let rebase_dir = ["rebase-merge", "rebase-apply"]
+        .into_iter()
+        .find(|dir| git_dir.join(dir).is_dir());
+    if git_dir.join("MERGE_HEAD").exists() {
+        operations.push(Operation::Merge);
+    } else if git_dir.join("rebase-apply/applying").exists() {
+        let patch = git_dir.join("rebase-apply/patch");
+        let empty_patch = fs::metadata(patch).is_ok_and(|m| m.len() == 0);
+        operations.push(Operation::Am { empty_patch });
+    } else if let Some(dir) = rebase_dir {
+        let interactive = git_dir.join(dir).join("interactive").exists();
+        let branch = read(&format!("{dir}/head-name"))?
+            .filter(|name| name != "detached HEAD")
+            .map(short_branch);
+        let onto = read(&format!("{dir}/onto"))?.map(abbreviate);
+        let editing = interactive && !git_dir.join("MERGE_MSG").exists();
+        let commands = |name: &str| -> Result<Vec<String>> {
+            let content = read(&format!("{dir}/{name}"))?.unwrap_or_default();
+            Ok(content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| abbreviate_command(repo, &abbreviator, line))
+                .collect())
+        };
+        let (done, todo) = if interactive {
+            (commands("done")?, commands("git-rebase-todo")?)
+        } else {
+            (vec![], vec![])
+        };
+        operations.push(Operation::Rebase {
+            interactive,
+            branch,
+            onto,
+            editing,
+            done,
+            todo,
+        });
+    } else if git_dir.join("CHERRY_PICK_HEAD").exists() {
+        let commit = read("CHERRY_PICK_HEAD")?.map(abbreviate);
+        operations.push(Operation::CherryPick { commit });
+    } else if git_dir.join("REVERT_HEAD").exists() {
+        let commit = read("REVERT_HEAD")?.map(abbreviate);
+        operations.push(Operation::Revert { commit });
+    }
+    if git_dir.join("BISECT_LOG").exists() {
+        let branch = read("BISECT_START")?
+            .filter(|start| !start.is_empty())
+            .map(short_branch);
+        operations.push(Operation::Bisect { branch });
+    }
+    Ok(operations)
+}
+
+/// Prints what an operation in progress is and how to continue or abort it,
+/// like the long format of `git status`, followed by an empty line.
+///
+/// `has_unmerged` tells whether the index still has conflicts.
+pub fn print_operation(
+    operation: &Operation,
+    has_unmerged: bool,
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    let mut lines: Vec<String> = vec![];
+    let mut hint = |line: &str| lines.push(format!("  ({line})"));
+    let mut text = vec![];
+    match operation {
+        Operation::Interrupted { operation } => {
+            text.push(format!("The operation '{operation}' was interrupted."));
+            hint("use \"good_git recover --continue\" to finish it");
+            hint("use \"good_git recover --abort\" to undo it");
+        }
+        Operation::Merge if has_unmerged => {
+            text.push("You have unmerged paths.".to_string());
+            hint("fix conflicts and run \"git commit\"");
+            hint("use \"git merge --abort\" to abort the merge");
+        }
+        Operation::Merge => {
+            text.push("All conflicts fixed but you are still merging.".to_string());
+            hint("use \"git commit\" to conclude merge");
+        }
+        Operation::Am { empty_patch } => {
+            text.push("You are in the middle of an am session.".to_string());
+            if *empty_patch {
+                text.push("The current patch is empty.".to_string());
+            } else {
+                hint("fix conflicts and then run \"git am --continue\"");
+            }
+            hint("use \"git am --skip\" to skip this patch");
+            hint("use \"git am --abort\" to restore the original branch");
+        }
+        Operation::Rebase {
+            interactive,
+            branch,
+            onto,
+            editing,
+            done,
+            todo,
+        } => {
+            if *interactive {
+                print_rebase_commands(done, todo, stdout)?;
+            }
+            let doing = if *editing && !has_unmerged {
+                "editing a commit while rebasing"
+            } else {
+                "rebasing"
+            };
+            text.push(match (branch, onto) {
+                (Some(branch), Some(onto)) => {
+                    format!("You are currently {doing} branch '{branch}' on '{onto}'.")
+                }
+                _ => format!("You are currently {doing}."),
+            });
+            if has_unmerged {
+                hint("fix conflicts and then run \"git rebase --continue\"");
+                hint("use \"git rebase --skip\" to skip this patch");
+                hint("use \"git rebase --abort\" to check out the original branch");
+            } else if *editing {
+                hint("use \"git commit --amend\" to amend the current commit");
+                hint("use \"git rebase --continue\" once you are satisfied with your changes");
+            } else {
+                hint("all conflicts fixed: run \"git rebase --continue\"");
+            }
+        }
+        Operation::CherryPick { commit } | Operation::Revert { commit } => {
+            let (name, doing, noun) = match operation {
+                Operation::CherryPick { .. } => ("cherry-pick", "cherry-picking", "Cherry-pick"),
+                _ => ("revert", "reverting", "Revert"),
+            };
+            text.push(match commit {
+                Some(commit) => format!("You are currently {doing} commit {commit}."),
+                None => format!("{noun} currently in progress."),
+            });
+            if has_unmerged {
+                hint(&format!("fix conflicts and run \"git {name} --continue\""));
+            } else if commit.is_none() {
+                hint(&format!("run \"git {name} --continue\" to continue"));
+            } else {
+                hint(&format!(
+                    "all conflicts fixed: run \"git {name} --continue\""
+                ));
+            }
+            hint(&format!("use \"git {name} --skip\" to skip this patch"));
+            hint(&format!(
+                "use \"git {name} --abort\" to cancel the {name} operation"
+            ));
+        }
+        Operation::Bisect { branch } => {
+            text.push(match branch {
+                Some(branch) => {
+                    format!("You are currently bisecting, started from branch '{branch}'.")
+                }
+                None => "You are currently bisecting.".to_string(),
+            });
+            hint("use \"git bisect reset\" to get back to the original branch");
+        }
+    }
+    for line in text.iter().chain(&lines) {
+        writeln!(stdout, "{line}")?;
+    }
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Like git, only the last and next 2 commands of a rebase are shown.
+const REBASE_COMMANDS_SHOWN: usize = 2;
+
+fn print_rebase_commands(
+    done: &[String],
+    todo: &[String],
+    stdout: &mut dyn io::Write,
+) -> Result<()> {
+    match done.len() {
+        0 => writeln!(stdout, "No commands done.")?,
+        1 => writeln!(stdout, "Last command done (1 command done):")?,
+        n => writeln!(stdout, "Last commands done ({n} commands done):")?,
+    }
+    for command in &done[done.len().saturating_sub(REBASE_COMMANDS_SHOWN)..] {
+        writeln!(stdout, "   {command}")?;
+    }
+    if done.len() > REBASE_COMMANDS_SHOWN {
+        writeln!(stdout, "  (see more in file .git/rebase-merge/done)")?;
+    }
+    match todo.len() {
+        0 => writeln!(stdout, "No commands remaining.")?,
+        1 => writeln!(stdout, "Next command to do (1 remaining command):")?,
+        n => writeln!(stdout, "Next commands to do ({n} remaining commands):")?,
+    }
+    for command in todo.iter().take(REBASE_COMMANDS_SHOWN) {
+        writeln!(stdout, "   {command}")?;
+    }
+    if !todo.is_empty() {
+        writeln!(
+            stdout,
+            "  (use \"git rebase --edit-todo\" to view and edit)"
+        )?;
+    }
+    Ok(())
+}
+
+/// Abbreviates the hash of a rebase command like `pick <hash> <subject>`.
+fn abbreviate_command(repo: &Repo, abbreviator: &object::Abbreviator, line: &str) -> String {
+    let mut parts = line.splitn(3, ' ');
+    let (Some(command), Some(hash)) = (parts.next(), parts.next()) else {
+        return line.to_string();
+    };
+    if ["exec", "x", "label", "l"].contains(&command) {
+        return line.to_string();
+    }
+    let Ok(hash) = Object::resolve_rev(repo, hash) else {
+        return line.to_string();
+    };
+    let hash = abbreviator.abbreviate(&hash).unwrap_or(hash);
+    match parts.next() {
+        Some(rest) => format!("{command} {hash} {rest}"),
+        None => format!("{command} {hash}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operations_in_progress() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::new(tmp.path());
+        let git_dir = repo.git_dir();
+        fs::create_dir_all(git_dir.join("rebase-merge")).unwrap();
+        assert_eq!(
+            operations_in_progress(&repo).unwrap(),
+            [Operation::Rebase {
+                interactive: false,
+                branch: None,
+                onto: None,
+                editing: false,
+                done: vec![],
+                todo: vec![],
+            }]
+        );
+
+        fs::write(git_dir.join("rebase-merge/interactive"), "").unwrap();
+        fs::write(git_dir.join("rebase-merge/head-name"), "refs/heads/topic\n").unwrap();
+        fs::write(git_dir.join("BISECT_LOG"), "").unwrap();
+        fs::write(git_dir.join("BISECT_START"), "main\n").unwrap();
+        assert_eq!(
+            operations_in_progress(&repo).unwrap(),
+            [
+                Operation::Rebase {
+                    interactive: true,
+                    branch: Some("topic".to_string()),
+                    onto: None,
+                    editing: true,
+                    done: vec![],
+                    todo: vec![],
+                },
+                Operation::Bisect {
+                    branch: Some("main".to_string())
+                }
+            ]
+        );
+
+        // A merge takes precedence.
+        fs::write(git_dir.join("MERGE_HEAD"), "").unwrap();
+        assert_eq!(operations_in_progress(&repo).unwrap()[0], Operation::Merge);
+
+        fs::write(
+            git_dir.join("JOURNAL"),
+            "operation checkout main\nnew-tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n",
+        )
+        .unwrap();
+        assert_eq!(
+            operations_in_progress(&repo).unwrap()[0],
+            Operation::Interrupted {
+                operation: "checkout main".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_print_operation() {
+        let print = |operation: &Operation, has_unmerged: bool| {
+            let mut stdout = Vec::new();
+            print_operation(operation, has_unmerged, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        assert_eq!(
+            print(&Operation::Merge, true),
+            "You have unmerged paths.\n  \
+             (fix conflicts and run \"git commit\")\n  \
+             (use \"git merge --abort\" to abort the merge)\n\n"
+        );
+        let rebase = Operation::Rebase {
+            interactive: false,
+            branch: Some("topic".to_string()),
+            onto: Some("1234567".to_string()),
+            editing: false,
+            done: vec![],
+            todo: vec![],
+        };
+        assert_eq!(
+            print(&rebase, false),
+            "You are currently rebasing branch 'topic' on '1234567'.\n  \
+             (all conflicts fixed: run \"git rebase --continue\")\n\n"
+        );
+        let cherry_pick = Operation::CherryPick {
+            commit: Some("abcdef0".to_string()),
+        };
+        assert_eq!(
+            print(&cherry_pick, true),
+            "You are currently cherry-picking commit abcdef0.\n  \
+             (fix conflicts and run \"git cherry-pick --continue\")\n  \
+             (use \"git cherry-pick --skip\" to skip this patch)\n  \
+             (use \"git cherry-pick --abort\" to cancel the cherry-pick operation)\n\n"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    fs,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{
+    attributes::{AttributeValue, Attributes},
+    config::Config,
+    object::{self, Commit, File, Object, ObjectType, Tree},
+    refs,
+    repo::Repo,
+};
+
+/// Converts content to text for humans with the `diff.<driver>.textconv`
+/// command of the `diff=<driver>` attribute of its path, e.g. to show the
+/// text of PDF files in diff, grep and cat-file.
+///
+/// With `diff.<driver>.cachetextconv`, the output for each blob is cached
+/// as notes in `refs/notes/textconv/<driver>`, like git. The notes commit
+/// message is the command, so changing the command invalidates the cache.
+pub struct Textconv<'a> {
+    repo: &'a Repo,
+    config: Config,
+    attributes: Attributes,
+    /// The caches read so far, by driver.
+    caches: Mutex<HashMap<String, Cache>>,
+}
+
+/// The notes of a driver, keyed by the hash of the converted blob.
+#[derive(Default)]
+struct Cache {
+    notes: HashMap<String, String>,
+    dirty: bool,
+}
+
+impl<'a> Textconv<'a> {
+    pub fn new(repo: &'a Repo) -> Result<Textconv<'a>> {
+        Ok(Textconv {
+            repo,
+            config: Config::load(repo)?,
+            attributes: Attributes::load(repo)?,
+            caches: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the driver and the command converting the content of a path,
+    /// if any.
+    pub fn driver(&self, path: &str) -> Result<Option<(String, &str)>> {
+        let Some(AttributeValue::Value(driver)) = self.attributes.get(path, "diff")? else {
+            return Ok(None);
+        };
+        let command = self.config.get(&format!("diff.{driver}.textconv"));
+        Ok(command.map(|command| (driver, command)))
+    }
+
+    /// Converts the content of a path, or returns `None` if the path has no
+    /// textconv driver.
+    ///
+    /// `hash` is the blob of the content, if it's in the repository, which
+    /// makes the output cacheable. Worktree files are always converted.
+    pub fn convert(
+        &self,
+        path: &str,
+        hash: Option<&str>,
+        content: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let Some((driver, command)) = self.driver(path)? else {
+            return Ok(None);
+        };
+        let driver = driver.as_str();
+        let cached = hash.is_some()
+            && self
+                .config
+                .get_bool(&format!("diff.{driver}.cachetextconv"))?
+                == Some(true);
+        if !cached {
+            return run(command, path, content).map(Some);
+        }
+        let hash = hash.expect("cached conversions have a hash");
+
+        let note = {
+            let mut caches = self.caches.lock().unwrap();
+            if !caches.contains_key(driver) {
+                let cache = read_cache(self.repo, driver, command)?;
+                caches.insert(driver.to_string(), cache);
+            }
+            caches[driver].notes.get(hash).cloned()
+        };
+        if let Some(note) = note {
+            if let Object::Blob(blob) = Object::from_hash(self.repo, &note)? {
+                return Ok(Some(blob.content));
+            }
+        }
+
+        let output = run(command, path, content)?;
+        let note = object::write_checked_object(self.repo, ObjectType::Blob, &output)?;
+        let mut caches = self.caches.lock().unwrap();
+        let cache = caches.get_mut(driver).expect("the cache was read above");
+        cache.notes.insert(hash.to_string(), note);
+        cache.dirty = true;
+        Ok(Some(output))
+    }
+
+    /// Writes the caches that have new conversions.
+    pub fn save(&self) -> Result<()> {
+        let caches = self.caches.lock().unwrap();
+        for (driver, cache) in caches.iter().filter(|(_, cache)| cache.dirty) {
+            let command = self
+                .config
+                .get(&format!("diff.{driver}.textconv"))
+                .unwrap_or_default();
+            write_cache(self.repo, &self.config, driver, command, cache)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs a textconv command like git does, through the shell with the path of
+/// a temporary file holding the content as argument.
+fn run(command: &str, path: &str, content: &[u8]) -> Result<Vec<u8>> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    // Like git, keep the file name, as some converters look at the extension.
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let temp_path = std::env::temp_dir().join(format!(
+        "good_git_{}_{}_{name}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&temp_path, content)?;
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{command} \"$@\""))
+        .arg(command)
+        .arg(&temp_path)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output();
+    fs::remove_file(&temp_path)?;
+
+    let output = output?;
+    if !output.status.success() {
+        return Err(anyhow!("Unable to read files to diff: {command} failed"));
+    }
+    Ok(output.stdout)
+}
+
+fn notes_ref(driver: &str) -> String {
+    format!("refs/notes/textconv/{driver}")
+}
+
+/// Reads the notes of a driver, which are discarded if they were made by
+/// another command.
+fn read_cache(repo: &Repo, driver: &str, command: &str) -> Result<Cache> {
+    let Ok(hash) = refs::find_ref(repo, &notes_ref(driver)) else {
+        return Ok(Cache::default());
+    };
+    let Object::Commit(commit) = Object::from_hash(repo, &hash)? else {
+        return Err(anyhow!("{} is not a commit", notes_ref(driver)));
+    };
+    let mut cache = Cache::default();
+    if commit.message == command {
+        collect_notes(repo, &commit.tree, "", &mut cache.notes)?;
+    }
+    Ok(cache)
+}
+
+/// Collects the notes of a notes tree. Git may fan notes out into
+/// directories named after the first characters of the hash, like `ab/cdef…`.
+fn collect_notes(
+    repo: &Repo,
+    tree: &str,
+    prefix: &str,
+    notes: &mut HashMap<String, String>,
+) -> Result<()> {
+    let Object::Tree(tree) = Object::from_hash(repo, tree)? else {
+        return Err(anyhow!("Not a tree: {tree}"));
+    };
+    for file in tree.files {
+        let name = format!("{prefix}{}", file.name);
+        match file.object_type() {
+            ObjectType::Tree => collect_notes(repo, &file.hash, &name, notes)?,
+            ObjectType::Blob => {
+                notes.insert(name, file.hash);
+            }
+            ObjectType::Commit | ObjectType::Tag => {}
+        }
+    }
+    Ok(())
+}
+
+/// Writes the notes of a driver as a flat tree, in a commit without parents
+/// since older caches are never needed.
+fn write_cache(
+    repo: &Repo,
+    config: &Config,
+    driver: &str,
+    command: &str,
+    cache: &Cache,
+) -> Result<()> {
+    let files = cache
+        .notes
+        .iter()
+        .map(|(hash, note)| File {
+            mode: "100644".to_string(),
+            name: hash.clone(),
+            hash: note.clone(),
+        })
+        .collect();
+    let tree = object::write_checked_object(repo, ObjectType::Tree, &Tree::new(files).to_bytes())?;
+
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+    let ident = format!(
+        "{} <{}> {} +0000",
+        config.get("user.name").unwrap_or("good_git"),
+        config.get("user.email").unwrap_or("good_git@localhost"),
+        now.as_secs()
+    );
+    let commit = Commit {
+        tree,
+        author: ident.clone(),
+        committer: ident,
+        message: command.to_string(),
+        ..Default::default()
+    };
+    let hash = object::write_checked_object(repo, ObjectType::Commit, &commit.to_bytes())?;
+    refs::update_ref(repo, &notes_ref(driver), &hash)
+}
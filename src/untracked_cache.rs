@@ -0,0 +1,645 @@
+use anyhow::{anyhow, Context, Result};
+use std::{collections::HashSet, fs, io, io::prelude::*, path::Path};
+
+use crate::{
+    ewah,
+    index::{self, read_varint, write_varint},
+    object::Blob,
+};
+
+/// The flags of the directory walk of `status`, git's
+/// `DIR_SHOW_OTHER_DIRECTORIES | DIR_HIDE_EMPTY_DIRECTORIES`: directories
+/// without tracked files are listed instead of their files, unless they
+/// have none.
+pub const STATUS_DIR_FLAGS: u32 = 0b110;
+
+/// The untracked cache, stored in the `UNTR` index extension.
+///
+/// It remembers the untracked files of each directory along with the stat
+/// data of the directory, so listing untracked files can skip directories
+/// that didn't change. See `core.untrackedCache`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UntrackedCache {
+    /// NUL terminated strings describing the environments the cache is valid
+    /// in, see [`UntrackedCache::ident`].
+    pub idents: Vec<u8>,
+    pub info_exclude: ExcludeFile,
+    pub excludes_file: ExcludeFile,
+    /// Flags of the directory walk the cache was built with.
+    pub dir_flags: u32,
+    /// The name of per-directory exclude files, normally `.gitignore`.
+    pub exclude_per_dir: String,
+    pub root: Option<UntrackedDir>,
+}
+
+/// The stat data and hash of a global exclude file. A null hash means that
+/// the file doesn't exist.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExcludeFile {
+    pub stat: StatData,
+    pub hash: String,
+}
+
+impl ExcludeFile {
+    /// Returns the stat data and hash of an exclude file, which may not
+    /// exist.
+    pub fn load(path: Option<&Path>) -> Result<ExcludeFile> {
+        let null = ExcludeFile {
+            hash: "0".repeat(40),
+            ..Default::default()
+        };
+        let Some(path) = path else {
+            return Ok(null);
+        };
+        match fs::read(path) {
+            Ok(content) => Ok(ExcludeFile {
+                stat: StatData::from_metadata(&fs::metadata(path)?),
+                hash: content_hash(content),
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(null),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Returns the hash of the `.gitignore` file of a directory, if any.
+pub fn exclude_hash(dir: &Path) -> Result<Option<String>> {
+    match fs::read(dir.join(".gitignore")) {
+        Ok(content) => Ok(Some(content_hash(content))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns the hash git records for an exclude file: the blob hash of its
+/// content with a newline appended, since that's how git reads it, unless
+/// it's empty.
+fn content_hash(mut content: Vec<u8>) -> String {
+    if !content.is_empty() {
+        content.push(b'\n');
+    }
+    Blob::new(content).hash()
+}
+
+/// A directory in the untracked cache.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UntrackedDir {
+    /// The name of the directory, empty for the root.
+    pub name: String,
+    /// The untracked files and directories, directories ending with a `/`.
+    pub untracked: Vec<String>,
+    pub subdirs: Vec<UntrackedDir>,
+    /// The stat data of the directory when it was scanned, `None` if the
+    /// entry was invalidated.
+    pub stat: Option<StatData>,
+    /// Whether the directory was only scanned to check if it's empty.
+    pub check_only: bool,
+    /// The hash of the per-directory exclude file, if it was valid.
+    pub exclude_hash: Option<String>,
+}
+
+/// Stat data as stored on disk by git, without the file mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StatData {
+    pub ctime_seconds: u32,
+    pub ctime_nanoseconds: u32,
+    pub mtime_seconds: u32,
+    pub mtime_nanoseconds: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Size, truncated to 32 bits.
+    pub size: u32,
+}
+
+impl StatData {
+    /// Returns the stat data of a file. Like git, values are truncated to
+    /// 32 bits.
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> StatData {
+        use std::os::unix::fs::MetadataExt;
+        StatData {
+            ctime_seconds: metadata.ctime() as u32,
+            ctime_nanoseconds: metadata.ctime_nsec() as u32,
+            mtime_seconds: metadata.mtime() as u32,
+            mtime_nanoseconds: metadata.mtime_nsec() as u32,
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+        }
+    }
+
+    /// Returns the stat data of a file. Only the modification time and size
+    /// are available on this platform.
+    #[cfg(not(unix))]
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> StatData {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        StatData {
+            mtime_seconds: mtime.as_secs() as u32,
+            mtime_nanoseconds: mtime.subsec_nanos(),
+            size: metadata.len() as u32,
+            ..Default::default()
+        }
+    }
+
+    fn from_reader(reader: &mut &[u8]) -> Result<StatData> {
+        let mut values = [0_u32; 9];
+        for value in values.iter_mut() {
+            let mut buf = [0_u8; 4];
+            reader
+                .read_exact(&mut buf)
+                .context("Untracked cache is truncated")?;
+            *value = u32::from_be_bytes(buf);
+        }
+        let [ctime_seconds, ctime_nanoseconds, mtime_seconds, mtime_nanoseconds, dev, ino, uid, gid, size] =
+            values;
+        Ok(StatData {
+            ctime_seconds,
+            ctime_nanoseconds,
+            mtime_seconds,
+            mtime_nanoseconds,
+            dev,
+            ino,
+            uid,
+            gid,
+            size,
+        })
+    }
+
+    fn write_to(&self, s: &mut Vec<u8>) {
+        for value in [
+            self.ctime_seconds,
+            self.ctime_nanoseconds,
+            self.mtime_seconds,
+            self.mtime_nanoseconds,
+            self.dev,
+            self.ino,
+            self.uid,
+            self.gid,
+            self.size,
+        ] {
+            s.extend(value.to_be_bytes());
+        }
+    }
+}
+
+impl UntrackedCache {
+    /// Returns an empty cache for a working tree.
+    pub fn new(worktree: &Path) -> UntrackedCache {
+        UntrackedCache {
+            idents: UntrackedCache::ident(worktree),
+            info_exclude: ExcludeFile {
+                hash: "0".repeat(40),
+                ..Default::default()
+            },
+            excludes_file: ExcludeFile {
+                hash: "0".repeat(40),
+                ..Default::default()
+            },
+            dir_flags: 0,
+            exclude_per_dir: ".gitignore".to_string(),
+            root: None,
+        }
+    }
+
+    /// Returns the environment string git uses to make sure the cache isn't
+    /// used on another machine or after the working tree was moved.
+    pub fn ident(worktree: &Path) -> Vec<u8> {
+        let system = if cfg!(target_os = "linux") {
+            "Linux"
+        } else if cfg!(target_os = "macos") {
+            "Darwin"
+        } else if cfg!(windows) {
+            "Windows"
+        } else {
+            std::env::consts::OS
+        };
+        format!("Location {}, system {system}\0", worktree.display()).into_bytes()
+    }
+
+    /// Returns whether the cache was created for this working tree.
+    pub fn is_valid_for(&self, worktree: &Path) -> bool {
+        let ident = UntrackedCache::ident(worktree);
+        self.idents
+            .split_inclusive(|&b| b == 0)
+            .any(|existing| existing == ident)
+    }
+
+    /// Returns whether `status` can use the cache in a working tree with
+    /// these global exclude files: it was made there by the same directory
+    /// walk, with the same ignore files.
+    pub fn is_usable(
+        &self,
+        worktree: &Path,
+        info_exclude: &ExcludeFile,
+        excludes_file: &ExcludeFile,
+    ) -> bool {
+        self.is_valid_for(worktree)
+            && self.dir_flags == STATUS_DIR_FLAGS
+            && self.exclude_per_dir == ".gitignore"
+            && self.info_exclude.hash == info_exclude.hash
+            && self.excludes_file.hash == excludes_file.hash
+    }
+
+    /// Returns the cached entry for a directory, given as a path relative to
+    /// the root of the working tree without a trailing `/`.
+    pub fn dir(&self, path: &str) -> Option<&UntrackedDir> {
+        let mut dir = self.root.as_ref()?;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            dir = dir.subdirs.iter().find(|subdir| subdir.name == name)?;
+        }
+        Some(dir)
+    }
+
+    /// Returns the untracked files of a directory if it didn't change since
+    /// it was cached, according to its stat data.
+    pub fn untracked(&self, path: &str, stat: &StatData) -> Option<&[String]> {
+        let dir = self.dir(path)?;
+        (dir.stat.as_ref() == Some(stat)).then_some(dir.untracked.as_slice())
+    }
+
+    /// Records the untracked files of a directory, creating its entry and
+    /// the entries of its parents as needed.
+    pub fn update(
+        &mut self,
+        path: &str,
+        stat: StatData,
+        exclude_hash: Option<String>,
+        untracked: Vec<String>,
+    ) {
+        let dir = self.dir_mut(path);
+        dir.stat = Some(stat);
+        dir.check_only = false;
+        dir.exclude_hash = exclude_hash;
+        dir.untracked = untracked;
+    }
+
+    /// Records a directory that was only scanned to check if it has
+    /// untracked files, with the file that was found, if any.
+    pub fn update_check_only(
+        &mut self,
+        path: &str,
+        stat: StatData,
+        exclude_hash: Option<String>,
+        found: Option<String>,
+    ) {
+        let dir = self.dir_mut(path);
+        dir.stat = Some(stat);
+        dir.check_only = true;
+        dir.exclude_hash = exclude_hash;
+        dir.untracked = found.into_iter().collect();
+    }
+
+    /// Forgets the subdirectories of a directory that weren't seen when it
+    /// was scanned again, like directories that aren't tracked anymore.
+    pub fn prune(&mut self, path: &str, seen: &HashSet<String>) {
+        self.dir_mut(path)
+            .subdirs
+            .retain(|subdir| seen.contains(&subdir.name));
+    }
+
+    fn dir_mut(&mut self, path: &str) -> &mut UntrackedDir {
+        let mut dir = self.root.get_or_insert_with(UntrackedDir::default);
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let position = match dir.subdirs.iter().position(|subdir| subdir.name == name) {
+                Some(position) => position,
+                None => {
+                    dir.subdirs.push(UntrackedDir {
+                        name: name.to_string(),
+                        ..Default::default()
+                    });
+                    dir.subdirs.len() - 1
+                }
+            };
+            dir = &mut dir.subdirs[position];
+        }
+        dir
+    }
+
+    /// Invalidates the directories containing a path, so they're scanned
+    /// again. Used when a file is added to or removed from the index, which
+    /// doesn't change the stat data of its directory.
+    pub fn invalidate_path(&mut self, path: &str) {
+        let Some(mut dir) = self.root.as_mut() else {
+            return;
+        };
+        let mut names = path.split('/').peekable();
+        while let Some(name) = names.next() {
+            dir.stat = None;
+            if names.peek().is_none() {
+                break;
+            }
+            match dir.subdirs.iter_mut().find(|subdir| subdir.name == name) {
+                Some(subdir) => dir = subdir,
+                None => break,
+            }
+        }
+    }
+
+    /// Parses the data of an `UNTR` extension.
+    ///
+    /// The format is a header with the idents, the stat data and hashes of
+    /// the global exclude files, the directory walk flags and the name of
+    /// per-directory exclude files. Then come the directories in depth-first
+    /// order, each with its untracked files, followed by bitmaps telling
+    /// which directories are valid, check-only and have a valid exclude
+    /// file, the stat data and hashes of those directories, and a NUL. A
+    /// cache without directories ends right after their count, like git
+    /// writes it.
+    pub fn from_bytes(s: &[u8]) -> Result<UntrackedCache> {
+        let mut reader = s;
+        let idents_len = read_varint(&mut reader)?;
+        let idents = reader
+            .get(..idents_len)
+            .ok_or(anyhow!("Untracked cache is truncated"))?
+            .to_vec();
+        reader = &reader[idents_len..];
+        let info_exclude_stat = StatData::from_reader(&mut reader)?;
+        let excludes_file_stat = StatData::from_reader(&mut reader)?;
+        let dir_flags = read_u32(&mut reader)?;
+        let info_exclude = ExcludeFile {
+            stat: info_exclude_stat,
+            hash: read_hash(&mut reader)?,
+        };
+        let excludes_file = ExcludeFile {
+            stat: excludes_file_stat,
+            hash: read_hash(&mut reader)?,
+        };
+        let exclude_per_dir = read_string(&mut reader)?;
+
+        let dir_count = read_varint(&mut reader)?;
+        let root = if dir_count == 0 {
+            if !reader.is_empty() {
+                return Err(anyhow!("Invalid untracked cache terminator"));
+            }
+            None
+        } else {
+            let mut dirs_in_order = 0;
+            let mut root = UntrackedDir::from_reader(&mut reader, &mut dirs_in_order)?;
+            if dirs_in_order != dir_count {
+                return Err(anyhow!("Invalid untracked cache directory count"));
+            }
+            let mut read_bitmap = || -> Result<HashSet<usize>> {
+                Ok(ewah::read(&mut reader, dir_count)?.into_iter().collect())
+            };
+            let valid = read_bitmap()?;
+            let check_only = read_bitmap()?;
+            let hash_valid = read_bitmap()?;
+
+            // All the stat data comes before all the hashes.
+            let mut position = 0;
+            root.visit_mut(&mut |dir| {
+                if valid.contains(&position) {
+                    dir.stat = Some(StatData::from_reader(&mut reader)?);
+                }
+                dir.check_only = check_only.contains(&position);
+                position += 1;
+                Ok(())
+            })?;
+            let mut position = 0;
+            root.visit_mut(&mut |dir| {
+                if hash_valid.contains(&position) {
+                    dir.exclude_hash = Some(read_hash(&mut reader)?);
+                }
+                position += 1;
+                Ok(())
+            })?;
+            if reader != b"\0" {
+                return Err(anyhow!("Invalid untracked cache terminator"));
+            }
+            Some(root)
+        };
+
+        Ok(UntrackedCache {
+            idents,
+            info_exclude,
+            excludes_file,
+            dir_flags,
+            exclude_per_dir,
+            root,
+        })
+    }
+
+    /// Serializes the cache as the data of an `UNTR` extension.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut s = vec![];
+        write_varint(&mut s, self.idents.len());
+        s.extend(&self.idents);
+        self.info_exclude.stat.write_to(&mut s);
+        self.excludes_file.stat.write_to(&mut s);
+        s.extend(self.dir_flags.to_be_bytes());
+        s.extend(hex::decode(&self.info_exclude.hash).expect("hashes are valid"));
+        s.extend(hex::decode(&self.excludes_file.hash).expect("hashes are valid"));
+        s.extend(self.exclude_per_dir.as_bytes());
+        s.push(0);
+
+        let mut dirs = vec![];
+        if let Some(root) = &self.root {
+            root.collect(&mut dirs);
+        }
+        write_varint(&mut s, dirs.len());
+        if dirs.is_empty() {
+            return s;
+        }
+        for dir in &dirs {
+            write_varint(&mut s, dir.untracked.len());
+            write_varint(&mut s, dir.subdirs.len());
+            s.extend(dir.name.as_bytes());
+            s.push(0);
+            for name in &dir.untracked {
+                s.extend(name.as_bytes());
+                s.push(0);
+            }
+        }
+        let positions = |keep: fn(&UntrackedDir) -> bool| -> Vec<usize> {
+            (0..dirs.len()).filter(|&i| keep(dirs[i])).collect()
+        };
+        s.extend(ewah::write(&positions(|dir| dir.stat.is_some())));
+        s.extend(ewah::write(&positions(|dir| dir.check_only)));
+        s.extend(ewah::write(&positions(|dir| dir.exclude_hash.is_some())));
+        for stat in dirs.iter().filter_map(|dir| dir.stat.as_ref()) {
+            stat.write_to(&mut s);
+        }
+        for hash in dirs.iter().filter_map(|dir| dir.exclude_hash.as_ref()) {
+            s.extend(hex::decode(hash).expect("hashes are valid"));
+        }
+        s.push(0);
+        s
+    }
+}
+
+impl UntrackedDir {
+    /// Reads a directory block and the blocks of its subdirectories. `count`
+    /// is incremented for each block.
+    ///
+    /// Like [`crate::cache_tree::CacheTree`] nodes, blocks are read in a loop
+    /// so that corrupt data can't overflow the call stack.
+    fn from_reader(reader: &mut &[u8], count: &mut usize) -> Result<UntrackedDir> {
+        // (directory, number of subdirectories left to read)
+        let mut stack: Vec<(UntrackedDir, usize)> = vec![];
+        loop {
+            if stack.len() > index::MAX_DIR_DEPTH {
+                return Err(anyhow!("Untracked cache is too deep"));
+            }
+            *count += 1;
+            let untracked_count = read_varint(reader)?;
+            let subdir_count = read_varint(reader)?;
+            let name = read_string(reader)?;
+            let mut untracked = vec![];
+            for _ in 0..untracked_count {
+                untracked.push(read_string(reader)?);
+            }
+            let dir = UntrackedDir {
+                name,
+                untracked,
+                ..Default::default()
+            };
+            stack.push((dir, subdir_count));
+            while let Some(&(_, 0)) = stack.last() {
+                let (dir, _) = stack.pop().expect("the stack isn't empty");
+                match stack.last_mut() {
+                    Some((parent, left)) => {
+                        parent.subdirs.push(dir);
+                        *left -= 1;
+                    }
+                    None => return Ok(dir),
+                }
+            }
+        }
+    }
+
+    /// Calls `f` on this directory and its subdirectories in depth-first
+    /// order.
+    fn visit_mut(&mut self, f: &mut dyn FnMut(&mut UntrackedDir) -> Result<()>) -> Result<()> {
+        f(self)?;
+        for subdir in &mut self.subdirs {
+            subdir.visit_mut(f)?;
+        }
+        Ok(())
+    }
+
+    /// Collects the directories in depth-first order, which is the order of
+    /// the bitmaps.
+    fn collect<'a>(&'a self, dirs: &mut Vec<&'a UntrackedDir>) {
+        dirs.push(self);
+        for subdir in &self.subdirs {
+            subdir.collect(dirs);
+        }
+    }
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<u32> {
+    let mut buf = [0_u8; 4];
+    reader
+        .read_exact(&mut buf)
+        .context("Untracked cache is truncated")?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_hash(reader: &mut &[u8]) -> Result<String> {
+    let mut hash = [0_u8; 20];
+    reader
+        .read_exact(&mut hash)
+        .context("Untracked cache is truncated")?;
+    Ok(hex::encode(hash))
+}
+
+fn read_string(reader: &mut &[u8]) -> Result<String> {
+    let mut s = vec![];
+    reader.read_until(0, &mut s)?;
+    if s.pop() != Some(0) {
+        return Err(anyhow!("Untracked cache is truncated"));
+    }
+    Ok(String::from_utf8(s)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(mtime_seconds: u32) -> StatData {
+        StatData {
+            mtime_seconds,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_untracked_cache_round_trips() {
+        let mut cache = UntrackedCache::new(Path::new("/repo"));
+        let s = cache.to_bytes();
+        // Like git, there is no terminator without directories.
+        assert!(s.ends_with(b".gitignore\0\0"));
+        assert_eq!(UntrackedCache::from_bytes(&s).unwrap(), cache);
+        assert_eq!(
+            UntrackedCache::from_bytes(&[s.as_slice(), b"\0"].concat())
+                .unwrap_err()
+                .to_string(),
+            "Invalid untracked cache terminator"
+        );
+
+        cache.update("", stat(1), Some("ab".repeat(20)), vec!["a/".to_string()]);
+        cache.update("a/b", stat(2), None, vec!["x".to_string(), "y".to_string()]);
+        cache.update("c", stat(3), None, vec![]);
+        let s = cache.to_bytes();
+        assert_eq!(UntrackedCache::from_bytes(&s).unwrap(), cache);
+        // "a" was only created as a parent, so it isn't valid.
+        assert_eq!(cache.dir("a").unwrap().stat, None);
+    }
+
+    #[test]
+    fn test_untracked_cache_lookup_and_invalidate() {
+        let mut cache = UntrackedCache::new(Path::new("/repo"));
+        assert!(cache.is_valid_for(Path::new("/repo")));
+        assert!(!cache.is_valid_for(Path::new("/other")));
+
+        cache.update("", stat(1), None, vec!["new.txt".to_string()]);
+        cache.update("a", stat(2), None, vec!["b/".to_string()]);
+        cache.update("a/b", stat(3), None, vec!["c.txt".to_string()]);
+        assert_eq!(
+            cache.untracked("a/b", &stat(3)),
+            Some(["c.txt".to_string()].as_slice())
+        );
+        assert_eq!(cache.untracked("a/b", &stat(4)), None);
+        assert_eq!(cache.untracked("missing", &stat(3)), None);
+
+        cache.invalidate_path("a/d.txt");
+        assert_eq!(cache.untracked("", &stat(1)), None);
+        assert_eq!(cache.untracked("a", &stat(2)), None);
+        assert!(cache.untracked("a/b", &stat(3)).is_some());
+    }
+
+    #[test]
+    fn test_untracked_cache_check_only() {
+        let mut cache = UntrackedCache::new(Path::new("/repo"));
+        cache.update("", stat(1), None, vec!["a/".to_string()]);
+        cache.update_check_only("a", stat(2), None, Some("b/".to_string()));
+        cache.update_check_only("a/b", stat(3), None, Some("c".to_string()));
+        cache.update("d", stat(4), None, vec![]);
+        let s = cache.to_bytes();
+        assert_eq!(UntrackedCache::from_bytes(&s).unwrap(), cache);
+        assert!(cache.dir("a/b").unwrap().check_only);
+
+        cache.prune("", &HashSet::from(["d".to_string()]));
+        assert_eq!(cache.dir("a"), None);
+        assert!(cache.dir("d").is_some());
+    }
+
+    #[test]
+    fn test_untracked_cache_truncated() {
+        let s = UntrackedCache::new(Path::new("/repo")).to_bytes();
+        assert_eq!(
+            UntrackedCache::from_bytes(&s[..40])
+                .unwrap_err()
+                .to_string(),
+            "Untracked cache is truncated"
+        );
+    }
+}
@@ -0,0 +1,347 @@
+/// How [`wildmatch`] matches.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Flags {
+    /// Match paths: `*`, `?` and `[...]` don't match `/`, and `**` matches
+    /// any number of directories when it's a whole path component. Without
+    /// it, `*` matches `/` like `**`.
+    pub pathname: bool,
+    /// Compare letters ignoring their case.
+    pub casefold: bool,
+}
+
+impl Flags {
+    pub const PATHNAME: Flags = Flags {
+        pathname: true,
+        casefold: false,
+    };
+}
+
+/// The outcome of matching part of a pattern, which tells the `*` being
+/// matched whether trying more text can help.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Match,
+    NoMatch,
+    /// No more text can match.
+    AbortAll,
+    /// No more text can match until a `**` is reached.
+    AbortToStarStar,
+}
+
+/// Matches a glob against text like git's wildmatch, which is used for
+/// ignore and attributes patterns, pathspecs and ref patterns.
+///
+/// `*` matches any string and `?` any character. `[...]` matches a character
+/// of a set, with ranges like `a-z`, classes like `[:alpha:]` and `!` or `^`
+/// to negate it, and `\` escapes the next character. An unclosed `[` never
+/// matches.
+pub fn wildmatch(pattern: &[u8], text: &[u8], flags: Flags) -> bool {
+    dowild(pattern, text, flags) == Outcome::Match
+}
+
+/// Returns whether a pattern has characters that [`wildmatch`] treats
+/// specially, so that it isn't matched as a literal string.
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '\\'])
+}
+
+fn dowild(pattern: &[u8], text: &[u8], flags: Flags) -> Outcome {
+    // Past the end, both are seen as NUL like in git's C code.
+    let at = |s: &[u8], i: usize| s.get(i).copied().unwrap_or(0);
+    let fold = |c: u8| match flags.casefold {
+        true => c.to_ascii_lowercase(),
+        false => c,
+    };
+    let mut p = 0;
+    let mut t = 0;
+    while p < pattern.len() {
+        let mut p_ch = fold(pattern[p]);
+        let mut t_ch = fold(at(text, t));
+        if t_ch == 0 && p_ch != b'*' {
+            return Outcome::AbortAll;
+        }
+        match p_ch {
+            b'?' => {
+                if flags.pathname && t_ch == b'/' {
+                    return Outcome::NoMatch;
+                }
+            }
+            b'*' => {
+                p += 1;
+                let match_slash = if at(pattern, p) == b'*' && flags.pathname {
+                    let starts_component = p < 2 || pattern[p - 2] == b'/';
+                    while at(pattern, p) == b'*' {
+                        p += 1;
+                    }
+                    let ends_component = p == pattern.len()
+                        || pattern[p] == b'/'
+                        || (pattern[p] == b'\\' && at(pattern, p + 1) == b'/');
+                    if starts_component && ends_component {
+                        // `**/` also matches no directory at all.
+                        if at(pattern, p) == b'/'
+                            && dowild(&pattern[p + 1..], &text[t..], flags) == Outcome::Match
+                        {
+                            return Outcome::Match;
+                        }
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    while at(pattern, p) == b'*' {
+                        p += 1;
+                    }
+                    // Without pathname, `*` is like `**`.
+                    !flags.pathname
+                };
+                if p == pattern.len() {
+                    // A trailing `*` only matches the rest of a path
+                    // component.
+                    if !match_slash && text[t..].contains(&b'/') {
+                        return Outcome::NoMatch;
+                    }
+                    return Outcome::Match;
+                }
+                if !match_slash && pattern[p] == b'/' {
+                    // `*/` matches up to the next `/`, which the loop
+                    // matches.
+                    let Some(slash) = text[t..].iter().position(|&c| c == b'/') else {
+                        return Outcome::NoMatch;
+                    };
+                    t += slash;
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                loop {
+                    if t_ch == 0 {
+                        break;
+                    }
+                    // Before a literal, the text up to that literal belongs
+                    // to the `*`.
+                    if !b"*?[\\".contains(&pattern[p]) {
+                        let literal = fold(pattern[p]);
+                        loop {
+                            t_ch = fold(at(text, t));
+                            if t_ch == 0 || (!match_slash && t_ch == b'/') || t_ch == literal {
+                                break;
+                            }
+                            t += 1;
+                        }
+                        if t_ch != literal {
+                            return Outcome::NoMatch;
+                        }
+                    }
+                    match dowild(&pattern[p..], &text[t..], flags) {
+                        Outcome::NoMatch if !match_slash && t_ch == b'/' => {
+                            return Outcome::AbortToStarStar;
+                        }
+                        Outcome::NoMatch => {}
+                        Outcome::AbortToStarStar if match_slash => {}
+                        matched => return matched,
+                    }
+                    t += 1;
+                    t_ch = fold(at(text, t));
+                }
+                return Outcome::AbortAll;
+            }
+            b'[' => {
+                p += 1;
+                p_ch = at(pattern, p);
+                if p_ch == b'^' {
+                    p_ch = b'!';
+                }
+                let negated = p_ch == b'!';
+                if negated {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                let mut prev_ch = 0;
+                let mut matched = false;
+                loop {
+                    if p_ch == 0 {
+                        return Outcome::AbortAll;
+                    }
+                    if p_ch == b'\\' {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == 0 {
+                            return Outcome::AbortAll;
+                        }
+                        matched |= t_ch == fold(p_ch);
+                    } else if p_ch == b'-'
+                        && prev_ch != 0
+                        && at(pattern, p + 1) != 0
+                        && at(pattern, p + 1) != b']'
+                    {
+                        p += 1;
+                        p_ch = pattern[p];
+                        if p_ch == b'\\' {
+                            p += 1;
+                            p_ch = at(pattern, p);
+                            if p_ch == 0 {
+                                return Outcome::AbortAll;
+                            }
+                        }
+                        // `t_ch` is folded to lowercase, so with casefold
+                        // its uppercase is tried too.
+                        let in_range = |c: u8| (prev_ch..=p_ch).contains(&c);
+                        matched |= in_range(t_ch)
+                            || (flags.casefold && in_range(t_ch.to_ascii_uppercase()));
+                        p_ch = 0;
+                    } else if p_ch == b'[' && at(pattern, p + 1) == b':' {
+                        let start = p + 2;
+                        let Some(length) = pattern[start..].iter().position(|&c| c == b']') else {
+                            return Outcome::AbortAll;
+                        };
+                        let end = start + length;
+                        if length == 0 || pattern[end - 1] != b':' {
+                            // Without `:]`, this is a `[` in the set.
+                            matched |= t_ch == b'[';
+                        } else {
+                            let Some(class) = class_matches(&pattern[start..end - 1], t_ch, flags)
+                            else {
+                                return Outcome::AbortAll;
+                            };
+                            matched |= class;
+                            p = end;
+                            p_ch = 0;
+                        }
+                    } else {
+                        matched |= t_ch == fold(p_ch);
+                    }
+                    prev_ch = p_ch;
+                    p += 1;
+                    p_ch = at(pattern, p);
+                    if p_ch == b']' {
+                        break;
+                    }
+                }
+                if matched == negated || (flags.pathname && t_ch == b'/') {
+                    return Outcome::NoMatch;
+                }
+            }
+            _ => {
+                if p_ch == b'\\' {
+                    p += 1;
+                    p_ch = fold(at(pattern, p));
+                }
+                if t_ch != p_ch {
+                    return Outcome::NoMatch;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+    match t == text.len() {
+        true => Outcome::Match,
+        false => Outcome::NoMatch,
+    }
+}
+
+/// Returns whether a character is in a class like `alpha` of `[:alpha:]`, or
+/// `None` for unknown classes.
+fn class_matches(class: &[u8], c: u8, flags: Flags) -> Option<bool> {
+    Some(match class {
+        b"alnum" => c.is_ascii_alphanumeric(),
+        b"alpha" => c.is_ascii_alphabetic(),
+        b"blank" => c == b' ' || c == b'\t',
+        b"cntrl" => c.is_ascii_control(),
+        b"digit" => c.is_ascii_digit(),
+        b"graph" => c.is_ascii_graphic(),
+        b"lower" => c.is_ascii_lowercase() || (flags.casefold && c.is_ascii_uppercase()),
+        b"print" => c.is_ascii_graphic() || c == b' ',
+        b"punct" => c.is_ascii_punctuation(),
+        b"space" => c.is_ascii_whitespace() || c == b'\x0b',
+        // With casefold, the character was folded to lowercase.
+        b"upper" => c.is_ascii_uppercase() || (flags.casefold && c.is_ascii_lowercase()),
+        b"xdigit" => c.is_ascii_hexdigit(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+        wildmatch(pattern, text, Flags::PATHNAME)
+    }
+
+    #[test]
+    fn test_pathname() {
+        assert!(glob_match(b"*.log", b"a.log"));
+        assert!(!glob_match(b"*.log", b"dir/a.log"));
+        assert!(glob_match(b"**/a.log", b"a.log"));
+        assert!(glob_match(b"**/a.log", b"x/y/a.log"));
+        assert!(glob_match(b"a/**/b", b"a/b"));
+        assert!(glob_match(b"a/**/b", b"a/x/y/b"));
+        assert!(!glob_match(b"a/**/b", b"ab"));
+        assert!(glob_match(b"a/**", b"a/x/y"));
+        assert!(!glob_match(b"a/**", b"a"));
+        assert!(glob_match(b"a**b", b"axxb"));
+        assert!(!glob_match(b"a**b", b"a/b"));
+        assert!(glob_match(b"?.[ch]", b"a.c"));
+        assert!(!glob_match(b"?.[!ch]", b"a.c"));
+        assert!(glob_match(b"[a-c]x", b"bx"));
+        assert!(glob_match(b"[]]", b"]"));
+        assert!(glob_match(b"\\*", b"*"));
+        assert!(!glob_match(b"\\*", b"a"));
+        assert!(glob_match(b"*.c", b"main.c"));
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"a?c", b"abc"));
+        assert!(!glob_match(b"a?c", b"a/c"));
+        assert!(!glob_match(b"*.c", b"src/main.c"));
+        assert!(glob_match(b"src/*.c", b"src/main.c"));
+        assert!(!glob_match(b"*.c", b"main.h"));
+        assert!(glob_match(b"*/b/*.c", b"a/b/c.c"));
+        assert!(!glob_match(b"*/b/*.c", b"a/x/b/c.c"));
+        assert!(glob_match(b"**/b/**/*.c", b"a/x/b/y/z/c.c"));
+        assert!(!glob_match(b"[!a]", b"/"));
+    }
+
+    #[test]
+    fn test_without_pathname() {
+        let flags = Flags::default();
+        assert!(wildmatch(b"*.c", b"src/main.c", flags));
+        assert!(wildmatch(b"v1.*", b"v1.2.3", flags));
+        assert!(wildmatch(
+            b"refs/*/main",
+            b"refs/remotes/origin/main",
+            flags
+        ));
+        assert!(wildmatch(b"?", b"/", flags));
+        assert!(!wildmatch(b"v1.*", b"v2.0", flags));
+        assert!(!wildmatch(b"a/**/b", b"a/b", flags));
+    }
+
+    #[test]
+    fn test_brackets() {
+        let flags = Flags::default();
+        assert!(wildmatch(b"[[:digit:]][[:alpha:]]", b"1a", flags));
+        assert!(!wildmatch(b"[[:digit:]]", b"a", flags));
+        assert!(wildmatch(b"[[:space:][:punct:]]", b"!", flags));
+        assert!(wildmatch(b"[^[:upper:]]", b"a", flags));
+        assert!(wildmatch(b"[a-]", b"-", flags));
+        assert!(wildmatch(b"[\\]]", b"]", flags));
+        assert!(wildmatch(b"[[]", b"[", flags));
+        assert!(wildmatch(b"[[:x]", b":", flags));
+        // Unclosed brackets and unknown classes never match.
+        assert!(!wildmatch(b"a[", b"a[", flags));
+        assert!(!wildmatch(b"[[:foo:]]", b"a", flags));
+    }
+
+    #[test]
+    fn test_casefold() {
+        let flags = Flags {
+            pathname: true,
+            casefold: true,
+        };
+        assert!(wildmatch(b"*.TXT", b"a.txt", flags));
+        assert!(wildmatch(b"[A-C]", b"b", flags));
+        assert!(wildmatch(b"[a-c]", b"B", flags));
+        assert!(wildmatch(b"[[:upper:]]", b"a", flags));
+        assert!(!wildmatch(b"*.TXT", b"a.txt", Flags::PATHNAME));
+    }
+}
@@ -0,0 +1,352 @@
+use regex::bytes::Regex;
+use std::ops::Range;
+
+use crate::{
+    add_patch::{Hunk, Line},
+    color::{DiffColors, RESET},
+    diff, xdiff,
+};
+
+/// How changed words are shown, like git's `--word-diff=<mode>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordDiffMode {
+    /// `[-removed-]{+added+}`.
+    Plain,
+    /// Only colors, removed words in red and added words in green by
+    /// default, like `--color-words`.
+    Color,
+    /// A line per run of words, starting with ` `, `-` or `+`, and `~` for
+    /// the ends of lines, for scripts.
+    Porcelain,
+}
+
+impl WordDiffMode {
+    /// Returns the mode of `--word-diff=<mode>`, `None` for `none`.
+    pub fn parse(mode: &str) -> anyhow::Result<Option<WordDiffMode>> {
+        match mode {
+            "plain" => Ok(Some(WordDiffMode::Plain)),
+            "color" => Ok(Some(WordDiffMode::Color)),
+            "porcelain" => Ok(Some(WordDiffMode::Porcelain)),
+            "none" => Ok(None),
+            _ => Err(anyhow::anyhow!("bad --word-diff argument: {mode}")),
+        }
+    }
+
+    /// Returns the styles of context, removed and added words, in their
+    /// colors if any, and what ends lines.
+    fn styles(self, colors: Option<&DiffColors>) -> ([Style<'_>; 3], &'static str) {
+        let (context, old, new) = match colors {
+            Some(colors) => (&*colors.context, &*colors.old, &*colors.new),
+            None => ("", "", ""),
+        };
+        let style = |prefix, suffix, color| Style {
+            prefix,
+            suffix,
+            color,
+        };
+        match self {
+            WordDiffMode::Plain => (
+                [
+                    style("", "", context),
+                    style("[-", "-]", old),
+                    style("{+", "+}", new),
+                ],
+                "\n",
+            ),
+            WordDiffMode::Color => (
+                [
+                    style("", "", context),
+                    style("", "", old),
+                    style("", "", new),
+                ],
+                "\n",
+            ),
+            WordDiffMode::Porcelain => (
+                [
+                    style(" ", "\n", context),
+                    style("-", "\n", old),
+                    style("+", "\n", new),
+                ],
+                "~\n",
+            ),
+        }
+    }
+}
+
+/// How the text of runs of words is written in a mode.
+struct Style<'a> {
+    prefix: &'static str,
+    suffix: &'static str,
+    color: &'a str,
+}
+
+/// Returns a hunk with the removed and added lines between its unchanged
+/// lines compared word by word, like `git diff --word-diff`.
+///
+/// Words are the matches of `regex`, where a match is cut at its first
+/// newline, or else runs of non-whitespace. Like git, the text between words
+/// is taken from the new lines, so changes of whitespace only aren't shown.
+///
+/// With `colors`, the header and the words are colored like in patches, see
+/// [`diff::write_patch`], which is what [`WordDiffMode::Color`] needs.
+pub fn hunk_to_bytes(
+    hunk: &Hunk,
+    mode: WordDiffMode,
+    regex: Option<&Regex>,
+    colors: Option<&DiffColors>,
+) -> Vec<u8> {
+    let mut out = diff::hunk_header(hunk, colors);
+
+    let (mut removed, mut added) = (vec![], vec![]);
+    for line in &hunk.lines {
+        // Like git, lines without a newline at the end of the file are
+        // compared as if they had one.
+        let with_newline = |text: &[u8]| {
+            let mut text = text.to_vec();
+            if !text.ends_with(b"\n") {
+                text.push(b'\n');
+            }
+            text
+        };
+        match line {
+            Line::Removed(text) => removed.extend(with_newline(text)),
+            Line::Added(text) => added.extend(with_newline(text)),
+            Line::Context(text) => {
+                write_words(&removed, &added, mode.styles(colors), regex, &mut out);
+                (removed, added) = (vec![], vec![]);
+                write_context_line(&with_newline(text), mode, colors, &mut out);
+            }
+        }
+    }
+    write_words(&removed, &added, mode.styles(colors), regex, &mut out);
+    out
+}
+
+/// Writes an unchanged line of a hunk, in the `context` color before the `\r`
+/// ending it if any.
+fn write_context_line(
+    text: &[u8],
+    mode: WordDiffMode,
+    colors: Option<&DiffColors>,
+    out: &mut Vec<u8>,
+) {
+    let text = text.strip_suffix(b"\n").unwrap_or(text);
+    let mut line = match mode {
+        WordDiffMode::Porcelain => vec![b' '],
+        _ => vec![],
+    };
+    line.extend(text);
+    match colors {
+        Some(colors) => {
+            let (line, cr) = match line.strip_suffix(b"\r") {
+                Some(line) => (line, &b"\r"[..]),
+                None => (&line[..], &b""[..]),
+            };
+            if !line.is_empty() {
+                out.extend(colors.context.as_bytes());
+                out.extend(line);
+                out.extend(RESET.as_bytes());
+            }
+            out.extend(cr);
+        }
+        None => out.extend(line),
+    }
+    if mode == WordDiffMode::Porcelain {
+        out.extend(b"\n~");
+    }
+    out.push(b'\n');
+}
+
+/// Writes the words of `removed` and `added` lines in the styles of context,
+/// removed and added words, like git's `diff_words_show`.
+fn write_words(
+    removed: &[u8],
+    added: &[u8],
+    ([context, old, new], newline): ([Style; 3], &str),
+    regex: Option<&Regex>,
+    out: &mut Vec<u8>,
+) {
+    if added.is_empty() {
+        write_run(removed, &old, newline, out);
+        return;
+    }
+
+    let (old_words, new_words) = (words(removed, regex), words(added, regex));
+    let old_slices: Vec<&[u8]> = old_words
+        .iter()
+        .map(|word| &removed[word.clone()])
+        .collect();
+    let new_slices: Vec<&[u8]> = new_words.iter().map(|word| &added[word.clone()]).collect();
+    // Git diffs words without the indent heuristic.
+    let (removed_words, added_words) = xdiff::diff(&old_slices, &new_slices, false);
+
+    // The range of the text of the words in `start..end`, or the empty range
+    // after the word before if there are none.
+    let span = |words: &[Range<usize>], start: usize, end: usize| match start < end {
+        true => words[start].start..words[end - 1].end,
+        false if start == 0 => 0..0,
+        false => words[start - 1].end..words[start - 1].end,
+    };
+    let mut current = 0;
+    let (mut i, mut j) = (0, 0);
+    loop {
+        while i < old_words.len() && j < new_words.len() && !removed_words[i] && !added_words[j] {
+            (i, j) = (i + 1, j + 1);
+        }
+        if i == old_words.len() && j == new_words.len() {
+            break;
+        }
+        let (old_start, new_start) = (i, j);
+        while i < old_words.len() && removed_words[i] {
+            i += 1;
+        }
+        while j < new_words.len() && added_words[j] {
+            j += 1;
+        }
+        let old_span = span(&old_words, old_start, i);
+        let new_span = span(&new_words, new_start, j);
+        write_run(&added[current..new_span.start], &context, newline, out);
+        write_run(&removed[old_span], &old, newline, out);
+        current = new_span.end;
+        write_run(&added[new_span], &new, newline, out);
+    }
+    write_run(&added[current..], &context, newline, out);
+}
+
+/// Writes text in a style, each of its lines ending with `newline`.
+fn write_run(text: &[u8], style: &Style, newline: &str, out: &mut Vec<u8>) {
+    let mut lines = text.split(|&b| b == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        if !line.is_empty() {
+            out.extend(style.color.as_bytes());
+            out.extend(style.prefix.as_bytes());
+            out.extend(line);
+            out.extend(style.suffix.as_bytes());
+            if !style.color.is_empty() {
+                out.extend(RESET.as_bytes());
+            }
+        }
+        if lines.peek().is_some() {
+            out.extend(newline.as_bytes());
+        }
+    }
+}
+
+/// Returns the ranges of the words of a text, like git's `diff_words_fill`.
+fn words(text: &[u8], regex: Option<&Regex>) -> Vec<Range<usize>> {
+    let is_space = |b: u8| matches!(b, b' ' | b'\t' | b'\n' | b'\r');
+    let mut words = vec![];
+    let mut i = 0;
+    while i < text.len() {
+        let word = match regex {
+            Some(regex) => {
+                let Some(found) = regex.find(&text[i..]) else {
+                    break;
+                };
+                let (start, end) = (i + found.start(), i + found.end());
+                let end = match text[start..end].iter().position(|&b| b == b'\n') {
+                    Some(newline) => start + newline,
+                    None => end,
+                };
+                // Like git, an empty match ends the words.
+                if start >= end {
+                    break;
+                }
+                start..end
+            }
+            None => {
+                let Some(start) = (i..text.len()).find(|&k| !is_space(text[k])) else {
+                    break;
+                };
+                let end = (start + 1..text.len())
+                    .find(|&k| is_space(text[k]))
+                    .unwrap_or(text.len());
+                start..end
+            }
+        };
+        i = word.end;
+        words.push(word);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::add_patch;
+
+    #[test]
+    fn test_words() {
+        let text = b"foo(bar,  baz)\n  qux\n";
+        let found = |regex: Option<&Regex>| -> Vec<&str> {
+            words(text, regex)
+                .into_iter()
+                .map(|word| std::str::from_utf8(&text[word]).unwrap())
+                .collect()
+        };
+        assert_eq!(found(None), ["foo(bar,", "baz)", "qux"]);
+        let regex = Regex::new(r"[a-z]+|[^[:space:]]").unwrap();
+        assert_eq!(
+            found(Some(&regex)),
+            ["foo", "(", "bar", ",", "baz", ")", "qux"]
+        );
+        // Matches are cut at newlines, and empty ones end the words.
+        let regex = Regex::new(r"z\)\s*q|\s").unwrap();
+        assert_eq!(found(Some(&regex)), [" ", " ", "z)"]);
+    }
+
+    #[test]
+    fn test_hunk_to_bytes() {
+        let old = b"The quick brown fox\njumps over the lazy dog.\nkeep\nfoo(bar, baz)\nend\n";
+        let new =
+            b"The quick red fox\njumps over the lazy cat!\nkeep\nfoo(bar,  qux)\nnew line\nend\n";
+        let hunks = add_patch::hunks(old, new, 3);
+        let show = |mode, regex: Option<&Regex>| {
+            let colors = DiffColors::default();
+            let colors = (mode == WordDiffMode::Color).then_some(&colors);
+            String::from_utf8(hunk_to_bytes(&hunks[0], mode, regex, colors)).unwrap()
+        };
+        assert_eq!(
+            show(WordDiffMode::Plain, None),
+            "@@ -1,5 +1,6 @@\n\
+             The quick [-brown-]{+red+} fox\n\
+             jumps over the lazy [-dog.-]{+cat!+}\n\
+             keep\n\
+             foo(bar,  [-baz)-]{+qux)+}\n\
+             {+new line+}\n\
+             end\n"
+        );
+        assert_eq!(
+            show(WordDiffMode::Color, None),
+            "\x1b[36m@@ -1,5 +1,6 @@\x1b[m\n\
+             The quick \x1b[31mbrown\x1b[m\x1b[32mred\x1b[m fox\n\
+             jumps over the lazy \x1b[31mdog.\x1b[m\x1b[32mcat!\x1b[m\n\
+             keep\x1b[m\n\
+             foo(bar,  \x1b[31mbaz)\x1b[m\x1b[32mqux)\x1b[m\n\
+             \x1b[32mnew line\x1b[m\n\
+             end\x1b[m\n"
+        );
+        let regex = Regex::new(".").unwrap();
+        assert!(
+            show(WordDiffMode::Plain, Some(&regex)).contains("The quick [-b-]r[-own-]{+ed+} fox\n")
+        );
+
+        let hunks = add_patch::hunks(b"x y\nz\n", b"x  y\n\nnew\n", 3);
+        assert_eq!(
+            String::from_utf8(hunk_to_bytes(
+                &hunks[0],
+                WordDiffMode::Porcelain,
+                None,
+                None
+            ))
+            .unwrap(),
+            "@@ -1,2 +1,3 @@\n x  y\n~\n~\n-z\n+new\n~\n"
+        );
+        // Lines are only removed.
+        let hunks = add_patch::hunks(b"a\nb c\nd\n", b"a\nd\n", 3);
+        assert_eq!(
+            String::from_utf8(hunk_to_bytes(&hunks[0], WordDiffMode::Plain, None, None)).unwrap(),
+            "@@ -1,3 +1,2 @@\na\n[-b c-]\nd\n"
+        );
+    }
+}
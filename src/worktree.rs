@@ -0,0 +1,194 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{refs, repo::Repo};
+
+/// A working tree of a repository: the main one, or a linked one whose git
+/// folder is `.git/worktrees/<id>` in the main one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Worktree {
+    /// The root of the working tree.
+    pub path: PathBuf,
+    /// The name of the git folder of a linked worktree, `None` for the main
+    /// worktree.
+    pub id: Option<String>,
+    /// What HEAD points to, `None` if it can't be read.
+    pub head: Option<refs::Head>,
+    /// Why the worktree is locked, empty if no reason was given.
+    pub locked: Option<String>,
+}
+
+impl Worktree {
+    /// Returns the repository as seen from the worktree.
+    pub fn repo(&self) -> Repo {
+        Repo::new(&self.path)
+    }
+
+    /// Returns the git folder of a linked worktree.
+    pub fn git_dir(&self, repo: &Repo) -> Option<PathBuf> {
+        let id = self.id.as_ref()?;
+        Some(repo.common_dir().join("worktrees").join(id))
+    }
+}
+
+/// Returns the main worktree, then the linked worktrees sorted by path.
+///
+/// Linked worktrees whose `gitdir` file is missing are skipped.
+pub fn list(repo: &Repo) -> Result<Vec<Worktree>> {
+    let common_dir = repo.common_dir();
+    let main_path = common_dir
+        .parent()
+        .ok_or(anyhow!("Invalid git folder: {}", common_dir.display()))?;
+    let main = Worktree {
+        path: fs::canonicalize(main_path).unwrap_or(main_path.to_path_buf()),
+        id: None,
+        head: read_head(&common_dir),
+        locked: None,
+    };
+
+    let mut linked = vec![];
+    let dir = common_dir.join("worktrees");
+    if dir.is_dir() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let Some(id) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let git_dir = entry.path();
+            let Ok(gitdir) = fs::read_to_string(git_dir.join("gitdir")) else {
+                continue;
+            };
+            // `gitdir` points to the `.git` file of the worktree.
+            let dot_git = PathBuf::from(gitdir.trim_end_matches(['\n', '\r']));
+            let path = dot_git.parent().map_or(dot_git.clone(), Path::to_path_buf);
+            let locked = fs::read_to_string(git_dir.join("locked"))
+                .ok()
+                .map(|reason| reason.trim_end_matches('\n').to_string());
+            linked.push(Worktree {
+                path,
+                id: Some(id),
+                head: read_head(&git_dir),
+                locked,
+            });
+        }
+    }
+    linked.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok([main].into_iter().chain(linked).collect())
+}
+
+/// Finds a worktree by its path.
+pub fn find(repo: &Repo, worktree: &str) -> Result<Worktree> {
+    let path = fs::canonicalize(worktree).ok();
+    list(repo)?
+        .into_iter()
+        .find(|candidate| path.is_some() && fs::canonicalize(&candidate.path).ok() == path)
+        .ok_or(anyhow!("'{worktree}' is not a working tree"))
+}
+
+/// Returns the paths of the worktrees that have a branch checked out. A
+/// branch should only be checked out in one worktree at a time.
+pub fn checked_out(repo: &Repo, branch: &str) -> Result<Vec<PathBuf>> {
+    Ok(list(repo)?
+        .into_iter()
+        .filter(|worktree| worktree.head == Some(refs::Head::Branch(branch.to_string())))
+        .map(|worktree| worktree.path)
+        .collect())
+}
+
+/// Creates the git folder of a linked worktree in the main one and points
+/// the `.git` file of `path` to it, with HEAD set to `head`, a hash or
+/// `ref: <branch>`. The files aren't checked out.
+///
+/// The worktree is named after its folder, with a number appended if the
+/// name is taken.
+pub fn create(repo: &Repo, path: &Path, head: &str) -> Result<Repo> {
+    let worktrees = repo.common_dir().join("worktrees");
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(anyhow!("Invalid worktree path: {}", path.display()))?;
+    let id = (0..)
+        .map(|n| match n {
+            0 => name.to_string(),
+            n => format!("{name}{n}"),
+        })
+        .find(|id| !worktrees.join(id).exists())
+        .expect("the range is infinite");
+    let git_dir = worktrees.join(&id);
+    fs::create_dir_all(&git_dir)?;
+    fs::create_dir_all(path)?;
+    let common_dir = fs::canonicalize(repo.common_dir())?;
+    let path = fs::canonicalize(path)?;
+    fs::write(git_dir.join("commondir"), "../..\n")?;
+    fs::write(
+        git_dir.join("gitdir"),
+        format!("{}\n", path.join(".git").display()),
+    )?;
+    fs::write(git_dir.join("HEAD"), format!("{head}\n"))?;
+    fs::write(
+        path.join(".git"),
+        format!(
+            "gitdir: {}\n",
+            common_dir.join("worktrees").join(&id).display()
+        ),
+    )?;
+    Ok(Repo::new(&path))
+}
+
+fn read_head(git_dir: &Path) -> Option<refs::Head> {
+    let value = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    Some(refs::Head::from_value(value.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_and_list() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(tmpdir.path()).unwrap();
+        let repo = Repo::new(&root.join("main"));
+        crate::init_repo(&repo, "main").unwrap();
+
+        let linked = create(&repo, &root.join("b"), "ref: refs/heads/b").unwrap();
+        create(&repo, &root.join("a"), "0123").unwrap();
+        // The name is taken by `b`.
+        fs::create_dir(root.join("other")).unwrap();
+        create(&repo, &root.join("other/b"), "0123").unwrap();
+
+        assert!(linked.is_linked_worktree());
+        assert_eq!(linked.git_dir(), root.join("main/.git/worktrees/b"));
+        assert_eq!(linked.common_dir(), root.join("main/.git"));
+        let worktrees = list(&linked).unwrap();
+        let ids: Vec<Option<&str>> = worktrees
+            .iter()
+            .map(|worktree| worktree.id.as_deref())
+            .collect();
+        assert_eq!(ids, [None, Some("a"), Some("b"), Some("b1")]);
+        assert_eq!(worktrees[0].path, root.join("main"));
+        assert_eq!(
+            worktrees[2].head,
+            Some(refs::Head::Branch("refs/heads/b".to_string()))
+        );
+        assert_eq!(
+            checked_out(&repo, "refs/heads/b").unwrap(),
+            [root.join("b")]
+        );
+        assert!(checked_out(&repo, "refs/heads/c").unwrap().is_empty());
+
+        let path = root.join("other/b");
+        assert_eq!(
+            find(&repo, path.to_str().unwrap()).unwrap().id.as_deref(),
+            Some("b1")
+        );
+        assert!(find(&repo, "other/b").is_err());
+        assert_eq!(
+            find(&repo, "c").unwrap_err().to_string(),
+            "'c' is not a working tree"
+        );
+    }
+}
@@ -0,0 +1,819 @@
+use std::collections::HashMap;
+
+/// Above this many edits, the search for a shortest edit script settles for
+/// a good enough one, see [`Search::split`].
+const MAX_COST_MIN: isize = 256;
+const HEUR_MIN_COST: isize = 256;
+/// The length of a run of equal lines worth splitting at.
+const SNAKE_COUNT: isize = 20;
+const K_HEUR: isize = 4;
+/// Lines with more matches than this in the other file may be discarded
+/// before the search, see [`discard`].
+const MAX_EQ_LIMIT: usize = 1024;
+const SIMSCAN_WINDOW: isize = 100;
+const KPDIS_RUN: isize = 4;
+
+const MAX_INDENT: isize = 200;
+const MAX_BLANKS: isize = 20;
+const INDENT_HEURISTIC_MAX_SLIDING: isize = 100;
+
+/// Returns which lines of `old` and `new` are removed and added, like git's
+/// default diff algorithm, so that diffs look the same as git's.
+///
+/// That is Myers' algorithm in linear space, with git's heuristics that
+/// give up on a shortest edit script for large inputs and discard lines
+/// without matches first. Groups of changes are then slid to line up with
+/// the changes of the other file, or else with `indent_heuristic`, to the
+/// position the indent heuristic finds the most natural.
+pub fn diff(old: &[&[u8]], new: &[&[u8]], indent_heuristic: bool) -> (Vec<bool>, Vec<bool>) {
+    let mut classes: HashMap<&[u8], usize> = HashMap::new();
+    // The number of lines of each class in each file.
+    let mut counts: Vec<[usize; 2]> = vec![];
+    let mut sides = [(old, vec![]), (new, vec![])];
+    for (side, (lines, line_classes)) in sides.iter_mut().enumerate() {
+        for &line in lines.iter() {
+            let next = classes.len();
+            let class = *classes.entry(line).or_insert(next);
+            if class == counts.len() {
+                counts.push([0, 0]);
+            }
+            counts[class][side] += 1;
+            line_classes.push(class);
+        }
+    }
+    let [(_, classes1), (_, classes2)] = sides;
+    let mut file1 = File::new(old, classes1);
+    let mut file2 = File::new(new, classes2);
+
+    // The lines of the common prefix and suffix are unchanged.
+    let common = file1.len().min(file2.len());
+    let start = (0..common)
+        .find(|&i| file1.classes[i] != file2.classes[i])
+        .unwrap_or(common);
+    let suffix = (0..common - start)
+        .find(|&i| file1.classes[file1.len() - 1 - i] != file2.classes[file2.len() - 1 - i])
+        .unwrap_or(common - start);
+    let ends = [(start, file1.len() - suffix), (start, file2.len() - suffix)];
+    let (kept1, kept2) = discard(&mut file1, &mut file2, &counts, ends);
+
+    let ndiags = kept1.len() + kept2.len() + 3;
+    let mut search = Search {
+        kvd: vec![0; 2 * ndiags + 2],
+        forward: kept2.len() as isize + 1,
+        backward: (ndiags + kept2.len()) as isize + 1,
+        max_cost: bogo_sqrt(ndiags as isize).max(MAX_COST_MIN),
+    };
+    let (lim1, lim2) = (kept1.len() as isize, kept2.len() as isize);
+    let kept = [kept1, kept2];
+    search.compare(
+        &kept,
+        &mut [&mut file1, &mut file2],
+        (0, lim1),
+        (0, lim2),
+        false,
+    );
+
+    compact(&mut file1, &file2, indent_heuristic);
+    compact(&mut file2, &file1, indent_heuristic);
+    let changed = |file: File| file.changed[1..file.changed.len() - 1].to_vec();
+    (changed(file1), changed(file2))
+}
+
+/// The lines of a file being diffed.
+struct File<'a> {
+    lines: &'a [&'a [u8]],
+    /// The class of each line, the same for equal lines of both files.
+    classes: Vec<usize>,
+    /// Whether each line changed, with an unchanged line before the first
+    /// one and after the last one.
+    changed: Vec<bool>,
+}
+
+impl<'a> File<'a> {
+    fn new(lines: &'a [&'a [u8]], classes: Vec<usize>) -> File<'a> {
+        File {
+            lines,
+            classes,
+            changed: vec![false; lines.len() + 2],
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    fn is_changed(&self, i: isize) -> bool {
+        self.changed[(i + 1) as usize]
+    }
+
+    fn set_changed(&mut self, i: isize, changed: bool) {
+        self.changed[(i + 1) as usize] = changed;
+    }
+}
+
+/// The lines of a file kept for the search.
+#[derive(Debug, Default)]
+struct Kept {
+    /// The index of each line in the file.
+    lines: Vec<isize>,
+    classes: Vec<usize>,
+}
+
+impl Kept {
+    fn len(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+/// Marks the lines between `ends` that have no match in the other file as
+/// changed, as well as the lines with many matches that are surrounded by
+/// lines without, which only slow the search down. Returns the other lines.
+fn discard(
+    file1: &mut File,
+    file2: &mut File,
+    counts: &[[usize; 2]],
+    ends: [(usize, usize); 2],
+) -> (Kept, Kept) {
+    // 0 for lines without matches, 1 for lines with some and 2 for lines
+    // with many.
+    let matches = |file: &File, (start, end): (usize, usize), other: usize| -> Vec<u8> {
+        let limit = (bogo_sqrt(file.len() as isize) as usize).min(MAX_EQ_LIMIT);
+        let mut matches = vec![0; file.len()];
+        for i in start..end {
+            matches[i] = match counts[file.classes[i]][other] {
+                0 => 0,
+                count if count >= limit => 2,
+                _ => 1,
+            };
+        }
+        matches
+    };
+    let matches1 = matches(file1, ends[0], 1);
+    let matches2 = matches(file2, ends[1], 0);
+    let keep = |file: &mut File, matches: &[u8], (start, end): (usize, usize)| -> Kept {
+        let mut kept = Kept::default();
+        for i in start..end {
+            if matches[i] == 1
+                || (matches[i] == 2 && !is_discardable(matches, i as isize, start, end - 1))
+            {
+                kept.lines.push(i as isize);
+                kept.classes.push(file.classes[i]);
+            } else {
+                file.set_changed(i as isize, true);
+            }
+        }
+        kept
+    };
+    let kept1 = keep(file1, &matches1, ends[0]);
+    let kept2 = keep(file2, &matches2, ends[1]);
+    (kept1, kept2)
+}
+
+/// Returns whether a line with many matches is in the middle of lines
+/// without matches, mostly, and can be discarded.
+fn is_discardable(matches: &[u8], i: isize, start: usize, end: usize) -> bool {
+    let start = (start as isize).max(i - SIMSCAN_WINDOW);
+    let end = (end as isize).min(i + SIMSCAN_WINDOW);
+    let run = |step: isize| {
+        let (mut none, mut many) = (0, 1);
+        let mut j = i + step;
+        while start <= j && j <= end {
+            match matches[j as usize] {
+                0 => none += 1,
+                2 => many += 1,
+                _ => break,
+            }
+            j += step;
+        }
+        (none, many)
+    };
+    let (none_before, many_before) = run(-1);
+    if none_before == 0 {
+        return false;
+    }
+    let (none_after, many_after) = run(1);
+    if none_after == 0 {
+        return false;
+    }
+    let many = many_before + many_after;
+    many * KPDIS_RUN < many + none_before + none_after
+}
+
+/// An approximation of the square root, for limits.
+fn bogo_sqrt(mut n: isize) -> isize {
+    let mut i = 1;
+    while n > 0 {
+        i <<= 1;
+        n >>= 2;
+    }
+    i
+}
+
+/// The state of the search for an edit script: the furthest reaching paths
+/// on each diagonal, forward and backward.
+struct Search {
+    kvd: Vec<isize>,
+    /// Where the diagonal 0 of each direction is in `kvd`.
+    forward: isize,
+    backward: isize,
+    max_cost: isize,
+}
+
+/// Where [`Search::split`] splits the lines to compare, and whether each
+/// half must have a shortest edit script.
+struct Split {
+    i1: isize,
+    i2: isize,
+    min_low: bool,
+    min_high: bool,
+}
+
+impl Search {
+    fn f(&self, d: isize) -> isize {
+        self.kvd[(self.forward + d) as usize]
+    }
+
+    fn set_f(&mut self, d: isize, value: isize) {
+        self.kvd[(self.forward + d) as usize] = value;
+    }
+
+    fn b(&self, d: isize) -> isize {
+        self.kvd[(self.backward + d) as usize]
+    }
+
+    fn set_b(&mut self, d: isize, value: isize) {
+        self.kvd[(self.backward + d) as usize] = value;
+    }
+
+    /// Marks the kept lines between `off` and `lim` that differ as changed,
+    /// by splitting them around the middle of an edit script until one side
+    /// is empty.
+    fn compare(
+        &mut self,
+        kept: &[Kept; 2],
+        files: &mut [&mut File; 2],
+        (mut off1, mut lim1): (isize, isize),
+        (mut off2, mut lim2): (isize, isize),
+        need_min: bool,
+    ) {
+        let (ha1, ha2) = (&kept[0].classes, &kept[1].classes);
+        let class1 = |i: isize| ha1[i as usize];
+        let class2 = |i: isize| ha2[i as usize];
+        while off1 < lim1 && off2 < lim2 && class1(off1) == class2(off2) {
+            (off1, off2) = (off1 + 1, off2 + 1);
+        }
+        while off1 < lim1 && off2 < lim2 && class1(lim1 - 1) == class2(lim2 - 1) {
+            (lim1, lim2) = (lim1 - 1, lim2 - 1);
+        }
+
+        if off1 == lim1 || off2 == lim2 {
+            for (side, (off, lim)) in [(off1, lim1), (off2, lim2)].into_iter().enumerate() {
+                for i in off..lim {
+                    files[side].set_changed(kept[side].lines[i as usize], true);
+                }
+            }
+        } else {
+            let split = self.split(ha1, (off1, lim1), ha2, (off2, lim2), need_min);
+            let low = ((off1, split.i1), (off2, split.i2));
+            self.compare(kept, files, low.0, low.1, split.min_low);
+            let high = ((split.i1, lim1), (split.i2, lim2));
+            self.compare(kept, files, high.0, high.1, split.min_high);
+        }
+    }
+
+    /// Finds where to split the lines, on the middle snake of a shortest
+    /// edit script, or on a good enough snake when the edit script gets
+    /// long.
+    fn split(
+        &mut self,
+        ha1: &[usize],
+        (off1, lim1): (isize, isize),
+        ha2: &[usize],
+        (off2, lim2): (isize, isize),
+        need_min: bool,
+    ) -> Split {
+        let ha1 = |i: isize| ha1[i as usize];
+        let ha2 = |i: isize| ha2[i as usize];
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+        self.set_f(fmid, off1);
+        self.set_b(bmid, lim1);
+
+        let mut ec = 1;
+        loop {
+            let mut got_snake = false;
+            // Extend the diagonals by one, or shrink them at the edges.
+            if fmin > dmin {
+                fmin -= 1;
+                self.set_f(fmin - 1, -1);
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                self.set_f(fmax + 1, -1);
+            } else {
+                fmax -= 1;
+            }
+            for d in (fmin..=fmax).rev().step_by(2) {
+                let mut i1 = match self.f(d - 1) >= self.f(d + 1) {
+                    true => self.f(d - 1) + 1,
+                    false => self.f(d + 1),
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && ha1(i1) == ha2(i2) {
+                    (i1, i2) = (i1 + 1, i2 + 1);
+                }
+                if i1 - prev1 > SNAKE_COUNT {
+                    got_snake = true;
+                }
+                self.set_f(d, i1);
+                if odd && bmin <= d && d <= bmax && self.b(d) <= i1 {
+                    return Split {
+                        i1,
+                        i2,
+                        min_low: true,
+                        min_high: true,
+                    };
+                }
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                self.set_b(bmin - 1, isize::MAX);
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                self.set_b(bmax + 1, isize::MAX);
+            } else {
+                bmax -= 1;
+            }
+            for d in (bmin..=bmax).rev().step_by(2) {
+                let mut i1 = match self.b(d - 1) < self.b(d + 1) {
+                    true => self.b(d - 1),
+                    false => self.b(d + 1) - 1,
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && ha1(i1 - 1) == ha2(i2 - 1) {
+                    (i1, i2) = (i1 - 1, i2 - 1);
+                }
+                if prev1 - i1 > SNAKE_COUNT {
+                    got_snake = true;
+                }
+                self.set_b(d, i1);
+                if !odd && fmin <= d && d <= fmax && i1 <= self.f(d) {
+                    return Split {
+                        i1,
+                        i2,
+                        min_low: true,
+                        min_high: true,
+                    };
+                }
+            }
+
+            if need_min {
+                ec += 1;
+                continue;
+            }
+
+            // Past some cost, a long enough snake far enough from the
+            // corners is good enough.
+            if got_snake && ec > HEUR_MIN_COST {
+                let mut best = 0;
+                let mut split = None;
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let dd = (d - fmid).abs();
+                    let i1 = self.f(d);
+                    let i2 = i1 - d;
+                    let v = (i1 - off1) + (i2 - off2) - dd;
+                    if v > K_HEUR * ec
+                        && v > best
+                        && off1 + SNAKE_COUNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_COUNT <= i2
+                        && i2 < lim2
+                        && (1..=SNAKE_COUNT).all(|k| ha1(i1 - k) == ha2(i2 - k))
+                    {
+                        best = v;
+                        split = Some((i1, i2));
+                    }
+                }
+                if let Some((i1, i2)) = split {
+                    return Split {
+                        i1,
+                        i2,
+                        min_low: true,
+                        min_high: false,
+                    };
+                }
+
+                let mut best = 0;
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let dd = (d - bmid).abs();
+                    let i1 = self.b(d);
+                    let i2 = i1 - d;
+                    let v = (lim1 - i1) + (lim2 - i2) - dd;
+                    if v > K_HEUR * ec
+                        && v > best
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_COUNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_COUNT
+                        && (0..SNAKE_COUNT).all(|k| ha1(i1 + k) == ha2(i2 + k))
+                    {
+                        best = v;
+                        split = Some((i1, i2));
+                    }
+                }
+                if let Some((i1, i2)) = split {
+                    return Split {
+                        i1,
+                        i2,
+                        min_low: false,
+                        min_high: true,
+                    };
+                }
+            }
+
+            // Enough is enough: take the furthest reaching path.
+            if ec >= self.max_cost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let mut i1 = self.f(d).min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        (i1, i2) = (lim2 + d, lim2);
+                    }
+                    if fbest < i1 + i2 {
+                        (fbest, fbest1) = (i1 + i2, i1);
+                    }
+                }
+                let (mut bbest, mut bbest1) = (isize::MAX, isize::MAX);
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let mut i1 = off1.max(self.b(d));
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        (i1, i2) = (off2 + d, off2);
+                    }
+                    if i1 + i2 < bbest {
+                        (bbest, bbest1) = (i1 + i2, i1);
+                    }
+                }
+                return match (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    true => Split {
+                        i1: fbest1,
+                        i2: fbest - fbest1,
+                        min_low: true,
+                        min_high: false,
+                    },
+                    false => Split {
+                        i1: bbest1,
+                        i2: bbest - bbest1,
+                        min_low: false,
+                        min_high: true,
+                    },
+                };
+            }
+            ec += 1;
+        }
+    }
+}
+
+/// A group of changed lines, from `start` to `end` excluded, which is empty
+/// where the other file has changes.
+#[derive(Debug, Clone, Copy)]
+struct Group {
+    start: isize,
+    end: isize,
+}
+
+impl Group {
+    fn first(file: &File) -> Group {
+        let mut end = 0;
+        while file.is_changed(end) {
+            end += 1;
+        }
+        Group { start: 0, end }
+    }
+
+    /// Moves to the next group, unless this is the last one.
+    fn next(&mut self, file: &File) -> bool {
+        if self.end == file.len() as isize {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while file.is_changed(self.end) {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, file: &File) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while file.is_changed(self.start - 1) {
+            self.start -= 1;
+        }
+        true
+    }
+
+    /// Slides the group down by a line, if the line after it is the same as
+    /// its first one, merging it with the next group if they touch.
+    fn slide_down(&mut self, file: &mut File) -> bool {
+        let len = file.len() as isize;
+        if self.end < len && file.classes[self.start as usize] == file.classes[self.end as usize] {
+            file.set_changed(self.start, false);
+            file.set_changed(self.end, true);
+            self.start += 1;
+            self.end += 1;
+            while file.is_changed(self.end) {
+                self.end += 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn slide_up(&mut self, file: &mut File) -> bool {
+        if self.start > 0
+            && file.classes[(self.start - 1) as usize] == file.classes[(self.end - 1) as usize]
+        {
+            self.start -= 1;
+            self.end -= 1;
+            file.set_changed(self.start, true);
+            file.set_changed(self.end, false);
+            while file.is_changed(self.start - 1) {
+                self.start -= 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Slides the groups of changes of `file` to where they read best, like
+/// git's `xdl_change_compact`, keeping track of the groups of `other`.
+fn compact(file: &mut File, other: &File, indent_heuristic: bool) {
+    let mut g = Group::first(file);
+    let mut go = Group::first(other);
+    loop {
+        if g.end != g.start {
+            // Slide the group up, then down as far as possible, merging it
+            // with the groups it meets.
+            let mut end_matching_other;
+            let mut earliest_end;
+            loop {
+                let size = g.end - g.start;
+                end_matching_other = -1;
+                while g.slide_up(file) {
+                    assert!(go.previous(other), "group sync broken sliding up");
+                }
+                earliest_end = g.end;
+                if go.end > go.start {
+                    end_matching_other = g.end;
+                }
+                while g.slide_down(file) {
+                    assert!(go.next(other), "group sync broken sliding down");
+                    if go.end > go.start {
+                        end_matching_other = g.end;
+                    }
+                }
+                if size == g.end - g.start {
+                    break;
+                }
+            }
+
+            if g.end == earliest_end {
+                // The group can't slide.
+            } else if end_matching_other != -1 {
+                // Line it up with the last group of the other file it can.
+                while go.end == go.start {
+                    assert!(g.slide_up(file), "match disappeared");
+                    assert!(go.previous(other), "group sync broken sliding to match");
+                }
+            } else if indent_heuristic {
+                let size = g.end - g.start;
+                let mut shift = earliest_end
+                    .max(g.end - size - 1)
+                    .max(g.end - INDENT_HEURISTIC_MAX_SLIDING);
+                let mut best: Option<(isize, Score)> = None;
+                while shift <= g.end {
+                    let mut score = Score::default();
+                    score.add(&measure_split(file, shift));
+                    score.add(&measure_split(file, shift - size));
+                    if best.is_none_or(|(_, best)| score.cmp(&best) <= 0) {
+                        best = Some((shift, score));
+                    }
+                    shift += 1;
+                }
+                let best_shift = best.expect("the group can slide").0;
+                while g.end > best_shift {
+                    assert!(g.slide_up(file), "best shift unreached");
+                    assert!(
+                        go.previous(other),
+                        "group sync broken sliding to blank line"
+                    );
+                }
+            }
+        }
+        if !g.next(file) {
+            break;
+        }
+        assert!(go.next(other), "group sync broken moving to next group");
+    }
+}
+
+/// What the lines around a split between lines look like, for the indent
+/// heuristic.
+#[derive(Debug, Default)]
+struct SplitMeasurement {
+    end_of_file: bool,
+    /// The indent of the line after the split, -1 if it's blank.
+    indent: isize,
+    /// The number of blank lines before the split, and the indent of the
+    /// line before them, -1 if there is none.
+    pre_blank: isize,
+    pre_indent: isize,
+    /// The same for the lines after the line after the split.
+    post_blank: isize,
+    post_indent: isize,
+}
+
+/// Measures the split before line `split`.
+fn measure_split(file: &File, split: isize) -> SplitMeasurement {
+    let len = file.len() as isize;
+    let mut m = SplitMeasurement::default();
+    if split >= len {
+        m.end_of_file = true;
+        m.indent = -1;
+    } else {
+        m.indent = indent(file.lines[split as usize]);
+    }
+
+    m.pre_indent = -1;
+    let mut i = split - 1;
+    while i >= 0 {
+        m.pre_indent = indent(file.lines[i as usize]);
+        if m.pre_indent != -1 {
+            break;
+        }
+        m.pre_blank += 1;
+        if m.pre_blank == MAX_BLANKS {
+            m.pre_indent = 0;
+            break;
+        }
+        i -= 1;
+    }
+
+    m.post_indent = -1;
+    let mut i = split + 1;
+    while i < len {
+        m.post_indent = indent(file.lines[i as usize]);
+        if m.post_indent != -1 {
+            break;
+        }
+        m.post_blank += 1;
+        if m.post_blank == MAX_BLANKS {
+            m.post_indent = 0;
+            break;
+        }
+        i += 1;
+    }
+    m
+}
+
+/// Returns the indent of a line, with tabs to the next multiple of 8, or -1
+/// if it's blank.
+fn indent(line: &[u8]) -> isize {
+    let mut indent = 0;
+    for &c in line {
+        if !c.is_ascii_whitespace() && c != b'\x0b' {
+            return indent;
+        }
+        match c {
+            b' ' => indent += 1,
+            b'\t' => indent += 8 - indent % 8,
+            _ => {}
+        }
+        if indent >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+/// The badness of the splits around a group of changes: the lower, the
+/// more natural.
+#[derive(Debug, Default, Clone, Copy)]
+struct Score {
+    effective_indent: isize,
+    penalty: isize,
+}
+
+impl Score {
+    fn add(&mut self, m: &SplitMeasurement) {
+        const START_OF_FILE_PENALTY: isize = 1;
+        const END_OF_FILE_PENALTY: isize = 21;
+        const TOTAL_BLANK_WEIGHT: isize = -30;
+        const POST_BLANK_WEIGHT: isize = 6;
+        const RELATIVE_INDENT_PENALTY: isize = -4;
+        const RELATIVE_INDENT_WITH_BLANK_PENALTY: isize = 10;
+        const RELATIVE_OUTDENT_PENALTY: isize = 24;
+        const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: isize = 17;
+        const RELATIVE_DEDENT_PENALTY: isize = 23;
+        const RELATIVE_DEDENT_WITH_BLANK_PENALTY: isize = 17;
+
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += START_OF_FILE_PENALTY;
+        }
+        if m.end_of_file {
+            self.penalty += END_OF_FILE_PENALTY;
+        }
+        let post_blank = match m.indent {
+            -1 => 1 + m.post_blank,
+            _ => 0,
+        };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+        self.penalty += POST_BLANK_WEIGHT * post_blank;
+        let indent = match m.indent {
+            -1 => m.post_indent,
+            indent => indent,
+        };
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent;
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+        } else if indent > m.pre_indent {
+            self.penalty += match any_blanks {
+                true => RELATIVE_INDENT_WITH_BLANK_PENALTY,
+                false => RELATIVE_INDENT_PENALTY,
+            };
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            self.penalty += match any_blanks {
+                true => RELATIVE_OUTDENT_WITH_BLANK_PENALTY,
+                false => RELATIVE_OUTDENT_PENALTY,
+            };
+        } else {
+            self.penalty += match any_blanks {
+                true => RELATIVE_DEDENT_WITH_BLANK_PENALTY,
+                false => RELATIVE_DEDENT_PENALTY,
+            };
+        }
+    }
+
+    /// Compares two scores, negative if this one is better.
+    fn cmp(&self, other: &Score) -> isize {
+        const INDENT_WEIGHT: isize = 60;
+        let indents = (self.effective_indent - other.effective_indent).signum();
+        INDENT_WEIGHT * indents + (self.penalty - other.penalty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(s: &str) -> Vec<&[u8]> {
+        s.as_bytes().split_inclusive(|&b| b == b'\n').collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let (old, new) = (lines("a\nb\nc\n"), lines("a\nB\nc\nd\n"));
+        assert_eq!(
+            diff(&old, &new, true),
+            (vec![false, true, false], vec![false, true, false, true])
+        );
+        assert_eq!(diff(&[], &old, true), (vec![], vec![true; 3]));
+    }
+
+    #[test]
+    fn test_compact() {
+        // An added function is shown whole rather than starting at the end
+        // of the previous one.
+        let old = lines("fn a() {\n}\n\nfn c() {\n}\n");
+        let new = lines("fn a() {\n}\n\nfn b() {\n}\n\nfn c() {\n}\n");
+        let (_, added) = diff(&old, &new, true);
+        assert_eq!(added, [false, false, false, true, true, true, false, false]);
+
+        // Added lines are slid down as far as they go without a better
+        // place.
+        let (old, new) = (lines("a\n}\n"), lines("a\n}\nb\n}\n"));
+        let (removed, added) = diff(&old, &new, true);
+        assert_eq!(removed, [false, false]);
+        assert_eq!(added, [false, false, true, true]);
+    }
+}
@@ -1,9 +1,10 @@
 use flate2::{write::ZlibEncoder, Compression};
-use good_git::object::{Commit, Tree};
+use good_git::object::{Commit, Tag, Tree};
 use good_git::repo::Repo;
+use good_git::revwalk::Missing;
 use rstest::fixture;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn write_compressed_object(dir: PathBuf, hash: &str, object_content: &[u8]) {
     let (short_hash, long_hash) = hash.split_at(2);
@@ -54,21 +55,19 @@ fn create_commit(dir: PathBuf, hash: &str, commit: &Commit) {
     // ...
     // <empty line>
     // [commit message]
+    let mut parents = String::new();
+    for parent in &commit.parents {
+        parents.push_str(&format!("parent {parent}\n"));
+    }
     let content = format!(
         "\
 tree {}
 encoding {}
 committer {}
 author {}
-parent {}
-
+{}
 {}",
-        commit.tree,
-        commit.encoding,
-        commit.committer,
-        commit.author,
-        commit.parent,
-        commit.message
+        commit.tree, commit.encoding, commit.committer, commit.author, parents, commit.message
     )
     .into_bytes();
 
@@ -78,6 +77,89 @@ parent {}
     write_compressed_object(dir, hash, &full_bytes);
 }
 
+fn create_tag(dir: PathBuf, hash: &str, tag: &Tag) {
+    let content = format!(
+        "object {}\ntype {}\ntag {}\ntagger {}\n\n{}",
+        tag.object, tag.object_type, tag.tag, tag.tagger, tag.message
+    )
+    .into_bytes();
+
+    let header = format!("tag {}\0", content.len()).into_bytes();
+    let full_bytes = [header, content].concat();
+
+    write_compressed_object(dir, hash, &full_bytes);
+}
+
+fn create_ref(dir: PathBuf, name: &str, hash: &str) {
+    let path = dir.join(".git").join(name);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, format!("{hash}\n")).unwrap();
+}
+
+/// Initializes a repository on `main` at `root`, committing as `name
+/// <email>`.
+fn init_user_repo(root: &Path, name: &str, email: &str) -> Repo {
+    let repo = Repo::new(root);
+    good_git::init_repo(&repo, "main").unwrap();
+    std::fs::write(
+        repo.git_dir().join("config"),
+        format!("[user]\n\tname = {name}\n\temail = {email}\n"),
+    )
+    .unwrap();
+    repo
+}
+
+/// Initializes a repository in a temporary directory, see
+/// [`init_user_repo`].
+fn user_repo(name: &str, email: &str) -> (tempfile::TempDir, Repo) {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let repo = init_user_repo(tmpdir.path(), name, email);
+    (tmpdir, repo)
+}
+
+/// Writes a file, stages it and commits it, returning the new HEAD.
+fn add_and_commit(repo: &Repo, path: &str, content: &str, message: &str) -> String {
+    std::fs::write(repo.root.join(path), content).unwrap();
+    let options = good_git::AddOptions::default();
+    good_git::add(repo, &[path.to_string()], &options, &mut Vec::new()).unwrap();
+    let options = good_git::CommitOptions {
+        message: message.to_string(),
+        ..Default::default()
+    };
+    good_git::commit(repo, &options, &mut Vec::new()).unwrap();
+    good_git::object::Object::resolve_rev(repo, "HEAD").unwrap()
+}
+
+/// Stages every change in the working tree and commits it.
+fn commit_all(repo: &Repo, message: &str, stdout: &mut Vec<u8>) {
+    let options = good_git::AddOptions {
+        all: true,
+        ..Default::default()
+    };
+    good_git::add(repo, &[], &options, stdout).unwrap();
+    let options = good_git::CommitOptions {
+        message: message.to_string(),
+        ..Default::default()
+    };
+    good_git::commit(repo, &options, stdout).unwrap();
+}
+
+/// Runs the command line in a directory and returns its output, for the
+/// options only parsed there.
+fn run_good_git(dir: &Path, args: &[&str]) -> String {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_good_git"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
 #[fixture]
 fn test_repo() -> tempfile::TempDir {
     let tmpdir = tempfile::tempdir().unwrap();
@@ -119,7 +201,7 @@ fn test_repo() -> tempfile::TempDir {
 
     let commit = Commit {
         tree: "99887766554433221100aabbccddeeff00112233".to_string(),
-        parent: "".to_string(),
+        parents: vec![],
         author: "Bob <hello@bob.test>".to_string(),
         committer: "Alice <bye@alice.test>".to_string(),
         encoding: "".to_string(),
@@ -133,7 +215,7 @@ fn test_repo() -> tempfile::TempDir {
 
     let commit = Commit {
         tree: "99887766554433221100aabbccddeeff00112233".to_string(),
-        parent: "aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb".to_string(),
+        parents: vec!["aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb".to_string()],
         author: "Captain Nemo <nemo@nautilus.sea>".to_string(),
         committer: "Sherlock Holmes <sherlock@baker.street>".to_string(),
         encoding: "".to_string(),
@@ -145,6 +227,30 @@ fn test_repo() -> tempfile::TempDir {
         &commit,
     );
 
+    let tag = Tag {
+        object: "aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb".to_string(),
+        object_type: "commit".to_string(),
+        tag: "v1".to_string(),
+        tagger: "Bob <hello@bob.test>".to_string(),
+        message: "The first version".to_string(),
+    };
+    create_tag(
+        git_dir.clone(),
+        "eeeeeeeeeeeeeeeeeeeeffffffffffffffffffff",
+        &tag,
+    );
+
+    create_ref(
+        git_dir.clone(),
+        "refs/heads/main",
+        "ccccccccccccccccccccdddddddddddddddddddd",
+    );
+    create_ref(
+        git_dir.clone(),
+        "refs/tags/v1",
+        "eeeeeeeeeeeeeeeeeeeeffffffffffffffffffff",
+    );
+
     tmpdir
 }
 
@@ -167,6 +273,28 @@ mod tests {
         assert_eq!(stdout, b"test content\n\n");
     }
 
+    #[test]
+    fn test_show_index() {
+        // A version 2 index of a pack with the blob of `test content\n`.
+        let mut index = b"\xfftOc\0\0\0\x02".to_vec();
+        for byte in 0..=255_u32 {
+            index.extend(u32::from(byte >= 0xd6).to_be_bytes());
+        }
+        index.extend(hex::decode("d670460b4b4aece5915caf5c68d12f560a9fe3e4").unwrap());
+        index.extend(0x0ee2_0c11_u32.to_be_bytes());
+        index.extend(12_u32.to_be_bytes());
+        index.extend([0; 40]);
+
+        let mut stdout = Vec::new();
+        good_git::show_index(&mut index.as_slice(), &mut stdout).unwrap();
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            "12 d670460b4b4aece5915caf5c68d12f560a9fe3e4 (0ee20c11)\n"
+        );
+
+        assert!(good_git::show_index(&mut &index[..100], &mut Vec::new()).is_err());
+    }
+
     #[rstest]
     #[case("d670", b"test content\n\n".to_vec())]
     #[case("d67046", b"test content\n\n".to_vec())]
@@ -188,6 +316,8 @@ mod tests {
     #[case("d")]
     #[case("d6")]
     #[case("hello")]
+    #[case("aéb")]
+    #[case("d6/7")]
     fn test_cat_file_fails_if_rev_not_found(test_repo: tempfile::TempDir, #[case] input: String) {
         let repo = Repo::new(test_repo.path());
         let mut stdout = Vec::new();
@@ -228,6 +358,17 @@ from a good client
         );
     }
 
+    #[rstest]
+    fn test_cat_file_binary_blob(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let hash = "0123456789012345678901234567890123456789";
+        create_blob(test_repo.path().to_path_buf(), hash, "\u{0}\u{1}bin\u{ff}");
+        let mut stdout = Vec::new();
+
+        good_git::cat_file(&repo, hash, &mut stdout).unwrap();
+        assert_eq!(stdout, "\u{0}\u{1}bin\u{ff}".as_bytes());
+    }
+
     #[rstest]
     fn test_cat_file_commit(test_repo: tempfile::TempDir) {
         let repo = Repo::new(test_repo.path());
@@ -266,10 +407,123 @@ This is a good commit
         assert_eq!(
             stdout,
             b"\
-cccccc - Here is a better commit - \"Sherlock Holmes <sherlock@baker.street>\"
-aaaaaa - This is a good commit - \"Alice <bye@alice.test>\"
+ccccccc - Here is a better commit - \"Sherlock Holmes <sherlock@baker.street>\"
+aaaaaaa - This is a good commit - \"Alice <bye@alice.test>\"
 ",
         );
+
+        // Subjects are padded so that committers line up, and committers
+        // are truncated.
+        let options = good_git::LogOptions {
+            oneline: true,
+            full_hash: true,
+            subject_width: Some(12),
+            committer_width: Some(10),
+            ..Default::default()
+        };
+        stdout.clear();
+        good_git::log_with_options(&repo, "aaaa", &options, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb - This is a .. - \"Alice <b..\"\n"
+        );
+
+        // Git's default format, with the parents of merges and the dates of
+        // authors in their time zone.
+        let merge = Commit {
+            tree: "99887766554433221100aabbccddeeff00112233".to_string(),
+            parents: vec![
+                "ccccccccccccccccccccdddddddddddddddddddd".to_string(),
+                "aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb".to_string(),
+            ],
+            author: "A U Thor <author@example.com> 1112911993 +0200".to_string(),
+            committer: "A U Thor <author@example.com> 1112911993 +0200".to_string(),
+            message: "Merge\n\nWith a body  \n\n".to_string(),
+            ..Default::default()
+        };
+        let merge = good_git::object::write_object(
+            &repo,
+            good_git::object::ObjectType::Commit,
+            &merge.to_bytes(),
+        )
+        .unwrap();
+        let options = good_git::LogOptions {
+            full_hash: true,
+            ..Default::default()
+        };
+        stdout.clear();
+        good_git::log_with_options(&repo, &merge, &options, &mut stdout).unwrap();
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            format!(
+                "\
+commit {merge}
+Merge: ccccccc aaaaaaa
+Author: A U Thor <author@example.com>
+Date:   Fri Apr 8 00:13:13 2005 +0200
+
+    Merge
+    
+    With a body
+
+commit ccccccccccccccccccccdddddddddddddddddddd
+Author: Captain Nemo <nemo@nautilus.sea>
+
+    Here is a better commit
+
+commit aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb
+Author: Bob <hello@bob.test>
+
+    This is a good commit
+"
+            )
+        );
+
+        let log = |format: good_git::pretty::Format| {
+            let options = good_git::LogOptions {
+                format,
+                ..Default::default()
+            };
+            let mut stdout = Vec::new();
+            good_git::log_with_options(&repo, &merge, &options, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        assert!(log(good_git::pretty::Format::Fuller).contains(
+            "\
+Author:     A U Thor <author@example.com>
+AuthorDate: Fri Apr 8 00:13:13 2005 +0200
+Commit:     A U Thor <author@example.com>
+CommitDate: Fri Apr 8 00:13:13 2005 +0200
+
+    Merge
+"
+        ));
+        assert!(log(good_git::pretty::Format::Raw).contains(
+            "\
+parent ccccccccccccccccccccdddddddddddddddddddd
+parent aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb
+author A U Thor <author@example.com> 1112911993 +0200
+"
+        ));
+        assert_eq!(
+            log(good_git::pretty::Format::Oneline).lines().last(),
+            Some("aaaaaaa This is a good commit")
+        );
+        assert_eq!(
+            log(good_git::pretty::Format::Tformat("%h %s%n%b".to_string())),
+            format!(
+                "{} Merge\nWith a body  \n\nccccccc Here is a better commit\n\n\
+                 aaaaaaa This is a good commit\n\n",
+                &merge[..7]
+            )
+        );
+        assert_eq!(
+            log(good_git::pretty::Format::Format("%h".to_string())),
+            format!("{}\nccccccc\naaaaaaa", &merge[..7])
+        );
+        // Colors and the encoding are dropped, unknown placeholders kept.
+        let format = "%C(red)%h%e%Creset %Cblue%Q %C(".to_string();
+        assert!(log(good_git::pretty::Format::Tformat(format)).ends_with("\naaaaaaa %Q %C(\n"));
     }
 
     #[rstest]
@@ -293,4 +547,5648 @@ aaaaaa - This is a good commit - \"Alice <bye@alice.test>\"
 
         assert_eq!(stdout, b"test content\n\n");
     }
+
+    #[rstest]
+    fn test_hash_object_literally(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let commit = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                      author A U Thor <a@example.com> 0123 +0000\n\
+                      committer A U Thor <a@example.com> 0 +0000\n\nMessage\n";
+        let hash_object = |literally: bool| {
+            let options = good_git::HashObjectOptions {
+                object_type: Some(good_git::object::ObjectType::Commit),
+                literally,
+            };
+            let mut stdout = Vec::new();
+            good_git::hash_object_with_options(
+                good_git::HashObjectMode::Write(&repo),
+                &mut commit.as_bytes(),
+                &options,
+                &mut stdout,
+            )
+            .map(|_| String::from_utf8(stdout).unwrap())
+        };
+
+        assert_eq!(
+            hash_object(false).unwrap_err().to_string(),
+            "object fails fsck: zeroPaddedDate: invalid author/committer line - zero-padded date"
+        );
+        // Malformed historical objects can be reproduced byte for byte.
+        let hash = hash_object(true).unwrap();
+        assert_eq!(hash, "050f935046172a8f8f99b42e887a95b3a806cdd8\n");
+        let mut stdout = Vec::new();
+        good_git::cat_file_type(&repo, hash.trim(), &mut stdout).unwrap();
+        assert_eq!(stdout, b"commit\n");
+    }
+
+    #[rstest]
+    fn test_stats(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let mut stdout = Vec::new();
+
+        good_git::stats(&repo, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "\
+commits: 2
+contributors: 2
+objects:
+  blob: 2
+  commit: 2
+  tag: 1
+  tree: 1
+largest blobs:
+  1234567890abcdef1234567890abcdef12345678 31
+  d670460b4b4aece5915caf5c68d12f560a9fe3e4 13
+max tree depth: 1
+"
+        );
+    }
+
+    #[rstest]
+    fn test_analyze_large_blobs(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let mut stdout = Vec::new();
+
+        good_git::analyze_large_blobs(&repo, 1, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "1234567890abcdef1234567890abcdef12345678 31 more.txt\n"
+        );
+    }
+
+    #[rstest]
+    #[case(
+        good_git::ShowRefOptions::default(),
+        "\
+ccccccccccccccccccccdddddddddddddddddddd refs/heads/main
+eeeeeeeeeeeeeeeeeeeeffffffffffffffffffff refs/tags/v1
+"
+    )]
+    #[case(good_git::ShowRefOptions { heads: true, ..Default::default() }, "\
+ccccccccccccccccccccdddddddddddddddddddd refs/heads/main
+")]
+    #[case(good_git::ShowRefOptions { tags: true, dereference: true, ..Default::default() }, "\
+eeeeeeeeeeeeeeeeeeeeffffffffffffffffffff refs/tags/v1
+aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb refs/tags/v1^{}
+")]
+    #[case(good_git::ShowRefOptions { patterns: vec!["main".to_string()], ..Default::default() }, "\
+ccccccccccccccccccccdddddddddddddddddddd refs/heads/main
+")]
+    #[case(good_git::ShowRefOptions { verify: true, patterns: vec!["HEAD".to_string()], ..Default::default() }, "\
+ccccccccccccccccccccdddddddddddddddddddd HEAD
+")]
+    fn test_show_ref(
+        test_repo: tempfile::TempDir,
+        #[case] options: good_git::ShowRefOptions,
+        #[case] expected: &str,
+    ) {
+        let repo = Repo::new(test_repo.path());
+        let mut stdout = Vec::new();
+
+        good_git::show_ref(&repo, &options, &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(good_git::ShowRefOptions { verify: true, patterns: vec!["main".to_string()], ..Default::default() }, "'main' - not a valid ref")]
+    #[case(good_git::ShowRefOptions { patterns: vec!["ain".to_string()], ..Default::default() }, "No matching refs found")]
+    fn test_show_ref_fails(
+        test_repo: tempfile::TempDir,
+        #[case] options: good_git::ShowRefOptions,
+        #[case] expected: &str,
+    ) {
+        let repo = Repo::new(test_repo.path());
+        let mut stdout = Vec::new();
+
+        let err = good_git::show_ref(&repo, &options, &mut stdout).unwrap_err();
+        assert_eq!(err.to_string(), expected);
+    }
+
+    #[rstest]
+    fn test_branch_and_tag_list(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        good_git::refs::update_ref(
+            &repo,
+            "refs/heads/longername",
+            "aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb",
+        )
+        .unwrap();
+        good_git::refs::update_ref(
+            &repo,
+            "refs/tags/v2",
+            "ccccccccccccccccccccdddddddddddddddddddd",
+        )
+        .unwrap();
+        let mut stdout = Vec::new();
+
+        good_git::branch_list(&repo, &Default::default(), &mut stdout).unwrap();
+        good_git::tag_list(&repo, &Default::default(), &Default::default(), &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "  longername\n* main\nv1\nv2\n"
+        );
+
+        let mut columns = good_git::column::ColumnOptions {
+            padding: 2,
+            ..Default::default()
+        };
+        columns.mode.parse("always").unwrap();
+        stdout.clear();
+        good_git::branch_list(&repo, &columns, &mut stdout).unwrap();
+        good_git::tag_list(&repo, &Default::default(), &columns, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "  longername  * main\nv1  v2\n"
+        );
+
+        good_git::checkout_detach(&repo, Some("v1"), &mut stdout).unwrap();
+        stdout.clear();
+        good_git::branch_list(&repo, &Default::default(), &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "* (HEAD detached at aaaaaaa)\n  longername\n  main\n"
+        );
+    }
+
+    #[rstest]
+    fn test_tag_list_patterns(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        for tag in ["v1.0", "v1.1", "V2.0", "release/2024"] {
+            good_git::refs::update_ref(
+                &repo,
+                &format!("refs/tags/{tag}"),
+                "aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb",
+            )
+            .unwrap();
+        }
+        let tag_list = |patterns: &[&str], ignore_case: bool| {
+            let options = good_git::TagListOptions {
+                patterns: patterns.iter().map(|p| p.to_string()).collect(),
+                ignore_case,
+            };
+            let mut stdout = Vec::new();
+            good_git::tag_list(&repo, &options, &Default::default(), &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+
+        assert_eq!(tag_list(&["v1.*"], false), "v1.0\nv1.1\n");
+        assert_eq!(tag_list(&["v[0-9].0"], true), "V2.0\nv1.0\n");
+        // `*` matches `/` in tag patterns.
+        assert_eq!(tag_list(&["*4", "v1.1"], false), "release/2024\nv1.1\n");
+        assert_eq!(tag_list(&["v3*"], false), "");
+    }
+
+    #[rstest]
+    fn test_commit_graph(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+
+        let layers = good_git::graph::commit_graph(
+            &repo,
+            &["ccccccccccccccccccccdddddddddddddddddddd".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            layers,
+            vec![
+                vec![good_git::graph::GraphNode {
+                    hash: "ccccccccccccccccccccdddddddddddddddddddd".to_string(),
+                    parents: vec!["aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb".to_string()],
+                    refs: vec!["HEAD".to_string(), "refs/heads/main".to_string()],
+                    author: "Captain Nemo <nemo@nautilus.sea>".to_string(),
+                    committer: "Sherlock Holmes <sherlock@baker.street>".to_string(),
+                    subject: "Here is a better commit".to_string(),
+                }],
+                vec![good_git::graph::GraphNode {
+                    hash: "aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb".to_string(),
+                    parents: vec![],
+                    refs: vec![],
+                    author: "Bob <hello@bob.test>".to_string(),
+                    committer: "Alice <bye@alice.test>".to_string(),
+                    subject: "This is a good commit".to_string(),
+                }],
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_merge_base_is_ancestor(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let is_ancestor = |a: &str, b: &str| good_git::merge_base_is_ancestor(&repo, a, b).unwrap();
+
+        assert!(is_ancestor("aaaa", "main"));
+        assert!(is_ancestor("main", "main"));
+        assert!(!is_ancestor("main", "aaaa"));
+    }
+
+    #[rstest]
+    fn test_name_rev(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let mut stdout = Vec::new();
+
+        // Names from tags are preferred, even if they are further away.
+        good_git::name_rev(
+            &repo,
+            &["cccc".to_string(), "aaaa".to_string()],
+            &mut stdout,
+        )
+        .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "cccc main\naaaa tags/v1\n"
+        );
+
+        std::fs::remove_file(test_repo.path().join(".git/refs/tags/v1")).unwrap();
+        stdout.clear();
+        good_git::name_rev(&repo, &["aaaa".to_string()], &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), "aaaa main~1\n");
+    }
+
+    #[test]
+    fn test_worktree() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = tmpdir.path().canonicalize().unwrap();
+        let repo = init_user_repo(&root.join("main"), "Alice", "alice@example.com");
+        let mut stdout = Vec::new();
+        std::fs::write(repo.root.join("a"), "a\n").unwrap();
+        good_git::add(&repo, &["a".to_string()], &Default::default(), &mut stdout).unwrap();
+        let options = good_git::CommitOptions {
+            message: "One".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut stdout).unwrap();
+        let one = good_git::refs::find_ref(&repo, "HEAD").unwrap();
+
+        // A branch named after the folder is created and checked out.
+        stdout.clear();
+        let path = root.join("feature");
+        good_git::worktree_add(&repo, &path, None, &Default::default(), &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            format!(
+                "Preparing worktree (new branch 'feature')\nHEAD is now at {} One\n",
+                &one[..7]
+            )
+        );
+        assert_eq!(std::fs::read_to_string(path.join("a")).unwrap(), "a\n");
+        assert_eq!(
+            good_git::worktree_add(
+                &repo,
+                &root.join("other"),
+                Some("main"),
+                &Default::default(),
+                &mut stdout
+            )
+            .unwrap_err()
+            .to_string(),
+            format!("'main' is already checked out at '{}'", repo.root.display())
+        );
+
+        // Repo::from_dir follows the `.git` file, and the refs and objects
+        // are shared.
+        let linked = Repo::from_dir(&path).unwrap().unwrap();
+        assert_eq!(linked.git_dir(), repo.git_dir().join("worktrees/feature"));
+        std::fs::write(path.join("b"), "b\n").unwrap();
+        good_git::add(
+            &linked,
+            &["b".to_string()],
+            &Default::default(),
+            &mut stdout,
+        )
+        .unwrap();
+        let options = good_git::CommitOptions {
+            message: "Two".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&linked, &options, &mut stdout).unwrap();
+        let two = good_git::refs::find_ref(&repo, "refs/heads/feature").unwrap();
+        assert_ne!(two, one);
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), one);
+        assert_eq!(
+            good_git::switch_branch(&repo, "feature", &mut stdout)
+                .unwrap_err()
+                .to_string(),
+            format!("'feature' is already checked out at '{}'", path.display())
+        );
+
+        // Worktrees are found by their whole path.
+        let feature = path.to_str().unwrap();
+        assert_eq!(
+            good_git::worktree_lock(&repo, "feature", None)
+                .unwrap_err()
+                .to_string(),
+            "'feature' is not a working tree"
+        );
+        good_git::worktree_lock(&repo, feature, Some("on a usb drive")).unwrap();
+        stdout.clear();
+        good_git::worktree_list(&repo, false, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            format!(
+                "{main:width$} {} [main]\n{feature:width$} {} [feature] locked\n",
+                &one[..7],
+                &two[..7],
+                main = repo.root.display(),
+                feature = path.display(),
+                width = path.display().to_string().len() + 1,
+            )
+        );
+        stdout.clear();
+        good_git::worktree_list(&repo, true, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            format!(
+                "worktree {}\nHEAD {one}\nbranch refs/heads/main\n\n\
+                 worktree {}\nHEAD {two}\nbranch refs/heads/feature\nlocked on a usb drive\n\n",
+                repo.root.display(),
+                path.display(),
+            )
+        );
+
+        assert_eq!(
+            good_git::worktree_remove(&repo, feature, 1)
+                .unwrap_err()
+                .to_string(),
+            "cannot remove a locked working tree, lock reason: on a usb drive\n\
+             use 'remove -f -f' to override or unlock first"
+        );
+        good_git::worktree_unlock(&repo, feature).unwrap();
+        std::fs::write(path.join("c"), "c\n").unwrap();
+        assert_eq!(
+            good_git::worktree_remove(&repo, feature, 0)
+                .unwrap_err()
+                .to_string(),
+            format!("'{feature}' contains modified or untracked files, use --force to delete it")
+        );
+        assert_eq!(
+            good_git::worktree_remove(&repo, repo.root.to_str().unwrap(), 2)
+                .unwrap_err()
+                .to_string(),
+            format!("'{}' is a main working tree", repo.root.display())
+        );
+        good_git::worktree_remove(&repo, feature, 1).unwrap();
+        assert!(!path.exists());
+        assert!(!repo.git_dir().join("worktrees/feature").exists());
+        assert_eq!(
+            good_git::refs::find_ref(&repo, "refs/heads/feature").unwrap(),
+            two
+        );
+
+        // A failed checkout removes the worktree and the branch created for
+        // it.
+        let broken = good_git::object::write_object(
+            &repo,
+            good_git::object::ObjectType::Commit,
+            format!(
+                "tree {}\nauthor A <a@a> 0 +0000\ncommitter A <a@a> 0 +0000\n\nBroken\n",
+                "1".repeat(40)
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let options = good_git::WorktreeAddOptions {
+            new_branch: Some("broken".to_string()),
+            ..Default::default()
+        };
+        good_git::worktree_add(&repo, &path, Some(&broken), &options, &mut Vec::new()).unwrap_err();
+        assert!(!path.exists());
+        assert!(!repo.git_dir().join("worktrees/feature").exists());
+        assert!(good_git::refs::find_ref(&repo, "refs/heads/broken").is_err());
+        assert!(!repo.git_dir().join("logs/refs/heads/broken").exists());
+    }
+
+    #[test]
+    fn test_per_worktree_refs() {
+        use good_git::refs;
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = tmpdir.path().canonicalize().unwrap();
+        let repo = init_user_repo(&root.join("main"), "Alice", "alice@example.com");
+        let options = good_git::CommitOptions {
+            message: "One".to_string(),
+            allow_empty: true,
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+        let one = refs::find_ref(&repo, "HEAD").unwrap();
+        let path = root.join("feature");
+        good_git::worktree_add(&repo, &path, None, &Default::default(), &mut Vec::new()).unwrap();
+        let linked = Repo::from_dir(&path).unwrap().unwrap();
+
+        // Bisect state and refs/worktree/ belong to each worktree.
+        refs::update_ref(&linked, "refs/bisect/bad", &one).unwrap();
+        refs::update_ref(&repo, "refs/worktree/mark", &one).unwrap();
+        assert!(linked.git_dir().join("refs/bisect/bad").is_file());
+        assert!(refs::find_ref(&repo, "refs/bisect/bad").is_err());
+        assert!(refs::find_ref(&linked, "refs/worktree/mark").is_err());
+        let names = |repo: &Repo| -> Vec<String> {
+            refs::list(repo)
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect()
+        };
+        assert_eq!(
+            names(&linked),
+            ["refs/bisect/bad", "refs/heads/feature", "refs/heads/main"]
+        );
+        assert_eq!(
+            names(&repo),
+            [
+                "refs/heads/feature",
+                "refs/heads/main",
+                "refs/worktree/mark"
+            ]
+        );
+
+        // The per-worktree refs of other worktrees can be read.
+        assert_eq!(
+            refs::find_ref(&repo, "worktrees/feature/refs/bisect/bad").unwrap(),
+            one
+        );
+        assert_eq!(
+            refs::find_ref(&linked, "main-worktree/refs/worktree/mark").unwrap(),
+            one
+        );
+        assert_eq!(
+            refs::expand(&linked, "main-worktree/HEAD").as_deref(),
+            Some("main-worktree/HEAD")
+        );
+        assert_eq!(refs::find_ref(&linked, "main-worktree/HEAD").unwrap(), one);
+    }
+
+    #[test]
+    fn test_rev_list_cherry_pick() {
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        let mut stdout = Vec::new();
+        let commit_file = |content: &str, message: &str, stdout: &mut Vec<u8>| {
+            std::fs::write(repo.root.join("f"), content).unwrap();
+            good_git::add(&repo, &["f".to_string()], &Default::default(), stdout).unwrap();
+            let options = good_git::CommitOptions {
+                message: message.to_string(),
+                ..Default::default()
+            };
+            good_git::commit(&repo, &options, stdout).unwrap();
+            good_git::refs::find_ref(&repo, "HEAD").unwrap()
+        };
+        let base = commit_file("1\n2\n3\n4\n5\n6\n7\n8\n9\n", "Base", &mut stdout);
+        good_git::refs::update_ref(&repo, "refs/heads/side", &base).unwrap();
+        let two = commit_file("1\ntwo\n3\n4\n5\n6\n7\n8\n9\n", "Two", &mut stdout);
+        let picked = commit_file("1\ntwo\n3\n4\n5\n6\n7\n8\nnine\n", "Nine", &mut stdout);
+        good_git::switch_branch(&repo, "side", &mut stdout).unwrap();
+        let nine = commit_file("1\n2\n3\n4\n5\n6\n7\n8\nnine\n", "Nine", &mut stdout);
+
+        let rev_list = |options: &good_git::RevListOptions| {
+            let mut stdout = Vec::new();
+            good_git::rev_list(&repo, &["main...side".to_string()], options, &mut stdout).unwrap();
+            let mut lines: Vec<String> = String::from_utf8(stdout)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect();
+            lines.sort();
+            lines
+        };
+        let mut expected = vec![format!("<{two}"), format!("<{picked}"), format!(">{nine}")];
+        expected.sort();
+        let options = good_git::RevListOptions {
+            left_right: true,
+            ..Default::default()
+        };
+        assert_eq!(rev_list(&options), expected);
+        let options = good_git::RevListOptions {
+            left_right: true,
+            cherry_pick: true,
+            ..Default::default()
+        };
+        assert_eq!(rev_list(&options), [format!("<{two}")]);
+        let options = good_git::RevListOptions {
+            cherry_pick: true,
+            ..Default::default()
+        };
+        assert_eq!(rev_list(&options), [two.clone()]);
+
+        let options = good_git::LogOptions {
+            oneline: true,
+            left_right: true,
+            cherry_pick: true,
+            ..Default::default()
+        };
+        stdout.clear();
+        good_git::log_with_options(&repo, "side...main", &options, &mut stdout).unwrap();
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(stdout.starts_with(&format!("> {} - Two - ", &two[..7])));
+        assert_eq!(stdout.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_push() {
+        use good_git::object::Object;
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = tmpdir.path().canonicalize().unwrap();
+        let remote = Repo::bare(&root.join("remote.git"));
+        good_git::init_repo(&remote, "main").unwrap();
+        let repo = init_user_repo(&root.join("local"), "Alice", "alice@example.com");
+        let config_path = repo.git_dir().join("config");
+        good_git::config::set_value(&config_path, "remote.origin.url", "../remote.git").unwrap();
+        let mut stdout = Vec::new();
+        let commit = |message: &str, stdout: &mut Vec<u8>| {
+            let options = good_git::CommitOptions {
+                message: message.to_string(),
+                allow_empty: true,
+                ..Default::default()
+            };
+            good_git::commit(&repo, &options, stdout).unwrap();
+            good_git::refs::find_ref(&repo, "HEAD").unwrap()
+        };
+        let one = commit("One", &mut stdout);
+        let push = |options: &good_git::PushOptions, stdout: &mut Vec<u8>| {
+            stdout.clear();
+            good_git::push(&repo, None, &[], options, stdout)
+        };
+
+        // With push.default=simple, the branch needs an upstream.
+        let error = push(&Default::default(), &mut stdout).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("The current branch main has no upstream branch."));
+
+        // With push.autoSetupRemote, it's set.
+        let mut config = std::fs::read_to_string(&config_path).unwrap();
+        config.push_str("[push]\n\tautoSetupRemote = true\n");
+        std::fs::write(&config_path, config).unwrap();
+        push(&Default::default(), &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "To ../remote.git\n \
+             * [new branch]      main -> main\n\
+             branch 'main' set up to track 'origin/main'.\n"
+        );
+        assert_eq!(
+            good_git::refs::find_ref(&remote, "refs/heads/main").unwrap(),
+            one
+        );
+        assert_eq!(
+            good_git::refs::find_ref(&repo, "refs/remotes/origin/main").unwrap(),
+            one
+        );
+        let config = std::fs::read_to_string(&config_path).unwrap();
+        assert!(
+            config.ends_with("[branch \"main\"]\n\tremote = origin\n\tmerge = refs/heads/main\n")
+        );
+        let Object::Commit(pushed) = Object::from_hash(&remote, &one).unwrap() else {
+            panic!("{one} is not a commit");
+        };
+        assert!(matches!(
+            Object::from_hash(&remote, &pushed.tree).unwrap(),
+            Object::Tree(_)
+        ));
+
+        push(&Default::default(), &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "Everything up-to-date\n"
+        );
+
+        let two = commit("Two", &mut stdout);
+        push(&Default::default(), &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            format!(
+                "To ../remote.git\n   {}..{}  main -> main\n",
+                &one[..7],
+                &two[..7]
+            )
+        );
+
+        // Rewriting history requires forcing the push.
+        good_git::refs::update_ref(&repo, "refs/heads/main", &one).unwrap();
+        let three = commit("Three", &mut stdout);
+        let error = push(&Default::default(), &mut stdout).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "failed to push some refs to '../remote.git'"
+        );
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "To ../remote.git\n ! [rejected]        main -> main (non-fast-forward)\n"
+        );
+        assert_eq!(
+            good_git::refs::find_ref(&remote, "refs/heads/main").unwrap(),
+            two
+        );
+        let options = good_git::PushOptions {
+            force: true,
+            ..Default::default()
+        };
+        push(&options, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            format!(
+                "To ../remote.git\n + {}...{} main -> main (forced update)\n",
+                &two[..7],
+                &three[..7]
+            )
+        );
+        assert_eq!(
+            good_git::refs::find_ref(&remote, "refs/heads/main").unwrap(),
+            three
+        );
+
+        // Explicit refspecs can push to another branch.
+        stdout.clear();
+        let refspecs = ["main:other".to_string()];
+        good_git::push(
+            &repo,
+            Some("origin"),
+            &refspecs,
+            &Default::default(),
+            &mut stdout,
+        )
+        .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "To ../remote.git\n * [new branch]      main -> other\n"
+        );
+        assert_eq!(
+            good_git::refs::find_ref(&remote, "refs/heads/other").unwrap(),
+            three
+        );
+    }
+
+    #[test]
+    fn test_transfer_restrictions() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = tmpdir.path().canonicalize().unwrap();
+        let remote = Repo::bare(&root.join("remote.git"));
+        good_git::init_repo(&remote, "main").unwrap();
+        std::fs::write(
+            remote.git_dir().join("config"),
+            "[transfer]\n\thideRefs = refs/heads/hidden\n\
+             [receive]\n\tmaxInputSize = 1\n",
+        )
+        .unwrap();
+        let repo = init_user_repo(&root.join("local"), "Alice", "alice@example.com");
+        let config_path = repo.git_dir().join("config");
+        good_git::config::set_value(&config_path, "remote.origin.url", "../remote.git").unwrap();
+        let options = good_git::CommitOptions {
+            message: "One".to_string(),
+            allow_empty: true,
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+        let one = good_git::refs::find_ref(&repo, "HEAD").unwrap();
+        let push = |refspec: &str, stdout: &mut Vec<u8>| {
+            stdout.clear();
+            let refspecs = [refspec.to_string()];
+            good_git::push(&repo, None, &refspecs, &Default::default(), stdout)
+        };
+        let mut stdout = Vec::new();
+
+        // The objects are bigger than receive.maxInputSize.
+        assert!(push("main", &mut stdout).is_err());
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "remote: fatal: pack exceeds maximum allowed size\n\
+             To ../remote.git\n ! [remote rejected] main -> main (unpacker error)\n"
+        );
+        assert!(good_git::refs::find_ref(&remote, "refs/heads/main").is_err());
+
+        std::fs::write(
+            remote.git_dir().join("config"),
+            "[transfer]\n\thideRefs = refs/heads/hidden\n",
+        )
+        .unwrap();
+        assert!(push("main:hidden/one", &mut stdout).is_err());
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "To ../remote.git\n \
+             ! [remote rejected] main -> hidden/one (deny updating a hidden ref)\n"
+        );
+        push("main", &mut stdout).unwrap();
+        good_git::refs::update_ref(&remote, "refs/heads/hidden/one", &one).unwrap();
+
+        // Hidden refs aren't fetched.
+        stdout.clear();
+        good_git::fetch(&repo, None, &[], &Default::default(), &mut stdout).unwrap();
+        assert!(good_git::refs::find_ref(&repo, "refs/remotes/origin/main").is_ok());
+        assert!(good_git::refs::find_ref(&repo, "refs/remotes/origin/hidden/one").is_err());
+        let refspecs = ["hidden/one".to_string()];
+        let error =
+            good_git::fetch(&repo, None, &refspecs, &Default::default(), &mut stdout).unwrap_err();
+        assert_eq!(error.to_string(), "couldn't find remote ref hidden/one");
+
+        let mut config = std::fs::read_to_string(&config_path).unwrap();
+        config.push_str("[protocol]\n\tallow = never\n");
+        std::fs::write(&config_path, config).unwrap();
+        let error =
+            good_git::fetch(&repo, None, &[], &Default::default(), &mut stdout).unwrap_err();
+        assert_eq!(error.to_string(), "transport 'file' not allowed");
+    }
+
+    #[test]
+    fn test_fetch_shallow() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = tmpdir.path().canonicalize().unwrap();
+        let remote = init_user_repo(&root.join("remote"), "Alice", "alice@example.com");
+        let mut commits = vec![];
+        for message in ["One", "Two", "Three", "Four", "Five"] {
+            let options = good_git::CommitOptions {
+                message: message.to_string(),
+                allow_empty: true,
+                ..Default::default()
+            };
+            good_git::commit(&remote, &options, &mut Vec::new()).unwrap();
+            commits.push(good_git::refs::find_ref(&remote, "HEAD").unwrap());
+        }
+        good_git::refs::update_ref(&remote, "refs/tags/v2", &commits[1]).unwrap();
+        let repo = init_user_repo(&root.join("local"), "Alice", "alice@example.com");
+        let config = repo.git_dir().join("config");
+        good_git::config::set_value(&config, "remote.origin.url", "../remote").unwrap();
+        let mut stdout = Vec::new();
+        let fetch = |options: &good_git::FetchOptions, stdout: &mut Vec<u8>| {
+            stdout.clear();
+            good_git::fetch(&repo, None, &[], options, stdout)
+        };
+        let shallow = || std::fs::read_to_string(repo.git_dir().join("shallow")).ok();
+        let history = || {
+            let mut stdout = Vec::new();
+            good_git::log(&repo, "origin/main", &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap().lines().count()
+        };
+
+        let error = fetch(
+            &good_git::FetchOptions {
+                unshallow: true,
+                ..Default::default()
+            },
+            &mut stdout,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "--unshallow on a complete repository does not make sense"
+        );
+
+        fetch(
+            &good_git::FetchOptions {
+                depth: Some(1),
+                ..Default::default()
+            },
+            &mut stdout,
+        )
+        .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "From ../remote\n * [new branch]      main       -> origin/main\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.git_dir().join("FETCH_HEAD")).unwrap(),
+            format!(
+                "{}\tnot-for-merge\tbranch 'main' of ../remote\n",
+                commits[4]
+            )
+        );
+        assert_eq!(shallow(), Some(format!("{}\n", commits[4])));
+        assert_eq!(history(), 1);
+
+        // Fetching without options keeps the history shallow.
+        fetch(&Default::default(), &mut stdout).unwrap();
+        assert_eq!(history(), 1);
+
+        fetch(
+            &good_git::FetchOptions {
+                deepen: Some(1),
+                ..Default::default()
+            },
+            &mut stdout,
+        )
+        .unwrap();
+        assert_eq!(shallow(), Some(format!("{}\n", commits[3])));
+        assert_eq!(history(), 2);
+
+        // The history is deepened from the shallow commits, below the
+        // fetched commit.
+        fetch(
+            &good_git::FetchOptions {
+                shallow_exclude: vec!["v2".to_string()],
+                ..Default::default()
+            },
+            &mut stdout,
+        )
+        .unwrap();
+        assert_eq!(shallow(), Some(format!("{}\n", commits[2])));
+        assert_eq!(history(), 3);
+
+        fetch(
+            &good_git::FetchOptions {
+                unshallow: true,
+                ..Default::default()
+            },
+            &mut stdout,
+        )
+        .unwrap();
+        assert_eq!(shallow(), None);
+        assert_eq!(history(), 5);
+
+        // So is it with a date, and without an identity.
+        let repo = Repo::new(&root.join("since"));
+        good_git::init_repo(&repo, "main").unwrap();
+        std::fs::write(
+            repo.git_dir().join("config"),
+            "[remote \"origin\"]\n\turl = ../remote\n",
+        )
+        .unwrap();
+        let fetch = |options: &good_git::FetchOptions| {
+            good_git::fetch(&repo, None, &[], options, &mut Vec::new()).unwrap()
+        };
+        fetch(&good_git::FetchOptions {
+            depth: Some(1),
+            ..Default::default()
+        });
+        fetch(&good_git::FetchOptions {
+            deepen: Some(1),
+            ..Default::default()
+        });
+        fetch(&good_git::FetchOptions {
+            shallow_since: Some("2005-04-07".to_string()),
+            ..Default::default()
+        });
+        assert!(!repo.git_dir().join("shallow").exists());
+
+        // The HEAD of a URL is fetched to be merged.
+        let url = root.join("remote").to_str().unwrap().to_string();
+        good_git::fetch(&repo, Some(&url), &[], &Default::default(), &mut Vec::new()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(repo.git_dir().join("FETCH_HEAD")).unwrap(),
+            format!("{}\t\t{url}\n", commits[4])
+        );
+    }
+
+    #[test]
+    fn test_fsck() {
+        use good_git::object::{write_object, File, ObjectType};
+
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        let mut stdout = Vec::new();
+        std::fs::write(repo.root.join("a"), "a\n").unwrap();
+        std::fs::write(repo.root.join("b"), "b\n").unwrap();
+        let paths = ["a".to_string(), "b".to_string()];
+        good_git::add(&repo, &paths, &Default::default(), &mut stdout).unwrap();
+        let options = good_git::CommitOptions {
+            message: "One".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut stdout).unwrap();
+
+        // The blob of the dangling tree is unreachable but not dangling.
+        let blob = write_object(&repo, ObjectType::Blob, b"dangling\n").unwrap();
+        let tree_blob = write_object(&repo, ObjectType::Blob, b"in tree\n").unwrap();
+        let tree = Tree::new(vec![File {
+            mode: "100644".to_string(),
+            name: "t".to_string(),
+            hash: tree_blob,
+        }]);
+        let tree = write_object(&repo, ObjectType::Tree, &tree.to_bytes()).unwrap();
+        let fsck = |options: &good_git::FsckOptions| {
+            let mut stdout = Vec::new();
+            let code = good_git::fsck(&repo, options, &mut stdout, &mut Vec::new()).unwrap();
+            (code, String::from_utf8(stdout).unwrap())
+        };
+        // Sorted by hash.
+        let expected = format!("dangling tree {tree}\ndangling blob {blob}\n");
+        assert_eq!(fsck(&Default::default()), (0, expected.clone()));
+        let connectivity_only = good_git::FsckOptions {
+            connectivity_only: true,
+            ..Default::default()
+        };
+        assert_eq!(fsck(&connectivity_only), (0, expected));
+        let options = good_git::FsckOptions {
+            unreachable: true,
+            ..Default::default()
+        };
+        assert_eq!(fsck(&options).1.lines().count(), 3);
+
+        // The blob in the index and in the commit is missing.
+        let a = "78981922613b2afb6025042ff6bd878ac1994e85";
+        let objects = repo.git_dir().join("objects");
+        std::fs::remove_file(objects.join(&a[..2]).join(&a[2..])).unwrap();
+        let report = good_git::fsck::check(&repo, true).unwrap();
+        assert_eq!(report.exit_code(), 2);
+        assert_eq!(
+            report.missing.into_iter().collect::<Vec<_>>(),
+            [(a.to_string(), ObjectType::Blob)]
+        );
+
+        // Reachable blobs that are corrupt are only found when content is
+        // validated.
+        let b = "61780798228d17af2d34fce4cfbdf35556832472";
+        let path = objects.join(&b[..2]).join(&b[2..]);
+        std::fs::write(&path, "garbage").unwrap();
+        assert_eq!(good_git::fsck::check(&repo, true).unwrap().exit_code(), 2);
+        let report = good_git::fsck::check(&repo, false).unwrap();
+        assert_eq!(
+            report.errors,
+            [format!(
+                "{b}: object corrupt or missing: {}",
+                path.display()
+            )]
+        );
+        assert_eq!(report.missing.len(), 2);
+        assert_eq!(report.exit_code(), 3);
+        let mut stderr = Vec::new();
+        let code =
+            good_git::fsck(&repo, &Default::default(), &mut Vec::new(), &mut stderr).unwrap();
+        assert_eq!(code, 3);
+        assert_eq!(
+            String::from_utf8(stderr).unwrap(),
+            format!(
+                "error: {b}: object corrupt or missing: {}\n",
+                path.display()
+            )
+        );
+    }
+
+    #[rstest]
+    fn test_rev_list(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let rev_list = |revs: &[&str], options: &good_git::RevListOptions| {
+            let revs: Vec<String> = revs.iter().map(|rev| rev.to_string()).collect();
+            let mut stdout = Vec::new();
+            good_git::rev_list(&repo, &revs, options, &mut stdout)
+                .map(|()| String::from_utf8(stdout).unwrap())
+        };
+        let objects = |missing| good_git::RevListOptions {
+            objects: true,
+            missing,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            rev_list(&["main"], &Default::default()).unwrap(),
+            "ccccccccccccccccccccdddddddddddddddddddd\n\
+             aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb\n"
+        );
+        assert_eq!(
+            rev_list(&["main", "^v1"], &Default::default()).unwrap(),
+            "ccccccccccccccccccccdddddddddddddddddddd\n"
+        );
+        assert_eq!(
+            rev_list(&["v1"], &objects(Default::default())).unwrap(),
+            "aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb\n\
+             eeeeeeeeeeeeeeeeeeeeffffffffffffffffffff v1\n\
+             99887766554433221100aabbccddeeff00112233 \n\
+             d670460b4b4aece5915caf5c68d12f560a9fe3e4 test.txt\n\
+             1234567890abcdef1234567890abcdef12345678 more.txt\n"
+        );
+
+        std::fs::remove_file(
+            test_repo
+                .path()
+                .join(".git/objects/12/34567890abcdef1234567890abcdef12345678"),
+        )
+        .unwrap();
+        assert_eq!(
+            rev_list(&["main"], &objects(Default::default()))
+                .unwrap_err()
+                .to_string(),
+            "missing blob object '1234567890abcdef1234567890abcdef12345678'"
+        );
+        let commits_and_tree = "ccccccccccccccccccccdddddddddddddddddddd\n\
+                                aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb\n\
+                                99887766554433221100aabbccddeeff00112233 \n\
+                                d670460b4b4aece5915caf5c68d12f560a9fe3e4 test.txt\n";
+        assert_eq!(
+            rev_list(&["main"], &objects(Missing::AllowAny)).unwrap(),
+            commits_and_tree
+        );
+        assert_eq!(
+            rev_list(&["main"], &objects(Missing::Print)).unwrap(),
+            format!("{commits_and_tree}?1234567890abcdef1234567890abcdef12345678\n")
+        );
+
+        // Missing commits end the walk, and are listed too.
+        std::fs::remove_file(
+            test_repo
+                .path()
+                .join(".git/objects/aa/aaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb"),
+        )
+        .unwrap();
+        assert_eq!(
+            rev_list(&["main"], &Default::default())
+                .unwrap_err()
+                .to_string(),
+            "Failed to traverse parents of commit ccccccccccccccccccccdddddddddddddddddddd"
+        );
+        let options = good_git::RevListOptions {
+            all: true,
+            missing: Missing::Print,
+            ..Default::default()
+        };
+        assert_eq!(
+            rev_list(&[], &options).unwrap(),
+            "ccccccccccccccccccccdddddddddddddddddddd\n\
+             ?aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb\n"
+        );
+    }
+
+    #[rstest]
+    fn test_abbreviate(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+
+        assert_eq!(
+            good_git::object::Abbreviator::from_config(&repo)
+                .unwrap()
+                .abbreviate("d670460b4b4aece5915caf5c68d12f560a9fe3e4")
+                .unwrap(),
+            "d670460"
+        );
+
+        // An object sharing the first 10 characters makes 11 characters necessary.
+        create_blob(
+            test_repo.path().to_path_buf(),
+            "d670460b4b000000000000000000000000000000",
+            "other",
+        );
+        assert_eq!(
+            good_git::object::Abbreviator::from_config(&repo)
+                .unwrap()
+                .abbreviate("d670460b4b4aece5915caf5c68d12f560a9fe3e4")
+                .unwrap(),
+            "d670460b4b4"
+        );
+
+        std::fs::write(repo.git_dir().join("config"), "[core]\n\tabbrev = 12\n").unwrap();
+        assert_eq!(
+            good_git::object::Abbreviator::from_config(&repo)
+                .unwrap()
+                .abbreviate("d670460b4b4aece5915caf5c68d12f560a9fe3e4")
+                .unwrap(),
+            "d670460b4b4a"
+        );
+    }
+
+    #[rstest]
+    fn test_checkout_detach(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let mut stdout = Vec::new();
+
+        good_git::checkout_detach(&repo, Some("main"), &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "HEAD is now at ccccccc Here is a better commit\n"
+        );
+        assert_eq!(
+            good_git::refs::head(&repo).unwrap(),
+            good_git::refs::Head::Detached("ccccccccccccccccccccdddddddddddddddddddd".to_string())
+        );
+
+        // Log and rev resolution keep working with a detached HEAD.
+        stdout.clear();
+        good_git::log(&repo, "HEAD", &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap().lines().count(), 2);
+    }
+
+    #[rstest]
+    fn test_checkout_orphan(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::write(
+            repo.git_dir().join("config"),
+            "[user]\n\tname = Alice\n\temail = alice@example.com\n",
+        )
+        .unwrap();
+        std::fs::create_dir(repo.root.join("dir")).unwrap();
+        std::fs::write(repo.root.join("dir/a.txt"), "a\n").unwrap();
+        good_git::index::Index::default().write(&repo).unwrap();
+        let mut stdout = Vec::new();
+        good_git::add(
+            &repo,
+            &["dir".to_string()],
+            &Default::default(),
+            &mut stdout,
+        )
+        .unwrap();
+        let options = good_git::CommitOptions {
+            message: "Add dir".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut stdout).unwrap();
+        std::fs::write(repo.root.join("untracked.txt"), "untracked\n").unwrap();
+        stdout.clear();
+
+        assert_eq!(
+            good_git::checkout_orphan(&repo, "main", false, &mut stdout)
+                .unwrap_err()
+                .to_string(),
+            "A branch named 'main' already exists"
+        );
+        good_git::checkout_orphan(&repo, "gh-pages", false, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "Switched to a new branch 'gh-pages'\n"
+        );
+        assert_eq!(
+            good_git::refs::head(&repo).unwrap(),
+            good_git::refs::Head::Branch("refs/heads/gh-pages".to_string())
+        );
+        assert!(good_git::index::Index::read(&repo)
+            .unwrap()
+            .entries
+            .is_empty());
+        assert!(!repo.root.join("dir").exists());
+        assert!(repo.root.join("untracked.txt").exists());
+
+        // The first commit on the branch has no parents.
+        std::fs::write(repo.root.join("index.html"), "<html>\n").unwrap();
+        good_git::add(
+            &repo,
+            &["index.html".to_string()],
+            &Default::default(),
+            &mut stdout,
+        )
+        .unwrap();
+        stdout.clear();
+        let options = good_git::CommitOptions {
+            message: "Publish".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut stdout).unwrap();
+        assert!(std::str::from_utf8(&stdout)
+            .unwrap()
+            .starts_with("[gh-pages (root-commit) "));
+        let good_git::object::Object::Commit(head) =
+            good_git::object::Object::from_rev(&repo, "gh-pages").unwrap()
+        else {
+            panic!("gh-pages is not a commit");
+        };
+        assert!(head.parents.is_empty());
+
+        // With `keep`, the index and the working tree are left as they are.
+        good_git::checkout_orphan(&repo, "other", true, &mut stdout).unwrap();
+        assert_eq!(
+            good_git::index::Index::read(&repo).unwrap().entries.len(),
+            1
+        );
+        assert!(repo.root.join("index.html").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_checkout_files_in_the_way() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = init_user_repo(&tmpdir.path().join("repo"), "Alice", "alice@example.com");
+        let outside = tmpdir.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        let mut stdout = Vec::new();
+        std::fs::write(repo.root.join("d"), "d\n").unwrap();
+        std::os::unix::fs::symlink(&outside, repo.root.join("l")).unwrap();
+        commit_all(&repo, "One", &mut stdout);
+        let main = good_git::refs::find_ref(&repo, "refs/heads/main").unwrap();
+
+        // `other` replaces the file d and the symlink l with directories.
+        good_git::refs::update_ref(&repo, "refs/heads/other", &main).unwrap();
+        good_git::switch_branch(&repo, "other", &mut stdout).unwrap();
+        std::fs::remove_file(repo.root.join("d")).unwrap();
+        std::fs::remove_file(repo.root.join("l")).unwrap();
+        for dir in ["d", "l"] {
+            std::fs::create_dir(repo.root.join(dir)).unwrap();
+            std::fs::write(repo.root.join(dir).join("x"), "x\n").unwrap();
+        }
+        commit_all(&repo, "Two", &mut stdout);
+
+        good_git::switch_branch(&repo, "main", &mut stdout).unwrap();
+        assert_eq!(std::fs::read_to_string(repo.root.join("d")).unwrap(), "d\n");
+        assert!(repo.root.join("l").is_symlink());
+        good_git::switch_branch(&repo, "other", &mut stdout).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("d/x")).unwrap(),
+            "x\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("l/x")).unwrap(),
+            "x\n"
+        );
+        assert!(!outside.join("x").exists());
+
+        // Untracked files in the directories would be lost.
+        std::fs::write(repo.root.join("d/y"), "y\n").unwrap();
+        assert_eq!(
+            good_git::switch_branch(&repo, "main", &mut stdout)
+                .unwrap_err()
+                .to_string(),
+            "The following untracked working tree files would be overwritten by checkout:\n\
+             \td\n\
+             Please move or remove them before you switch branches.\n\
+             Aborting"
+        );
+        std::fs::remove_file(repo.root.join("d/y")).unwrap();
+        good_git::switch_branch(&repo, "main", &mut stdout).unwrap();
+
+        // Checking out paths replaces the file and the symlink in the way.
+        good_git::checkout_paths(
+            &repo,
+            Some("other"),
+            &["d/x".to_string(), "l/x".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("d/x")).unwrap(),
+            "x\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("l/x")).unwrap(),
+            "x\n"
+        );
+        assert!(!outside.join("x").exists());
+        let paths: Vec<String> = good_git::index::Index::read(&repo)
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert_eq!(paths, ["d/x", "l/x"]);
+    }
+
+    #[test]
+    fn test_checkout_branch() {
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        let mut stdout = Vec::new();
+        for name in ["a", "b", "c"] {
+            std::fs::write(repo.root.join(name), format!("{name}\n")).unwrap();
+        }
+        commit_all(&repo, "One", &mut stdout);
+        let main = good_git::refs::find_ref(&repo, "refs/heads/main").unwrap();
+
+        // `other` modifies a, removes c and adds d/x.
+        good_git::refs::update_ref(&repo, "refs/heads/other", &main).unwrap();
+        good_git::switch_branch(&repo, "other", &mut stdout).unwrap();
+        std::fs::write(repo.root.join("a"), "a2\n").unwrap();
+        std::fs::remove_file(repo.root.join("c")).unwrap();
+        std::fs::create_dir(repo.root.join("d")).unwrap();
+        std::fs::write(repo.root.join("d/x"), "x\n").unwrap();
+        commit_all(&repo, "Two", &mut stdout);
+        let other = good_git::refs::find_ref(&repo, "refs/heads/other").unwrap();
+
+        // Unrelated local changes are carried over.
+        std::fs::write(repo.root.join("b"), "b2\n").unwrap();
+        stdout.clear();
+        good_git::checkout(&repo, "main", &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "M\tb\nSwitched to branch 'main'\n"
+        );
+        assert_eq!(
+            good_git::refs::head(&repo).unwrap(),
+            good_git::refs::Head::Branch("refs/heads/main".to_string())
+        );
+        assert_eq!(std::fs::read_to_string(repo.root.join("a")).unwrap(), "a\n");
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("b")).unwrap(),
+            "b2\n"
+        );
+        assert_eq!(std::fs::read_to_string(repo.root.join("c")).unwrap(), "c\n");
+        assert!(!repo.root.join("d").exists());
+        let paths: Vec<String> = good_git::index::Index::read(&repo)
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert_eq!(paths, ["a", "b", "c"]);
+
+        stdout.clear();
+        good_git::checkout(&repo, "main", &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "M\tb\nAlready on 'main'\n"
+        );
+
+        // Local changes to files that differ between the branches are kept.
+        std::fs::write(repo.root.join("a"), "a3\n").unwrap();
+        assert_eq!(
+            good_git::checkout(&repo, "other", &mut stdout)
+                .unwrap_err()
+                .to_string(),
+            "Your local changes to the following files would be overwritten by checkout:\n\
+             \ta\n\
+             Please commit your changes or stash them before you switch branches.\n\
+             Aborting"
+        );
+        std::fs::write(repo.root.join("a"), "a\n").unwrap();
+        std::fs::create_dir(repo.root.join("d")).unwrap();
+        std::fs::write(repo.root.join("d/x"), "untracked\n").unwrap();
+        assert_eq!(
+            good_git::switch_branch(&repo, "other", &mut stdout)
+                .unwrap_err()
+                .to_string(),
+            "The following untracked working tree files would be overwritten by checkout:\n\
+             \td/x\n\
+             Please move or remove them before you switch branches.\n\
+             Aborting"
+        );
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), main);
+        std::fs::remove_dir_all(repo.root.join("d")).unwrap();
+        // So are untracked files where directories are needed.
+        std::fs::write(repo.root.join("d"), "untracked\n").unwrap();
+        assert_eq!(
+            good_git::switch_branch(&repo, "other", &mut stdout)
+                .unwrap_err()
+                .to_string(),
+            "The following untracked working tree files would be overwritten by checkout:\n\
+             \td\n\
+             Please move or remove them before you switch branches.\n\
+             Aborting"
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("d")).unwrap(),
+            "untracked\n"
+        );
+        std::fs::remove_file(repo.root.join("d")).unwrap();
+
+        good_git::switch_branch(&repo, "other", &mut stdout).unwrap();
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), other);
+        assert!(!repo.root.join("c").exists());
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("d/x")).unwrap(),
+            "x\n"
+        );
+        assert_eq!(
+            good_git::switch_branch(&repo, "missing", &mut stdout)
+                .unwrap_err()
+                .to_string(),
+            "invalid reference: missing"
+        );
+
+        // A commit that isn't a branch detaches HEAD.
+        stdout.clear();
+        good_git::checkout(&repo, &main, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            format!("M\tb\nHEAD is now at {} One\n", &main[..7])
+        );
+        assert_eq!(
+            good_git::refs::head(&repo).unwrap(),
+            good_git::refs::Head::Detached(main.clone())
+        );
+        assert!(std::fs::read_to_string(repo.git_dir().join("logs/HEAD"))
+            .unwrap()
+            .ends_with(&format!("\tcheckout: moving from other to {main}\n")));
+    }
+
+    #[test]
+    fn test_recover() {
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        let mut stdout = Vec::new();
+        for name in ["a", "b", "c"] {
+            std::fs::write(repo.root.join(name), format!("{name}\n")).unwrap();
+        }
+        commit_all(&repo, "One", &mut stdout);
+        let main = good_git::refs::find_ref(&repo, "refs/heads/main").unwrap();
+        good_git::refs::update_ref(&repo, "refs/heads/other", &main).unwrap();
+        good_git::switch_branch(&repo, "other", &mut stdout).unwrap();
+        std::fs::write(repo.root.join("a"), "a2\n").unwrap();
+        std::fs::remove_file(repo.root.join("c")).unwrap();
+        commit_all(&repo, "Two", &mut stdout);
+        good_git::switch_branch(&repo, "main", &mut stdout).unwrap();
+        let tree = |rev: &str| {
+            let hash = good_git::object::Object::resolve_rev(&repo, rev).unwrap();
+            match good_git::object::Object::from_hash(&repo, &hash).unwrap() {
+                good_git::object::Object::Commit(commit) => commit.tree,
+                _ => unreachable!(),
+            }
+        };
+        let journal = good_git::journal::Journal {
+            operation: "checkout other".to_string(),
+            old_tree: Some(tree("main")),
+            new_tree: tree("other"),
+            refs: vec![good_git::journal::RefChange {
+                name: "HEAD".to_string(),
+                old: Some("ref: refs/heads/main".to_string()),
+                new: "ref: refs/heads/other".to_string(),
+            }],
+        };
+
+        stdout.clear();
+        good_git::recover(&repo, None, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "No interrupted operation to recover\n"
+        );
+
+        // A checkout interrupted after writing `a`, with a local change to
+        // `b` and the lock of the index left behind.
+        std::fs::write(repo.root.join("b"), "b2\n").unwrap();
+        journal.begin(&repo).unwrap();
+        std::fs::write(repo.root.join("a"), "a2\n").unwrap();
+        std::fs::write(repo.git_dir().join("index.lock"), "").unwrap();
+        stdout.clear();
+        good_git::recover(&repo, None, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "An interrupted 'checkout other' was found\n  \
+             (use \"good_git recover --continue\" to finish it or \"good_git recover --abort\" to undo it)\n"
+        );
+        assert_eq!(
+            good_git::checkout(&repo, "main", &mut stdout)
+                .unwrap_err()
+                .to_string(),
+            "An interrupted 'checkout other' was found\n\
+             Use \"good_git recover --continue\" to finish it or \"good_git recover --abort\" to undo it"
+        );
+
+        // A recent lock may belong to a running process.
+        let lock_path = repo.git_dir().join("index.lock");
+        assert_eq!(
+            good_git::recover(
+                &repo,
+                Some(good_git::journal::Recovery::Forward),
+                &mut stdout,
+            )
+            .unwrap_err()
+            .to_string(),
+            format!(
+                "Unable to recover: '{}' exists\n\
+                 Another git process seems to be running in this repository.\n\
+                 If it's not, remove the file manually and try again.",
+                lock_path.display()
+            )
+        );
+        let lock = std::fs::File::options()
+            .write(true)
+            .open(&lock_path)
+            .unwrap();
+        lock.set_modified(std::time::SystemTime::now() - good_git::journal::STALE_LOCK_AGE)
+            .unwrap();
+
+        stdout.clear();
+        good_git::recover(
+            &repo,
+            Some(good_git::journal::Recovery::Forward),
+            &mut stdout,
+        )
+        .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "Finished 'checkout other'\n"
+        );
+        assert_eq!(
+            good_git::refs::head(&repo).unwrap(),
+            good_git::refs::Head::Branch("refs/heads/other".to_string())
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("a")).unwrap(),
+            "a2\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("b")).unwrap(),
+            "b2\n"
+        );
+        assert!(!repo.root.join("c").exists());
+        assert!(!repo.git_dir().join("JOURNAL").exists());
+        assert!(!repo.git_dir().join("JOURNAL_INDEX").exists());
+        stdout.clear();
+        let options = good_git::StatusOptions {
+            format: good_git::StatusFormat::PorcelainV1,
+            ..Default::default()
+        };
+        good_git::status(&repo, &options, &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), " M b\n");
+
+        // The same checkout interrupted after removing `c`, rolled back.
+        good_git::switch_branch(&repo, "main", &mut stdout).unwrap();
+        journal.begin(&repo).unwrap();
+        std::fs::remove_file(repo.root.join("c")).unwrap();
+        stdout.clear();
+        good_git::recover(&repo, Some(good_git::journal::Recovery::Back), &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "Undid 'checkout other'\n"
+        );
+        assert_eq!(
+            good_git::refs::head(&repo).unwrap(),
+            good_git::refs::Head::Branch("refs/heads/main".to_string())
+        );
+        assert_eq!(std::fs::read_to_string(repo.root.join("c")).unwrap(), "c\n");
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("b")).unwrap(),
+            "b2\n"
+        );
+        good_git::switch_branch(&repo, "other", &mut stdout).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_checkout_file_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        let config = repo.git_dir().join("config");
+        let mut stdout = Vec::new();
+        let add_all = || {
+            let options = good_git::AddOptions {
+                all: true,
+                ..Default::default()
+            };
+            good_git::add(&repo, &[], &options, &mut Vec::new()).unwrap();
+        };
+        let set_mode = |name: &str, mode: u32| {
+            std::fs::set_permissions(repo.root.join(name), std::fs::Permissions::from_mode(mode))
+                .unwrap();
+        };
+        let is_executable = |name: &str| {
+            std::fs::metadata(repo.root.join(name))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o100
+                != 0
+        };
+        let status = || {
+            let mut stdout = Vec::new();
+            let options = good_git::StatusOptions {
+                format: good_git::StatusFormat::PorcelainV1,
+                ..Default::default()
+            };
+            good_git::status(&repo, &options, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        let index_mode = |path: &str| {
+            good_git::index::Index::read(&repo)
+                .unwrap()
+                .entries
+                .into_iter()
+                .find(|entry| entry.path == path)
+                .unwrap()
+                .mode
+        };
+
+        std::fs::write(repo.root.join("run.sh"), "echo\n").unwrap();
+        set_mode("run.sh", 0o755);
+        std::fs::write(repo.root.join("target"), "target\n").unwrap();
+        std::os::unix::fs::symlink("target", repo.root.join("link")).unwrap();
+        commit_all(&repo, "One", &mut stdout);
+        let one = good_git::refs::find_ref(&repo, "refs/heads/main").unwrap();
+        good_git::refs::update_ref(&repo, "refs/heads/one", &one).unwrap();
+        set_mode("run.sh", 0o644);
+        commit_all(&repo, "Two", &mut stdout);
+
+        // The executable bit is set and cleared by checkouts.
+        good_git::switch_branch(&repo, "one", &mut stdout).unwrap();
+        assert!(is_executable("run.sh"));
+        good_git::switch_branch(&repo, "main", &mut stdout).unwrap();
+        assert!(!is_executable("run.sh"));
+        assert_eq!(status(), "");
+
+        // Without core.fileMode, the executable bit of files is ignored and
+        // the mode of their entries kept.
+        good_git::config::set_value(&config, "core.fileMode", "false").unwrap();
+        set_mode("run.sh", 0o755);
+        assert_eq!(status(), "");
+        add_all();
+        assert_eq!(index_mode("run.sh"), 0o100644);
+
+        // Without core.symlinks, symlinks are checked out as files holding
+        // their target.
+        good_git::config::set_value(&config, "core.symlinks", "false").unwrap();
+        std::fs::remove_file(repo.root.join("link")).unwrap();
+        good_git::checkout_paths(&repo, None, &["link".to_string()]).unwrap();
+        let metadata = std::fs::symlink_metadata(repo.root.join("link")).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(std::fs::read(repo.root.join("link")).unwrap(), b"target");
+        assert_eq!(status(), "");
+        add_all();
+        assert_eq!(index_mode("link"), 0o120000);
+    }
+
+    #[test]
+    fn test_ignore_case() {
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        let config = repo.git_dir().join("config");
+        let mut stdout = Vec::new();
+        let add = |pathspecs: &[&str]| {
+            let pathspecs: Vec<String> = pathspecs.iter().map(|s| s.to_string()).collect();
+            let options = good_git::AddOptions {
+                all: pathspecs.is_empty(),
+                ..Default::default()
+            };
+            good_git::add(&repo, &pathspecs, &options, &mut Vec::new()).unwrap();
+        };
+        let commit = |message: &str, stdout: &mut Vec<u8>| {
+            let options = good_git::CommitOptions {
+                message: message.to_string(),
+                ..Default::default()
+            };
+            good_git::commit(&repo, &options, stdout).unwrap();
+        };
+        let index_paths = || -> Vec<String> {
+            good_git::index::Index::read(&repo)
+                .unwrap()
+                .entries
+                .into_iter()
+                .map(|entry| entry.path)
+                .collect()
+        };
+
+        std::fs::write(repo.root.join("README.md"), "One\n").unwrap();
+        std::fs::create_dir(repo.root.join("docs")).unwrap();
+        std::fs::write(repo.root.join("docs/guide.md"), "Guide\n").unwrap();
+        std::fs::write(repo.root.join(".gitignore"), "*.LOG\n").unwrap();
+        add(&[]);
+        commit("One", &mut stdout);
+        let main = good_git::refs::find_ref(&repo, "refs/heads/main").unwrap();
+        good_git::refs::update_ref(&repo, "refs/heads/base", &main).unwrap();
+        std::fs::write(repo.root.join("a.txt"), "a\n").unwrap();
+        std::fs::write(repo.root.join("A.txt"), "A\n").unwrap();
+        add(&[]);
+        commit("Two", &mut stdout);
+        good_git::switch_branch(&repo, "base", &mut stdout).unwrap();
+        good_git::config::set_value(&config, "core.ignoreCase", "true").unwrap();
+
+        // Of the paths only differing by case, only one is checked out.
+        stdout.clear();
+        good_git::switch_branch(&repo, "main", &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "warning: the following paths have collided (e.g. case-sensitive paths\n\
+             on a case-insensitive filesystem) and only one from the same\n\
+             colliding group is in the working tree:\n\n  \
+             'A.txt'\n  'a.txt'\nD\ta.txt\nSwitched to branch 'main'\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("A.txt")).unwrap(),
+            "A\n"
+        );
+        assert!(!repo.root.join("a.txt").exists());
+        assert!(index_paths().contains(&"a.txt".to_string()));
+
+        // Files are matched with their entries and ignore patterns whatever
+        // their case, as if the file system had renamed them.
+        std::fs::rename(repo.root.join("README.md"), repo.root.join("readme.md")).unwrap();
+        std::fs::write(repo.root.join("readme.md"), "Two\n").unwrap();
+        std::fs::create_dir(repo.root.join("Docs")).unwrap();
+        std::fs::write(repo.root.join("Docs/new.md"), "New\n").unwrap();
+        std::fs::write(repo.root.join("debug.log"), "").unwrap();
+        let status = good_git::status::Status::compute(&repo).unwrap();
+        assert_eq!(status.untracked, vec!["Docs/new.md"]);
+        add(&["readme.md", "Docs/new.md"]);
+        assert_eq!(
+            index_paths(),
+            vec![
+                ".gitignore",
+                "A.txt",
+                "README.md",
+                "a.txt",
+                "docs/guide.md",
+                "docs/new.md"
+            ]
+        );
+        let status = good_git::status::Status::compute(&repo).unwrap();
+        assert!(status.untracked.is_empty());
+    }
+
+    #[rstest]
+    fn test_checkout_paths(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let mut stdout = Vec::new();
+        good_git::checkout_detach(&repo, None, &mut stdout).unwrap();
+        std::fs::write(repo.root.join("test.txt"), "changed\n").unwrap();
+        std::fs::write(repo.root.join("more.txt"), "changed\n").unwrap();
+        std::fs::write(repo.root.join("new.txt"), "new\n").unwrap();
+        good_git::add(
+            &repo,
+            &["more.txt".to_string(), "new.txt".to_string()],
+            &Default::default(),
+            &mut stdout,
+        )
+        .unwrap();
+
+        // From the index, only the working tree changes.
+        good_git::checkout_paths(&repo, None, &["test.txt".to_string()]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("test.txt")).unwrap(),
+            "test content\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("more.txt")).unwrap(),
+            "changed\n"
+        );
+
+        // From a commit, both change, and files only in the index are kept.
+        good_git::checkout_paths(&repo, Some("main"), &["".to_string()]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("more.txt")).unwrap(),
+            "more content\nfrom a good client"
+        );
+        let index = good_git::index::Index::read(&repo).unwrap();
+        let entries: Vec<(&str, &str)> = index
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.hash.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("more.txt", "1234567890abcdef1234567890abcdef12345678"),
+                ("new.txt", "3e757656cf36eca53338e520d134963a44f793f8"),
+                ("test.txt", "d670460b4b4aece5915caf5c68d12f560a9fe3e4"),
+            ]
+        );
+        assert_eq!(
+            good_git::refs::head(&repo).unwrap(),
+            good_git::refs::Head::Detached("ccccccccccccccccccccdddddddddddddddddddd".to_string())
+        );
+
+        assert_eq!(
+            good_git::checkout_paths(&repo, Some("main"), &["new.txt".to_string()])
+                .unwrap_err()
+                .to_string(),
+            "pathspec 'new.txt' did not match any file(s) known to git"
+        );
+    }
+
+    #[test]
+    fn test_unborn_branch() {
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        assert_eq!(
+            good_git::refs::unborn_branch(&repo).unwrap(),
+            Some("refs/heads/main".to_string())
+        );
+
+        let mut stdout = Vec::new();
+        good_git::log(&repo, "HEAD", &mut stdout).unwrap();
+        assert!(stdout.is_empty());
+        good_git::log_json_graph(&repo, "HEAD", &mut stdout).unwrap();
+        assert_eq!(stdout, b"{\"layers\":[]}\n");
+        stdout.clear();
+        good_git::status(&repo, &Default::default(), &mut stdout).unwrap();
+        assert!(std::str::from_utf8(&stdout)
+            .unwrap()
+            .contains("\nNo commits yet\n"));
+        assert_eq!(
+            good_git::checkout_detach(&repo, None, &mut stdout)
+                .unwrap_err()
+                .to_string(),
+            "You are on a branch yet to be born"
+        );
+        assert_eq!(
+            good_git::object::Object::resolve_rev(&repo, "HEAD")
+                .unwrap_err()
+                .to_string(),
+            "Your current branch 'main' does not have any commits yet"
+        );
+
+        // The first commit creates the branch.
+        std::fs::write(repo.root.join("test.txt"), "test content\n").unwrap();
+        good_git::add(
+            &repo,
+            &["test.txt".to_string()],
+            &Default::default(),
+            &mut stdout,
+        )
+        .unwrap();
+        let options = good_git::CommitOptions {
+            message: "First".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut stdout).unwrap();
+        assert_eq!(good_git::refs::unborn_branch(&repo).unwrap(), None);
+        stdout.clear();
+        good_git::log(&repo, "HEAD", &mut stdout).unwrap();
+        assert!(std::str::from_utf8(&stdout)
+            .unwrap()
+            .contains(" - First - "));
+    }
+
+    #[rstest]
+    fn test_textconv(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let script = test_repo.path().join("upper.sh");
+        let runs = test_repo.path().join("runs");
+        std::fs::write(
+            &script,
+            format!("echo run >> {}\ntr a-z A-Z < \"$1\"\n", runs.display()),
+        )
+        .unwrap();
+        std::fs::write(
+            repo.git_dir().join("config"),
+            format!(
+                "[user]\n\tname = Alice\n\temail = alice@example.com\n\
+                 [diff \"upper\"]\n\ttextconv = sh {}\n\tcachetextconv = true\n",
+                script.display()
+            ),
+        )
+        .unwrap();
+        std::fs::write(repo.root.join(".gitattributes"), "*.up diff=upper\n").unwrap();
+        std::fs::write(repo.root.join("doc.up"), "hello\n").unwrap();
+        good_git::index::Index::default().write(&repo).unwrap();
+        let mut stdout = Vec::new();
+        good_git::add(
+            &repo,
+            &["doc.up".to_string()],
+            &Default::default(),
+            &mut stdout,
+        )
+        .unwrap();
+        let options = good_git::CommitOptions {
+            message: "Add doc".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut stdout).unwrap();
+
+        stdout.clear();
+        good_git::cat_file_textconv(&repo, "HEAD:doc.up", &mut stdout).unwrap();
+        assert_eq!(stdout, b"HELLO\n");
+        let notes = good_git::refs::find_ref(&repo, "refs/notes/textconv/upper").unwrap();
+        let good_git::object::Object::Commit(notes) =
+            good_git::object::Object::from_hash(&repo, &notes).unwrap()
+        else {
+            panic!("the notes are not a commit");
+        };
+        assert_eq!(notes.message, format!("sh {}", script.display()));
+
+        // The cached output is used for the same blob.
+        let grep = |options: &good_git::GrepOptions| {
+            let mut stdout = Vec::new();
+            good_git::grep(&repo, "HELLO", &[], options, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        let options = good_git::GrepOptions {
+            cached: true,
+            textconv: true,
+            ..Default::default()
+        };
+        assert_eq!(grep(&options), "doc.up:HELLO\n");
+        assert_eq!(std::fs::read_to_string(&runs).unwrap(), "run\n");
+        assert_eq!(
+            grep(&good_git::GrepOptions {
+                textconv: false,
+                ..options
+            }),
+            ""
+        );
+
+        // Worktree files are converted without caching.
+        assert_eq!(
+            grep(&good_git::GrepOptions {
+                textconv: true,
+                ..Default::default()
+            }),
+            "doc.up:HELLO\n"
+        );
+        assert_eq!(std::fs::read_to_string(&runs).unwrap(), "run\nrun\n");
+
+        assert_eq!(
+            good_git::cat_file_textconv(&repo, "HEAD", &mut stdout)
+                .unwrap_err()
+                .to_string(),
+            "<object> required in the form <rev>:<path>, got 'HEAD'"
+        );
+    }
+
+    #[rstest]
+    fn test_update_index(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        good_git::index::Index::default().write(&repo).unwrap();
+        std::fs::write(repo.root.join("a.txt"), "a\n").unwrap();
+        let update_index = |paths: &[&str], options: &good_git::UpdateIndexOptions| {
+            let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+            let mut stdout = Vec::new();
+            good_git::update_index(&repo, &paths, options, &mut stdout)
+                .map(|up_to_date| (up_to_date, String::from_utf8(stdout).unwrap()))
+                .map_err(|e| e.to_string())
+        };
+        let entries = || {
+            good_git::index::Index::read(&repo)
+                .unwrap()
+                .entries
+                .into_iter()
+                .map(|entry| (entry.path, entry.stage))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            update_index(&["a.txt"], &Default::default()).unwrap_err(),
+            "a.txt: cannot add to the index - missing --add option?"
+        );
+        let add = good_git::UpdateIndexOptions {
+            add: true,
+            cacheinfo: vec![
+                "100644,d670460b4b4aece5915caf5c68d12f560a9fe3e4,dir/test.txt".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            update_index(&["a.txt"], &add).unwrap(),
+            (true, String::new())
+        );
+        assert_eq!(
+            entries(),
+            vec![("a.txt".to_string(), 0), ("dir/test.txt".to_string(), 0)]
+        );
+
+        // Entries can be added from the output of ls-files --stage and
+        // ls-tree, and removed with a mode of 0.
+        let options = good_git::UpdateIndexOptions {
+            index_info: Some(
+                "0 0000000000000000000000000000000000000000\tdir/test.txt\n\
+                 100644 d670460b4b4aece5915caf5c68d12f560a9fe3e4 1\tc.txt\n\
+                 100644 d670460b4b4aece5915caf5c68d12f560a9fe3e4 2\tc.txt\n\
+                 100644 blob d670460b4b4aece5915caf5c68d12f560a9fe3e4\tb.txt\n"
+                    .to_string(),
+            ),
+            ..Default::default()
+        };
+        update_index(&[], &options).unwrap();
+        assert_eq!(
+            entries(),
+            vec![
+                ("a.txt".to_string(), 0),
+                ("b.txt".to_string(), 0),
+                ("c.txt".to_string(), 1),
+                ("c.txt".to_string(), 2),
+            ]
+        );
+
+        let refresh = good_git::UpdateIndexOptions {
+            refresh: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            update_index(&[], &refresh).unwrap(),
+            (
+                false,
+                "b.txt: needs update\nc.txt: needs merge\n".to_string()
+            )
+        );
+        assert_eq!(
+            update_index(&["b.txt"], &Default::default()).unwrap_err(),
+            "b.txt: does not exist and --remove not passed"
+        );
+        let remove = good_git::UpdateIndexOptions {
+            remove: true,
+            ..Default::default()
+        };
+        update_index(&["b.txt", "c.txt"], &remove).unwrap();
+        assert_eq!(entries(), vec![("a.txt".to_string(), 0)]);
+
+        // Refreshing updates the stat data of unchanged files.
+        let a_path = repo.root.join("a.txt");
+        std::fs::write(&a_path, "a\n").unwrap();
+        let file = std::fs::File::options().write(true).open(&a_path).unwrap();
+        file.set_modified(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let metadata = std::fs::symlink_metadata(&a_path).unwrap();
+        let index = good_git::index::Index::read(&repo).unwrap();
+        assert!(!index.entries[0].matches_stat(&metadata, good_git::index::FileModes::default()));
+        assert_eq!(update_index(&[], &refresh).unwrap(), (true, String::new()));
+        let index = good_git::index::Index::read(&repo).unwrap();
+        assert!(index.entries[0].matches_stat(&metadata, good_git::index::FileModes::default()));
+
+        assert_eq!(
+            update_index(
+                &[],
+                &good_git::UpdateIndexOptions {
+                    cacheinfo: vec!["100644,1234,x".to_string()],
+                    ..Default::default()
+                }
+            )
+            .unwrap_err(),
+            "option 'cacheinfo' expects <mode>,<sha1>,<path>"
+        );
+    }
+
+    #[rstest]
+    fn test_assume_unchanged_and_skip_worktree(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        good_git::index::Index::default().write(&repo).unwrap();
+        std::fs::write(repo.root.join("a.txt"), "a\n").unwrap();
+        std::fs::write(repo.root.join("b.txt"), "b\n").unwrap();
+        let update_index = |paths: &[&str], options: &good_git::UpdateIndexOptions| {
+            let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+            good_git::update_index(&repo, &paths, options, &mut Vec::new())
+                .map_err(|e| e.to_string())
+        };
+        let add = good_git::UpdateIndexOptions {
+            add: true,
+            ..Default::default()
+        };
+        update_index(&["a.txt", "b.txt"], &add).unwrap();
+        let assume_unchanged = |value| good_git::UpdateIndexOptions {
+            assume_unchanged: Some(value),
+            ..Default::default()
+        };
+        update_index(&["a.txt"], &assume_unchanged(true)).unwrap();
+        update_index(
+            &["b.txt"],
+            &good_git::UpdateIndexOptions {
+                skip_worktree: Some(true),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let index = good_git::index::Index::read(&repo).unwrap();
+        assert!(index.entries[0].assume_valid && !index.entries[0].skip_worktree);
+        assert!(index.entries[1].skip_worktree && !index.entries[1].assume_valid);
+        assert_eq!(
+            update_index(&["nope"], &assume_unchanged(true)).unwrap_err(),
+            "Unable to mark file nope"
+        );
+
+        // The files are neither checked for changes nor staged.
+        std::fs::write(repo.root.join("a.txt"), "changed\n").unwrap();
+        std::fs::remove_file(repo.root.join("b.txt")).unwrap();
+        let unstaged = || {
+            good_git::status::Status::compute(&repo)
+                .unwrap()
+                .paths
+                .into_iter()
+                .filter(|path| path.unstaged.is_some())
+                .map(|path| path.path)
+                .collect::<Vec<_>>()
+        };
+        assert!(unstaged().is_empty());
+        let add = |pathspecs: &[&str]| {
+            let pathspecs: Vec<String> = pathspecs.iter().map(|p| p.to_string()).collect();
+            let options = good_git::AddOptions {
+                all: true,
+                ..Default::default()
+            };
+            good_git::add(&repo, &pathspecs, &options, &mut Vec::new()).map_err(|e| e.to_string())
+        };
+        add(&[]).unwrap();
+        add(&["a.txt"]).unwrap();
+        assert_eq!(good_git::index::Index::read(&repo).unwrap(), index);
+        assert!(add(&["b.txt"]).unwrap_err().starts_with(
+            "The following paths and/or pathspecs matched paths that exist\n\
+             outside of your sparse-checkout definition"
+        ));
+
+        // Reading a tree keeps the files outside of the sparse checkout out
+        // of the working tree.
+        let blob =
+            good_git::object::write_object(&repo, good_git::object::ObjectType::Blob, b"new b\n")
+                .unwrap();
+        let mut new_index = good_git::index::Index::read(&repo).unwrap();
+        new_index.entries[1] = good_git::index::IndexEntry {
+            mode: 0o100644,
+            hash: blob.clone(),
+            path: "b.txt".to_string(),
+            ..Default::default()
+        };
+        let tree = new_index.write_tree(&repo).unwrap();
+        let options = good_git::ReadTreeOptions {
+            merge: true,
+            update: true,
+            ..Default::default()
+        };
+        good_git::read_tree(&repo, &[tree], &options).unwrap();
+        let index = good_git::index::Index::read(&repo).unwrap();
+        assert_eq!(index.entries[1].hash, blob);
+        assert!(index.entries[0].assume_valid && index.entries[1].skip_worktree);
+        assert!(!repo.root.join("b.txt").exists());
+
+        update_index(
+            &["a.txt", "b.txt"],
+            &good_git::UpdateIndexOptions {
+                assume_unchanged: Some(false),
+                skip_worktree: Some(false),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(unstaged(), vec!["a.txt", "b.txt"]);
+    }
+
+    #[rstest]
+    fn test_diff_plumbing(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let blob = |content: &str| {
+            good_git::object::write_object(
+                &repo,
+                good_git::object::ObjectType::Blob,
+                content.as_bytes(),
+            )
+            .unwrap()
+        };
+        let tree = |files: &[(&str, &str)]| {
+            let mut index = good_git::index::Index::default();
+            for (path, hash) in files {
+                index.add(good_git::index::IndexEntry {
+                    mode: 0o100644,
+                    hash: hash.to_string(),
+                    path: path.to_string(),
+                    ..Default::default()
+                });
+            }
+            index.write_tree(&repo).unwrap()
+        };
+        let (a, a2, b) = (blob("a\n"), blob("a2\n"), blob("b\n"));
+        let old = tree(&[("a.txt", &a), ("dir/b.txt", &b)]);
+        let new = tree(&[("a.txt", &a2), ("dir/b.txt", &b), ("dir/c.txt", &b)]);
+        let zero = "0".repeat(40);
+
+        let diff_tree = |revs: &[&str], options: &good_git::DiffTreeOptions| {
+            let revs: Vec<String> = revs.iter().map(|rev| rev.to_string()).collect();
+            let mut stdout = Vec::new();
+            good_git::diff_tree(&repo, &revs, &[], options, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        let old_dir = good_git::object::Object::resolve_rev(&repo, &format!("{old}:dir")).unwrap();
+        let new_dir = good_git::object::Object::resolve_rev(&repo, &format!("{new}:dir")).unwrap();
+        assert_eq!(
+            diff_tree(&[&old, &new], &Default::default()),
+            format!(
+                ":100644 100644 {a} {a2} M\ta.txt\n\
+                 :040000 040000 {old_dir} {new_dir} M\tdir\n"
+            )
+        );
+        let recursive = good_git::DiffTreeOptions {
+            recursive: true,
+            format: good_git::diff::RawFormat::NameStatus,
+            ..Default::default()
+        };
+        assert_eq!(
+            diff_tree(&[&old, &new], &recursive),
+            "M\ta.txt\nA\tdir/c.txt\n"
+        );
+
+        // Commits are compared with their parent, and root commits are
+        // skipped unless asked for.
+        assert_eq!(diff_tree(&["main"], &recursive), "");
+        let root = "aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb";
+        assert_eq!(diff_tree(&[root], &recursive), "");
+        let root_options = good_git::DiffTreeOptions {
+            root: true,
+            ..recursive
+        };
+        assert_eq!(
+            diff_tree(&[root], &root_options),
+            format!("{root}\nA\tmore.txt\nA\ttest.txt\n")
+        );
+
+        let mut stdout = Vec::new();
+        let stdin = format!("{old} {new}\n{root}\n");
+        good_git::diff_tree_stdin(
+            &repo,
+            &mut stdin.as_bytes(),
+            &["dir".to_string()],
+            &root_options,
+            &mut stdout,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            format!("{old} {new}\nA\tdir/c.txt\n")
+        );
+
+        // The index has the new tree, with a change in the working tree.
+        good_git::read_tree(&repo, &[new.clone()], &Default::default()).unwrap();
+        std::fs::write(repo.root.join("a.txt"), "changed\n").unwrap();
+        std::fs::create_dir(repo.root.join("dir")).unwrap();
+        std::fs::write(repo.root.join("dir/b.txt"), "b\n").unwrap();
+        let mut stdout = Vec::new();
+        good_git::diff_files(&repo, &[], &Default::default(), &mut stdout).unwrap();
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            format!(
+                ":100644 100644 {a2} {zero} M\ta.txt\n\
+                 :100644 000000 {b} {zero} D\tdir/c.txt\n"
+            )
+        );
+        let options = good_git::DiffFilesOptions {
+            patch: Some(good_git::diff::PatchOptions::default()),
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        good_git::diff_files(&repo, &[], &options, &mut stdout).unwrap();
+        let changed = good_git::object::Blob::new(b"changed\n".to_vec()).hash();
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            format!(
+                "diff --git a/a.txt b/a.txt\n\
+                 index {}..{} 100644\n\
+                 --- a/a.txt\n\
+                 +++ b/a.txt\n\
+                 @@ -1 +1 @@\n\
+                 -a2\n\
+                 +changed\n\
+                 diff --git a/dir/c.txt b/dir/c.txt\n\
+                 deleted file mode 100644\n\
+                 index {}..0000000\n\
+                 --- a/dir/c.txt\n\
+                 +++ /dev/null\n\
+                 @@ -1 +0,0 @@\n\
+                 -b\n",
+                &a2[..7],
+                &changed[..7],
+                &b[..7]
+            )
+        );
+
+        let diff_index = |cached| {
+            let options = good_git::DiffIndexOptions {
+                cached,
+                format: good_git::diff::RawFormat::Raw,
+                stat: None,
+                patch: None,
+            };
+            let mut stdout = Vec::new();
+            good_git::diff_index(&repo, &old, &[], &options, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        assert_eq!(
+            diff_index(true),
+            format!(
+                ":100644 100644 {a} {a2} M\ta.txt\n\
+                 :000000 100644 {zero} {b} A\tdir/c.txt\n"
+            )
+        );
+        assert_eq!(
+            diff_index(false),
+            format!(":100644 100644 {a} {zero} M\ta.txt\n")
+        );
+    }
+
+    #[rstest]
+    fn test_write_tree(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        good_git::index::Index::default().write(&repo).unwrap();
+        let options = good_git::UpdateIndexOptions {
+            add: true,
+            cacheinfo: vec![
+                "100644,d670460b4b4aece5915caf5c68d12f560a9fe3e4,dir/test.txt".to_string(),
+            ],
+            ..Default::default()
+        };
+        good_git::update_index(&repo, &[], &options, &mut Vec::new()).unwrap();
+
+        let mut stdout = Vec::new();
+        good_git::write_tree(&repo, false, &mut stdout).unwrap();
+        assert_eq!(stdout, b"49654ef7620b7b016fa7d8e164ae85189c22bad3\n");
+        let index = good_git::index::Index::read(&repo).unwrap();
+        assert_eq!(
+            index.cache_tree.unwrap().hash.as_deref(),
+            Some("49654ef7620b7b016fa7d8e164ae85189c22bad3")
+        );
+        let good_git::object::Object::Tree(tree) =
+            good_git::object::Object::from_hash(&repo, "49654ef7620b7b016fa7d8e164ae85189c22bad3")
+                .unwrap()
+        else {
+            panic!("not a tree");
+        };
+        assert_eq!(
+            tree.files[0].hash,
+            "80865964295ae2f11d27383e5f9c0b58a8ef21da"
+        );
+
+        let options = good_git::UpdateIndexOptions {
+            add: true,
+            cacheinfo: vec!["100644,0123456789012345678901234567890123456789,missing".to_string()],
+            ..Default::default()
+        };
+        good_git::update_index(&repo, &[], &options, &mut Vec::new()).unwrap();
+        assert_eq!(
+            good_git::write_tree(&repo, false, &mut stdout)
+                .unwrap_err()
+                .to_string(),
+            "invalid object 100644 0123456789012345678901234567890123456789 for 'missing'"
+        );
+        good_git::write_tree(&repo, true, &mut stdout).unwrap();
+    }
+
+    #[rstest]
+    fn test_diff(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let main = good_git::refs::find_ref(&repo, "refs/heads/main").unwrap();
+        let config = repo.git_dir().join("config");
+        good_git::config::set_value(&config, "user.name", "Alice").unwrap();
+        good_git::config::set_value(&config, "user.email", "alice@example.com").unwrap();
+        good_git::index::Index::default().write(&repo).unwrap();
+        let add = |content: &str| {
+            std::fs::write(repo.root.join("a.txt"), content).unwrap();
+            let paths = ["a.txt".to_string()];
+            good_git::add(&repo, &paths, &Default::default(), &mut Vec::new()).unwrap();
+        };
+        add("1\n");
+        let options = good_git::CommitOptions {
+            message: "One".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+        add("2\n");
+        std::fs::write(repo.root.join("a.txt"), "3\n").unwrap();
+
+        let diff = |revs: &[&str], pathspecs: &[&str], options: &good_git::DiffOptions| {
+            let revs: Vec<String> = revs.iter().map(|rev| rev.to_string()).collect();
+            let pathspecs: Vec<String> = pathspecs.iter().map(|path| path.to_string()).collect();
+            let mut stdout = Vec::new();
+            good_git::diff(&repo, &revs, &pathspecs, options, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        let changed_lines = |revs: &[&str], cached: bool| {
+            let options = good_git::DiffOptions {
+                cached,
+                patch: Some(Default::default()),
+                ..Default::default()
+            };
+            diff(revs, &[], &options)
+                .lines()
+                .filter(|line| line.len() == 2 && (line.starts_with('-') || line.starts_with('+')))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!(changed_lines(&[], false), "-2 +3");
+        assert_eq!(changed_lines(&[], true), "-1 +2");
+        assert_eq!(changed_lines(&["HEAD"], false), "-1 +3");
+        assert_eq!(changed_lines(&["HEAD"], true), "-1 +2");
+
+        // Revisions are compared with each other, and pathspecs limit the
+        // changes.
+        let name_status = good_git::DiffOptions {
+            format: good_git::diff::RawFormat::NameStatus,
+            ..Default::default()
+        };
+        let expected = "A\ta.txt\nD\tmore.txt\nD\ttest.txt\n";
+        assert_eq!(diff(&[&main, "HEAD"], &[], &name_status), expected);
+        assert_eq!(diff(&[&format!("{main}..")], &[], &name_status), expected);
+        assert_eq!(
+            diff(&[&format!("HEAD..{main}")], &["a.txt"], &name_status),
+            "D\ta.txt\n"
+        );
+        assert_eq!(
+            diff(&[&format!("{main}...HEAD")], &[], &name_status),
+            expected
+        );
+
+        // Hashes of raw changes are abbreviated, unless asked otherwise.
+        let hash = "0cfbf08886fca9a91cb753ec8734c84fcbe52c9f";
+        assert_eq!(
+            diff(&[], &[], &Default::default()),
+            format!(":100644 100644 {} 0000000 M\ta.txt\n", &hash[..7])
+        );
+        let options = good_git::DiffOptions {
+            no_abbrev: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            diff(&[], &[], &options),
+            format!(":100644 100644 {hash} {} M\ta.txt\n", "0".repeat(40))
+        );
+
+        // Files whose content is back to the one in the index are unchanged.
+        std::fs::write(repo.root.join("a.txt"), "2\n").unwrap();
+        assert_eq!(diff(&[], &[], &name_status), "");
+    }
+
+    #[test]
+    fn test_diff_path_options() {
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        std::fs::create_dir(repo.root.join("src")).unwrap();
+        add_and_commit(&repo, "src/a.txt", "1\n", "One");
+        add_and_commit(&repo, "b.txt", "1\n", "Two");
+        std::fs::write(repo.root.join("src/a.txt"), "2\n").unwrap();
+        std::fs::write(repo.root.join("b.txt"), "2\n").unwrap();
+        let headers = |dir: &Path, args: &[&str]| {
+            run_good_git(dir, args)
+                .lines()
+                .filter(|line| line.starts_with("diff --git") || line.starts_with("+++"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        assert_eq!(
+            headers(&repo.root, &["diff", "HEAD"]),
+            "diff --git a/b.txt b/b.txt\n+++ b/b.txt\n\
+             diff --git a/src/a.txt b/src/a.txt\n+++ b/src/a.txt"
+        );
+        assert_eq!(
+            headers(&repo.root, &["diff", "--no-prefix", "HEAD", "b.txt"]),
+            "diff --git b.txt b.txt\n+++ b.txt"
+        );
+        assert_eq!(
+            headers(
+                &repo.root,
+                &[
+                    "diff",
+                    "--src-prefix=old/",
+                    "--dst-prefix=new/",
+                    "HEAD",
+                    "b.txt"
+                ]
+            ),
+            "diff --git old/b.txt new/b.txt\n+++ new/b.txt"
+        );
+        assert_eq!(
+            headers(&repo.root, &["diff", "--relative=src", "HEAD"]),
+            "diff --git a/a.txt b/a.txt\n+++ b/a.txt"
+        );
+        // Without a path, paths are relative to the current directory.
+        assert_eq!(
+            headers(
+                &repo.root.join("src"),
+                &["diff-index", "-p", "--relative", "HEAD"]
+            ),
+            "diff --git a/a.txt b/a.txt\n+++ b/a.txt"
+        );
+    }
+
+    #[test]
+    fn test_diff_ambiguous_argument() {
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        add_and_commit(&repo, "a.txt", "1\n", "One");
+        std::fs::write(repo.root.join("a.txt"), "2\n").unwrap();
+        let diff = |args: &[&str]| {
+            std::process::Command::new(env!("CARGO_BIN_EXE_good_git"))
+                .arg("diff")
+                .args(args)
+                .current_dir(&repo.root)
+                .output()
+                .unwrap()
+        };
+
+        let output = diff(&["HEAD~1", "HEAD"]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains(
+            "ambiguous argument 'HEAD~1': unknown revision or path not in the working tree."
+        ));
+        assert!(diff(&["HEAD", "a.txt"]).status.success());
+        // Paths after `--` don't have to exist.
+        assert!(diff(&["HEAD", "--", "gone.txt"]).status.success());
+    }
+
+    #[rstest]
+    fn test_diff_binary(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let write = |path: &str, content: &[u8]| {
+            std::fs::write(repo.root.join(path), content).unwrap();
+        };
+        write(".gitattributes", b"text.txt -diff\n");
+        write("bin.dat", b"a\0b\n");
+        write("text.txt", b"x\n");
+        let paths = ["bin.dat".to_string(), "text.txt".to_string()];
+        good_git::add(&repo, &paths, &Default::default(), &mut Vec::new()).unwrap();
+        write("bin.dat", b"a\0c\n");
+        write("text.txt", b"y\n");
+
+        let diff = |patch: good_git::diff::PatchOptions, stat| {
+            let options = good_git::DiffOptions {
+                stat,
+                patch: Some(patch),
+                ..Default::default()
+            };
+            let mut stdout = Vec::new();
+            good_git::diff(&repo, &[], &[], &options, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        // Files with a NUL byte or a `-diff` attribute are binary.
+        let output = diff(Default::default(), None);
+        let binary_lines: Vec<&str> = output
+            .lines()
+            .filter(|line| !line.starts_with("diff") && !line.starts_with("index"))
+            .collect();
+        assert_eq!(
+            binary_lines,
+            [
+                "Binary files a/bin.dat and b/bin.dat differ",
+                "Binary files a/text.txt and b/text.txt differ"
+            ]
+        );
+        let text = good_git::diff::PatchOptions {
+            text: true,
+            ..Default::default()
+        };
+        assert!(diff(text, None).contains("\n-x\n+y\n"));
+
+        // Binary patches have the new content, then the old one.
+        let binary = good_git::diff::PatchOptions {
+            binary: true,
+            ..Default::default()
+        };
+        let output = diff(binary, Some(good_git::diff::StatFormat::Numstat));
+        assert!(output.starts_with("-\t-\tbin.dat\n-\t-\ttext.txt\n\ndiff --git"));
+        assert!(output.contains(
+            "index 1a23e4be731d2f539deeea324686d000ccdfbfcd..\
+             659b72404b70ab54da8f878f31930baac622ca49 100644\n\
+             GIT binary patch\n\
+             literal 4\n"
+        ));
+        assert!(output.ends_with("literal 2\nJcmb>60ssK~0D}Mk\n\n"));
+    }
+
+    #[rstest]
+    fn test_diff_word_diff(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let write = |path: &str, content: &str| {
+            std::fs::write(repo.root.join(path), content).unwrap();
+        };
+        write(".gitattributes", "*.c diff=c\n");
+        write("a.txt", "call(one, two)\n");
+        write("a.c", "call(one, two)\n");
+        let paths = ["a.txt".to_string(), "a.c".to_string()];
+        good_git::add(&repo, &paths, &Default::default(), &mut Vec::new()).unwrap();
+        write("a.txt", "call(one,  three)\n");
+        write("a.c", "call(one,  three)\n");
+        let config = repo.git_dir().join("config");
+        good_git::config::set_value(&config, "diff.c.wordRegex", "[a-z]+|[^[:space:]]").unwrap();
+
+        let diff = |word_diff, word_regex: Option<&str>| {
+            let options = good_git::DiffOptions {
+                patch: Some(good_git::diff::PatchOptions {
+                    word_diff: Some(word_diff),
+                    word_regex: word_regex.map(str::to_string),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            let mut stdout = Vec::new();
+            good_git::diff(&repo, &[], &[], &options, &mut stdout).unwrap();
+            let stdout = String::from_utf8(stdout).unwrap();
+            stdout
+                .lines()
+                .filter(|line| line.contains("call") || line.contains("three"))
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        // Words are runs of non-whitespace, or the matches of the word regex
+        // of the diff driver.
+        let plain = good_git::word_diff::WordDiffMode::Plain;
+        assert_eq!(
+            diff(plain, None),
+            [
+                "call(one,  [-two-]{+three+})",
+                "call(one,  [-two)-]{+three)+}"
+            ]
+        );
+        assert_eq!(diff(plain, Some(r"\S")), ["call(one,  t[-wo-]{+hree+})"; 2]);
+        let porcelain = good_git::word_diff::WordDiffMode::Porcelain;
+        assert_eq!(
+            diff(porcelain, None),
+            [" call(one,  ", "+three", " call(one,  ", "+three)"]
+        );
+    }
+
+    #[rstest]
+    fn test_color(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::write(repo.root.join("test.txt"), "test content\n").unwrap();
+        let paths = ["test.txt".to_string()];
+        good_git::add(&repo, &paths, &Default::default(), &mut Vec::new()).unwrap();
+        std::fs::write(repo.root.join("test.txt"), "test content  \nnew\n").unwrap();
+
+        let options = good_git::DiffOptions {
+            patch: Some(good_git::diff::PatchOptions {
+                colors: Some(Default::default()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        good_git::diff(&repo, &[], &[], &options, &mut stdout).unwrap();
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(stdout.starts_with("\x1b[1mdiff --git a/test.txt b/test.txt\x1b[m\n"));
+        // Trailing whitespace in added lines is highlighted.
+        assert!(stdout.ends_with(
+            "\x1b[36m@@ -1 +1,2 @@\x1b[m\n\
+             \x1b[31m-test content\x1b[m\n\
+             \x1b[32m+\x1b[m\x1b[32mtest content\x1b[m\x1b[41m  \x1b[m\n\
+             \x1b[32m+\x1b[m\x1b[32mnew\x1b[m\n"
+        ));
+
+        let options = good_git::StatusOptions {
+            colors: Some(Default::default()),
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        good_git::status(&repo, &options, &mut stdout).unwrap();
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(stdout.contains("\t\x1b[32mdeleted:    more.txt\x1b[m\n"));
+        assert!(stdout.contains("\t\x1b[31mmodified:   test.txt\x1b[m\n"));
+
+        let options = good_git::LogOptions {
+            oneline: true,
+            decorate: true,
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        good_git::log_with_options(&repo, "main", &options, &mut stdout).unwrap();
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            "ccccccc (HEAD -> main) - Here is a better commit - \"Sherlock Holmes <sherlock@baker.street>\"
+aaaaaaa (tag: v1) - This is a good commit - \"Alice <bye@alice.test>\"
+"
+        );
+        let options = good_git::LogOptions {
+            colors: Some(Default::default()),
+            ..options
+        };
+        let mut stdout = Vec::new();
+        good_git::log_with_options(&repo, "main", &options, &mut stdout).unwrap();
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(stdout.starts_with(
+            "\x1b[33mccccccc\x1b[m\x1b[33m (\x1b[m\
+             \x1b[1;36mHEAD -> \x1b[m\x1b[1;32mmain\x1b[m\x1b[33m)\x1b[m - "
+        ));
+        assert!(stdout.contains("\n\x1b[33maaaaaaa\x1b[m\x1b[33m (\x1b[m\x1b[1;33mtag: v1\x1b[m"));
+    }
+
+    #[rstest]
+    fn test_difftool(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        good_git::index::Index::default().write(&repo).unwrap();
+        std::fs::write(repo.root.join("test.txt"), "test content\n").unwrap();
+        good_git::add(
+            &repo,
+            &["test.txt".to_string()],
+            &Default::default(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        std::fs::write(repo.root.join("test.txt"), "changed\n").unwrap();
+
+        // The tool sees the changed file on both sides, and its edits are
+        // copied back.
+        let seen = repo.root.join("seen");
+        let options = good_git::DifftoolOptions {
+            extcmd: Some(format!(
+                "sh -c 'ls \"$0\" \"$1\" > {}; echo edited > \"$1/test.txt\"'",
+                seen.display()
+            )),
+            ..Default::default()
+        };
+        good_git::difftool(&repo, &[], &options, &mut Vec::new()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&seen)
+                .unwrap()
+                .lines()
+                .filter(|line| *line == "test.txt")
+                .count(),
+            2
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("test.txt")).unwrap(),
+            "edited\n"
+        );
+
+        // Files also changed in the working tree are left, with a warning.
+        let both_changed = good_git::DifftoolOptions {
+            extcmd: Some(format!(
+                "sh -c 'echo theirs > \"$1/test.txt\"; echo mine > {}'",
+                repo.root.join("test.txt").display()
+            )),
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        good_git::difftool(&repo, &[], &both_changed, &mut stdout).unwrap();
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(stdout.starts_with(&format!(
+            "warning: both files modified: '{}' and '",
+            repo.root.join("test.txt").display()
+        )));
+        assert!(stdout.contains("warning: you may want to cleanup or recover these.\n"));
+        let kept = stdout
+            .split_once("warning: temporary files exist in '")
+            .and_then(|(_, rest)| rest.split_once("'."))
+            .unwrap()
+            .0;
+        std::fs::remove_dir_all(kept).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("test.txt")).unwrap(),
+            "mine\n"
+        );
+        std::fs::write(repo.root.join("test.txt"), "edited\n").unwrap();
+
+        // Trees are compared with each other or with the index.
+        std::fs::remove_file(&seen).unwrap();
+        let mut before = Vec::new();
+        good_git::write_tree(&repo, false, &mut before).unwrap();
+        let before = String::from_utf8(before).unwrap().trim().to_string();
+        good_git::add(
+            &repo,
+            &["test.txt".to_string()],
+            &Default::default(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        let mut after = Vec::new();
+        good_git::write_tree(&repo, false, &mut after).unwrap();
+        let after = String::from_utf8(after).unwrap().trim().to_string();
+        good_git::difftool(&repo, &[before, after.clone()], &options, &mut Vec::new()).unwrap();
+        assert!(seen.exists());
+        std::fs::remove_file(&seen).unwrap();
+        let options = good_git::DifftoolOptions {
+            cached: true,
+            ..options
+        };
+        good_git::difftool(&repo, &[after], &options, &mut Vec::new()).unwrap();
+        assert!(!seen.exists());
+
+        let options = good_git::DifftoolOptions::default();
+        assert_eq!(
+            good_git::difftool(&repo, &[], &options, &mut Vec::new())
+                .unwrap_err()
+                .to_string(),
+            "No diff tool configured, set diff.tool or use --tool"
+        );
+    }
+
+    #[rstest]
+    fn test_read_tree(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let flat = "80865964295ae2f11d27383e5f9c0b58a8ef21da";
+        let nested = "49654ef7620b7b016fa7d8e164ae85189c22bad3";
+        let mut stdout = Vec::new();
+        good_git::index::Index::default().write(&repo).unwrap();
+        let options = good_git::UpdateIndexOptions {
+            add: true,
+            cacheinfo: vec![
+                "100644,d670460b4b4aece5915caf5c68d12f560a9fe3e4,dir/test.txt".to_string(),
+            ],
+            ..Default::default()
+        };
+        good_git::update_index(&repo, &[], &options, &mut stdout).unwrap();
+        good_git::write_tree(&repo, false, &mut stdout).unwrap();
+        let blob = good_git::object::write_object(
+            &repo,
+            good_git::object::ObjectType::Blob,
+            b"other content\n",
+        )
+        .unwrap();
+        let mut index = good_git::index::Index::default();
+        index.add(good_git::index::IndexEntry {
+            mode: 0o100644,
+            hash: blob.clone(),
+            path: "test.txt".to_string(),
+            ..Default::default()
+        });
+        let other = index.write_tree(&repo).unwrap();
+
+        let options = good_git::ReadTreeOptions::default();
+        good_git::read_tree(&repo, &[flat.to_string()], &options).unwrap();
+        std::fs::write(test_repo.path().join("test.txt"), "test content\n").unwrap();
+
+        // Moving from one tree to another updates the working tree.
+        let options = good_git::ReadTreeOptions {
+            merge: true,
+            update: true,
+            ..Default::default()
+        };
+        good_git::read_tree(&repo, &[flat.to_string(), nested.to_string()], &options).unwrap();
+        assert!(!test_repo.path().join("test.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(test_repo.path().join("dir/test.txt")).unwrap(),
+            "test content\n"
+        );
+
+        // Conflicts are left as unmerged stages.
+        let options = good_git::ReadTreeOptions {
+            merge: true,
+            ..Default::default()
+        };
+        let trees = [flat.to_string(), nested.to_string(), other.clone()];
+        good_git::read_tree(&repo, &trees, &options).unwrap();
+        let mut stdout = Vec::new();
+        good_git::ls_files(&repo, true, &mut stdout).unwrap();
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            format!(
+                "100644 d670460b4b4aece5915caf5c68d12f560a9fe3e4 0\tdir/test.txt\n\
+                 100644 d670460b4b4aece5915caf5c68d12f560a9fe3e4 1\ttest.txt\n\
+                 100644 {blob} 3\ttest.txt\n"
+            )
+        );
+        assert_eq!(
+            good_git::read_tree(&repo, &trees, &options)
+                .unwrap_err()
+                .to_string(),
+            "You need to resolve your current index first"
+        );
+
+        // Staged changes that would be lost are refused.
+        let options = good_git::ReadTreeOptions::default();
+        good_git::read_tree(&repo, &[nested.to_string()], &options).unwrap();
+        let options = good_git::ReadTreeOptions {
+            merge: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            good_git::read_tree(&repo, &[other.clone(), flat.to_string()], &options)
+                .unwrap_err()
+                .to_string(),
+            "Entry 'test.txt' would be overwritten by merge. Cannot merge."
+        );
+
+        let options = good_git::ReadTreeOptions {
+            prefix: Some("sub/".to_string()),
+            ..Default::default()
+        };
+        good_git::read_tree(&repo, &[nested.to_string()], &options).unwrap();
+        let mut stdout = Vec::new();
+        good_git::ls_files(&repo, false, &mut stdout).unwrap();
+        assert_eq!(stdout, b"dir/test.txt\nsub/dir/test.txt\n");
+        assert_eq!(
+            good_git::read_tree(&repo, &[nested.to_string()], &options)
+                .unwrap_err()
+                .to_string(),
+            "Entry 'sub/dir/test.txt' overlaps with an existing entry.  Cannot bind."
+        );
+
+        // Entries where the prefix needs a directory, or a file, are
+        // replaced.
+        let options = good_git::ReadTreeOptions {
+            prefix: Some("dir/test.txt".to_string()),
+            ..Default::default()
+        };
+        good_git::read_tree(&repo, &[nested.to_string()], &options).unwrap();
+        let options = good_git::ReadTreeOptions {
+            prefix: Some("sub".to_string()),
+            ..Default::default()
+        };
+        good_git::read_tree(&repo, &[flat.to_string()], &options).unwrap();
+        let mut stdout = Vec::new();
+        good_git::ls_files(&repo, false, &mut stdout).unwrap();
+        assert_eq!(
+            stdout,
+            b"dir/test.txt/dir/test.txt\nsub/dir/test.txt\nsub/test.txt\n"
+        );
+        let options = good_git::ReadTreeOptions {
+            prefix: Some(String::new()),
+            ..Default::default()
+        };
+        good_git::read_tree(&repo, &[flat.to_string()], &options).unwrap();
+        let mut stdout = Vec::new();
+        good_git::ls_files(&repo, false, &mut stdout).unwrap();
+        assert_eq!(
+            stdout,
+            b"dir/test.txt/dir/test.txt\nsub/dir/test.txt\nsub/test.txt\ntest.txt\n"
+        );
+
+        let options = good_git::ReadTreeOptions {
+            update: true,
+            ..Default::default()
+        };
+        assert!(good_git::read_tree(&repo, &[flat.to_string()], &options).is_err());
+    }
+
+    #[rstest]
+    fn test_hash_file_streams_big_files(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::write(
+            repo.git_dir().join("config"),
+            "[core]\n\tbigFileThreshold = 4\n",
+        )
+        .unwrap();
+        let path = test_repo.path().join("big.txt");
+        std::fs::write(&path, "big content\n").unwrap();
+        let mut stdout = Vec::new();
+
+        good_git::hash_file(good_git::HashObjectMode::Write(&repo), &path, &mut stdout).unwrap();
+        assert_eq!(stdout, b"800554f856a9790be0f6c08fb87e58aa5ef14580\n");
+
+        let (size, mut reader) =
+            good_git::object::open_blob(&repo, "800554f856a9790be0f6c08fb87e58aa5ef14580").unwrap();
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!(size, 12);
+        assert_eq!(content, "big content\n");
+
+        // Checkouts stream them to the working tree.
+        let options = good_git::AddOptions::default();
+        good_git::add(&repo, &["big.txt".to_string()], &options, &mut stdout).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        good_git::checkout_paths(&repo, None, &["big.txt".to_string()]).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "big content\n");
+    }
+
+    #[rstest]
+    #[case("d670460b4b4aece5915caf5c68d12f560a9fe3e4", "blob\n", "13\n")]
+    #[case("99887766554433221100aabbccddeeff00112233", "tree\n", "72\n")]
+    #[case("main", "commit\n", "218\n")]
+    #[case("v1", "tag\n", "113\n")]
+    fn test_cat_file_type_and_size(
+        test_repo: tempfile::TempDir,
+        #[case] rev: &str,
+        #[case] expected_type: &str,
+        #[case] expected_size: &str,
+    ) {
+        let repo = Repo::new(test_repo.path());
+        let mut stdout = Vec::new();
+
+        good_git::cat_file_type(&repo, rev, &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), expected_type);
+
+        stdout.clear();
+        good_git::cat_file_size(&repo, rev, &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), expected_size);
+    }
+
+    #[rstest]
+    #[case(
+        good_git::LsTreeOptions::default(),
+        "100644 blob d670460b4b4aece5915caf5c68d12f560a9fe3e4\ttest.txt\n\
+         100644 blob 1234567890abcdef1234567890abcdef12345678\tmore.txt\n"
+    )]
+    #[case(
+        good_git::LsTreeOptions { long: true, ..Default::default() },
+        "100644 blob d670460b4b4aece5915caf5c68d12f560a9fe3e4      13\ttest.txt\n\
+         100644 blob 1234567890abcdef1234567890abcdef12345678      31\tmore.txt\n"
+    )]
+    #[case(
+        good_git::LsTreeOptions { name_only: true, ..Default::default() },
+        "test.txt\nmore.txt\n"
+    )]
+    #[case(
+        good_git::LsTreeOptions { trees_only: true, ..Default::default() },
+        ""
+    )]
+    #[case(
+        good_git::LsTreeOptions {
+            format: Some("%(path) %(objectsize) %(objecttype)%x00".to_string()),
+            ..Default::default()
+        },
+        "test.txt 13 blob\0\nmore.txt 31 blob\0\n"
+    )]
+    fn test_ls_tree(
+        test_repo: tempfile::TempDir,
+        #[case] options: good_git::LsTreeOptions,
+        #[case] expected: &str,
+    ) {
+        let repo = Repo::new(test_repo.path());
+        let mut stdout = Vec::new();
+
+        // Commits are resolved to their tree.
+        good_git::ls_tree(&repo, "main", &options, &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), expected);
+    }
+
+    #[rstest]
+    fn test_ls_tree_fails_on_unknown_placeholder(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let options = good_git::LsTreeOptions {
+            format: Some("%(nope)".to_string()),
+            ..Default::default()
+        };
+
+        let result = good_git::ls_tree(&repo, "main", &options, &mut Vec::new());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unknown format placeholder: %(nope)"
+        );
+    }
+
+    #[rstest]
+    fn test_commit_refuses_empty_commits_and_messages(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::write(
+            repo.git_dir().join("config"),
+            "[user]\n\tname = Alice\n\temail = alice@example.com\n",
+        )
+        .unwrap();
+        let mut index = good_git::index::Index::default();
+        index.add(good_git::index::IndexEntry {
+            mode: 0o100644,
+            hash: "d670460b4b4aece5915caf5c68d12f560a9fe3e4".to_string(),
+            path: "test.txt".to_string(),
+            ..Default::default()
+        });
+        index.write(&repo).unwrap();
+
+        let commit = |options: &good_git::CommitOptions| {
+            let mut stdout = Vec::new();
+            good_git::commit(&repo, options, &mut stdout)
+                .map(|_| String::from_utf8(stdout).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        let options = good_git::CommitOptions {
+            message: "Add test.txt".to_string(),
+            ..Default::default()
+        };
+        let output = commit(&options).unwrap();
+        assert!(output.starts_with("[main "), "{output}");
+        assert!(output.ends_with("] Add test.txt\n"), "{output}");
+
+        assert_eq!(
+            commit(&options).unwrap_err(),
+            "Nothing to commit (use --allow-empty to create an empty commit)"
+        );
+        let options = good_git::CommitOptions {
+            message: " \n".to_string(),
+            allow_empty: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            commit(&options).unwrap_err(),
+            "Aborting commit due to empty commit message (use --allow-empty-message to allow it)"
+        );
+        commit(&good_git::CommitOptions {
+            allow_empty_message: true,
+            ..options
+        })
+        .unwrap();
+
+        let head = good_git::object::Object::from_rev(&repo, "HEAD").unwrap();
+        let good_git::object::Object::Commit(head) = head else {
+            panic!("HEAD is not a commit");
+        };
+        assert_eq!(head.message, "");
+        assert_eq!(head.author, head.committer);
+        assert!(head.author.starts_with("Alice <alice@example.com> "));
+    }
+
+    #[rstest]
+    fn test_commit_refreshes_index_and_writes_reflogs(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::write(
+            repo.git_dir().join("config"),
+            "[user]\n\tname = Alice\n\temail = alice@example.com\n\
+             [author]\n\tname = Bob\n",
+        )
+        .unwrap();
+        std::fs::write(repo.root.join("test.txt"), "test content\n").unwrap();
+        let mut index = good_git::index::Index::default();
+        index.add(good_git::index::IndexEntry {
+            mode: 0o100644,
+            hash: "d670460b4b4aece5915caf5c68d12f560a9fe3e4".to_string(),
+            path: "test.txt".to_string(),
+            ..Default::default()
+        });
+        index.write(&repo).unwrap();
+
+        let options = good_git::CommitOptions {
+            message: "Add test.txt\n\nWith a body.".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+
+        let head = good_git::refs::find_ref(&repo, "HEAD").unwrap();
+        let good_git::object::Object::Commit(commit) =
+            good_git::object::Object::from_hash(&repo, &head).unwrap()
+        else {
+            panic!("HEAD is not a commit");
+        };
+        assert_eq!(
+            commit.parents,
+            vec!["ccccccccccccccccccccdddddddddddddddddddd"]
+        );
+        assert!(commit.author.starts_with("Bob <alice@example.com> "));
+        assert!(commit.committer.starts_with("Alice <alice@example.com> "));
+
+        let entry = &good_git::index::Index::read(&repo).unwrap().entries[0];
+        assert_ne!(entry.mtime_seconds, 0);
+
+        for log in ["logs/HEAD", "logs/refs/heads/main"] {
+            let reflog = std::fs::read_to_string(repo.git_dir().join(log)).unwrap();
+            assert!(
+                reflog.starts_with(&format!(
+                    "ccccccccccccccccccccdddddddddddddddddddd {head} {} ",
+                    "Alice <alice@example.com>"
+                )),
+                "{reflog}"
+            );
+            assert!(reflog.ends_with("\tcommit: Add test.txt\n"), "{reflog}");
+        }
+    }
+
+    #[rstest]
+    fn test_commit_amend(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::write(
+            repo.git_dir().join("config"),
+            "[user]\n\tname = Alice\n\temail = alice@example.com\n",
+        )
+        .unwrap();
+        let set_index = |files: &[(&str, &str)]| {
+            let mut index = good_git::index::Index::default();
+            for (path, hash) in files {
+                index.add(good_git::index::IndexEntry {
+                    mode: 0o100644,
+                    hash: hash.to_string(),
+                    path: path.to_string(),
+                    ..Default::default()
+                });
+            }
+            index.write(&repo).unwrap();
+        };
+        let head_commit = || {
+            let head = good_git::refs::find_ref(&repo, "HEAD").unwrap();
+            let good_git::object::Object::Commit(commit) =
+                good_git::object::Object::from_hash(&repo, &head).unwrap()
+            else {
+                panic!("HEAD is not a commit");
+            };
+            (head, commit)
+        };
+        let amend = |message: &str| good_git::CommitOptions {
+            message: message.to_string(),
+            amend: true,
+            ..Default::default()
+        };
+
+        set_index(&[("test.txt", "d670460b4b4aece5915caf5c68d12f560a9fe3e4")]);
+        // The author of the amended commit has no date, so amending would
+        // write a malformed commit.
+        assert_eq!(
+            good_git::commit(&repo, &amend(""), &mut Vec::new())
+                .unwrap_err()
+                .to_string(),
+            "object fails fsck: missingSpaceBeforeDate: \
+             invalid author/committer line - missing space before date"
+        );
+        let (_, malformed) = head_commit();
+        let amended = good_git::object::Commit {
+            author: format!("{} 1112911993 -0700", malformed.author),
+            ..malformed
+        };
+        let amended = good_git::object::write_object(
+            &repo,
+            good_git::object::ObjectType::Commit,
+            &amended.to_bytes(),
+        )
+        .unwrap();
+        good_git::refs::update_ref(&repo, "refs/heads/main", &amended).unwrap();
+
+        good_git::commit(&repo, &amend(""), &mut Vec::new()).unwrap();
+        let (head, commit) = head_commit();
+        // The parents, the author and the message of the amended commit are
+        // kept.
+        assert_eq!(commit.tree, "80865964295ae2f11d27383e5f9c0b58a8ef21da");
+        assert_eq!(
+            commit.parents,
+            vec!["aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb"]
+        );
+        assert_eq!(
+            commit.author,
+            "Captain Nemo <nemo@nautilus.sea> 1112911993 -0700"
+        );
+        assert!(commit.committer.starts_with("Alice <alice@example.com> "));
+        assert_eq!(commit.message, "Here is a better commit");
+        let reflog = std::fs::read_to_string(repo.git_dir().join("logs/refs/heads/main")).unwrap();
+        assert!(
+            reflog.starts_with(&format!("{amended} {head} ")),
+            "{reflog}"
+        );
+        assert!(
+            reflog.ends_with("\tcommit (amend): Here is a better commit\n"),
+            "{reflog}"
+        );
+
+        good_git::commit(&repo, &amend("Reworded"), &mut Vec::new()).unwrap();
+        let (_, commit) = head_commit();
+        assert_eq!(commit.message, "Reworded");
+        assert_eq!(
+            commit.parents,
+            vec!["aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbb"]
+        );
+
+        // Amending a commit with the tree of its parent would keep it empty.
+        let options = good_git::CommitOptions {
+            message: "Empty".to_string(),
+            allow_empty: true,
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+        assert!(good_git::commit(&repo, &amend(""), &mut Vec::new())
+            .unwrap_err()
+            .to_string()
+            .starts_with("You asked to amend the most recent commit"));
+
+        // Merges can be amended without changes, and keep their parents.
+        let (head, commit) = head_commit();
+        let merge = good_git::object::Commit {
+            parents: vec![head, amended.clone()],
+            message: "Merge".to_string(),
+            ..commit
+        };
+        let merge = good_git::object::write_object(
+            &repo,
+            good_git::object::ObjectType::Commit,
+            &merge.to_bytes(),
+        )
+        .unwrap();
+        good_git::refs::update_ref(&repo, "refs/heads/main", &merge).unwrap();
+        good_git::commit(&repo, &amend("Merged"), &mut Vec::new()).unwrap();
+        let (_, commit) = head_commit();
+        assert_eq!(commit.message, "Merged");
+        let Some(good_git::object::Object::Commit(merge)) =
+            good_git::object::Object::from_hash(&repo, &merge).ok()
+        else {
+            panic!("Not a commit");
+        };
+        assert_eq!(commit.parents, merge.parents);
+    }
+
+    #[rstest]
+    fn test_add_patch(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let lines: String = (1..=20).map(|i| i.to_string() + "\n").collect();
+        std::fs::write(repo.root.join("test.txt"), &lines).unwrap();
+        good_git::index::Index::default().write(&repo).unwrap();
+        let options = good_git::AddOptions::default();
+        good_git::add(&repo, &["test.txt".to_string()], &options, &mut Vec::new()).unwrap();
+        let staged = || {
+            let index = good_git::index::Index::read(&repo).unwrap();
+            let good_git::object::Object::Blob(blob) =
+                good_git::object::Object::from_hash(&repo, &index.entries[0].hash).unwrap()
+            else {
+                panic!("Not a blob");
+            };
+            String::from_utf8(blob.content).unwrap()
+        };
+
+        let changed = lines
+            .replacen("2\n", "two\n", 1)
+            .replace("19\n", "nineteen\n");
+        std::fs::write(repo.root.join("test.txt"), &changed).unwrap();
+        let hunks = good_git::worktree_hunks(&repo, "test.txt").unwrap();
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(
+            hunks[0].to_string(),
+            "@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n"
+        );
+        good_git::stage_hunks(&repo, "test.txt", &hunks, &[false, true]).unwrap();
+        assert_eq!(staged(), lines.replace("19\n", "nineteen\n"));
+        // The unstaged hunk is left.
+        let hunks = good_git::worktree_hunks(&repo, "test.txt").unwrap();
+        assert_eq!(hunks.len(), 1);
+        assert!(good_git::stage_hunks(&repo, "test.txt", &hunks, &[]).is_err());
+
+        let mut stdout = Vec::new();
+        good_git::add_patch(&repo, &[], &mut "?\ny\n".as_bytes(), &mut stdout).unwrap();
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(stdout.starts_with("diff --git a/test.txt b/test.txt\n"));
+        assert!(stdout.contains("(1/1) Stage this hunk [y,n,q,a,d,?]? y - stage this hunk\n"));
+        assert_eq!(staged(), changed);
+        assert!(good_git::worktree_hunks(&repo, "test.txt")
+            .unwrap()
+            .is_empty());
+
+        let mut stdout = Vec::new();
+        good_git::add_patch(&repo, &[], &mut "".as_bytes(), &mut stdout).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap(), "No changes.\n");
+
+        std::fs::remove_file(repo.root.join("test.txt")).unwrap();
+        let mut stdout = Vec::new();
+        good_git::add_patch(&repo, &[], &mut "y\n".as_bytes(), &mut stdout).unwrap();
+        assert!(String::from_utf8(stdout)
+            .unwrap()
+            .ends_with("(1/1) Stage deletion [y,n,q,a,d,?]? "));
+        assert!(good_git::index::Index::read(&repo)
+            .unwrap()
+            .entries
+            .is_empty());
+    }
+
+    #[rstest]
+    fn test_apply(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let lines: String = (1..=10).map(|i| i.to_string() + "\n").collect();
+        std::fs::write(repo.root.join("test.txt"), &lines).unwrap();
+        good_git::index::Index::default().write(&repo).unwrap();
+        let options = good_git::AddOptions::default();
+        good_git::add(&repo, &["test.txt".to_string()], &options, &mut Vec::new()).unwrap();
+        let read = |path: &str| std::fs::read_to_string(repo.root.join(path)).unwrap();
+
+        let patch = "diff --git a/test.txt b/test.txt\n\
+            index 1234567..89abcde 100644\n\
+            --- a/test.txt\n\
+            +++ b/test.txt\n\
+            @@ -4,5 +4,5 @@\n\
+            \x204\n\
+            \x205\n\
+            \x206\n\
+            -7\n\
+            +seven\n\
+            \x208\n\
+            diff --git a/dir/new.txt b/dir/new.txt\n\
+            new file mode 100644\n\
+            index 0000000..3e75765\n\
+            --- /dev/null\n\
+            +++ b/dir/new.txt\n\
+            @@ -0,0 +1 @@\n\
+            +new\n";
+        let apply = |options: &good_git::ApplyOptions| {
+            good_git::apply(&repo, patch.as_bytes(), options, &mut Vec::new())
+        };
+        let check = good_git::ApplyOptions {
+            check: true,
+            ..Default::default()
+        };
+        apply(&check).unwrap();
+        assert!(!repo.root.join("dir").exists());
+
+        // Hunks are found where lines were added before them.
+        std::fs::write(repo.root.join("test.txt"), format!("0\n{lines}")).unwrap();
+        apply(&Default::default()).unwrap();
+        assert_eq!(
+            read("test.txt"),
+            format!("0\n{}", lines.replace("7\n", "seven\n"))
+        );
+        assert_eq!(read("dir/new.txt"), "new\n");
+        // The new file exists now.
+        assert!(apply(&Default::default()).is_err());
+        assert_eq!(
+            read("test.txt"),
+            format!("0\n{}", lines.replace("7\n", "seven\n"))
+        );
+
+        let reverse = good_git::ApplyOptions {
+            reverse: true,
+            ..Default::default()
+        };
+        apply(&reverse).unwrap();
+        assert_eq!(read("test.txt"), format!("0\n{lines}"));
+        assert!(!repo.root.join("dir").exists());
+        assert!(apply(&reverse).is_err());
+
+        // The index is patched without the working tree.
+        let cached = good_git::ApplyOptions {
+            cached: true,
+            ..Default::default()
+        };
+        apply(&cached).unwrap();
+        assert_eq!(read("test.txt"), format!("0\n{lines}"));
+        assert!(!repo.root.join("dir").exists());
+        let mut stdout = Vec::new();
+        good_git::ls_files(&repo, false, &mut stdout).unwrap();
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            "dir/new.txt\ntest.txt\n"
+        );
+        let index = good_git::index::Index::read(&repo).unwrap();
+        let good_git::object::Object::Blob(blob) =
+            good_git::object::Object::from_hash(&repo, &index.entries[1].hash).unwrap()
+        else {
+            panic!("Not a blob");
+        };
+        assert_eq!(blob.content, lines.replace("7\n", "seven\n").as_bytes());
+
+        assert!(
+            good_git::apply(&repo, b"no patch\n", &Default::default(), &mut Vec::new()).is_err()
+        );
+
+        // Why hunks don't apply is reported.
+        std::fs::write(repo.root.join("test.txt"), "other\n").unwrap();
+        let mut stderr = Vec::new();
+        let err = good_git::apply(&repo, patch.as_bytes(), &Default::default(), &mut stderr);
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "test.txt: patch does not apply"
+        );
+        assert_eq!(
+            String::from_utf8(stderr).unwrap(),
+            "error: patch failed: test.txt:4\n"
+        );
+
+        // Paths that escape the working tree are refused before any write.
+        let escape = "diff --git a/../x b/../x\n\
+            new file mode 100644\n\
+            --- /dev/null\n\
+            +++ b/../x\n\
+            @@ -0,0 +1 @@\n\
+            +x\n";
+        let err = good_git::apply(&repo, escape.as_bytes(), &cached, &mut Vec::new());
+        assert_eq!(err.unwrap_err().to_string(), "invalid path '../x'");
+        assert_eq!(good_git::index::Index::read(&repo).unwrap(), index);
+    }
+
+    #[rstest]
+    fn test_format_patch() {
+        let (tmpdir, repo) = user_repo("J\u{f6}rg", "jorg@example.com");
+        let commit = |message: &str| {
+            let options = good_git::CommitOptions {
+                message: message.to_string(),
+                allow_empty: true,
+                ..Default::default()
+            };
+            good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+        };
+        let add = |path: &str, content: &str| {
+            std::fs::write(repo.root.join(path), content).unwrap();
+            let options = good_git::AddOptions::default();
+            good_git::add(&repo, &[path.to_string()], &options, &mut Vec::new()).unwrap();
+        };
+        add("test.txt", "one\ntwo\n");
+        commit("Add test.txt");
+        commit("Nothing: at all");
+        add("test.txt", "one\n2\n");
+        add("new.txt", "new\n");
+        commit("Change test.txt\n\nAnd add new.txt.");
+
+        let dir = tmpdir.path().join("patches");
+        let options = good_git::FormatPatchOptions {
+            max_count: Some(2),
+            output_directory: Some(dir.clone()),
+            signature: Some("sig".to_string()),
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        good_git::format_patch(&repo, &[], &options, &mut stdout).unwrap();
+        let first = dir.join("0001-Nothing-at-all.patch");
+        let second = dir.join("0002-Change-test.txt.patch");
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            format!("{}\n{}\n", first.display(), second.display())
+        );
+        // Like git, empty commits have empty files.
+        assert_eq!(std::fs::read(&first).unwrap(), b"");
+        let mail = std::fs::read_to_string(&second).unwrap();
+        assert!(mail.contains("\nFrom: =?UTF-8?q?J=C3=B6rg?= <jorg@example.com>\n"));
+        assert!(mail.contains(
+            "\nSubject: [PATCH 2/2] Change test.txt\n\
+             \n\
+             And add new.txt.\n\
+             ---\n \
+             new.txt  | 1 +\n \
+             test.txt | 2 +-\n \
+             2 files changed, 2 insertions(+), 1 deletion(-)\n \
+             create mode 100644 new.txt\n\
+             \n\
+             diff --git a/new.txt b/new.txt\n"
+        ));
+        assert!(mail.ends_with("+2\n-- \nsig\n\n"));
+
+        // The mail is a patch that can be applied.
+        let options = good_git::ApplyOptions {
+            reverse: true,
+            ..Default::default()
+        };
+        good_git::apply(&repo, mail.as_bytes(), &options, &mut Vec::new()).unwrap();
+        assert!(!repo.root.join("new.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("test.txt")).unwrap(),
+            "one\ntwo\n"
+        );
+
+        // Empty commits are left out of mboxes, though they keep their number.
+        let options = good_git::FormatPatchOptions {
+            max_count: Some(3),
+            stdout: true,
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        good_git::format_patch(&repo, &[], &options, &mut stdout).unwrap();
+        let mbox = String::from_utf8(stdout).unwrap();
+        let subjects: Vec<&str> = mbox
+            .lines()
+            .filter(|line| line.starts_with("Subject: "))
+            .collect();
+        assert_eq!(
+            subjects,
+            [
+                "Subject: [PATCH 1/3] Add test.txt",
+                "Subject: [PATCH 3/3] Change test.txt"
+            ]
+        );
+
+        // Renames are detected, and non-ASCII messages have MIME headers.
+        let sources = ["test.txt".to_string()];
+        good_git::mv(
+            &repo,
+            &sources,
+            "moved.txt",
+            &Default::default(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        commit("Move test.txt\n\nTo moved.txt \u{e0} la fois.");
+        let options = good_git::FormatPatchOptions {
+            max_count: Some(1),
+            stdout: true,
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        good_git::format_patch(&repo, &[], &options, &mut stdout).unwrap();
+        assert!(String::from_utf8(stdout).unwrap().ends_with(
+            "\nSubject: [PATCH] Move test.txt\n\
+             MIME-Version: 1.0\n\
+             Content-Type: text/plain; charset=UTF-8\n\
+             Content-Transfer-Encoding: 8bit\n\
+             \n\
+             To moved.txt \u{e0} la fois.\n\
+             ---\n \
+             test.txt => moved.txt | 0\n \
+             1 file changed, 0 insertions(+), 0 deletions(-)\n \
+             rename test.txt => moved.txt (100%)\n\
+             \n\
+             diff --git a/test.txt b/moved.txt\n\
+             similarity index 100%\n\
+             rename from test.txt\n\
+             rename to moved.txt\n"
+        ));
+    }
+
+    #[rstest]
+    fn test_am() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let init = |name: &str, user: &str| {
+            init_user_repo(
+                &tmpdir.path().join(name),
+                user,
+                &format!("{user}@example.com"),
+            )
+        };
+        let add = |repo: &Repo, path: &str, content: &str| {
+            std::fs::write(repo.root.join(path), content).unwrap();
+            let options = good_git::AddOptions::default();
+            good_git::add(repo, &[path.to_string()], &options, &mut Vec::new()).unwrap();
+        };
+        let commit = |repo: &Repo, message: &str| {
+            let options = good_git::CommitOptions {
+                message: message.to_string(),
+                ..Default::default()
+            };
+            good_git::commit(repo, &options, &mut Vec::new()).unwrap();
+        };
+        let head = |repo: &Repo| match good_git::object::Object::from_rev(repo, "HEAD").unwrap() {
+            good_git::object::Object::Commit(commit) => commit,
+            _ => panic!("HEAD is not a commit"),
+        };
+
+        let upstream = init("upstream", "J\u{f6}rg");
+        add(&upstream, "test.txt", "one\ntwo\n");
+        commit(&upstream, "Add test.txt");
+        add(&upstream, "test.txt", "one\n2\n");
+        add(&upstream, "new.txt", "new\n");
+        commit(&upstream, "Change test.txt\n\nAnd add new.txt.");
+        let options = good_git::FormatPatchOptions {
+            max_count: Some(1),
+            stdout: true,
+            ..Default::default()
+        };
+        let mut mbox = Vec::new();
+        good_git::format_patch(&upstream, &[], &options, &mut mbox).unwrap();
+        let mbox = String::from_utf8(mbox).unwrap();
+
+        let repo = init("repo", "Alice");
+        add(&repo, "test.txt", "one\ntwo\n");
+        commit(&repo, "Start");
+        let mut stdout = Vec::new();
+        good_git::am(&repo, &mbox, &Default::default(), &mut stdout).unwrap();
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            "Applying: Change test.txt\n"
+        );
+        let applied = head(&repo);
+        assert_eq!(applied.message, head(&upstream).message);
+        assert_eq!(applied.author, head(&upstream).author);
+        assert!(applied.committer.starts_with("Alice <Alice@example.com> "));
+        assert_eq!(applied.tree, head(&upstream).tree);
+        assert!(!good_git::am::dir(&repo).exists());
+
+        // The session stops at a patch that doesn't apply.
+        let repo = init("other", "Alice");
+        add(&repo, "test.txt", "ONE\ntwo\n");
+        commit(&repo, "Shout");
+        let shout = good_git::refs::find_ref(&repo, "HEAD").unwrap();
+        assert!(good_git::am(&repo, &mbox, &Default::default(), &mut Vec::new()).is_err());
+        assert!(good_git::am::dir(&repo).exists());
+        // Another session can't start until then.
+        assert!(good_git::am(&repo, &mbox, &Default::default(), &mut Vec::new()).is_err());
+        let resume = |resume| good_git::am_resume(&repo, resume, &mut Vec::new());
+        assert!(resume(good_git::AmResume::Continue)
+            .unwrap_err()
+            .to_string()
+            .starts_with("No changes"));
+
+        add(&repo, "test.txt", "ONE\n2\n");
+        add(&repo, "new.txt", "new\n");
+        resume(good_git::AmResume::Continue).unwrap();
+        assert_eq!(head(&repo).parents, [shout]);
+        assert_eq!(head(&repo).author, applied.author);
+        assert!(!good_git::am::dir(&repo).exists());
+        assert!(resume(good_git::AmResume::Abort).is_err());
+
+        // Aborting goes back to where HEAD was.
+        assert!(good_git::am(&repo, &mbox, &Default::default(), &mut Vec::new()).is_err());
+        add(&repo, "new.txt", "changed\n");
+        resume(good_git::AmResume::Abort).unwrap();
+        assert!(!good_git::am::dir(&repo).exists());
+        assert_eq!(head(&repo).message, applied.message);
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("new.txt")).unwrap(),
+            "new\n"
+        );
+
+        // Commits made after the session stopped are kept.
+        assert!(good_git::am(&repo, &mbox, &Default::default(), &mut Vec::new()).is_err());
+        add(&repo, "mine.txt", "mine\n");
+        commit(&repo, "My work");
+        let mut stdout = Vec::new();
+        good_git::am_resume(&repo, good_git::AmResume::Abort, &mut stdout).unwrap();
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            "You seem to have moved HEAD since the last 'am' failure.\n\
+             Not rewinding to ORIG_HEAD\n"
+        );
+        assert!(!good_git::am::dir(&repo).exists());
+        assert_eq!(head(&repo).message, "My work");
+        assert!(repo.root.join("mine.txt").exists());
+
+        // With --3way, patches whose context changed are merged.
+        add(&upstream, "test.txt", "1\n2\n3\n4\n5\n6\n7\n8\n");
+        commit(&upstream, "Count");
+        add(&upstream, "test.txt", "1\nTWO\n3\n4\n5\n6\n7\n8\n");
+        commit(&upstream, "Shout two");
+        let mut mbox = Vec::new();
+        good_git::format_patch(&upstream, &[], &options, &mut mbox).unwrap();
+        let mbox = String::from_utf8(mbox).unwrap();
+        let three_way = good_git::AmOptions { three_way: true };
+        let repo = init("merged", "Alice");
+        add(&repo, "test.txt", "1\n2\n3\n4\n5\n6\n7\n8\n");
+        commit(&repo, "Count");
+        add(&repo, "test.txt", "1\n2\n3\n4\nFIVE\n6\n7\n8\n");
+        commit(&repo, "Shout five");
+        let mut stdout = Vec::new();
+        good_git::am(&repo, &mbox, &three_way, &mut stdout).unwrap();
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            "Applying: Shout two\n\
+             Using index info to reconstruct a base tree...\n\
+             Falling back to patching base and 3-way merge...\n\
+             Auto-merging test.txt\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("test.txt")).unwrap(),
+            "1\nTWO\n3\n4\nFIVE\n6\n7\n8\n"
+        );
+        assert_eq!(head(&repo).message, "Shout two");
+
+        // Conflicts are left with markers.
+        let repo = init("conflicted", "Alice");
+        add(&repo, "test.txt", "1\n2\n3\n4\n5\n6\n7\n8\n");
+        commit(&repo, "Count");
+        add(&repo, "test.txt", "1\nzwei\n3\n4\n5\n6\n7\n8\n");
+        commit(&repo, "Translate two");
+        let mut stdout = Vec::new();
+        assert!(good_git::am(&repo, &mbox, &three_way, &mut stdout).is_err());
+        assert!(String::from_utf8(stdout)
+            .unwrap()
+            .ends_with("CONFLICT (content): Merge conflict in test.txt\n"));
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("test.txt")).unwrap(),
+            "1\n<<<<<<< HEAD\nzwei\n=======\nTWO\n>>>>>>> Shout two\n3\n4\n5\n6\n7\n8\n"
+        );
+        let stages: Vec<u8> = good_git::index::Index::read(&repo)
+            .unwrap()
+            .entries
+            .iter()
+            .map(|entry| entry.stage)
+            .collect();
+        assert_eq!(stages, [1, 2, 3]);
+    }
+
+    #[rstest]
+    fn test_cherry() {
+        let (_tmpdir, repo) = user_repo("A U Thor", "author@example.com");
+        let commit = |path: &str, content: &str, message: &str| {
+            add_and_commit(&repo, path, content, message)
+        };
+        let base = commit("a.txt", "a\n", "Add a.txt");
+        good_git::refs::update_ref(&repo, "refs/heads/topic", &base).unwrap();
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        let picked = commit("b.txt", "b\n", "Add b.txt");
+        let kept = commit("a.txt", "a\nchanged\n", "Change a.txt");
+        good_git::switch_branch(&repo, "main", &mut Vec::new()).unwrap();
+        commit("c.txt", "c\n", "Add c.txt");
+        // The same change, with another message.
+        commit("b.txt", "b\n", "Add b.txt again");
+
+        let cherry = |upstream: Option<&str>, limit: Option<&str>, verbose: bool| {
+            let mut stdout = Vec::new();
+            good_git::cherry(&repo, upstream, Some("topic"), limit, verbose, &mut stdout)
+                .map(|()| String::from_utf8(stdout).unwrap())
+        };
+        assert_eq!(
+            cherry(Some("main"), None, false).unwrap(),
+            format!("- {picked}\n+ {kept}\n")
+        );
+        assert_eq!(
+            cherry(Some("main"), Some(&picked), true).unwrap(),
+            format!("+ {kept} Change a.txt\n")
+        );
+        assert_eq!(cherry(Some("topic"), None, false).unwrap(), "");
+        assert_eq!(
+            cherry(None, None, false).unwrap_err().to_string(),
+            "Could not find a tracked remote branch, please specify <upstream> manually."
+        );
+
+        // The patch IDs of patches are those of their commits.
+        let mut mbox = vec![];
+        let options = good_git::FormatPatchOptions {
+            stdout: true,
+            ..Default::default()
+        };
+        good_git::format_patch(&repo, &["topic..main".to_string()], &options, &mut mbox).unwrap();
+        let ids = good_git::patch_id::patch_ids(&mbox, true, false);
+        let config = good_git::config::Config::load(&repo).unwrap();
+        let classifier = good_git::binary::BinaryClassifier::from_config(&config).unwrap();
+        assert_eq!(ids.len(), 2);
+        for (id, hash) in ids {
+            let good_git::object::Object::Commit(commit) =
+                good_git::object::Object::from_hash(&repo, &hash).unwrap()
+            else {
+                panic!("Not a commit: {hash}");
+            };
+            let expected = good_git::patch_id::commit_patch_id(&repo, &classifier, &commit);
+            assert_eq!(Some(id), expected.unwrap());
+        }
+    }
+
+    #[rstest]
+    fn test_range_diff() {
+        let (_tmpdir, repo) = user_repo("A U Thor", "author@example.com");
+        let commit = |path: &str, content: &str, message: &str| {
+            add_and_commit(&repo, path, content, message)
+        };
+        let base = commit("a.txt", "1\n2\n3\n4\n5\n6\n7\n8\n9\n", "Add a.txt");
+        good_git::refs::update_ref(&repo, "refs/heads/v1", &base).unwrap();
+        good_git::refs::update_ref(&repo, "refs/heads/v2", &base).unwrap();
+        good_git::switch_branch(&repo, "v1", &mut Vec::new()).unwrap();
+        let old_b = commit("b.txt", "b\n", "Add b.txt");
+        let old_a = commit("a.txt", "1\n2\n3\n4\nB\n6\n7\n8\n9\n", "Change a.txt");
+        let dropped = commit("c.txt", "c\n", "Add c.txt");
+        good_git::switch_branch(&repo, "v2", &mut Vec::new()).unwrap();
+        let new_b = commit("b.txt", "b\n", "Add b.txt");
+        let new_a = commit("a.txt", "1\n2\n3\n4\nBB\n6\n7\n8\n9\n", "Change a.txt");
+        let added = commit("d.txt", "d\n", "Add d.txt");
+
+        let mut stdout = Vec::new();
+        let options = good_git::RangeDiffOptions::default();
+        good_git::range_diff(&repo, "main..v1", "main..v2", &options, &mut stdout).unwrap();
+        let [old_b, old_a, dropped, new_b, new_a, added] =
+            [old_b, old_a, dropped, new_b, new_a, added].map(|hash| {
+                good_git::object::Abbreviator::from_config(&repo)
+                    .unwrap()
+                    .abbreviate(&hash)
+                    .unwrap()
+            });
+        let dashes = "-".repeat(old_b.len());
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            format!(
+                "1:  {old_b} = 1:  {new_b} Add b.txt\n\
+                 2:  {old_a} ! 2:  {new_a} Change a.txt\n    \
+                 @@ a.txt\n    \
+                 \x20 3\n    \
+                 \x20 4\n    \
+                 \x20-5\n    \
+                 -+B\n    \
+                 ++BB\n    \
+                 \x20 6\n    \
+                 \x20 7\n    \
+                 \x20 8\n\
+                 3:  {dropped} < -:  {dashes} Add c.txt\n\
+                 -:  {dashes} > 3:  {added} Add d.txt\n"
+            )
+        );
+    }
+
+    #[rstest]
+    fn test_merge() {
+        let (_tmpdir, repo) = user_repo("A U Thor", "author@example.com");
+        let commit = |path: &str, content: &str, message: &str| {
+            add_and_commit(&repo, path, content, message)
+        };
+        let merge = |options: &good_git::MergeOptions| {
+            let mut stdout = Vec::new();
+            good_git::merge(&repo, "topic", options, &mut stdout)
+                .map(|clean| (clean, String::from_utf8(stdout).unwrap()))
+        };
+        let base = commit("a.txt", "1\n2\n3\n4\n5\n6\n7\n8\n9\n", "Add a.txt");
+        good_git::refs::update_ref(&repo, "refs/heads/topic", &base).unwrap();
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        let topic = commit("b.txt", "b\n", "Add b.txt");
+        good_git::switch_branch(&repo, "main", &mut Vec::new()).unwrap();
+
+        let options = good_git::MergeOptions {
+            fast_forward: good_git::FastForward::Only,
+            ..Default::default()
+        };
+        let (clean, stdout) = merge(&options).unwrap();
+        assert!(clean);
+        let [base_abbrev, topic_abbrev] = [&base, &topic].map(|hash| {
+            good_git::object::Abbreviator::from_config(&repo)
+                .unwrap()
+                .abbreviate(hash)
+                .unwrap()
+        });
+        assert_eq!(
+            stdout,
+            format!(
+                "Updating {base_abbrev}..{topic_abbrev}\n\
+                 Fast-forward\n \
+                 b.txt | 1 +\n \
+                 1 file changed, 1 insertion(+)\n \
+                 create mode 100644 b.txt\n"
+            )
+        );
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), topic);
+        assert_eq!(good_git::refs::find_ref(&repo, "ORIG_HEAD").unwrap(), base);
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("b.txt")).unwrap(),
+            "b\n"
+        );
+        assert_eq!(
+            merge(&Default::default()).unwrap(),
+            (true, "Already up to date.\n".to_string())
+        );
+
+        // Changes on both sides are merged in a merge commit.
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        let topic = commit("a.txt", "1\ntwo\n3\n4\n5\n6\n7\n8\n9\n", "Change 2");
+        good_git::switch_branch(&repo, "main", &mut Vec::new()).unwrap();
+        let head = commit("a.txt", "1\n2\n3\n4\n5\n6\n7\neight\n9\n", "Change 8");
+        assert_eq!(
+            merge(&options).unwrap_err().to_string(),
+            "Not possible to fast-forward, aborting."
+        );
+        let (clean, stdout) = merge(&Default::default()).unwrap();
+        assert!(clean);
+        assert_eq!(
+            stdout,
+            "Auto-merging a.txt\n\
+             Merge made by the 'ort' strategy.\n \
+             a.txt | 2 +-\n \
+             1 file changed, 1 insertion(+), 1 deletion(-)\n"
+        );
+        let merged = match good_git::object::Object::from_rev(&repo, "HEAD").unwrap() {
+            good_git::object::Object::Commit(commit) => commit,
+            _ => panic!("HEAD is not a commit"),
+        };
+        assert_eq!(merged.parents, [head, topic]);
+        assert_eq!(merged.message, "Merge branch 'topic'");
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("a.txt")).unwrap(),
+            "1\ntwo\n3\n4\n5\n6\n7\neight\n9\n"
+        );
+
+        // Conflicts are left in the index and the working tree.
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        let topic = commit("a.txt", "1\ntwo\n3\n4\nfive\n6\n7\n8\n9\n", "Change 5");
+        good_git::switch_branch(&repo, "main", &mut Vec::new()).unwrap();
+        let head = commit("a.txt", "1\ntwo\n3\n4\nFIVE\n6\n7\neight\n9\n", "Shout 5");
+        let (clean, stdout) = merge(&Default::default()).unwrap();
+        assert!(!clean);
+        assert_eq!(
+            stdout,
+            "Auto-merging a.txt\n\
+             CONFLICT (content): Merge conflict in a.txt\n\
+             Automatic merge failed; fix conflicts and then commit the result.\n"
+        );
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), head);
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("a.txt")).unwrap(),
+            "1\ntwo\n3\n4\n<<<<<<< HEAD\nFIVE\n=======\nfive\n>>>>>>> topic\n6\n7\neight\n9\n"
+        );
+        let index = good_git::index::Index::read(&repo).unwrap();
+        let stages: Vec<_> = index
+            .entries
+            .iter()
+            .filter(|entry| entry.path == "a.txt")
+            .map(|entry| entry.stage)
+            .collect();
+        assert_eq!(stages, [1, 2, 3]);
+        assert_eq!(
+            merge(&Default::default()).unwrap_err().to_string(),
+            "Merging is not possible because you have unmerged files."
+        );
+        let state = good_git::merge::State::read(&repo).unwrap().unwrap();
+        assert_eq!(state.merge_heads, [topic.clone()]);
+        assert_eq!(
+            state.message,
+            "Merge branch 'topic'\n\n# Conflicts:\n#\ta.txt\n"
+        );
+        let mut stdout = Vec::new();
+        good_git::status(&repo, &Default::default(), &mut stdout).unwrap();
+        assert!(String::from_utf8(stdout)
+            .unwrap()
+            .contains("Unmerged paths:\n  (use \"git add <file>...\" to mark resolution)\n\tboth modified:   a.txt\n"));
+        // HEAD can't move until the merge is concluded.
+        assert_eq!(
+            good_git::switch_branch(&repo, "topic", &mut Vec::new())
+                .unwrap_err()
+                .to_string(),
+            "cannot switch branch while merging"
+        );
+        assert!(good_git::checkout_detach(&repo, Some(&topic), &mut Vec::new()).is_err());
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), head);
+
+        // Committing the resolution concludes the merge, with its message.
+        commit("a.txt", "1\ntwo\n3\n4\nFive\n6\n7\neight\n9\n", "");
+        let merged = match good_git::object::Object::from_rev(&repo, "HEAD").unwrap() {
+            good_git::object::Object::Commit(commit) => commit,
+            _ => panic!("HEAD is not a commit"),
+        };
+        assert_eq!(merged.parents, [head, topic]);
+        assert_eq!(
+            merged.message,
+            "Merge branch 'topic'\n\n# Conflicts:\n#\ta.txt"
+        );
+        assert_eq!(good_git::merge::State::read(&repo).unwrap(), None);
+        assert_eq!(
+            good_git::merge_abort(&repo).unwrap_err().to_string(),
+            "There is no merge to abort (MERGE_HEAD missing)."
+        );
+
+        // Aborting a merge goes back to HEAD, keeping other local changes.
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        commit(
+            "a.txt",
+            "one
+two
+3
+4
+five
+6
+7
+8
+9
+",
+            "Change 1",
+        );
+        good_git::switch_branch(&repo, "main", &mut Vec::new()).unwrap();
+        let head = commit(
+            "a.txt",
+            "ONE
+two
+3
+4
+Five
+6
+7
+eight
+9
+",
+            "Shout 1",
+        );
+        std::fs::write(repo.root.join("b.txt"), "local\n").unwrap();
+        assert!(!merge(&Default::default()).unwrap().0);
+        good_git::merge_abort(&repo).unwrap();
+        assert_eq!(good_git::merge::State::read(&repo).unwrap(), None);
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), head);
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("a.txt")).unwrap(),
+            "ONE\ntwo\n3\n4\nFive\n6\n7\neight\n9\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("b.txt")).unwrap(),
+            "local\n"
+        );
+        let index = good_git::index::Index::read(&repo).unwrap();
+        assert!(index.entries.iter().all(|entry| entry.stage == 0));
+
+        // Renames are shown in the diffstat.
+        std::fs::write(repo.root.join("b.txt"), "b\n").unwrap();
+        good_git::refs::update_ref(&repo, "refs/heads/topic", &head).unwrap();
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        let sources = ["a.txt".to_string()];
+        good_git::mv(
+            &repo,
+            &sources,
+            "c.txt",
+            &Default::default(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        commit(
+            "c.txt",
+            "ONE\ntwo\n3\n4\nFive\n6\n7\neight\nnine\n",
+            "Move a.txt",
+        );
+        good_git::switch_branch(&repo, "main", &mut Vec::new()).unwrap();
+        let (clean, stdout) = merge(&Default::default()).unwrap();
+        assert!(clean);
+        assert!(stdout.ends_with(
+            "Fast-forward\n \
+             a.txt => c.txt | 2 +-\n \
+             1 file changed, 1 insertion(+), 1 deletion(-)\n \
+             rename a.txt => c.txt (84%)\n"
+        ));
+    }
+
+    #[rstest]
+    fn test_merge_squash_and_no_commit() {
+        let (_tmpdir, repo) = user_repo("A U Thor", "author@example.com");
+        let commit = |path: &str, content: &str, message: &str| {
+            add_and_commit(&repo, path, content, message)
+        };
+        let merge = |options: &good_git::MergeOptions| {
+            let mut stdout = Vec::new();
+            good_git::merge(&repo, "topic", options, &mut stdout)
+                .map(|clean| (clean, String::from_utf8(stdout).unwrap()))
+        };
+        let head_commit = || match good_git::object::Object::from_rev(&repo, "HEAD").unwrap() {
+            good_git::object::Object::Commit(commit) => commit,
+            _ => panic!("HEAD is not a commit"),
+        };
+        let base = commit("a.txt", "1\n2\n3\n4\n5\n6\n7\n8\n9\n", "Add a.txt");
+        good_git::refs::update_ref(&repo, "refs/heads/topic", &base).unwrap();
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        let topic = commit("a.txt", "1\ntwo\n3\n4\n5\n6\n7\n8\n9\n", "Change 2");
+        good_git::switch_branch(&repo, "main", &mut Vec::new()).unwrap();
+        let head = commit("a.txt", "1\n2\n3\n4\n5\n6\n7\neight\n9\n", "Change 8");
+
+        // A squash merge stages the result without moving HEAD.
+        let squash = good_git::MergeOptions {
+            squash: true,
+            ..Default::default()
+        };
+        let (clean, stdout) = merge(&squash).unwrap();
+        assert!(clean);
+        assert_eq!(
+            stdout,
+            "Auto-merging a.txt\n\
+             Automatic merge went well; stopped before committing as requested\n\
+             Squash commit -- not updating HEAD\n"
+        );
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), head);
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("a.txt")).unwrap(),
+            "1\ntwo\n3\n4\n5\n6\n7\neight\n9\n"
+        );
+        let state = good_git::merge::State::read(&repo).unwrap().unwrap();
+        assert!(state.merge_heads.is_empty());
+        assert!(repo.git_dir().join("SQUASH_MSG").exists());
+        assert!(state.message.starts_with(&format!(
+            "Squashed commit of the following:\n\ncommit {topic}\nAuthor: A U Thor <author@example.com>\n"
+        )));
+        assert!(state.message.ends_with("\n\n    Change 2\n"));
+
+        // Its commit has a single parent.
+        commit("a.txt", "1\ntwo\n3\n4\n5\n6\n7\neight\n9\n", "");
+        let squashed = head_commit();
+        assert_eq!(squashed.parents, [head]);
+        assert!(squashed
+            .message
+            .starts_with("Squashed commit of the following:"));
+        assert_eq!(good_git::merge::State::read(&repo).unwrap(), None);
+
+        // --no-commit stops before the merge commit, which the next commit
+        // creates.
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        let topic = commit("a.txt", "1\ntwo\n3\n4\nfive\n6\n7\n8\n9\n", "Change 5");
+        good_git::switch_branch(&repo, "main", &mut Vec::new()).unwrap();
+        let head = good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap();
+        let no_commit = good_git::MergeOptions {
+            no_commit: true,
+            ..Default::default()
+        };
+        let (clean, stdout) = merge(&no_commit).unwrap();
+        assert!(clean);
+        assert_eq!(
+            stdout,
+            "Auto-merging a.txt\n\
+             Automatic merge went well; stopped before committing as requested\n"
+        );
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), head);
+        let state = good_git::merge::State::read(&repo).unwrap().unwrap();
+        assert_eq!(state.merge_heads, [topic.clone()]);
+        assert_eq!(state.message, "Merge branch 'topic'\n");
+        commit("a.txt", "1\ntwo\n3\n4\nfive\n6\n7\neight\n9\n", "");
+        let merged = head_commit();
+        assert_eq!(merged.parents, [head, topic]);
+        assert_eq!(merged.message, "Merge branch 'topic'");
+
+        let options = good_git::MergeOptions {
+            fast_forward: good_git::FastForward::Never,
+            squash: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            merge(&options).unwrap_err().to_string(),
+            "You cannot combine --squash with --no-ff."
+        );
+    }
+
+    #[rstest]
+    fn test_cherry_pick() {
+        let (_tmpdir, repo) = user_repo("A U Thor", "author@example.com");
+        let commit = |path: &str, content: &str, message: &str| {
+            std::fs::write(repo.root.join(path), content).unwrap();
+            let options = good_git::AddOptions::default();
+            good_git::add(&repo, &[path.to_string()], &options, &mut Vec::new()).unwrap();
+            let options = good_git::CommitOptions {
+                message: message.to_string(),
+                author: good_git::ident::Ident::parse("Other <other@example.com> 1 +0000"),
+                ..Default::default()
+            };
+            good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+            good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap()
+        };
+        let head_commit = || match good_git::object::Object::from_rev(&repo, "HEAD").unwrap() {
+            good_git::object::Object::Commit(commit) => commit,
+            _ => panic!("HEAD is not a commit"),
+        };
+        let cherry_pick = |revs: &[&str], options: &good_git::CherryPickOptions| {
+            let revs: Vec<String> = revs.iter().map(|rev| rev.to_string()).collect();
+            let mut stdout = Vec::new();
+            good_git::cherry_pick(&repo, &revs, options, &mut stdout)
+                .map(|_| String::from_utf8(stdout).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        let read = |path: &str| std::fs::read_to_string(repo.root.join(path)).unwrap();
+        let base = commit("a.txt", "1\n2\n3\n4\n5\n6\n7\n8\n9\n", "Add a.txt");
+        good_git::refs::update_ref(&repo, "refs/heads/topic", &base).unwrap();
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        let two = commit("a.txt", "1\ntwo\n3\n4\n5\n6\n7\n8\n9\n", "Change 2");
+        let five = commit("a.txt", "1\ntwo\n3\n4\nfive\n6\n7\n8\n9\n", "Change 5");
+        commit("a.txt", "1\ntwo\n3\n4\nfive\n6\n7\neight\n9\n", "Change 8");
+        good_git::switch_branch(&repo, "main", &mut Vec::new()).unwrap();
+        let head = commit("a.txt", "1\n2\n3\n4\nFIVE\n6\n7\n8\n9\n", "Shout 5");
+
+        // The change is committed with the author and message of the commit.
+        let stdout = cherry_pick(&[&two], &Default::default()).unwrap();
+        assert!(stdout.starts_with("Auto-merging a.txt\n[main "));
+        let picked = head_commit();
+        assert_eq!(picked.parents, [head.clone()]);
+        assert_eq!(picked.message, "Change 2");
+        assert!(picked.author.starts_with("Other <other@example.com> 1 "));
+        assert_eq!(read("a.txt"), "1\ntwo\n3\n4\nFIVE\n6\n7\n8\n9\n");
+
+        // A range stops at conflicts, with the commits left.
+        let picked = good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap();
+        let range = format!("{two}..topic");
+        let abbrev = good_git::object::Abbreviator::from_config(&repo)
+            .unwrap()
+            .abbreviate(&five)
+            .unwrap();
+        let error = cherry_pick(&[&range], &Default::default()).unwrap_err();
+        assert!(error.starts_with(&format!("could not apply {abbrev}... Change 5\n")));
+        assert_eq!(
+            read("a.txt"),
+            format!(
+                "1\ntwo\n3\n4\n<<<<<<< HEAD\nFIVE\n=======\nfive\n>>>>>>> {abbrev} (Change 5)\n6\n7\n8\n9\n"
+            )
+        );
+        let state = good_git::merge::State::read(&repo).unwrap().unwrap();
+        assert_eq!(state.cherry_pick_head.as_ref(), Some(&five));
+        assert_eq!(state.message, "Change 5\n\n# Conflicts:\n#\ta.txt\n");
+        let sequencer = good_git::sequencer::State::read(&repo).unwrap().unwrap();
+        assert_eq!(sequencer.head.as_ref(), Some(&picked));
+        assert_eq!(sequencer.todo.len(), 2);
+        assert_eq!(
+            cherry_pick(&[&two], &Default::default()).unwrap_err(),
+            "cherry-pick is already in progress\n\
+             hint: try \"git cherry-pick (--continue | --abort | --quit)\""
+        );
+
+        // Continuing commits the resolution and picks the other commits.
+        let resume = |resume| good_git::cherry_pick_resume(&repo, resume, &mut Vec::new());
+        assert_eq!(
+            resume(good_git::SequencerResume::Continue)
+                .unwrap_err()
+                .to_string(),
+            "Committing is not possible because you have unmerged files."
+        );
+        std::fs::write(repo.root.join("a.txt"), "1\ntwo\n3\n4\nFive\n6\n7\n8\n9\n").unwrap();
+        let options = good_git::AddOptions::default();
+        good_git::add(&repo, &["a.txt".to_string()], &options, &mut Vec::new()).unwrap();
+        resume(good_git::SequencerResume::Continue).unwrap();
+        assert_eq!(head_commit().message, "Change 8");
+        assert_eq!(read("a.txt"), "1\ntwo\n3\n4\nFive\n6\n7\neight\n9\n");
+        assert_eq!(good_git::merge::State::read(&repo).unwrap(), None);
+        assert_eq!(good_git::sequencer::State::read(&repo).unwrap(), None);
+        let reflog = std::fs::read_to_string(repo.git_dir().join("logs/HEAD")).unwrap();
+        let actions: Vec<&str> = reflog
+            .lines()
+            .rev()
+            .take(3)
+            .map(|line| line.split_once('\t').unwrap().1)
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                "cherry-pick: Change 8",
+                "commit (cherry-pick): Change 5",
+                "cherry-pick: Change 2"
+            ]
+        );
+
+        // Aborting goes back to where HEAD was.
+        let head = good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap();
+        cherry_pick(&[&base, &five], &Default::default()).unwrap_err();
+        resume(good_git::SequencerResume::Abort).unwrap();
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), head);
+        assert_eq!(read("a.txt"), "1\ntwo\n3\n4\nFive\n6\n7\neight\n9\n");
+        assert_eq!(good_git::merge::State::read(&repo).unwrap(), None);
+        assert_eq!(good_git::sequencer::State::read(&repo).unwrap(), None);
+        assert_eq!(
+            resume(good_git::SequencerResume::Abort)
+                .unwrap_err()
+                .to_string(),
+            "no cherry-pick or revert in progress"
+        );
+
+        // HEAD isn't rewound if it moved since the sequence stopped.
+        cherry_pick(&[&base, &five], &Default::default()).unwrap_err();
+        let moved = commit("a.txt", "resolved\n", "Resolve");
+        let mut stdout = Vec::new();
+        good_git::cherry_pick_resume(&repo, good_git::SequencerResume::Abort, &mut stdout).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            "warning: You seem to have moved HEAD. Not rewinding, check your HEAD!\n"
+        );
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), moved);
+        assert_eq!(good_git::sequencer::State::read(&repo).unwrap(), None);
+        commit("a.txt", "1\ntwo\n3\n4\nFive\n6\n7\neight\n9\n", "Restore");
+
+        // Without committing, the changes are only staged.
+        let options = good_git::CherryPickOptions { no_commit: true };
+        let new = commit("b.txt", "b\n", "Add b.txt");
+        good_git::refs::update_ref(&repo, "refs/heads/main", &head).unwrap();
+        cherry_pick(&[&new], &options).unwrap();
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), head);
+        assert_eq!(read("b.txt"), "b\n");
+        let index = good_git::index::Index::read(&repo).unwrap();
+        assert!(index.entries.iter().any(|entry| entry.path == "b.txt"));
+        assert_eq!(good_git::merge::State::read(&repo).unwrap(), None);
+    }
+
+    #[rstest]
+    fn test_revert() {
+        let (_tmpdir, repo) = user_repo("A U Thor", "author@example.com");
+        let commit = |path: &str, content: &str, message: &str| {
+            std::fs::write(repo.root.join(path), content).unwrap();
+            let options = good_git::AddOptions::default();
+            good_git::add(&repo, &[path.to_string()], &options, &mut Vec::new()).unwrap();
+            let options = good_git::CommitOptions {
+                message: message.to_string(),
+                author: good_git::ident::Ident::parse("Other <other@example.com> 1 +0000"),
+                ..Default::default()
+            };
+            good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+            good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap()
+        };
+        let head_commit = || match good_git::object::Object::from_rev(&repo, "HEAD").unwrap() {
+            good_git::object::Object::Commit(commit) => commit,
+            _ => panic!("HEAD is not a commit"),
+        };
+        let revert = |rev: &str, options: &good_git::RevertOptions| {
+            let mut stdout = Vec::new();
+            good_git::revert(&repo, &[rev.to_string()], options, &mut stdout)
+                .map(|_| String::from_utf8(stdout).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        let read = |path: &str| std::fs::read_to_string(repo.root.join(path)).unwrap();
+        commit("a.txt", "1\n2\n3\n4\n5\n6\n7\n8\n9\n", "Add a.txt");
+        let two = commit("a.txt", "1\ntwo\n3\n4\n5\n6\n7\n8\n9\n", "Change 2");
+        let five = commit("a.txt", "1\ntwo\n3\n4\nfive\n6\n7\n8\n9\n", "Change 5");
+        commit("a.txt", "1\ntwo\n3\n4\nFIVE\n6\n7\n8\n9\n", "Shout 5");
+
+        // The inverse change is committed by the current author.
+        let head = good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap();
+        revert(&two, &Default::default()).unwrap();
+        let reverted = head_commit();
+        assert_eq!(reverted.parents, [head]);
+        assert_eq!(
+            reverted.message,
+            format!("Revert \"Change 2\"\n\nThis reverts commit {two}.")
+        );
+        assert!(reverted
+            .author
+            .starts_with("A U Thor <author@example.com> "));
+        assert_eq!(read("a.txt"), "1\n2\n3\n4\nFIVE\n6\n7\n8\n9\n");
+        let reflog = std::fs::read_to_string(repo.git_dir().join("logs/HEAD")).unwrap();
+        assert!(reflog.ends_with("\trevert: Revert \"Change 2\"\n"));
+
+        // Conflicts are left for the commit concluding the revert.
+        let head = good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap();
+        let abbrev = good_git::object::Abbreviator::from_config(&repo)
+            .unwrap()
+            .abbreviate(&five)
+            .unwrap();
+        let error = revert(&five, &Default::default()).unwrap_err();
+        assert!(error.starts_with(&format!("could not revert {abbrev}... Change 5\n")));
+        assert_eq!(
+            read("a.txt"),
+            format!(
+                "1\n2\n3\n4\n<<<<<<< HEAD\nFIVE\n=======\n5\n>>>>>>> parent of {abbrev} (Change 5)\n6\n7\n8\n9\n"
+            )
+        );
+        let state = good_git::merge::State::read(&repo).unwrap().unwrap();
+        assert_eq!(state.revert_head.as_ref(), Some(&five));
+        assert_eq!(
+            state.message,
+            format!(
+                "Revert \"Change 5\"\n\nThis reverts commit {five}.\n\n# Conflicts:\n#\ta.txt\n"
+            )
+        );
+        assert_eq!(
+            good_git::cherry_pick_resume(&repo, good_git::SequencerResume::Skip, &mut Vec::new())
+                .unwrap_err()
+                .to_string(),
+            "no cherry-pick in progress"
+        );
+        commit("a.txt", "1\n2\n3\n4\n5\n6\n7\n8\n9\n", "");
+        let reverted = head_commit();
+        assert_eq!(reverted.parents, [head.clone()]);
+        assert!(reverted.message.starts_with("Revert \"Change 5\"\n"));
+        assert_eq!(good_git::merge::State::read(&repo).unwrap(), None);
+
+        // Without committing, the inverse change is staged, and the revert
+        // recorded for the message of the commit.
+        let head = good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap();
+        let options = good_git::RevertOptions { no_commit: true };
+        revert(&head, &options).unwrap();
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), head);
+        assert_eq!(read("a.txt"), "1\n2\n3\n4\nFIVE\n6\n7\n8\n9\n");
+        let state = good_git::merge::State::read(&repo).unwrap().unwrap();
+        assert_eq!(state.revert_head, Some(head));
+    }
+
+    #[test]
+    fn test_rebase() {
+        let (_tmpdir, repo) = user_repo("A U Thor", "author@example.com");
+        let commit = |path: &str, content: &str, message: &str| {
+            std::fs::write(repo.root.join(path), content).unwrap();
+            let options = good_git::AddOptions::default();
+            good_git::add(&repo, &[path.to_string()], &options, &mut Vec::new()).unwrap();
+            let options = good_git::CommitOptions {
+                message: message.to_string(),
+                author: good_git::ident::Ident::parse("Other <other@example.com> 1 +0000"),
+                ..Default::default()
+            };
+            good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+            good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap()
+        };
+        let commit_of = |rev: &str| match good_git::object::Object::from_rev(&repo, rev).unwrap() {
+            good_git::object::Object::Commit(commit) => commit,
+            _ => panic!("{rev} is not a commit"),
+        };
+        let rebase = |upstream: &str| {
+            let mut stdout = Vec::new();
+            good_git::rebase(&repo, upstream, &Default::default(), &mut stdout)
+                .map(|_| String::from_utf8(stdout).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        let resume = |resume: good_git::RebaseResume| {
+            let mut stdout = Vec::new();
+            good_git::rebase_resume(&repo, resume, &mut stdout)
+                .map(|_| String::from_utf8(stdout).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        let read = |path: &str| std::fs::read_to_string(repo.root.join(path)).unwrap();
+        let base = commit("a.txt", "1\n2\n3\n4\n5\n6\n7\n8\n9\n", "Add a.txt");
+        good_git::refs::update_ref(&repo, "refs/heads/topic", &base).unwrap();
+        let shout = commit("a.txt", "1\n2\n3\n4\nFIVE\n6\n7\n8\n9\n", "Shout 5");
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        commit("a.txt", "1\ntwo\n3\n4\n5\n6\n7\n8\n9\n", "Change 2");
+        let five = commit("a.txt", "1\ntwo\n3\n4\nfive\n6\n7\n8\n9\n", "Change 5");
+        let eight = commit("a.txt", "1\ntwo\n3\n4\nfive\n6\n7\neight\n9\n", "Change 8");
+
+        // The commits are picked onto the upstream until one conflicts.
+        let abbrev = good_git::object::Abbreviator::from_config(&repo)
+            .unwrap()
+            .abbreviate(&five)
+            .unwrap();
+        let error = rebase("main").unwrap_err();
+        assert!(error.starts_with(&format!("could not apply {abbrev}... Change 5\n")));
+        assert_eq!(
+            good_git::refs::head(&repo).unwrap(),
+            good_git::refs::Head::Detached(
+                good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap()
+            )
+        );
+        let picked = commit_of("HEAD");
+        assert_eq!(picked.parents, [shout.clone()]);
+        assert_eq!(picked.message, "Change 2");
+        assert!(picked.author.starts_with("Other <other@example.com> "));
+        let state = good_git::rebase::State::read(&repo).unwrap().unwrap();
+        assert_eq!(state.head_name.as_deref(), Some("refs/heads/topic"));
+        assert_eq!(state.onto, shout);
+        assert_eq!(
+            state.todo,
+            [good_git::rebase::Instruction::new(
+                good_git::rebase::Command::Pick,
+                &eight
+            )]
+        );
+        assert_eq!(state.stopped.as_ref(), Some(&five));
+        assert_eq!(good_git::refs::find_ref(&repo, "ORIG_HEAD").unwrap(), eight);
+        let state = good_git::merge::State::read(&repo).unwrap().unwrap();
+        assert_eq!(state.rebase_head, Some(five));
+        assert_eq!(
+            rebase("main").unwrap_err().lines().next(),
+            Some("It seems that there is already a rebase-merge directory, and")
+        );
+
+        // The resolved commit keeps its author, and the branch is checked out
+        // again at the end.
+        assert_eq!(
+            resume(good_git::RebaseResume::Continue).unwrap_err(),
+            "a.txt: needs merge\nYou must edit all merge conflicts and then\n\
+             mark them as resolved using git add"
+        );
+        std::fs::write(repo.root.join("a.txt"), "1\ntwo\n3\n4\nFive\n6\n7\n8\n9\n").unwrap();
+        let options = good_git::AddOptions::default();
+        good_git::add(&repo, &["a.txt".to_string()], &options, &mut Vec::new()).unwrap();
+        let stdout = resume(good_git::RebaseResume::Continue).unwrap();
+        assert!(stdout.ends_with("Successfully rebased and updated refs/heads/topic.\n"));
+        assert_eq!(
+            good_git::refs::head(&repo).unwrap(),
+            good_git::refs::Head::Branch("refs/heads/topic".to_string())
+        );
+        let rebased = commit_of("HEAD");
+        assert_eq!(rebased.message, "Change 8");
+        let resolved = commit_of(&rebased.parents[0]);
+        assert_eq!(resolved.message, "Change 5");
+        assert!(resolved.author.starts_with("Other <other@example.com> "));
+        assert_eq!(read("a.txt"), "1\ntwo\n3\n4\nFive\n6\n7\neight\n9\n");
+        assert_eq!(good_git::rebase::State::read(&repo).unwrap(), None);
+        let reflog = std::fs::read_to_string(repo.git_dir().join("logs/HEAD")).unwrap();
+        let messages: Vec<&str> = reflog
+            .lines()
+            .map(|line| line.split('\t').nth(1).unwrap())
+            .skip_while(|message| !message.starts_with("rebase"))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "rebase (start): checkout main",
+                "rebase (pick): Change 2",
+                "rebase (continue): Change 5",
+                "rebase (pick): Change 8",
+                "rebase (finish): returning to refs/heads/topic",
+            ]
+        );
+        assert_eq!(
+            rebase("main").unwrap(),
+            "Current branch topic is up to date.\n"
+        );
+
+        // Aborting checks out the branch as it was before.
+        good_git::switch_branch(&repo, "main", &mut Vec::new()).unwrap();
+        commit("a.txt", "1\nTWO\n3\n4\nFIVE\n6\n7\n8\n9\n", "Shout 2");
+        let head = commit("b.txt", "b\n", "Add b.txt");
+        rebase("topic").unwrap_err();
+        resume(good_git::RebaseResume::Abort).unwrap();
+        assert_eq!(
+            good_git::refs::head(&repo).unwrap(),
+            good_git::refs::Head::Branch("refs/heads/main".to_string())
+        );
+        assert_eq!(good_git::refs::find_ref(&repo, "HEAD").unwrap(), head);
+        assert_eq!(read("a.txt"), "1\nTWO\n3\n4\nFIVE\n6\n7\n8\n9\n");
+        assert_eq!(good_git::merge::State::read(&repo).unwrap(), None);
+        assert_eq!(
+            resume(good_git::RebaseResume::Abort).unwrap_err(),
+            "No rebase in progress?"
+        );
+
+        // Skipping drops the commit that conflicts.
+        rebase("topic").unwrap_err();
+        let stdout = resume(good_git::RebaseResume::Skip).unwrap();
+        assert!(stdout.ends_with("Successfully rebased and updated refs/heads/main.\n"));
+        assert_eq!(
+            commit_of("HEAD").parents,
+            [good_git::object::Object::resolve_rev(&repo, "topic").unwrap()]
+        );
+        assert_eq!(read("a.txt"), "1\ntwo\n3\n4\nFive\n6\n7\neight\n9\n");
+        assert_eq!(read("b.txt"), "b\n");
+    }
+
+    #[test]
+    fn test_rebase_interactive() {
+        let (_tmpdir, repo) = user_repo("A U Thor", "author@example.com");
+        // The editors reword the messages of "one" and "four", and the first
+        // instruction of the todo list, whatever the editors of the user.
+        std::env::set_var("GIT_EDITOR", "sed -i -e s/^one/One/ -e s/^four/Four/");
+        std::env::set_var("GIT_SEQUENCE_EDITOR", "sed -i 1s/^pick/reword/");
+        let commit = |path: &str, content: &str, message: &str| {
+            add_and_commit(&repo, path, content, message)
+        };
+        let commit_of = |rev: &str| match good_git::object::Object::from_rev(&repo, rev).unwrap() {
+            good_git::object::Object::Commit(commit) => commit,
+            _ => panic!("{rev} is not a commit"),
+        };
+        let rebase_with_todo = |todo: &[(good_git::rebase::Command, &str)]| {
+            let todo = todo
+                .iter()
+                .map(|(command, hash)| good_git::rebase::Instruction::new(*command, hash))
+                .collect();
+            let mut stdout = Vec::new();
+            good_git::rebase_with_todo(&repo, "main", todo, &mut stdout)
+                .map(|_| String::from_utf8(stdout).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        let read = |path: &str| std::fs::read_to_string(repo.root.join(path)).unwrap();
+        let base = commit("a.txt", "a\n", "Add a.txt");
+        good_git::refs::update_ref(&repo, "refs/heads/topic", &base).unwrap();
+        let main = commit("b.txt", "b\n", "Add b.txt");
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        let one = commit("n.txt", "1\n", "one");
+        let two = commit("n.txt", "2\n", "two\n\nBody of two");
+        let three = commit("n.txt", "3\n", "three");
+        let four = commit("n.txt", "4\n", "four");
+        let five = commit("n.txt", "5\n", "five");
+
+        use good_git::rebase::Command::*;
+        assert_eq!(
+            rebase_with_todo(&[(Fixup, &one), (Pick, &two)]).unwrap_err(),
+            "cannot 'fixup' without a previous commit"
+        );
+        assert_eq!(good_git::rebase::State::read(&repo).unwrap(), None);
+
+        // The squashed and fixed up commits are melded into one, with the
+        // message edited by the user, and the rebase stops after an edit.
+        let stdout = rebase_with_todo(&[
+            (Pick, &one),
+            (Squash, &two),
+            (Fixup, &three),
+            (Edit, &four),
+            (Drop, &five),
+        ])
+        .unwrap();
+        let abbrev = good_git::object::Abbreviator::from_config(&repo)
+            .unwrap()
+            .abbreviate(&four)
+            .unwrap();
+        assert!(stdout.contains(&format!("Stopped at {abbrev}...  four\n")));
+        let squashed = commit_of("HEAD").parents[0].clone();
+        assert_eq!(commit_of(&squashed).message, "One\n\ntwo\n\nBody of two");
+        assert_eq!(commit_of(&squashed).parents, [main]);
+        let state = good_git::rebase::State::read(&repo).unwrap().unwrap();
+        assert_eq!(
+            state.amend.as_ref(),
+            Some(&good_git::refs::find_ref(&repo, "HEAD").unwrap())
+        );
+        assert_eq!(
+            state.todo,
+            [good_git::rebase::Instruction::new(Drop, &five)]
+        );
+
+        // Continuing amends the commit stopped at with the staged changes.
+        std::fs::write(repo.root.join("n.txt"), "44\n").unwrap();
+        let options = good_git::AddOptions::default();
+        good_git::add(&repo, &["n.txt".to_string()], &options, &mut Vec::new()).unwrap();
+        let mut stdout = Vec::new();
+        good_git::rebase_resume(&repo, good_git::RebaseResume::Continue, &mut stdout).unwrap();
+        assert!(String::from_utf8(stdout)
+            .unwrap()
+            .ends_with("Successfully rebased and updated refs/heads/topic.\n"));
+        let amended = commit_of("HEAD");
+        assert_eq!(amended.message, "four");
+        assert_eq!(amended.parents, [squashed.clone()]);
+        assert_eq!(read("n.txt"), "44\n");
+
+        // The todo list is edited with the sequence editor.
+        let mut stdout = Vec::new();
+        let options = good_git::RebaseOptions { interactive: true };
+        good_git::rebase(&repo, &squashed, &options, &mut stdout).unwrap();
+        let reworded = commit_of("HEAD");
+        assert_eq!(reworded.message, "Four");
+        assert_eq!(reworded.parents, [squashed]);
+    }
+
+    #[test]
+    fn test_rerere() {
+        let (_tmpdir, repo) = user_repo("A U Thor", "author@example.com");
+        let config = repo.git_dir().join("config");
+        good_git::config::set_value(&config, "rerere.enabled", "true").unwrap();
+        let commit = |path: &str, content: &str, message: &str| {
+            std::fs::write(repo.root.join(path), content).unwrap();
+            let options = good_git::AddOptions::default();
+            good_git::add(&repo, &[path.to_string()], &options, &mut Vec::new()).unwrap();
+            let options = good_git::CommitOptions {
+                message: message.to_string(),
+                ..Default::default()
+            };
+            let mut stdout = Vec::new();
+            good_git::commit(&repo, &options, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        let merge = || {
+            let mut stdout = Vec::new();
+            let clean = good_git::merge(&repo, "topic", &Default::default(), &mut stdout).unwrap();
+            (clean, String::from_utf8(stdout).unwrap())
+        };
+        let read = |path: &str| std::fs::read_to_string(repo.root.join(path)).unwrap();
+        commit("a.txt", "1\n2\n3\n4\n5\n6\n7\n8\n9\n", "Add a.txt");
+        let base = good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap();
+        good_git::refs::update_ref(&repo, "refs/heads/topic", &base).unwrap();
+        good_git::switch_branch(&repo, "topic", &mut Vec::new()).unwrap();
+        commit("a.txt", "1\ntwo\n3\n4\n5\n6\n7\n8\n9\n", "Change 2");
+        good_git::switch_branch(&repo, "main", &mut Vec::new()).unwrap();
+        commit("a.txt", "1\nTWO\n3\n4\n5\n6\n7\n8\n9\n", "Shout 2");
+        let head = good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap();
+
+        // The conflict is recorded, and its resolution once committed.
+        let (clean, stdout) = merge();
+        assert!(!clean);
+        assert!(stdout.contains("Recorded preimage for 'a.txt'\n"));
+        let cache = good_git::rerere::cache_dir(&repo);
+        let ids: Vec<_> = std::fs::read_dir(&cache).unwrap().collect();
+        assert_eq!(ids.len(), 1);
+        let id = ids[0].as_ref().unwrap().path();
+        assert_eq!(
+            std::fs::read_to_string(id.join("preimage")).unwrap(),
+            "1\n<<<<<<<\nTWO\n=======\ntwo\n>>>>>>>\n3\n4\n5\n6\n7\n8\n9\n"
+        );
+        let stdout = commit("a.txt", "1\nTwo\n3\n4\n5\n6\n7\n8\n9\n", "Merge topic");
+        assert!(stdout.starts_with("Recorded resolution for 'a.txt'.\n"));
+        assert_eq!(
+            std::fs::read_to_string(id.join("postimage")).unwrap(),
+            read("a.txt")
+        );
+
+        // The same conflict is resolved the same way, but left unmerged in
+        // the index.
+        good_git::refs::update_ref(&repo, "refs/heads/again", &head).unwrap();
+        good_git::switch_branch(&repo, "again", &mut Vec::new()).unwrap();
+        commit("a.txt", "1\nTWO\n3\n4\n5\n6\n7\n8\nNINE\n", "Shout 9");
+        let (clean, stdout) = merge();
+        assert!(!clean);
+        assert!(stdout.contains("Resolved 'a.txt' using previous resolution.\n"));
+        assert_eq!(read("a.txt"), "1\nTwo\n3\n4\n5\n6\n7\n8\nNINE\n");
+        let index = good_git::index::Index::read(&repo).unwrap();
+        assert!(index.entries.iter().any(|entry| entry.stage == 2));
+    }
+
+    #[test]
+    fn test_blame() {
+        let (_tmpdir, repo) = user_repo("A U Thor", "author@example.com");
+        let commit = |content: &str, message: &str| {
+            std::fs::write(repo.root.join("a.txt"), content).unwrap();
+            let options = good_git::AddOptions::default();
+            good_git::add(&repo, &["a.txt".to_string()], &options, &mut Vec::new()).unwrap();
+            let options = good_git::CommitOptions {
+                message: message.to_string(),
+                ..Default::default()
+            };
+            good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+            good_git::object::Object::resolve_rev(&repo, "HEAD").unwrap()
+        };
+        let blame = |rev: Option<&str>, options: &good_git::BlameOptions| {
+            let mut stdout = Vec::new();
+            good_git::blame(&repo, rev, "a.txt", options, &mut stdout)
+                .map(|_| String::from_utf8(stdout).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        // The commit and content of each line of the default output.
+        let lines = |rev: Option<&str>, options: &good_git::BlameOptions| {
+            blame(rev, options)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    let (hash, rest) = line.split_once(' ').unwrap();
+                    let (_, content) = rest.split_once(") ").unwrap();
+                    (hash.to_string(), content.to_string())
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = commit("one\ntwo\nthree\n", "First");
+        let second = commit("one\n  two\nTHREE\nfour\n", "Second");
+        let boundary = format!("^{}", &first[..7]);
+        let short = |hash: &str| hash[..8].to_string();
+
+        assert_eq!(
+            lines(None, &Default::default()),
+            [
+                (boundary.clone(), "one".to_string()),
+                (short(&second), "  two".to_string()),
+                (short(&second), "THREE".to_string()),
+                (short(&second), "four".to_string()),
+            ]
+        );
+
+        // Whitespace changes are blamed on the commit before with -w.
+        let options = good_git::BlameOptions {
+            ignore_whitespace: true,
+            ranges: vec!["2,+2".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            lines(None, &options),
+            [
+                (boundary.clone(), "  two".to_string()),
+                (short(&second), "THREE".to_string()),
+            ]
+        );
+
+        // The lines of the working tree not committed yet.
+        std::fs::write(repo.root.join("a.txt"), "one\n  two\nTHREE\nfour\nfive\n").unwrap();
+        assert_eq!(
+            lines(None, &Default::default()).last().unwrap(),
+            &("00000000".to_string(), "five".to_string())
+        );
+        assert_eq!(lines(Some(&first), &Default::default()).len(), 3);
+
+        let options = good_git::BlameOptions {
+            ranges: vec!["3".to_string()],
+            format: good_git::BlameFormat::Porcelain,
+            ..Default::default()
+        };
+        let porcelain = blame(Some(&second), &options).unwrap();
+        assert!(porcelain.starts_with(&format!("{second} 3 3 2\nauthor A U Thor\n")));
+        assert!(porcelain.contains(&format!("summary Second\nprevious {first} a.txt\n")));
+        assert!(porcelain.ends_with(&format!("filename a.txt\n\tTHREE\n{second} 4 4\n\tfour\n")));
+
+        let options = good_git::BlameOptions {
+            ranges: vec!["20".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            blame(Some(&second), &options),
+            Err("file a.txt has only 4 lines".to_string())
+        );
+    }
+
+    #[rstest]
+    fn test_add(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::create_dir_all(repo.root.join("dir/sub")).unwrap();
+        std::fs::write(repo.root.join("test.txt"), "test content\n").unwrap();
+        std::fs::write(repo.root.join("dir/a.txt"), "a\n").unwrap();
+        std::fs::write(repo.root.join("dir/sub/b.txt"), "b\n").unwrap();
+
+        let add = |pathspecs: &[&str], options: &good_git::AddOptions| {
+            let pathspecs: Vec<String> = pathspecs.iter().map(|p| p.to_string()).collect();
+            let mut stdout = Vec::new();
+            good_git::add(&repo, &pathspecs, options, &mut stdout)
+                .map(|_| String::from_utf8(stdout).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        let paths = || {
+            good_git::index::Index::read(&repo)
+                .unwrap()
+                .entries
+                .into_iter()
+                .map(|entry| (entry.path, entry.hash))
+                .collect::<Vec<_>>()
+        };
+        let verbose = good_git::AddOptions {
+            verbose: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            add(&["test.txt", "dir"], &verbose).unwrap(),
+            "add 'test.txt'\nadd 'dir/a.txt'\nadd 'dir/sub/b.txt'\n"
+        );
+        let entries = paths();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|(path, _)| path.as_str())
+                .collect::<Vec<_>>(),
+            ["dir/a.txt", "dir/sub/b.txt", "test.txt"]
+        );
+        assert_eq!(entries[2].1, "d670460b4b4aece5915caf5c68d12f560a9fe3e4");
+        assert!(repo
+            .git_dir()
+            .join("objects/d6/70460b4b4aece5915caf5c68d12f560a9fe3e4")
+            .exists());
+        // Nothing changed.
+        assert_eq!(add(&["dir"], &verbose).unwrap(), "");
+
+        // -u only stages tracked files, including removals.
+        std::fs::write(repo.root.join("new.txt"), "new\n").unwrap();
+        std::fs::remove_file(repo.root.join("dir/a.txt")).unwrap();
+        let update = good_git::AddOptions {
+            update: true,
+            ..verbose
+        };
+        assert_eq!(add(&[], &update).unwrap(), "remove 'dir/a.txt'\n");
+        let all = good_git::AddOptions {
+            all: true,
+            ..Default::default()
+        };
+        add(&[], &all).unwrap();
+        assert_eq!(
+            paths()
+                .iter()
+                .map(|(path, _)| path.as_str())
+                .collect::<Vec<_>>(),
+            ["dir/sub/b.txt", "new.txt", "test.txt"]
+        );
+
+        assert_eq!(
+            add(&[], &Default::default()).unwrap_err(),
+            "Nothing specified, nothing added."
+        );
+        assert_eq!(
+            add(&["missing.txt"], &Default::default()).unwrap_err(),
+            "pathspec 'missing.txt' did not match any files"
+        );
+    }
+
+    #[rstest]
+    fn test_filters(test_repo: tempfile::TempDir) {
+        use good_git::object::Object;
+
+        let repo = Repo::new(test_repo.path());
+        std::fs::write(
+            repo.git_dir().join("config"),
+            "[filter \"upper\"]\n\tclean = tr a-z A-Z\n\tsmudge = tr A-Z a-z\n",
+        )
+        .unwrap();
+        std::fs::write(repo.root.join(".gitattributes"), "*.txt filter=upper\n").unwrap();
+        std::fs::write(repo.root.join("a.txt"), "hello\n").unwrap();
+        std::fs::write(repo.root.join("b.md"), "hello\n").unwrap();
+
+        let paths = ["a.txt".to_string(), "b.md".to_string()];
+        good_git::add(&repo, &paths, &Default::default(), &mut Vec::new()).unwrap();
+        let index = good_git::index::Index::read(&repo).unwrap();
+        let blob = |path: &str| {
+            let entry = index
+                .entries
+                .iter()
+                .find(|entry| entry.path == path)
+                .unwrap();
+            let Object::Blob(blob) = Object::from_hash(&repo, &entry.hash).unwrap() else {
+                panic!("{path} is not a blob");
+            };
+            String::from_utf8(blob.content).unwrap()
+        };
+        assert_eq!(blob("a.txt"), "HELLO\n");
+        assert_eq!(blob("b.md"), "hello\n");
+
+        // Files whose stat data changed are compared once cleaned.
+        let file = std::fs::File::options()
+            .write(true)
+            .open(repo.root.join("a.txt"))
+            .unwrap();
+        file.set_modified(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let status = good_git::status::Status::compute(&repo).unwrap();
+        assert!(status.paths.iter().any(|path| path.path == "a.txt"));
+        assert!(status.paths.iter().all(|path| path.unstaged.is_none()));
+        assert!(good_git::diff::diff_files(&repo).unwrap().is_empty());
+
+        std::fs::remove_file(repo.root.join("a.txt")).unwrap();
+        good_git::checkout_paths(&repo, None, &["a.txt".to_string()]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("a.txt")).unwrap(),
+            "hello\n"
+        );
+    }
+
+    #[test]
+    fn test_archive() {
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        std::fs::create_dir(repo.root.join("bin")).unwrap();
+        std::fs::write(repo.root.join("README"), "hello\n").unwrap();
+        std::fs::write(repo.root.join("bin/run"), "#!/bin/sh\n").unwrap();
+        let mut permissions = std::fs::metadata(repo.root.join("bin/run"))
+            .unwrap()
+            .permissions();
+        std::os::unix::fs::PermissionsExt::set_mode(&mut permissions, 0o755);
+        std::fs::set_permissions(repo.root.join("bin/run"), permissions).unwrap();
+        let paths = ["README".to_string(), "bin/run".to_string()];
+        good_git::add(&repo, &paths, &Default::default(), &mut Vec::new()).unwrap();
+        let options = good_git::CommitOptions {
+            message: "One".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+        let head = good_git::refs::find_ref(&repo, "HEAD").unwrap();
+
+        let options = good_git::ArchiveOptions {
+            prefix: "project-1.0/".to_string(),
+            ..Default::default()
+        };
+        let mut tar = Vec::new();
+        good_git::archive(&repo, "main", &options, &mut tar).unwrap();
+        assert_eq!(tar.len() % 10240, 0);
+
+        // Lists the (type, name, mode, content) of the entries.
+        let field = |record: &[u8], range: std::ops::Range<usize>| {
+            let field = &record[range];
+            let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+            String::from_utf8(field[..end].to_vec()).unwrap()
+        };
+        let mut entries = vec![];
+        let mut offset = 0;
+        while tar[offset] != 0 {
+            let record = &tar[offset..offset + 512];
+            let size = usize::from_str_radix(&field(record, 124..135), 8).unwrap();
+            let content = &tar[offset + 512..offset + 512 + size];
+            entries.push((
+                record[156] as char,
+                field(record, 0..100),
+                field(record, 100..107),
+                String::from_utf8(content.to_vec()).unwrap(),
+            ));
+            offset += 512 + size.div_ceil(512) * 512;
+        }
+        let entry = |typeflag, name: &str, mode: &str, content: &str| {
+            (
+                typeflag,
+                name.to_string(),
+                mode.to_string(),
+                content.to_string(),
+            )
+        };
+        assert_eq!(
+            entries,
+            [
+                entry(
+                    'g',
+                    "pax_global_header",
+                    "0000666",
+                    &format!("52 comment={head}\n")
+                ),
+                entry('5', "project-1.0/", "0000775", ""),
+                entry('0', "project-1.0/README", "0000664", "hello\n"),
+                entry('5', "project-1.0/bin/", "0000775", ""),
+                entry('0', "project-1.0/bin/run", "0000775", "#!/bin/sh\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_archive_zip() {
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        std::fs::write(
+            repo.root.join(".gitattributes"),
+            "secret export-ignore\nVERSION export-subst\n",
+        )
+        .unwrap();
+        std::fs::write(repo.root.join("README"), "hello\n".repeat(100)).unwrap();
+        std::fs::write(repo.root.join("secret"), "password\n").unwrap();
+        // The hash is repeated so that deflating it always makes it smaller.
+        std::fs::write(
+            repo.root.join("VERSION"),
+            "$Format:%H %s$ $Format:%an$\n$Format:%H$\n",
+        )
+        .unwrap();
+        let options = good_git::AddOptions {
+            all: true,
+            ..Default::default()
+        };
+        good_git::add(&repo, &[], &options, &mut Vec::new()).unwrap();
+        let options = good_git::CommitOptions {
+            message: "One".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+        let head = good_git::refs::find_ref(&repo, "HEAD").unwrap();
+
+        let options = good_git::ArchiveOptions {
+            format: good_git::archive::Format::Zip,
+            ..Default::default()
+        };
+        let mut zip = Vec::new();
+        good_git::archive(&repo, "main", &options, &mut zip).unwrap();
+        assert!(zip.ends_with(head.as_bytes()));
+
+        // Lists the (name, method, content) of the entries, from their local
+        // headers.
+        let le16 = |offset: usize| u16::from_le_bytes([zip[offset], zip[offset + 1]]) as usize;
+        let le32 = |offset: usize| {
+            u32::from_le_bytes(zip[offset..offset + 4].try_into().unwrap()) as usize
+        };
+        let mut entries = vec![];
+        let mut offset = 0;
+        while le32(offset) == 0x04034b50 {
+            let (method, compressed_size) = (le16(offset + 8), le32(offset + 18));
+            let name_length = le16(offset + 26);
+            let data = offset + 30 + name_length + le16(offset + 28);
+            let name = String::from_utf8(zip[offset + 30..offset + 30 + name_length].to_vec());
+            let mut content = String::new();
+            match method {
+                8 => {
+                    flate2::read::DeflateDecoder::new(&zip[data..data + compressed_size])
+                        .read_to_string(&mut content)
+                        .unwrap();
+                }
+                _ => {
+                    content = String::from_utf8(zip[data..data + compressed_size].to_vec()).unwrap()
+                }
+            }
+            entries.push((name.unwrap(), method, content));
+            offset = data + compressed_size;
+        }
+        assert_eq!(
+            entries,
+            [
+                (
+                    ".gitattributes".to_string(),
+                    8,
+                    "secret export-ignore\nVERSION export-subst\n".to_string()
+                ),
+                ("README".to_string(), 8, "hello\n".repeat(100)),
+                (
+                    "VERSION".to_string(),
+                    8,
+                    format!("{head} One Alice\n{head}\n")
+                ),
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_lfs_pointers(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::write(
+            repo.git_dir().join("config"),
+            "[user]\n\tname = Alice\n\temail = alice@example.com\n",
+        )
+        .unwrap();
+        std::fs::write(repo.root.join(".gitattributes"), "*.bin filter=lfs\n").unwrap();
+        let pointer = good_git::lfs::Pointer {
+            oid: "4d7a".repeat(16),
+            size: 12,
+        };
+        std::fs::write(repo.root.join("a.bin"), pointer.to_string()).unwrap();
+        std::fs::write(repo.root.join("b.txt"), pointer.to_string()).unwrap();
+        let paths = ["a.bin".to_string(), "b.txt".to_string()];
+        good_git::add(&repo, &paths, &Default::default(), &mut Vec::new()).unwrap();
+        let options = good_git::CommitOptions {
+            message: "Add pointers".to_string(),
+            ..Default::default()
+        };
+        good_git::commit(&repo, &options, &mut Vec::new()).unwrap();
+
+        // Without a Git LFS client, the pointers are the content.
+        let status = good_git::status::Status::compute(&repo).unwrap();
+        assert_eq!(status.lfs_pointers, ["a.bin"]);
+        let mut stdout = Vec::new();
+        good_git::status(&repo, &Default::default(), &mut stdout).unwrap();
+        assert!(String::from_utf8(stdout).unwrap().contains(
+            "Git LFS pointers not checked out:\n  \
+             (install Git LFS and use \"git lfs pull\" to check out their content)\n\
+             \ta.bin\n\n"
+        ));
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        good_git::cat_file_filters(&repo, "HEAD:a.bin", &mut stdout, &mut stderr).unwrap();
+        assert_eq!(stdout, pointer.to_string().as_bytes());
+        assert_eq!(
+            std::str::from_utf8(&stderr).unwrap(),
+            "warning: a.bin: checked out as a Git LFS pointer, filter 'lfs' has no smudge command\n"
+        );
+
+        // A Git LFS client is hooked in as the filter driver.
+        let mut config = std::fs::read_to_string(repo.git_dir().join("config")).unwrap();
+        config.push_str("[filter \"lfs\"]\n\tsmudge = echo hello world\n");
+        std::fs::write(repo.git_dir().join("config"), config).unwrap();
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        good_git::cat_file_filters(&repo, "HEAD:a.bin", &mut stdout, &mut stderr).unwrap();
+        assert_eq!(stdout, b"hello world\n");
+        assert!(stderr.is_empty());
+        std::fs::remove_file(repo.root.join("a.bin")).unwrap();
+        good_git::checkout_paths(&repo, None, &["a.bin".to_string()]).unwrap();
+        let status = good_git::status::Status::compute(&repo).unwrap();
+        assert!(status.lfs_pointers.is_empty());
+    }
+
+    #[rstest]
+    fn test_check_attr(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::write(
+            repo.root.join(".gitattributes"),
+            "[attr]generated -diff linguist-generated\n\
+             *.c text eol=lf\n\
+             *.png binary\n\
+             gen/* generated\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(repo.root.join("gen")).unwrap();
+        std::fs::write(repo.root.join("gen/.gitattributes"), "*.c -text diff\n").unwrap();
+
+        let check_attr = |attributes: &[&str], paths: &[&str]| {
+            let attributes: Vec<String> = attributes.iter().map(|a| a.to_string()).collect();
+            let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+            let mut stdout = Vec::new();
+            good_git::check_attr(&repo, &attributes, &paths, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        assert_eq!(
+            check_attr(&["text", "eol"], &["a.c", "a.h"]),
+            "a.c: text: set\na.c: eol: lf\na.h: text: unspecified\na.h: eol: unspecified\n"
+        );
+        assert_eq!(
+            check_attr(&[], &["a.png", "gen/a.c"]),
+            "a.png: binary: set\n\
+             a.png: diff: unset\n\
+             a.png: merge: unset\n\
+             a.png: text: unset\n\
+             gen/a.c: diff: set\n\
+             gen/a.c: text: unset\n\
+             gen/a.c: generated: set\n\
+             gen/a.c: linguist-generated: set\n\
+             gen/a.c: eol: lf\n"
+        );
+        // Ignored lines are reported once their file is read.
+        std::fs::create_dir_all(repo.root.join("bad")).unwrap();
+        std::fs::write(repo.root.join("bad/.gitattributes"), "*.c a+b\n").unwrap();
+        assert_eq!(
+            check_attr(&["text"], &["a.c", "bad/a.c", "bad/b.c"]),
+            "a.c: text: set\n\
+             a+b is not a valid attribute name: bad/.gitattributes:1\n\
+             bad/a.c: text: set\n\
+             bad/b.c: text: set\n"
+        );
+    }
+
+    #[rstest]
+    fn test_status_untracked_cache(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let untracked = || good_git::status::Status::compute(&repo).unwrap().untracked;
+        let cached = || {
+            good_git::index::Index::read(&repo)
+                .unwrap()
+                .untracked_cache
+                .is_some()
+        };
+        std::fs::create_dir_all(repo.root.join("dir/sub")).unwrap();
+        std::fs::write(repo.root.join("dir/sub/a.o"), "").unwrap();
+        std::fs::write(repo.root.join("new.txt"), "").unwrap();
+        assert_eq!(untracked(), ["dir/", "new.txt"]);
+        assert!(!cached());
+
+        let config = repo.git_dir().join("config");
+        good_git::config::set_value(&config, "core.untrackedCache", "true").unwrap();
+        assert_eq!(untracked(), ["dir/", "new.txt"]);
+        assert!(cached());
+        assert_eq!(untracked(), ["dir/", "new.txt"]);
+
+        std::fs::write(repo.root.join(".gitignore"), "*.o\n").unwrap();
+        assert_eq!(untracked(), [".gitignore", "new.txt"]);
+        // Files in directories that were only checked for files are seen.
+        std::fs::write(repo.root.join("dir/sub/b.txt"), "").unwrap();
+        assert_eq!(untracked(), [".gitignore", "dir/", "new.txt"]);
+        std::fs::remove_file(repo.root.join("new.txt")).unwrap();
+        assert_eq!(untracked(), [".gitignore", "dir/"]);
+
+        good_git::config::set_value(&config, "core.untrackedCache", "keep").unwrap();
+        assert_eq!(untracked(), [".gitignore", "dir/"]);
+        assert!(cached());
+        good_git::config::set_value(&config, "core.untrackedCache", "false").unwrap();
+        assert_eq!(untracked(), [".gitignore", "dir/"]);
+        assert!(!cached());
+    }
+
+    #[rstest]
+    fn test_ignore(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::write(
+            repo.root.join(".gitignore"),
+            "*.log\nbuild/\n!important.log\n",
+        )
+        .unwrap();
+        std::fs::write(repo.git_dir().join("info/exclude"), "/new.*\n").unwrap();
+        std::fs::create_dir_all(repo.root.join("build")).unwrap();
+        std::fs::create_dir_all(repo.root.join("dir")).unwrap();
+        for path in [
+            "a.log",
+            "important.log",
+            "build/out",
+            "dir/x.log",
+            "dir/y.txt",
+            "new.txt",
+        ] {
+            std::fs::write(repo.root.join(path), "").unwrap();
+        }
+
+        let status = good_git::status::Status::compute(&repo).unwrap();
+        assert_eq!(status.untracked, [".gitignore", "dir/", "important.log"]);
+
+        let check_ignore = |paths: &[&str], options: &good_git::CheckIgnoreOptions| {
+            let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+            let mut stdout = Vec::new();
+            let found = good_git::check_ignore(&repo, &paths, options, &mut stdout).unwrap();
+            (found, String::from_utf8(stdout).unwrap())
+        };
+        let paths = ["a.log", "important.log", "build/out", "new.txt", "test.txt"];
+        assert_eq!(
+            check_ignore(&paths, &Default::default()),
+            (true, "a.log\nbuild/out\nnew.txt\n".to_string())
+        );
+        let verbose = good_git::CheckIgnoreOptions {
+            verbose: true,
+            non_matching: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            check_ignore(&paths, &verbose).1,
+            ".gitignore:1:*.log\ta.log\n\
+             .gitignore:3:!important.log\timportant.log\n\
+             .gitignore:2:build/\tbuild/out\n\
+             .git/info/exclude:1:/new.*\tnew.txt\n\
+             ::\ttest.txt\n"
+        );
+        assert_eq!(
+            check_ignore(&["important.log"], &Default::default()),
+            (false, String::new())
+        );
+
+        // Ignored files are skipped when adding a directory, and can only be
+        // named with `force`.
+        let add = |pathspecs: &[&str], options: &good_git::AddOptions| {
+            let pathspecs: Vec<String> = pathspecs.iter().map(|p| p.to_string()).collect();
+            good_git::add(&repo, &pathspecs, options, &mut Vec::new()).map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            add(&["dir", "a.log", "new.txt"], &Default::default()).unwrap_err(),
+            "The following paths are ignored by one of your .gitignore files:\n\
+             a.log\nnew.txt\nhint: Use -f if you really want to add them."
+        );
+        let force = good_git::AddOptions {
+            force: true,
+            ..Default::default()
+        };
+        add(&["new.txt"], &force).unwrap();
+        let index = good_git::index::Index::read(&repo).unwrap();
+        let tracked: Vec<&str> = index.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(tracked, ["dir/y.txt", "new.txt"]);
+
+        let clean = |options: &good_git::CleanOptions| {
+            let mut stdout = Vec::new();
+            good_git::clean(&repo, options, &mut stdout)
+                .map(|()| String::from_utf8(stdout).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            clean(&Default::default()).unwrap_err(),
+            "clean.requireForce defaults to true and neither -i, -n, nor -f given; \
+             refusing to clean"
+        );
+        let dry_run = good_git::CleanOptions {
+            dry_run: true,
+            directories: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            clean(&dry_run).unwrap(),
+            "Would remove .gitignore\nWould remove important.log\n"
+        );
+        let only_ignored = good_git::CleanOptions {
+            force: true,
+            directories: true,
+            ignored: good_git::CleanIgnored::Only,
+            ..Default::default()
+        };
+        assert_eq!(
+            clean(&only_ignored).unwrap(),
+            "Removing a.log\nRemoving build/\nRemoving dir/x.log\n"
+        );
+        assert!(!repo.root.join("build").exists());
+        assert!(repo.root.join("important.log").exists());
+    }
+
+    #[rstest]
+    #[case(&["content"], "main:test.txt:test content\nmain:more.txt:more content\n")]
+    #[case(&["-n", "-i", "GOOD"], "main:more.txt:2:from a good client\n")]
+    #[case(&["-c", "content"], "main:test.txt:1\nmain:more.txt:1\n")]
+    #[case(&["-l", "-F", "t c"], "main:test.txt\n")]
+    #[case(&["-v", "content"], "main:more.txt:from a good client\n")]
+    fn test_grep_tree(test_repo: tempfile::TempDir, #[case] args: &[&str], #[case] expected: &str) {
+        let repo = Repo::new(test_repo.path());
+        let (flags, pattern) = args.split_at(args.len() - 1);
+        let options = good_git::GrepOptions {
+            tree: Some("main".to_string()),
+            ignore_case: flags.contains(&"-i"),
+            invert_match: flags.contains(&"-v"),
+            fixed_strings: flags.contains(&"-F"),
+            line_number: flags.contains(&"-n"),
+            files_with_matches: flags.contains(&"-l"),
+            count: flags.contains(&"-c"),
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        assert!(good_git::grep(&repo, pattern[0], &[], &options, &mut stdout).unwrap());
+        assert_eq!(String::from_utf8(stdout).unwrap(), expected);
+    }
+
+    #[rstest]
+    fn test_grep_worktree_and_index(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::create_dir(repo.root.join("dir")).unwrap();
+        std::fs::write(repo.root.join("dir/a.txt"), "staged\n").unwrap();
+        std::fs::write(repo.root.join("b.txt"), "staged\n").unwrap();
+        let pathspecs = ["dir".to_string(), "b.txt".to_string()];
+        good_git::add(&repo, &pathspecs, &Default::default(), &mut Vec::new()).unwrap();
+        std::fs::write(repo.root.join("dir/a.txt"), "changed\n").unwrap();
+        std::fs::write(repo.root.join("untracked.txt"), "staged\n").unwrap();
+
+        let grep = |pattern: &str, pathspecs: &[String], cached: bool| {
+            let options = good_git::GrepOptions {
+                cached,
+                ..Default::default()
+            };
+            let mut stdout = Vec::new();
+            let found = good_git::grep(&repo, pattern, pathspecs, &options, &mut stdout).unwrap();
+            (found, String::from_utf8(stdout).unwrap())
+        };
+        assert_eq!(
+            grep("staged", &[], false),
+            (true, "b.txt:staged\n".to_string())
+        );
+        assert_eq!(
+            grep("staged", &[], true),
+            (true, "b.txt:staged\ndir/a.txt:staged\n".to_string())
+        );
+        assert_eq!(
+            grep("staged", &["dir".to_string()], true),
+            (true, "dir/a.txt:staged\n".to_string())
+        );
+        assert_eq!(grep("missing", &[], false), (false, String::new()));
+    }
+
+    #[test]
+    fn test_grep_and_cat_file_use_diff_attributes() {
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        std::fs::write(repo.root.join(".gitattributes"), "*.dat -diff\n").unwrap();
+        add_and_commit(&repo, "a.txt", "match\n", "One");
+        add_and_commit(&repo, "b.dat", "match\n", "Two");
+
+        let mut stdout = Vec::new();
+        good_git::grep(&repo, "match", &[], &Default::default(), &mut stdout).unwrap();
+        assert_eq!(stdout, b"a.txt:match\nBinary file b.dat matches\n");
+
+        // Binary blobs are printed as is, text gets a final newline.
+        stdout.clear();
+        good_git::cat_file(&repo, "HEAD:b.dat", &mut stdout).unwrap();
+        assert_eq!(stdout, b"match\n");
+        stdout.clear();
+        good_git::cat_file(&repo, "HEAD:a.txt", &mut stdout).unwrap();
+        assert_eq!(stdout, b"match\n\n");
+    }
+
+    #[rstest]
+    fn test_rm(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::create_dir(repo.root.join("dir")).unwrap();
+        std::fs::write(repo.root.join("dir/a.txt"), "a\n").unwrap();
+        std::fs::write(repo.root.join("test.txt"), "test content\n").unwrap();
+        std::fs::write(
+            repo.root.join("more.txt"),
+            "more content\nfrom a good client",
+        )
+        .unwrap();
+        let all = good_git::AddOptions {
+            all: true,
+            ..Default::default()
+        };
+        good_git::add(&repo, &[], &all, &mut Vec::new()).unwrap();
+
+        let rm = |pathspecs: &[&str], options: &good_git::RmOptions| {
+            let pathspecs: Vec<String> = pathspecs.iter().map(|p| p.to_string()).collect();
+            let mut stdout = Vec::new();
+            good_git::rm(&repo, &pathspecs, options, &mut stdout)
+                .map(|_| String::from_utf8(stdout).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        let keep = "(use --cached to keep the file, or -f to force removal)";
+
+        // dir/a.txt isn't in HEAD.
+        assert_eq!(
+            rm(&["dir"], &Default::default()).unwrap_err(),
+            "not removing 'dir' recursively without -r"
+        );
+        let recursive = good_git::RmOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            rm(&["dir"], &recursive).unwrap_err(),
+            format!("the following file has changes staged in the index:\n    dir/a.txt\n{keep}")
+        );
+        let cached = good_git::RmOptions {
+            cached: true,
+            ..recursive
+        };
+        assert_eq!(rm(&["dir"], &cached).unwrap(), "rm 'dir/a.txt'\n");
+        assert!(repo.root.join("dir/a.txt").exists());
+
+        // The blob of more.txt in HEAD is different.
+        std::fs::write(repo.root.join("test.txt"), "changed\n").unwrap();
+        assert_eq!(
+            rm(&["test.txt", "more.txt"], &Default::default()).unwrap_err(),
+            format!(
+                "the following file has changes staged in the index:\n    more.txt\n{keep}\n\
+                 the following file has local modifications:\n    test.txt\n{keep}"
+            )
+        );
+        let force = good_git::RmOptions {
+            force: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            rm(&["test.txt", "more.txt"], &force).unwrap(),
+            "rm 'more.txt'\nrm 'test.txt'\n"
+        );
+        assert!(!repo.root.join("test.txt").exists());
+
+        assert!(good_git::index::Index::read(&repo)
+            .unwrap()
+            .entries
+            .is_empty());
+        assert_eq!(
+            rm(&["test.txt"], &force).unwrap_err(),
+            "pathspec 'test.txt' did not match any files"
+        );
+    }
+
+    #[rstest]
+    fn test_mv(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        std::fs::create_dir_all(repo.root.join("dir/sub")).unwrap();
+        std::fs::write(repo.root.join("dir/sub/a.txt"), "a\n").unwrap();
+        std::fs::write(repo.root.join("test.txt"), "test content\n").unwrap();
+        std::fs::write(repo.root.join("b.txt"), "b\n").unwrap();
+        let all = good_git::AddOptions {
+            all: true,
+            ..Default::default()
+        };
+        good_git::add(&repo, &[], &all, &mut Vec::new()).unwrap();
+        std::fs::write(repo.root.join("untracked.txt"), "u\n").unwrap();
+
+        let mv = |sources: &[&str], destination: &str, options: &good_git::MvOptions| {
+            let sources: Vec<String> = sources.iter().map(|p| p.to_string()).collect();
+            let mut stdout = Vec::new();
+            good_git::mv(&repo, &sources, destination, options, &mut stdout)
+                .map(|_| String::from_utf8(stdout).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        let paths = || {
+            good_git::index::Index::read(&repo)
+                .unwrap()
+                .entries
+                .into_iter()
+                .map(|entry| entry.path)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            mv(&["test.txt"], "b.txt", &Default::default()).unwrap_err(),
+            "destination exists, source=test.txt, destination=b.txt"
+        );
+        assert_eq!(
+            mv(&["untracked.txt"], "u.txt", &Default::default()).unwrap_err(),
+            "not under version control, source=untracked.txt, destination=u.txt"
+        );
+        assert_eq!(
+            mv(&["dir"], "dir/sub", &Default::default()).unwrap_err(),
+            "can not move directory into itself, source=dir, destination=dir/sub/dir"
+        );
+
+        let verbose = good_git::MvOptions {
+            verbose: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            mv(&["dir"], "moved", &verbose).unwrap(),
+            "Renaming dir to moved\n"
+        );
+        assert!(repo.root.join("moved/sub/a.txt").exists());
+        let skip_errors = good_git::MvOptions {
+            skip_errors: true,
+            ..Default::default()
+        };
+        mv(&["missing.txt", "test.txt"], "moved/sub", &skip_errors).unwrap();
+        let force = good_git::MvOptions {
+            force: true,
+            ..Default::default()
+        };
+        mv(&["b.txt"], "moved/sub/a.txt", &force).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(repo.root.join("moved/sub/a.txt")).unwrap(),
+            "b\n"
+        );
+        assert_eq!(paths(), ["moved/sub/a.txt", "moved/sub/test.txt"]);
+    }
+
+    #[rstest]
+    fn test_status(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let status = || {
+            let mut stdout = Vec::new();
+            good_git::status(&repo, &Default::default(), &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        std::fs::write(repo.root.join("test.txt"), "test content\n").unwrap();
+        std::fs::write(repo.root.join("new.txt"), "new\n").unwrap();
+        let pathspecs = ["test.txt".to_string(), "new.txt".to_string()];
+        good_git::add(&repo, &pathspecs, &Default::default(), &mut Vec::new()).unwrap();
+        std::fs::write(repo.root.join("new.txt"), "changed\n").unwrap();
+        std::fs::create_dir_all(repo.root.join("untracked/dir")).unwrap();
+        std::fs::write(repo.root.join("untracked/dir/file.txt"), "").unwrap();
+
+        assert_eq!(
+            status(),
+            "On branch main
+Changes to be committed:
+  (use \"git restore --staged <file>...\" to unstage)
+\tdeleted:    more.txt
+\tnew file:   new.txt
+
+Changes not staged for commit:
+  (use \"git add <file>...\" to update what will be committed)
+  (use \"git restore <file>...\" to discard changes in working directory)
+\tmodified:   new.txt
+
+Untracked files:
+  (use \"git add <file>...\" to include in what will be committed)
+\tuntracked/
+
+"
+        );
+
+        // new.txt has the content of test.txt, so it's seen as a rename of it.
+        std::fs::write(repo.root.join("new.txt"), "test content\n").unwrap();
+        std::fs::remove_file(repo.root.join("test.txt")).unwrap();
+        let all = good_git::AddOptions {
+            all: true,
+            ..Default::default()
+        };
+        good_git::add(&repo, &[], &all, &mut Vec::new()).unwrap();
+        assert!(status().contains("\trenamed:    test.txt -> new.txt\n"));
+
+        std::fs::write(repo.git_dir().join("HEAD"), "ref: refs/heads/unborn\n").unwrap();
+        let force = good_git::RmOptions {
+            force: true,
+            recursive: true,
+            ..Default::default()
+        };
+        let pathspecs = ["new.txt".to_string(), "untracked".to_string()];
+        good_git::rm(&repo, &pathspecs, &force, &mut Vec::new()).unwrap();
+        assert_eq!(
+            status(),
+            "On branch unborn\n\nNo commits yet\n\n\
+             nothing to commit (create/copy files and use \"git add\" to track)\n"
+        );
+    }
+
+    #[rstest]
+    fn test_status_porcelain(test_repo: tempfile::TempDir) {
+        let repo = Repo::new(test_repo.path());
+        let status = |format: good_git::StatusFormat, null_terminated: bool| {
+            let options = good_git::StatusOptions {
+                format,
+                branch: true,
+                null_terminated,
+                ..Default::default()
+            };
+            let mut stdout = Vec::new();
+            good_git::status(&repo, &options, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        std::fs::write(repo.root.join("renamed.txt"), "test content\n").unwrap();
+        std::fs::write(
+            repo.root.join("more.txt"),
+            "more content\nfrom a good client",
+        )
+        .unwrap();
+        let pathspecs = ["renamed.txt".to_string(), "more.txt".to_string()];
+        good_git::add(&repo, &pathspecs, &Default::default(), &mut Vec::new()).unwrap();
+        std::fs::remove_file(repo.root.join("more.txt")).unwrap();
+        std::fs::write(repo.root.join("new\tfile"), "").unwrap();
+
+        // The hash of more.txt in HEAD isn't its real hash.
+        assert_eq!(
+            status(good_git::StatusFormat::PorcelainV1, false),
+            "## main\nMD more.txt\nR  test.txt -> renamed.txt\n?? \"new\\tfile\"\n"
+        );
+        assert_eq!(
+            status(good_git::StatusFormat::PorcelainV1, true),
+            "## main\0MD more.txt\0R  renamed.txt\0test.txt\0?? new\tfile\0"
+        );
+        assert_eq!(
+            status(good_git::StatusFormat::PorcelainV2, false),
+            "# branch.oid ccccccccccccccccccccdddddddddddddddddddd
+# branch.head main
+1 MD N... 100644 100644 000000 1234567890abcdef1234567890abcdef12345678 \
+             7a6937eb0b7beeec83ab68e7e03d0d8622ba35e9 more.txt
+2 R. N... 100644 100644 100644 d670460b4b4aece5915caf5c68d12f560a9fe3e4 \
+             d670460b4b4aece5915caf5c68d12f560a9fe3e4 R100 renamed.txt\ttest.txt
+? \"new\\tfile\"
+"
+        );
+    }
+
+    #[test]
+    fn test_status_porcelain_v2_upstream() {
+        let (_tmpdir, repo) = user_repo("Alice", "alice@example.com");
+        let one = add_and_commit(&repo, "a.txt", "1\n", "One");
+        good_git::refs::update_ref(&repo, "refs/remotes/origin/main", &one).unwrap();
+        let two = add_and_commit(&repo, "a.txt", "2\n", "Two");
+        let config = repo.git_dir().join("config");
+        let fetch = "+refs/heads/*:refs/remotes/origin/*";
+        good_git::config::set_value(&config, "remote.origin.fetch", fetch).unwrap();
+        good_git::config::set_value(&config, "branch.main.remote", "origin").unwrap();
+        let status = |merge: &str| {
+            good_git::config::set_value(&config, "branch.main.merge", merge).unwrap();
+            let options = good_git::StatusOptions {
+                format: good_git::StatusFormat::PorcelainV2,
+                branch: true,
+                ..Default::default()
+            };
+            let mut stdout = Vec::new();
+            good_git::status(&repo, &options, &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+
+        let head = format!("# branch.oid {two}\n# branch.head main\n");
+        assert_eq!(
+            status("refs/heads/main"),
+            format!("{head}# branch.upstream origin/main\n# branch.ab +1 -0\n")
+        );
+        // An upstream that doesn't exist has no counts.
+        assert_eq!(
+            status("refs/heads/gone"),
+            format!("{head}# branch.upstream origin/gone\n")
+        );
+    }
 }